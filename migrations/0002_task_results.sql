@@ -0,0 +1,2 @@
+ALTER TABLE tasks ADD COLUMN result_summary TEXT;
+ALTER TABLE tasks ADD COLUMN result_data TEXT;
@@ -0,0 +1,7 @@
+CREATE TABLE task_processes (
+    task_id TEXT PRIMARY KEY,
+    pid INTEGER NOT NULL,
+    process_start TEXT,
+    daemon_pid INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
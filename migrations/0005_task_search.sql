@@ -0,0 +1,19 @@
+CREATE VIRTUAL TABLE task_search USING fts5(
+    task_id UNINDEXED,
+    description,
+    log,
+    log_indexed UNINDEXED,
+    tokenize = 'porter unicode61'
+);
+
+INSERT INTO task_search (task_id, description, log, log_indexed)
+SELECT id, description, '', 0 FROM tasks;
+
+CREATE TRIGGER tasks_search_insert AFTER INSERT ON tasks BEGIN
+    INSERT INTO task_search (task_id, description, log, log_indexed)
+    VALUES (new.id, new.description, '', 0);
+END;
+
+CREATE TRIGGER tasks_search_delete AFTER DELETE ON tasks BEGIN
+    DELETE FROM task_search WHERE task_id = old.id;
+END;
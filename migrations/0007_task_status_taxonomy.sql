@@ -0,0 +1,35 @@
+-- Widen the task status check constraint. SQLite cannot alter a constraint,
+-- so the table is rebuilt. The search triggers are dropped first so the
+-- rebuild does not clear the search index.
+DROP TRIGGER tasks_search_insert;
+DROP TRIGGER tasks_search_delete;
+
+CREATE TABLE tasks_new (
+    id TEXT PRIMARY KEY,
+    environment_id TEXT NOT NULL UNIQUE REFERENCES environments(id),
+    project_id TEXT NOT NULL REFERENCES projects(id),
+    provider TEXT NOT NULL,
+    description TEXT NOT NULL,
+    status TEXT NOT NULL CHECK (status IN ('pending', 'started', 'complete', 'failed', 'cancelled', 'timed_out', 'interrupted')),
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    result_summary TEXT,
+    result_data TEXT,
+    resource_usage TEXT,
+    labels TEXT NOT NULL DEFAULT '[]'
+);
+
+INSERT INTO tasks_new (id, environment_id, project_id, provider, description, status, created_at, updated_at, result_summary, result_data, resource_usage, labels)
+SELECT id, environment_id, project_id, provider, description, status, created_at, updated_at, result_summary, result_data, resource_usage, labels FROM tasks;
+
+DROP TABLE tasks;
+ALTER TABLE tasks_new RENAME TO tasks;
+
+CREATE TRIGGER tasks_search_insert AFTER INSERT ON tasks BEGIN
+    INSERT INTO task_search (task_id, description, log, log_indexed)
+    VALUES (new.id, new.description, '', 0);
+END;
+
+CREATE TRIGGER tasks_search_delete AFTER DELETE ON tasks BEGIN
+    DELETE FROM task_search WHERE task_id = old.id;
+END;
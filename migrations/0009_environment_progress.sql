@@ -0,0 +1,2 @@
+ALTER TABLE environments ADD COLUMN progress REAL;
+ALTER TABLE environments ADD COLUMN progress_message TEXT;
@@ -0,0 +1,20 @@
+-- Allow the 'degraded' environment status, for pooled environments taken out
+-- of service. SQLite cannot alter a constraint, so the table is rebuilt.
+
+CREATE TABLE environments_new (
+    id TEXT PRIMARY KEY,
+    project_id TEXT NOT NULL REFERENCES projects(id),
+    provider TEXT NOT NULL,
+    status TEXT NOT NULL CHECK (status IN ('preparing', 'pool', 'in_use', 'removing', 'failed', 'degraded')),
+    metadata TEXT NOT NULL DEFAULT '{}',
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    progress REAL,
+    progress_message TEXT
+);
+
+INSERT INTO environments_new (id, project_id, provider, status, metadata, created_at, updated_at, progress, progress_message)
+SELECT id, project_id, provider, status, metadata, created_at, updated_at, progress, progress_message FROM environments;
+
+DROP TABLE environments;
+ALTER TABLE environments_new RENAME TO environments;
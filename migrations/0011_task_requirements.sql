@@ -0,0 +1 @@
+ALTER TABLE tasks ADD COLUMN requirements TEXT NOT NULL DEFAULT '[]';
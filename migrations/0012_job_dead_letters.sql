@@ -0,0 +1,44 @@
+-- Jobs that exhaust their retries move to 'dead' instead of 'failed' and keep
+-- every attempt's error. SQLite cannot alter a constraint, so the table is
+-- rebuilt.
+CREATE TABLE jobs_new (
+    id TEXT PRIMARY KEY,
+    type TEXT NOT NULL,
+    payload TEXT NOT NULL DEFAULT '{}',
+    status TEXT NOT NULL CHECK (status IN ('pending', 'running', 'complete', 'dead')),
+    dedupe_key TEXT,
+    attempt INTEGER NOT NULL DEFAULT 0,
+    not_before TEXT,
+    lease_expires_at TEXT,
+    last_error TEXT,
+    errors TEXT NOT NULL DEFAULT '[]',
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+INSERT INTO jobs_new (id, type, payload, status, dedupe_key, attempt, not_before, lease_expires_at, last_error, errors, created_at, updated_at)
+SELECT
+    id,
+    type,
+    payload,
+    CASE status WHEN 'failed' THEN 'dead' ELSE status END,
+    dedupe_key,
+    attempt,
+    not_before,
+    lease_expires_at,
+    last_error,
+    CASE
+        WHEN status = 'failed' AND last_error IS NOT NULL
+        THEN json_array(json_object('attempt', attempt + 1, 'error', last_error, 'at', updated_at))
+        ELSE '[]'
+    END,
+    created_at,
+    updated_at
+FROM jobs;
+
+DROP TABLE jobs;
+ALTER TABLE jobs_new RENAME TO jobs;
+
+CREATE UNIQUE INDEX jobs_dedupe_key_unique
+ON jobs(dedupe_key)
+WHERE dedupe_key IS NOT NULL;
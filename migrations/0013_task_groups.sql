@@ -0,0 +1,9 @@
+CREATE TABLE task_groups (
+    id TEXT PRIMARY KEY,
+    project_id TEXT NOT NULL REFERENCES projects(id),
+    description TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+ALTER TABLE tasks ADD COLUMN group_id TEXT REFERENCES task_groups(id);
@@ -0,0 +1,81 @@
+CREATE TABLE events (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,
+    subject_id TEXT NOT NULL,
+    action TEXT NOT NULL,
+    status TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX events_subject ON events (kind, subject_id);
+
+CREATE TRIGGER tasks_event_insert AFTER INSERT ON tasks BEGIN
+    INSERT INTO events (kind, subject_id, action, status)
+    VALUES ('task', new.id, 'created', new.status);
+END;
+
+CREATE TRIGGER tasks_event_update AFTER UPDATE ON tasks BEGIN
+    INSERT INTO events (kind, subject_id, action, status)
+    VALUES ('task', new.id, 'updated', new.status);
+END;
+
+CREATE TRIGGER tasks_event_delete AFTER DELETE ON tasks BEGIN
+    INSERT INTO events (kind, subject_id, action, status)
+    VALUES ('task', old.id, 'removed', old.status);
+END;
+
+CREATE TRIGGER environments_event_insert AFTER INSERT ON environments BEGIN
+    INSERT INTO events (kind, subject_id, action, status)
+    VALUES ('environment', new.id, 'created', new.status);
+END;
+
+CREATE TRIGGER environments_event_update AFTER UPDATE ON environments BEGIN
+    INSERT INTO events (kind, subject_id, action, status)
+    VALUES ('environment', new.id, 'updated', new.status);
+END;
+
+CREATE TRIGGER environments_event_delete AFTER DELETE ON environments BEGIN
+    INSERT INTO events (kind, subject_id, action, status)
+    VALUES ('environment', old.id, 'removed', old.status);
+END;
+
+CREATE TRIGGER projects_event_insert AFTER INSERT ON projects BEGIN
+    INSERT INTO events (kind, subject_id, action) VALUES ('project', new.id, 'created');
+END;
+
+CREATE TRIGGER projects_event_update AFTER UPDATE ON projects BEGIN
+    INSERT INTO events (kind, subject_id, action) VALUES ('project', new.id, 'updated');
+END;
+
+CREATE TRIGGER projects_event_delete AFTER DELETE ON projects BEGIN
+    INSERT INTO events (kind, subject_id, action) VALUES ('project', old.id, 'removed');
+END;
+
+CREATE TRIGGER task_groups_event_insert AFTER INSERT ON task_groups BEGIN
+    INSERT INTO events (kind, subject_id, action) VALUES ('group', new.id, 'created');
+END;
+
+CREATE TRIGGER task_groups_event_update AFTER UPDATE ON task_groups BEGIN
+    INSERT INTO events (kind, subject_id, action) VALUES ('group', new.id, 'updated');
+END;
+
+CREATE TRIGGER task_groups_event_delete AFTER DELETE ON task_groups BEGIN
+    INSERT INTO events (kind, subject_id, action) VALUES ('group', old.id, 'removed');
+END;
+
+-- Leases are renewed constantly, so only status changes of jobs are events.
+CREATE TRIGGER jobs_event_insert AFTER INSERT ON jobs BEGIN
+    INSERT INTO events (kind, subject_id, action, status)
+    VALUES ('job', new.id, 'created', new.status);
+END;
+
+CREATE TRIGGER jobs_event_update AFTER UPDATE OF status ON jobs
+WHEN old.status IS NOT new.status BEGIN
+    INSERT INTO events (kind, subject_id, action, status)
+    VALUES ('job', new.id, 'updated', new.status);
+END;
+
+CREATE TRIGGER jobs_event_delete AFTER DELETE ON jobs BEGIN
+    INSERT INTO events (kind, subject_id, action, status)
+    VALUES ('job', old.id, 'removed', old.status);
+END;
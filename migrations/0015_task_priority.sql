@@ -0,0 +1,8 @@
+-- Jobs are claimed highest priority first, then oldest first. A task's jobs
+-- take its priority: high = 1, normal = 0, low = -1.
+ALTER TABLE tasks ADD COLUMN priority TEXT NOT NULL DEFAULT 'normal'
+    CHECK (priority IN ('high', 'normal', 'low'));
+
+ALTER TABLE jobs ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX jobs_claim_order ON jobs(priority DESC, created_at);
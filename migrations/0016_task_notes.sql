@@ -0,0 +1,2 @@
+-- Operator notes, oldest first, as a JSON array of {"at", "text"} objects.
+ALTER TABLE tasks ADD COLUMN notes TEXT NOT NULL DEFAULT '[]';
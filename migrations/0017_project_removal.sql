@@ -0,0 +1,3 @@
+-- Set when a project is removed while it still has environments. The project
+-- is hidden from then on and deleted once the last of them is gone.
+ALTER TABLE projects ADD COLUMN removing_at TEXT;
@@ -0,0 +1,38 @@
+CREATE TABLE environment_status_history (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    environment_id TEXT NOT NULL,
+    status TEXT NOT NULL,
+    previous_status TEXT,
+    -- The job that was running for the environment when it changed, if any.
+    job_id TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX environment_status_history_environment
+ON environment_status_history (environment_id, seq);
+
+-- Environments that already exist start with their current status.
+INSERT INTO environment_status_history (environment_id, status, created_at)
+SELECT id, status, updated_at FROM environments ORDER BY created_at;
+
+CREATE TRIGGER environments_status_history_insert AFTER INSERT ON environments BEGIN
+    INSERT INTO environment_status_history (environment_id, status)
+    VALUES (new.id, new.status);
+END;
+
+CREATE TRIGGER environments_status_history_update AFTER UPDATE OF status ON environments
+WHEN old.status IS NOT new.status BEGIN
+    INSERT INTO environment_status_history (environment_id, status, previous_status, job_id)
+    VALUES (
+        new.id,
+        new.status,
+        old.status,
+        (SELECT id FROM jobs
+         WHERE status = 'running' AND json_extract(payload, '$.env_id') = new.id
+         ORDER BY updated_at DESC LIMIT 1)
+    );
+END;
+
+CREATE TRIGGER environments_status_history_delete AFTER DELETE ON environments BEGIN
+    DELETE FROM environment_status_history WHERE environment_id = old.id;
+END;
@@ -0,0 +1,3 @@
+-- Issued when a run_task job is queued for the environment's task, and used
+-- up when that job starts the task, so a duplicate job can't start it again.
+ALTER TABLE environments ADD COLUMN claim_token TEXT;
@@ -0,0 +1,50 @@
+-- How each preparation of an environment ended, kept after the environment
+-- is removed so providers can be compared over time.
+CREATE TABLE environment_prepare_outcomes (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    environment_id TEXT NOT NULL,
+    provider TEXT NOT NULL,
+    succeeded INTEGER NOT NULL,
+    -- From the environment entering `preparing` to it leaving, retries
+    -- included.
+    duration_ms INTEGER NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX environment_prepare_outcomes_provider
+ON environment_prepare_outcomes (provider, seq);
+
+-- Preparations that already ended, as far as the status history goes back.
+INSERT INTO environment_prepare_outcomes
+    (environment_id, provider, succeeded, duration_ms, created_at)
+SELECT
+    h.environment_id,
+    e.provider,
+    h.status != 'failed',
+    COALESCE(CAST(ROUND((julianday(h.created_at) - julianday(
+        (SELECT p.created_at FROM environment_status_history p
+         WHERE p.environment_id = h.environment_id
+           AND p.status = 'preparing' AND p.seq < h.seq
+         ORDER BY p.seq DESC LIMIT 1)
+    )) * 86400000) AS INTEGER), 0),
+    h.created_at
+FROM environment_status_history h
+JOIN environments e ON e.id = h.environment_id
+WHERE h.previous_status = 'preparing' AND h.status IN ('pool', 'in_use', 'failed')
+ORDER BY h.seq;
+
+CREATE TRIGGER environments_prepare_outcome AFTER UPDATE OF status ON environments
+WHEN old.status = 'preparing' AND new.status IN ('pool', 'in_use', 'failed') BEGIN
+    INSERT INTO environment_prepare_outcomes
+        (environment_id, provider, succeeded, duration_ms)
+    VALUES (
+        new.id,
+        new.provider,
+        new.status != 'failed',
+        COALESCE(CAST(ROUND((julianday('now') - julianday(
+            (SELECT created_at FROM environment_status_history
+             WHERE environment_id = new.id AND status = 'preparing'
+             ORDER BY seq DESC LIMIT 1)
+        )) * 86400000) AS INTEGER), 0)
+    );
+END;
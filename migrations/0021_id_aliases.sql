@@ -0,0 +1,31 @@
+-- Short numbers for tasks and environments, shown as `t12` and `e7` and
+-- accepted wherever their IDs are. AUTOINCREMENT keeps a removed task's or
+-- environment's number from being given to a new one.
+CREATE TABLE task_aliases (
+    alias INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE environment_aliases (
+    alias INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE
+);
+
+INSERT INTO task_aliases (id) SELECT id FROM tasks ORDER BY created_at, id;
+INSERT INTO environment_aliases (id) SELECT id FROM environments ORDER BY created_at, id;
+
+CREATE TRIGGER tasks_alias_insert AFTER INSERT ON tasks BEGIN
+    INSERT INTO task_aliases (id) VALUES (new.id);
+END;
+
+CREATE TRIGGER tasks_alias_delete AFTER DELETE ON tasks BEGIN
+    DELETE FROM task_aliases WHERE id = old.id;
+END;
+
+CREATE TRIGGER environments_alias_insert AFTER INSERT ON environments BEGIN
+    INSERT INTO environment_aliases (id) VALUES (new.id);
+END;
+
+CREATE TRIGGER environments_alias_delete AFTER DELETE ON environments BEGIN
+    DELETE FROM environment_aliases WHERE id = old.id;
+END;
@@ -0,0 +1,45 @@
+-- When each task and environment last changed status, so lists can show how
+-- long something has been in its status and sort by it. Triggers fill these
+-- in from the updated_at of the statement that changed the status; every
+-- status change sets updated_at too. They're kept beside the rows rather than
+-- in them so filling them in doesn't update the rows and record events.
+CREATE TABLE task_status_times (
+    id TEXT PRIMARY KEY,
+    changed_at TEXT NOT NULL
+);
+
+CREATE TABLE environment_status_times (
+    id TEXT PRIMARY KEY,
+    changed_at TEXT NOT NULL
+);
+
+-- Existing rows have no record of when their status changed, so they start
+-- from their last update.
+INSERT INTO task_status_times (id, changed_at) SELECT id, updated_at FROM tasks;
+INSERT INTO environment_status_times (id, changed_at) SELECT id, updated_at FROM environments;
+
+CREATE TRIGGER tasks_status_time_insert AFTER INSERT ON tasks BEGIN
+    INSERT OR REPLACE INTO task_status_times (id, changed_at) VALUES (new.id, new.updated_at);
+END;
+
+CREATE TRIGGER tasks_status_time_update AFTER UPDATE OF status ON tasks
+WHEN old.status IS NOT new.status BEGIN
+    INSERT OR REPLACE INTO task_status_times (id, changed_at) VALUES (new.id, new.updated_at);
+END;
+
+CREATE TRIGGER tasks_status_time_delete AFTER DELETE ON tasks BEGIN
+    DELETE FROM task_status_times WHERE id = old.id;
+END;
+
+CREATE TRIGGER environments_status_time_insert AFTER INSERT ON environments BEGIN
+    INSERT OR REPLACE INTO environment_status_times (id, changed_at) VALUES (new.id, new.updated_at);
+END;
+
+CREATE TRIGGER environments_status_time_update AFTER UPDATE OF status ON environments
+WHEN old.status IS NOT new.status BEGIN
+    INSERT OR REPLACE INTO environment_status_times (id, changed_at) VALUES (new.id, new.updated_at);
+END;
+
+CREATE TRIGGER environments_status_time_delete AFTER DELETE ON environments BEGIN
+    DELETE FROM environment_status_times WHERE id = old.id;
+END;
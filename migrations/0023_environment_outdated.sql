@@ -0,0 +1,24 @@
+-- When a pooled environment fell behind its project's base branch, as found
+-- by the daemon's project watcher. NULL while it's current.
+ALTER TABLE environments ADD COLUMN outdated_since TEXT;
+
+-- Marking an environment outdated records its own event, and clearing the
+-- mark records an update, so the generic trigger skips both.
+DROP TRIGGER environments_event_update;
+
+CREATE TRIGGER environments_event_update AFTER UPDATE ON environments
+WHEN old.outdated_since IS new.outdated_since BEGIN
+    INSERT INTO events (kind, subject_id, action, status)
+    VALUES ('environment', new.id, 'updated', new.status);
+END;
+
+CREATE TRIGGER environments_event_outdated AFTER UPDATE OF outdated_since ON environments
+WHEN old.outdated_since IS NOT new.outdated_since BEGIN
+    INSERT INTO events (kind, subject_id, action, status)
+    VALUES (
+        'environment',
+        new.id,
+        CASE WHEN new.outdated_since IS NULL THEN 'updated' ELSE 'outdated' END,
+        new.status
+    );
+END;
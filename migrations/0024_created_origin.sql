@@ -0,0 +1,12 @@
+-- Where the request that created each task and environment came from: the
+-- kind of client (cli, tui, or api), and the host, user, and working
+-- directory it ran as. Rows from before this, and environments the daemon
+-- creates on its own, have none.
+ALTER TABLE tasks ADD COLUMN created_via TEXT;
+ALTER TABLE tasks ADD COLUMN created_host TEXT;
+ALTER TABLE tasks ADD COLUMN created_user TEXT;
+ALTER TABLE tasks ADD COLUMN created_cwd TEXT;
+ALTER TABLE environments ADD COLUMN created_via TEXT;
+ALTER TABLE environments ADD COLUMN created_host TEXT;
+ALTER TABLE environments ADD COLUMN created_user TEXT;
+ALTER TABLE environments ADD COLUMN created_cwd TEXT;
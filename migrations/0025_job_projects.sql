@@ -0,0 +1,11 @@
+-- The project each job works for, taken from the task or environment its
+-- payload names, so the claimer can take turns between projects rather than
+-- draining one project's backlog before another's first job. Jobs for no
+-- project, such as notifications, have none.
+ALTER TABLE jobs ADD COLUMN project_id TEXT;
+
+UPDATE jobs SET project_id = COALESCE(
+    (SELECT project_id FROM tasks WHERE id = json_extract(jobs.payload, '$.task_id')),
+    (SELECT project_id FROM environments WHERE id = json_extract(jobs.payload, '$.env_id'))
+)
+WHERE status IN ('pending', 'running');
@@ -0,0 +1,6 @@
+-- What a task's runner reported spending, from its structured result: model
+-- tokens in and out, and cost in US dollars. Each is null when the runner
+-- didn't say, so totals only count what was reported.
+ALTER TABLE tasks ADD COLUMN tokens_in INTEGER;
+ALTER TABLE tasks ADD COLUMN tokens_out INTEGER;
+ALTER TABLE tasks ADD COLUMN cost_usd REAL;
@@ -0,0 +1,19 @@
+-- Copies of an environment's files taken with `work env snapshot`, kept
+-- under the data directory until the environment is removed.
+CREATE TABLE environment_snapshots (
+    id TEXT PRIMARY KEY,
+    environment_id TEXT NOT NULL,
+    name TEXT,
+    -- What the provider needs to restore it, such as the method and path.
+    metadata TEXT NOT NULL,
+    size_bytes INTEGER,
+    created_at TEXT NOT NULL,
+    restored_at TEXT
+);
+
+CREATE INDEX environment_snapshots_environment
+ON environment_snapshots (environment_id, created_at);
+
+CREATE TRIGGER environments_snapshots_delete AFTER DELETE ON environments BEGIN
+    DELETE FROM environment_snapshots WHERE environment_id = old.id;
+END;
@@ -0,0 +1,5 @@
+-- Keep a task's description in the search index when it changes, not only
+-- when the task is created.
+CREATE TRIGGER tasks_search_update AFTER UPDATE OF description ON tasks BEGIN
+    UPDATE task_search SET description = new.description WHERE task_id = new.id;
+END;
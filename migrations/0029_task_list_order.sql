@@ -0,0 +1,2 @@
+-- Page through tasks newest first without sorting the whole table.
+CREATE INDEX tasks_created ON tasks (created_at, id);
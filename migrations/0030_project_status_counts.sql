@@ -0,0 +1,4 @@
+-- Count a project's tasks and environments by status from the index alone,
+-- so `work status` stays quick enough for a shell prompt as tasks pile up.
+CREATE INDEX tasks_project_status ON tasks (project_id, status);
+CREATE INDEX environments_project_status ON environments (project_id, status);
@@ -0,0 +1,19 @@
+-- Other names for a project, made when `work project new --adopt` finds its
+-- path already registered under another name. An alias is accepted wherever
+-- the project's name is, and no project may be named one.
+CREATE TABLE project_aliases (
+    alias TEXT PRIMARY KEY,
+    project_id TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX project_aliases_project ON project_aliases (project_id);
+
+CREATE TRIGGER projects_aliases_delete AFTER DELETE ON projects BEGIN
+    DELETE FROM project_aliases WHERE project_id = old.id;
+END;
+
+-- A project's row lists its aliases, so adding one changes the project.
+CREATE TRIGGER project_aliases_event_insert AFTER INSERT ON project_aliases BEGIN
+    INSERT INTO events (kind, subject_id, action) VALUES ('project', new.project_id, 'updated');
+END;
@@ -0,0 +1,12 @@
+-- Environments a snapshot or restore is working on. Tasks don't start in a
+-- locked environment and its jobs wait until the lock is released. The
+-- daemon clears them when it starts, since whatever held them is gone.
+CREATE TABLE environment_locks (
+    environment_id TEXT PRIMARY KEY,
+    operation TEXT NOT NULL,
+    locked_at TEXT NOT NULL
+);
+
+CREATE TRIGGER environments_locks_delete AFTER DELETE ON environments BEGIN
+    DELETE FROM environment_locks WHERE environment_id = old.id;
+END;
@@ -0,0 +1,161 @@
+//! Plain text from terminal output.
+//!
+//! Task runners started on a pty color and redraw their output, and the log
+//! keeps those escape sequences. Readers that want plain text, like search,
+//! the TUI, and `work task logs --plain`, strip them here; the TUI renders
+//! colors from the [`Piece`]s they split into.
+
+/// Drop ANSI escape sequences and other control characters from `text`,
+/// keeping newlines and tabs.
+pub fn strip(text: &str) -> String {
+    let stripped = Stripper::default().push(text.as_bytes());
+    String::from_utf8_lossy(&stripped).into_owned()
+}
+
+/// Plain text, and the SGR (`ESC [ ... m`) sequences that style it.
+#[derive(Debug, PartialEq)]
+pub enum Piece {
+    Text(String),
+    /// The parameters of an SGR sequence, such as `1;32`.
+    Sgr(String),
+}
+
+/// Split `text` into [`Piece`]s, for readers that render colors. Other
+/// escape sequences and control characters are dropped, as [`strip`] drops
+/// them.
+pub fn pieces(text: &str) -> Vec<Piece> {
+    let mut stripper = Stripper::default();
+    let mut pieces = Vec::new();
+    let mut text_bytes = Vec::new();
+    for &byte in text.as_bytes() {
+        match stripper.step(byte) {
+            Step::Keep => text_bytes.push(byte),
+            Step::Sgr => {
+                if !text_bytes.is_empty() {
+                    let bytes = std::mem::take(&mut text_bytes);
+                    pieces.push(Piece::Text(String::from_utf8_lossy(&bytes).into_owned()));
+                }
+                let params = String::from_utf8_lossy(&stripper.params).into_owned();
+                pieces.push(Piece::Sgr(params));
+            }
+            Step::Drop => {}
+        }
+    }
+    if !text_bytes.is_empty() {
+        pieces.push(Piece::Text(
+            String::from_utf8_lossy(&text_bytes).into_owned(),
+        ));
+    }
+    pieces
+}
+
+/// Strips escape sequences from output that arrives in chunks, carrying a
+/// sequence split across chunks over to the next one.
+#[derive(Default)]
+pub struct Stripper {
+    state: State,
+    /// The parameter bytes of the CSI sequence being read.
+    params: Vec<u8>,
+}
+
+#[derive(Default, Clone, Copy, PartialEq)]
+enum State {
+    #[default]
+    Text,
+    /// After ESC.
+    Escape,
+    /// Inside `ESC [`, which ends at the first byte in `@..=~`.
+    Csi,
+    /// Inside `ESC ]`, which ends at BEL or `ESC \`.
+    Osc,
+    /// After ESC inside an OSC sequence.
+    OscEscape,
+}
+
+/// What to do with a byte.
+enum Step {
+    Keep,
+    Drop,
+    /// The byte ended an SGR sequence, whose parameters are in `params`.
+    Sgr,
+}
+
+impl Stripper {
+    pub fn push(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(input.len());
+        for &byte in input {
+            if let Step::Keep = self.step(byte) {
+                out.push(byte);
+            }
+        }
+        out
+    }
+
+    fn step(&mut self, byte: u8) -> Step {
+        let (state, step) = match (self.state, byte) {
+            (State::Text, 0x1b) => (State::Escape, Step::Drop),
+            (State::Text, b'\n' | b'\t') => (State::Text, Step::Keep),
+            (State::Text, byte) if byte < 0x20 || byte == 0x7f => (State::Text, Step::Drop),
+            (State::Text, _) => (State::Text, Step::Keep),
+            (State::Escape, b'[') => {
+                self.params.clear();
+                (State::Csi, Step::Drop)
+            }
+            (State::Escape, b']') => (State::Osc, Step::Drop),
+            // Two-byte sequences such as `ESC =` or `ESC 7`.
+            (State::Escape, _) => (State::Text, Step::Drop),
+            (State::Csi, b'm') => (State::Text, Step::Sgr),
+            (State::Csi, b'@'..=b'~') => (State::Text, Step::Drop),
+            (State::Csi, byte) => {
+                self.params.push(byte);
+                (State::Csi, Step::Drop)
+            }
+            (State::Osc, 0x07) => (State::Text, Step::Drop),
+            (State::Osc, 0x1b) => (State::OscEscape, Step::Drop),
+            (State::Osc, _) => (State::Osc, Step::Drop),
+            (State::OscEscape, b'\\') => (State::Text, Step::Drop),
+            (State::OscEscape, _) => (State::Osc, Step::Drop),
+        };
+        self.state = state;
+        step
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_ansi_and_control_characters() {
+        assert_eq!(
+            strip("\x1b[1;32mok\x1b[0m done\r\n\x02next\tline"),
+            "ok done\nnext\tline"
+        );
+        assert_eq!(
+            strip("\x1b]0;title\x07a\x1b]8;;http://x\x1b\\b\x1b=c"),
+            "abc"
+        );
+    }
+
+    #[test]
+    fn carries_split_sequences_across_chunks() {
+        let mut stripper = Stripper::default();
+        let mut out = stripper.push(b"red \x1b[3");
+        out.extend(stripper.push(b"1mtext\x1b"));
+        out.extend(stripper.push("[0m é".as_bytes()));
+        assert_eq!(String::from_utf8(out).unwrap(), "red text é");
+    }
+
+    #[test]
+    fn pieces_keep_sgr_sequences_and_drop_the_rest() {
+        assert_eq!(
+            pieces("\x1b]0;title\x07a \x1b[1;32mok\x1b[2K\r\n\x1b[0m"),
+            vec![
+                Piece::Text("a ".to_string()),
+                Piece::Sgr("1;32".to_string()),
+                Piece::Text("ok\n".to_string()),
+                Piece::Sgr("0".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,408 @@
+use std::path::{Path, PathBuf};
+
+use work::client::DaemonClient;
+use work::db::{EventQuery, TERMINAL_TASK_STATUSES, Task};
+use work::redact::Redactor;
+use work::{config, paths};
+
+/// How much of the end of each log goes into a bundle.
+const LOG_TAIL_BYTES: u64 = 256 * 1024;
+
+/// Failed tasks whose logs are included when no task is given.
+const MAX_FAILED_TASK_LOGS: usize = 10;
+
+/// Config keys whose values are replaced, matched case-insensitively as
+/// substrings. Every value in an `env` table is replaced too.
+const SECRET_KEY_PARTS: &[&str] = &[
+    "token",
+    "secret",
+    "password",
+    "passwd",
+    "credential",
+    "api-key",
+    "api_key",
+    "apikey",
+];
+
+const REDACTED: &str = "[redacted]";
+
+/// Collect daemon logs, state, job history, redacted config, and log tails
+/// into a gzipped tarball at `output`. `[redact] patterns` are taken out of
+/// everything but the config, which has its secrets replaced by key. With `task_id`, state and logs are
+/// limited to that task and its environment. Anything that can't be
+/// collected is listed in the bundle's `manifest.json` rather than failing
+/// the bundle, since the daemon or database may be what's broken.
+pub async fn create(
+    client: &DaemonClient,
+    task_id: Option<&str>,
+    output: &Path,
+) -> anyhow::Result<()> {
+    if let Some(id) = task_id {
+        client.get_task(id).await?;
+    }
+    let name = format!("work-debug-{}", chrono::Utc::now().format("%Y%m%d-%H%M%S"));
+    let staging = tempfile::Builder::new()
+        .prefix(&format!("{name}-"))
+        .tempdir()?;
+    let root = staging.path().join(&name);
+    std::fs::create_dir_all(root.join("logs"))?;
+
+    let mut bundle = Bundle {
+        root: &root,
+        redactor: work::redact::configured()?,
+        problems: Vec::new(),
+    };
+    bundle.collect(client, task_id).await;
+    bundle.write_manifest(client, task_id)?;
+    archive(staging.path(), &name, output)
+}
+
+struct Bundle<'a> {
+    root: &'a Path,
+    redactor: Option<Redactor>,
+    /// What couldn't be collected, and why.
+    problems: Vec<String>,
+}
+
+impl Bundle<'_> {
+    async fn collect(&mut self, client: &DaemonClient, task_id: Option<&str>) {
+        if let Ok(state_dir) = paths::state_dir() {
+            for log in ["daemon.out.log", "daemon.err.log", "access.log", "tui.log"] {
+                self.copy_tail(&state_dir.join(log), &format!("logs/{log}"));
+            }
+        }
+        self.copy_config();
+
+        match client.list_projects().await {
+            Ok(projects) => self.write_json("projects.json", &projects),
+            Err(e) => self.problem("projects", e),
+        }
+
+        let tasks = match task_id {
+            Some(id) => client.get_task(id).await.map(|task| vec![task]),
+            None => client.list_tasks().await,
+        };
+        let tasks = match tasks {
+            Ok(tasks) => tasks,
+            Err(e) => {
+                self.problem("tasks", e);
+                Vec::new()
+            }
+        };
+        self.write_json("tasks.json", &tasks);
+
+        let environments = match client.list_environments().await {
+            Ok(envs) => envs
+                .into_iter()
+                .filter(|env| task_id.is_none() || tasks.iter().any(|t| t.environment_id == env.id))
+                .collect(),
+            Err(e) => {
+                self.problem("environments", e);
+                Vec::new()
+            }
+        };
+        self.write_json("environments.json", &environments);
+
+        match client.list_jobs().await {
+            Ok(jobs) => {
+                let jobs: Vec<_> = jobs
+                    .into_iter()
+                    .filter(|job| {
+                        task_id.is_none()
+                            || tasks.iter().any(|t| {
+                                job.payload["task_id"] == t.id.as_str()
+                                    || job.payload["env_id"] == t.environment_id.as_str()
+                            })
+                    })
+                    .collect();
+                self.write_json("jobs.json", &jobs);
+            }
+            Err(e) => self.problem("jobs", e),
+        }
+
+        let mut events = Vec::new();
+        let subjects: Vec<(&str, &str)> = match task_id {
+            Some(_) => tasks
+                .iter()
+                .flat_map(|t| {
+                    [
+                        ("task", t.id.as_str()),
+                        ("environment", t.environment_id.as_str()),
+                    ]
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        let queries: Vec<EventQuery> = if subjects.is_empty() {
+            vec![EventQuery::default()]
+        } else {
+            subjects
+                .iter()
+                .map(|(kind, id)| EventQuery {
+                    kind: Some(kind.to_string()),
+                    subject_id: Some(id.to_string()),
+                    ..EventQuery::default()
+                })
+                .collect()
+        };
+        for query in &queries {
+            match client.list_events(query).await {
+                Ok(found) => events.extend(found),
+                Err(e) => self.problem("events", e),
+            }
+        }
+        events.sort_by_key(|event| event.seq);
+        self.write_json("events.json", &events);
+
+        for task in logged_tasks(&tasks, task_id.is_some()) {
+            if let Ok(path) = paths::task_log_path(&task.id) {
+                self.copy_tail(&path, &format!("logs/tasks/{}.log", task.id));
+            }
+            if let Ok(path) = paths::environment_log_path(&task.environment_id) {
+                self.copy_tail(
+                    &path,
+                    &format!("logs/environments/{}.log", task.environment_id),
+                );
+            }
+        }
+    }
+
+    /// Copy the end of the log at `path`, if there is one.
+    fn copy_tail(&mut self, path: &Path, name: &str) {
+        // Bundles are made to be shared, so encrypted logs stay out of them.
+        if work::log_crypt::is_encrypted(path) {
+            self.problems.push(format!("{name}: encrypted, left out"));
+            return;
+        }
+        match tail(path, LOG_TAIL_BYTES) {
+            Ok(Some(contents)) => match &self.redactor {
+                Some(redactor) => {
+                    let redacted = redactor.redact_str(&contents).into_owned();
+                    self.write(name, redacted.as_bytes());
+                }
+                None => self.write(name, contents.as_bytes()),
+            },
+            Ok(None) => {}
+            Err(e) => self.problem(name, e),
+        }
+    }
+
+    fn copy_config(&mut self) {
+        let Ok(path) = config::path() else {
+            return;
+        };
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            return;
+        };
+        match redact_config(&text) {
+            Ok(redacted) => self.write("config.toml", redacted.as_bytes()),
+            // Unparseable config may still hold secrets, so leave it out.
+            Err(e) => self.problem("config.toml", e),
+        }
+    }
+
+    fn write_manifest(&self, client: &DaemonClient, task_id: Option<&str>) -> anyhow::Result<()> {
+        let manifest = serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "created_at": chrono::Utc::now().to_rfc3339(),
+            "task": task_id,
+            "daemon_running": !client.is_read_only(),
+            "problems": self.problems,
+        });
+        std::fs::write(
+            self.root.join("manifest.json"),
+            serde_json::to_string_pretty(&manifest)? + "\n",
+        )?;
+        Ok(())
+    }
+
+    fn write_json<T: serde::Serialize>(&mut self, name: &str, value: &T) {
+        let json = serde_json::to_value(value).and_then(|mut value| {
+            if let Some(redactor) = &self.redactor {
+                redactor.redact_json(&mut value);
+            }
+            serde_json::to_string_pretty(&value)
+        });
+        match json {
+            Ok(json) => self.write(name, (json + "\n").as_bytes()),
+            Err(e) => self.problem(name, e.into()),
+        }
+    }
+
+    fn write(&mut self, name: &str, contents: &[u8]) {
+        let path = self.root.join(name);
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| std::fs::write(&path, contents));
+        if let Err(e) = result {
+            self.problem(name, e.into());
+        }
+    }
+
+    fn problem(&mut self, what: &str, e: anyhow::Error) {
+        self.problems.push(format!("{what}: {e:#}"));
+    }
+}
+
+/// Tasks whose logs go in the bundle: the one asked about, or else the most
+/// recently created of those that finished without completing and weren't
+/// cancelled.
+fn logged_tasks(tasks: &[Task], requested: bool) -> Vec<&Task> {
+    if requested {
+        return tasks.iter().collect();
+    }
+    let mut failed: Vec<&Task> = tasks
+        .iter()
+        .filter(|t| {
+            TERMINAL_TASK_STATUSES.contains(&t.status.as_str())
+                && !matches!(t.status.as_str(), "complete" | "cancelled")
+        })
+        .collect();
+    failed.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    failed.truncate(MAX_FAILED_TASK_LOGS);
+    failed
+}
+
+/// The last `max_bytes` of the file at `path`, starting on a line boundary,
+/// or `None` if there is no such file.
+fn tail(path: &Path, max_bytes: u64) -> anyhow::Result<Option<String>> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let len = file.metadata()?.len();
+    let start = len.saturating_sub(max_bytes);
+    file.seek(SeekFrom::Start(start))?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    let mut text = String::from_utf8_lossy(&bytes).into_owned();
+    if start > 0 {
+        let skip = text.find('\n').map_or(0, |newline| newline + 1);
+        text = format!("[work: first {} bytes omitted]\n{}", start, &text[skip..]);
+    }
+    Ok(Some(text))
+}
+
+/// `text`, a config file, with secret-looking values replaced.
+fn redact_config(text: &str) -> anyhow::Result<String> {
+    let mut table: toml::Table = text.parse()?;
+    redact_table(&mut table);
+    Ok(toml::to_string(&table)?)
+}
+
+fn redact_table(table: &mut toml::Table) {
+    for (key, value) in table.iter_mut() {
+        if key == "env" || is_secret_key(key) {
+            redact_value(value);
+        } else {
+            match value {
+                toml::Value::Table(inner) => redact_table(inner),
+                toml::Value::Array(items) => {
+                    for item in items {
+                        if let toml::Value::Table(inner) = item {
+                            redact_table(inner);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Replace every leaf under `value`, keeping its keys for context.
+fn redact_value(value: &mut toml::Value) {
+    match value {
+        toml::Value::Table(inner) => {
+            for (_, value) in inner.iter_mut() {
+                redact_value(value);
+            }
+        }
+        toml::Value::Array(items) => {
+            for item in items {
+                redact_value(item);
+            }
+        }
+        _ => *value = toml::Value::String(REDACTED.to_string()),
+    }
+}
+
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    SECRET_KEY_PARTS.iter().any(|part| key.contains(part))
+}
+
+/// Write `staging/name` to `output` as a gzipped tarball.
+fn archive(staging: &Path, name: &str, output: &Path) -> anyhow::Result<()> {
+    let output = std::path::absolute(output)?;
+    let status = std::process::Command::new("tar")
+        .arg("-czf")
+        .arg(&output)
+        .arg("-C")
+        .arg(staging)
+        .arg(name)
+        .status()?;
+    if !status.success() {
+        anyhow::bail!("tar failed ({status})");
+    }
+    Ok(())
+}
+
+/// Where a bundle goes when `--output` isn't given.
+pub fn default_output() -> PathBuf {
+    PathBuf::from(format!(
+        "work-debug-{}.tar.gz",
+        chrono::Utc::now().format("%Y%m%d-%H%M%S")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_env_tables_and_secret_keys() {
+        let redacted = redact_config(
+            r#"
+[tasks.providers.agent]
+type = "command"
+command = "agent"
+env = { OPENAI_API_KEY = "sk-live", MODE = "fast" }
+
+[environments.providers.sandbox]
+type = "script"
+path = "/opt/sandbox.sh"
+api-token = "abc123"
+"#,
+        )
+        .unwrap();
+
+        assert!(!redacted.contains("sk-live"), "{redacted}");
+        assert!(!redacted.contains("fast"), "{redacted}");
+        assert!(!redacted.contains("abc123"), "{redacted}");
+        assert!(redacted.contains("OPENAI_API_KEY"), "{redacted}");
+        assert!(redacted.contains("/opt/sandbox.sh"), "{redacted}");
+        assert!(redacted.contains(r#"command = "agent""#), "{redacted}");
+    }
+
+    #[test]
+    fn tail_starts_on_a_line_boundary() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("log");
+        std::fs::write(&path, "first line\nsecond line\nthird line\n").unwrap();
+
+        assert_eq!(
+            tail(&path, 1024).unwrap().unwrap(),
+            "first line\nsecond line\nthird line\n"
+        );
+        assert_eq!(
+            tail(&path, 15).unwrap().unwrap(),
+            "[work: first 19 bytes omitted]\nthird line\n"
+        );
+        assert_eq!(tail(&dir.path().join("missing"), 10).unwrap(), None);
+    }
+}
@@ -1,28 +1,169 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
 
+use anyhow::Context as _;
 use http_body_util::{BodyExt, Full};
-use hyper::body::Bytes;
+use hyper::body::{Bytes, Incoming};
 use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::UnixStream;
+use tokio::process::{Child, ChildStdin, ChildStdout};
 
-use crate::db::{Environment, Project, Task};
+use crate::config::HostConfig;
+use crate::error::{Error, ErrorCode};
+use crate::models::{
+    CostStats, Environment, EnvironmentPlan, EnvironmentSnapshot, EnvironmentStatusChange, Event,
+    EventQuery, ImportConflict, ImportSummary, IntegrityProblem, Job, JobDetail, LogSection,
+    MaintenanceSummary, NewTask, PathMap, PeerStats, Project, ProjectRemoval, ProviderStats,
+    ReloadSummary, RemapSummary, RepairSummary, SearchHit, StateExport, Task, TaskCounts,
+    TaskGroup, TaskPage, TaskSort,
+};
 
+/// Connection state changes reported by [`DaemonClient::subscribe_events`].
 pub enum DaemonEvent {
+    /// The event stream connected to the daemon without a way to replay what
+    /// it missed, e.g. on first connect; refetch whatever you display.
     Connected,
-    Updated,
+    /// The event stream reconnected and the events missed while it was down
+    /// follow as [`DaemonEvent::Updated`], so there is nothing to refetch.
+    Resumed,
+    /// Daemon state changed.
+    Updated(Event),
+    /// The daemon went away. The client keeps retrying in the background.
     Disconnected,
 }
 
+/// HTTP client for the daemon's Unix socket API.
+///
+/// Errors returned by the daemon are surfaced as `anyhow` errors wrapping a
+/// [`crate::error::Error`]; use [`Error::code_of`] to branch on the code.
+#[derive(Clone)]
 pub struct DaemonClient {
-    socket_path: PathBuf,
+    endpoint: Endpoint,
+    /// How long to wait for a daemon started on demand, if one should be,
+    /// and where to report starting it.
+    auto_start: Option<(std::time::Duration, Progress)>,
+    /// The client requests say they come from, if any.
+    origin: Option<String>,
+    /// Called with the daemon's version from the first response it sends.
+    on_version: Option<VersionCheck>,
+    version_seen: Arc<std::sync::OnceLock<()>>,
+}
+
+/// Header naming the client of a request, such as `cli`. The rest of its
+/// [`Origin`](crate::models::Origin) comes from the socket's peer credentials, not the client.
+pub(crate) const ORIGIN_HEADER: &str = "x-work-client";
+
+/// Response header naming the version of `work` the daemon runs.
+pub(crate) const VERSION_HEADER: &str = "x-work-version";
+
+type VersionCheck = Arc<dyn Fn(&str) + Send + Sync>;
+
+type Progress = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Where the daemon listens.
+#[derive(Clone)]
+enum Endpoint {
+    Socket(PathBuf),
+    /// A daemon on another machine. Each connection runs
+    /// `work daemon proxy` there over SSH and speaks HTTP through its stdio.
+    Ssh(HostConfig),
+    /// No daemon: reads go straight to the database, and anything that
+    /// would change state fails.
+    Database,
 }
 
 impl DaemonClient {
+    /// Connect to the daemon socket in the default runtime directory, which
+    /// honors `WORK_HOME` and `XDG_RUNTIME_DIR`.
     pub fn new() -> anyhow::Result<Self> {
         let runtime_dir = crate::paths::runtime_dir()?;
-        Ok(Self {
-            socket_path: runtime_dir.join("work.sock"),
-        })
+        Ok(Self::with_socket_path(runtime_dir.join("work.sock")))
+    }
+
+    /// Connect to a daemon listening on a specific socket.
+    pub fn with_socket_path(socket_path: impl Into<PathBuf>) -> Self {
+        Self {
+            endpoint: Endpoint::Socket(socket_path.into()),
+            auto_start: None,
+            origin: None,
+            on_version: None,
+            version_seen: Arc::default(),
+        }
+    }
+
+    /// Connect to the daemon on another machine over SSH. The host needs
+    /// `work` installed and its daemon running.
+    pub fn over_ssh(host: HostConfig) -> Self {
+        Self {
+            endpoint: Endpoint::Ssh(host),
+            auto_start: None,
+            origin: None,
+            on_version: None,
+            version_seen: Arc::default(),
+        }
+    }
+
+    /// Read this machine's database directly instead of asking a daemon, for
+    /// when the daemon is stopped. Listing and fetching projects,
+    /// environments, tasks, groups, jobs, and events work; every other request fails
+    /// with a hint to start the daemon. Opens the database read-only for the
+    /// rest of the process.
+    pub fn read_only() -> Self {
+        crate::db::set_read_only();
+        Self {
+            endpoint: Endpoint::Database,
+            auto_start: None,
+            origin: None,
+            on_version: None,
+            version_seen: Arc::default(),
+        }
+    }
+
+    /// When a request can't connect to the local daemon, start it in the
+    /// background, wait up to `timeout` for it to be ready, and retry the
+    /// request once. Progress messages are passed to `report`.
+    pub fn auto_start(
+        mut self,
+        timeout: std::time::Duration,
+        report: impl Fn(&str) + Send + Sync + 'static,
+    ) -> Self {
+        self.auto_start = Some((timeout, Arc::new(report)));
+        self
+    }
+
+    /// Tell the daemon that requests come from `client`, such as `cli` or
+    /// `tui`. The daemon records it on the tasks and environments they
+    /// create, with the user and directory it finds on the other end of the
+    /// socket; requests that don't say are recorded as from `api`.
+    pub fn origin(mut self, client: &str) -> Self {
+        self.origin = Some(client.to_string());
+        self
+    }
+
+    /// Call `check` once with the version of `work` the daemon runs, as
+    /// reported on the first response to any request. Daemons older than
+    /// this report an empty string.
+    pub fn on_version(mut self, check: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.on_version = Some(Arc::new(check));
+        self
+    }
+
+    /// Whether this client reads the database directly because the daemon
+    /// is stopped.
+    pub fn is_read_only(&self) -> bool {
+        matches!(self.endpoint, Endpoint::Database)
+    }
+
+    /// The machine the daemon runs on, if not this one. Its environments'
+    /// paths and provider commands are only usable there.
+    pub fn host(&self) -> Option<&HostConfig> {
+        match &self.endpoint {
+            Endpoint::Ssh(host) => Some(host),
+            Endpoint::Socket(_) | Endpoint::Database => None,
+        }
     }
 
     async fn request(
@@ -31,78 +172,238 @@ impl DaemonClient {
         uri: &str,
         body: Option<&str>,
     ) -> anyhow::Result<(hyper::StatusCode, String)> {
-        let stream = UnixStream::connect(&self.socket_path).await.map_err(|e| {
-            anyhow::anyhow!(
-                "could not connect to daemon at {}: {e}\nIs the daemon running? Start it with: work daemon start",
-                self.socket_path.display(),
-            )
-        })?;
-
-        let io = TokioIo::new(stream);
-        let (mut sender, conn) = hyper::client::conn::http1::handshake(io).await?;
-        tokio::spawn(conn);
+        let (status, bytes) = self.request_bytes(method, uri, body).await?;
+        Ok((status, String::from_utf8(bytes.to_vec())?))
+    }
 
+    /// Like `request`, for responses that may not be UTF-8, such as logs.
+    async fn request_bytes(
+        &self,
+        method: hyper::Method,
+        uri: &str,
+        body: Option<&str>,
+    ) -> anyhow::Result<(hyper::StatusCode, Bytes)> {
         let req_body = match body {
             Some(b) => Full::new(Bytes::from(b.to_owned())),
             None => Full::new(Bytes::new()),
         };
 
-        let mut builder = hyper::Request::builder()
-            .method(method)
-            .uri(uri)
-            .header("host", "localhost");
+        let build = || {
+            let mut builder = hyper::Request::builder()
+                .method(method.clone())
+                .uri(uri)
+                .header("host", "localhost");
+            if body.is_some() {
+                builder = builder.header("content-type", "application/json");
+            }
+            if let Some(client) = &self.origin {
+                builder = builder.header(ORIGIN_HEADER, client);
+            }
+            builder.body(req_body.clone())
+        };
 
-        if body.is_some() {
-            builder = builder.header("content-type", "application/json");
+        let res = match self.endpoint.send(build()?).await {
+            Err(e) if e.is::<NotRunning>() && self.auto_start.is_some() => {
+                self.start_daemon().await?;
+                self.endpoint.send(build()?).await?
+            }
+            res => res?,
+        };
+        if let Some(check) = &self.on_version {
+            let version = res
+                .headers()
+                .get(VERSION_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            self.version_seen.get_or_init(|| check(version));
         }
-
-        let req = builder.body(req_body)?;
-        let res = sender.send_request(req).await?;
         let status = res.status();
-        let res_bytes = res.into_body().collect().await?.to_bytes();
-        let text = String::from_utf8(res_bytes.to_vec())?;
+        Ok((status, res.into_body().collect().await?.to_bytes()))
+    }
 
-        Ok((status, text))
+    async fn start_daemon(&self) -> anyhow::Result<()> {
+        let Some((timeout, report)) = &self.auto_start else {
+            return Ok(());
+        };
+        report("the daemon is not running; starting it");
+        // Boxed because waiting for readiness sends requests of its own.
+        match Box::pin(crate::daemon::ensure_running(*timeout)).await {
+            Ok(Some(pid)) => report(&format!("daemon started (pid {pid})")),
+            Ok(None) => {}
+            Err(e) => {
+                return Err(e.context("could not start the daemon (auto-start-daemon is set)"));
+            }
+        }
+        Ok(())
     }
 
+    /// List registered projects, ordered by name.
     pub async fn list_projects(&self) -> anyhow::Result<Vec<Project>> {
+        if self.is_read_only() {
+            return crate::db::list_projects();
+        }
         let (status, body) = self.request(hyper::Method::GET, "/projects", None).await?;
         if !status.is_success() {
-            anyhow::bail!("{}", extract_error(&body));
+            return Err(api_error(status, &body).into());
         }
         Ok(serde_json::from_str(&body)?)
     }
 
-    pub async fn create_project(&self, name: &str, path: &str) -> anyhow::Result<()> {
-        let payload = serde_json::json!({"name": name, "path": path}).to_string();
+    /// Register a project rooted at `path`. A path inside or around a
+    /// registered project's fails with [`ErrorCode::ProjectPathOverlaps`]
+    /// unless `nested` is set.
+    pub async fn create_project(&self, name: &str, path: &str, nested: bool) -> anyhow::Result<()> {
+        let payload = serde_json::json!({"name": name, "path": path, "nested": nested}).to_string();
         let (status, body) = self
             .request(hyper::Method::POST, "/projects", Some(&payload))
             .await?;
         if !status.is_success() {
-            anyhow::bail!("{}", extract_error(&body));
+            return Err(api_error(status, &body).into());
         }
         Ok(())
     }
 
-    pub async fn delete_project(&self, name: &str) -> anyhow::Result<()> {
-        let uri = format!("/projects/{name}");
+    /// Let the project `name` answer to `alias` too.
+    pub async fn add_project_alias(&self, name: &str, alias: &str) -> anyhow::Result<Project> {
+        let payload = serde_json::json!({ "alias": alias }).to_string();
+        let uri = format!("/projects/{name}/aliases");
+        let (status, body) = self
+            .request(hyper::Method::POST, &uri, Some(&payload))
+            .await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Remove a project by name, queueing removal of its environments. A
+    /// project with tasks fails with [`ErrorCode::ProjectInUse`] unless
+    /// `force` is set, which removes its tasks as
+    /// [`remove_task`](Self::remove_task) would.
+    pub async fn delete_project(&self, name: &str, force: bool) -> anyhow::Result<ProjectRemoval> {
+        let uri = format!("/projects/{name}?force={force}");
         let (status, body) = self.request(hyper::Method::DELETE, &uri, None).await?;
         if !status.is_success() {
-            anyhow::bail!("{}", extract_error(&body));
+            return Err(api_error(status, &body).into());
         }
-        Ok(())
+        Ok(serde_json::from_str(&body)?)
     }
 
+    /// Delete all daemon state.
     pub async fn reset_database(&self) -> anyhow::Result<()> {
         let (status, body) = self
             .request(hyper::Method::POST, "/reset-database", None)
             .await?;
         if !status.is_success() {
-            anyhow::bail!("{}", extract_error(&body));
+            return Err(api_error(status, &body).into());
         }
         Ok(())
     }
 
+    /// Have the daemon re-read and check its config, like SIGHUP. Returns the
+    /// settings that changed since the last load.
+    pub async fn reload_config(&self) -> anyhow::Result<ReloadSummary> {
+        let (status, body) = self
+            .request(hyper::Method::POST, "/config/reload", None)
+            .await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Requests each peer has made since the daemon started.
+    pub async fn list_peers(&self) -> anyhow::Result<Vec<PeerStats>> {
+        let (status, body) = self.request(hyper::Method::GET, "/peers", None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Export projects, plus finished tasks when `include_tasks` is set.
+    pub async fn export_state(&self, include_tasks: bool) -> anyhow::Result<StateExport> {
+        let uri = if include_tasks {
+            "/export?tasks=true"
+        } else {
+            "/export"
+        };
+        let (status, body) = self.request(hyper::Method::GET, uri, None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Load an export produced by [`DaemonClient::export_state`]. Nothing is
+    /// imported if any project conflicts under `on_conflict`.
+    pub async fn import_state(
+        &self,
+        state: &StateExport,
+        on_conflict: ImportConflict,
+    ) -> anyhow::Result<ImportSummary> {
+        let payload = serde_json::json!({ "state": state, "on_conflict": on_conflict }).to_string();
+        let (status, body) = self
+            .request(hyper::Method::POST, "/import", Some(&payload))
+            .await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Rewrite project paths and environment metadata paths in place.
+    pub async fn remap_paths(&self, maps: &[PathMap]) -> anyhow::Result<RemapSummary> {
+        let payload = serde_json::json!({ "maps": maps }).to_string();
+        let (status, body) = self
+            .request(hyper::Method::POST, "/remap-paths", Some(&payload))
+            .await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Look for rows that refer to missing rows or paths, or are stuck in a
+    /// state nothing will move them out of.
+    pub async fn check_integrity(&self) -> anyhow::Result<Vec<IntegrityProblem>> {
+        if self.is_read_only() {
+            return crate::db::check_integrity();
+        }
+        let (status, body) = self.request(hyper::Method::GET, "/db/check", None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Fix the problems named by `keys`, as returned by
+    /// [`IntegrityProblem::key`]. Problems already gone are skipped.
+    pub async fn repair_integrity(&self, keys: &[String]) -> anyhow::Result<RepairSummary> {
+        let payload = serde_json::json!({ "keys": keys }).to_string();
+        let (status, body) = self
+            .request(hyper::Method::POST, "/db/repair", Some(&payload))
+            .await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Prune old completed jobs and events and compact the database now,
+    /// rather than waiting for the daemon's next maintenance pass.
+    pub async fn maintain_database(&self) -> anyhow::Result<MaintenanceSummary> {
+        let (status, body) = self
+            .request(hyper::Method::POST, "/db/maintain", None)
+            .await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Queue an environment prepare. With `claim_after_prepare`, the
+    /// environment is claimed once ready instead of joining the pool.
     pub async fn prepare_environment(
         &self,
         project_id: &str,
@@ -119,48 +420,240 @@ impl DaemonClient {
             .request(hyper::Method::POST, "/environments", Some(&payload))
             .await?;
         if !status.is_success() {
-            anyhow::bail!("{}", extract_error(&body));
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Ask a provider what preparing an environment for a project would do,
+    /// without doing it.
+    pub async fn plan_environment(
+        &self,
+        project_id: &str,
+        provider: &str,
+    ) -> anyhow::Result<EnvironmentPlan> {
+        let payload = serde_json::json!({
+            "project_id": project_id,
+            "provider": provider,
+        })
+        .to_string();
+        let (status, body) = self
+            .request(hyper::Method::POST, "/environments/plan", Some(&payload))
+            .await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Register an existing checkout at `path` as an in-use environment.
+    pub async fn adopt_environment(
+        &self,
+        project_id: &str,
+        provider: &str,
+        path: &str,
+    ) -> anyhow::Result<Environment> {
+        let payload = serde_json::json!({
+            "project_id": project_id,
+            "provider": provider,
+            "path": path,
+        })
+        .to_string();
+        let (status, body) = self
+            .request(hyper::Method::POST, "/environments/adopt", Some(&payload))
+            .await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
         }
         Ok(serde_json::from_str(&body)?)
     }
 
+    /// List all environments.
     pub async fn list_environments(&self) -> anyhow::Result<Vec<Environment>> {
+        if self.is_read_only() {
+            return crate::db::list_environments();
+        }
         let (status, body) = self
             .request(hyper::Method::GET, "/environments", None)
             .await?;
         if !status.is_success() {
-            anyhow::bail!("{}", extract_error(&body));
+            return Err(api_error(status, &body).into());
         }
         Ok(serde_json::from_str(&body)?)
     }
 
+    /// Fetch an environment by ID.
     pub async fn get_environment(&self, id: &str) -> anyhow::Result<Environment> {
+        if self.is_read_only() {
+            return crate::db::get_environment(&crate::db::resolve_environment_id(id)?);
+        }
         let uri = format!("/environments/{id}");
         let (status, body) = self.request(hyper::Method::GET, &uri, None).await?;
         if !status.is_success() {
-            anyhow::bail!("{}", extract_error(&body));
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// An environment's provider log as it is now.
+    pub async fn environment_log(&self, id: &str) -> anyhow::Result<Vec<u8>> {
+        if self.is_read_only() {
+            let path = crate::paths::environment_log_path(&crate::db::resolve_environment_id(id)?)?;
+            return match std::fs::read(path) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+                result => crate::redact::log(result?),
+            };
+        }
+        let uri = format!("/environments/{id}/logs?follow=false");
+        let (status, body) = self.request_bytes(hyper::Method::GET, &uri, None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &String::from_utf8_lossy(&body)).into());
+        }
+        Ok(body.to_vec())
+    }
+
+    /// How much disk an environment's worktree takes on the daemon's
+    /// machine, in KB, if it can be measured.
+    pub async fn environment_disk_usage(&self, id: &str) -> anyhow::Result<Option<u64>> {
+        if self.is_read_only() {
+            let env = crate::db::get_environment(&crate::db::resolve_environment_id(id)?)?;
+            let Some(path) = env.metadata["worktree_path"].as_str().map(str::to_string) else {
+                return Ok(None);
+            };
+            return Ok(tokio::task::spawn_blocking(move || {
+                crate::environment::disk_usage_kb(&path)
+            })
+            .await?);
+        }
+        let uri = format!("/environments/{id}/disk-usage");
+        let (status, body) = self.request(hyper::Method::GET, &uri, None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        let usage: serde_json::Value = serde_json::from_str(&body)?;
+        Ok(usage["kb"].as_u64())
+    }
+
+    /// Fetch an environment's status changes, oldest first.
+    pub async fn environment_status_history(
+        &self,
+        id: &str,
+    ) -> anyhow::Result<Vec<EnvironmentStatusChange>> {
+        if self.is_read_only() {
+            return crate::db::environment_status_history(&crate::db::resolve_environment_id(id)?);
+        }
+        let uri = format!("/environments/{id}/history");
+        let (status, body) = self.request(hyper::Method::GET, &uri, None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Snapshots of the environment with ID `id`, newest first.
+    pub async fn list_environment_snapshots(
+        &self,
+        id: &str,
+    ) -> anyhow::Result<Vec<EnvironmentSnapshot>> {
+        if self.is_read_only() {
+            return crate::db::list_environment_snapshots(&crate::db::resolve_environment_id(id)?);
+        }
+        let uri = format!("/environments/{id}/snapshots");
+        let (status, body) = self.request(hyper::Method::GET, &uri, None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Save the files of the environment with ID `id`, optionally under
+    /// `name`.
+    pub async fn snapshot_environment(
+        &self,
+        id: &str,
+        name: Option<&str>,
+    ) -> anyhow::Result<EnvironmentSnapshot> {
+        let uri = format!("/environments/{id}/snapshots");
+        let payload = serde_json::json!({ "name": name }).to_string();
+        let (status, body) = self
+            .request(hyper::Method::POST, &uri, Some(&payload))
+            .await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Put the files of the environment with ID `id` back as `snapshot`, an
+    /// ID, unique ID prefix, or name, saved them.
+    pub async fn restore_environment(
+        &self,
+        id: &str,
+        snapshot: &str,
+    ) -> anyhow::Result<EnvironmentSnapshot> {
+        let uri = format!("/environments/{id}/restore");
+        let payload = serde_json::json!({ "snapshot": snapshot }).to_string();
+        let (status, body) = self
+            .request(hyper::Method::POST, &uri, Some(&payload))
+            .await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Stats for each environment provider that has been used, over its last
+    /// `last` preparations.
+    pub async fn provider_stats(&self, last: usize) -> anyhow::Result<Vec<ProviderStats>> {
+        if self.is_read_only() {
+            return crate::db::provider_stats(last);
+        }
+        let uri = format!("/environments/providers?last={last}");
+        let (status, body) = self.request(hyper::Method::GET, &uri, None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
         }
         Ok(serde_json::from_str(&body)?)
     }
 
+    /// Reported task costs by project and month, for the project with ID
+    /// `project_id` or every project.
+    pub async fn cost_stats(&self, project_id: Option<&str>) -> anyhow::Result<Vec<CostStats>> {
+        if self.is_read_only() {
+            return crate::db::cost_stats(project_id);
+        }
+        let uri = match project_id {
+            Some(id) => format!("/stats/costs?project={id}"),
+            None => "/stats/costs".to_string(),
+        };
+        let (status, body) = self.request(hyper::Method::GET, &uri, None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Queue an update of a pooled environment.
     pub async fn update_environment(&self, id: &str) -> anyhow::Result<Environment> {
         let uri = format!("/environments/{id}/update");
         let (status, body) = self.request(hyper::Method::POST, &uri, None).await?;
         if !status.is_success() {
-            anyhow::bail!("{}", extract_error(&body));
+            return Err(api_error(status, &body).into());
         }
         Ok(serde_json::from_str(&body)?)
     }
 
+    /// Claim a pooled environment by ID.
     pub async fn claim_environment(&self, id: &str) -> anyhow::Result<Environment> {
         let uri = format!("/environments/{id}/claim");
         let (status, body) = self.request(hyper::Method::POST, &uri, None).await?;
         if !status.is_success() {
-            anyhow::bail!("{}", extract_error(&body));
+            return Err(api_error(status, &body).into());
         }
         Ok(serde_json::from_str(&body)?)
     }
 
+    /// Claim the next pooled environment for a project and provider.
     pub async fn claim_next_environment(
         &self,
         provider: &str,
@@ -172,11 +665,39 @@ impl DaemonClient {
             .request(hyper::Method::POST, "/environments/claim", Some(&payload))
             .await?;
         if !status.is_success() {
-            anyhow::bail!("{}", extract_error(&body));
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Claim `count` environments for a project and provider at once,
+    /// preparing new ones for whatever the pool can't cover.
+    pub async fn claim_environments(
+        &self,
+        provider: &str,
+        project_id: &str,
+        count: usize,
+    ) -> anyhow::Result<Vec<Environment>> {
+        let payload = serde_json::json!({
+            "provider": provider,
+            "project_id": project_id,
+            "count": count
+        })
+        .to_string();
+        let (status, body) = self
+            .request(
+                hyper::Method::POST,
+                "/environments/claim-batch",
+                Some(&payload),
+            )
+            .await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
         }
         Ok(serde_json::from_str(&body)?)
     }
 
+    /// Remove an environment, optionally without calling its provider.
     pub async fn remove_environment(&self, id: &str, skip_provider: bool) -> anyhow::Result<()> {
         let uri = if skip_provider {
             format!("/environments/{id}?skip_provider=true")
@@ -185,51 +706,182 @@ impl DaemonClient {
         };
         let (status, body) = self.request(hyper::Method::DELETE, &uri, None).await?;
         if !status.is_success() {
-            anyhow::bail!("{}", extract_error(&body));
+            return Err(api_error(status, &body).into());
         }
         Ok(())
     }
 
-    pub async fn create_task(
-        &self,
-        project_id: &str,
-        provider: &str,
-        env_provider: &str,
-        description: &str,
-    ) -> anyhow::Result<Task> {
-        let payload = serde_json::json!({
-            "project_id": project_id,
-            "provider": provider,
-            "env_provider": env_provider,
-            "description": description,
-        })
-        .to_string();
+    /// Create a task and queue it to run. With `source_ref`, the task's
+    /// environment is based on that branch or commit instead of the base
+    /// branch. Without `env_provider`, the daemon picks a provider tagged with
+    /// every one of the task's `requirements`.
+    pub async fn create_task(&self, task: &NewTask) -> anyhow::Result<Task> {
+        let payload = serde_json::to_string(task)?;
         let (status, body) = self
             .request(hyper::Method::POST, "/tasks", Some(&payload))
             .await?;
         if !status.is_success() {
-            anyhow::bail!("{}", extract_error(&body));
+            return Err(api_error(status, &body).into());
         }
         Ok(serde_json::from_str(&body)?)
     }
 
+    /// List tasks, newest first.
     pub async fn list_tasks(&self) -> anyhow::Result<Vec<Task>> {
+        if self.is_read_only() {
+            return crate::db::list_tasks()?
+                .into_iter()
+                .map(crate::redact::task)
+                .collect();
+        }
         let (status, body) = self.request(hyper::Method::GET, "/tasks", None).await?;
         if !status.is_success() {
-            anyhow::bail!("{}", extract_error(&body));
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Fetch part of the task list, filtered and in order as `page` says.
+    /// Newest first, the next page starts at the [`cursor`](Task::cursor)
+    /// of the last task in this one.
+    pub async fn list_tasks_page(&self, page: &TaskPage) -> anyhow::Result<Vec<Task>> {
+        if self.is_read_only() {
+            return crate::db::page_tasks(page)?
+                .into_iter()
+                .map(crate::redact::task)
+                .collect();
+        }
+        let mut params = Vec::new();
+        if let Some(limit) = page.limit {
+            params.push(format!("limit={limit}"));
+        }
+        if let Some(cursor) = &page.cursor {
+            params.push(format!("cursor={}", encode_query_value(cursor)));
+        }
+        if let Some(offset) = page.offset {
+            params.push(format!("offset={offset}"));
+        }
+        if let Some(status) = &page.status {
+            params.push(format!("status={}", encode_query_value(status)));
+        }
+        if let Some(project) = &page.project {
+            params.push(format!("project={}", encode_query_value(project)));
+        }
+        if page.sort != TaskSort::Created {
+            let sort = serde_json::to_value(page.sort)?;
+            params.push(format!("sort={}", sort.as_str().unwrap_or_default()));
+        }
+        let mut uri = "/tasks".to_string();
+        if !params.is_empty() {
+            uri.push('?');
+            uri.push_str(&params.join("&"));
+        }
+        let (status, body) = self.request(hyper::Method::GET, &uri, None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Count tasks, in all and by project, without listing them.
+    pub async fn count_tasks(&self) -> anyhow::Result<TaskCounts> {
+        if self.is_read_only() {
+            return crate::db::count_tasks();
+        }
+        let (status, body) = self
+            .request(hyper::Method::GET, "/tasks/counts", None)
+            .await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
         }
         Ok(serde_json::from_str(&body)?)
     }
 
+    /// A task's log as it is now, redacted by the daemon's patterns.
+    pub async fn task_log(&self, id: &str) -> anyhow::Result<Vec<u8>> {
+        if self.is_read_only() {
+            let path = crate::paths::task_log_path(&crate::db::resolve_task_id(id)?)?;
+            let contents = match crate::log_crypt::read(&path) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                result => result?,
+            };
+            return crate::redact::log(contents);
+        }
+        let uri = format!("/tasks/{id}/logs?follow=false");
+        let (status, body) = self.request_bytes(hyper::Method::GET, &uri, None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &String::from_utf8_lossy(&body)).into());
+        }
+        Ok(body.to_vec())
+    }
+
+    /// Fetch a task by ID.
     pub async fn get_task(&self, id: &str) -> anyhow::Result<Task> {
+        if self.is_read_only() {
+            return crate::redact::task(crate::db::get_task(&crate::db::resolve_task_id(id)?)?);
+        }
         let uri = format!("/tasks/{id}");
         let (status, body) = self.request(hyper::Method::GET, &uri, None).await?;
         if !status.is_success() {
-            anyhow::bail!("{}", extract_error(&body));
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Add and remove labels on a task.
+    pub async fn update_task_labels(
+        &self,
+        id: &str,
+        add: &[String],
+        remove: &[String],
+    ) -> anyhow::Result<Task> {
+        let uri = format!("/tasks/{id}/labels");
+        let payload = serde_json::json!({ "add": add, "remove": remove }).to_string();
+        let (status, body) = self
+            .request(hyper::Method::POST, &uri, Some(&payload))
+            .await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Add a note to a task, which also goes into its log.
+    pub async fn add_task_note(&self, id: &str, text: &str) -> anyhow::Result<Task> {
+        let uri = format!("/tasks/{id}/notes");
+        let payload = serde_json::json!({ "text": text }).to_string();
+        let (status, body) = self
+            .request(hyper::Method::POST, &uri, Some(&payload))
+            .await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Search task descriptions, and task logs when `include_logs` is set.
+    /// Results are ordered by relevance.
+    pub async fn search_tasks(
+        &self,
+        query: &str,
+        include_logs: bool,
+        limit: Option<usize>,
+    ) -> anyhow::Result<Vec<SearchHit>> {
+        let mut uri = format!("/search?q={}", encode_query_value(query));
+        if include_logs {
+            uri.push_str("&logs=true");
+        }
+        if let Some(limit) = limit {
+            uri.push_str(&format!("&limit={limit}"));
+        }
+        let (status, body) = self.request(hyper::Method::GET, &uri, None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
         }
         Ok(serde_json::from_str(&body)?)
     }
 
+    /// Remove a task and its environment.
     pub async fn remove_task(&self, id: &str, skip_provider: bool) -> anyhow::Result<()> {
         let uri = if skip_provider {
             format!("/tasks/{id}?skip_provider=true")
@@ -238,18 +890,175 @@ impl DaemonClient {
         };
         let (status, body) = self.request(hyper::Method::DELETE, &uri, None).await?;
         if !status.is_success() {
-            anyhow::bail!("{}", extract_error(&body));
+            return Err(api_error(status, &body).into());
+        }
+        Ok(())
+    }
+
+    /// Create an empty task group in a project. Add tasks to it by setting
+    /// [`NewTask::group_id`].
+    pub async fn create_task_group(
+        &self,
+        project_id: &str,
+        description: &str,
+    ) -> anyhow::Result<TaskGroup> {
+        let payload = serde_json::json!({
+            "project_id": project_id,
+            "description": description,
+        })
+        .to_string();
+        let (status, body) = self
+            .request(hyper::Method::POST, "/groups", Some(&payload))
+            .await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// List task groups with their rolled-up status, newest first.
+    pub async fn list_task_groups(&self) -> anyhow::Result<Vec<TaskGroup>> {
+        if self.is_read_only() {
+            return crate::db::list_task_groups();
+        }
+        let (status, body) = self.request(hyper::Method::GET, "/groups", None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Fetch a task group by ID.
+    pub async fn get_task_group(&self, id: &str) -> anyhow::Result<TaskGroup> {
+        if self.is_read_only() {
+            return crate::db::get_task_group(id);
+        }
+        let uri = format!("/groups/{id}");
+        let (status, body) = self.request(hyper::Method::GET, &uri, None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Remove a task group. A group with tasks fails with
+    /// [`ErrorCode::GroupNotEmpty`] unless `cascade` is set, which removes
+    /// its tasks as [`remove_task`](Self::remove_task) would.
+    pub async fn remove_task_group(
+        &self,
+        id: &str,
+        cascade: bool,
+        skip_provider: bool,
+    ) -> anyhow::Result<()> {
+        let uri = format!("/groups/{id}?cascade={cascade}&skip_provider={skip_provider}");
+        let (status, body) = self.request(hyper::Method::DELETE, &uri, None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
         }
         Ok(())
     }
 
+    /// Whether the daemon has finished starting up. `Ok(false)` means it is
+    /// reachable but still initializing.
+    pub async fn ready(&self) -> anyhow::Result<bool> {
+        let (status, _) = self.request(hyper::Method::GET, "/ready", None).await?;
+        Ok(status.is_success())
+    }
+
+    /// The version of the `work` binary the daemon is running, or `None`
+    /// when reading the database directly. Daemons older than this check
+    /// report an empty string.
+    pub async fn daemon_version(&self) -> anyhow::Result<Option<String>> {
+        if self.is_read_only() {
+            return Ok(None);
+        }
+        let (status, body) = self.request(hyper::Method::GET, "/health", None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        let health: serde_json::Value = serde_json::from_str(&body)?;
+        Ok(Some(
+            health["version"].as_str().unwrap_or_default().to_string(),
+        ))
+    }
+
+    /// Retry until the daemon is reachable and ready, or `timeout` elapses.
+    pub async fn wait_until_ready(&self, timeout: std::time::Duration) -> anyhow::Result<()> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let last = match self.ready().await {
+                Ok(true) => return Ok(()),
+                Ok(false) => "daemon is still starting".to_string(),
+                Err(e) => e.to_string(),
+            };
+            if tokio::time::Instant::now() >= deadline {
+                anyhow::bail!("daemon not ready after {}s: {last}", timeout.as_secs_f64());
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+    }
+
+    /// List background jobs, newest first.
+    pub async fn list_jobs(&self) -> anyhow::Result<Vec<Job>> {
+        if self.is_read_only() {
+            return crate::db::list_jobs();
+        }
+        let (status, body) = self.request(hyper::Method::GET, "/jobs", None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Fetch a job and the retry policy the daemon applies to it.
+    pub async fn get_job(&self, id: &str) -> anyhow::Result<JobDetail> {
+        if self.is_read_only() {
+            let job = crate::db::get_job(id)?;
+            let retry_policy = crate::config::load()
+                .map(|config| config.retry_policy(&job.job_type))
+                .unwrap_or_else(|_| crate::config::RetryPolicy::default_for(&job.job_type));
+            return Ok(JobDetail { job, retry_policy });
+        }
+        let uri = format!("/jobs/{id}");
+        let (status, body) = self.request(hyper::Method::GET, &uri, None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Requeue a dead job for a fresh set of attempts.
+    pub async fn retry_job(&self, id: &str) -> anyhow::Result<Job> {
+        let uri = format!("/jobs/{id}/retry");
+        let (status, body) = self.request(hyper::Method::POST, &uri, None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Delete every dead job, returning how many were removed.
+    pub async fn purge_dead_jobs(&self) -> anyhow::Result<usize> {
+        let (status, body) = self
+            .request(hyper::Method::DELETE, "/jobs/dead", None)
+            .await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        let value: serde_json::Value = serde_json::from_str(&body)?;
+        Ok(value["purged"].as_u64().unwrap_or(0) as usize)
+    }
+
+    /// Subscribe to daemon state changes. Reconnects automatically until the
+    /// receiver is dropped, resuming from the last event seen.
     pub fn subscribe_events(&self) -> tokio::sync::mpsc::Receiver<DaemonEvent> {
         let (tx, rx) = tokio::sync::mpsc::channel(64);
-        let socket_path = self.socket_path.clone();
+        let endpoint = self.endpoint.clone();
 
         tokio::spawn(async move {
+            let mut last_seq = None;
             loop {
-                if let Err(e) = Self::stream_events(&socket_path, &tx).await {
+                if let Err(e) = Self::stream_events(&endpoint, &tx, &mut last_seq).await {
                     tracing::debug!(error = %e, "event stream disconnected, reconnecting");
                 }
 
@@ -269,75 +1078,136 @@ impl DaemonClient {
         rx
     }
 
+    /// Forward one connection's events to `tx`. `last_seq` is the last
+    /// event seen, so a reconnect replays whatever happened in between.
     async fn stream_events(
-        socket_path: &std::path::Path,
+        endpoint: &Endpoint,
         tx: &tokio::sync::mpsc::Sender<DaemonEvent>,
+        last_seq: &mut Option<i64>,
     ) -> anyhow::Result<()> {
-        let stream = UnixStream::connect(socket_path).await?;
-        let io = TokioIo::new(stream);
-        let (mut sender, conn) = hyper::client::conn::http1::handshake(io).await?;
-        tokio::spawn(conn);
-
-        let req = hyper::Request::builder()
+        let mut builder = hyper::Request::builder()
             .method(hyper::Method::GET)
             .uri("/events")
-            .header("host", "localhost")
-            .body(Full::new(Bytes::new()))?;
+            .header("host", "localhost");
+        if let Some(seq) = last_seq {
+            builder = builder.header("last-event-id", seq.to_string());
+        }
+        let req = builder.body(Full::new(Bytes::new()))?;
 
-        let res = sender.send_request(req).await?;
+        let res = endpoint.send(req).await?;
 
         if !res.status().is_success() {
             anyhow::bail!("event stream returned {}", res.status());
         }
 
-        // Signal a poll on connect so the TUI refreshes.
-        if tx.send(DaemonEvent::Connected).await.is_err() {
-            return Ok(());
-        }
-
         let mut body = res.into_body();
+        let mut pending = String::new();
         while let Some(frame) = body.frame().await {
             let frame = frame?;
-            if let Some(data) = frame.data_ref()
-                && !data.is_empty()
-                && tx.send(DaemonEvent::Updated).await.is_err()
-            {
-                return Ok(());
+            let Some(data) = frame.data_ref() else {
+                continue;
+            };
+            pending.push_str(&String::from_utf8_lossy(data));
+            // Events end with a blank line and may span frames.
+            while let Some(end) = pending.find("\n\n") {
+                let raw: String = pending.drain(..end + 2).collect();
+                let mut name = None;
+                let mut data = None;
+                for line in raw.lines() {
+                    if let Some(value) = line.strip_prefix("event: ") {
+                        name = Some(value.trim());
+                    } else if let Some(value) = line.strip_prefix("data: ") {
+                        data = Some(value);
+                    } else if let Some(seq) = line
+                        .strip_prefix("id: ")
+                        .and_then(|id| id.trim().parse().ok())
+                    {
+                        *last_seq = Some(seq);
+                    }
+                }
+                let event = match name {
+                    Some("reset") => DaemonEvent::Connected,
+                    Some("resume") => DaemonEvent::Resumed,
+                    _ => match data.map(serde_json::from_str) {
+                        Some(Ok(event)) => DaemonEvent::Updated(event),
+                        _ => {
+                            tracing::debug!(event = %raw, "ignoring malformed event");
+                            continue;
+                        }
+                    },
+                };
+                if tx.send(event).await.is_err() {
+                    return Ok(());
+                }
             }
         }
 
         Ok(())
     }
 
+    /// Recorded changes to tasks, environments, projects, groups, and jobs,
+    /// oldest first.
+    pub async fn list_events(&self, query: &EventQuery) -> anyhow::Result<Vec<Event>> {
+        if self.is_read_only() {
+            return crate::db::list_events(query);
+        }
+        let mut params = Vec::new();
+        if let Some(since) = query.since {
+            params.push(format!("since={since}"));
+        }
+        if let Some(kind) = &query.kind {
+            params.push(format!("kind={}", encode_query_value(kind)));
+        }
+        if let Some(subject_id) = &query.subject_id {
+            params.push(format!("subject_id={}", encode_query_value(subject_id)));
+        }
+        if let Some(limit) = query.limit {
+            params.push(format!("limit={limit}"));
+        }
+        let mut uri = "/events/history".to_string();
+        if !params.is_empty() {
+            uri.push('?');
+            uri.push_str(&params.join("&"));
+        }
+        let (status, body) = self.request(hyper::Method::GET, &uri, None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// The `::group::` sections of a task's log so far.
+    pub async fn task_log_sections(&self, id: &str) -> anyhow::Result<Vec<LogSection>> {
+        if self.is_read_only() {
+            return crate::log_sections::of_task(&crate::db::resolve_task_id(id)?);
+        }
+        let uri = format!("/tasks/{id}/logs/sections");
+        let (status, body) = self.request(hyper::Method::GET, &uri, None).await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body).into());
+        }
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Stream a task's log output to `on_chunk` until the task finishes.
     pub async fn tail_task_logs(
         &self,
         task_id: &str,
         mut on_chunk: impl FnMut(&[u8]),
     ) -> anyhow::Result<()> {
-        let stream = UnixStream::connect(&self.socket_path).await.map_err(|e| {
-            anyhow::anyhow!(
-                "could not connect to daemon at {}: {e}\nIs the daemon running? Start it with: work daemon start",
-                self.socket_path.display(),
-            )
-        })?;
-
-        let io = TokioIo::new(stream);
-        let (mut sender, conn) = hyper::client::conn::http1::handshake(io).await?;
-        tokio::spawn(conn);
-
         let req = hyper::Request::builder()
             .method(hyper::Method::GET)
             .uri(format!("/tasks/{task_id}/logs"))
             .header("host", "localhost")
             .body(Full::new(Bytes::new()))?;
 
-        let res = sender.send_request(req).await?;
+        let res = self.endpoint.send(req).await?;
         let status = res.status();
 
         if !status.is_success() {
             let body_bytes = res.into_body().collect().await?.to_bytes();
             let text = String::from_utf8(body_bytes.to_vec())?;
-            anyhow::bail!("{}", extract_error(&text));
+            return Err(api_error(status, &text).into());
         }
 
         let mut body = res.into_body();
@@ -351,35 +1221,25 @@ impl DaemonClient {
         Ok(())
     }
 
+    /// Stream an environment's provider log output to `on_chunk`.
     pub async fn tail_environment_logs(
         &self,
         env_id: &str,
         mut on_chunk: impl FnMut(&[u8]),
     ) -> anyhow::Result<()> {
-        let stream = UnixStream::connect(&self.socket_path).await.map_err(|e| {
-            anyhow::anyhow!(
-                "could not connect to daemon at {}: {e}\nIs the daemon running? Start it with: work daemon start",
-                self.socket_path.display(),
-            )
-        })?;
-
-        let io = TokioIo::new(stream);
-        let (mut sender, conn) = hyper::client::conn::http1::handshake(io).await?;
-        tokio::spawn(conn);
-
         let req = hyper::Request::builder()
             .method(hyper::Method::GET)
             .uri(format!("/environments/{env_id}/logs"))
             .header("host", "localhost")
             .body(Full::new(Bytes::new()))?;
 
-        let res = sender.send_request(req).await?;
+        let res = self.endpoint.send(req).await?;
         let status = res.status();
 
         if !status.is_success() {
             let body_bytes = res.into_body().collect().await?.to_bytes();
             let text = String::from_utf8(body_bytes.to_vec())?;
-            anyhow::bail!("{}", extract_error(&text));
+            return Err(api_error(status, &text).into());
         }
 
         let mut body = res.into_body();
@@ -394,9 +1254,218 @@ impl DaemonClient {
     }
 }
 
-fn extract_error(body: &str) -> String {
-    serde_json::from_str::<serde_json::Value>(body)
-        .ok()
-        .and_then(|v| v.get("error")?.as_str().map(String::from))
-        .unwrap_or_else(|| body.to_string())
+impl Endpoint {
+    /// Open a connection and send `req` over it. The connection closes once
+    /// the response body is dropped.
+    async fn send(
+        &self,
+        req: hyper::Request<Full<Bytes>>,
+    ) -> anyhow::Result<hyper::Response<Incoming>> {
+        let io = match self {
+            Self::Socket(path) => {
+                let stream = UnixStream::connect(path)
+                    .await
+                    .map_err(|source| NotRunning {
+                        path: path.clone(),
+                        source,
+                    })?;
+                Connection::Socket(stream)
+            }
+            Self::Ssh(host) => {
+                let control_dir = crate::paths::runtime_dir().ok();
+                let mut child = tokio::process::Command::new("ssh")
+                    .args(ssh_args(host, control_dir.as_deref()))
+                    .stdin(std::process::Stdio::piped())
+                    .stdout(std::process::Stdio::piped())
+                    .stderr(std::process::Stdio::null())
+                    .kill_on_drop(true)
+                    .spawn()
+                    .context("could not run ssh")?;
+                let stdin = child.stdin.take().context("ssh stdin was not captured")?;
+                let stdout = child.stdout.take().context("ssh stdout was not captured")?;
+                Connection::Ssh {
+                    _child: child,
+                    stdin,
+                    stdout,
+                }
+            }
+            Self::Database => anyhow::bail!(
+                "the daemon is not running, and only listing and showing work without it\nStart it with: work daemon start"
+            ),
+        };
+
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(io)).await?;
+        tokio::spawn(conn);
+        let res = sender.send_request(req).await;
+        match self {
+            Self::Ssh(host) => res.with_context(|| {
+                format!(
+                    "could not reach the daemon on {} over ssh\nCheck that `ssh {} {}` works and the daemon is running there",
+                    host.ssh,
+                    host.ssh,
+                    host.remote_command(PROXY_ARGS)
+                )
+            }),
+            Self::Socket(_) | Self::Database => Ok(res?),
+        }
+    }
+}
+
+/// Nothing is listening on the daemon's socket.
+#[derive(Debug)]
+struct NotRunning {
+    path: PathBuf,
+    source: std::io::Error,
+}
+
+impl std::fmt::Display for NotRunning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "could not connect to daemon at {}: {}\nIs the daemon running? Start it with: work daemon start, or set auto-start-daemon = true",
+            self.path.display(),
+            self.source
+        )
+    }
+}
+
+impl std::error::Error for NotRunning {}
+
+/// Arguments for `ssh` that run the daemon proxy on `host`. With a
+/// `control_dir`, requests share one SSH connection that lingers for a minute
+/// after the last one, so each request skips the handshake.
+fn ssh_args(host: &HostConfig, control_dir: Option<&Path>) -> Vec<String> {
+    let mut args = vec!["-T".to_string()];
+    if let Some(dir) = control_dir {
+        args.extend([
+            "-o".to_string(),
+            "ControlMaster=auto".to_string(),
+            "-o".to_string(),
+            format!("ControlPath={}", dir.join("ssh-%C").display()),
+            "-o".to_string(),
+            "ControlPersist=60".to_string(),
+        ]);
+    }
+    args.push(host.ssh.clone());
+    args.push(host.remote_command(PROXY_ARGS));
+    args
+}
+
+/// The `work` subcommand that relays stdio to the host's daemon.
+const PROXY_ARGS: &str = "daemon proxy";
+
+/// A connection to the daemon: its socket, or an `ssh` process relaying to it.
+enum Connection {
+    Socket(UnixStream),
+    Ssh {
+        /// Killed when the connection drops.
+        _child: Child,
+        stdin: ChildStdin,
+        stdout: ChildStdout,
+    },
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Socket(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Ssh { stdout, .. } => Pin::new(stdout).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            Self::Socket(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Ssh { stdin, .. } => Pin::new(stdin).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Socket(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Ssh { stdin, .. } => Pin::new(stdin).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Socket(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Ssh { stdin, .. } => Pin::new(stdin).poll_shutdown(cx),
+        }
+    }
+}
+
+fn encode_query_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Decode a failed response into the daemon's coded error. Bodies that are
+/// not coded errors (a proxy, or a daemon older than this client) keep their
+/// text as the message.
+fn api_error(status: hyper::StatusCode, body: &str) -> Error {
+    serde_json::from_str(body).unwrap_or_else(|_| {
+        let code = match status.as_u16() {
+            400 => ErrorCode::InvalidRequest,
+            503 => ErrorCode::NotReady,
+            _ => ErrorCode::Internal,
+        };
+        let message = serde_json::from_str::<serde_json::Value>(body)
+            .ok()
+            .and_then(|v| v.get("error")?.as_str().map(String::from))
+            .unwrap_or_else(|| body.to_string());
+        Error::new(code, message)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssh_args_run_the_proxy_over_a_shared_connection() {
+        let host = HostConfig {
+            ssh: "me@devbox".to_string(),
+            work_path: Some("~/.cargo/bin/work".to_string()),
+            work_home: Some("/srv/work".to_string()),
+        };
+        let args = ssh_args(&host, Some(Path::new("/run/work")));
+        assert_eq!(args[0], "-T");
+        assert!(args.contains(&"ControlPath=/run/work/ssh-%C".to_string()));
+        assert_eq!(
+            args[args.len() - 2..],
+            [
+                "me@devbox",
+                "~/.cargo/bin/work --work-home /srv/work daemon proxy"
+            ]
+        );
+
+        let plain = HostConfig {
+            ssh: "devbox".to_string(),
+            work_path: None,
+            work_home: None,
+        };
+        assert_eq!(
+            ssh_args(&plain, None),
+            ["-T", "devbox", "work daemon proxy"]
+        );
+    }
 }
@@ -1,7 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use crate::paths;
 
+/// Repository-local defaults, read from the root of a project's checkout.
+pub const PROJECT_CONFIG_FILE: &str = ".work.toml";
+
 #[derive(Default, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
@@ -13,29 +19,756 @@ pub struct Config {
     pub projects: Option<HashMap<String, ProjectConfig>>,
     pub tasks: Option<TasksConfig>,
     pub environments: Option<EnvironmentsConfig>,
+    pub editor: Option<EditorConfig>,
+    pub notifications: Option<NotificationsConfig>,
+    pub tui: Option<TuiConfig>,
+    /// Remote machines `--host` can name, e.g. `[hosts.devbox]`.
+    #[serde(default)]
+    pub hosts: HashMap<String, HostConfig>,
+    /// Start the daemon when a command can't connect to it.
+    #[serde(default)]
+    pub auto_start_daemon: bool,
+    /// How tables show task and environment IDs.
+    #[serde(default)]
+    pub id_format: IdFormat,
+    /// `--format` for commands that don't give one.
+    pub default_format: Option<String>,
+    /// `--format` for commands in a group that don't give one, keyed by the
+    /// group's command, e.g. `[formats] task = "json"`.
+    #[serde(default)]
+    pub formats: HashMap<String, String>,
+    pub redact: Option<RedactConfig>,
+    /// Defaults from a project's `.work.toml`, layered under `[projects.NAME]`.
+    #[serde(skip)]
+    pub repository: Option<RepositoryConfig>,
+}
+
+/// `[redact]`: secrets to keep out of task logs and descriptions.
+#[derive(Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RedactConfig {
+    /// Regular expressions whose matches are replaced with `[redacted]`.
+    #[serde(default)]
+    pub patterns: Vec<String>,
+}
+
+/// What the ID column of `work task list` and `work env list` holds. Either
+/// way, an alias, a full ID, or an unambiguous prefix of one is accepted
+/// wherever an ID is.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdFormat {
+    /// The alias, such as `t12`, beside the full ID.
+    #[default]
+    Full,
+    /// Only the alias, for narrower tables.
+    Alias,
+}
+
+/// A loaded `.work.toml` and the project it belongs to.
+pub struct RepositoryConfig {
+    pub project_name: String,
+    pub path: PathBuf,
+    pub config: ProjectConfig,
+}
+
+/// Where an effective setting came from, as reported by `work config show`.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConfigSource {
+    /// A `[projects.NAME]` table in the global config.
+    Project,
+    /// The project's `.work.toml`.
+    Repository,
+    /// A top-level key in the global config.
+    Global,
+    /// Provider options under `[environments.providers.NAME]`.
+    Provider,
+}
+
+/// A machine running its own daemon, managed over SSH with `--host`.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HostConfig {
+    /// SSH destination, e.g. `user@devbox`.
+    pub ssh: String,
+    /// The `work` executable on the host. Defaults to `work` on its `PATH`.
+    pub work_path: Option<String>,
+    /// `WORK_HOME` on the host, when its daemon does not use the default.
+    pub work_home: Option<String>,
+}
+
+impl HostConfig {
+    /// A command line for the host's shell that runs `work` with `args`.
+    /// `work-path` and `work-home` are left for that shell to expand.
+    pub fn remote_command(&self, args: &str) -> String {
+        let work = self.work_path.as_deref().unwrap_or("work");
+        match &self.work_home {
+            Some(home) => format!("{work} --work-home {home} {args}"),
+            None => format!("{work} {args}"),
+        }
+    }
 }
 
 #[derive(Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct DaemonConfig {
     #[serde(default)]
     pub debug: bool,
+    /// Serve the dashboard page at `/ui`.
+    #[serde(default)]
+    pub dashboard: bool,
+    pub min_free_disk_gb: Option<f64>,
+    pub max_load_average: Option<f64>,
+    /// Retry overrides keyed by job type, e.g. `[daemon.retries.prepare_environment]`.
+    #[serde(default)]
+    pub retries: HashMap<String, RetryConfig>,
+    /// Prepare/update rate limits keyed by environment provider, e.g.
+    /// `[daemon.rate-limits.git-worktree]`.
+    #[serde(default)]
+    pub rate_limits: HashMap<String, RateLimitConfig>,
+    /// Exit after this long (e.g. `"30m"`) with no connections and no work
+    /// in flight.
+    pub idle_timeout: Option<String>,
+    /// How long jobs in flight get to finish when the daemon stops before
+    /// they are requeued, e.g. `"30s"`.
+    pub shutdown_grace: Option<String>,
+    /// Watch project checkouts' git refs and mark pooled environments
+    /// outdated when their base branch moves.
+    #[serde(default)]
+    pub watch_projects: bool,
+    /// Requests each peer may make per window, e.g.
+    /// `[daemon.request-rate-limit]`. `jitter` doesn't apply.
+    pub request_rate_limit: Option<RateLimitConfig>,
+}
+
+/// Formats `--format` accepts.
+const OUTPUT_FORMATS: [&str; 3] = ["human", "plain", "json"];
+
+fn output_format(value: &str) -> anyhow::Result<&'static str> {
+    OUTPUT_FORMATS
+        .into_iter()
+        .find(|format| *format == value)
+        .ok_or_else(|| anyhow::anyhow!("unknown format {value:?} (use human, plain, or json)"))
+}
+
+/// How long jobs in flight get to finish when the daemon stops, unless
+/// `[daemon] shutdown-grace` says.
+pub const DEFAULT_SHUTDOWN_GRACE: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[derive(Clone, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RateLimitConfig {
+    pub max: u32,
+    /// Window `max` applies to, e.g. `"1m"`. Defaults to one minute.
+    pub per: Option<String>,
+    /// Upper bound on the random delay added when a job is deferred.
+    /// Defaults to one slot's share of the window (`per / max`).
+    pub jitter: Option<String>,
+}
+
+impl RateLimitConfig {
+    /// The limit with its defaults filled in. `table` names the config table
+    /// it came from, for errors.
+    fn resolve(&self, table: &str) -> anyhow::Result<RateLimit> {
+        if self.max == 0 {
+            anyhow::bail!("{table} max must be at least 1");
+        }
+
+        let to_std = |value: &str| -> anyhow::Result<std::time::Duration> {
+            parse_duration(value)?
+                .to_std()
+                .map_err(|_| anyhow::anyhow!("invalid duration {value:?}"))
+        };
+        let per = to_std(self.per.as_deref().unwrap_or("1m"))?;
+        if per.is_zero() {
+            anyhow::bail!("{table} per must be longer than zero");
+        }
+        let jitter = match self.jitter.as_deref() {
+            Some(jitter) => to_std(jitter)?,
+            None => per / self.max,
+        };
+        Ok(RateLimit {
+            max: self.max,
+            per,
+            jitter,
+        })
+    }
+}
+
+/// At most `max` operations may start within any `per` window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub max: u32,
+    pub per: std::time::Duration,
+    pub jitter: std::time::Duration,
+}
+
+#[derive(Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RetryConfig {
+    pub limit: Option<i64>,
+    pub max_delay: Option<i64>,
+}
+
+/// Effective retry behavior for a job type: how many times a failed job is
+/// retried, and the cap on its exponential backoff.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct RetryPolicy {
+    pub limit: i64,
+    pub max_delay_seconds: i64,
+}
+
+impl RetryPolicy {
+    /// Built-in policy. Prepares clone over the network and get more patience.
+    pub fn default_for(job_type: &str) -> Self {
+        match job_type {
+            "prepare_environment" => Self {
+                limit: 5,
+                max_delay_seconds: 300,
+            },
+            _ => Self {
+                limit: 2,
+                max_delay_seconds: 60,
+            },
+        }
+    }
+
+    /// Backoff before retrying after failed attempt `attempt` (0-based).
+    pub fn delay_seconds(&self, attempt: i64) -> i64 {
+        let exp = (attempt.max(1) as u32).min(20);
+        2_i64.pow(exp).min(self.max_delay_seconds)
+    }
 }
 
 #[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct TasksConfig {
+    #[serde(default)]
     pub providers: HashMap<String, TaskProviderConfig>,
+    /// Remove finished tasks this long after they last changed, e.g. `"14d"`.
+    pub auto_remove_after: Option<String>,
+    /// Terminal statuses eligible for auto-removal.
+    #[serde(default = "default_auto_remove_statuses")]
+    pub statuses: Vec<String>,
+    /// Tasks carrying this label are never auto-removed.
+    #[serde(default = "default_exempt_label")]
+    pub exempt_label: String,
+    /// Cap on each task log, in megabytes. Past it, the middle of the log is
+    /// cut out.
+    pub max_log_mb: Option<f64>,
+    /// Encrypt task logs on disk with a key kept in the OS keychain.
+    #[serde(default)]
+    pub encrypt_logs: bool,
+    /// Start each line of task output with a timestamp and the stream it
+    /// came from.
+    #[serde(default)]
+    pub log_timestamps: bool,
+    /// How much of the machine task runners may take.
+    #[serde(default)]
+    pub resources: TaskResources,
+}
+
+/// The `[tasks.resources]` table, applied to every command a task runs so
+/// agent builds don't starve interactive work.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TaskResources {
+    /// Niceness runners start with, from 0 (normal) to 19 (lowest).
+    pub nice: Option<i32>,
+    /// Disk I/O priority runners start with.
+    pub io_class: Option<IoClass>,
+    /// Linux only: the memory, in megabytes, each task command may use
+    /// before the kernel reclaims or kills it. The command runs in its own
+    /// systemd scope, so the daemon needs a user systemd session.
+    pub memory_max_mb: Option<u64>,
+}
+
+/// A disk I/O priority class. On macOS, `idle` throttles the runner's disk
+/// I/O and `best-effort` leaves it alone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoClass {
+    BestEffort,
+    Idle,
+}
+
+fn default_auto_remove_statuses() -> Vec<String> {
+    vec!["complete".to_string()]
+}
+
+fn default_exempt_label() -> String {
+    "keep".to_string()
+}
+
+/// When finished tasks of a project are removed automatically.
+#[derive(Debug, PartialEq)]
+pub struct RetentionPolicy {
+    pub max_age: chrono::Duration,
+    pub statuses: Vec<String>,
+    pub exempt_label: String,
+}
+
+impl RetentionPolicy {
+    /// Whether a task is old enough, in an eligible status, and not exempt.
+    /// Age counts from when the task reached its status, so later edits such
+    /// as labels or notes don't hold it back.
+    pub fn is_expired(&self, task: &crate::db::Task, now: chrono::DateTime<chrono::Utc>) -> bool {
+        if !self.statuses.contains(&task.status) || task.labels.contains(&self.exempt_label) {
+            return false;
+        }
+        let since = task
+            .status_changed_at
+            .as_deref()
+            .unwrap_or(&task.updated_at);
+        chrono::DateTime::parse_from_rfc3339(since)
+            .is_ok_and(|changed| now.signed_duration_since(changed) >= self.max_age)
+    }
+}
+
+/// Parse a duration such as `90s`, `30m`, `12h`, `14d`, or `2w`.
+pub fn parse_duration(value: &str) -> anyhow::Result<chrono::Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| anyhow::anyhow!("duration {value:?} is missing a unit (s, m, h, d, w)"))?;
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid duration {value:?}"))?;
+    let duration = match unit {
+        "s" => chrono::Duration::try_seconds(amount),
+        "m" => chrono::Duration::try_minutes(amount),
+        "h" => chrono::Duration::try_hours(amount),
+        "d" => chrono::Duration::try_days(amount),
+        "w" => chrono::Duration::try_weeks(amount),
+        _ => anyhow::bail!("invalid duration unit in {value:?} (use s, m, h, d, or w)"),
+    };
+    duration.ok_or_else(|| anyhow::anyhow!("duration {value:?} is out of range"))
 }
 
 #[derive(serde::Deserialize)]
 #[serde(tag = "type")]
 pub enum TaskProviderConfig {
     #[serde(rename = "command")]
-    Command { command: String, args: Vec<String> },
+    Command {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        stdin: Option<TaskProviderStdin>,
+        /// Run on a pseudo-terminal, for runners that buffer their output or
+        /// drop color when it goes to a pipe.
+        #[serde(default)]
+        pty: bool,
+        #[serde(rename = "pty-columns")]
+        pty_columns: Option<u16>,
+        #[serde(rename = "pty-rows")]
+        pty_rows: Option<u16>,
+        /// A command, program first, run in the environment before
+        /// `command`. If it fails, `command` is skipped and the task fails.
+        #[serde(default)]
+        pre: Vec<String>,
+        /// A command, program first, run in the environment after `command`.
+        #[serde(default)]
+        post: Vec<String>,
+        /// Whether `post` runs after `pre` or `command` failed.
+        #[serde(default, rename = "post-on-failure")]
+        post_on_failure: PostOnFailure,
+        /// How the runner is asked to stop when its task is cancelled.
+        #[serde(default)]
+        cancel: TaskCancelConfig,
+    },
+    /// A stand-in runner for tests and demos that prints `output`, takes
+    /// `latency-ms`, and exits with `exit-code`, without an agent installed.
+    #[serde(rename = "mock")]
+    Mock {
+        /// Lines to print. `{task_description}` is replaced as in `args`.
+        #[serde(default = "default_mock_output")]
+        output: Vec<String>,
+        #[serde(default, rename = "latency-ms")]
+        latency_ms: u64,
+        #[serde(default, rename = "exit-code")]
+        exit_code: i32,
+        /// Recorded as the task's result summary.
+        summary: Option<String>,
+    },
+}
+
+fn default_mock_output() -> Vec<String> {
+    vec!["{task_description}".to_string()]
+}
+
+/// When a task provider's `post` command runs if an earlier step failed. A
+/// cancelled task never runs it.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PostOnFailure {
+    /// Run `post` only when `pre` and `command` succeeded.
+    #[default]
+    Skip,
+    Always,
+}
+
+/// The `cancel` table of a task provider. When a running task is cancelled,
+/// `signal` goes to the runner's process group, the file the runner was
+/// given as `WORK_CANCEL_FILE` is created, and whatever is still running
+/// after `grace` gets SIGKILL. A runner that needs to checkpoint before it
+/// exits can trap the signal or poll for the file.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct TaskCancelConfig {
+    #[serde(default)]
+    pub signal: CancelSignal,
+    /// Such as `30s`; 5 seconds if unset.
+    pub grace: Option<String>,
+}
+
+/// How long a cancelled runner has before SIGKILL unless its provider says.
+pub const DEFAULT_CANCEL_GRACE: std::time::Duration = std::time::Duration::from_secs(5);
+
+impl TaskCancelConfig {
+    pub fn grace(&self) -> anyhow::Result<std::time::Duration> {
+        match &self.grace {
+            Some(grace) => Ok(parse_duration(grace)?.to_std()?),
+            None => Ok(DEFAULT_CANCEL_GRACE),
+        }
+    }
+}
+
+/// The signal a cancelled runner is sent first. With `none`, it only gets
+/// the cancel file, then SIGKILL.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+pub enum CancelSignal {
+    #[default]
+    #[serde(rename = "SIGTERM")]
+    Term,
+    #[serde(rename = "SIGINT")]
+    Int,
+    #[serde(rename = "SIGHUP")]
+    Hup,
+    #[serde(rename = "none")]
+    None,
+}
+
+impl CancelSignal {
+    /// As it's written in the config.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Term => "SIGTERM",
+            Self::Int => "SIGINT",
+            Self::Hup => "SIGHUP",
+            Self::None => "none",
+        }
+    }
+
+    pub fn number(self) -> Option<libc::c_int> {
+        match self {
+            Self::Term => Some(libc::SIGTERM),
+            Self::Int => Some(libc::SIGINT),
+            Self::Hup => Some(libc::SIGHUP),
+            Self::None => None,
+        }
+    }
+}
+
+/// Window size a task's pty reports unless the provider sets one.
+pub const DEFAULT_PTY_COLUMNS: u16 = 120;
+pub const DEFAULT_PTY_ROWS: u16 = 40;
+
+impl TaskProviderConfig {
+    /// How the provider's runners are cancelled. Mock runners take the
+    /// defaults.
+    pub fn cancel(&self) -> TaskCancelConfig {
+        match self {
+            Self::Command { cancel, .. } => cancel.clone(),
+            Self::Mock { .. } => TaskCancelConfig::default(),
+        }
+    }
+
+    /// Columns and rows of the pty to run tasks on, if the provider uses one.
+    pub fn pty_size(&self) -> Option<(u16, u16)> {
+        let Self::Command {
+            pty,
+            pty_columns,
+            pty_rows,
+            ..
+        } = self
+        else {
+            return None;
+        };
+        pty.then(|| {
+            (
+                pty_columns.unwrap_or(DEFAULT_PTY_COLUMNS),
+                pty_rows.unwrap_or(DEFAULT_PTY_ROWS),
+            )
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TaskProviderStdin {
+    Description,
+}
+
+/// The `[editor]` table, used instead of `$EDITOR` when `command` is set.
+#[derive(Default, serde::Deserialize)]
+pub struct EditorConfig {
+    pub command: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// The `[tui]` table.
+#[derive(Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TuiConfig {
+    /// Keys for TUI actions by action name, each replacing that action's
+    /// default keys, e.g. `delete = "x"` or `scroll_down = ["j", "down"]`.
+    #[serde(default)]
+    pub keys: BTreeMap<String, KeyBinding>,
+}
+
+/// One key or several for a `[tui.keys]` action.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub enum KeyBinding {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeyBinding {
+    pub fn keys(&self) -> &[String] {
+        match self {
+            KeyBinding::One(key) => std::slice::from_ref(key),
+            KeyBinding::Many(keys) => keys,
+        }
+    }
+}
+
+/// Editors that hand the file to an already-running window and exit
+/// immediately unless asked to wait.
+const DETACHING_EDITORS: &[&str] = &[
+    "code",
+    "code-insiders",
+    "codium",
+    "cursor",
+    "windsurf",
+    "subl",
+    "zed",
+    "mate",
+];
+
+/// A resolved editor invocation. The file to edit is appended to `args`.
+#[derive(Debug, PartialEq)]
+pub struct EditorCommand {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl EditorCommand {
+    /// Whether the program is known to detach without `--wait`.
+    pub fn detaches(&self) -> bool {
+        let name = Path::new(&self.program)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(&self.program);
+        DETACHING_EDITORS.contains(&name)
+    }
+
+    fn with_wait(mut self) -> Self {
+        let waits = self.args.iter().any(|arg| arg == "--wait" || arg == "-w");
+        if self.detaches() && !waits {
+            self.args.push("--wait".to_string());
+        }
+        self
+    }
 }
 
 #[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct EnvironmentsConfig {
-    pub providers: HashMap<String, EnvironmentProviderConfig>,
+    #[serde(default)]
+    pub providers: HashMap<String, EnvironmentProviderEntry>,
+    /// Providers whose pooled environments any project may claim.
+    #[serde(default)]
+    pub shared_pools: Vec<String>,
+    /// Record interactive `work env exec` sessions, as if `--record` were
+    /// passed.
+    #[serde(default)]
+    pub record_sessions: bool,
+    /// Size cap for the provider cache, in gigabytes.
+    pub cache_max_gb: Option<f64>,
+}
+
+const DEFAULT_CACHE_MAX_GB: f64 = 10.0;
+
+/// Built-in providers check out a specific project, so their environments
+/// can never serve another one.
+const PROJECT_BOUND_PROVIDERS: &[&str] =
+    &["git-worktree", "apfs-worktree", "tmpfs", IN_PLACE_PROVIDER];
+
+/// Environment providers that need no `type` in config.
+pub const BUILT_IN_ENVIRONMENT_PROVIDERS: &[&str] =
+    &["git-worktree", "apfs-worktree", "tmpfs", IN_PLACE_PROVIDER];
+
+/// The built-in provider that runs tasks in the project checkout itself.
+/// A task has to ask for it with `in_place`, and only one such task per
+/// project runs at a time.
+pub const IN_PLACE_PROVIDER: &str = "inplace";
+
+/// An `[environments.providers.NAME]` table. Tables with a `type` define a
+/// custom provider; tables without one hold prepare options for a built-in
+/// provider such as `git-worktree`.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub enum EnvironmentProviderEntry {
+    Custom(EnvironmentProviderConfig),
+    BuiltIn(BuiltInProviderConfig),
+}
+
+impl EnvironmentProviderEntry {
+    pub fn options(&self) -> &PrepareOptions {
+        match self {
+            EnvironmentProviderEntry::Custom(
+                EnvironmentProviderConfig::Script { options, .. }
+                | EnvironmentProviderConfig::Mock { options, .. }
+                | EnvironmentProviderConfig::Ssh { options, .. },
+            ) => options,
+            EnvironmentProviderEntry::BuiltIn(config) => &config.options,
+        }
+    }
+
+    /// Capabilities the provider's environments offer, matched against
+    /// `work task new --require`.
+    pub fn tags(&self) -> &[String] {
+        match self {
+            EnvironmentProviderEntry::Custom(
+                EnvironmentProviderConfig::Script { tags, .. }
+                | EnvironmentProviderConfig::Mock { tags, .. }
+                | EnvironmentProviderConfig::Ssh { tags, .. },
+            ) => tags,
+            EnvironmentProviderEntry::BuiltIn(config) => &config.tags,
+        }
+    }
+
+    /// Variables for the provider's subprocesses.
+    pub fn process_env(&self) -> &ProcessEnv {
+        match self {
+            EnvironmentProviderEntry::Custom(
+                EnvironmentProviderConfig::Script { process_env, .. }
+                | EnvironmentProviderConfig::Mock { process_env, .. }
+                | EnvironmentProviderConfig::Ssh { process_env, .. },
+            ) => process_env,
+            EnvironmentProviderEntry::BuiltIn(config) => &config.process_env,
+        }
+    }
+}
+
+/// Environment variables for the commands a provider runs: its script, or
+/// `git` for the built-in providers. A daemon started by launchd or systemd
+/// gets a minimal `PATH`, so tools from e.g. Homebrew need `path-prepend`.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProcessEnv {
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Directories searched before `PATH`. A leading `~/` is the home
+    /// directory.
+    #[serde(default)]
+    pub path_prepend: Vec<String>,
+}
+
+impl ProcessEnv {
+    /// The variables to set. `PATH` is `path-prepend` followed by `env.PATH`,
+    /// or the daemon's own `PATH` if `env` doesn't set one.
+    pub fn vars(&self) -> Vec<(String, String)> {
+        let mut vars: Vec<(String, String)> = self
+            .env
+            .iter()
+            .filter(|(key, _)| self.path_prepend.is_empty() || key.as_str() != "PATH")
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        if !self.path_prepend.is_empty() {
+            let base = self
+                .env
+                .get("PATH")
+                .cloned()
+                .or_else(|| std::env::var("PATH").ok())
+                .unwrap_or_default();
+            let mut dirs: Vec<String> = self
+                .path_prepend
+                .iter()
+                .map(|dir| expand_home(dir).to_string_lossy().into_owned())
+                .collect();
+            if !base.is_empty() {
+                dirs.push(base);
+            }
+            vars.push(("PATH".to_string(), dirs.join(":")));
+        }
+        vars
+    }
+
+    /// A command for `program` with these variables set. `program` is looked
+    /// up on the resulting `PATH`.
+    pub fn command(&self, program: impl AsRef<std::ffi::OsStr>) -> std::process::Command {
+        let mut command = std::process::Command::new(program);
+        command.envs(self.vars());
+        command
+    }
+}
+
+/// Settings for a built-in provider. Unknown keys are rejected: a table
+/// whose `type` is missing or misspelled lands here, and would otherwise
+/// quietly configure a built-in provider.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BuiltInProviderConfig {
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Directory `tmpfs` creates environments in. It should be RAM-backed.
+    pub root: Option<String>,
+    /// Size cap for all `tmpfs` environments together, in megabytes.
+    pub max_size_mb: Option<u64>,
+    #[serde(flatten)]
+    pub options: PrepareOptions,
+    #[serde(flatten)]
+    pub process_env: ProcessEnv,
+}
+
+/// Options passed to `EnvironmentProvider::prepare`.
+#[derive(Clone, Default, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PrepareOptions {
+    /// Clone depth, for providers that clone the project.
+    pub depth: Option<u32>,
+    /// Restrict the checkout to these paths (cone-mode sparse checkout).
+    #[serde(default)]
+    pub sparse_paths: Vec<String>,
+    /// Branch new environments start from, for providers that create branches.
+    pub base_branch: Option<String>,
+    /// Branch or commit a task asked to start from. Set per task, never from
+    /// config, and takes precedence over `base_branch`.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub source_ref: Option<String>,
+    /// Directories the built-in local providers fill from the provider cache.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cached_paths: Vec<CachedPath>,
+}
+
+/// A directory in each environment, such as `node_modules`, built once by
+/// `command` and then copied from the provider cache for as long as
+/// `key-files` stay the same.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CachedPath {
+    /// Relative to the checkout.
+    pub path: String,
+    /// Files in the checkout whose contents the directory is built from.
+    #[serde(default)]
+    pub key_files: Vec<String>,
+    /// Shell command, run in the checkout, that creates `path`.
+    pub command: String,
 }
 
 #[derive(Default, serde::Deserialize)]
@@ -45,30 +778,474 @@ pub struct ProjectConfig {
     pub environment_provider: Option<String>,
     #[serde(alias = "default-task-provider")]
     pub task_provider: Option<String>,
+    pub base_branch: Option<String>,
+    /// Overrides the provider's `sparse-paths` option; `[]` checks out
+    /// everything.
+    pub sparse_paths: Option<Vec<String>>,
+    /// Overrides `[tasks] auto-remove-after`; `"never"` disables it.
+    pub auto_remove_after: Option<String>,
+    /// The only environment providers the project may use, when set.
+    pub allowed_env_providers: Option<Vec<String>>,
+    /// The only task providers the project may use, when set.
+    pub allowed_task_providers: Option<Vec<String>>,
+    /// Save what each task changed in its worktree as a `patch.diff`.
+    pub capture_diff: Option<bool>,
+}
+
+impl ProjectConfig {
+    fn allowed_providers(&self, kind: ProviderKind) -> Option<&Vec<String>> {
+        match kind {
+            ProviderKind::Task => self.allowed_task_providers.as_ref(),
+            ProviderKind::Environment => self.allowed_env_providers.as_ref(),
+        }
+    }
+}
+
+/// `[notifications]`: scripts the daemon runs as events happen.
+#[derive(Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NotificationsConfig {
+    #[serde(default)]
+    pub providers: HashMap<String, NotificationProviderConfig>,
 }
 
 #[derive(serde::Deserialize)]
 #[serde(tag = "type")]
+pub enum NotificationProviderConfig {
+    /// Run `path` with each event as JSON on stdin. A leading `~/` is the
+    /// home directory.
+    #[serde(rename = "script")]
+    Script {
+        path: String,
+        /// Event types to send, such as `task.complete`, `environment.*`, or
+        /// `*` for all of them.
+        events: Vec<String>,
+        /// How long the script may run, e.g. `"30s"`. Defaults to 30 seconds.
+        timeout: Option<String>,
+        #[serde(flatten)]
+        process_env: ProcessEnv,
+    },
+}
+
+/// What events are about, the `KIND` of a `KIND.ACTION` or `KIND.STATUS`
+/// event type.
+pub const EVENT_KINDS: &[&str] = &["task", "environment", "project", "group", "job"];
+
+const DEFAULT_NOTIFICATION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+impl NotificationProviderConfig {
+    pub fn events(&self) -> &[String] {
+        let Self::Script { events, .. } = self;
+        events
+    }
+
+    /// Whether an event going by any of `types` is sent to this provider.
+    pub fn wants(&self, types: &[String]) -> bool {
+        self.events().iter().any(|pattern| {
+            types.iter().any(|ty| {
+                pattern == "*"
+                    || pattern == ty
+                    || pattern
+                        .strip_suffix(".*")
+                        .is_some_and(|kind| ty.split('.').next() == Some(kind))
+            })
+        })
+    }
+
+    pub fn timeout(&self) -> anyhow::Result<std::time::Duration> {
+        let Self::Script { timeout, .. } = self;
+        match timeout {
+            Some(timeout) => Ok(parse_duration(timeout)?.to_std()?),
+            None => Ok(DEFAULT_NOTIFICATION_TIMEOUT),
+        }
+    }
+}
+
+/// `path` with a leading `~/` replaced by the home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// The two kinds of provider a project's allowlists restrict.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProviderKind {
+    Task,
+    Environment,
+}
+
+impl ProviderKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Task => "task",
+            Self::Environment => "environment",
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(tag = "type", deny_unknown_fields)]
 pub enum EnvironmentProviderConfig {
     #[serde(rename = "script")]
-    Script { path: String },
+    Script {
+        path: String,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(flatten)]
+        options: PrepareOptions,
+        #[serde(flatten)]
+        process_env: ProcessEnv,
+    },
+    /// Empty directories for tests and demos, with lifecycle steps that can
+    /// be made slow or made to fail.
+    #[serde(rename = "mock")]
+    Mock {
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(flatten)]
+        flags: MockFlags,
+        #[serde(flatten)]
+        options: PrepareOptions,
+        #[serde(flatten)]
+        process_env: ProcessEnv,
+    },
+    /// Checkouts on another machine, for tasks that need its hardware or
+    /// toolchain. Every step runs there over `ssh`.
+    #[serde(rename = "ssh")]
+    Ssh {
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(flatten)]
+        remote: SshRemote,
+        #[serde(flatten)]
+        options: PrepareOptions,
+        #[serde(flatten)]
+        process_env: ProcessEnv,
+    },
+}
+
+/// Where an `ssh` provider makes its environments and how it sets them up.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SshRemote {
+    /// What to give `ssh`: a host name, `user@host`, or a `Host` from
+    /// `~/.ssh/config`.
+    pub host: Option<String>,
+    /// Where checkouts go on the remote, with `{project}` and `{env_id}`
+    /// filled in. A leading `~/` is the remote user's home directory.
+    pub path: Option<String>,
+    /// Shell command run in each new checkout, such as `make deps`.
+    pub setup: Option<String>,
+    /// URL the remote clones from. Defaults to the project's `origin`.
+    pub repository: Option<String>,
+    /// Options passed to `ssh` before the host, such as `["-p", "2222"]`.
+    #[serde(default)]
+    pub ssh_args: Vec<String>,
 }
 
+/// Where an `ssh` provider puts checkouts unless its `path` says.
+pub const DEFAULT_SSH_PATH: &str = "~/work-environments/{project}/{env_id}";
+
+/// How a mock environment behaves. Copied into each environment's metadata
+/// when it is prepared, so later steps follow the metadata rather than the
+/// current config, and tests can change one environment by editing it.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MockFlags {
+    /// How long each lifecycle step takes.
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Steps that fail: any of `prepare`, `update`, `claim`, `remove`,
+    /// `run`, `snapshot`, and `restore`.
+    #[serde(default)]
+    pub fail: Vec<String>,
+}
+
+/// The steps `fail` can name.
+pub const MOCK_STEPS: &[&str] = &[
+    "prepare", "update", "claim", "remove", "run", "snapshot", "restore",
+];
+
 impl Config {
+    /// The editor to open files in: `[editor]` if it sets a command,
+    /// otherwise `$EDITOR`.
+    pub fn editor(&self) -> anyhow::Result<EditorCommand> {
+        self.editor_with_env(std::env::var("EDITOR").ok())
+    }
+
+    fn editor_with_env(&self, env_editor: Option<String>) -> anyhow::Result<EditorCommand> {
+        if let Some(editor) = &self.editor
+            && let Some(command) = &editor.command
+        {
+            return Ok(EditorCommand {
+                program: command.clone(),
+                args: editor.args.clone(),
+            }
+            .with_wait());
+        }
+
+        // $EDITOR may carry its own flags, e.g. "code --wait".
+        let env_editor = env_editor.unwrap_or_default();
+        let mut words = env_editor.split_whitespace().map(str::to_string);
+        let Some(program) = words.next() else {
+            anyhow::bail!("no editor configured (set $EDITOR or [editor] command in config)");
+        };
+        Ok(EditorCommand {
+            program,
+            args: words.collect(),
+        }
+        .with_wait())
+    }
+
     pub fn default_task_provider_for_project(&self, project_name: &str) -> Option<String> {
-        self.projects
-            .as_ref()
-            .and_then(|p| p.get(project_name))
-            .and_then(|p| p.task_provider.clone())
-            .or_else(|| self.task_provider.clone())
+        self.task_provider_setting(project_name)
+            .map(|(value, _)| value)
     }
 
     pub fn default_environment_provider_for_project(&self, project_name: &str) -> Option<String> {
-        self.projects
+        self.environment_provider_setting(project_name)
+            .map(|(value, _)| value)
+    }
+
+    pub fn task_provider_setting(&self, project_name: &str) -> Option<(String, ConfigSource)> {
+        self.project_setting(project_name, |p| p.task_provider.as_ref())
+            .or_else(|| {
+                self.task_provider
+                    .clone()
+                    .map(|value| (value, ConfigSource::Global))
+            })
+    }
+
+    pub fn environment_provider_setting(
+        &self,
+        project_name: &str,
+    ) -> Option<(String, ConfigSource)> {
+        self.project_setting(project_name, |p| p.environment_provider.as_ref())
+            .or_else(|| {
+                self.environment_provider
+                    .clone()
+                    .map(|value| (value, ConfigSource::Global))
+            })
+    }
+
+    /// The base branch for a project's environments. A project-level setting
+    /// overrides the provider's `base-branch` option.
+    pub fn base_branch_setting(
+        &self,
+        project_name: &str,
+        provider: &str,
+    ) -> Option<(String, ConfigSource)> {
+        self.project_setting(project_name, |p| p.base_branch.as_ref())
+            .or_else(|| {
+                self.prepare_options(provider)
+                    .base_branch
+                    .map(|value| (value, ConfigSource::Provider))
+            })
+    }
+
+    /// The paths a project's environments check out, when sparse. A
+    /// project-level setting overrides the provider's `sparse-paths` option.
+    pub fn sparse_paths_setting(
+        &self,
+        project_name: &str,
+        provider: &str,
+    ) -> Option<(Vec<String>, ConfigSource)> {
+        self.project_setting(project_name, |p| p.sparse_paths.as_ref())
+            .or_else(|| {
+                Some(self.prepare_options(provider).sparse_paths)
+                    .filter(|paths| !paths.is_empty())
+                    .map(|paths| (paths, ConfigSource::Provider))
+            })
+    }
+
+    /// Look up a per-project setting: `[projects.NAME]` in the global config
+    /// wins over the project's `.work.toml`.
+    fn project_setting<T: Clone>(
+        &self,
+        project_name: &str,
+        get: impl Fn(&ProjectConfig) -> Option<&T>,
+    ) -> Option<(T, ConfigSource)> {
+        let global = self
+            .projects
             .as_ref()
             .and_then(|p| p.get(project_name))
-            .and_then(|p| p.environment_provider.clone())
-            .or_else(|| self.environment_provider.clone())
+            .and_then(&get)
+            .map(|value| (value.clone(), ConfigSource::Project));
+        global.or_else(|| {
+            self.repository
+                .as_ref()
+                .filter(|r| r.project_name == project_name)
+                .and_then(|r| get(&r.config))
+                .map(|value| (value.clone(), ConfigSource::Repository))
+        })
+    }
+
+    /// The rate limit on prepares and updates for an environment provider.
+    pub fn rate_limit(&self, provider: &str) -> anyhow::Result<Option<RateLimit>> {
+        self.daemon
+            .as_ref()
+            .and_then(|d| d.rate_limits.get(provider))
+            .map(|limit| limit.resolve(&format!("[daemon.rate-limits.{provider}]")))
+            .transpose()
+    }
+
+    /// The limit on requests to the daemon from each peer, if any.
+    pub fn request_rate_limit(&self) -> anyhow::Result<Option<RateLimit>> {
+        self.daemon
+            .as_ref()
+            .and_then(|d| d.request_rate_limit.as_ref())
+            .map(|limit| limit.resolve("[daemon.request-rate-limit]"))
+            .transpose()
+    }
+
+    /// How long the daemon may sit idle before it exits, if it should.
+    pub fn idle_timeout(&self) -> anyhow::Result<Option<std::time::Duration>> {
+        let Some(value) = self.daemon.as_ref().and_then(|d| d.idle_timeout.as_deref()) else {
+            return Ok(None);
+        };
+        let timeout = parse_duration(value)?
+            .to_std()
+            .ok()
+            .filter(|timeout| !timeout.is_zero())
+            .ok_or_else(|| anyhow::anyhow!("[daemon] idle-timeout must be longer than zero"))?;
+        Ok(Some(timeout))
+    }
+
+    /// How long the daemon waits for jobs in flight to finish when it stops.
+    /// Zero requeues them right away.
+    pub fn shutdown_grace(&self) -> anyhow::Result<std::time::Duration> {
+        let Some(value) = self
+            .daemon
+            .as_ref()
+            .and_then(|d| d.shutdown_grace.as_deref())
+        else {
+            return Ok(DEFAULT_SHUTDOWN_GRACE);
+        };
+        Ok(parse_duration(value)
+            .map_err(|e| anyhow::anyhow!("[daemon] shutdown-grace: {e}"))?
+            .to_std()?)
+    }
+
+    /// The `--format` a command in `group`, such as `task` for `work task
+    /// list`, uses when none is given: the group's `[formats]` entry, then
+    /// `default-format`. `WORK_FORMAT` overrides both.
+    pub fn default_format(&self, group: &str) -> anyhow::Result<Option<&'static str>> {
+        if let Some(format) = self.formats.get(group) {
+            return output_format(format)
+                .map(Some)
+                .map_err(|e| anyhow::anyhow!("[formats] {group}: {e}"));
+        }
+        self.default_format
+            .as_deref()
+            .map(|format| output_format(format).map_err(|e| anyhow::anyhow!("default-format: {e}")))
+            .transpose()
+    }
+
+    /// What to redact from task logs and descriptions, or `None` when
+    /// there are no `[redact] patterns`.
+    pub fn redactor(&self) -> anyhow::Result<Option<crate::redact::Redactor>> {
+        let patterns = self.redact.as_ref().map_or(&[][..], |r| &r.patterns);
+        crate::redact::Redactor::new(patterns)
+            .map_err(|e| anyhow::anyhow!("[redact] patterns: {e}"))
+    }
+
+    /// Whether the daemon serves its dashboard at `/ui`.
+    pub fn serves_dashboard(&self) -> bool {
+        self.daemon.as_ref().is_some_and(|d| d.dashboard)
+    }
+
+    /// Whether the daemon watches projects for base branches that move.
+    pub fn watches_projects(&self) -> bool {
+        self.daemon.as_ref().is_some_and(|d| d.watch_projects)
+    }
+
+    pub fn retry_policy(&self, job_type: &str) -> RetryPolicy {
+        let mut policy = RetryPolicy::default_for(job_type);
+        if let Some(overrides) = self.daemon.as_ref().and_then(|d| d.retries.get(job_type)) {
+            if let Some(limit) = overrides.limit {
+                policy.limit = limit.max(0);
+            }
+            if let Some(max_delay) = overrides.max_delay {
+                policy.max_delay_seconds = max_delay.max(1);
+            }
+        }
+        policy
+    }
+
+    /// Whether a project may use `provider`. Allowlists in `[projects.NAME]`
+    /// and in the project's `.work.toml` both apply, so a repository can
+    /// narrow what the global config allows but never widen it.
+    pub fn allows_provider(&self, project_name: &str, kind: ProviderKind, provider: &str) -> bool {
+        let global = self.projects.as_ref().and_then(|p| p.get(project_name));
+        let repository = self
+            .repository
+            .as_ref()
+            .filter(|r| r.project_name == project_name)
+            .map(|r| &r.config);
+        global
+            .into_iter()
+            .chain(repository)
+            .filter_map(|p| p.allowed_providers(kind))
+            .all(|list| list.iter().any(|p| p == provider))
+    }
+
+    /// Whether a project's tasks save a `patch.diff` of what they changed.
+    pub fn capture_diff(&self, project_name: &str) -> bool {
+        self.project_setting(project_name, |p| p.capture_diff.as_ref())
+            .is_some_and(|(capture, _)| capture)
+    }
+
+    /// The auto-removal policy for a project's tasks, if one applies.
+    pub fn task_retention(&self, project_name: &str) -> anyhow::Result<Option<RetentionPolicy>> {
+        let after = self
+            .project_setting(project_name, |p| p.auto_remove_after.as_ref())
+            .map(|(value, _)| value)
+            .or_else(|| self.tasks.as_ref()?.auto_remove_after.clone());
+        let Some(after) = after.filter(|a| a != "never") else {
+            return Ok(None);
+        };
+
+        let (statuses, exempt_label) = match &self.tasks {
+            Some(tasks) => (tasks.statuses.clone(), tasks.exempt_label.clone()),
+            None => (default_auto_remove_statuses(), default_exempt_label()),
+        };
+        let terminal = crate::db::TERMINAL_TASK_STATUSES;
+        if let Some(status) = statuses.iter().find(|s| !terminal.contains(&s.as_str())) {
+            anyhow::bail!(
+                "[tasks] statuses may only contain terminal statuses ({}), got {status:?}",
+                terminal.join(", ")
+            );
+        }
+
+        Ok(Some(RetentionPolicy {
+            max_age: parse_duration(&after)?,
+            statuses,
+            exempt_label,
+        }))
+    }
+
+    /// Configured notification providers, sorted by name.
+    pub fn notification_providers(&self) -> Vec<(&str, &NotificationProviderConfig)> {
+        let mut providers: Vec<_> = self
+            .notifications
+            .iter()
+            .flat_map(|n| n.providers.iter())
+            .map(|(name, provider)| (name.as_str(), provider))
+            .collect();
+        providers.sort_by_key(|(name, _)| *name);
+        providers
+    }
+
+    pub fn get_notification_provider(
+        &self,
+        name: &str,
+    ) -> anyhow::Result<&NotificationProviderConfig> {
+        self.notifications
+            .as_ref()
+            .and_then(|n| n.providers.get(name))
+            .ok_or_else(|| anyhow::anyhow!("notification provider not found: {name}"))
     }
 
     pub fn get_task_provider(&self, name: &str) -> anyhow::Result<&TaskProviderConfig> {
@@ -78,19 +1255,452 @@ impl Config {
             .ok_or_else(|| anyhow::anyhow!("task provider not found: {name}"))
     }
 
+    /// Task log size limit from `[tasks] max-log-mb`, if set.
+    pub fn max_task_log_bytes(&self) -> Option<u64> {
+        let mb = self.tasks.as_ref()?.max_log_mb.filter(|mb| *mb > 0.0)?;
+        Some((mb * 1024.0 * 1024.0) as u64)
+    }
+
+    /// Whether `[tasks] encrypt-logs` is set.
+    pub fn encrypt_task_logs(&self) -> bool {
+        self.tasks.as_ref().is_some_and(|t| t.encrypt_logs)
+    }
+
+    /// Whether `[tasks] log-timestamps` is set.
+    pub fn task_log_timestamps(&self) -> bool {
+        self.tasks.as_ref().is_some_and(|t| t.log_timestamps)
+    }
+
+    /// Scheduling and memory limits for task runners from `[tasks.resources]`.
+    pub fn task_resources(&self) -> TaskResources {
+        self.tasks
+            .as_ref()
+            .map(|t| t.resources.clone())
+            .unwrap_or_default()
+    }
+
+    /// Size cap for the provider cache from `[environments] cache-max-gb`,
+    /// 10 GB by default.
+    pub fn cache_max_bytes(&self) -> u64 {
+        let gb = self
+            .environments
+            .as_ref()
+            .and_then(|e| e.cache_max_gb)
+            .unwrap_or(DEFAULT_CACHE_MAX_GB)
+            .max(0.0);
+        (gb * 1024.0 * 1024.0 * 1024.0) as u64
+    }
+
+    /// Whether pooled environments of `provider` are shared across projects.
+    /// Off unless the provider is listed in `[environments] shared-pools`.
+    pub fn shares_pool(&self, provider: &str) -> bool {
+        !PROJECT_BOUND_PROVIDERS.contains(&provider)
+            && self
+                .environments
+                .as_ref()
+                .is_some_and(|e| e.shared_pools.iter().any(|p| p == provider))
+    }
+
+    /// Each script environment provider and the path of its script, by name.
+    pub fn script_providers(&self) -> Vec<(&str, &Path)> {
+        let mut scripts: Vec<(&str, &Path)> = self
+            .environments
+            .iter()
+            .flat_map(|e| e.providers.iter())
+            .filter_map(|(name, entry)| match entry {
+                EnvironmentProviderEntry::Custom(EnvironmentProviderConfig::Script {
+                    path,
+                    ..
+                }) => Some((name.as_str(), Path::new(path))),
+                EnvironmentProviderEntry::Custom(
+                    EnvironmentProviderConfig::Mock { .. } | EnvironmentProviderConfig::Ssh { .. },
+                )
+                | EnvironmentProviderEntry::BuiltIn(_) => None,
+            })
+            .collect();
+        scripts.sort();
+        scripts
+    }
+
+    /// Settings that parse but cannot be used, such as a rate limit with a
+    /// bad window or a script provider that is not executable.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if let Some(name) = &self.task_provider
+            && self.get_task_provider(name).is_err()
+        {
+            problems.push(format!("task provider {name} is not configured"));
+        }
+        if let Some(name) = &self.environment_provider
+            && !BUILT_IN_ENVIRONMENT_PROVIDERS.contains(&name.as_str())
+            && self.get_environment_provider(name).is_err()
+        {
+            problems.push(format!("environment provider {name} is not configured"));
+        }
+
+        let mut providers: Vec<(&String, &EnvironmentProviderEntry)> = self
+            .environments
+            .iter()
+            .flat_map(|e| e.providers.iter())
+            .collect();
+        providers.sort_by_key(|(name, _)| *name);
+        for (name, entry) in providers {
+            match entry {
+                EnvironmentProviderEntry::Custom(EnvironmentProviderConfig::Script {
+                    path,
+                    ..
+                }) => {
+                    if let Some(problem) = executable_problem(Path::new(path)) {
+                        problems.push(format!("environment provider {name}: {problem}"));
+                    }
+                }
+                EnvironmentProviderEntry::Custom(EnvironmentProviderConfig::Mock {
+                    flags, ..
+                }) => {
+                    for step in flags
+                        .fail
+                        .iter()
+                        .filter(|s| !MOCK_STEPS.contains(&s.as_str()))
+                    {
+                        problems.push(format!(
+                            "environment provider {name}: unknown step {step:?} in fail (use {})",
+                            MOCK_STEPS.join(", ")
+                        ));
+                    }
+                }
+                EnvironmentProviderEntry::Custom(EnvironmentProviderConfig::Ssh {
+                    remote, ..
+                }) => {
+                    if remote.host.is_none() {
+                        problems.push(format!(
+                            "environment provider {name}: ssh providers need a host"
+                        ));
+                    }
+                }
+                EnvironmentProviderEntry::BuiltIn(_)
+                    if !BUILT_IN_ENVIRONMENT_PROVIDERS.contains(&name.as_str()) =>
+                {
+                    problems.push(format!(
+                        "environment provider {name} is missing `type`; only built-in providers take option-only tables"
+                    ));
+                }
+                EnvironmentProviderEntry::BuiltIn(_) => {}
+            }
+        }
+
+        let mut task_providers: Vec<(&String, &TaskProviderConfig)> =
+            self.tasks.iter().flat_map(|t| t.providers.iter()).collect();
+        task_providers.sort_by_key(|(name, _)| *name);
+        for (name, provider) in task_providers {
+            if let Err(e) = provider.cancel().grace() {
+                problems.push(format!("task provider {name}: cancel.grace: {e}"));
+            }
+        }
+
+        let mut rate_limited: Vec<&String> = self
+            .daemon
+            .iter()
+            .flat_map(|d| d.rate_limits.keys())
+            .collect();
+        rate_limited.sort();
+        for provider in rate_limited {
+            if let Err(e) = self.rate_limit(provider) {
+                problems.push(e.to_string());
+            }
+        }
+
+        if let Err(e) = self.request_rate_limit() {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = self.idle_timeout() {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = self.shutdown_grace() {
+            problems.push(e.to_string());
+        }
+        if let Some(format) = &self.default_format
+            && let Err(e) = output_format(format)
+        {
+            problems.push(format!("default-format: {e}"));
+        }
+        if let Err(e) = self.redactor() {
+            problems.push(e.to_string());
+        }
+        let mut formats: Vec<(&String, &String)> = self.formats.iter().collect();
+        formats.sort();
+        for (group, format) in formats {
+            if let Err(e) = output_format(format) {
+                problems.push(format!("[formats] {group}: {e}"));
+            }
+        }
+
+        for (name, provider) in self.notification_providers() {
+            let NotificationProviderConfig::Script { path, events, .. } = provider;
+            if let Some(problem) = executable_problem(&expand_home(path)) {
+                problems.push(format!("notification provider {name}: {problem}"));
+            }
+            if events.is_empty() {
+                problems.push(format!(
+                    "notification provider {name}: events is empty, so nothing is sent"
+                ));
+            }
+            for pattern in events {
+                let known = match pattern.split_once('.') {
+                    Some((kind, rest)) => EVENT_KINDS.contains(&kind) && !rest.is_empty(),
+                    None => pattern == "*",
+                };
+                if !known {
+                    problems.push(format!(
+                        "notification provider {name}: event {pattern:?} should be KIND.ACTION, KIND.STATUS, or KIND.* with KIND one of {}",
+                        EVENT_KINDS.join(", ")
+                    ));
+                }
+            }
+            if let Err(e) = provider.timeout() {
+                problems.push(format!("notification provider {name}: timeout: {e}"));
+            }
+        }
+
+        let resources = self.task_resources();
+        if resources.nice.is_some_and(|nice| !(0..=19).contains(&nice)) {
+            problems.push("tasks.resources: nice must be between 0 and 19".to_string());
+        }
+        if resources.memory_max_mb == Some(0) {
+            problems.push("tasks.resources: memory-max-mb must be at least 1".to_string());
+        }
+        if resources.memory_max_mb.is_some() && !cfg!(target_os = "linux") {
+            problems.push("tasks.resources: memory-max-mb is only supported on Linux".to_string());
+        }
+
+        let mut projects: Vec<&str> = vec![""];
+        projects.extend(
+            self.projects
+                .iter()
+                .flat_map(|p| p.keys().map(String::as_str)),
+        );
+        projects.sort();
+        for project in projects {
+            if let Err(e) = self.task_retention(project) {
+                let problem = e.to_string();
+                if !problems.contains(&problem) {
+                    problems.push(problem);
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// The host `--host NAME` refers to: a `[hosts.NAME]` profile, or else
+    /// `NAME` itself as an SSH destination.
+    pub fn host(&self, name: &str) -> HostConfig {
+        self.hosts.get(name).cloned().unwrap_or_else(|| HostConfig {
+            ssh: name.to_string(),
+            work_path: None,
+            work_home: None,
+        })
+    }
+
+    /// Whether interactive exec sessions are recorded without `--record`.
+    pub fn records_sessions(&self) -> bool {
+        self.environments
+            .as_ref()
+            .is_some_and(|e| e.record_sessions)
+    }
+
     pub fn get_environment_provider(
         &self,
         name: &str,
     ) -> anyhow::Result<&EnvironmentProviderConfig> {
+        match self
+            .environments
+            .as_ref()
+            .and_then(|e| e.providers.get(name))
+        {
+            Some(EnvironmentProviderEntry::Custom(config)) => Ok(config),
+            _ => anyhow::bail!("environment provider not found in config: {name}"),
+        }
+    }
+
+    /// Variables for the subprocesses of an environment provider, built-in or
+    /// custom.
+    pub fn provider_process_env(&self, name: &str) -> ProcessEnv {
+        self.environments
+            .as_ref()
+            .and_then(|e| e.providers.get(name))
+            .map(|entry| entry.process_env().clone())
+            .unwrap_or_default()
+    }
+
+    /// Settings for a built-in provider, if it has an
+    /// `[environments.providers.NAME]` table.
+    pub fn built_in_provider(&self, name: &str) -> Option<&BuiltInProviderConfig> {
+        match self.environments.as_ref()?.providers.get(name)? {
+            EnvironmentProviderEntry::BuiltIn(config) => Some(config),
+            EnvironmentProviderEntry::Custom(_) => None,
+        }
+    }
+
+    /// Prepare options configured for an environment provider, built-in or custom.
+    pub fn prepare_options(&self, name: &str) -> PrepareOptions {
+        self.environments
+            .as_ref()
+            .and_then(|e| e.providers.get(name))
+            .map(|entry| entry.options().clone())
+            .unwrap_or_default()
+    }
+
+    /// Capability tags configured for an environment provider.
+    pub fn provider_tags(&self, name: &str) -> &[String] {
         self.environments
             .as_ref()
             .and_then(|e| e.providers.get(name))
-            .ok_or_else(|| anyhow::anyhow!("environment provider not found in config: {name}"))
+            .map(|entry| entry.tags())
+            .unwrap_or_default()
+    }
+
+    /// Configured environment providers tagged with every requirement, sorted
+    /// by name with `preferred` first when it qualifies.
+    pub fn providers_with_tags(
+        &self,
+        requirements: &[String],
+        preferred: Option<&str>,
+    ) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .environments
+            .iter()
+            .flat_map(|e| e.providers.iter())
+            .filter(|(_, entry)| requirements.iter().all(|r| entry.tags().contains(r)))
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort_by_key(|name| (Some(name.as_str()) != preferred, name.clone()));
+        names
+    }
+
+    /// Prepare options for a provider with project-level settings applied.
+    pub fn prepare_options_for_project(
+        &self,
+        provider: &str,
+        project_name: &str,
+    ) -> PrepareOptions {
+        let mut options = self.prepare_options(provider);
+        options.base_branch = self
+            .base_branch_setting(project_name, provider)
+            .map(|(value, _)| value);
+        options.sparse_paths = self
+            .sparse_paths_setting(project_name, provider)
+            .map(|(value, _)| value)
+            .unwrap_or_default();
+        options
+    }
+}
+
+pub fn path() -> anyhow::Result<PathBuf> {
+    Ok(paths::config_dir()?.join("config.toml"))
+}
+
+/// Why `path` cannot be run as a provider script, if it cannot.
+pub fn executable_problem(path: &Path) -> Option<String> {
+    match std::fs::metadata(path) {
+        Err(_) => Some(format!("{} does not exist", path.display())),
+        Ok(meta) if !meta.is_file() => Some(format!("{} is not a file", path.display())),
+        Ok(meta) if meta.permissions().mode() & 0o111 == 0 => {
+            Some(format!("{} is not executable", path.display()))
+        }
+        Ok(_) => None,
+    }
+}
+
+/// A setting that differs between two versions of the config file.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ConfigChange {
+    /// Dotted key, e.g. `daemon.rate-limits.git-worktree.max`.
+    pub setting: String,
+    /// Previous value as TOML, or `None` if the setting was added.
+    #[serde(default)]
+    pub old: Option<String>,
+    /// New value as TOML, or `None` if the setting was removed.
+    #[serde(default)]
+    pub new: Option<String>,
+}
+
+/// What a config reload changed.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ReloadSummary {
+    #[serde(default)]
+    pub changes: Vec<ConfigChange>,
+}
+
+/// The settings that differ between two config tables, sorted by key.
+pub fn diff(old: &toml::Table, new: &toml::Table) -> Vec<ConfigChange> {
+    let (old, new) = (flatten(old), flatten(new));
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    keys.into_iter()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .map(|key| ConfigChange {
+            setting: key.clone(),
+            old: old.get(key).cloned(),
+            new: new.get(key).cloned(),
+        })
+        .collect()
+}
+
+/// Leaf values of a table keyed by their dotted path. Arrays count as one
+/// value.
+fn flatten(table: &toml::Table) -> BTreeMap<String, String> {
+    fn walk(prefix: &str, table: &toml::Table, out: &mut BTreeMap<String, String>) {
+        for (key, value) in table {
+            let path = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{prefix}.{key}")
+            };
+            match value {
+                toml::Value::Table(inner) => walk(&path, inner, out),
+                other => {
+                    out.insert(path, other.to_string());
+                }
+            }
+        }
+    }
+    let mut out = BTreeMap::new();
+    walk("", table, &mut out);
+    out
+}
+
+/// The config table a running daemon has accepted. Once set, `load` reads
+/// it instead of the file, so edits take effect only when a reload accepts
+/// them.
+static ACCEPTED: Mutex<Option<toml::Table>> = Mutex::new(None);
+
+/// Make `table` the config that `load` returns from now on.
+pub fn accept(table: toml::Table) {
+    *ACCEPTED.lock().unwrap_or_else(|e| e.into_inner()) = Some(table);
+}
+
+/// The table last passed to `accept`, if any.
+pub fn accepted() -> Option<toml::Table> {
+    ACCEPTED.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// The config file as a raw table, empty if there is no file.
+pub fn load_table() -> anyhow::Result<toml::Table> {
+    let path = path()?;
+
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => Ok(toml::from_str(&contents)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(toml::Table::new()),
+        Err(e) => Err(e.into()),
     }
 }
 
 pub fn load() -> anyhow::Result<Config> {
-    let path = paths::config_dir()?.join("config.toml");
+    if let Some(table) = accepted() {
+        return Ok(toml::Value::Table(table).try_into()?);
+    }
+
+    let path = path()?;
 
     match std::fs::read_to_string(&path) {
         Ok(contents) => Ok(toml::from_str(&contents)?),
@@ -99,9 +1709,313 @@ pub fn load() -> anyhow::Result<Config> {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::{Config, EnvironmentProviderConfig};
+/// Load the global config along with the `.work.toml` at the root of a
+/// project's checkout, if there is one.
+pub fn load_for_project(project_name: &str, project_path: &Path) -> anyhow::Result<Config> {
+    let mut config = load()?;
+    let path = project_path.join(PROJECT_CONFIG_FILE);
+
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => {
+            let project_config = toml::from_str(&contents)
+                .map_err(|e| anyhow::anyhow!("invalid {}: {e}", path.display()))?;
+            config.repository = Some(RepositoryConfig {
+                project_name: project_name.to_string(),
+                path,
+                config: project_config,
+            });
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CachedPath, CancelSignal, Config, ConfigSource, DEFAULT_CANCEL_GRACE, DEFAULT_PTY_COLUMNS,
+        DEFAULT_PTY_ROWS, DEFAULT_SHUTDOWN_GRACE, EditorCommand, EnvironmentProviderConfig,
+        IoClass, PostOnFailure, PrepareOptions, ProviderKind, RateLimit, RepositoryConfig,
+        RetryPolicy, TaskProviderConfig, TaskProviderStdin, TaskResources, parse_duration,
+    };
+
+    #[test]
+    fn pools_are_shared_only_for_listed_project_agnostic_providers() {
+        let config: Config = toml::from_str(
+            r#"
+[environments]
+shared-pools = ["docker", "git-worktree"]
+
+[environments.providers.docker]
+type = "script"
+path = "/bin/docker-provider"
+
+[environments.providers.sandbox]
+type = "script"
+path = "/bin/sandbox-provider"
+"#,
+        )
+        .unwrap();
+
+        assert!(config.shares_pool("docker"));
+        assert!(!config.shares_pool("sandbox"));
+        assert!(!config.shares_pool("git-worktree"));
+        assert!(!Config::default().shares_pool("docker"));
+    }
+
+    #[test]
+    fn diff_lists_changed_settings_by_dotted_key() {
+        let old: toml::Table = toml::from_str(
+            r#"
+environment-provider = "git-worktree"
+
+[daemon.rate-limits.git-worktree]
+max = 2
+"#,
+        )
+        .unwrap();
+        let new: toml::Table = toml::from_str(
+            r#"
+[daemon]
+debug = true
+
+[daemon.rate-limits.git-worktree]
+max = 4
+"#,
+        )
+        .unwrap();
+
+        let change = |setting: &str, old: Option<&str>, new: Option<&str>| super::ConfigChange {
+            setting: setting.to_string(),
+            old: old.map(str::to_string),
+            new: new.map(str::to_string),
+        };
+        assert_eq!(
+            super::diff(&old, &new),
+            [
+                change("daemon.debug", None, Some("true")),
+                change("daemon.rate-limits.git-worktree.max", Some("2"), Some("4")),
+                change("environment-provider", Some("\"git-worktree\""), None),
+            ]
+        );
+        assert!(super::diff(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn problems_flag_unusable_settings() {
+        let config: Config = toml::from_str(
+            r#"
+task-provider = "missing"
+
+[daemon.rate-limits.git-worktree]
+max = 2
+per = "soon"
+
+[environments.providers.sandbox]
+type = "script"
+path = "/nonexistent/sandbox.sh"
+
+[environments.providers.typo]
+depth = 1
+"#,
+        )
+        .unwrap();
+
+        let problems = config.problems();
+        assert_eq!(problems.len(), 4, "{problems:?}");
+        assert_eq!(problems[0], "task provider missing is not configured");
+        assert!(problems[1].contains("/nonexistent/sandbox.sh does not exist"));
+        assert!(problems[2].starts_with("environment provider typo is missing `type`"));
+        assert!(Config::default().problems().is_empty());
+    }
+
+    #[test]
+    fn notification_problems_name_the_provider() {
+        let config: Config = toml::from_str(
+            r#"
+[notifications.providers.slack]
+type = "script"
+path = "/bin/true"
+events = ["task.complete", "tasks.failed", "environment.*", "*"]
+timeout = "later"
+"#,
+        )
+        .unwrap();
+
+        let problems = config.problems();
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].starts_with("notification provider slack: event \"tasks.failed\""));
+        assert!(problems[1].starts_with("notification provider slack: timeout:"));
+    }
+
+    #[test]
+    fn task_provider_cancel_defaults_and_problems() {
+        let config: Config = toml::from_str(
+            r#"
+[tasks.providers.agent]
+type = "command"
+command = "agent"
+cancel = { signal = "SIGINT", grace = "2m" }
+
+[tasks.providers.plain]
+type = "command"
+command = "true"
+
+[tasks.providers.hasty]
+type = "command"
+command = "true"
+cancel = { grace = "soon" }
+"#,
+        )
+        .unwrap();
+
+        let agent = config.get_task_provider("agent").unwrap().cancel();
+        assert_eq!(agent.signal, CancelSignal::Int);
+        assert_eq!(agent.grace().unwrap(), std::time::Duration::from_secs(120));
+        let plain = config.get_task_provider("plain").unwrap().cancel();
+        assert_eq!(plain.signal, CancelSignal::Term);
+        assert_eq!(plain.grace().unwrap(), DEFAULT_CANCEL_GRACE);
+
+        let problems = config.problems();
+        assert_eq!(problems.len(), 1, "{problems:?}");
+        assert!(problems[0].starts_with("task provider hasty: cancel.grace:"));
+    }
+
+    #[test]
+    fn max_log_mb_sets_task_log_limit() {
+        let config: Config = toml::from_str("[tasks]\nmax-log-mb = 1.5\n").unwrap();
+        assert_eq!(config.max_task_log_bytes(), Some(1_572_864));
+
+        let config: Config = toml::from_str("[tasks]\nmax-log-mb = 0\n").unwrap();
+        assert_eq!(config.max_task_log_bytes(), None);
+        assert_eq!(Config::default().max_task_log_bytes(), None);
+    }
+
+    #[test]
+    fn encrypt_logs_is_off_by_default_and_works_with_a_log_cap() {
+        assert!(!Config::default().encrypt_task_logs());
+
+        let config: Config = toml::from_str("[tasks]\nencrypt-logs = true\n").unwrap();
+        assert!(config.encrypt_task_logs());
+        assert!(config.problems().is_empty());
+
+        let config: Config =
+            toml::from_str("[tasks]\nencrypt-logs = true\nmax-log-mb = 10\n").unwrap();
+        assert!(config.problems().is_empty());
+    }
+
+    #[test]
+    fn redact_patterns_compile_or_flag_a_problem() {
+        assert!(Config::default().redactor().unwrap().is_none());
+
+        let config: Config =
+            toml::from_str("[redact]\npatterns = [\"ghp_[A-Za-z0-9]+\"]\n").unwrap();
+        let redactor = config.redactor().unwrap().unwrap();
+        assert_eq!(redactor.redact_str("token ghp_abc1"), "token [redacted]");
+        assert!(config.problems().is_empty());
+
+        let config: Config = toml::from_str("[redact]\npatterns = [\"ghp_(\"]\n").unwrap();
+        assert_eq!(config.problems().len(), 1);
+        assert!(config.problems()[0].starts_with("[redact] patterns"));
+    }
+
+    #[test]
+    fn provider_env_prepends_path() {
+        let config: Config = toml::from_str(
+            r#"
+[environments.providers.brew]
+type = "script"
+path = "/bin/true"
+path-prepend = ["/opt/homebrew/bin", "~/bin"]
+env = { PATH = "/usr/bin:/bin", HOMEBREW_NO_AUTO_UPDATE = "1" }
+
+[environments.providers.git-worktree]
+env = { GIT_TERMINAL_PROMPT = "0" }
+"#,
+        )
+        .unwrap();
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(
+            config.provider_process_env("brew").vars(),
+            vec![
+                ("HOMEBREW_NO_AUTO_UPDATE".to_string(), "1".to_string()),
+                (
+                    "PATH".to_string(),
+                    format!(
+                        "/opt/homebrew/bin:{}:/usr/bin:/bin",
+                        home.join("bin").display()
+                    )
+                ),
+            ]
+        );
+        assert_eq!(
+            config.provider_process_env("git-worktree").vars(),
+            vec![("GIT_TERMINAL_PROMPT".to_string(), "0".to_string())]
+        );
+        assert!(config.provider_process_env("missing").vars().is_empty());
+    }
+
+    #[test]
+    fn cache_max_gb_sets_cache_cap() {
+        let config: Config = toml::from_str("[environments]\ncache-max-gb = 0.5\n").unwrap();
+        assert_eq!(config.cache_max_bytes(), 536_870_912);
+        assert_eq!(Config::default().cache_max_bytes(), 10_737_418_240);
+    }
+
+    #[test]
+    fn editor_config_takes_precedence_over_env() {
+        let config: Config = toml::from_str(
+            r#"
+[editor]
+command = "nvim"
+args = ["-u", "NONE"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.editor_with_env(Some("vi".to_string())).unwrap(),
+            EditorCommand {
+                program: "nvim".to_string(),
+                args: vec!["-u".to_string(), "NONE".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn editor_from_env_splits_flags() {
+        let config = Config::default();
+
+        assert_eq!(
+            config
+                .editor_with_env(Some("emacsclient -t".to_string()))
+                .unwrap(),
+            EditorCommand {
+                program: "emacsclient".to_string(),
+                args: vec!["-t".to_string()],
+            }
+        );
+        assert!(config.editor_with_env(None).is_err());
+        assert!(config.editor_with_env(Some("  ".to_string())).is_err());
+    }
+
+    #[test]
+    fn detaching_editors_are_made_to_wait() {
+        let config = Config::default();
+
+        let editor = config
+            .editor_with_env(Some("/usr/local/bin/code".to_string()))
+            .unwrap();
+        assert_eq!(editor.args, vec!["--wait"]);
+
+        let editor = config.editor_with_env(Some("subl -w".to_string())).unwrap();
+        assert_eq!(editor.args, vec!["-w"]);
+
+        let editor = config.editor_with_env(Some("vim".to_string())).unwrap();
+        assert!(editor.args.is_empty());
+    }
 
     #[test]
     fn project_specific_defaults_override_global_defaults() {
@@ -202,8 +2116,634 @@ path = "/tmp/sandbox-provider.sh"
         let provider = config.get_environment_provider("sandbox").unwrap();
         assert!(matches!(
             provider,
-            EnvironmentProviderConfig::Script { path }
+            EnvironmentProviderConfig::Script { path, .. }
             if path == "/tmp/sandbox-provider.sh"
         ));
     }
+
+    #[test]
+    fn built_in_provider_prepare_options_deserialize() {
+        let config: Config = toml::from_str(
+            r#"
+[environments.providers.git-worktree]
+sparse-paths = ["crates/app"]
+cached-paths = [
+  { path = "node_modules", key-files = ["package-lock.json"], command = "npm ci" },
+]
+
+[environments.providers.sandbox]
+type = "script"
+path = "/tmp/sandbox-provider.sh"
+depth = 1
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.prepare_options("git-worktree"),
+            PrepareOptions {
+                depth: None,
+                sparse_paths: vec!["crates/app".to_string()],
+                base_branch: None,
+                source_ref: None,
+                cached_paths: vec![CachedPath {
+                    path: "node_modules".to_string(),
+                    key_files: vec!["package-lock.json".to_string()],
+                    command: "npm ci".to_string(),
+                }],
+            }
+        );
+        assert_eq!(config.prepare_options("sandbox").depth, Some(1));
+        assert_eq!(
+            config.prepare_options("apfs-worktree"),
+            PrepareOptions::default()
+        );
+        assert!(config.get_environment_provider("git-worktree").is_err());
+        assert!(config.get_environment_provider("sandbox").is_ok());
+    }
+
+    #[test]
+    fn environment_provider_tables_reject_unknown_keys() {
+        // A misspelled `type` must not turn a script provider into options
+        // for a built-in one.
+        for table in [
+            "[environments.providers.sandbox]\ntpye = \"script\"\npath = \"/tmp/p.sh\"\n",
+            "[environments.providers.git-worktree]\nsparse-path = [\"crates\"]\n",
+            "[environments.providers.sandbox]\ntype = \"script\"\npath = \"/tmp/p.sh\"\ndpeth = 1\n",
+        ] {
+            assert!(toml::from_str::<Config>(table).is_err(), "{table}");
+        }
+    }
+
+    #[test]
+    fn host_names_a_profile_or_an_ssh_destination() {
+        let config: Config = toml::from_str(
+            r#"
+[hosts.devbox]
+ssh = "me@devbox.internal"
+work-path = "~/.cargo/bin/work"
+"#,
+        )
+        .unwrap();
+
+        let devbox = config.host("devbox");
+        assert_eq!(devbox.ssh, "me@devbox.internal");
+        assert_eq!(devbox.work_path.as_deref(), Some("~/.cargo/bin/work"));
+        let other = config.host("me@other");
+        assert_eq!(other.ssh, "me@other");
+        assert_eq!(other.work_path, None);
+    }
+
+    #[test]
+    fn providers_match_requirements_by_tags() {
+        let config: Config = toml::from_str(
+            r#"
+[environments.providers.git-worktree]
+tags = ["node20"]
+
+[environments.providers.gpu-box]
+type = "script"
+path = "/tmp/gpu.sh"
+tags = ["gpu", "node20"]
+
+[environments.providers.cuda]
+type = "script"
+path = "/tmp/cuda.sh"
+tags = ["gpu"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.provider_tags("cuda"), ["gpu"]);
+        assert!(config.provider_tags("apfs-worktree").is_empty());
+        let gpu = vec!["gpu".to_string()];
+        assert_eq!(config.providers_with_tags(&gpu, None), ["cuda", "gpu-box"]);
+        assert_eq!(
+            config.providers_with_tags(&gpu, Some("gpu-box")),
+            ["gpu-box", "cuda"]
+        );
+        let both = vec!["gpu".to_string(), "node20".to_string()];
+        assert_eq!(config.providers_with_tags(&both, None), ["gpu-box"]);
+        assert!(
+            config
+                .providers_with_tags(&["tpu".to_string()], None)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn repository_defaults_sit_between_project_and_global_config() {
+        let mut config: Config = toml::from_str(
+            r#"
+task-provider = "global-task"
+environment-provider = "global-env"
+
+[projects.backend]
+environment-provider = "backend-env"
+
+[environments.providers.git-worktree]
+base-branch = "develop"
+sparse-paths = ["web"]
+"#,
+        )
+        .unwrap();
+        config.repository = Some(RepositoryConfig {
+            project_name: "backend".to_string(),
+            path: ".work.toml".into(),
+            config: toml::from_str(
+                r#"
+task-provider = "repo-task"
+environment-provider = "repo-env"
+base-branch = "trunk"
+sparse-paths = ["services/api", "proto"]
+"#,
+            )
+            .unwrap(),
+        });
+
+        assert_eq!(
+            config.task_provider_setting("backend"),
+            Some(("repo-task".to_string(), ConfigSource::Repository))
+        );
+        assert_eq!(
+            config.environment_provider_setting("backend"),
+            Some(("backend-env".to_string(), ConfigSource::Project))
+        );
+        assert_eq!(
+            config
+                .prepare_options_for_project("git-worktree", "backend")
+                .base_branch
+                .as_deref(),
+            Some("trunk")
+        );
+        assert_eq!(
+            config
+                .prepare_options_for_project("git-worktree", "backend")
+                .sparse_paths,
+            ["services/api", "proto"]
+        );
+
+        // A repository config only applies to the project it was loaded for.
+        assert_eq!(
+            config.task_provider_setting("frontend"),
+            Some(("global-task".to_string(), ConfigSource::Global))
+        );
+        assert_eq!(
+            config.base_branch_setting("frontend", "git-worktree"),
+            Some(("develop".to_string(), ConfigSource::Provider))
+        );
+        assert_eq!(
+            config.sparse_paths_setting("frontend", "git-worktree"),
+            Some((vec!["web".to_string()], ConfigSource::Provider))
+        );
+    }
+
+    #[test]
+    fn parses_durations_with_units() {
+        assert_eq!(
+            parse_duration("90s").unwrap(),
+            chrono::Duration::seconds(90)
+        );
+        assert_eq!(parse_duration("14d").unwrap(), chrono::Duration::days(14));
+        assert_eq!(parse_duration("2w").unwrap(), chrono::Duration::weeks(2));
+        assert!(parse_duration("14").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("3y").is_err());
+    }
+
+    #[test]
+    fn task_resources_parse_and_report_bad_values() {
+        let config: Config = toml::from_str(
+            r#"
+[tasks.resources]
+nice = 10
+io-class = "idle"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.task_resources(),
+            TaskResources {
+                nice: Some(10),
+                io_class: Some(IoClass::Idle),
+                memory_max_mb: None,
+            }
+        );
+        assert!(config.problems().is_empty());
+        assert_eq!(Config::default().task_resources(), TaskResources::default());
+
+        let config: Config = toml::from_str("[tasks.resources]\nnice = 20\n").unwrap();
+        assert_eq!(
+            config.problems(),
+            vec!["tasks.resources: nice must be between 0 and 19".to_string()]
+        );
+    }
+
+    #[test]
+    fn provider_allowlists_only_narrow() {
+        let mut config: Config = toml::from_str(
+            r#"
+[projects.secure]
+allowed-env-providers = ["sandbox", "git-worktree"]
+"#,
+        )
+        .unwrap();
+        assert!(config.allows_provider("secure", ProviderKind::Environment, "sandbox"));
+        assert!(!config.allows_provider("secure", ProviderKind::Environment, "apfs-worktree"));
+        assert!(config.allows_provider("secure", ProviderKind::Task, "claude"));
+        assert!(config.allows_provider("other", ProviderKind::Environment, "apfs-worktree"));
+
+        config.repository = Some(RepositoryConfig {
+            project_name: "secure".to_string(),
+            path: ".work.toml".into(),
+            config: toml::from_str(r#"allowed-env-providers = ["sandbox", "docker"]"#).unwrap(),
+        });
+        assert!(config.allows_provider("secure", ProviderKind::Environment, "sandbox"));
+        assert!(!config.allows_provider("secure", ProviderKind::Environment, "git-worktree"));
+        assert!(!config.allows_provider("secure", ProviderKind::Environment, "docker"));
+    }
+
+    #[test]
+    fn task_retention_applies_project_overrides() {
+        let config: Config = toml::from_str(
+            r#"
+[tasks]
+auto-remove-after = "14d"
+statuses = ["complete", "failed"]
+
+[projects.scratch]
+auto-remove-after = "1d"
+
+[projects.archive]
+auto-remove-after = "never"
+"#,
+        )
+        .unwrap();
+
+        let policy = config.task_retention("backend").unwrap().unwrap();
+        assert_eq!(policy.max_age, chrono::Duration::days(14));
+        assert_eq!(policy.statuses, vec!["complete", "failed"]);
+        assert_eq!(policy.exempt_label, "keep");
+        assert_eq!(
+            config.task_retention("scratch").unwrap().unwrap().max_age,
+            chrono::Duration::days(1)
+        );
+        assert!(config.task_retention("archive").unwrap().is_none());
+        assert!(
+            Config::default()
+                .task_retention("backend")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn task_retention_rejects_non_terminal_statuses() {
+        let config: Config = toml::from_str(
+            r#"
+[tasks]
+auto-remove-after = "14d"
+statuses = ["started"]
+"#,
+        )
+        .unwrap();
+        assert!(config.task_retention("backend").is_err());
+    }
+
+    #[test]
+    fn retention_skips_recent_exempt_and_ineligible_tasks() {
+        let config: Config = toml::from_str("[tasks]\nauto-remove-after = \"14d\"\n").unwrap();
+        let policy = config.task_retention("backend").unwrap().unwrap();
+        let now = chrono::Utc::now();
+        let task = |status: &str, age_days: i64, labels: &[&str]| crate::db::Task {
+            id: "t".to_string(),
+            environment_id: "e".to_string(),
+            project_id: "p".to_string(),
+            provider: "claude".to_string(),
+            description: String::new(),
+            status: status.to_string(),
+            result_summary: None,
+            result_data: None,
+            resource_usage: None,
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+            source_ref: None,
+            requirements: Vec::new(),
+            group_id: None,
+            priority: Default::default(),
+            notes: Vec::new(),
+            alias: None,
+            status_changed_at: Some((now - chrono::Duration::days(age_days)).to_rfc3339()),
+            origin: None,
+            cost: None,
+            created_at: String::new(),
+            // Edited since, which doesn't make it any younger.
+            updated_at: now.to_rfc3339(),
+        };
+
+        assert!(policy.is_expired(&task("complete", 15, &[]), now));
+        assert!(!policy.is_expired(&task("complete", 13, &[]), now));
+        assert!(!policy.is_expired(&task("complete", 15, &["keep"]), now));
+        assert!(!policy.is_expired(&task("failed", 15, &[]), now));
+    }
+
+    #[test]
+    fn daemon_resource_thresholds_deserialize() {
+        let config: Config = toml::from_str(
+            r#"
+[daemon]
+min-free-disk-gb = 20
+max-load-average = 8.5
+"#,
+        )
+        .unwrap();
+
+        let daemon = config.daemon.unwrap();
+        assert_eq!(daemon.min_free_disk_gb, Some(20.0));
+        assert_eq!(daemon.max_load_average, Some(8.5));
+    }
+
+    #[test]
+    fn retry_overrides_apply_per_job_type() {
+        let config: Config = toml::from_str(
+            r#"
+[daemon.retries.remove_environment]
+limit = 1
+
+[daemon.retries.prepare_environment]
+max-delay = 600
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.retry_policy("remove_environment"),
+            RetryPolicy {
+                limit: 1,
+                max_delay_seconds: 60,
+            }
+        );
+        assert_eq!(
+            config.retry_policy("prepare_environment"),
+            RetryPolicy {
+                limit: 5,
+                max_delay_seconds: 600,
+            }
+        );
+        assert_eq!(
+            config.retry_policy("run_task"),
+            RetryPolicy::default_for("run_task")
+        );
+    }
+
+    #[test]
+    fn rate_limits_apply_per_provider() {
+        let config: Config = toml::from_str(
+            r#"
+[daemon.rate-limits.git-worktree]
+max = 10
+
+[daemon.rate-limits.sandbox]
+max = 2
+per = "30s"
+jitter = "1s"
+
+[daemon.rate-limits.broken]
+max = 0
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.rate_limit("git-worktree").unwrap(),
+            Some(RateLimit {
+                max: 10,
+                per: std::time::Duration::from_secs(60),
+                jitter: std::time::Duration::from_secs(6),
+            })
+        );
+        assert_eq!(
+            config.rate_limit("sandbox").unwrap(),
+            Some(RateLimit {
+                max: 2,
+                per: std::time::Duration::from_secs(30),
+                jitter: std::time::Duration::from_secs(1),
+            })
+        );
+        assert_eq!(config.rate_limit("apfs-worktree").unwrap(), None);
+        assert!(config.rate_limit("broken").is_err());
+    }
+
+    #[test]
+    fn request_rate_limit_defaults_to_a_minute_window() {
+        assert_eq!(Config::default().request_rate_limit().unwrap(), None);
+
+        let config: Config = toml::from_str("[daemon.request-rate-limit]\nmax = 600\n").unwrap();
+        let limit = config.request_rate_limit().unwrap().unwrap();
+        assert_eq!(limit.max, 600);
+        assert_eq!(limit.per, std::time::Duration::from_secs(60));
+
+        let config: Config =
+            toml::from_str("[daemon.request-rate-limit]\nmax = 5\nper = \"0s\"\n").unwrap();
+        let problems = config.problems();
+        assert!(
+            problems
+                .iter()
+                .any(|p| p == "[daemon.request-rate-limit] per must be longer than zero"),
+            "{problems:?}"
+        );
+    }
+
+    #[test]
+    fn default_format_prefers_the_group_entry() {
+        assert_eq!(Config::default().default_format("task").unwrap(), None);
+
+        let config: Config =
+            toml::from_str("default-format = \"json\"\n\n[formats]\nenv = \"plain\"\n").unwrap();
+        assert_eq!(config.default_format("task").unwrap(), Some("json"));
+        assert_eq!(config.default_format("env").unwrap(), Some("plain"));
+
+        let config: Config = toml::from_str("[formats]\ntask = \"yaml\"\n").unwrap();
+        assert!(config.default_format("task").is_err());
+        assert_eq!(
+            config.problems(),
+            vec!["[formats] task: unknown format \"yaml\" (use human, plain, or json)".to_string()]
+        );
+    }
+
+    #[test]
+    fn shutdown_grace_defaults_and_allows_zero() {
+        assert_eq!(
+            Config::default().shutdown_grace().unwrap(),
+            DEFAULT_SHUTDOWN_GRACE
+        );
+        let config: Config = toml::from_str("[daemon]\nshutdown-grace = \"0s\"\n").unwrap();
+        assert_eq!(config.shutdown_grace().unwrap(), std::time::Duration::ZERO);
+        let config: Config = toml::from_str("[daemon]\nshutdown-grace = \"soon\"\n").unwrap();
+        assert_eq!(config.problems().len(), 1);
+    }
+
+    #[test]
+    fn idle_timeout_is_optional_and_positive() {
+        assert_eq!(Config::default().idle_timeout().unwrap(), None);
+
+        let config: Config = toml::from_str("[daemon]\nidle-timeout = \"30m\"\n").unwrap();
+        assert_eq!(
+            config.idle_timeout().unwrap(),
+            Some(std::time::Duration::from_secs(30 * 60))
+        );
+
+        let config: Config = toml::from_str("[daemon]\nidle-timeout = \"0s\"\n").unwrap();
+        assert_eq!(
+            config.problems(),
+            vec!["[daemon] idle-timeout must be longer than zero".to_string()]
+        );
+    }
+
+    #[test]
+    fn retry_delay_backs_off_up_to_max() {
+        let policy = RetryPolicy::default_for("prepare_environment");
+        assert_eq!(policy.delay_seconds(0), 2);
+        assert_eq!(policy.delay_seconds(3), 8);
+        assert_eq!(policy.delay_seconds(10), 300);
+    }
+
+    #[test]
+    fn task_provider_stdin_description_deserializes() {
+        let config: Config = toml::from_str(
+            r#"
+[tasks.providers.claude]
+type = "command"
+command = "claude"
+args = ["-p"]
+stdin = "description"
+"#,
+        )
+        .unwrap();
+
+        let provider = config.get_task_provider("claude").unwrap();
+        assert!(matches!(
+            provider,
+            TaskProviderConfig::Command {
+                stdin: Some(TaskProviderStdin::Description),
+                ..
+            }
+        ));
+        assert_eq!(provider.pty_size(), None);
+    }
+
+    #[test]
+    fn task_provider_pre_and_post_default_to_none_and_skipping() {
+        let config: Config = toml::from_str(
+            r#"
+[tasks.providers.plain]
+type = "command"
+command = "agent"
+
+[tasks.providers.chained]
+type = "command"
+command = "agent"
+pre = ["git", "checkout", "-b", "work"]
+post = ["git", "commit", "-am", "done"]
+post-on-failure = "always"
+"#,
+        )
+        .unwrap();
+
+        let TaskProviderConfig::Command {
+            pre,
+            post,
+            post_on_failure,
+            ..
+        } = config.get_task_provider("plain").unwrap()
+        else {
+            panic!("expected a command provider");
+        };
+        assert!(pre.is_empty() && post.is_empty());
+        assert_eq!(*post_on_failure, PostOnFailure::Skip);
+
+        let TaskProviderConfig::Command {
+            pre,
+            post,
+            post_on_failure,
+            ..
+        } = config.get_task_provider("chained").unwrap()
+        else {
+            panic!("expected a command provider");
+        };
+        assert_eq!(pre, &["git", "checkout", "-b", "work"]);
+        assert_eq!(post, &["git", "commit", "-am", "done"]);
+        assert_eq!(*post_on_failure, PostOnFailure::Always);
+    }
+
+    #[test]
+    fn mock_providers_deserialize_and_check_their_fail_steps() {
+        let config: Config = toml::from_str(
+            r#"
+[tasks.providers.fake]
+type = "mock"
+latency-ms = 50
+
+[environments.providers.flaky]
+type = "mock"
+tags = ["gpu"]
+latency-ms = 10
+fail = ["claim", "teleport"]
+"#,
+        )
+        .unwrap();
+
+        let TaskProviderConfig::Mock {
+            output,
+            latency_ms,
+            exit_code,
+            summary,
+        } = config.get_task_provider("fake").unwrap()
+        else {
+            panic!("expected a mock provider");
+        };
+        assert_eq!(output, &["{task_description}"]);
+        assert_eq!((*latency_ms, *exit_code, summary), (50, 0, &None));
+
+        let EnvironmentProviderConfig::Mock { flags, .. } =
+            config.get_environment_provider("flaky").unwrap()
+        else {
+            panic!("expected a mock provider");
+        };
+        assert_eq!(flags.latency_ms, 10);
+        assert_eq!(config.provider_tags("flaky"), ["gpu"]);
+        assert_eq!(
+            config.problems(),
+            [
+                "environment provider flaky: unknown step \"teleport\" in fail (use prepare, update, claim, remove, run, snapshot, restore)"
+            ]
+        );
+    }
+
+    #[test]
+    fn pty_size_defaults_when_pty_is_on() {
+        let config: Config = toml::from_str(
+            r#"
+[tasks.providers.agent]
+type = "command"
+command = "agent"
+pty = true
+
+[tasks.providers.wide]
+type = "command"
+command = "agent"
+pty = true
+pty-columns = 200
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.get_task_provider("agent").unwrap().pty_size(),
+            Some((DEFAULT_PTY_COLUMNS, DEFAULT_PTY_ROWS))
+        );
+        assert_eq!(
+            config.get_task_provider("wide").unwrap().pty_size(),
+            Some((200, DEFAULT_PTY_ROWS))
+        );
+    }
 }
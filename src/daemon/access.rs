@@ -0,0 +1,449 @@
+//! Who is calling the daemon, and how much.
+//!
+//! Every request is counted against its peer and written to `access.log` in
+//! the state directory, one JSON object per line, by a thread of its own so
+//! requests never wait on the disk. A peer on the socket is the process on
+//! the other end, by its credentials, or the connection when the kernel
+//! can't say, so one busy client doesn't use up another's limit. With
+//! `[daemon.request-rate-limit]`, a peer that goes over it gets
+//! `rate_limited` until its window frees up; `/health` and `/ready` are
+//! never limited, so waiting for the daemon still works.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+use axum::extract::ConnectInfo;
+use axum::extract::connect_info::Connected;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::serve::IncomingStream;
+
+use super::idle;
+use super::rate_limit::RateLimiter;
+use crate::config::RateLimit;
+use crate::error::{Error, ErrorCode};
+
+/// The log is moved to `access.log.1`, replacing the last one, once it
+/// grows past this.
+const MAX_LOG_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Routes the rate limit never applies to.
+const UNLIMITED_PATHS: [&str; 2] = ["/health", "/ready"];
+
+/// Peers remembered at once; the one seen least recently is forgotten to
+/// make room for another.
+const MAX_PEERS: usize = 1024;
+
+/// Log lines waiting for the writer before new ones are dropped.
+const LOG_BACKLOG: usize = 4096;
+
+/// Numbers each accepted connection, for peers without credentials.
+static CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+
+/// The process on the other end of a connection, as far as the socket can
+/// tell.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Peer {
+    pub uid: Option<u32>,
+    pub pid: Option<i32>,
+    /// Which connection since the daemon started this is.
+    pub connection: u64,
+}
+
+impl Peer {
+    pub fn of(stream: &tokio::net::UnixStream) -> Self {
+        let connection = CONNECTIONS.fetch_add(1, Ordering::Relaxed) + 1;
+        match stream.peer_cred() {
+            Ok(cred) => Self {
+                uid: Some(cred.uid()),
+                pid: cred.pid(),
+                connection,
+            },
+            Err(e) => {
+                tracing::debug!(error = %e, "failed to read peer credentials");
+                Self {
+                    connection,
+                    ..Self::default()
+                }
+            }
+        }
+    }
+
+    /// Where a request on this connection came from: `client`, as the
+    /// request says, and the user and working directory of the process on
+    /// the other end, as the kernel says, on this machine.
+    pub fn origin(&self, client: String) -> crate::db::Origin {
+        crate::db::Origin {
+            client,
+            host: hostname(),
+            user: self.uid.and_then(user_name),
+            cwd: self.pid.and_then(process_cwd),
+        }
+    }
+
+    /// What requests are counted and limited by: the process, or the
+    /// connection when its pid is unknown.
+    fn key(&self) -> String {
+        match (self.uid, self.pid) {
+            (Some(uid), Some(pid)) => format!("uid:{uid}/pid:{pid}"),
+            (Some(uid), None) => format!("uid:{uid}/conn:{}", self.connection),
+            (None, _) => format!("conn:{}", self.connection),
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, idle::Listener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, idle::Listener>) -> Self {
+        stream.io().peer()
+    }
+}
+
+/// This machine's hostname, if it has one.
+fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its length, and gethostname writes no
+    // more than that.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8(buf[..len].to_vec())
+        .ok()
+        .filter(|name| !name.is_empty())
+}
+
+/// The name of the account with `uid`, if it has one.
+fn user_name(uid: u32) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 4096];
+    // SAFETY: `passwd` is plain data, for which all zeroes is valid.
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    // SAFETY: every pointer is valid for the call, and the buffer for its
+    // length; on success `found` points at `passwd`, whose strings live in
+    // `buf`.
+    let rc = unsafe { libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut found) };
+    if rc != 0 || found.is_null() {
+        return None;
+    }
+    // SAFETY: as above, `pw_name` is a NUL-terminated string in `buf`.
+    let name = unsafe { std::ffi::CStr::from_ptr(passwd.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// The working directory of process `pid`, while it's running.
+#[cfg(target_os = "linux")]
+fn process_cwd(pid: i32) -> Option<String> {
+    let dir = std::fs::read_link(format!("/proc/{pid}/cwd")).ok()?;
+    Some(dir.to_string_lossy().into_owned())
+}
+
+/// The working directory of process `pid`, while it's running.
+#[cfg(target_os = "macos")]
+fn process_cwd(pid: i32) -> Option<String> {
+    // SAFETY: `proc_vnodepathinfo` is plain data, for which all zeroes is
+    // valid.
+    let mut info: libc::proc_vnodepathinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_vnodepathinfo>() as libc::c_int;
+    // SAFETY: the buffer is `info`, valid for `size` bytes.
+    let written = unsafe {
+        libc::proc_pidinfo(
+            pid,
+            libc::PROC_PIDVNODEPATHINFO,
+            0,
+            (&raw mut info).cast(),
+            size,
+        )
+    };
+    if written != size {
+        return None;
+    }
+    // SAFETY: the kernel NUL-terminates the path within the array.
+    let path = unsafe { std::ffi::CStr::from_ptr(info.pvi_cdir.vip_path.as_ptr().cast()) };
+    Some(path.to_string_lossy().into_owned()).filter(|path| !path.is_empty())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn process_cwd(_pid: i32) -> Option<String> {
+    None
+}
+
+/// Requests a peer has made since the daemon started.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct PeerStats {
+    /// `uid:N/pid:M` for a local process, or `conn:N` for a connection
+    /// whose process is unknown.
+    pub peer: String,
+    pub requests: u64,
+    /// Requests turned away by the rate limit, counted in `requests` too.
+    pub rate_limited: u64,
+    /// Responses with a 5xx status.
+    pub errors: u64,
+    pub last_pid: Option<i32>,
+    pub last_seen_at: Option<String>,
+}
+
+#[derive(Default)]
+struct Accounting {
+    limit: Option<RateLimit>,
+    limiter: RateLimiter,
+    peers: BTreeMap<String, PeerStats>,
+}
+
+static ACCOUNTING: Mutex<Option<Accounting>> = Mutex::new(None);
+
+fn accounting() -> MutexGuard<'static, Option<Accounting>> {
+    ACCOUNTING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Apply the rate limit in config.toml. A config that doesn't load leaves
+/// requests unlimited, but a bad limit keeps the daemon from starting.
+pub fn init() -> anyhow::Result<()> {
+    let limit = match crate::config::load() {
+        Ok(config) => config.request_rate_limit()?,
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to load request rate limit");
+            None
+        }
+    };
+    set_limit(limit);
+    Ok(())
+}
+
+/// Apply `[daemon.request-rate-limit]`, at startup and on every reload.
+pub fn set_limit(limit: Option<RateLimit>) {
+    accounting().get_or_insert_with(Accounting::default).limit = limit;
+}
+
+/// Every peer that has made a request, by peer.
+pub fn peers() -> Vec<PeerStats> {
+    accounting()
+        .as_ref()
+        .map(|a| a.peers.values().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Count the request against its peer, turn it away if the peer is over
+/// the limit, and log it once it has a response.
+pub async fn track(
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    request: Request<axum::body::Body>,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let client = request
+        .headers()
+        .get(crate::client::ORIGIN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("api")
+        .to_string();
+
+    let response = match admit(&peer, &path, Instant::now()) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => rate_limited(retry_after),
+    };
+
+    let entry = serde_json::json!({
+        "at": chrono::Utc::now().to_rfc3339(),
+        "peer": peer.key(),
+        "pid": peer.pid,
+        "client": client,
+        "method": method,
+        "path": path,
+        "status": response.status().as_u16(),
+        "latency_ms": started.elapsed().as_millis() as u64,
+    });
+    record(&peer, response.status().as_u16(), &entry);
+    response
+}
+
+/// Count a request from `peer` and check it against the rate limit,
+/// returning how long the peer has to wait if it's over.
+fn admit(peer: &Peer, path: &str, now: Instant) -> Result<(), Duration> {
+    let mut accounting = accounting();
+    let accounting = accounting.get_or_insert_with(Accounting::default);
+    let key = peer.key();
+    if !accounting.peers.contains_key(&key)
+        && accounting.peers.len() >= MAX_PEERS
+        && let Some(stale) = accounting
+            .peers
+            .values()
+            .min_by(|a, b| a.last_seen_at.cmp(&b.last_seen_at))
+            .map(|stats| stats.peer.clone())
+    {
+        accounting.peers.remove(&stale);
+        accounting.limiter.forget(&stale);
+    }
+    let stats = accounting
+        .peers
+        .entry(key.clone())
+        .or_insert_with(|| PeerStats {
+            peer: key.clone(),
+            ..PeerStats::default()
+        });
+    stats.requests += 1;
+    stats.last_pid = peer.pid.or(stats.last_pid);
+    stats.last_seen_at = Some(chrono::Utc::now().to_rfc3339());
+
+    let Some(limit) = accounting.limit else {
+        return Ok(());
+    };
+    if UNLIMITED_PATHS.contains(&path) {
+        return Ok(());
+    }
+    let admitted = accounting.limiter.try_acquire(&key, &limit, now);
+    if admitted.is_err() {
+        stats.rate_limited += 1;
+    }
+    admitted
+}
+
+fn rate_limited(retry_after: Duration) -> Response {
+    let retry_after_ms = retry_after.as_millis() as u64;
+    let error = Error::new(
+        ErrorCode::RateLimited,
+        format!(
+            "too many requests; try again in {}s",
+            retry_after.as_secs().max(1)
+        ),
+    )
+    .with_details(serde_json::json!({ "retry_after_ms": retry_after_ms }));
+    let status = axum::http::StatusCode::from_u16(error.code.http_status())
+        .unwrap_or(axum::http::StatusCode::TOO_MANY_REQUESTS);
+    (
+        status,
+        [(
+            axum::http::header::RETRY_AFTER,
+            retry_after.as_secs().max(1).to_string(),
+        )],
+        axum::Json(error),
+    )
+        .into_response()
+}
+
+fn record(peer: &Peer, status: u16, entry: &serde_json::Value) {
+    if status >= 500 {
+        let mut accounting = accounting();
+        if let Some(stats) = accounting
+            .as_mut()
+            .and_then(|a| a.peers.get_mut(&peer.key()))
+        {
+            stats.errors += 1;
+        }
+    }
+    match log_writer().try_send(entry.to_string()) {
+        Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+        Err(TrySendError::Full(_)) => {
+            tracing::warn!("access log is behind; dropping an entry");
+        }
+    }
+}
+
+/// Where log lines go to be written by the `access-log` thread, started
+/// the first time one is sent.
+fn log_writer() -> &'static SyncSender<String> {
+    static WRITER: OnceLock<SyncSender<String>> = OnceLock::new();
+    WRITER.get_or_init(|| {
+        let (tx, rx) = mpsc::sync_channel::<String>(LOG_BACKLOG);
+        let spawned = std::thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || {
+                let mut log = None;
+                for entry in rx {
+                    if let Err(e) = append(&mut log, &entry) {
+                        tracing::warn!(error = %e, "failed to write access log");
+                        log = None;
+                    }
+                }
+            });
+        if let Err(e) = spawned {
+            tracing::warn!(error = %e, "failed to start access log writer");
+        }
+        tx
+    })
+}
+
+/// Append `entry` to the access log, opening it, or rotating it when it's
+/// too big, first.
+fn append(log: &mut Option<std::fs::File>, entry: &str) -> anyhow::Result<()> {
+    let path = crate::paths::state_dir()?.join("access.log");
+    if log
+        .as_ref()
+        .is_some_and(|file| file.metadata().is_ok_and(|m| m.len() > MAX_LOG_BYTES))
+    {
+        std::fs::rename(&path, path.with_extension("log.1"))?;
+        *log = None;
+    }
+    let file = match log {
+        Some(file) => file,
+        None => {
+            std::fs::create_dir_all(crate::paths::state_dir()?)?;
+            log.insert(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)?,
+            )
+        }
+    };
+    writeln!(file, "{entry}")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peers_are_counted_and_limited_by_process() {
+        let peer = |connection| Peer {
+            uid: Some(501),
+            pid: Some(7),
+            connection,
+        };
+        let other = Peer {
+            uid: Some(501),
+            pid: Some(9),
+            connection: 4,
+        };
+        let unknown = |connection| Peer {
+            connection,
+            ..Peer::default()
+        };
+        let start = Instant::now();
+        set_limit(Some(RateLimit {
+            max: 2,
+            per: Duration::from_secs(60),
+            jitter: Duration::ZERO,
+        }));
+
+        assert!(admit(&peer(1), "/tasks", start).is_ok());
+        assert!(admit(&peer(2), "/tasks", start).is_ok());
+        assert_eq!(
+            admit(&peer(3), "/tasks", start + Duration::from_secs(15)),
+            Err(Duration::from_secs(45))
+        );
+        // Health checks and other processes, even the same user's, aren't
+        // held up, and neither are connections without credentials.
+        assert!(admit(&peer(3), "/health", start).is_ok());
+        assert!(admit(&other, "/tasks", start).is_ok());
+        assert!(admit(&unknown(5), "/tasks", start).is_ok());
+        assert!(admit(&unknown(5), "/tasks", start).is_ok());
+        assert!(admit(&unknown(6), "/tasks", start).is_ok());
+        set_limit(None);
+
+        let peers = peers();
+        let stats = peers.iter().find(|p| p.peer == "uid:501/pid:7").unwrap();
+        assert_eq!(stats.requests, 4);
+        assert_eq!(stats.rate_limited, 1);
+        assert_eq!(stats.last_pid, Some(7));
+        assert!(peers.iter().any(|p| p.peer == "uid:501/pid:9"));
+        assert!(peers.iter().any(|p| p.peer == "conn:6"));
+    }
+}
@@ -0,0 +1,129 @@
+//! `WORK_CHAOS`: a hidden setting that makes the job processor misbehave on
+//! purpose, to exercise retries, lease expiry, and reconciliation.
+//!
+//! It holds comma-separated rules, `JOB_TYPE:RATE[:EFFECT]`, such as
+//! `prepare_environment:0.3,run_task:0.1:delay=10s`. Each job of the type, or
+//! of any type for `*`, is hit with probability `RATE`, and a hit job:
+//!
+//! - `fail` (the default): fails before doing anything, like a provider error.
+//! - `delay=DURATION`: waits that long, with its lease renewed, then runs.
+//! - `stall`: is dropped without renewing its lease, like a daemon that died
+//!   mid-job, so it's claimed again once the lease runs out.
+
+use std::hash::{BuildHasher, Hasher};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+static RULES: OnceLock<Vec<Rule>> = OnceLock::new();
+
+/// What happens to a job a rule hits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Effect {
+    Fail,
+    Delay(Duration),
+    Stall,
+}
+
+#[derive(Debug, PartialEq)]
+struct Rule {
+    job_type: String,
+    rate: f64,
+    effect: Effect,
+}
+
+fn parse(spec: &str) -> anyhow::Result<Vec<Rule>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(|rule| {
+            let mut parts = rule.splitn(3, ':');
+            let job_type = parts.next().unwrap_or_default();
+            let rate = parts
+                .next()
+                .and_then(|rate| rate.parse::<f64>().ok())
+                .filter(|rate| (0.0..=1.0).contains(rate))
+                .ok_or_else(|| {
+                    anyhow::anyhow!("WORK_CHAOS rule {rule:?} needs a rate from 0 to 1")
+                })?;
+            let effect = match parts.next() {
+                None | Some("fail") => Effect::Fail,
+                Some("stall") => Effect::Stall,
+                Some(effect) => match effect.strip_prefix("delay=") {
+                    Some(delay) => Effect::Delay(crate::config::parse_duration(delay)?.to_std()?),
+                    None => anyhow::bail!(
+                        "WORK_CHAOS rule {rule:?} has unknown effect {effect:?} (use fail, delay=DURATION, or stall)"
+                    ),
+                },
+            };
+            Ok(Rule {
+                job_type: job_type.to_string(),
+                rate,
+                effect,
+            })
+        })
+        .collect()
+}
+
+/// Read `WORK_CHAOS`, failing on rules that don't parse.
+pub fn init() -> anyhow::Result<()> {
+    let rules = match std::env::var("WORK_CHAOS") {
+        Ok(spec) => parse(&spec)?,
+        Err(_) => Vec::new(),
+    };
+    if !rules.is_empty() {
+        tracing::warn!(rules = ?rules, "chaos mode is on; jobs will fail or stall on purpose");
+    }
+    let _ = RULES.set(rules);
+    Ok(())
+}
+
+/// A number from 0 up to 1, different on each call.
+fn random() -> f64 {
+    let bits = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// What to do to a job of `job_type`, if the first rule for it hits.
+pub fn roll(job_type: &str) -> Option<Effect> {
+    let rule = RULES
+        .get()?
+        .iter()
+        .find(|rule| rule.job_type == job_type || rule.job_type == "*")?;
+    (random() < rule.rate).then_some(rule.effect)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rules_and_rejects_bad_ones() {
+        assert_eq!(
+            parse("prepare_environment:0.3, run_task:1:delay=10s,*:0.05:stall").unwrap(),
+            [
+                Rule {
+                    job_type: "prepare_environment".to_string(),
+                    rate: 0.3,
+                    effect: Effect::Fail,
+                },
+                Rule {
+                    job_type: "run_task".to_string(),
+                    rate: 1.0,
+                    effect: Effect::Delay(Duration::from_secs(10)),
+                },
+                Rule {
+                    job_type: "*".to_string(),
+                    rate: 0.05,
+                    effect: Effect::Stall,
+                },
+            ]
+        );
+        assert!(parse("").unwrap().is_empty());
+        assert!(parse("run_task").is_err());
+        assert!(parse("run_task:1.5").is_err());
+        assert!(parse("run_task:0.5:explode").is_err());
+        assert!(parse("run_task:0.5:delay=soon").is_err());
+    }
+}
@@ -1,19 +1,26 @@
 use std::sync::LazyLock;
+use std::time::Duration;
 
-use tokio::sync::{Notify, broadcast};
+use tokio::sync::{Notify, broadcast, watch};
 
-static SENDER: LazyLock<broadcast::Sender<()>> = LazyLock::new(|| {
-    let (tx, _) = broadcast::channel(64);
+use crate::db::{self, Event, EventQuery};
+
+/// How often the publisher checks the events table for new rows.
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Most events read from the table per check.
+const PUBLISH_BATCH: usize = 500;
+
+static SENDER: LazyLock<broadcast::Sender<Event>> = LazyLock::new(|| {
+    let (tx, _) = broadcast::channel(1024);
     tx
 });
 
 static SHUTDOWN: LazyLock<Notify> = LazyLock::new(Notify::new);
 
-pub fn notify() {
-    let _ = SENDER.send(());
-}
-
-pub fn subscribe() -> broadcast::Receiver<()> {
+/// Live events, in `seq` order. A receiver that lags behind is closed, and
+/// its client should reconnect and replay what it missed from the table.
+pub fn subscribe() -> broadcast::Receiver<Event> {
     SENDER.subscribe()
 }
 
@@ -24,3 +31,54 @@ pub fn shutdown() {
 pub async fn shutdown_notified() {
     SHUTDOWN.notified().await;
 }
+
+/// Broadcast events as they are committed to the events table, until
+/// `shutdown` is set.
+pub async fn publish(mut shutdown: watch::Receiver<bool>) {
+    let mut last_seq = match tokio::task::spawn_blocking(db::latest_event_seq).await {
+        Ok(Ok(seq)) => seq,
+        Ok(Err(e)) => {
+            tracing::error!(error = %e, "failed to read latest event");
+            0
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "failed to read latest event");
+            0
+        }
+    };
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(PUBLISH_INTERVAL) => {}
+            _ = shutdown.changed() => break,
+        }
+
+        let query = EventQuery {
+            since: Some(last_seq),
+            limit: Some(PUBLISH_BATCH),
+            ..EventQuery::default()
+        };
+        let result = tokio::task::spawn_blocking(move || {
+            let events = db::list_events(&query)?;
+            // A reset database numbers its events from 1 again.
+            let reset = events.is_empty() && db::latest_event_seq()? < last_seq;
+            anyhow::Ok((events, reset))
+        })
+        .await;
+        match result {
+            Ok(Ok((_, true))) => {
+                tracing::debug!("event sequence reset");
+                last_seq = 0;
+            }
+            Ok(Ok((events, false))) => {
+                for event in events {
+                    last_seq = event.seq;
+                    // No receivers is fine; nobody is listening.
+                    let _ = SENDER.send(event);
+                }
+            }
+            Ok(Err(e)) => tracing::error!(error = %e, "failed to read events"),
+            Err(e) => tracing::error!(error = %e, "failed to read events"),
+        }
+    }
+}
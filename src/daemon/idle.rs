@@ -0,0 +1,175 @@
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{UnixListener, UnixStream};
+
+use super::access::Peer;
+
+/// The longest the daemon goes between checks for being idle.
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Open connections to the daemon, and when the last one opened or closed.
+#[derive(Clone)]
+pub struct Activity(Arc<Inner>);
+
+struct Inner {
+    open: AtomicUsize,
+    last: Mutex<Instant>,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self(Arc::new(Inner {
+            open: AtomicUsize::new(0),
+            last: Mutex::new(Instant::now()),
+        }))
+    }
+
+    fn touch(&self) {
+        *self.0.last.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+    }
+
+    /// How long nothing has been connected, or `None` while something is.
+    fn idle_for(&self) -> Option<Duration> {
+        if self.0.open.load(Ordering::SeqCst) > 0 {
+            return None;
+        }
+        Some(
+            self.0
+                .last
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .elapsed(),
+        )
+    }
+}
+
+/// A socket listener that counts its open connections in an [`Activity`].
+pub struct Listener {
+    inner: UnixListener,
+    activity: Activity,
+}
+
+impl Listener {
+    pub fn new(inner: UnixListener, activity: Activity) -> Self {
+        Self { inner, activity }
+    }
+}
+
+impl axum::serve::Listener for Listener {
+    type Io = Connection;
+    type Addr = tokio::net::unix::SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        let (stream, addr) = axum::serve::Listener::accept(&mut self.inner).await;
+        self.activity.0.open.fetch_add(1, Ordering::SeqCst);
+        self.activity.touch();
+        let connection = Connection {
+            peer: Peer::of(&stream),
+            stream,
+            activity: self.activity.clone(),
+        };
+        (connection, addr)
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        self.inner.local_addr()
+    }
+}
+
+/// An accepted connection, counted as open until it is dropped.
+pub struct Connection {
+    stream: UnixStream,
+    activity: Activity,
+    peer: Peer,
+}
+
+impl Connection {
+    /// Who opened the connection.
+    pub fn peer(&self) -> Peer {
+        self.peer
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.activity.0.open.fetch_sub(1, Ordering::SeqCst);
+        self.activity.touch();
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// Resolves once nothing has been connected and no work has been in flight
+/// for `[daemon] idle-timeout`. Never resolves while it is unset. The setting
+/// is read again on each check, so a reload applies without a restart.
+pub async fn wait(activity: Activity) {
+    loop {
+        let timeout = match crate::config::load().and_then(|c| c.idle_timeout()) {
+            Ok(timeout) => timeout,
+            Err(e) => {
+                tracing::debug!(error = %e, "failed to read idle timeout");
+                None
+            }
+        };
+        tokio::time::sleep(timeout.map_or(CHECK_INTERVAL, |t| t.min(CHECK_INTERVAL))).await;
+
+        let Some(timeout) = timeout else {
+            continue;
+        };
+        if activity.idle_for().is_none_or(|idle| idle < timeout) {
+            continue;
+        }
+        match tokio::task::spawn_blocking(crate::db::has_active_work).await {
+            Ok(Ok(false)) => {}
+            // The timeout runs from when the work finishes.
+            Ok(Ok(true)) => {
+                activity.touch();
+                continue;
+            }
+            Ok(Err(e)) => {
+                tracing::warn!(error = %e, "failed to check for active work");
+                continue;
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "active work check failed");
+                continue;
+            }
+        }
+        tracing::info!(
+            idle_secs = timeout.as_secs(),
+            "idle timeout reached, shutting down"
+        );
+        return;
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::db;
+use crate::error::{Error, ErrorCode};
+
+/// How long events are kept for `GET /events/history` and for clients
+/// catching up after a reconnect.
+pub const EVENT_RETENTION_DAYS: i64 = 7;
+
+/// How long completed jobs are kept for `work job list`. Dead jobs are kept
+/// until retried or purged.
+pub const JOB_RETENTION_DAYS: i64 = 7;
+
+/// How long after startup the first database maintenance pass is due.
+const MAINTENANCE_STARTUP_DELAY: Duration = Duration::from_secs(10 * 60);
+/// How long after one maintenance pass the next is due.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+/// Up to how much later than due a pass is scheduled, picked at random.
+const MAINTENANCE_SPREAD: Duration = Duration::from_secs(30 * 60);
+/// How long a due pass waits for the daemon to go quiet before running
+/// anyway.
+const MAINTENANCE_MAX_WAIT: Duration = Duration::from_secs(60 * 60);
+/// How often a due pass asks whether the daemon has gone quiet.
+const MAINTENANCE_RECHECK: Duration = Duration::from_secs(30);
+
+/// Stage removal of finished tasks that have outlived their project's
+/// retention policy. Removal goes through the normal `remove_task` job, so
+/// environments are cleaned up by their provider as usual.
+pub fn remove_expired_tasks() -> anyhow::Result<()> {
+    let tasks = db::list_tasks()?;
+    let env_statuses: HashMap<String, String> = db::list_environments()?
+        .into_iter()
+        .map(|env| (env.id, env.status))
+        .collect();
+    let now = chrono::Utc::now();
+
+    for project in db::list_projects()? {
+        let config = match crate::config::load_for_project(&project.name, Path::new(&project.path))
+        {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::warn!(project = %project.name, error = %e, "failed to load config for task retention");
+                continue;
+            }
+        };
+        let policy = match config.task_retention(&project.name) {
+            Ok(Some(policy)) => policy,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::warn!(project = %project.name, error = %e, "invalid task retention config");
+                continue;
+            }
+        };
+
+        for task in tasks.iter().filter(|t| t.project_id == project.id) {
+            if !policy.is_expired(task, now) {
+                continue;
+            }
+            // Already being removed.
+            if env_statuses
+                .get(&task.environment_id)
+                .is_some_and(|status| status == "removing")
+            {
+                continue;
+            }
+            match db::stage_remove_task(&task.id) {
+                Ok(()) => {
+                    tracing::info!(task_id = %task.id, project = %project.name, status = %task.status, "auto-removing expired task");
+                }
+                // Nothing left for a provider to clean up, so the task goes
+                // right away rather than failing on every sweep.
+                Err(e) if Error::code_of(&e) == ErrorCode::EnvNotFound => {
+                    match db::delete_task_without_environment(&task.id) {
+                        Ok(()) => {
+                            tracing::info!(task_id = %task.id, project = %project.name, status = %task.status, "removed expired task whose environment was gone");
+                        }
+                        Err(e) => {
+                            tracing::warn!(task_id = %task.id, error = %e, "failed to remove expired task");
+                        }
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!(task_id = %task.id, error = %e, "failed to stage task auto-removal");
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Prune completed jobs and events past retention and compact the
+/// database. See [`db::maintain`].
+pub fn maintain_database() -> anyhow::Result<db::MaintenanceSummary> {
+    let now = chrono::Utc::now();
+    let summary = db::maintain(
+        now - chrono::Duration::days(JOB_RETENTION_DAYS),
+        now - chrono::Duration::days(EVENT_RETENTION_DAYS),
+    )?;
+    tracing::info!(
+        jobs_pruned = summary.jobs_pruned,
+        events_pruned = summary.events_pruned,
+        bytes_freed = summary.bytes_freed,
+        converted = summary.converted,
+        elapsed_ms = summary.elapsed_ms,
+        "database maintained"
+    );
+    Ok(summary)
+}
+
+/// When the next database maintenance pass runs. A due pass waits for a
+/// moment with no jobs in flight and no task runners going, so the vacuum
+/// doesn't hold up real work, and runs regardless once it has waited
+/// [`MAINTENANCE_MAX_WAIT`]. Each pass is scheduled a random part of
+/// [`MAINTENANCE_SPREAD`] late, so daemons started together, one per
+/// profile, don't all maintain their databases at once.
+pub struct MaintenanceSchedule {
+    due: Instant,
+    next_check: Instant,
+}
+
+impl MaintenanceSchedule {
+    pub fn new(now: Instant) -> Self {
+        let due = now + MAINTENANCE_STARTUP_DELAY + spread();
+        Self {
+            due,
+            next_check: due,
+        }
+    }
+
+    /// Whether a pass should start at `now`, scheduling the next one if so.
+    /// `busy` is asked at most every [`MAINTENANCE_RECHECK`] while a pass
+    /// is due.
+    pub fn should_run(&mut self, now: Instant, busy: impl FnOnce() -> bool) -> bool {
+        if now < self.next_check {
+            return false;
+        }
+        if now.duration_since(self.due) < MAINTENANCE_MAX_WAIT && busy() {
+            self.next_check = now + MAINTENANCE_RECHECK;
+            return false;
+        }
+        self.due = now + MAINTENANCE_INTERVAL + spread();
+        self.next_check = self.due;
+        true
+    }
+}
+
+fn spread() -> Duration {
+    use std::hash::BuildHasher;
+    let random = std::collections::hash_map::RandomState::new().hash_one(Instant::now());
+    Duration::from_secs(random % MAINTENANCE_SPREAD.as_secs())
+}
+
+/// Delete the snapshots of environments that no longer exist, in case
+/// whatever deleted one didn't take its snapshots along.
+pub fn remove_orphaned_snapshots() -> anyhow::Result<()> {
+    let entries = match std::fs::read_dir(crate::paths::snapshots_dir()?) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let env_ids: std::collections::HashSet<String> = db::list_environments()?
+        .into_iter()
+        .map(|env| env.id)
+        .collect();
+    for entry in entries {
+        let env_id = entry?.file_name().to_string_lossy().into_owned();
+        if !env_ids.contains(&env_id) {
+            tracing::info!(env_id = %env_id, "removing snapshots of a deleted environment");
+            crate::environment::remove_snapshots(&env_id);
+        }
+    }
+    Ok(())
+}
+
+/// Evict least recently used provider cache entries until the cache fits
+/// `[environments] cache-max-gb`.
+pub fn trim_cache() -> anyhow::Result<()> {
+    let max_bytes = crate::config::load()?.cache_max_bytes();
+    let eviction = crate::environment::CacheHandle::open()?.evict(max_bytes)?;
+    if eviction.entries > 0 {
+        tracing::info!(
+            entries = eviction.entries,
+            bytes = eviction.bytes,
+            "evicted provider cache entries"
+        );
+    }
+    Ok(())
+}
+
+/// Remove every `tmpfs` environment. They live in memory, so they are
+/// removed when the daemon stops rather than left to vanish at the next
+/// reboot, and again at startup in case the daemon didn't stop cleanly.
+/// Environments attached to a task are kept as `degraded` records.
+pub fn remove_tmpfs_environments() -> anyhow::Result<()> {
+    let envs: Vec<db::Environment> = db::list_environments()?
+        .into_iter()
+        .filter(|env| env.provider == "tmpfs")
+        .collect();
+    if envs.is_empty() {
+        return Ok(());
+    }
+    let provider = crate::environment::get_provider("tmpfs")?;
+
+    for env in envs {
+        if env.metadata["worktree_path"].is_string()
+            && let Err(e) = provider.remove(&env.metadata, None)
+        {
+            tracing::warn!(env_id = %env.id, error = %e, "failed to remove tmpfs environment");
+            continue;
+        }
+        if env.status == "degraded" {
+            continue;
+        }
+        match db::force_delete_environment(&env.id) {
+            Ok(()) => {
+                crate::environment::remove_snapshots(&env.id);
+                tracing::info!(env_id = %env.id, "removed tmpfs environment");
+            }
+            Err(e) if Error::code_of(&e) == ErrorCode::EnvAttachedToTask => {
+                let mut metadata = env.metadata;
+                metadata["degraded_reason"] =
+                    serde_json::json!("tmpfs environment removed when the daemon stopped");
+                db::update_environment_metadata(&env.id, &metadata)?;
+                db::update_environment_status(&env.id, "degraded")?;
+                tracing::info!(env_id = %env.id, "removed tmpfs environment attached to a task");
+            }
+            Err(e) => {
+                tracing::warn!(env_id = %env.id, error = %e, "failed to delete tmpfs environment");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maintenance_waits_for_a_quiet_moment_but_not_forever() {
+        let start = Instant::now();
+        let mut schedule = MaintenanceSchedule::new(start);
+        let due = schedule.due;
+        assert!(due >= start + MAINTENANCE_STARTUP_DELAY);
+        assert!(!schedule.should_run(due - Duration::from_secs(1), || false));
+
+        assert!(!schedule.should_run(due, || true));
+        // Asked again only after the recheck interval.
+        assert!(!schedule.should_run(due + Duration::from_secs(1), || {
+            panic!("asked too soon")
+        }));
+        assert!(schedule.should_run(due + MAINTENANCE_RECHECK, || false));
+        assert!(schedule.due >= due + MAINTENANCE_RECHECK + MAINTENANCE_INTERVAL);
+
+        let due = schedule.due;
+        assert!(schedule.should_run(due + MAINTENANCE_MAX_WAIT, || true));
+    }
+}
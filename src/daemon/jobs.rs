@@ -1,16 +1,25 @@
+use std::collections::HashMap;
 use std::io::Write;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::Path;
 use std::sync::Arc;
 
 use tokio::sync::{Semaphore, oneshot, watch};
+use tokio::task::{JoinError, JoinSet};
 
 use crate::db;
+use crate::log_stamp::Stream;
 
 const POLL_INTERVAL_MS: u64 = 100;
 const CLAIM_BATCH_LIMIT: usize = 8;
 const MAX_CONCURRENT_JOBS: usize = 8;
-const JOB_LEASE_SECONDS: i64 = 30;
+pub(super) const JOB_LEASE_SECONDS: i64 = 30;
 const JOB_LEASE_RENEW_INTERVAL_SECONDS: u64 = 10;
-const RETRY_LIMIT: i64 = 2;
+const RESOURCE_DEFER_SECONDS: i64 = 30;
+const LOCK_DEFER_SECONDS: i64 = 5;
+const ORPHAN_REAP_INTERVAL_SECONDS: u64 = 30;
+const TASK_RETENTION_INTERVAL_SECONDS: u64 = 300;
+const SHUTDOWN_REQUEUE_REASON: &str = "daemon stopped before the job finished";
 
 fn env_id_for_lifecycle_job(job: &db::Job) -> Option<&str> {
     match job.job_type.as_str() {
@@ -23,6 +32,19 @@ fn env_id_for_lifecycle_job(job: &db::Job) -> Option<&str> {
     }
 }
 
+/// The snapshot or restore holding the environment `job` works on, if one
+/// is. Such jobs wait for it to finish.
+fn locked_environment(job: &db::Job) -> Option<String> {
+    let env_id = match job.job_type.as_str() {
+        "run_task" => job.payload["env_id"].as_str(),
+        _ => env_id_for_lifecycle_job(job),
+    }?;
+    db::environment_lock(env_id).unwrap_or_else(|e| {
+        tracing::warn!(env_id = %env_id, error = %e, "failed to check environment lock");
+        None
+    })
+}
+
 fn append_environment_lifecycle_log(env_id: &str, line: &str) {
     let log_path = match crate::paths::environment_log_path(env_id) {
         Ok(path) => path,
@@ -61,25 +83,123 @@ fn environment_log_path(env_id: &str) -> Option<std::path::PathBuf> {
     crate::paths::environment_log_path(env_id).ok()
 }
 
+/// Store progress a provider reports for `env_id` so clients can show it.
+fn progress_sink(env_id: &str) -> crate::environment::ProgressSink {
+    let env_id = env_id.to_string();
+    Box::new(move |progress| {
+        if let Err(e) =
+            db::set_environment_progress(&env_id, progress.fraction, progress.message.as_deref())
+        {
+            tracing::warn!(env_id = %env_id, error = %e, "failed to record environment progress");
+        }
+    })
+}
+
 pub async fn run(mut shutdown: watch::Receiver<bool>) {
     tracing::info!("job processor started");
     let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_JOBS));
+    let mut rate_limiter = super::rate_limit::RateLimiter::default();
+    super::readiness::set_accepting_jobs(true);
+    let mut last_reap = std::time::Instant::now();
+    // `None` runs the first retention pass right after startup.
+    let mut last_retention: Option<std::time::Instant> = None;
+    let mut maintenance = super::janitor::MaintenanceSchedule::new(std::time::Instant::now());
+    let mut in_flight = JoinSet::new();
+    let mut in_flight_jobs = HashMap::new();
 
     loop {
+        while let Some(result) = in_flight.try_join_next_with_id() {
+            in_flight_jobs.remove(&finished_id(result));
+        }
+
+        if last_reap.elapsed().as_secs() >= ORPHAN_REAP_INTERVAL_SECONDS {
+            last_reap = std::time::Instant::now();
+            tokio::task::spawn_blocking(|| {
+                if let Err(e) = super::reaper::reap_orphans() {
+                    tracing::error!(error = %e, "failed to reap orphaned task processes");
+                }
+            });
+        }
+
+        if last_retention.is_none_or(|t| t.elapsed().as_secs() >= TASK_RETENTION_INTERVAL_SECONDS) {
+            last_retention = Some(std::time::Instant::now());
+            tokio::task::spawn_blocking(|| {
+                if let Err(e) = super::janitor::remove_expired_tasks() {
+                    tracing::error!(error = %e, "failed to remove expired tasks");
+                }
+                if let Err(e) = super::janitor::trim_cache() {
+                    tracing::error!(error = %e, "failed to trim provider cache");
+                }
+                if let Err(e) = super::janitor::remove_orphaned_snapshots() {
+                    tracing::error!(error = %e, "failed to remove orphaned snapshots");
+                }
+            });
+        }
+
+        if maintenance.should_run(std::time::Instant::now(), || {
+            db::has_active_work().unwrap_or(true)
+        }) {
+            tokio::task::spawn_blocking(|| {
+                if let Err(e) = super::janitor::maintain_database() {
+                    tracing::error!(error = %e, "failed to maintain the database");
+                }
+            });
+        }
+
         let available = permits.available_permits();
         if available > 0 {
             let claim_limit = available.min(CLAIM_BATCH_LIMIT);
             match db::claim_pending_jobs(claim_limit, JOB_LEASE_SECONDS) {
                 Ok(jobs) => {
+                    super::trace::claimed(&jobs).await;
+                    let mut host_constraint = None;
+                    let mut config = None;
+                    if jobs.iter().any(|job| is_rate_limited_job(&job.job_type)) {
+                        match crate::config::load() {
+                            Ok(loaded) => {
+                                if jobs.iter().any(|job| job.job_type == "prepare_environment") {
+                                    host_constraint = super::resources::host_constraint(&loaded);
+                                }
+                                config = Some(loaded);
+                            }
+                            Err(e) => {
+                                tracing::warn!(error = %e, "failed to load config for resource checks");
+                            }
+                        }
+                    }
+
                     for job in jobs {
+                        if let Some(operation) = locked_environment(&job) {
+                            let reason = format!("environment is locked for a {operation}");
+                            defer_job(&job, &reason, LOCK_DEFER_SECONDS);
+                            continue;
+                        }
+
+                        if job.job_type == "prepare_environment"
+                            && let Some(reason) = host_constraint.as_deref()
+                        {
+                            defer_job(&job, reason, RESOURCE_DEFER_SECONDS);
+                            continue;
+                        }
+
+                        if let Some(config) = &config
+                            && let Some((reason, delay)) =
+                                rate_limit_delay(&mut rate_limiter, config, &job)
+                        {
+                            defer_job(&job, &reason, delay);
+                            continue;
+                        }
+
                         let permits = permits.clone();
-                        tokio::spawn(async move {
+                        let claimed = job.clone();
+                        let handle = in_flight.spawn(async move {
                             let _permit = match permits.acquire_owned().await {
                                 Ok(permit) => permit,
                                 Err(_) => return,
                             };
                             process_job(job).await;
                         });
+                        in_flight_jobs.insert(handle.id(), claimed);
                     }
                 }
                 Err(e) => {
@@ -92,15 +212,183 @@ pub async fn run(mut shutdown: watch::Receiver<bool>) {
             _ = tokio::time::sleep(std::time::Duration::from_millis(POLL_INTERVAL_MS)) => {}
             _ = shutdown.changed() => {
                 tracing::info!("job processor shutting down");
+                super::readiness::set_accepting_jobs(false);
                 break;
             }
         }
     }
+
+    finish_in_flight(in_flight, in_flight_jobs).await;
+}
+
+fn finished_id(result: Result<(tokio::task::Id, ()), JoinError>) -> tokio::task::Id {
+    match result {
+        Ok((id, ())) => id,
+        Err(e) => e.id(),
+    }
+}
+
+/// Give the jobs still running when the daemon stops `[daemon]
+/// shutdown-grace` to finish, then requeue the rest, so the next daemon
+/// picks them up right away instead of waiting for their leases to run out.
+///
+/// Aborting a job can't stop work it handed to a blocking thread, such as a
+/// provider script, which a requeued job then starts over. A task runner is
+/// stopped instead, by [`interrupt_task_run`].
+async fn finish_in_flight(mut in_flight: JoinSet<()>, mut jobs: HashMap<tokio::task::Id, db::Job>) {
+    if in_flight.is_empty() {
+        return;
+    }
+    let grace = crate::config::load()
+        .and_then(|config| config.shutdown_grace())
+        .unwrap_or_else(|e| {
+            tracing::warn!(error = %e, "failed to load shutdown grace; using the default");
+            crate::config::DEFAULT_SHUTDOWN_GRACE
+        });
+    tracing::info!(
+        jobs = in_flight.len(),
+        grace_ms = grace.as_millis() as u64,
+        "waiting for jobs in flight"
+    );
+    let waited = tokio::time::timeout(grace, async {
+        while let Some(result) = in_flight.join_next_with_id().await {
+            jobs.remove(&finished_id(result));
+        }
+    })
+    .await;
+    if waited.is_ok() {
+        return;
+    }
+
+    in_flight.abort_all();
+    while let Some(result) = in_flight.join_next_with_id().await {
+        // A job that finished before it could be stopped is done.
+        if let Ok((id, ())) = result {
+            jobs.remove(&id);
+        }
+    }
+    for job in jobs.into_values() {
+        if job.job_type == "run_task" && interrupt_task_run(&job).await {
+            continue;
+        }
+        match db::requeue_interrupted_job(&job.id, SHUTDOWN_REQUEUE_REASON) {
+            Ok(true) => {
+                tracing::info!(id = %job.id, job_type = %job.job_type, "requeued job the daemon stopped under");
+                super::trace::finished(
+                    &job,
+                    super::trace::Outcome::Interrupted,
+                    Some(SHUTDOWN_REQUEUE_REASON),
+                );
+                if let Some(env_id) = env_id_for_lifecycle_job(&job) {
+                    append_environment_lifecycle_log(
+                        env_id,
+                        &format!(
+                            "job={} phase=requeued reason={SHUTDOWN_REQUEUE_REASON}",
+                            job.job_type
+                        ),
+                    );
+                }
+            }
+            Ok(false) => {}
+            Err(e) => tracing::error!(id = %job.id, error = %e, "failed to requeue job"),
+        }
+    }
+}
+
+/// Stop the runner of a `run_task` job the daemon is stopping under, the way
+/// cancelling it would, and mark its task interrupted, as a restart finds a
+/// task whose runner outlived its daemon. The job used up the environment's
+/// claim when it started the task, so running it again would do nothing; it
+/// is finished instead. Returns false, to requeue the job, if it hadn't
+/// started its task yet.
+async fn interrupt_task_run(job: &db::Job) -> bool {
+    let Some(task_id) = job.payload["task_id"].as_str().map(str::to_string) else {
+        return false;
+    };
+    if !db::get_task(&task_id).is_ok_and(|task| task.status == "started") {
+        return false;
+    }
+
+    // Ending the process group ends the blocking thread waiting on it, and
+    // marks the task cancelled so the thread skips any steps left.
+    let terminate_id = task_id.clone();
+    let stopped = tokio::task::spawn_blocking(move || super::reaper::terminate_task(&terminate_id))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|stopped| stopped);
+    if let Err(e) = stopped {
+        tracing::warn!(task_id = %task_id, error = %e, "failed to stop task runner");
+    }
+    log_step(&task_id, &format!("interrupted: {SHUTDOWN_REQUEUE_REASON}"));
+
+    let interrupted = db::update_task_status(&task_id, "interrupted")
+        .and_then(|_| db::mark_job_complete(&job.id));
+    match interrupted {
+        Ok(()) => {
+            tracing::info!(task_id = %task_id, id = %job.id, "interrupted task the daemon stopped under");
+            super::trace::finished(
+                job,
+                super::trace::Outcome::Interrupted,
+                Some(SHUTDOWN_REQUEUE_REASON),
+            );
+        }
+        Err(e) => tracing::error!(task_id = %task_id, error = %e, "failed to interrupt task"),
+    }
+    true
+}
+
+/// Job types that reach out to the provider's remote (fetches, clones).
+fn is_rate_limited_job(job_type: &str) -> bool {
+    matches!(job_type, "prepare_environment" | "update_environment")
 }
 
-fn retry_delay_seconds(attempt: i64) -> i64 {
-    let exp = (attempt.max(1) as u32).min(5);
-    (2_i64.pow(exp)).min(60)
+/// If the job's provider is over its rate limit, the reason and how many
+/// seconds to defer it, including jitter.
+fn rate_limit_delay(
+    limiter: &mut super::rate_limit::RateLimiter,
+    config: &crate::config::Config,
+    job: &db::Job,
+) -> Option<(String, i64)> {
+    if !is_rate_limited_job(&job.job_type) {
+        return None;
+    }
+    let env = db::get_environment(job.payload["env_id"].as_str()?).ok()?;
+    let limit = match config.rate_limit(&env.provider) {
+        Ok(limit) => limit?,
+        Err(e) => {
+            tracing::warn!(provider = %env.provider, error = %e, "ignoring invalid rate limit");
+            return None;
+        }
+    };
+
+    let wait = limiter
+        .try_acquire(&env.provider, &limit, std::time::Instant::now())
+        .err()?;
+    let delay = wait + super::rate_limit::jitter(limit.jitter);
+    let reason = format!(
+        "rate limit for {} ({} per {}s)",
+        env.provider,
+        limit.max,
+        limit.per.as_secs()
+    );
+    Some((reason, delay.as_secs_f64().ceil() as i64))
+}
+
+fn defer_job(job: &db::Job, reason: &str, delay_seconds: i64) {
+    tracing::info!(id = %job.id, job_type = %job.job_type, reason = %reason, "deferring job");
+    if let Some(env_id) = env_id_for_lifecycle_job(job) {
+        append_environment_lifecycle_log(
+            env_id,
+            &format!(
+                "job={} phase=deferred delay_seconds={} reason={}",
+                job.job_type, delay_seconds, reason
+            ),
+        );
+    }
+    if let Err(e) = db::defer_job(&job.id, reason, delay_seconds) {
+        tracing::error!(id = %job.id, error = %e, "failed to defer job");
+    }
+    super::trace::finished(job, super::trace::Outcome::Deferred, Some(reason));
 }
 
 fn spawn_job_lease_heartbeat(job_id: String) -> (oneshot::Sender<()>, tokio::task::JoinHandle<()>) {
@@ -127,7 +415,7 @@ fn spawn_job_lease_heartbeat(job_id: String) -> (oneshot::Sender<()>, tokio::tas
     (stop_tx, handle)
 }
 
-async fn process_job(job: db::Job) {
+pub(super) async fn process_job(job: db::Job) {
     let lifecycle_env_id = env_id_for_lifecycle_job(&job).map(str::to_string);
     let attempt_number = job.attempt + 1;
 
@@ -147,16 +435,34 @@ async fn process_job(job: db::Job) {
         );
     }
 
+    let chaos = super::chaos::roll(&job.job_type);
+    if chaos == Some(super::chaos::Effect::Stall) {
+        // Leave the job running with a lease nobody renews.
+        tracing::warn!(id = %job.id, job_type = %job.job_type, "chaos: stalling job");
+        return;
+    }
+
     let (lease_stop_tx, lease_handle) = spawn_job_lease_heartbeat(job.id.clone());
 
-    let result = match job.job_type.as_str() {
-        "prepare_environment" => prepare_environment(&job).await,
-        "update_environment" => update_environment(&job).await,
-        "claim_environment" => claim_environment(&job).await,
-        "remove_environment" => remove_environment(&job).await,
-        "remove_task" => remove_task(&job).await,
-        "run_task" => run_task(&job).await,
-        other => Err(anyhow::anyhow!("unknown job type: {other}")),
+    if let Some(super::chaos::Effect::Delay(delay)) = chaos {
+        tracing::warn!(id = %job.id, job_type = %job.job_type, delay_ms = delay.as_millis() as u64, "chaos: delaying job");
+        tokio::time::sleep(delay).await;
+    }
+    // Replaying a trace, a job fails as its recorded attempt did.
+    let recorded = super::trace::recorded_failure(&job.id);
+    let result = match (job.job_type.as_str(), &recorded) {
+        (_, Some(recorded)) => Err(anyhow::anyhow!("{}", recorded.error)),
+        (job_type, None) if chaos == Some(super::chaos::Effect::Fail) => {
+            Err(anyhow::anyhow!("chaos: injected {job_type} failure"))
+        }
+        ("prepare_environment", None) => prepare_environment(&job).await,
+        ("update_environment", None) => update_environment(&job).await,
+        ("claim_environment", None) => claim_environment(&job).await,
+        ("remove_environment", None) => remove_environment(&job).await,
+        ("remove_task", None) => remove_task(&job).await,
+        ("run_task", None) => run_task(&job).await,
+        ("notify", None) => super::notify::run(&job).await,
+        (other, None) => Err(anyhow::anyhow!("unknown job type: {other}")),
     };
     let _ = lease_stop_tx.send(());
     let _ = lease_handle.await;
@@ -166,6 +472,7 @@ async fn process_job(job: db::Job) {
             if let Err(e) = db::mark_job_complete(&job.id) {
                 tracing::error!(id = %job.id, error = %e, "failed to mark job complete");
             }
+            super::trace::finished(&job, super::trace::Outcome::Complete, None);
             if let Some(env_id) = lifecycle_env_id.as_deref() {
                 append_environment_lifecycle_log(
                     env_id,
@@ -180,9 +487,20 @@ async fn process_job(job: db::Job) {
             tracing::error!(id = %job.id, error = %e, "job failed");
             let error_message = e.to_string();
 
-            let can_retry = job.attempt < RETRY_LIMIT;
+            let policy = match crate::config::load() {
+                Ok(config) => config.retry_policy(&job.job_type),
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to load config for retry policy");
+                    crate::config::RetryPolicy::default_for(&job.job_type)
+                }
+            };
+            let limit = recorded
+                .as_ref()
+                .and_then(|r| r.retry_limit)
+                .unwrap_or(policy.limit);
+            let can_retry = job.attempt < limit;
             if can_retry {
-                let delay = retry_delay_seconds(job.attempt);
+                let delay = policy.delay_seconds(job.attempt);
                 if let Some(env_id) = lifecycle_env_id.as_deref() {
                     append_environment_lifecycle_log(
                         env_id,
@@ -208,9 +526,12 @@ async fn process_job(job: db::Job) {
                             ),
                         );
                     }
-                    let _ = db::mark_job_failed(&job.id, &error_message);
                     apply_terminal_failure_side_effects(&job);
+                    let _ = db::mark_job_dead(&job.id, &error_message);
+                    super::trace::finished(&job, super::trace::Outcome::Dead, Some(&error_message));
+                    return;
                 }
+                super::trace::finished(&job, super::trace::Outcome::Retry, Some(&error_message));
                 return;
             }
 
@@ -218,16 +539,19 @@ async fn process_job(job: db::Job) {
                 append_environment_lifecycle_log(
                     env_id,
                     &format!(
-                        "job={} attempt={} phase=failed error={}",
+                        "job={} attempt={} phase=dead error={}",
                         job.job_type, attempt_number, error_message
                     ),
                 );
             }
 
-            if let Err(mark_err) = db::mark_job_failed(&job.id, &error_message) {
-                tracing::error!(id = %job.id, error = %mark_err, "failed to mark job failed");
-            }
+            // Fail the task and environment while the job is still running,
+            // so their status history records which job failed them.
             apply_terminal_failure_side_effects(&job);
+            if let Err(mark_err) = db::mark_job_dead(&job.id, &error_message) {
+                tracing::error!(id = %job.id, error = %mark_err, "failed to mark job dead");
+            }
+            super::trace::finished(&job, super::trace::Outcome::Dead, Some(&error_message));
         }
     }
 }
@@ -241,7 +565,6 @@ fn apply_terminal_failure_side_effects(job: &db::Job) {
             if let Some(task_id) = job.payload["task_id"].as_str() {
                 let _ = db::update_task_status(task_id, "failed");
             }
-            super::events::notify();
         }
         "run_task" => {
             if let Some(task_id) = job.payload["task_id"].as_str() {
@@ -250,7 +573,6 @@ fn apply_terminal_failure_side_effects(job: &db::Job) {
             if let Some(env_id) = job.payload["env_id"].as_str() {
                 let _ = db::update_environment_status(env_id, "failed");
             }
-            super::events::notify();
         }
         "claim_environment" => {
             if let Some(env_id) = job.payload["env_id"].as_str() {
@@ -259,19 +581,17 @@ fn apply_terminal_failure_side_effects(job: &db::Job) {
             if let Some(task_id) = job.payload["task_id"].as_str() {
                 let _ = db::update_task_status(task_id, "failed");
             }
-            super::events::notify();
         }
-        "update_environment" | "remove_environment" => {
+        "update_environment" => {
             if let Some(env_id) = job.payload["env_id"].as_str() {
                 let _ = db::update_environment_status(env_id, "failed");
             }
-            super::events::notify();
         }
-        "remove_task" => {
+        "remove_environment" | "remove_task" => {
             if let Some(env_id) = job.payload["env_id"].as_str() {
                 let _ = db::update_environment_status(env_id, "failed");
+                let _ = db::abandon_project_removal(env_id);
             }
-            super::events::notify();
         }
         _ => {}
     }
@@ -283,6 +603,7 @@ async fn prepare_environment(job: &db::Job) -> anyhow::Result<()> {
         .ok_or_else(|| anyhow::anyhow!("job payload missing env_id"))?
         .to_string();
     let task_id = job.payload["task_id"].as_str().map(|s| s.to_string());
+    let source_ref = job.payload["source_ref"].as_str().map(|s| s.to_string());
     let claim_after_prepare = job.payload["claim_after_prepare"]
         .as_bool()
         .unwrap_or(false);
@@ -290,16 +611,7 @@ async fn prepare_environment(job: &db::Job) -> anyhow::Result<()> {
     let env = db::get_environment(&env_id)?;
     if env.status == "pool" || env.status == "in_use" {
         if let Some(task_id) = task_id.as_deref() {
-            let dedupe = format!("run_task:task:{task_id}");
-            db::create_job_with_dedupe(
-                "run_task",
-                &serde_json::json!({
-                    "task_id": task_id,
-                    "env_id": env_id,
-                }),
-                Some(&dedupe),
-            )?;
-            super::events::notify();
+            db::queue_run_task(task_id, &env_id)?;
         }
         return Ok(());
     }
@@ -319,8 +631,13 @@ async fn prepare_environment(job: &db::Job) -> anyhow::Result<()> {
 
     let eid = env_id.clone();
     let prepared_metadata = tokio::task::spawn_blocking(move || {
-        let provider = crate::environment::get_provider(&provider_name)?;
-        provider.prepare(&project, &eid, log_path.as_deref())
+        let mut provider = crate::environment::get_provider(&provider_name)?;
+        provider.on_progress(progress_sink(&eid));
+        let mut options = crate::config::load_for_project(&project.name, Path::new(&project.path))?
+            .prepare_options_for_project(&provider_name, &project.name);
+        options.source_ref = source_ref;
+        let cache = crate::environment::CacheHandle::open()?;
+        provider.prepare(&project, &eid, &options, &cache, log_path.as_deref())
     })
     .await??;
 
@@ -330,8 +647,10 @@ async fn prepare_environment(job: &db::Job) -> anyhow::Result<()> {
         let provider_name = env.provider.clone();
         let meta = prepared_metadata.clone();
         let log_path = environment_log_path(&env_id);
+        let eid = env_id.clone();
         tokio::task::spawn_blocking(move || {
-            let provider = crate::environment::get_provider(&provider_name)?;
+            let mut provider = crate::environment::get_provider(&provider_name)?;
+            provider.on_progress(progress_sink(&eid));
             provider.claim(&meta, log_path.as_deref())
         })
         .await??
@@ -343,18 +662,9 @@ async fn prepare_environment(job: &db::Job) -> anyhow::Result<()> {
     db::complete_preparing_environment(&env_id, final_status, &final_metadata)?;
 
     if let Some(task_id) = task_id.as_deref() {
-        let dedupe = format!("run_task:task:{task_id}");
-        db::create_job_with_dedupe(
-            "run_task",
-            &serde_json::json!({
-                "task_id": task_id,
-                "env_id": env_id,
-            }),
-            Some(&dedupe),
-        )?;
+        db::queue_run_task(task_id, &env_id)?;
     }
 
-    super::events::notify();
     tracing::info!(env_id = %env_id, status = %final_status, "environment prepared");
 
     Ok(())
@@ -373,17 +683,29 @@ async fn update_environment(job: &db::Job) -> anyhow::Result<()> {
         return Ok(());
     }
 
+    let project = db::get_project(&env.project_id)?;
     let provider_name = env.provider.clone();
     let metadata = env.metadata.clone();
     let log_path = environment_log_path(&env_id);
+    let eid = env_id.clone();
     let new_metadata = tokio::task::spawn_blocking(move || {
-        let provider = crate::environment::get_provider(&provider_name)?;
-        provider.update(&metadata, log_path.as_deref())
+        let mut provider = crate::environment::get_provider(&provider_name)?;
+        provider.on_progress(progress_sink(&eid));
+        let options = crate::config::load_for_project(&project.name, Path::new(&project.path))?
+            .prepare_options_for_project(&provider_name, &project.name);
+        provider.update(&metadata, &options, log_path.as_deref())
     })
     .await??;
 
     db::update_environment_metadata(&env_id, &new_metadata)?;
-    super::events::notify();
+    // An update is how an outdated environment catches up. The update itself
+    // succeeded either way, so a failed check is only logged.
+    let env = db::get_environment(&env_id)?;
+    let checked =
+        tokio::task::spawn_blocking(move || super::project_watch::check_environment(&env)).await?;
+    if let Err(e) = checked {
+        tracing::warn!(env_id = %env_id, error = %e, "failed to check updated environment");
+    }
     Ok(())
 }
 
@@ -394,7 +716,7 @@ async fn claim_environment(job: &db::Job) -> anyhow::Result<()> {
         .to_string();
     let task_id = job.payload["task_id"].as_str().map(|id| id.to_string());
 
-    let env = match db::get_environment(&env_id) {
+    let mut env = match db::get_environment(&env_id) {
         Ok(env) => env,
         Err(_) => return Ok(()),
     };
@@ -405,11 +727,46 @@ async fn claim_environment(job: &db::Job) -> anyhow::Result<()> {
         return Ok(());
     }
 
+    // A pooled environment must not hand leftovers from an earlier task to
+    // this one. Swap out environments that fail the provider's check until
+    // one passes or the pool runs dry and a fresh one is prepared instead.
+    if let Some(task_id) = task_id.as_deref() {
+        loop {
+            let provider_name = env.provider.clone();
+            let metadata = env.metadata.clone();
+            let conflict = tokio::task::spawn_blocking(move || {
+                let provider = crate::environment::get_provider(&provider_name)?;
+                Ok::<_, anyhow::Error>(
+                    provider
+                        .claim_conflict(&metadata)
+                        .unwrap_or_else(|e| Some(format!("could not check environment: {e}"))),
+                )
+            })
+            .await??;
+            let Some(reason) = conflict else { break };
+
+            tracing::warn!(env_id = %env.id, task_id = %task_id, reason = %reason, "environment degraded; finding another");
+            append_environment_lifecycle_log(&env.id, &format!("degraded: {reason}"));
+            let shared_pool = crate::config::load()
+                .map(|config| config.shares_pool(&env.provider))
+                .unwrap_or(false);
+            match db::degrade_task_environment(task_id, &env.id, &reason, shared_pool)? {
+                db::ReplacementEnvironment::Pooled(next_id) => env = db::get_environment(&next_id)?,
+                db::ReplacementEnvironment::Preparing(_) => {
+                    return Ok(());
+                }
+            }
+        }
+    }
+    let env_id = env.id.clone();
+
     let provider_name = env.provider.clone();
     let metadata = env.metadata.clone();
     let log_path = environment_log_path(&env_id);
+    let eid = env_id.clone();
     let new_metadata = tokio::task::spawn_blocking(move || {
-        let provider = crate::environment::get_provider(&provider_name)?;
+        let mut provider = crate::environment::get_provider(&provider_name)?;
+        provider.on_progress(progress_sink(&eid));
         provider.claim(&metadata, log_path.as_deref())
     })
     .await??;
@@ -419,19 +776,10 @@ async fn claim_environment(job: &db::Job) -> anyhow::Result<()> {
     if let Some(task_id) = task_id.as_deref() {
         let task = db::get_task(task_id)?;
         if task.status == "pending" {
-            let dedupe = format!("run_task:task:{task_id}");
-            db::create_job_with_dedupe(
-                "run_task",
-                &serde_json::json!({
-                    "task_id": task_id,
-                    "env_id": env_id,
-                }),
-                Some(&dedupe),
-            )?;
+            db::queue_run_task(task_id, &env_id)?;
         }
     }
 
-    super::events::notify();
     Ok(())
 }
 
@@ -451,14 +799,16 @@ async fn remove_environment(job: &db::Job) -> anyhow::Result<()> {
 
     tracing::info!(env_id = %env_id, provider = %provider_name, "removing environment");
 
-    tokio::task::spawn_blocking(move || {
-        let provider = crate::environment::get_provider(&provider_name)?;
-        provider.remove(&metadata, log_path.as_deref())
-    })
-    .await??;
+    if provider_name != db::IMPORTED_ENVIRONMENT_PROVIDER {
+        tokio::task::spawn_blocking(move || {
+            let provider = crate::environment::get_provider(&provider_name)?;
+            provider.remove(&metadata, log_path.as_deref())
+        })
+        .await??;
+    }
 
     db::delete_environment(&env_id)?;
-    super::events::notify();
+    crate::environment::remove_snapshots(&env_id);
 
     tracing::info!(env_id = %env_id, "environment removed");
 
@@ -475,7 +825,15 @@ async fn remove_task(job: &db::Job) -> anyhow::Result<()> {
         .ok_or_else(|| anyhow::anyhow!("job payload missing env_id"))?
         .to_string();
 
-    if let Ok(env) = db::get_environment(&env_id) {
+    // Stop a still-running task before its worktree disappears underneath it.
+    {
+        let task_id = task_id.clone();
+        tokio::task::spawn_blocking(move || super::reaper::terminate_task(&task_id)).await??;
+    }
+
+    if let Ok(env) = db::get_environment(&env_id)
+        && env.provider != db::IMPORTED_ENVIRONMENT_PROVIDER
+    {
         let provider_name = env.provider.clone();
         let metadata = env.metadata.clone();
         let log_path = environment_log_path(&env_id);
@@ -487,10 +845,16 @@ async fn remove_task(job: &db::Job) -> anyhow::Result<()> {
     }
 
     db::delete_task_and_environment(&task_id, &env_id)?;
+    crate::environment::remove_snapshots(&env_id);
     if let Ok(log_path) = crate::paths::task_log_path(&task_id) {
         let _ = std::fs::remove_file(log_path);
     }
-    super::events::notify();
+    if let Ok(result_path) = crate::paths::task_result_path(&task_id) {
+        let _ = std::fs::remove_file(result_path);
+    }
+    if let Ok(artifact_dir) = crate::paths::task_artifact_dir(&task_id) {
+        let _ = std::fs::remove_dir_all(artifact_dir);
+    }
     Ok(())
 }
 
@@ -505,89 +869,492 @@ async fn run_task(job: &db::Job) -> anyhow::Result<()> {
         .ok_or_else(|| anyhow::anyhow!("job payload missing env_id"))?;
 
     let task = db::get_task(task_id)?;
-    if task.status == "complete" || task.status == "failed" {
+    if task.is_terminal() {
         return Ok(());
     }
-    if task.status == "started" {
-        anyhow::bail!("task {task_id} is already started");
-    }
 
     let env = db::get_environment(env_id)?;
     if env.status != "in_use" {
         anyhow::bail!("environment {env_id} is not in use");
     }
 
-    db::start_task(task_id)?;
-    super::events::notify();
+    // Only the job holding the environment's claim token starts the task,
+    // so a copy of this job picked up after its lease ran out can't start a
+    // second runner in the same checkout.
+    let claim_token = job.payload["claim_token"].as_str();
+    let Some(task) = db::start_claimed_task(task_id, env_id, claim_token)? else {
+        tracing::warn!(task_id = %task_id, env_id = %env_id, "task already started by another job; skipping");
+        return Ok(());
+    };
 
-    let task_provider_config = config.get_task_provider(&task.provider)?;
-    let crate::config::TaskProviderConfig::Command { command: cmd, args } = task_provider_config;
+    if let Err(e) = run_started_task(&config, &task, &env).await {
+        // The claim is used up, so a retry could never start the task: fail
+        // it as running out of retries would, and finish the job once that
+        // is recorded.
+        tracing::error!(task_id = %task_id, env_id = %env_id, error = %e, "task run failed");
+        db::update_task_status(task_id, "failed")?;
+        db::update_environment_status(env_id, "failed")?;
+    }
+    Ok(())
+}
 
-    let resolved_args: Vec<String> = args
-        .iter()
-        .map(|a| a.replace("{task_description}", &task.description))
-        .collect();
+/// Run a task's commands once its job has started it, and record how the
+/// task ended.
+async fn run_started_task(
+    config: &crate::config::Config,
+    task: &db::Task,
+    env: &db::Environment,
+) -> anyhow::Result<()> {
+    let task_id = task.id.as_str();
 
-    let run_spec = {
+    let task_provider_config = config.get_task_provider(&task.provider)?;
+    let pty_size = task_provider_config.pty_size();
+    let resolve = |args: &[String]| -> Vec<String> {
+        args.iter()
+            .map(|a| a.replace("{task_description}", &task.description))
+            .collect()
+    };
+    let mut step_commands = Vec::new();
+    let post_on_failure = match task_provider_config {
+        crate::config::TaskProviderConfig::Command {
+            command: cmd,
+            args,
+            stdin,
+            pre,
+            post,
+            post_on_failure,
+            ..
+        } => {
+            let stdin =
+                stdin.map(|crate::config::TaskProviderStdin::Description| task.description.clone());
+
+            // The provider's `pre` and `post` commands run in the environment
+            // the same way as its main command, and only the main command
+            // gets stdin.
+            if let Some((program, args)) = pre.split_first() {
+                step_commands.push((TaskStep::Pre, program.clone(), resolve(args), None));
+            }
+            step_commands.push((TaskStep::Main, cmd.clone(), resolve(args), stdin));
+            if let Some((program, args)) = post.split_first() {
+                step_commands.push((TaskStep::Post, program.clone(), resolve(args), None));
+            }
+            *post_on_failure
+        }
+        crate::config::TaskProviderConfig::Mock {
+            output,
+            latency_ms,
+            exit_code,
+            summary,
+        } => {
+            let script = mock_task_script(&resolve(output), *latency_ms, *exit_code, summary);
+            step_commands.push((
+                TaskStep::Main,
+                "sh".to_string(),
+                vec!["-c".to_string(), script],
+                None,
+            ));
+            crate::config::PostOnFailure::Skip
+        }
+    };
+    let project = db::get_project(&env.project_id)?;
+    let steps = {
         let provider_name = env.provider.clone();
         let meta = env.metadata.clone();
-        let cmd = cmd.to_string();
-        let args = resolved_args.clone();
-        tokio::task::spawn_blocking(move || {
-            let provider = crate::environment::get_provider(&provider_name)?;
-            provider.run(&meta, &cmd, &args)
-        })
+        let env_id = env.id.clone();
+        tokio::task::spawn_blocking(
+            move || -> anyhow::Result<Vec<(TaskStep, crate::environment::RunSpec)>> {
+                let provider = crate::environment::get_provider(&provider_name)?;
+                step_commands
+                    .into_iter()
+                    .map(|(step, program, args, stdin)| {
+                        let run_spec = provider.run(&meta, &program, &args, stdin.as_deref())?;
+                        Ok((step, run_spec.in_environment(&env_id, &project.path)))
+                    })
+                    .collect()
+            },
+        )
         .await??
     };
 
     let log_path = crate::paths::task_log_path(task_id)?;
     std::fs::create_dir_all(log_path.parent().unwrap())?;
-    let log_file = std::fs::File::create(&log_path)?;
-    let stderr_file = log_file.try_clone()?;
+    let result_path = crate::paths::task_result_path(task_id)?;
+    std::fs::create_dir_all(result_path.parent().unwrap())?;
+    let _ = std::fs::remove_file(&result_path);
+    let cancel_path = crate::paths::task_cancel_path(task_id)?;
+    let _ = std::fs::remove_file(&cancel_path);
+
+    // The runner's output, read off a pipe or a pty, passes through the
+    // daemon, which writes it to the log (encrypting it or cutting it down
+    // when configured to) and streams it to anyone following the task. A pty
+    // log keeps the runner's escape sequences; readers strip them for plain
+    // text. Every step writes to the same pipe or pty.
+    let encrypt_logs = config.encrypt_task_logs();
+    let log: Box<dyn std::io::Write + Send> = match config.max_task_log_bytes() {
+        Some(max_bytes) if encrypt_logs => Box::new(super::task_log::CappedLog::new(
+            crate::log_crypt::EncryptedLog::create(&log_path)?,
+            max_bytes,
+        )),
+        None if encrypt_logs => Box::new(crate::log_crypt::EncryptedLog::create(&log_path)?),
+        Some(max_bytes) => Box::new(super::task_log::CappedLog::create(&log_path, max_bytes)?),
+        None => Box::new(std::fs::File::create(&log_path)?),
+    };
+    // Timestamped logs tag each line with its stream, so stderr gets a pipe
+    // of its own unless a pty merges the two.
+    let timestamps = config.task_log_timestamps();
+    let redactor = config.redactor()?;
+    let capture = |readers, log| {
+        super::task_log::capture(
+            readers,
+            log,
+            &log_path,
+            task_id,
+            timestamps,
+            redactor.clone(),
+        )
+    };
+    let (stdout, stderr, log_done): (std::os::fd::OwnedFd, std::os::fd::OwnedFd, _) = match pty_size
+    {
+        Some((columns, rows)) => {
+            let pty = super::pty::open(columns, rows)?;
+            let done = capture(vec![(Box::new(pty.leader) as _, Stream::Output)], log);
+            let follower: std::os::fd::OwnedFd = pty.follower.into();
+            (follower.try_clone()?, follower, done)
+        }
+        None if timestamps => {
+            let (out_reader, out_writer) = std::io::pipe()?;
+            let (err_reader, err_writer) = std::io::pipe()?;
+            let done = capture(
+                vec![
+                    (Box::new(out_reader) as _, Stream::Stdout),
+                    (Box::new(err_reader) as _, Stream::Stderr),
+                ],
+                log,
+            );
+            (out_writer.into(), err_writer.into(), done)
+        }
+        None => {
+            let (reader, writer) = std::io::pipe()?;
+            let done = capture(vec![(Box::new(reader) as _, Stream::Output)], log);
+            let writer: std::os::fd::OwnedFd = writer.into();
+            (writer.try_clone()?, writer, done)
+        }
+    };
+    // Notes left while the task was pending had no log to go in.
+    for note in &task.notes {
+        super::task_log::append_note(task_id, note);
+    }
 
-    tracing::info!(task_id = %task_id, command = %run_spec.program, log = %log_path.display(), "running task command");
+    let chained = steps.len() > 1;
+    let resources = config.task_resources();
+    let mut commands = Vec::new();
+    for (step, run_spec) in steps {
+        tracing::info!(task_id = %task_id, step = step.name(), command = %run_spec.program, log = %log_path.display(), "running task command");
 
-    let mut command = tokio::process::Command::new(&run_spec.program);
-    command.args(&run_spec.args);
+        let (program, args) =
+            super::task_limits::wrap(&run_spec.program, &run_spec.args, &resources);
+        let mut command = std::process::Command::new(program);
+        command.args(args);
+        super::task_limits::lower_priority(&mut command, &resources);
 
-    if let Some(cwd) = &run_spec.cwd {
-        command.current_dir(cwd);
+        if let Some(cwd) = &run_spec.cwd {
+            command.current_dir(cwd);
+        }
+
+        // The daemon usually has no TERM of its own, and runners given a pty
+        // look at it to decide how much color and cursor movement to use.
+        if pty_size.is_some() {
+            command.env("TERM", "xterm-256color");
+        }
+        for (key, value) in &run_spec.env {
+            command.env(key, value);
+        }
+        command.env("WORK_RESULT_PATH", &result_path);
+        command.env("WORK_CANCEL_FILE", &cancel_path);
+
+        if run_spec.stdin_data.is_some() {
+            command.stdin(std::process::Stdio::piped());
+        } else {
+            command.stdin(std::process::Stdio::null());
+        }
+
+        command.stdout(std::process::Stdio::from(stdout.try_clone()?));
+        command.stderr(std::process::Stdio::from(stderr.try_clone()?));
+        // Lead a new process group so the whole runner tree can be terminated.
+        command.process_group(0);
+
+        let label = std::iter::once(run_spec.program.as_str())
+            .chain(run_spec.args.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ");
+        commands.push((step, label, command, run_spec.stdin_data));
     }
+    // Close the daemon's copies of the output so capture sees EOF once the
+    // last step exits.
+    drop(stdout);
+    drop(stderr);
+
+    let diff_base = task_diff_base(task, env, encrypt_logs);
+
+    // Run on a blocking thread so each step can be reaped with wait4, which
+    // reports its resource usage.
+    let process_task_id = task_id.to_string();
+    let (status, usage, cancelled) = tokio::task::spawn_blocking(move || {
+        let task_id = process_task_id;
+        let mut status: Option<std::process::ExitStatus> = None;
+        let mut usage: Option<db::ResourceUsage> = None;
+        let mut cancelled = false;
+        for (step, label, mut command, stdin_data) in commands {
+            let failed = status.is_some_and(|s| !s.success());
+            let skip = match step {
+                TaskStep::Pre => false,
+                TaskStep::Main => failed,
+                TaskStep::Post => failed && post_on_failure == crate::config::PostOnFailure::Skip,
+            };
+            if cancelled || skip {
+                if chained && !cancelled {
+                    log_step(&task_id, &format!("{}: skipped", step.name()));
+                }
+                continue;
+            }
+            if chained {
+                super::task_log::mark(
+                    &task_id,
+                    &format!("{}{}", crate::log_sections::GROUP, step.name()),
+                );
+                log_step(&task_id, &format!("{}: {label}", step.name()));
+            }
+            let started = std::time::Instant::now();
+            let spawned = command.spawn();
+            // Drop the command's copies of the output so capture sees EOF
+            // once the last step exits.
+            drop(command);
+            let (step_status, step_usage) = match spawned {
+                Ok(child) => {
+                    let (step_status, step_usage) =
+                        run_task_step(&task_id, child, started, stdin_data)?;
+                    cancelled = super::reaper::take_cancelled(&task_id);
+                    if chained {
+                        log_step(&task_id, &format!("{}: {step_status}", step.name()));
+                    }
+                    (step_status, step_usage)
+                }
+                // A step that can't start fails the way a shell's missing
+                // command does, and the steps after it go on as usual.
+                Err(e) => {
+                    log_step(&task_id, &format!("{}: failed to start: {e}", step.name()));
+                    (
+                        std::process::ExitStatus::from_raw(127 << 8),
+                        db::ResourceUsage::default(),
+                    )
+                }
+            };
+            if chained {
+                super::task_log::mark(&task_id, crate::log_sections::END_GROUP);
+            }
+            usage = Some(match usage {
+                Some(total) => add_usage(total, step_usage),
+                None => step_usage,
+            });
+            // The first step to fail decides the task's status.
+            if !failed {
+                status = Some(step_status);
+            }
+        }
+        super::task_log::drain(&log_done);
+        let (Some(status), Some(usage)) = (status, usage) else {
+            anyhow::bail!("task {task_id} ran no commands");
+        };
+        anyhow::Ok((status, usage, cancelled))
+    })
+    .await??;
 
-    for (key, value) in &run_spec.env {
-        command.env(key, value);
+    db::set_task_resource_usage(task_id, &usage)?;
+    let _ = std::fs::remove_file(&cancel_path);
+
+    let task_status = super::task_status::from_exit(status, cancelled);
+
+    if let Some(result) = super::task_result::read(&result_path, &log_path) {
+        db::set_task_result(
+            task_id,
+            result.summary.as_deref(),
+            result.data.as_ref(),
+            result.cost.as_ref(),
+        )?;
+    }
+    // The runner writes its result file in the clear. Its contents are in
+    // the database now, so don't leave it beside an encrypted log.
+    if encrypt_logs {
+        let _ = std::fs::remove_file(&result_path);
     }
 
-    if run_spec.stdin_data.is_some() {
-        command.stdin(std::process::Stdio::piped());
-    } else {
-        command.stdin(std::process::Stdio::null());
+    if let Some((worktree, base)) = diff_base {
+        let diff_path = crate::paths::task_diff_path(task_id)?;
+        let diff_task_id = task_id.to_string();
+        tokio::task::spawn_blocking(move || {
+            match super::task_diff::capture(&worktree, &base, &diff_path) {
+                Ok(()) => log_step(
+                    &diff_task_id,
+                    &format!("diff: saved to {}", diff_path.display()),
+                ),
+                Err(e) => {
+                    tracing::warn!(task_id = %diff_task_id, error = %e, "failed to capture task diff");
+                    log_step(&diff_task_id, &format!("diff: not captured: {e}"));
+                }
+            }
+        })
+        .await?;
     }
 
-    command.stdout(std::process::Stdio::from(log_file));
-    command.stderr(std::process::Stdio::from(stderr_file));
+    db::update_task_status(task_id, task_status)?;
+
+    let index_task_id = task_id.to_string();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = super::search::index_task_log(&index_task_id) {
+            tracing::warn!(task_id = %index_task_id, error = %e, "failed to index task log");
+        }
+    });
+
+    tracing::info!(task_id = %task_id, status = %task_status, "task finished");
 
-    let mut child = command.spawn()?;
+    Ok(())
+}
 
-    if let Some(data) = run_spec.stdin_data {
-        use tokio::io::AsyncWriteExt;
-        if let Some(mut stdin) = child.stdin.take() {
-            stdin.write_all(&data).await?;
+/// The worktree and the commit or branch its environment started from, to
+/// diff a task's changes against when it finishes, if its project has
+/// `capture-diff` on. Tasks that can't have one
+/// say why in their log.
+fn task_diff_base(
+    task: &db::Task,
+    env: &db::Environment,
+    encrypt_logs: bool,
+) -> Option<(std::path::PathBuf, String)> {
+    let project = db::get_project(&task.project_id).ok()?;
+    let config = crate::config::load_for_project(&project.name, Path::new(&project.path)).ok()?;
+    if !config.capture_diff(&project.name) {
+        return None;
+    }
+    // A diff holds the same code a log might, so it isn't written in the
+    // clear beside an encrypted one.
+    if encrypt_logs {
+        log_step(&task.id, "diff: not captured, task logs are encrypted");
+        return None;
+    }
+    let Some(worktree) = env.metadata["worktree_path"].as_str() else {
+        log_step(
+            &task.id,
+            "diff: not captured, the environment has no local worktree",
+        );
+        return None;
+    };
+    let worktree = std::path::PathBuf::from(worktree);
+    let base_ref = env.metadata["source_commit"]
+        .as_str()
+        .or_else(|| env.metadata["base_branch"].as_str());
+    match super::task_diff::base(&worktree, base_ref) {
+        Ok(base) => Some((worktree, base)),
+        Err(e) => {
+            log_step(&task.id, &format!("diff: not captured: {e}"));
+            None
         }
     }
+}
 
-    let status = child.wait().await?;
+/// The shell script a mock task provider runs: it waits, prints `output`,
+/// records `summary` as the result, and exits with `exit_code`. Running it
+/// like any other command keeps logs, cancellation, and results on the
+/// same path as real runners.
+fn mock_task_script(
+    output: &[String],
+    latency_ms: u64,
+    exit_code: i32,
+    summary: &Option<String>,
+) -> String {
+    use crate::environment::shell_quote;
+
+    let mut script = Vec::new();
+    if latency_ms > 0 {
+        script.push(format!(
+            "sleep {}.{:03}",
+            latency_ms / 1000,
+            latency_ms % 1000
+        ));
+    }
+    if !output.is_empty() {
+        let lines: Vec<String> = output.iter().map(|line| shell_quote(line)).collect();
+        script.push(format!("printf '%s\\n' {}", lines.join(" ")));
+    }
+    if let Some(summary) = summary {
+        let result = serde_json::json!({ "summary": summary }).to_string();
+        script.push(format!(
+            "printf '%s' {} > \"$WORK_RESULT_PATH\"",
+            shell_quote(&result)
+        ));
+    }
+    script.push(format!("exit {exit_code}"));
+    script.join("\n")
+}
 
-    let task_status = if status.success() {
-        "complete"
-    } else {
-        "failed"
-    };
+/// One of the commands a task provider runs, in order.
+#[derive(Clone, Copy)]
+enum TaskStep {
+    Pre,
+    Main,
+    Post,
+}
 
-    db::update_task_status(task_id, task_status)?;
-    super::events::notify();
+impl TaskStep {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Pre => "pre",
+            Self::Main => "command",
+            Self::Post => "post",
+        }
+    }
+}
 
-    tracing::info!(task_id = %task_id, status = %task_status, "task finished");
+/// Mark where a step starts or how it ended in the task's log.
+fn log_step(task_id: &str, line: &str) {
+    super::task_log::append(task_id, &chrono::Utc::now().to_rfc3339(), line);
+}
 
-    Ok(())
+/// Record a spawned step of a task so it can be cancelled, feed it its
+/// input, and wait for it to exit.
+fn run_task_step(
+    task_id: &str,
+    mut child: std::process::Child,
+    started: std::time::Instant,
+    stdin_data: Option<Vec<u8>>,
+) -> std::io::Result<(std::process::ExitStatus, db::ResourceUsage)> {
+    let pid = child.id();
+    let process_start = super::reaper::process_start_time(pid);
+    if let Err(e) = db::insert_task_process(task_id, pid, process_start.as_deref()) {
+        tracing::warn!(task_id = %task_id, error = %e, "failed to record task process");
+    }
+    if let Some(data) = stdin_data
+        && let Some(mut stdin) = child.stdin.take()
+    {
+        // A command that exits without reading its input closes the pipe
+        // first; how it exited is what counts.
+        match stdin.write_all(&data) {
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+            result => result?,
+        }
+    }
+    let result = super::rusage::wait(child, started);
+    if let Err(e) = db::delete_task_process(task_id) {
+        tracing::warn!(task_id = %task_id, error = %e, "failed to clear task process");
+    }
+    result
+}
+
+/// Resource usage across a task's steps: times add up, and the peak is the
+/// highest of any step.
+fn add_usage(total: db::ResourceUsage, step: db::ResourceUsage) -> db::ResourceUsage {
+    db::ResourceUsage {
+        wall_ms: total.wall_ms + step.wall_ms,
+        user_cpu_ms: total.user_cpu_ms + step.user_cpu_ms,
+        system_cpu_ms: total.system_cpu_ms + step.system_cpu_ms,
+        peak_rss_kb: total.peak_rss_kb.max(step.peak_rss_kb),
+    }
 }
@@ -1,6 +1,27 @@
+mod access;
+mod chaos;
 pub mod events;
+mod idle;
+mod janitor;
 mod jobs;
+mod notify;
+mod project_watch;
+mod pty;
+mod rate_limit;
+mod readiness;
+mod reaper;
+mod reload;
+mod resources;
 mod routes;
+mod rusage;
+mod search;
+mod state;
+mod task_diff;
+mod task_limits;
+mod task_log;
+mod task_result;
+mod task_status;
+mod trace;
 
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -12,6 +33,10 @@ use tokio::net::UnixListener;
 use tokio::sync::watch;
 use tower_http::trace::TraceLayer;
 
+pub use access::PeerStats;
+pub use reload::set_log_level_handle;
+pub use trace::{Outcome, ReplayedJob, replay};
+
 fn pid_path(runtime_dir: &Path) -> PathBuf {
     runtime_dir.join("work.pid")
 }
@@ -20,6 +45,11 @@ fn socket_path(runtime_dir: &Path) -> PathBuf {
     runtime_dir.join("work.sock")
 }
 
+/// Whether a daemon is accepting connections on the socket in `runtime_dir`.
+pub fn is_running(runtime_dir: &Path) -> bool {
+    std::os::unix::net::UnixStream::connect(socket_path(runtime_dir)).is_ok()
+}
+
 fn cleanup(runtime_dir: &Path) {
     let pid = pid_path(runtime_dir);
     let sock = socket_path(runtime_dir);
@@ -42,6 +72,9 @@ pub async fn start(force: bool) -> anyhow::Result<()> {
         "starting daemon"
     );
 
+    chaos::init()?;
+    trace::init()?;
+
     let runtime_dir = crate::paths::runtime_dir()?;
     fs::create_dir_all(&runtime_dir)?;
 
@@ -61,26 +94,61 @@ pub async fn start(force: bool) -> anyhow::Result<()> {
     }
 
     crate::db::initialize()?;
+    crate::db::clear_environment_locks()?;
+    readiness::mark_migrated();
     tracing::debug!("database initialized");
 
+    tokio::task::spawn_blocking(|| {
+        if let Err(e) = reaper::reap_orphans() {
+            tracing::error!(error = %e, "failed to reap orphaned task processes");
+        }
+    });
+
+    if let Err(e) = janitor::remove_tmpfs_environments() {
+        tracing::error!(error = %e, "failed to remove leftover tmpfs environments");
+    }
+
+    reload::init();
+    access::init()?;
+    tokio::spawn(reload_on_hangup());
+
+    tokio::task::spawn_blocking(|| {
+        if let Err(e) = search::backfill() {
+            tracing::error!(error = %e, "failed to backfill task search index");
+        }
+    });
+
+    let shutdown = shutdown_signal();
+
     fs::write(&pid, std::process::id().to_string())?;
     tracing::debug!(path = %pid.display(), pid = std::process::id(), "wrote PID file");
 
-    let listener = UnixListener::bind(&sock)?;
+    let activity = idle::Activity::new();
+    let listener = idle::Listener::new(UnixListener::bind(&sock)?, activity.clone());
     tracing::info!(socket = %sock.display(), "listening");
 
     let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
+    let publisher_handle = tokio::spawn(events::publish(shutdown_rx.clone()));
+    let notify_handle = tokio::spawn(notify::dispatch(shutdown_rx.clone()));
+    let watch_handle = tokio::spawn(project_watch::run(shutdown_rx.clone()));
     let job_handle = tokio::spawn(jobs::run(shutdown_rx));
 
     let app = Router::new()
         .route("/events", get(routes::events))
+        .route("/events/history", get(routes::list_events))
         .route("/health", get(routes::health))
+        .route("/ready", get(routes::ready))
+        .route("/ui", get(routes::dashboard))
         .route(
             "/projects",
             get(routes::list_projects).post(routes::create_project),
         )
         .route("/projects/{name}", delete(routes::delete_project))
+        .route(
+            "/projects/{name}/aliases",
+            post(routes::add_project_alias),
+        )
         .route(
             "/environments",
             get(routes::list_environments).post(routes::prepare_environment),
@@ -91,18 +159,69 @@ pub async fn start(force: bool) -> anyhow::Result<()> {
         )
         .route("/environments/{id}/claim", post(routes::claim_environment))
         .route("/environments/claim", post(routes::claim_next_environment))
+        .route(
+            "/environments/claim-batch",
+            post(routes::claim_environment_batch),
+        )
+        .route("/environments/adopt", post(routes::adopt_environment))
+        .route("/environments/plan", post(routes::plan_environment))
+        .route("/environments/providers", get(routes::provider_stats))
         .route(
             "/environments/{id}",
             get(routes::get_environment).delete(routes::remove_environment),
         )
         .route("/environments/{id}/logs", get(routes::tail_environment_logs))
+        .route(
+            "/environments/{id}/disk-usage",
+            get(routes::environment_disk_usage),
+        )
+        .route(
+            "/environments/{id}/history",
+            get(routes::environment_status_history),
+        )
+        .route(
+            "/environments/{id}/snapshots",
+            get(routes::list_environment_snapshots).post(routes::snapshot_environment),
+        )
+        .route(
+            "/environments/{id}/restore",
+            post(routes::restore_environment),
+        )
         .route("/tasks", get(routes::list_tasks).post(routes::create_task))
+        .route("/tasks/counts", get(routes::count_tasks))
         .route(
             "/tasks/{id}",
             get(routes::get_task).delete(routes::remove_task),
         )
         .route("/tasks/{id}/logs", get(routes::tail_task_logs))
+        .route("/tasks/{id}/logs/sections", get(routes::task_log_sections))
+        .route("/tasks/{id}/labels", post(routes::update_task_labels))
+        .route("/tasks/{id}/notes", post(routes::add_task_note))
+        .route(
+            "/groups",
+            get(routes::list_task_groups).post(routes::create_task_group),
+        )
+        .route(
+            "/groups/{id}",
+            get(routes::get_task_group).delete(routes::remove_task_group),
+        )
+        .route("/search", get(routes::search_tasks))
+        .route("/stats/costs", get(routes::cost_stats))
+        .route("/jobs", get(routes::list_jobs))
+        .route("/jobs/dead", delete(routes::purge_dead_jobs))
+        .route("/jobs/{id}", get(routes::get_job))
+        .route("/jobs/{id}/retry", post(routes::retry_job))
         .route("/reset-database", post(routes::reset_database))
+        .route("/export", get(routes::export_state))
+        .route("/import", post(routes::import_state))
+        .route("/remap-paths", post(routes::remap_paths))
+        .route("/db/check", get(routes::check_integrity))
+        .route("/db/repair", post(routes::repair_integrity))
+        .route("/db/maintain", post(routes::maintain_database))
+        .route("/config/reload", post(routes::reload_config))
+        .route("/peers", get(routes::list_peers))
+        .layer(axum::middleware::from_fn(access::track))
+        .layer(axum::middleware::map_response(stamp_version))
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(|req: &axum::http::Request<_>| {
@@ -117,13 +236,19 @@ pub async fn start(force: bool) -> anyhow::Result<()> {
                 ),
         );
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(async {
-            shutdown_signal().await;
-            tracing::info!("closing event streams");
-            events::shutdown();
-        })
-        .await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<access::Peer>(),
+    )
+    .with_graceful_shutdown(async {
+        tokio::select! {
+            _ = shutdown => {}
+            _ = idle::wait(activity) => {}
+        }
+        tracing::info!("closing event streams");
+        events::shutdown();
+    })
+    .await?;
 
     // Spawn a task that forces exit on a second signal.
     let rd = runtime_dir.clone();
@@ -137,6 +262,14 @@ pub async fn start(force: bool) -> anyhow::Result<()> {
     tracing::info!("stopping job processor");
     let _ = shutdown_tx.send(true);
     let _ = job_handle.await;
+    let _ = publisher_handle.await;
+    let _ = notify_handle.await;
+    let _ = watch_handle.await;
+
+    tracing::info!("removing tmpfs environments");
+    if let Err(e) = tokio::task::spawn_blocking(janitor::remove_tmpfs_environments).await? {
+        tracing::error!(error = %e, "failed to remove tmpfs environments");
+    }
 
     cleanup(&runtime_dir);
     tracing::info!("daemon shut down");
@@ -144,14 +277,217 @@ pub async fn start(force: bool) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Name the version of `work` answering on every response, so clients can
+/// tell a daemon left running across an upgrade without asking separately.
+async fn stamp_version(mut res: axum::response::Response) -> axum::response::Response {
+    res.headers_mut().insert(
+        crate::client::VERSION_HEADER,
+        axum::http::HeaderValue::from_static(env!("CARGO_PKG_VERSION")),
+    );
+    res
+}
+
+/// Start the daemon as a detached background process and wait until it
+/// reports ready. Returns the daemon's PID.
+pub async fn start_detached(force: bool, timeout: std::time::Duration) -> anyhow::Result<u32> {
+    let runtime_dir = crate::paths::runtime_dir()?;
+    if !force && (pid_path(&runtime_dir).exists() || socket_path(&runtime_dir).exists()) {
+        anyhow::bail!(
+            "daemon already running (found runtime files in {}); use --force to override",
+            runtime_dir.display()
+        );
+    }
+
+    let state_dir = crate::paths::state_dir()?;
+    fs::create_dir_all(&state_dir)?;
+    let log = |name: &str| {
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(state_dir.join(name))
+    };
+
+    let mut command = Command::new(std::env::current_exe()?);
+    if let Some(profile) = crate::paths::profile() {
+        command.args(["--profile", &profile]);
+    }
+    command.args(["daemon", "start"]);
+    if force {
+        command.arg("--force");
+    }
+    {
+        use std::os::unix::process::CommandExt;
+        // Leave the caller's process group so its signals don't reach the daemon.
+        command.process_group(0);
+    }
+    let mut child = command
+        .stdin(std::process::Stdio::null())
+        .stdout(log("daemon.out.log")?)
+        .stderr(log("daemon.err.log")?)
+        .spawn()?;
+    let pid = child.id();
+
+    let client = crate::client::DaemonClient::new()?;
+    tokio::select! {
+        ready = client.wait_until_ready(timeout) => {
+            if let Err(e) = ready {
+                // Don't leave behind a daemon that may come up after the
+                // caller has given up on it.
+                stop_unready(&mut child, &runtime_dir).await;
+                return Err(e);
+            }
+        }
+        // Poll rather than block a thread on wait(), which would keep the
+        // runtime from shutting down once the daemon is ready.
+        exited = async {
+            loop {
+                if let Some(status) = child.try_wait()? {
+                    return Ok::<_, std::io::Error>(status);
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        } => {
+            let status = exited?;
+            anyhow::bail!(
+                "daemon exited with {status} before becoming ready; see {}",
+                state_dir.join("daemon.err.log").display()
+            );
+        }
+    }
+    Ok(pid)
+}
+
+/// Stop a daemon that didn't become ready in time. SIGTERM lets it remove
+/// its runtime files; one that is stuck gets SIGKILL after a few seconds,
+/// and its files are removed for it.
+async fn stop_unready(child: &mut std::process::Child, runtime_dir: &Path) {
+    // SAFETY: the child hasn't been reaped, so its PID is still ours.
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+    for _ in 0..50 {
+        if matches!(child.try_wait(), Ok(Some(_))) {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+    cleanup(runtime_dir);
+}
+
+/// Start the daemon in the background unless it is already running, and
+/// wait until it is ready. Returns the PID of a daemon this started. This is
+/// how commands start it on demand with `auto-start-daemon`. Runtime files
+/// left behind by a daemon that is no longer alive are cleared first.
+pub async fn ensure_running(timeout: std::time::Duration) -> anyhow::Result<Option<u32>> {
+    let runtime_dir = crate::paths::runtime_dir()?;
+    if is_running(&runtime_dir) {
+        return Ok(None);
+    }
+    let pid_file = pid_path(&runtime_dir);
+    let alive = fs::read_to_string(&pid_file)
+        .ok()
+        .and_then(|pid| pid.trim().parse::<i32>().ok())
+        // SAFETY: signal 0 only checks that the process exists.
+        .is_some_and(|pid| unsafe { libc::kill(pid, 0) } == 0);
+    if alive {
+        // Another command is starting it.
+        crate::client::DaemonClient::new()?
+            .wait_until_ready(timeout)
+            .await?;
+        return Ok(None);
+    }
+    let stale = pid_file.exists() || socket_path(&runtime_dir).exists();
+    match start_detached(stale, timeout).await {
+        Ok(pid) => Ok(Some(pid)),
+        // Lost a race with another command starting it.
+        Err(_) if is_running(&runtime_dir) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Relay stdin and stdout to the daemon socket until both sides close. This
+/// is what `work --host` runs on the remote machine over SSH.
+pub async fn proxy() -> anyhow::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let path = socket_path(&crate::paths::runtime_dir()?);
+    let stream = tokio::net::UnixStream::connect(&path)
+        .await
+        .map_err(|e| anyhow::anyhow!("could not connect to daemon at {}: {e}", path.display()))?;
+    let (mut from_daemon, mut to_daemon) = stream.into_split();
+
+    let upstream = async {
+        tokio::io::copy(&mut tokio::io::stdin(), &mut to_daemon).await?;
+        to_daemon.shutdown().await
+    };
+    let downstream = async {
+        let mut stdout = tokio::io::stdout();
+        tokio::io::copy(&mut from_daemon, &mut stdout).await?;
+        stdout.flush().await
+    };
+    tokio::try_join!(upstream, downstream)?;
+    Ok(())
+}
+
 const LABEL: &str = "com.jclem.work";
 
+/// The launchd label, suffixed with the profile name so each profile's
+/// daemon is a separate agent.
+fn label() -> String {
+    match crate::paths::profile() {
+        Some(profile) => format!("{LABEL}.{profile}"),
+        None => LABEL.to_string(),
+    }
+}
+
 fn plist_path() -> anyhow::Result<PathBuf> {
     let home =
         dirs::home_dir().ok_or_else(|| anyhow::anyhow!("could not determine home directory"))?;
     Ok(home
         .join("Library/LaunchAgents")
-        .join(format!("{LABEL}.plist")))
+        .join(format!("{}.plist", label())))
+}
+
+/// How to restart a running daemon so it picks up a new binary. A daemon
+/// installed with [`install`] is kept alive by launchd, which would keep
+/// running the old binary until asked to restart it.
+pub fn restart_hint() -> String {
+    match plist_path() {
+        Ok(path) if path.exists() => {
+            format!("run `launchctl kickstart -k gui/$(id -u)/{}`", label())
+        }
+        _ => "stop it and run `work daemon start`".to_string(),
+    }
+}
+
+/// Whether the daemon is registered to start on login.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Registration {
+    /// launchd loaded the agent from `plist`.
+    Loaded { plist: PathBuf },
+    /// `plist` exists but launchd hasn't loaded it.
+    NotLoaded { plist: PathBuf },
+    /// Nothing starts the daemon; it runs only when started by hand.
+    None,
+}
+
+/// How the daemon for the current profile is registered with launchd.
+pub fn registration() -> anyhow::Result<Registration> {
+    let plist = plist_path()?;
+    if !plist.exists() {
+        return Ok(Registration::None);
+    }
+    let loaded = Command::new("launchctl")
+        .args(["print", &format!("gui/{}/{}", get_uid()?, label())])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    Ok(if loaded {
+        Registration::Loaded { plist }
+    } else {
+        Registration::NotLoaded { plist }
+    })
 }
 
 fn get_uid() -> anyhow::Result<String> {
@@ -176,13 +512,12 @@ pub fn install() -> anyhow::Result<()> {
     <string>{label}</string>
     <key>ProgramArguments</key>
     <array>
-        <string>{binary}</string>
+        <string>{binary}</string>{profile_args}
         <string>daemon</string>
         <string>start</string>
         <string>--force</string>
     </array>
-    <key>KeepAlive</key>
-    <true/>
+    <key>KeepAlive</key>{keep_alive}
     <key>StandardOutPath</key>
     <string>{out_log}</string>
     <key>StandardErrorPath</key>
@@ -190,8 +525,18 @@ pub fn install() -> anyhow::Result<()> {
 </dict>
 </plist>
 "#,
-        label = LABEL,
+        label = label(),
         binary = binary_path.display(),
+        profile_args = crate::paths::profile()
+            .map(|p| format!("\n        <string>--profile</string>\n        <string>{p}</string>"))
+            .unwrap_or_default(),
+        // A daemon that exits when idle would be restarted straight away;
+        // only restart one that crashed.
+        keep_alive = if crate::config::load()?.idle_timeout()?.is_some() {
+            "\n    <dict>\n        <key>SuccessfulExit</key>\n        <false/>\n    </dict>"
+        } else {
+            "\n    <true/>"
+        },
         out_log = state_dir.join("daemon.out.log").display(),
         err_log = state_dir.join("daemon.err.log").display(),
     );
@@ -238,14 +583,38 @@ pub fn uninstall() -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn shutdown_signal() {
+/// Reload config each time the daemon receives SIGHUP.
+async fn reload_on_hangup() {
+    let mut sighup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => signal,
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to listen for SIGHUP");
+            return;
+        }
+    };
+    while sighup.recv().await.is_some() {
+        tracing::info!("received SIGHUP, reloading config");
+        match tokio::task::spawn_blocking(reload::reload).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => tracing::error!(error = %e, "config reload failed"),
+            Err(e) => tracing::error!(error = %e, "config reload task failed"),
+        }
+    }
+}
+
+/// Resolves on SIGINT or SIGTERM. The handlers are installed when this is
+/// called rather than when it is first polled, so a signal that arrives in
+/// between is caught instead of killing the process.
+fn shutdown_signal() -> impl std::future::Future<Output = ()> {
     let mut sigint =
         tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt()).unwrap();
     let mut sigterm =
         tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).unwrap();
 
-    tokio::select! {
-        _ = sigint.recv() => { tracing::debug!("received SIGINT"); }
-        _ = sigterm.recv() => { tracing::debug!("received SIGTERM"); }
+    async move {
+        tokio::select! {
+            _ = sigint.recv() => { tracing::debug!("received SIGINT"); }
+            _ = sigterm.recv() => { tracing::debug!("received SIGTERM"); }
+        }
     }
 }
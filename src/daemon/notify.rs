@@ -0,0 +1,281 @@
+//! Notification providers. Each event a `[notifications.providers.NAME]`
+//! entry subscribes to is queued as a `notify` job, which runs the provider's
+//! script with the event as JSON on stdin. A script that fails is retried
+//! like any other job, under `[daemon.retries.notify]`.
+//!
+//! An event goes by `KIND.ACTION`, such as `task.created`, and when its
+//! subject's status changed, by `KIND.STATUS` too, such as `task.complete`.
+
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{broadcast, watch};
+
+use crate::config::NotificationProviderConfig;
+use crate::db::{self, Event, EventQuery};
+
+/// The types `event` goes by, the one to report it as first. `previous` is
+/// the subject's event before it, so `task.complete` goes out when a task
+/// becomes complete rather than whenever a complete task changes. Going by
+/// the events table, rather than what this daemon has seen, keeps that true
+/// across restarts.
+fn types(event: &Event, previous: Option<&Event>) -> Vec<String> {
+    let action = format!("{}.{}", event.kind, event.action);
+    if event.action == "removed" {
+        return vec![action];
+    }
+    // A subject whose earlier events were pruned may have changed status
+    // since, so its status counts as new.
+    let previous = previous
+        .filter(|previous| previous.action != "removed")
+        .and_then(|previous| previous.status.as_deref());
+    let changed = event
+        .status
+        .as_deref()
+        .filter(|status| previous != Some(*status))
+        .map(|status| format!("{}.{status}", event.kind));
+    match changed {
+        Some(changed) if event.action == "updated" => vec![changed, action],
+        Some(changed) => vec![action, changed],
+        None => vec![action],
+    }
+}
+
+/// Queue a `notify` job for each event a notification provider wants,
+/// until `shutdown` is set.
+pub async fn dispatch(mut shutdown: watch::Receiver<bool>) {
+    let mut receiver = super::events::subscribe();
+    // The newest event handled, to catch up from the table after falling
+    // behind the live stream.
+    let mut last_seq = match tokio::task::spawn_blocking(db::latest_event_seq).await {
+        Ok(Ok(seq)) => seq,
+        Ok(Err(e)) => {
+            tracing::error!(error = %e, "failed to read latest event");
+            0
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "failed to read latest event");
+            0
+        }
+    };
+    // Events up to here were replayed from the table, so the same ones
+    // still queued on the live stream are skipped.
+    let mut replayed_to = None;
+
+    loop {
+        let first = tokio::select! {
+            event = receiver.recv() => event,
+            _ = shutdown.changed() => break,
+        };
+        let (mut batch, mut live) = match first {
+            Ok(event) => (Vec::new(), vec![event]),
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                tracing::warn!(
+                    missed,
+                    since = last_seq,
+                    "notifications fell behind; catching up from the events table"
+                );
+                let query = EventQuery {
+                    since: Some(last_seq),
+                    ..EventQuery::default()
+                };
+                match tokio::task::spawn_blocking(move || db::list_events(&query)).await {
+                    Ok(Ok(events)) => {
+                        replayed_to = events.last().map(|event| event.seq).or(replayed_to);
+                        (events, Vec::new())
+                    }
+                    Ok(Err(e)) => {
+                        tracing::error!(error = %e, "failed to read missed events");
+                        continue;
+                    }
+                    Err(e) => {
+                        tracing::error!(error = %e, "failed to read missed events");
+                        continue;
+                    }
+                }
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        while let Ok(event) = receiver.try_recv() {
+            live.push(event);
+        }
+        if let Some(seq) = replayed_to {
+            live.retain(|event| event.seq > seq);
+            // Once the live stream gets past the replay, it's caught up.
+            if !live.is_empty() {
+                replayed_to = None;
+            }
+        }
+        batch.append(&mut live);
+        let Some(last) = batch.last() else {
+            continue;
+        };
+        last_seq = last.seq;
+
+        match tokio::task::spawn_blocking(move || queue(&batch)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::error!(error = %e, "failed to queue notifications"),
+            Err(e) => tracing::error!(error = %e, "failed to queue notifications"),
+        }
+    }
+}
+
+fn queue(events: &[Event]) -> anyhow::Result<()> {
+    let config = crate::config::load()?;
+    let providers = config.notification_providers();
+    if providers.is_empty() {
+        return Ok(());
+    }
+
+    for event in events {
+        let types = types(event, db::previous_event(event)?.as_ref());
+        let wanted: Vec<&str> = providers
+            .iter()
+            .filter(|(_, provider)| provider.wants(&types))
+            .map(|(name, _)| *name)
+            .collect();
+        // Notifying about a notify job would queue another one, forever.
+        if wanted.is_empty() || is_notify_job(event) {
+            continue;
+        }
+        for provider in wanted {
+            let payload = serde_json::json!({
+                "provider": provider,
+                "type": types[0],
+                "event": event,
+            });
+            let job_id = db::queue_notification(&payload)?;
+            tracing::debug!(provider, event_type = %types[0], job_id = %job_id, "notification queued");
+        }
+    }
+    Ok(())
+}
+
+fn is_notify_job(event: &Event) -> bool {
+    event.kind == "job" && db::get_job(&event.subject_id).is_ok_and(|job| job.job_type == "notify")
+}
+
+/// The subject of an event as it is now, or null once it's gone.
+fn subject(kind: &str, id: &str) -> serde_json::Value {
+    let subject = match kind {
+        "task" => db::get_task(id).map(|t| serde_json::json!(t)),
+        "environment" => db::get_environment(id).map(|e| serde_json::json!(e)),
+        "project" => db::get_project(id).map(|p| serde_json::json!(p)),
+        "group" => db::get_task_group(id).map(|g| serde_json::json!(g)),
+        "job" => db::get_job(id).map(|j| serde_json::json!(j)),
+        _ => return serde_json::Value::Null,
+    };
+    subject.unwrap_or(serde_json::Value::Null)
+}
+
+/// Run a `notify` job: send its event to the provider's script, with the
+/// event's subject as it is now.
+pub async fn run(job: &db::Job) -> anyhow::Result<()> {
+    let provider_name = job.payload["provider"]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("job payload missing provider"))?;
+    let event = &job.payload["event"];
+    let kind = event["kind"].as_str().unwrap_or_default().to_string();
+    let subject_id = event["subject_id"].as_str().unwrap_or_default().to_string();
+
+    let config = crate::config::load()?;
+    let provider = config.get_notification_provider(provider_name)?;
+    let timeout = provider.timeout()?;
+    let NotificationProviderConfig::Script {
+        path, process_env, ..
+    } = provider;
+    let path = crate::config::expand_home(path);
+
+    let subject = tokio::task::spawn_blocking(move || subject(&kind, &subject_id)).await?;
+    let input = serde_json::to_vec(&serde_json::json!({
+        "provider": provider_name,
+        "type": job.payload["type"],
+        "event": event,
+        "subject": subject,
+    }))?;
+
+    let mut child = tokio::process::Command::from(process_env.command(&path))
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("failed to run {}: {e}", path.display()))?;
+    let stdin = child.stdin.take();
+    let finished = async move {
+        if let Some(mut stdin) = stdin {
+            // A script that doesn't read its input closes the pipe early.
+            let _ = stdin.write_all(&input).await;
+        }
+        child.wait_with_output().await
+    };
+    let output = tokio::time::timeout(timeout, finished)
+        .await
+        .map_err(|_| {
+            anyhow::anyhow!("{} timed out after {}s", path.display(), timeout.as_secs())
+        })??;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!(
+            "{} failed with {}: {}",
+            path.display(),
+            output.status,
+            stderr.trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: &str, action: &str, status: Option<&str>) -> Event {
+        Event {
+            seq: 1,
+            kind: kind.to_string(),
+            subject_id: "x".to_string(),
+            action: action.to_string(),
+            status: status.map(str::to_string),
+            created_at: String::new(),
+        }
+    }
+
+    #[test]
+    fn status_types_go_out_only_when_the_status_changes() {
+        let created = event("task", "created", Some("pending"));
+        assert_eq!(types(&created, None), ["task.created", "task.pending"]);
+        let complete = event("task", "updated", Some("complete"));
+        assert_eq!(
+            types(&complete, Some(&created)),
+            ["task.complete", "task.updated"]
+        );
+        assert_eq!(types(&complete, Some(&complete)), ["task.updated"]);
+        assert_eq!(
+            types(&event("task", "removed", Some("complete")), Some(&complete)),
+            ["task.removed"]
+        );
+        let removed = event("task", "removed", Some("complete"));
+        assert_eq!(
+            types(&complete, Some(&removed)),
+            ["task.complete", "task.updated"]
+        );
+        assert_eq!(
+            types(&event("project", "updated", None), None),
+            ["project.updated"]
+        );
+    }
+
+    #[test]
+    fn providers_want_exact_types_kinds_and_everything() {
+        let provider = |events: &[&str]| NotificationProviderConfig::Script {
+            path: "notify.sh".to_string(),
+            events: events.iter().map(|e| e.to_string()).collect(),
+            timeout: None,
+            process_env: Default::default(),
+        };
+        let types = ["task.complete".to_string(), "task.updated".to_string()];
+        assert!(provider(&["task.complete"]).wants(&types));
+        assert!(provider(&["task.*"]).wants(&types));
+        assert!(provider(&["*"]).wants(&types));
+        assert!(!provider(&["task.failed", "environment.*"]).wants(&types));
+    }
+}
@@ -0,0 +1,219 @@
+//! Project watching. With `[daemon] watch-projects = true`, the daemon
+//! watches the git refs of each project's checkout, and when they move,
+//! checks the project's pooled environments against the commit a new one
+//! would start from. One that doesn't have it is marked outdated, which
+//! records an `environment.outdated` event, until it catches up.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use notify::{RecursiveMode, Watcher};
+use tokio::sync::{broadcast, mpsc, watch};
+
+use crate::db;
+
+/// How long to wait for more ref changes before checking, since a commit,
+/// fetch, or rebase moves refs in bursts.
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// How often to pick up changes no event announces, such as turning
+/// `watch-projects` on, and recheck every project.
+const RESYNC: Duration = Duration::from_secs(60);
+
+/// Watch projects until `shutdown` is set.
+pub async fn run(mut shutdown: watch::Receiver<bool>) {
+    let (changed_tx, mut changed) = mpsc::unbounded_channel();
+    let mut watcher =
+        match notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+            if let Ok(event) = result {
+                for path in event.paths {
+                    let _ = changed_tx.send(path);
+                }
+            }
+        }) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                tracing::error!(error = %e, "failed to start the project watcher");
+                return;
+            }
+        };
+    let mut events = super::events::subscribe();
+    // Git directories being watched, and the projects checked out from each.
+    let mut watched: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    let mut resync = tokio::time::interval(RESYNC);
+
+    loop {
+        let full = tokio::select! {
+            _ = resync.tick() => true,
+            event = events.recv() => match event {
+                Ok(event) if event.kind == "project" => false,
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(_)) => true,
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            Some(path) = changed.recv() => {
+                let mut paths = vec![path];
+                tokio::time::sleep(SETTLE).await;
+                while let Ok(path) = changed.try_recv() {
+                    paths.push(path);
+                }
+                let projects = moved_projects(&watched, &paths);
+                if !projects.is_empty() {
+                    check_in_background(projects).await;
+                }
+                continue;
+            }
+            _ = shutdown.changed() => break,
+        };
+
+        let added = sync(&mut watcher, &mut watched).await;
+        let projects = if full {
+            watched.values().flatten().cloned().collect()
+        } else {
+            added
+        };
+        if !projects.is_empty() {
+            check_in_background(projects).await;
+        }
+    }
+}
+
+/// Watch the git directories of the projects there are now, and stop
+/// watching ones that are gone. Returns the projects newly watched.
+async fn sync(
+    watcher: &mut impl Watcher,
+    watched: &mut HashMap<PathBuf, Vec<String>>,
+) -> Vec<String> {
+    let wanted = match tokio::task::spawn_blocking(git_dirs).await {
+        Ok(Ok(wanted)) => wanted,
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "failed to list projects to watch");
+            return Vec::new();
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to list projects to watch");
+            return Vec::new();
+        }
+    };
+
+    for dir in watched.keys().filter(|dir| !wanted.contains_key(*dir)) {
+        let _ = watcher.unwatch(&dir.join("refs"));
+        let _ = watcher.unwatch(dir);
+    }
+    let mut added = Vec::new();
+    for (dir, projects) in &wanted {
+        let known = watched.get(dir);
+        added.extend(
+            projects
+                .iter()
+                .filter(|project| known.is_none_or(|known| !known.contains(project)))
+                .cloned(),
+        );
+        if known.is_some() {
+            continue;
+        }
+        // `refs` holds the branches; the directory itself holds `HEAD` and
+        // `packed-refs`, without the churn of `objects`.
+        let result = watcher
+            .watch(&dir.join("refs"), RecursiveMode::Recursive)
+            .and_then(|()| watcher.watch(dir, RecursiveMode::NonRecursive));
+        match result {
+            Ok(()) => tracing::debug!(dir = %dir.display(), "watching project refs"),
+            Err(e) => tracing::warn!(dir = %dir.display(), error = %e, "failed to watch project"),
+        }
+    }
+    *watched = wanted;
+    added
+}
+
+/// The git directory of every project to watch, or none when watching is
+/// off, in which case no outdated marks are left standing.
+fn git_dirs() -> anyhow::Result<HashMap<PathBuf, Vec<String>>> {
+    let mut dirs: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    if !crate::config::load()?.watches_projects() {
+        db::clear_outdated_environments()?;
+        return Ok(dirs);
+    }
+    for project in db::list_projects()? {
+        let env = crate::config::ProcessEnv::default();
+        match crate::environment::git_common_dir(Path::new(&project.path), &env) {
+            Ok(dir) => dirs.entry(dir).or_default().push(project.id),
+            Err(e) => {
+                tracing::debug!(project = %project.name, error = %e, "not watching project");
+            }
+        }
+    }
+    Ok(dirs)
+}
+
+/// The projects whose refs `paths` changed.
+fn moved_projects(watched: &HashMap<PathBuf, Vec<String>>, paths: &[PathBuf]) -> Vec<String> {
+    let mut projects = HashSet::new();
+    for path in paths {
+        for (dir, ids) in watched {
+            let Ok(relative) = path.strip_prefix(dir) else {
+                continue;
+            };
+            let is_ref = relative.starts_with("refs")
+                || relative == Path::new("HEAD")
+                || relative == Path::new("packed-refs");
+            if is_ref {
+                projects.extend(ids.iter().cloned());
+            }
+        }
+    }
+    projects.into_iter().collect()
+}
+
+async fn check_in_background(projects: Vec<String>) {
+    let result = tokio::task::spawn_blocking(move || {
+        for project_id in &projects {
+            if let Err(e) = check_project(project_id) {
+                tracing::warn!(project_id = %project_id, error = %e, "failed to check pooled environments");
+            }
+        }
+    })
+    .await;
+    if let Err(e) = result {
+        tracing::error!(error = %e, "failed to check pooled environments");
+    }
+}
+
+/// Mark each of the project's pooled environments outdated or current. One
+/// that can't be checked is logged and the rest are still checked.
+fn check_project(project_id: &str) -> anyhow::Result<()> {
+    let pooled = db::list_environments()?
+        .into_iter()
+        .filter(|env| env.project_id == project_id && env.status == "pool");
+    for env in pooled {
+        if let Err(e) = check_environment(&env) {
+            tracing::warn!(env_id = %env.id, error = %e, "failed to check pooled environment");
+        }
+    }
+    Ok(())
+}
+
+/// Mark a pooled environment outdated or current, as its checkout is now.
+/// Does nothing unless the daemon is watching projects.
+pub fn check_environment(env: &db::Environment) -> anyhow::Result<()> {
+    let config = crate::config::load()?;
+    if !config.watches_projects() {
+        return Ok(());
+    }
+    let project = db::get_project(&env.project_id)?;
+    let outdated = crate::environment::worktree_outdated(
+        Path::new(&project.path),
+        &env.metadata,
+        &config.provider_process_env(&env.provider),
+    )?;
+    if db::set_environment_outdated(&env.id, outdated.is_some())? {
+        match outdated {
+            Some(reason) => {
+                tracing::info!(env_id = %env.id, reason = %reason, "pooled environment outdated")
+            }
+            None => tracing::info!(env_id = %env.id, "pooled environment current again"),
+        }
+    }
+    Ok(())
+}
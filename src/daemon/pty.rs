@@ -0,0 +1,110 @@
+use std::fs::File;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+/// A pseudo-terminal for a task runner. The runner's stdout and stderr are
+/// `follower`; the daemon reads what it prints from `leader`.
+pub struct Pty {
+    pub leader: File,
+    pub follower: File,
+}
+
+/// Open a pty reporting a `columns` by `rows` window.
+///
+/// Output processing is turned off so the log gets the runner's newlines as
+/// written instead of `\r\n`.
+pub fn open(columns: u16, rows: u16) -> std::io::Result<Pty> {
+    let mut leader: libc::c_int = -1;
+    let mut follower: libc::c_int = -1;
+    let mut size = libc::winsize {
+        ws_row: rows,
+        ws_col: columns,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: both fd out-pointers and the winsize are valid; a null name and
+    // termios ask for none and the defaults.
+    let rc = unsafe {
+        libc::openpty(
+            &mut leader,
+            &mut follower,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            &raw mut size,
+        )
+    };
+    if rc == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: openpty succeeded, so both fds are open and owned by nobody else.
+    let (leader, follower) =
+        unsafe { (OwnedFd::from_raw_fd(leader), OwnedFd::from_raw_fd(follower)) };
+
+    // Keep the runner from inheriting the leader, or its own copy would hold
+    // the pty open after it exits. The follower is dup'd onto stdout and
+    // stderr, which clears the flag on those.
+    for fd in [&leader, &follower] {
+        // SAFETY: fd is open for the duration of the call.
+        if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    // SAFETY: termios is a plain C struct for which all-zero bytes are valid.
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    // SAFETY: follower is open and termios is a valid out-pointer.
+    if unsafe { libc::tcgetattr(follower.as_raw_fd(), &mut termios) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    termios.c_oflag &= !libc::OPOST;
+    // SAFETY: follower is open and termios was filled in by tcgetattr.
+    if unsafe { libc::tcsetattr(follower.as_raw_fd(), libc::TCSANOW, &termios) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(Pty {
+        leader: File::from(leader),
+        follower: File::from(follower),
+    })
+}
+
+/// Whether `err`, from reading a pty's leader, means the runner's side has
+/// closed. Linux reports that as EIO rather than end of file.
+pub fn is_closed(err: &std::io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EIO)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn runner_sees_a_terminal_of_the_requested_size() {
+        let pty = open(132, 43).unwrap();
+        let stdin = pty.follower.try_clone().unwrap();
+        let stdout = pty.follower.try_clone().unwrap();
+        let mut command = std::process::Command::new("sh");
+        command
+            .args(["-c", "test -t 1 && echo tty; stty size"])
+            .stdin(stdin)
+            .stdout(stdout)
+            .stderr(pty.follower);
+        let mut child = command.spawn().unwrap();
+        drop(command);
+        child.wait().unwrap();
+
+        let mut leader = pty.leader;
+        let mut output = Vec::new();
+        let mut buf = [0; 1024];
+        loop {
+            match leader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => output.extend_from_slice(&buf[..n]),
+                Err(e) if is_closed(&e) => break,
+                Err(e) => panic!("read failed: {e}"),
+            }
+        }
+        assert_eq!(String::from_utf8_lossy(&output), "tty\n43 132\n");
+    }
+}
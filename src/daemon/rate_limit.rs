@@ -0,0 +1,97 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::BuildHasher;
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimit;
+
+/// Sliding-window limiter over provider operations, keyed by provider name.
+#[derive(Default)]
+pub struct RateLimiter {
+    started: HashMap<String, VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+    /// Record an operation for `provider` if `limit` allows one at `now`.
+    /// Otherwise return how long until the oldest operation in the window
+    /// ages out.
+    pub fn try_acquire(
+        &mut self,
+        provider: &str,
+        limit: &RateLimit,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let started = self.started.entry(provider.to_string()).or_default();
+        while started
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= limit.per)
+        {
+            started.pop_front();
+        }
+
+        if started.len() < limit.max as usize {
+            started.push_back(now);
+            return Ok(());
+        }
+        let oldest = started.front().copied().unwrap_or(now);
+        Err(limit.per - now.duration_since(oldest))
+    }
+
+    /// Drop the window kept for `provider`.
+    pub fn forget(&mut self, provider: &str) {
+        self.started.remove(provider);
+    }
+}
+
+/// A random delay in `[0, max]`, so jobs deferred together do not all
+/// become due at the same moment.
+pub fn jitter(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    let random = RandomState::new().hash_one(Instant::now());
+    Duration::from_millis(random % (max.as_millis() as u64 + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(max: u32, per_secs: u64) -> RateLimit {
+        RateLimit {
+            max,
+            per: Duration::from_secs(per_secs),
+            jitter: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn allows_up_to_max_per_window() {
+        let mut limiter = RateLimiter::default();
+        let limit = limit(2, 60);
+        let start = Instant::now();
+
+        assert!(limiter.try_acquire("git", &limit, start).is_ok());
+        assert!(limiter.try_acquire("git", &limit, start).is_ok());
+        assert_eq!(
+            limiter.try_acquire("git", &limit, start + Duration::from_secs(20)),
+            Err(Duration::from_secs(40))
+        );
+        // Other providers have their own window.
+        assert!(limiter.try_acquire("docker", &limit, start).is_ok());
+        // Once the window passes, slots free up again.
+        assert!(
+            limiter
+                .try_acquire("git", &limit, start + Duration::from_secs(60))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn jitter_stays_within_bound() {
+        assert_eq!(jitter(Duration::ZERO), Duration::ZERO);
+        for _ in 0..100 {
+            assert!(jitter(Duration::from_millis(500)) <= Duration::from_millis(500));
+        }
+    }
+}
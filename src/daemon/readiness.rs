@@ -0,0 +1,27 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static MIGRATED: AtomicBool = AtomicBool::new(false);
+static ACCEPTING_JOBS: AtomicBool = AtomicBool::new(false);
+
+/// The database is initialized and migrations have run.
+pub fn mark_migrated() {
+    MIGRATED.store(true, Ordering::SeqCst);
+}
+
+/// Whether the job processor is claiming work.
+pub fn set_accepting_jobs(accepting: bool) {
+    ACCEPTING_JOBS.store(accepting, Ordering::SeqCst);
+}
+
+/// What the daemon is still waiting on before it can serve requests. Empty
+/// once it is ready.
+pub fn pending() -> Vec<&'static str> {
+    let mut pending = Vec::new();
+    if !MIGRATED.load(Ordering::SeqCst) {
+        pending.push("migrations");
+    }
+    if !ACCEPTING_JOBS.load(Ordering::SeqCst) {
+        pending.push("job processor");
+    }
+    pending
+}
@@ -0,0 +1,243 @@
+use std::collections::HashSet;
+use std::process::Command;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::{CancelSignal, DEFAULT_CANCEL_GRACE};
+use crate::db;
+
+/// Identify a process by its start time so a recycled PID is not mistaken
+/// for the process we spawned.
+pub fn process_start_time(pid: u32) -> Option<String> {
+    let output = Command::new("ps")
+        .args(["-o", "lstart=", "-p", &pid.to_string()])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let start = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!start.is_empty()).then_some(start)
+}
+
+fn is_same_process(process: &db::TaskProcess) -> bool {
+    let Ok(pid) = u32::try_from(process.pid) else {
+        return false;
+    };
+    match (&process.process_start, process_start_time(pid)) {
+        (Some(recorded), Some(current)) => *recorded == current,
+        // Without a recorded start time there is no telling a recycled PID
+        // from ours, and signalling a stranger's process group is worse
+        // than leaving an orphan running.
+        _ => false,
+    }
+}
+
+fn group_alive(pgid: libc::pid_t) -> bool {
+    // SAFETY: signal 0 only checks for existence and permission.
+    unsafe { libc::kill(-pgid, 0) == 0 }
+}
+
+/// Send `signal` to a task's process group, run `then`, and escalate to
+/// SIGKILL if the group has not exited within `grace`. Without a signal, the
+/// group only gets the grace period.
+fn terminate_group(pid: i64, signal: CancelSignal, grace: Duration, then: impl FnOnce()) {
+    let pgid = pid as libc::pid_t;
+    if pgid <= 1 {
+        return;
+    }
+
+    if let Some(signal) = signal.number() {
+        // SAFETY: task runners are spawned as their own process group leaders.
+        unsafe { libc::kill(-pgid, signal) };
+    }
+    then();
+
+    let deadline = Instant::now() + grace;
+    while Instant::now() < deadline {
+        if !group_alive(pgid) {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    tracing::warn!(
+        pid,
+        ?signal,
+        "process group outlived its cancel grace; sending SIGKILL"
+    );
+    // SAFETY: as above.
+    unsafe { libc::kill(-pgid, libc::SIGKILL) };
+}
+
+/// How the task's provider wants its runner cancelled, or the defaults when
+/// the task or provider is gone or the grace can't be read.
+fn cancel_policy(task: Option<&db::Task>) -> (CancelSignal, Duration) {
+    let cancel = task.and_then(|task| {
+        let config = crate::config::load().ok()?;
+        Some(config.get_task_provider(&task.provider).ok()?.cancel())
+    });
+    match cancel {
+        Some(cancel) => (
+            cancel.signal,
+            cancel.grace().unwrap_or(DEFAULT_CANCEL_GRACE),
+        ),
+        None => (CancelSignal::default(), DEFAULT_CANCEL_GRACE),
+    }
+}
+
+/// Create the task's `WORK_CANCEL_FILE`, for runners that poll for it.
+fn write_cancel_file(task_id: &str) {
+    let written = crate::paths::task_cancel_path(task_id).and_then(|path| {
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        Ok(std::fs::write(
+            &path,
+            format!("{}\n", chrono::Utc::now().to_rfc3339()),
+        )?)
+    });
+    if let Err(e) = written {
+        tracing::warn!(task_id, error = %e, "failed to write cancel file");
+    }
+}
+
+/// Tasks whose runner was stopped by [`terminate_task`] and has not yet been
+/// reaped by the job that started it.
+static CANCELLED: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Default::default);
+
+/// Whether the daemon cancelled this task's runner. Clears the mark.
+pub fn take_cancelled(task_id: &str) -> bool {
+    CANCELLED.lock().unwrap().remove(task_id)
+}
+
+/// Terminate the recorded process for a task, if it is still running.
+pub fn terminate_task(task_id: &str) -> anyhow::Result<()> {
+    let processes = db::list_task_processes()?;
+    let Some(process) = processes.into_iter().find(|p| p.task_id == task_id) else {
+        return Ok(());
+    };
+    if is_same_process(&process) {
+        let (signal, grace) = cancel_policy(db::get_task(task_id).ok().as_ref());
+        tracing::info!(
+            task_id,
+            pid = process.pid,
+            ?signal,
+            ?grace,
+            "cancelling task process"
+        );
+        CANCELLED.lock().unwrap().insert(task_id.to_string());
+        super::task_log::append(
+            task_id,
+            &chrono::Utc::now().to_rfc3339(),
+            &format!(
+                "cancelling: signal {}, then SIGKILL after {}s if still running",
+                signal.name(),
+                grace.as_secs()
+            ),
+        );
+        terminate_group(process.pid, signal, grace, || write_cancel_file(task_id));
+    }
+    db::delete_task_process(task_id)
+}
+
+/// Find task processes that no longer belong to a running task, or that
+/// were started by a previous daemon, and terminate them. Each gets its own
+/// thread, so one runner's grace period doesn't hold up the rest.
+pub fn reap_orphans() -> anyhow::Result<()> {
+    let daemon_pid = i64::from(std::process::id());
+
+    let mut orphans = Vec::new();
+    for process in db::list_task_processes()? {
+        let task = db::get_task(&process.task_id).ok();
+        let from_previous_daemon = process.daemon_pid != daemon_pid;
+        let reason = match &task {
+            None => "task was removed",
+            Some(task) if task.status != "started" => "task is no longer running",
+            Some(_) if from_previous_daemon => "daemon restarted while task was running",
+            Some(_) => continue,
+        };
+        orphans.push((process, task, from_previous_daemon, reason));
+    }
+
+    std::thread::scope(|scope| {
+        let reaps: Vec<_> = orphans
+            .iter()
+            .map(|(process, task, from_previous_daemon, reason)| {
+                scope.spawn(move || reap(process, task.as_ref(), *from_previous_daemon, reason))
+            })
+            .collect();
+        reaps
+            .into_iter()
+            .try_for_each(|reap| reap.join().expect("reaper thread panicked"))
+    })
+}
+
+fn reap(
+    process: &db::TaskProcess,
+    task: Option<&db::Task>,
+    from_previous_daemon: bool,
+    reason: &str,
+) -> anyhow::Result<()> {
+    if is_same_process(process) {
+        tracing::warn!(task_id = %process.task_id, pid = process.pid, reason, "terminating orphaned task process");
+        let (signal, grace) = cancel_policy(task);
+        terminate_group(process.pid, signal, grace, || {
+            write_cancel_file(&process.task_id)
+        });
+        super::task_log::append(
+            &process.task_id,
+            &chrono::Utc::now().to_rfc3339(),
+            &format!(
+                "terminated orphaned process group {} ({reason})",
+                process.pid
+            ),
+        );
+    }
+
+    if from_previous_daemon && task.is_some_and(|t| t.status == "started") {
+        db::update_task_status(&process.task_id, "interrupted")?;
+    }
+    db::delete_task_process(&process.task_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::CommandExt;
+
+    use super::*;
+
+    #[test]
+    fn terminates_process_group() {
+        let mut child = Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        assert!(process_start_time(pid).is_some());
+        // Reap concurrently, as the runner thread would, so no zombie lingers.
+        let waiter = std::thread::spawn(move || child.wait().unwrap());
+
+        terminate_group(
+            i64::from(pid),
+            CancelSignal::Term,
+            DEFAULT_CANCEL_GRACE,
+            || {},
+        );
+
+        assert!(!waiter.join().unwrap().success());
+    }
+
+    #[test]
+    fn unknown_start_time_is_not_the_same_process() {
+        let pid = std::process::id();
+        let process = |process_start: Option<String>| db::TaskProcess {
+            task_id: "task".to_string(),
+            pid: i64::from(pid),
+            process_start,
+            daemon_pid: i64::from(pid),
+        };
+        assert!(is_same_process(&process(process_start_time(pid))));
+        assert!(!is_same_process(&process(None)));
+        assert!(!is_same_process(&process(Some("then".to_string()))));
+    }
+}
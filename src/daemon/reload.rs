@@ -0,0 +1,83 @@
+use std::sync::OnceLock;
+
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::{Registry, reload};
+
+use crate::config::ReloadSummary;
+use crate::error::{Error, ErrorCode};
+
+static LOG_LEVEL: OnceLock<LogLevel> = OnceLock::new();
+
+struct LogLevel {
+    handle: reload::Handle<LevelFilter, Registry>,
+    /// `--debug` was passed, which config cannot turn off.
+    cli_debug: bool,
+}
+
+/// Let reloads change the daemon's log level as `[daemon] debug` changes.
+pub fn set_log_level_handle(handle: reload::Handle<LevelFilter, Registry>, cli_debug: bool) {
+    let _ = LOG_LEVEL.set(LogLevel { handle, cli_debug });
+}
+
+/// Accept the config the daemon started with.
+pub fn init() {
+    match crate::config::load_table() {
+        Ok(table) => crate::config::accept(table),
+        Err(e) => tracing::warn!(error = %e, "failed to read config at startup"),
+    }
+}
+
+/// Re-read config.toml, check it, and accept it as the config the daemon
+/// runs with, applying the settings it holds in memory.
+///
+/// A config with problems is rejected and leaves the last accepted one in
+/// effect.
+pub fn reload() -> anyhow::Result<ReloadSummary> {
+    let invalid = |message: String| Error::new(ErrorCode::InvalidConfig, message);
+    let table =
+        crate::config::load_table().map_err(|e| invalid(format!("failed to parse config: {e}")))?;
+    let config: crate::config::Config = toml::Value::Table(table.clone())
+        .try_into()
+        .map_err(|e| invalid(format!("failed to parse config: {e}")))?;
+
+    let problems = config.problems();
+    if !problems.is_empty() {
+        for problem in &problems {
+            tracing::warn!(problem = %problem, "config reload rejected");
+        }
+        return Err(
+            invalid(format!("config has problems: {}", problems.join("; ")))
+                .with_details(serde_json::json!({ "problems": problems }))
+                .into(),
+        );
+    }
+
+    let changes = crate::config::diff(&crate::config::accepted().unwrap_or_default(), &table);
+    for change in &changes {
+        tracing::info!(
+            setting = %change.setting,
+            old = change.old.as_deref().unwrap_or("(unset)"),
+            new = change.new.as_deref().unwrap_or("(unset)"),
+            "config setting changed"
+        );
+    }
+
+    if let Some(level) = LOG_LEVEL.get() {
+        let debug = level.cli_debug || config.daemon.as_ref().is_some_and(|d| d.debug);
+        let filter = if debug {
+            LevelFilter::DEBUG
+        } else {
+            LevelFilter::INFO
+        };
+        if let Err(e) = level.handle.modify(|current| *current = filter) {
+            tracing::warn!(error = %e, "failed to apply log level");
+        }
+    }
+
+    super::access::set_limit(config.request_rate_limit()?);
+    crate::redact::set_configured(config.redactor()?);
+
+    crate::config::accept(table);
+    tracing::info!(changed = changes.len(), "config reloaded");
+    Ok(ReloadSummary { changes })
+}
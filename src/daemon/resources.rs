@@ -0,0 +1,109 @@
+use std::path::Path;
+use std::process::Command;
+
+use crate::config::Config;
+
+/// Return a reason string if the host is too constrained to prepare new
+/// environments, based on the `[daemon]` resource thresholds.
+pub fn host_constraint(config: &Config) -> Option<String> {
+    let daemon = config.daemon.as_ref()?;
+
+    if let Some(min_free_gb) = daemon.min_free_disk_gb {
+        match crate::paths::data_dir()
+            .map_err(|e| e.to_string())
+            .and_then(|dir| free_disk_gb(&dir))
+        {
+            Ok(free_gb) if free_gb < min_free_gb => {
+                return Some(format!(
+                    "free disk {free_gb:.1}GB is below min-free-disk-gb {min_free_gb}"
+                ));
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!(error = %e, "failed to check free disk space"),
+        }
+    }
+
+    if let Some(max_load) = daemon.max_load_average {
+        match load_average() {
+            Ok(load) if load > max_load => {
+                return Some(format!(
+                    "load average {load:.2} is above max-load-average {max_load}"
+                ));
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!(error = %e, "failed to check load average"),
+        }
+    }
+
+    None
+}
+
+fn free_disk_gb(path: &Path) -> Result<f64, String> {
+    let output = Command::new("df")
+        .arg("-Pk")
+        .arg(path)
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("df exited with {}", output.status));
+    }
+    parse_df_available_kb(&String::from_utf8_lossy(&output.stdout))
+        .map(|kb| kb as f64 / (1024.0 * 1024.0))
+        .ok_or_else(|| "could not parse df output".to_string())
+}
+
+fn parse_df_available_kb(output: &str) -> Option<u64> {
+    // POSIX format: Filesystem 1024-blocks Used Available Capacity Mounted-on
+    output
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse()
+        .ok()
+}
+
+fn load_average() -> Result<f64, String> {
+    if let Ok(contents) = std::fs::read_to_string("/proc/loadavg") {
+        return parse_load_average(&contents).ok_or_else(|| "could not parse /proc/loadavg".into());
+    }
+
+    let output = Command::new("sysctl")
+        .args(["-n", "vm.loadavg"])
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("sysctl exited with {}", output.status));
+    }
+    parse_load_average(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| "could not parse vm.loadavg".to_string())
+}
+
+fn parse_load_average(output: &str) -> Option<f64> {
+    // Linux: "0.52 0.58 0.59 1/467 12345"; macOS: "{ 1.52 1.71 1.80 }"
+    output
+        .split_whitespace()
+        .find(|part| *part != "{")?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_available_kb_from_df() {
+        let output = "Filesystem 1024-blocks Used Available Capacity Mounted on\n/dev/disk3s5 971350180 605004452 348120948 64% /System/Volumes/Data\n";
+        assert_eq!(parse_df_available_kb(output), Some(348120948));
+    }
+
+    #[test]
+    fn parses_linux_and_macos_load_average() {
+        assert_eq!(
+            parse_load_average("0.52 0.58 0.59 1/467 12345\n"),
+            Some(0.52)
+        );
+        assert_eq!(parse_load_average("{ 1.52 1.71 1.80 }\n"), Some(1.52));
+    }
+}
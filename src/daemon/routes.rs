@@ -1,25 +1,167 @@
 use axum::Json;
 use axum::body::Body;
-use axum::extract::{Path, Query};
-use axum::http::StatusCode;
+use axum::extract::{FromRequestParts, Path, Query};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::IntoResponse;
 use serde_json::{Value, json};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 
-pub async fn events() -> impl IntoResponse {
+use crate::config::ProviderKind;
+use crate::error::{Error, ErrorCode};
+
+/// Respond with the code, message, and details of the coded [`Error`] in
+/// `err`, or a 500 `internal` error if it has none.
+fn error_response(err: &anyhow::Error) -> axum::response::Response {
+    let error = Error::from_anyhow(err);
+    let status =
+        StatusCode::from_u16(error.code.http_status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    (status, Json(json!(error))).into_response()
+}
+
+/// The `{id}` of a task route, resolved to the task's full ID from an alias
+/// or unambiguous prefix.
+pub struct TaskRef(pub String);
+
+/// The `{id}` of an environment route, resolved like [`TaskRef`].
+pub struct EnvironmentRef(pub String);
+
+async fn resolve_path<S: Send + Sync>(
+    parts: &mut Parts,
+    state: &S,
+    resolve: fn(&str) -> anyhow::Result<String>,
+) -> Result<String, axum::response::Response> {
+    let Path(reference) = Path::<String>::from_request_parts(parts, state)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    resolve(&reference).map_err(|e| error_response(&e))
+}
+
+/// Where a request came from: the client named by the header a client sends
+/// when built with [`DaemonClient::origin`](crate::client::DaemonClient::origin),
+/// or `api` without it, and the rest from the connection's
+/// [`Peer`](super::access::Peer).
+pub struct RequestOrigin(pub crate::db::Origin);
+
+impl<S: Send + Sync> FromRequestParts<S> for RequestOrigin {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let client = parts
+            .headers
+            .get(crate::client::ORIGIN_HEADER)
+            .and_then(|value| String::from_utf8(value.as_bytes().to_vec()).ok())
+            .filter(|value| !value.is_empty());
+        let peer = parts
+            .extensions
+            .get::<axum::extract::ConnectInfo<super::access::Peer>>()
+            .map(|info| info.0)
+            .unwrap_or_default();
+        Ok(Self(
+            peer.origin(client.unwrap_or_else(|| "api".to_string())),
+        ))
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for TaskRef {
+    type Rejection = axum::response::Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        resolve_path(parts, state, crate::db::resolve_task_id)
+            .await
+            .map(Self)
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for EnvironmentRef {
+    type Rejection = axum::response::Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        resolve_path(parts, state, crate::db::resolve_environment_id)
+            .await
+            .map(Self)
+    }
+}
+
+/// Query for `GET /events`. `since` is an alternative to the
+/// `Last-Event-ID` header for clients that can't set headers.
+#[derive(Default, serde::Deserialize)]
+pub struct EventsQuery {
+    pub since: Option<i64>,
+}
+
+/// Stream events as server-sent events, each with its `seq` as the event ID.
+/// A client that reconnects with `Last-Event-ID` (or `?since=`) first gets
+/// a `resume` event and then every event it missed. Without a cursor, or
+/// when the missed events have been pruned, the stream opens with a `reset`
+/// event instead, and the client should refetch whatever it shows.
+pub async fn events(headers: HeaderMap, Query(query): Query<EventsQuery>) -> impl IntoResponse {
+    let since = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<i64>().ok())
+        .or(query.since);
+
+    // Subscribe before reading the backlog so nothing falls between them.
     let mut rx = super::events::subscribe();
+    let replay = tokio::task::spawn_blocking(move || {
+        let latest = crate::db::latest_event_seq()?;
+        match since {
+            Some(since) if crate::db::can_replay_events_since(since)? => {
+                let query = crate::db::EventQuery {
+                    since: Some(since),
+                    ..Default::default()
+                };
+                anyhow::Ok(Replay::Resume(since, crate::db::list_events(&query)?))
+            }
+            _ => Ok(Replay::Reset(latest)),
+        }
+    })
+    .await;
+    let replay = match replay {
+        Ok(Ok(replay)) => replay,
+        Ok(Err(e)) => return error_response(&e),
+        Err(e) => return error_response(&e.into()),
+    };
     let (tx, mpsc_rx) = mpsc::channel::<Result<axum::body::Bytes, std::io::Error>>(64);
 
     tokio::spawn(async move {
+        let mut last_seq = match replay {
+            Replay::Reset(latest) => {
+                let frame = format!("event: reset\nid: {latest}\ndata: {{}}\n\n");
+                if tx.send(Ok(frame.into())).await.is_err() {
+                    return;
+                }
+                latest
+            }
+            Replay::Resume(since, backlog) => {
+                let frame = format!("event: resume\nid: {since}\ndata: {{}}\n\n");
+                if tx.send(Ok(frame.into())).await.is_err() {
+                    return;
+                }
+                let mut last_seq = since;
+                for event in backlog {
+                    last_seq = event.seq;
+                    if tx.send(Ok(sse_event(&event))).await.is_err() {
+                        return;
+                    }
+                }
+                last_seq
+            }
+        };
         loop {
             tokio::select! {
                 result = rx.recv() => {
-                    if result.is_err() {
+                    // A lagging stream ends; the client reconnects and replays.
+                    let Ok(event) = result else {
                         break;
+                    };
+                    if event.seq <= last_seq {
+                        continue;
                     }
-                    let chunk = axum::body::Bytes::from("data: update\n\n");
-                    if tx.send(Ok(chunk)).await.is_err() {
+                    last_seq = event.seq;
+                    if tx.send(Ok(sse_event(&event))).await.is_err() {
                         break;
                     }
                 }
@@ -41,22 +183,69 @@ pub async fn events() -> impl IntoResponse {
         ],
         body,
     )
+        .into_response()
+}
+
+/// How an `/events` stream starts.
+enum Replay {
+    /// The client has no cursor, or one whose events are gone: it should
+    /// refetch everything. Live events follow from this sequence number.
+    Reset(i64),
+    /// The events after the client's cursor, which it missed.
+    Resume(i64, Vec<crate::db::Event>),
+}
+
+fn sse_event(event: &crate::db::Event) -> axum::body::Bytes {
+    axum::body::Bytes::from(format!("id: {}\ndata: {}\n\n", event.seq, json!(event)))
+}
+
+pub async fn list_events(Query(query): Query<crate::db::EventQuery>) -> impl IntoResponse {
+    match crate::db::list_events(&query) {
+        Ok(events) => (StatusCode::OK, Json(json!(events))).into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, "failed to list events");
+            error_response(&e)
+        }
+    }
+}
+
+/// A dashboard page listing tasks and environments and following their logs.
+/// It is compiled in and reads everything else from this API. Unless
+/// `[daemon] dashboard` is on, `/ui` is a 404 like any unknown path.
+pub async fn dashboard() -> impl IntoResponse {
+    if !crate::config::load().is_ok_and(|c| c.serves_dashboard()) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    axum::response::Html(include_str!("../../assets/dashboard.html")).into_response()
 }
 
 pub async fn health() -> Json<Value> {
-    Json(json!({"status": "ok"}))
+    Json(json!({"status": "ok", "version": env!("CARGO_PKG_VERSION")}))
+}
+
+pub async fn ready() -> impl IntoResponse {
+    let pending = super::readiness::pending();
+    if pending.is_empty() {
+        return (StatusCode::OK, Json(json!({"status": "ready"}))).into_response();
+    }
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({
+            "status": "starting",
+            "code": ErrorCode::NotReady,
+            "message": format!("daemon is not ready (waiting for {})", pending.join(", ")),
+            "details": {"waiting_for": pending},
+        })),
+    )
+        .into_response()
 }
 
 pub async fn list_projects() -> impl IntoResponse {
-    match crate::db::list_projects() {
-        Ok(projects) => (StatusCode::OK, Json(json!(projects))).into_response(),
+    match super::state::projects() {
+        Ok(projects) => (StatusCode::OK, Json(projects.as_slice())).into_response(),
         Err(e) => {
             tracing::error!(error = %e, "failed to list projects");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"error": e.to_string()})),
-            )
-                .into_response()
+            error_response(&e)
         }
     }
 }
@@ -65,68 +254,222 @@ pub async fn list_projects() -> impl IntoResponse {
 pub struct CreateProjectRequest {
     pub name: String,
     pub path: String,
+    /// Allow a path inside or around a registered project's.
+    #[serde(default)]
+    pub nested: bool,
 }
 
 pub async fn create_project(Json(body): Json<CreateProjectRequest>) -> impl IntoResponse {
-    match crate::db::create_project(&body.name, &std::path::PathBuf::from(&body.path)) {
+    match crate::db::create_project(
+        &body.name,
+        &std::path::PathBuf::from(&body.path),
+        body.nested,
+    ) {
         Ok(()) => {
             tracing::debug!(name = %body.name, path = %body.path, "project created");
-            super::events::notify();
             (
                 StatusCode::CREATED,
                 Json(json!({"name": body.name, "path": body.path})),
             )
                 .into_response()
         }
-        Err(e) => {
-            let msg = e.to_string();
-            let status = if msg.contains("UNIQUE constraint") {
-                StatusCode::CONFLICT
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
-            };
-            (status, Json(json!({"error": msg}))).into_response()
-        }
+        Err(e) => error_response(&e),
     }
 }
 
-pub async fn delete_project(Path(name): Path<String>) -> impl IntoResponse {
-    match crate::db::delete_project(&name) {
-        Ok(()) => {
-            tracing::debug!(name = %name, "project removed");
-            super::events::notify();
-            StatusCode::NO_CONTENT.into_response()
+#[derive(serde::Deserialize)]
+pub struct AddProjectAliasRequest {
+    pub alias: String,
+}
+
+pub async fn add_project_alias(
+    Path(name): Path<String>,
+    Json(body): Json<AddProjectAliasRequest>,
+) -> impl IntoResponse {
+    match crate::db::add_project_alias(&name, &body.alias) {
+        Ok(project) => {
+            tracing::debug!(name = %name, alias = %body.alias, "project alias added");
+            (StatusCode::OK, Json(project)).into_response()
         }
-        Err(e) => {
-            let msg = e.to_string();
-            let status = if msg.contains("project not found") {
-                StatusCode::NOT_FOUND
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(Default, serde::Deserialize)]
+pub struct RemoveProjectQuery {
+    #[serde(default)]
+    pub force: bool,
+}
+
+pub async fn delete_project(
+    Path(name): Path<String>,
+    Query(query): Query<RemoveProjectQuery>,
+) -> impl IntoResponse {
+    match crate::db::delete_project(&name, query.force) {
+        Ok(removal) => {
+            tracing::debug!(
+                name = %name,
+                environments = removal.environment_ids.len(),
+                tasks = removal.task_ids.len(),
+                removed = removal.removed,
+                "project removed"
+            );
+            let status = if removal.removed {
+                StatusCode::OK
             } else {
-                StatusCode::INTERNAL_SERVER_ERROR
+                StatusCode::ACCEPTED
             };
-            (status, Json(json!({"error": msg}))).into_response()
+            (status, Json(json!(removal))).into_response()
         }
+        Err(e) => error_response(&e),
     }
 }
 
 pub async fn reset_database() -> impl IntoResponse {
-    match crate::db::reset() {
+    let result = crate::db::reset();
+    super::state::clear();
+    match result {
         Ok(()) => {
             tracing::debug!("database reset");
-            super::events::notify();
             StatusCode::NO_CONTENT.into_response()
         }
         Err(e) => {
             tracing::error!(error = %e, "failed to reset database");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"error": e.to_string()})),
+            error_response(&e)
+        }
+    }
+}
+
+#[derive(Default, serde::Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub tasks: bool,
+}
+
+pub async fn export_state(Query(query): Query<ExportQuery>) -> impl IntoResponse {
+    match crate::db::export_state(query.tasks).and_then(redact_export) {
+        Ok(state) => (StatusCode::OK, Json(json!(state))).into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, "failed to export state");
+            error_response(&e)
+        }
+    }
+}
+
+/// `state` with the configured patterns taken out of what its tasks and
+/// groups say.
+fn redact_export(mut state: crate::db::StateExport) -> anyhow::Result<crate::db::StateExport> {
+    let Some(redactor) = crate::redact::configured()? else {
+        return Ok(state);
+    };
+    for group in &mut state.groups {
+        redactor.redact_string(&mut group.description);
+    }
+    for task in &mut state.tasks {
+        redactor.redact_string(&mut task.description);
+        if let Some(summary) = &mut task.result_summary {
+            redactor.redact_string(summary);
+        }
+        if let Some(data) = &mut task.result_data {
+            redactor.redact_json(data);
+        }
+        for note in &mut task.notes {
+            redactor.redact_string(&mut note.text);
+        }
+    }
+    Ok(state)
+}
+
+#[derive(serde::Deserialize)]
+pub struct ImportStateRequest {
+    pub state: crate::db::StateExport,
+    #[serde(default)]
+    pub on_conflict: crate::db::ImportConflict,
+}
+
+pub async fn import_state(Json(body): Json<ImportStateRequest>) -> impl IntoResponse {
+    match crate::db::import_state(&body.state, body.on_conflict) {
+        Ok(summary) => {
+            tracing::debug!(?summary, "state imported");
+            (StatusCode::OK, Json(json!(summary))).into_response()
+        }
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct RemapPathsRequest {
+    pub maps: Vec<crate::db::PathMap>,
+}
+
+pub async fn remap_paths(Json(body): Json<RemapPathsRequest>) -> impl IntoResponse {
+    if body.maps.is_empty() {
+        return error_response(
+            &Error::new(
+                ErrorCode::InvalidRequest,
+                "at least one path map is required",
             )
-                .into_response()
+            .into(),
+        );
+    }
+    match crate::db::remap_paths(&body.maps) {
+        Ok(summary) => {
+            tracing::debug!(?summary, "paths remapped");
+            (StatusCode::OK, Json(json!(summary))).into_response()
+        }
+        Err(e) => error_response(&e),
+    }
+}
+
+pub async fn check_integrity() -> impl IntoResponse {
+    match tokio::task::spawn_blocking(crate::db::check_integrity).await {
+        Ok(Ok(problems)) => (StatusCode::OK, Json(json!(problems))).into_response(),
+        Ok(Err(e)) => error_response(&e),
+        Err(e) => error_response(&e.into()),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct RepairRequest {
+    /// Keys of the problems to fix, from `GET /db/check`.
+    pub keys: Vec<String>,
+}
+
+pub async fn repair_integrity(Json(body): Json<RepairRequest>) -> impl IntoResponse {
+    let result = tokio::task::spawn_blocking(move || crate::db::repair_integrity(&body.keys)).await;
+    match result {
+        Ok(Ok(summary)) => {
+            tracing::info!(fixed = ?summary.fixed, skipped = ?summary.skipped, "database repaired");
+            (StatusCode::OK, Json(json!(summary))).into_response()
         }
+        Ok(Err(e)) => error_response(&e),
+        Err(e) => error_response(&e.into()),
+    }
+}
+
+pub async fn maintain_database() -> impl IntoResponse {
+    match tokio::task::spawn_blocking(super::janitor::maintain_database).await {
+        Ok(Ok(summary)) => (StatusCode::OK, Json(json!(summary))).into_response(),
+        Ok(Err(e)) => error_response(&e),
+        Err(e) => error_response(&e.into()),
     }
 }
 
+pub async fn reload_config() -> impl IntoResponse {
+    let result = tokio::task::spawn_blocking(super::reload::reload)
+        .await
+        .unwrap_or_else(|e| Err(anyhow::anyhow!("reload task failed: {e}")));
+
+    match result {
+        Ok(summary) => (StatusCode::OK, Json(json!(summary))).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+pub async fn list_peers() -> impl IntoResponse {
+    (StatusCode::OK, Json(super::access::peers())).into_response()
+}
+
 #[derive(serde::Deserialize)]
 pub struct PrepareEnvironmentRequest {
     pub project_id: String,
@@ -135,95 +478,279 @@ pub struct PrepareEnvironmentRequest {
     pub claim_after_prepare: bool,
 }
 
-pub async fn prepare_environment(Json(body): Json<PrepareEnvironmentRequest>) -> impl IntoResponse {
-    let result = crate::db::stage_prepare_environment(
-        &body.project_id,
-        &body.provider,
-        body.claim_after_prepare,
-    );
+pub async fn prepare_environment(
+    RequestOrigin(origin): RequestOrigin,
+    Json(body): Json<PrepareEnvironmentRequest>,
+) -> impl IntoResponse {
+    let result = check_allowed(&body.project_id, ProviderKind::Environment, &body.provider)
+        .and_then(|()| {
+            // An in-place environment is the project checkout, which only a
+            // task confirmed with `in_place` gets.
+            if body.provider == crate::config::IN_PLACE_PROVIDER {
+                return Err(Error::new(
+                    ErrorCode::InvalidRequest,
+                    "the inplace environment provider only makes environments for tasks created with in_place (--in-place)",
+                )
+                .into());
+            }
+            crate::db::stage_prepare_environment(
+                &body.project_id,
+                &body.provider,
+                body.claim_after_prepare,
+                Some(&origin),
+            )
+        });
 
     match result {
         Ok(env) => {
             tracing::debug!(id = %env.id, provider = %env.provider, project_id = %env.project_id, "environment preparing");
-            super::events::notify();
             (StatusCode::ACCEPTED, Json(json!(env))).into_response()
         }
         Err(e) => {
             tracing::error!(error = %e, "failed to prepare environment");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"error": e.to_string()})),
-            )
-                .into_response()
+            error_response(&e)
         }
     }
 }
 
+#[derive(serde::Deserialize)]
+pub struct PlanEnvironmentRequest {
+    pub project_id: String,
+    pub provider: String,
+}
+
+pub async fn plan_environment(Json(body): Json<PlanEnvironmentRequest>) -> impl IntoResponse {
+    let result = tokio::task::spawn_blocking(move || {
+        check_allowed(&body.project_id, ProviderKind::Environment, &body.provider)?;
+        let project = crate::db::get_project(&body.project_id)?;
+        let provider = crate::environment::get_provider(&body.provider)
+            .map_err(|e| Error::new(ErrorCode::InvalidRequest, e.to_string()))?;
+        let options =
+            crate::config::load_for_project(&project.name, std::path::Path::new(&project.path))?
+                .prepare_options_for_project(&body.provider, &project.name);
+        provider.plan(&project, &crate::id::new_id(), &options)
+    })
+    .await
+    .unwrap_or_else(|e| Err(anyhow::anyhow!("plan task failed: {e}")));
+
+    match result {
+        Ok(plan) => (StatusCode::OK, Json(json!(plan))).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct AdoptEnvironmentRequest {
+    pub project_id: String,
+    pub provider: String,
+    pub path: String,
+}
+
+pub async fn adopt_environment(
+    RequestOrigin(origin): RequestOrigin,
+    Json(body): Json<AdoptEnvironmentRequest>,
+) -> impl IntoResponse {
+    let result = tokio::task::spawn_blocking(move || {
+        check_allowed(&body.project_id, ProviderKind::Environment, &body.provider)?;
+        let project = crate::db::get_project(&body.project_id)?;
+        let metadata = crate::environment::get_provider(&body.provider)
+            .and_then(|provider| provider.adopt(&project, std::path::Path::new(&body.path)))
+            .map_err(|e| Error::new(ErrorCode::InvalidRequest, e.to_string()))?;
+        crate::db::adopt_environment(&project.id, &body.provider, &metadata, Some(&origin))
+    })
+    .await
+    .unwrap_or_else(|e| Err(anyhow::anyhow!("adopt task failed: {e}")));
+
+    match result {
+        Ok(env) => {
+            tracing::debug!(id = %env.id, provider = %env.provider, project_id = %env.project_id, "environment adopted");
+            (StatusCode::CREATED, Json(json!(env))).into_response()
+        }
+        Err(e) => error_response(&e),
+    }
+}
+
 pub async fn list_environments() -> impl IntoResponse {
-    match crate::db::list_environments() {
-        Ok(envs) => (StatusCode::OK, Json(json!(envs))).into_response(),
+    match super::state::environments() {
+        Ok(envs) => (StatusCode::OK, Json(envs.as_slice())).into_response(),
         Err(e) => {
             tracing::error!(error = %e, "failed to list environments");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"error": e.to_string()})),
-            )
-                .into_response()
+            error_response(&e)
         }
     }
 }
 
-pub async fn get_environment(Path(id): Path<String>) -> impl IntoResponse {
+pub async fn get_environment(EnvironmentRef(id): EnvironmentRef) -> impl IntoResponse {
     match crate::db::get_environment(&id) {
         Ok(env) => (StatusCode::OK, Json(json!(env))).into_response(),
-        Err(e) => {
-            let msg = e.to_string();
-            let status = if msg.contains("not found") {
-                StatusCode::NOT_FOUND
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
-            };
-            (status, Json(json!({"error": msg}))).into_response()
+        Err(e) => error_response(&e),
+    }
+}
+
+/// How much disk the environment's worktree takes, as `du` counts it, in
+/// KB. `null` when it has no worktree here or `du` fails.
+pub async fn environment_disk_usage(EnvironmentRef(id): EnvironmentRef) -> impl IntoResponse {
+    let env = match crate::db::get_environment(&id) {
+        Ok(env) => env,
+        Err(e) => return error_response(&e),
+    };
+    let Some(path) = env.metadata["worktree_path"].as_str().map(str::to_string) else {
+        return (StatusCode::OK, Json(json!({ "kb": null }))).into_response();
+    };
+    let kb = tokio::task::spawn_blocking(move || crate::environment::disk_usage_kb(&path))
+        .await
+        .ok()
+        .flatten();
+    (StatusCode::OK, Json(json!({ "kb": kb }))).into_response()
+}
+
+pub async fn environment_status_history(EnvironmentRef(id): EnvironmentRef) -> impl IntoResponse {
+    match crate::db::environment_status_history(&id) {
+        Ok(history) => (StatusCode::OK, Json(json!(history))).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+pub async fn list_environment_snapshots(EnvironmentRef(id): EnvironmentRef) -> impl IntoResponse {
+    match crate::db::list_environment_snapshots(&id) {
+        Ok(snapshots) => (StatusCode::OK, Json(json!(snapshots))).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(Default, serde::Deserialize)]
+pub struct SnapshotEnvironmentRequest {
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+pub async fn snapshot_environment(
+    EnvironmentRef(id): EnvironmentRef,
+    Json(body): Json<SnapshotEnvironmentRequest>,
+) -> impl IntoResponse {
+    let result = tokio::task::spawn_blocking(move || {
+        let _lock = crate::db::lock_environment(&id, "snapshot")?;
+        let env = crate::db::get_environment(&id)?;
+        let provider = crate::environment::get_provider(&env.provider)?;
+        let snapshot_id = crate::id::new_id();
+        let dest = crate::paths::environment_snapshot_dir(&id)?.join(&snapshot_id);
+        std::fs::create_dir_all(&dest)?;
+        let taken = provider
+            .snapshot(&env.metadata, &dest)
+            .and_then(|metadata| {
+                let size = crate::environment::snapshot_size(&dest);
+                crate::db::create_environment_snapshot(
+                    &snapshot_id,
+                    &id,
+                    body.name.as_deref(),
+                    &metadata,
+                    size,
+                )
+            });
+        if taken.is_err() {
+            let _ = std::fs::remove_dir_all(&dest);
+        }
+        taken
+    })
+    .await
+    .unwrap_or_else(|e| Err(anyhow::anyhow!("snapshot task failed: {e}")));
+
+    match result {
+        Ok(snapshot) => {
+            tracing::debug!(id = %snapshot.id, env_id = %snapshot.environment_id, "environment snapshot taken");
+            (StatusCode::CREATED, Json(json!(snapshot))).into_response()
+        }
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct RestoreEnvironmentRequest {
+    /// Snapshot id, unique id prefix, or name.
+    pub snapshot: String,
+}
+
+pub async fn restore_environment(
+    EnvironmentRef(id): EnvironmentRef,
+    Json(body): Json<RestoreEnvironmentRequest>,
+) -> impl IntoResponse {
+    let result = tokio::task::spawn_blocking(move || {
+        let _lock = crate::db::lock_environment(&id, "restore")?;
+        let env = crate::db::get_environment(&id)?;
+        let snapshot = crate::db::find_environment_snapshot(&id, &body.snapshot)?;
+        let provider = crate::environment::get_provider(&env.provider)?;
+        provider.restore(&env.metadata, &snapshot.metadata)?;
+        crate::db::mark_snapshot_restored(&snapshot)
+    })
+    .await
+    .unwrap_or_else(|e| Err(anyhow::anyhow!("restore task failed: {e}")));
+
+    match result {
+        Ok(snapshot) => {
+            tracing::debug!(id = %snapshot.id, env_id = %snapshot.environment_id, "environment restored");
+            (StatusCode::OK, Json(json!(snapshot))).into_response()
         }
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ProviderStatsQuery {
+    #[serde(default = "default_stats_window")]
+    pub last: usize,
+}
+
+fn default_stats_window() -> usize {
+    20
+}
+
+pub async fn provider_stats(Query(query): Query<ProviderStatsQuery>) -> impl IntoResponse {
+    match crate::db::provider_stats(query.last) {
+        Ok(stats) => (StatusCode::OK, Json(json!(stats))).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(Default, serde::Deserialize)]
+pub struct CostStatsQuery {
+    pub project: Option<String>,
+}
+
+pub async fn cost_stats(Query(query): Query<CostStatsQuery>) -> impl IntoResponse {
+    match crate::db::cost_stats(query.project.as_deref()) {
+        Ok(stats) => (StatusCode::OK, Json(json!(stats))).into_response(),
+        Err(e) => error_response(&e),
     }
 }
 
-pub async fn update_environment(Path(id): Path<String>) -> impl IntoResponse {
+pub async fn update_environment(EnvironmentRef(id): EnvironmentRef) -> impl IntoResponse {
     let result = crate::db::stage_update_environment(&id);
 
     match result {
         Ok(env) => {
             tracing::debug!(id = %env.id, "environment update queued");
-            super::events::notify();
             (StatusCode::ACCEPTED, Json(json!(env))).into_response()
         }
         Err(e) => {
             tracing::error!(error = %e, "failed to update environment");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"error": e.to_string()})),
-            )
-                .into_response()
+            error_response(&e)
         }
     }
 }
 
-pub async fn claim_environment(Path(id): Path<String>) -> impl IntoResponse {
-    let result = crate::db::stage_claim_environment(&id);
+pub async fn claim_environment(EnvironmentRef(id): EnvironmentRef) -> impl IntoResponse {
+    let result = crate::db::get_environment(&id)
+        .and_then(|env| check_allowed(&env.project_id, ProviderKind::Environment, &env.provider))
+        .and_then(|()| crate::db::stage_claim_environment(&id));
 
     match result {
         Ok(env) => {
             tracing::debug!(id = %env.id, provider = %env.provider, "environment claim queued");
-            super::events::notify();
             (StatusCode::ACCEPTED, Json(json!(env))).into_response()
         }
         Err(e) => {
             tracing::error!(error = %e, "failed to claim environment");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"error": e.to_string()})),
-            )
-                .into_response()
+            error_response(&e)
         }
     }
 }
@@ -240,30 +767,78 @@ pub struct RemoveQuery {
     pub skip_provider: bool,
 }
 
+/// Whether `provider` serves a cross-project pool. Falls back to per-project
+/// pooling if the config cannot be read.
+fn shares_pool(provider: &str) -> bool {
+    match crate::config::load() {
+        Ok(config) => config.shares_pool(provider),
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to load config for pool sharing");
+            false
+        }
+    }
+}
+
 pub async fn claim_next_environment(
     Json(body): Json<ClaimNextEnvironmentRequest>,
 ) -> impl IntoResponse {
-    let result = crate::db::stage_claim_next_environment(&body.provider, &body.project_id);
+    let result = check_allowed(&body.project_id, ProviderKind::Environment, &body.provider)
+        .and_then(|()| {
+            crate::db::stage_claim_next_environment(
+                &body.provider,
+                &body.project_id,
+                shares_pool(&body.provider),
+            )
+        });
 
     match result {
         Ok(env) => {
             tracing::debug!(id = %env.id, provider = %env.provider, project_id = %env.project_id, "environment claim queued (next)");
-            super::events::notify();
             (StatusCode::ACCEPTED, Json(json!(env))).into_response()
         }
         Err(e) => {
             tracing::error!(error = %e, "failed to claim next environment");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"error": e.to_string()})),
+            error_response(&e)
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ClaimEnvironmentBatchRequest {
+    pub provider: String,
+    pub project_id: String,
+    pub count: usize,
+}
+
+pub async fn claim_environment_batch(
+    RequestOrigin(origin): RequestOrigin,
+    Json(body): Json<ClaimEnvironmentBatchRequest>,
+) -> impl IntoResponse {
+    let result = check_allowed(&body.project_id, ProviderKind::Environment, &body.provider)
+        .and_then(|()| {
+            crate::db::stage_claim_environments(
+                &body.provider,
+                &body.project_id,
+                shares_pool(&body.provider),
+                body.count,
+                Some(&origin),
             )
-                .into_response()
+        });
+
+    match result {
+        Ok(envs) => {
+            tracing::debug!(provider = %body.provider, project_id = %body.project_id, count = envs.len(), "environment batch claim queued");
+            (StatusCode::ACCEPTED, Json(json!(envs))).into_response()
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "failed to claim environment batch");
+            error_response(&e)
         }
     }
 }
 
 pub async fn remove_environment(
-    Path(id): Path<String>,
+    EnvironmentRef(id): EnvironmentRef,
     Query(query): Query<RemoveQuery>,
 ) -> impl IntoResponse {
     let result = if query.skip_provider {
@@ -275,96 +850,320 @@ pub async fn remove_environment(
     match result {
         Ok(()) => {
             tracing::debug!(id = %id, skip_provider = query.skip_provider, "environment removed request accepted");
-            super::events::notify();
             if query.skip_provider {
+                crate::environment::remove_snapshots(&id);
                 StatusCode::NO_CONTENT.into_response()
             } else {
                 StatusCode::ACCEPTED.into_response()
             }
         }
-        Err(e) => {
-            let msg = e.to_string();
-            let status = if msg.contains("not found") {
-                StatusCode::NOT_FOUND
-            } else if msg.contains("attached to task") {
-                StatusCode::CONFLICT
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
-            };
-            (status, Json(json!({"error": msg}))).into_response()
+        Err(e) => error_response(&e),
+    }
+}
+
+/// Environment providers a new task may run in, most preferred first: the
+/// provider it asked for, or every provider tagged with its requirements,
+/// starting with the project's default.
+fn environment_choices(
+    task: &crate::db::NewTask,
+) -> anyhow::Result<Vec<crate::db::EnvironmentChoice>> {
+    if task.requirements.is_empty() {
+        let Some(provider) = &task.env_provider else {
+            return Err(Error::new(
+                ErrorCode::InvalidRequest,
+                "env_provider is required when a task has no requirements",
+            )
+            .into());
+        };
+        check_allowed(&task.project_id, ProviderKind::Environment, provider)?;
+        return Ok(vec![crate::db::EnvironmentChoice {
+            provider: provider.clone(),
+            shared_pool: shares_pool(provider),
+        }]);
+    }
+
+    let project = crate::db::get_project(&task.project_id)?;
+    let config =
+        crate::config::load_for_project(&project.name, std::path::Path::new(&project.path))?;
+    let providers = match &task.env_provider {
+        Some(provider) => {
+            let tags = config.provider_tags(provider);
+            let missing: Vec<&str> = task
+                .requirements
+                .iter()
+                .filter(|r| !tags.contains(r))
+                .map(String::as_str)
+                .collect();
+            if !missing.is_empty() {
+                return Err(Error::new(
+                    ErrorCode::InvalidRequest,
+                    format!(
+                        "environment provider {provider} is not tagged with {}",
+                        missing.join(", ")
+                    ),
+                )
+                .with_details(json!({ "provider": provider, "missing": missing }))
+                .into());
+            }
+            if !config.allows_provider(&project.name, ProviderKind::Environment, provider) {
+                return Err(not_allowed(
+                    &project.name,
+                    ProviderKind::Environment,
+                    provider,
+                ));
+            }
+            vec![provider.clone()]
         }
+        None => {
+            let preferred = config.default_environment_provider_for_project(&project.name);
+            let tagged = config.providers_with_tags(&task.requirements, preferred.as_deref());
+            // Matching tags never put a task in the project checkout.
+            let allowed: Vec<String> = tagged
+                .iter()
+                .filter(|p| p.as_str() != crate::config::IN_PLACE_PROVIDER)
+                .filter(|p| config.allows_provider(&project.name, ProviderKind::Environment, p))
+                .cloned()
+                .collect();
+            if allowed.is_empty() && !tagged.is_empty() {
+                return Err(Error::new(
+                    ErrorCode::ProviderNotAllowed,
+                    format!(
+                        "project {} is not allowed to use any environment provider tagged with {}",
+                        project.name,
+                        task.requirements.join(", ")
+                    ),
+                )
+                .with_details(json!({
+                    "kind": ProviderKind::Environment.as_str(),
+                    "project": project.name,
+                    "providers": tagged,
+                }))
+                .into());
+            }
+            allowed
+        }
+    };
+    if providers.is_empty() {
+        return Err(Error::new(
+            ErrorCode::InvalidRequest,
+            format!(
+                "no environment provider is tagged with {}",
+                task.requirements.join(", ")
+            ),
+        )
+        .with_details(json!({ "requirements": task.requirements }))
+        .into());
     }
+
+    Ok(providers
+        .into_iter()
+        .map(|provider| crate::db::EnvironmentChoice {
+            shared_pool: config.shares_pool(&provider),
+            provider,
+        })
+        .collect())
 }
 
-#[derive(serde::Deserialize)]
-pub struct CreateTaskRequest {
-    pub project_id: String,
-    pub provider: String,
-    pub env_provider: String,
-    pub description: String,
+fn not_allowed(project: &str, kind: ProviderKind, provider: &str) -> anyhow::Error {
+    Error::new(
+        ErrorCode::ProviderNotAllowed,
+        format!(
+            "project {project} is not allowed to use {} provider {provider}",
+            kind.as_str()
+        ),
+    )
+    .with_details(json!({ "kind": kind.as_str(), "provider": provider, "project": project }))
+    .into()
+}
+
+/// Refuse `provider` when the project's allowlists leave it out.
+fn check_allowed(project_id: &str, kind: ProviderKind, provider: &str) -> anyhow::Result<()> {
+    let project = crate::db::get_project(project_id)?;
+    let config =
+        crate::config::load_for_project(&project.name, std::path::Path::new(&project.path))?;
+    if !config.allows_provider(&project.name, kind, provider) {
+        return Err(not_allowed(&project.name, kind, provider));
+    }
+    Ok(())
+}
+
+/// Check the task's providers against the daemon's config, which can be a
+/// different file from the one the CLI checked, so a mismatch fails the
+/// request instead of the task's jobs.
+fn check_providers(task: &crate::db::NewTask) -> anyhow::Result<()> {
+    let path = crate::config::path()?;
+    let config = crate::config::load()
+        .map_err(|e| Error::new(ErrorCode::InvalidConfig, format!("{}: {e}", path.display())))?;
+    let unknown = |kind: &str, provider: &str| -> anyhow::Error {
+        Error::new(
+            ErrorCode::UnknownProvider,
+            format!(
+                "{kind} provider {provider} is not configured in the daemon's config ({})",
+                path.display()
+            ),
+        )
+        .with_details(json!({ "kind": kind, "provider": provider, "config_path": path }))
+        .into()
+    };
+
+    if config.get_task_provider(&task.provider).is_err() {
+        return Err(unknown("task", &task.provider));
+    }
+    if let Some(provider) = &task.env_provider
+        && !crate::config::BUILT_IN_ENVIRONMENT_PROVIDERS.contains(&provider.as_str())
+        && config.get_environment_provider(provider).is_err()
+    {
+        return Err(unknown("environment", provider));
+    }
+    Ok(())
 }
 
-pub async fn create_task(Json(body): Json<CreateTaskRequest>) -> impl IntoResponse {
-    let result = crate::db::stage_task_create(
-        &body.project_id,
-        &body.provider,
-        &body.env_provider,
-        &body.description,
-    );
+/// Refuse to run in the project checkout unless the task confirmed it with
+/// `in_place`, and refuse `in_place` for any other provider.
+fn check_in_place(task: &crate::db::NewTask) -> anyhow::Result<()> {
+    let provider = task.env_provider.as_deref();
+    let in_place_provider = provider == Some(crate::config::IN_PLACE_PROVIDER);
+    if in_place_provider && !task.in_place {
+        return Err(Error::new(
+            ErrorCode::InvalidRequest,
+            "the inplace environment provider runs tasks in the project checkout itself; confirm with in_place (--in-place)",
+        )
+        .into());
+    }
+    if task.in_place && !in_place_provider {
+        return Err(Error::new(
+            ErrorCode::InvalidRequest,
+            format!(
+                "in_place tasks run in the inplace environment provider, not {}",
+                provider.unwrap_or("a tagged one")
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+pub async fn create_task(
+    RequestOrigin(origin): RequestOrigin,
+    Json(body): Json<crate::db::NewTask>,
+) -> impl IntoResponse {
+    let result = check_providers(&body)
+        .and_then(|()| check_in_place(&body))
+        .and_then(|()| check_allowed(&body.project_id, ProviderKind::Task, &body.provider))
+        .and_then(|()| environment_choices(&body))
+        .and_then(|choices| crate::db::stage_task_create(&body, &choices, Some(&origin)));
 
     match result {
         Ok(task) => {
             tracing::debug!(id = %task.id, provider = %task.provider, "task created");
-            super::events::notify();
             (StatusCode::ACCEPTED, Json(json!(task))).into_response()
         }
-        Err(e) => {
-            let msg = e.to_string();
-            let status = if msg.contains("UNIQUE constraint") {
-                StatusCode::CONFLICT
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
-            };
-            (status, Json(json!({"error": msg}))).into_response()
-        }
+        Err(e) => error_response(&e),
     }
 }
 
-pub async fn list_tasks() -> impl IntoResponse {
-    match crate::db::list_tasks() {
-        Ok(tasks) => (StatusCode::OK, Json(json!(tasks))).into_response(),
+/// Every task, from the cached list, or one page of them, read from the
+/// table so a long history isn't loaded to serve part of it.
+pub async fn list_tasks(Query(page): Query<crate::db::TaskPage>) -> impl IntoResponse {
+    let tasks = if page.is_everything() {
+        super::state::tasks()
+    } else {
+        tokio::task::spawn_blocking(move || crate::db::page_tasks(&page).map(std::sync::Arc::new))
+            .await
+            .unwrap_or_else(|e| Err(e.into()))
+    };
+    match tasks {
+        Ok(tasks) => match crate::redact::configured() {
+            Ok(Some(redactor)) => {
+                let tasks: Vec<_> = tasks
+                    .iter()
+                    .cloned()
+                    .map(|mut task| {
+                        redactor.redact_task(&mut task);
+                        task
+                    })
+                    .collect();
+                (StatusCode::OK, Json(tasks)).into_response()
+            }
+            Ok(None) => (StatusCode::OK, Json(tasks.as_slice())).into_response(),
+            Err(e) => error_response(&e),
+        },
         Err(e) => {
             tracing::error!(error = %e, "failed to list tasks");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"error": e.to_string()})),
-            )
-                .into_response()
+            error_response(&e)
         }
     }
 }
 
-pub async fn get_task(Path(id): Path<String>) -> impl IntoResponse {
-    match crate::db::get_task(&id) {
+pub async fn count_tasks() -> impl IntoResponse {
+    match tokio::task::spawn_blocking(crate::db::count_tasks).await {
+        Ok(Ok(counts)) => (StatusCode::OK, Json(json!(counts))).into_response(),
+        Ok(Err(e)) => error_response(&e),
+        Err(e) => error_response(&e.into()),
+    }
+}
+
+pub async fn get_task(TaskRef(id): TaskRef) -> impl IntoResponse {
+    match crate::db::get_task(&id).and_then(crate::redact::task) {
         Ok(task) => (StatusCode::OK, Json(json!(task))).into_response(),
-        Err(e) => {
-            let msg = e.to_string();
-            let status = if msg.contains("not found") {
-                StatusCode::NOT_FOUND
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
-            };
-            (status, Json(json!({"error": msg}))).into_response()
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateTaskLabelsRequest {
+    #[serde(default)]
+    pub add: Vec<String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+pub async fn update_task_labels(
+    TaskRef(id): TaskRef,
+    Json(body): Json<UpdateTaskLabelsRequest>,
+) -> impl IntoResponse {
+    match crate::db::update_task_labels(&id, &body.add, &body.remove) {
+        Ok(task) => (StatusCode::OK, Json(json!(task))).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct AddTaskNoteRequest {
+    pub text: String,
+}
+
+/// Record a note on the task and echo it into the task's log, so it sits
+/// next to the output it's about.
+pub async fn add_task_note(
+    TaskRef(id): TaskRef,
+    Json(body): Json<AddTaskNoteRequest>,
+) -> impl IntoResponse {
+    let note = match crate::db::add_task_note(&id, &body.text) {
+        Ok(note) => note,
+        Err(e) => return error_response(&e),
+    };
+    match crate::db::get_task(&id) {
+        Ok(task) => {
+            // A pending task has no log yet. Its runner writes the notes in
+            // when it starts.
+            if task.status != "pending" {
+                super::task_log::append_note(&id, &note);
+            }
+            (StatusCode::CREATED, Json(json!(task))).into_response()
         }
+        Err(e) => error_response(&e),
     }
 }
 
 pub async fn remove_task(
-    Path(id): Path<String>,
+    TaskRef(id): TaskRef,
     Query(query): Query<RemoveQuery>,
 ) -> impl IntoResponse {
     let result = if query.skip_provider {
-        crate::db::force_delete_task(&id)
+        crate::db::force_delete_task(&id).map(|env_id| {
+            crate::environment::remove_snapshots(&env_id);
+        })
     } else {
         crate::db::stage_remove_task(&id)
     };
@@ -372,54 +1171,195 @@ pub async fn remove_task(
     match result {
         Ok(()) => {
             tracing::debug!(id = %id, skip_provider = query.skip_provider, "task removed request accepted");
-            super::events::notify();
             if query.skip_provider {
                 StatusCode::NO_CONTENT.into_response()
             } else {
                 StatusCode::ACCEPTED.into_response()
             }
         }
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct CreateTaskGroupRequest {
+    pub project_id: String,
+    pub description: String,
+}
+
+pub async fn create_task_group(Json(body): Json<CreateTaskGroupRequest>) -> impl IntoResponse {
+    match crate::db::create_task_group(&body.project_id, &body.description) {
+        Ok(group) => (StatusCode::CREATED, Json(json!(group))).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+pub async fn list_task_groups() -> impl IntoResponse {
+    match super::state::groups() {
+        Ok(groups) => (StatusCode::OK, Json(groups.as_slice())).into_response(),
         Err(e) => {
-            let msg = e.to_string();
-            let status = if msg.contains("not found") {
-                StatusCode::NOT_FOUND
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
-            };
-            (status, Json(json!({"error": msg}))).into_response()
+            tracing::error!(error = %e, "failed to list task groups");
+            error_response(&e)
         }
     }
 }
 
-pub async fn tail_task_logs(Path(id): Path<String>) -> impl IntoResponse {
-    let task = match crate::db::get_task(&id) {
-        Ok(t) => t,
+pub async fn get_task_group(Path(id): Path<String>) -> impl IntoResponse {
+    match crate::db::get_task_group(&id) {
+        Ok(group) => (StatusCode::OK, Json(json!(group))).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(Default, serde::Deserialize)]
+pub struct RemoveGroupQuery {
+    #[serde(default)]
+    pub cascade: bool,
+    #[serde(default)]
+    pub skip_provider: bool,
+}
+
+pub async fn remove_task_group(
+    Path(id): Path<String>,
+    Query(query): Query<RemoveGroupQuery>,
+) -> impl IntoResponse {
+    match crate::db::remove_task_group(&id, query.cascade, query.skip_provider) {
+        Ok(deleted) => {
+            for env_id in &deleted {
+                crate::environment::remove_snapshots(env_id);
+            }
+            tracing::debug!(id = %id, cascade = query.cascade, "task group removed");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => error_response(&e),
+    }
+}
+
+pub async fn list_jobs() -> impl IntoResponse {
+    match crate::db::list_jobs() {
+        Ok(jobs) => (StatusCode::OK, Json(json!(jobs))).into_response(),
         Err(e) => {
-            let msg = e.to_string();
-            let status = if msg.contains("not found") {
-                StatusCode::NOT_FOUND
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
-            };
-            return (status, Json(json!({"error": msg}))).into_response();
+            tracing::error!(error = %e, "failed to list jobs");
+            error_response(&e)
+        }
+    }
+}
+
+/// Default number of results returned by `GET /search`.
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+
+#[derive(serde::Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    #[serde(default)]
+    pub logs: bool,
+    pub limit: Option<usize>,
+}
+
+pub async fn search_tasks(Query(query): Query<SearchQuery>) -> impl IntoResponse {
+    let limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+    let result =
+        tokio::task::spawn_blocking(move || crate::db::search_tasks(&query.q, query.logs, limit))
+            .await;
+
+    match result {
+        Ok(Ok(hits)) => match redact_hits(hits) {
+            Ok(hits) => (StatusCode::OK, Json(json!(hits))).into_response(),
+            Err(e) => error_response(&e),
+        },
+        Ok(Err(e)) => error_response(&e),
+        Err(e) => error_response(&e.into()),
+    }
+}
+
+/// `hits` with the configured patterns taken out of their tasks and
+/// snippets. A snippet that had something taken out loses its highlights,
+/// which no longer line up with it.
+fn redact_hits(mut hits: Vec<crate::db::SearchHit>) -> anyhow::Result<Vec<crate::db::SearchHit>> {
+    if let Some(redactor) = crate::redact::configured()? {
+        for hit in &mut hits {
+            redactor.redact_task(&mut hit.task);
+            if let std::borrow::Cow::Owned(snippet) = redactor.redact_str(&hit.snippet) {
+                hit.snippet = snippet;
+                hit.highlights.clear();
+            }
+        }
+    }
+    Ok(hits)
+}
+
+pub async fn retry_job(Path(id): Path<String>) -> impl IntoResponse {
+    match crate::db::retry_dead_job(&id) {
+        Ok(job) => {
+            tracing::info!(id = %job.id, job_type = %job.job_type, "dead job requeued");
+            (StatusCode::OK, Json(json!(job))).into_response()
         }
+        Err(e) => error_response(&e),
+    }
+}
+
+pub async fn purge_dead_jobs() -> impl IntoResponse {
+    match crate::db::purge_dead_jobs() {
+        Ok(purged) => (StatusCode::OK, Json(json!({ "purged": purged }))).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+pub async fn get_job(Path(id): Path<String>) -> impl IntoResponse {
+    let job = match crate::db::get_job(&id) {
+        Ok(job) => job,
+        Err(e) => return error_response(&e),
     };
 
-    let log_path = match crate::paths::task_log_path(&id) {
-        Ok(p) => p,
+    let retry_policy = match crate::config::load() {
+        Ok(config) => config.retry_policy(&job.job_type),
         Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"error": e.to_string()})),
-            )
-                .into_response();
+            tracing::warn!(error = %e, "failed to load config for retry policy");
+            crate::config::RetryPolicy::default_for(&job.job_type)
         }
     };
 
+    let detail = crate::db::JobDetail { job, retry_policy };
+    (StatusCode::OK, Json(json!(detail))).into_response()
+}
+
+/// Query for the log endpoints. `follow=false` returns the log as it is
+/// now, for clients that poll, instead of streaming it.
+#[derive(serde::Deserialize)]
+pub struct LogsQuery {
+    #[serde(default = "following")]
+    pub follow: bool,
+}
+
+fn following() -> bool {
+    true
+}
+
+pub async fn tail_task_logs(
+    TaskRef(id): TaskRef,
+    Query(query): Query<LogsQuery>,
+) -> impl IntoResponse {
+    let task = match crate::db::get_task(&id) {
+        Ok(t) => t,
+        Err(e) => return error_response(&e),
+    };
+
+    let log_path = match crate::paths::task_log_path(&id) {
+        Ok(p) => p,
+        Err(e) => return error_response(&e),
+    };
+
     // If the task is already terminal, return the full log file.
-    if task.status == "complete" || task.status == "failed" {
-        let contents = std::fs::read(&log_path).unwrap_or_default();
-        return (StatusCode::OK, contents).into_response();
+    if task.is_terminal() || !query.follow {
+        let contents = match crate::log_crypt::read(&log_path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return error_response(&e.into()),
+        };
+        return match crate::redact::log(contents) {
+            Ok(contents) => (StatusCode::OK, contents).into_response(),
+            Err(e) => error_response(&e),
+        };
     }
 
     // Stream logs via a channel.
@@ -430,75 +1370,91 @@ pub async fn tail_task_logs(Path(id): Path<String>) -> impl IntoResponse {
     (StatusCode::OK, body).into_response()
 }
 
-pub async fn tail_environment_logs(Path(id): Path<String>) -> impl IntoResponse {
+pub async fn task_log_sections(TaskRef(id): TaskRef) -> impl IntoResponse {
+    match tokio::task::spawn_blocking(move || crate::log_sections::of_task(&id)).await {
+        Ok(Ok(sections)) => (StatusCode::OK, Json(sections)).into_response(),
+        Ok(Err(e)) => error_response(&e),
+        Err(e) => error_response(&anyhow::anyhow!("log index task failed: {e}")),
+    }
+}
+
+pub async fn tail_environment_logs(
+    EnvironmentRef(id): EnvironmentRef,
+    Query(query): Query<LogsQuery>,
+) -> impl IntoResponse {
     if let Err(e) = crate::db::get_environment(&id) {
-        let msg = e.to_string();
-        let status = if msg.contains("not found") {
-            StatusCode::NOT_FOUND
-        } else {
-            StatusCode::INTERNAL_SERVER_ERROR
-        };
-        return (status, Json(json!({"error": msg}))).into_response();
+        return error_response(&e);
     }
 
     let log_path = match crate::paths::environment_log_path(&id) {
         Ok(p) => p,
-        Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"error": e.to_string()})),
-            )
-                .into_response();
-        }
+        Err(e) => return error_response(&e),
     };
 
+    if !query.follow {
+        return match std::fs::read(&log_path) {
+            Ok(contents) => match crate::redact::log(contents) {
+                Ok(contents) => (StatusCode::OK, contents).into_response(),
+                Err(e) => error_response(&e),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StatusCode::OK.into_response(),
+            Err(e) => error_response(&e.into()),
+        };
+    }
+
+    let redactor = match crate::redact::configured() {
+        Ok(redactor) => redactor.map(crate::redact::LineRedactor::new),
+        Err(e) => return error_response(&e),
+    };
     let (tx, rx) = mpsc::channel::<Result<axum::body::Bytes, std::io::Error>>(64);
-    tokio::spawn(tail_environment_log_to_channel(id, log_path, tx));
+    tokio::spawn(tail_environment_log_to_channel(id, log_path, redactor, tx));
     let stream = ReceiverStream::new(rx);
     let body = Body::from_stream(stream);
     (StatusCode::OK, body).into_response()
 }
 
+/// `contents` of a log to send, with the configured patterns taken out.
+/// When they can't be loaded, the stream ends with an error instead.
+fn redacted_chunk(contents: Vec<u8>) -> Result<axum::body::Bytes, std::io::Error> {
+    crate::redact::log(contents)
+        .map(axum::body::Bytes::from)
+        .map_err(std::io::Error::other)
+}
+
 async fn tail_log_to_channel(
     task_id: String,
     log_path: std::path::PathBuf,
     tx: mpsc::Sender<Result<axum::body::Bytes, std::io::Error>>,
 ) {
-    use std::io::{Read, Seek};
-
-    let mut pos = 0u64;
+    // Wait for the runner to start, unless the task finishes without one.
     let mut tick: u64 = 0;
-
-    loop {
-        // Read any new bytes from the log file.
-        if let Ok(metadata) = std::fs::metadata(&log_path)
-            && metadata.len() > pos
-            && let Ok(mut f) = std::fs::File::open(&log_path)
-            && f.seek(std::io::SeekFrom::Start(pos)).is_ok()
-        {
-            let mut buf = vec![0u8; (metadata.len() - pos) as usize];
-            if f.read_exact(&mut buf).is_ok() {
-                pos = metadata.len();
-                if tx.send(Ok(axum::body::Bytes::from(buf))).await.is_err() {
-                    return; // client disconnected
-                }
+    let (so_far, mut output) = loop {
+        let id = task_id.clone();
+        match tokio::task::spawn_blocking(move || super::task_log::follow(&id)).await {
+            Ok(Ok(Some(live))) => break live,
+            Ok(Ok(None)) => {}
+            Ok(Err(e)) => {
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+            Err(e) => {
+                let _ = tx.send(Err(std::io::Error::other(e))).await;
+                return;
             }
         }
 
         // Check task status every ~1s (every 10 ticks).
         if tick.is_multiple_of(10)
             && let Ok(task) = crate::db::get_task(&task_id)
-            && (task.status == "complete" || task.status == "failed")
+            && task.is_terminal()
         {
-            // Drain remaining bytes.
-            if let Ok(metadata) = std::fs::metadata(&log_path)
-                && metadata.len() > pos
-                && let Ok(mut f) = std::fs::File::open(&log_path)
-                && f.seek(std::io::SeekFrom::Start(pos)).is_ok()
-            {
-                let mut buf = vec![0u8; (metadata.len() - pos) as usize];
-                if f.read_exact(&mut buf).is_ok() {
-                    let _ = tx.send(Ok(axum::body::Bytes::from(buf))).await;
+            match crate::log_crypt::read(&log_path) {
+                Ok(contents) => {
+                    let _ = tx.send(redacted_chunk(contents)).await;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
                 }
             }
             return; // dropping tx closes the stream
@@ -506,12 +1462,51 @@ async fn tail_log_to_channel(
 
         tick = tick.wrapping_add(1);
         tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    };
+
+    if !so_far.is_empty() && tx.send(redacted_chunk(so_far)).await.is_err() {
+        return; // client disconnected
+    }
+
+    // Output arrives as the daemon writes it. The stream ends when the
+    // runner's output closes, or once the task is finished if something it
+    // left behind holds the output open.
+    let mut status_check = tokio::time::interval(std::time::Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            chunk = output.recv() => match chunk {
+                Ok(chunk) => {
+                    if tx.send(Ok(chunk)).await.is_err() {
+                        return;
+                    }
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    let marker = format!(
+                        "\n[work: fell behind and skipped {skipped} chunk(s) of output; `work task logs` has all of it]\n"
+                    );
+                    if tx.send(Ok(axum::body::Bytes::from(marker))).await.is_err() {
+                        return;
+                    }
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+            },
+            _ = status_check.tick() => {
+                if let Ok(task) = crate::db::get_task(&task_id)
+                    && task.is_terminal()
+                {
+                    return;
+                }
+            }
+        }
     }
 }
 
+/// Follow an environment's log, redacting it a line at a time since it's
+/// read in whatever pieces the provider has written so far.
 async fn tail_environment_log_to_channel(
     env_id: String,
     log_path: std::path::PathBuf,
+    mut redactor: Option<crate::redact::LineRedactor>,
     tx: mpsc::Sender<Result<axum::body::Bytes, std::io::Error>>,
 ) {
     use std::io::{Read, Seek};
@@ -528,6 +1523,9 @@ async fn tail_environment_log_to_channel(
             let mut buf = vec![0u8; (metadata.len() - pos) as usize];
             if f.read_exact(&mut buf).is_ok() {
                 pos = metadata.len();
+                if let Some(redactor) = &mut redactor {
+                    buf = redactor.push(&buf);
+                }
                 if tx.send(Ok(axum::body::Bytes::from(buf))).await.is_err() {
                     return;
                 }
@@ -544,9 +1542,17 @@ async fn tail_environment_log_to_channel(
                 {
                     let mut buf = vec![0u8; (metadata.len() - pos) as usize];
                     if f.read_exact(&mut buf).is_ok() {
+                        if let Some(redactor) = &mut redactor {
+                            buf = redactor.push(&buf);
+                        }
                         let _ = tx.send(Ok(axum::body::Bytes::from(buf))).await;
                     }
                 }
+                if let Some(redactor) = &mut redactor {
+                    let _ = tx
+                        .send(Ok(axum::body::Bytes::from(redactor.finish())))
+                        .await;
+                }
                 return;
             }
         }
@@ -0,0 +1,66 @@
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, ExitStatus};
+use std::time::Instant;
+
+use crate::db::ResourceUsage;
+
+/// Wait for `child` with `wait4` so its resource usage can be recorded.
+///
+/// CPU time and peak RSS cover the child and any descendants it waited for.
+pub fn wait(child: Child, started: Instant) -> std::io::Result<(ExitStatus, ResourceUsage)> {
+    let pid = child.id() as libc::pid_t;
+    let mut status: libc::c_int = 0;
+    // SAFETY: rusage is a plain C struct for which all-zero bytes are valid.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    loop {
+        // SAFETY: pid is our own unreaped child and both out-pointers are valid.
+        let rc = unsafe { libc::wait4(pid, &mut status, 0, &mut usage) };
+        if rc != -1 {
+            break;
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+
+    let usage = ResourceUsage {
+        wall_ms: started.elapsed().as_millis() as u64,
+        user_cpu_ms: timeval_ms(usage.ru_utime),
+        system_cpu_ms: timeval_ms(usage.ru_stime),
+        peak_rss_kb: max_rss_kb(usage.ru_maxrss),
+    };
+    Ok((ExitStatus::from_raw(status), usage))
+}
+
+fn timeval_ms(tv: libc::timeval) -> u64 {
+    (tv.tv_sec as u64) * 1000 + (tv.tv_usec as u64) / 1000
+}
+
+fn max_rss_kb(max_rss: libc::c_long) -> u64 {
+    // macOS reports ru_maxrss in bytes; Linux reports kilobytes.
+    if cfg!(target_os = "macos") {
+        max_rss as u64 / 1024
+    } else {
+        max_rss as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_and_reports_usage() {
+        let started = Instant::now();
+        let child = std::process::Command::new("sh")
+            .args(["-c", "exit 3"])
+            .spawn()
+            .unwrap();
+
+        let (status, usage) = wait(child, started).unwrap();
+        assert_eq!(status.code(), Some(3));
+        assert!(usage.peak_rss_kb > 0);
+    }
+}
@@ -0,0 +1,47 @@
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use crate::db;
+
+/// Only the tail of very large task logs is indexed.
+const MAX_INDEXED_LOG_BYTES: u64 = 1024 * 1024;
+
+/// Add a finished task's log to the search index. Encrypted logs are left
+/// out, since the index keeps their text in the clear.
+pub fn index_task_log(task_id: &str) -> anyhow::Result<()> {
+    let log_path = crate::paths::task_log_path(task_id)?;
+    let log = if crate::log_crypt::is_encrypted(&log_path) {
+        String::new()
+    } else {
+        read_indexable_log(&log_path).unwrap_or_default()
+    };
+    db::set_task_search_log(task_id, &log)
+}
+
+/// Index logs of finished tasks that predate the search index, or whose
+/// indexing was interrupted.
+pub fn backfill() -> anyhow::Result<()> {
+    let task_ids = db::list_unindexed_task_logs()?;
+    if !task_ids.is_empty() {
+        tracing::info!(count = task_ids.len(), "indexing task logs for search");
+    }
+    for task_id in task_ids {
+        if let Err(e) = index_task_log(&task_id) {
+            tracing::warn!(task_id = %task_id, error = %e, "failed to index task log");
+        }
+    }
+    Ok(())
+}
+
+fn read_indexable_log(path: &Path) -> Option<String> {
+    let mut file = std::fs::File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    file.seek(std::io::SeekFrom::Start(
+        len.saturating_sub(MAX_INDEXED_LOG_BYTES),
+    ))
+    .ok()?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).ok()?;
+    // Strip terminal styling so it does not split or pollute indexed words.
+    Some(crate::ansi::strip(&String::from_utf8_lossy(&bytes)))
+}
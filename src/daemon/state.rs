@@ -0,0 +1,80 @@
+//! In-memory copies of the project, environment, task, and group lists that
+//! the list routes serve, so clients polling them don't each rescan the
+//! tables.
+//!
+//! Triggers record an event in the same transaction as every change to
+//! those tables, so the newest event's sequence number names a version of
+//! the database. A list loaded at one sequence number is exact until it
+//! moves, and the next request after that reloads it.
+
+use std::sync::{Arc, Mutex};
+
+use crate::db::{self, Environment, Project, Task, TaskGroup};
+
+/// One list, and the sequence number it was loaded at.
+struct Slot<T>(Mutex<Option<(i64, Arc<Vec<T>>)>>);
+
+impl<T> Slot<T> {
+    const fn new() -> Self {
+        Self(Mutex::new(None))
+    }
+
+    fn get(&self, load: fn() -> anyhow::Result<Vec<T>>) -> anyhow::Result<Arc<Vec<T>>> {
+        // Read the sequence number first: a change committed in between
+        // makes the list newer than its label, never older.
+        let seq = db::latest_event_seq()?;
+        if let Some((cached_seq, items)) = &*self.lock()
+            && *cached_seq == seq
+        {
+            return Ok(items.clone());
+        }
+
+        let items = Arc::new(load()?);
+        let mut slot = self.lock();
+        if slot
+            .as_ref()
+            .is_none_or(|(cached_seq, _)| *cached_seq <= seq)
+        {
+            *slot = Some((seq, items.clone()));
+        }
+        Ok(items)
+    }
+
+    fn clear(&self) {
+        *self.lock() = None;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<(i64, Arc<Vec<T>>)>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+static PROJECTS: Slot<Project> = Slot::new();
+static ENVIRONMENTS: Slot<Environment> = Slot::new();
+static TASKS: Slot<Task> = Slot::new();
+static GROUPS: Slot<TaskGroup> = Slot::new();
+
+pub fn projects() -> anyhow::Result<Arc<Vec<Project>>> {
+    PROJECTS.get(db::list_projects)
+}
+
+pub fn environments() -> anyhow::Result<Arc<Vec<Environment>>> {
+    ENVIRONMENTS.get(db::list_environments)
+}
+
+pub fn tasks() -> anyhow::Result<Arc<Vec<Task>>> {
+    TASKS.get(db::list_tasks)
+}
+
+pub fn groups() -> anyhow::Result<Arc<Vec<TaskGroup>>> {
+    GROUPS.get(db::list_task_groups)
+}
+
+/// Forget every list. A reset database numbers its events from 1 again, so
+/// sequence numbers from before it can't be trusted.
+pub fn clear() {
+    PROJECTS.clear();
+    ENVIRONMENTS.clear();
+    TASKS.clear();
+    GROUPS.clear();
+}
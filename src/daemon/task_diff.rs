@@ -0,0 +1,173 @@
+//! `patch.diff` artifacts. With `capture-diff` on for a project, what a task
+//! changed in its worktree is saved when it finishes, so there's a record of
+//! it after the environment is gone.
+
+use std::path::{Path, PathBuf};
+
+/// What to diff a worktree against once the task finishes: `base_ref`, the
+/// commit or branch its environment started from, or without one the commit
+/// the worktree is at now.
+pub fn base(worktree: &Path, base_ref: Option<&str>) -> anyhow::Result<String> {
+    if let Some(base_ref) = base_ref {
+        git(
+            worktree,
+            &["rev-parse", "--verify", &format!("{base_ref}^{{commit}}")],
+            None,
+        )?;
+        return Ok(base_ref.to_string());
+    }
+    let out = git(worktree, &["rev-parse", "--verify", "HEAD"], None)?;
+    Ok(String::from_utf8_lossy(&out).trim().to_string())
+}
+
+/// Write how the worktree's files differ from where it forked from `base` to
+/// `out`: commits made since, staged and unstaged changes, and new files
+/// `.gitignore` doesn't exclude. Diffing from the merge base leaves out
+/// whatever landed on `base` meanwhile. Files are staged in a copy of the
+/// worktree's index beside `out`, so the worktree itself is left as the task
+/// left it.
+pub fn capture(worktree: &Path, base: &str, out: &Path) -> anyhow::Result<()> {
+    let dir = out
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("no directory for {}", out.display()))?;
+    std::fs::create_dir_all(dir)?;
+    let index = dir.join("patch.index");
+
+    let result = (|| {
+        // Starting from the worktree's own index keeps what a sparse checkout
+        // left out from showing up as deleted.
+        match index_path(worktree) {
+            Ok(own) if std::fs::copy(&own, &index).is_ok() => {}
+            _ => {
+                git(worktree, &["read-tree", "HEAD"], Some(&index))?;
+            }
+        }
+        git(worktree, &["add", "--all"], Some(&index))?;
+        let fork_point = git(worktree, &["merge-base", "HEAD", base], None)?;
+        let fork_point = String::from_utf8_lossy(&fork_point).trim().to_string();
+        let diff = git(
+            worktree,
+            &[
+                "diff",
+                "--cached",
+                "--binary",
+                "--no-color",
+                "--no-ext-diff",
+                &fork_point,
+            ],
+            Some(&index),
+        )?;
+        std::fs::write(out, diff)?;
+        anyhow::Ok(())
+    })();
+    let _ = std::fs::remove_file(&index);
+    result
+}
+
+fn index_path(worktree: &Path) -> anyhow::Result<PathBuf> {
+    let out = git(worktree, &["rev-parse", "--git-path", "index"], None)?;
+    Ok(worktree.join(String::from_utf8_lossy(&out).trim()))
+}
+
+fn git(dir: &Path, args: &[&str], index: Option<&Path>) -> anyhow::Result<Vec<u8>> {
+    let mut command = std::process::Command::new("git");
+    command.args(args).current_dir(dir);
+    if let Some(index) = index {
+        command.env("GIT_INDEX_FILE", index);
+    }
+    let output = command.output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("git {} failed: {}", args.join(" "), stderr.trim());
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(dir: &Path, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {args:?} failed");
+    }
+
+    #[test]
+    fn captures_commits_edits_and_new_files_without_touching_the_index() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let repo = tmp.path().join("repo");
+        std::fs::create_dir(&repo).unwrap();
+        run(&repo, &["init", "-q"]);
+        std::fs::write(repo.join("kept.txt"), "one\n").unwrap();
+        std::fs::write(repo.join(".gitignore"), "ignored.txt\n").unwrap();
+        run(&repo, &["add", "."]);
+        run(&repo, &["commit", "-qm", "initial"]);
+
+        let base = base(&repo, None).unwrap();
+        std::fs::write(repo.join("committed.txt"), "c\n").unwrap();
+        run(&repo, &["add", "committed.txt"]);
+        run(&repo, &["commit", "-qm", "task commit"]);
+        std::fs::write(repo.join("kept.txt"), "one\ntwo\n").unwrap();
+        std::fs::write(repo.join("new.txt"), "n\n").unwrap();
+        std::fs::write(repo.join("ignored.txt"), "i\n").unwrap();
+
+        let out = tmp.path().join("artifacts/patch.diff");
+        capture(&repo, &base, &out).unwrap();
+        let diff = std::fs::read_to_string(&out).unwrap();
+        assert!(diff.contains("+++ b/committed.txt"), "{diff}");
+        assert!(diff.contains("+two"), "{diff}");
+        assert!(diff.contains("+++ b/new.txt"), "{diff}");
+        assert!(!diff.contains("ignored.txt"), "{diff}");
+        assert!(!tmp.path().join("artifacts/patch.index").exists());
+
+        // The new file is still untracked in the worktree's own index.
+        let status = std::process::Command::new("git")
+            .args(["status", "--porcelain"])
+            .current_dir(&repo)
+            .output()
+            .unwrap();
+        let status = String::from_utf8(status.stdout).unwrap();
+        assert!(status.contains("?? new.txt"), "{status}");
+    }
+
+    #[test]
+    fn diffs_from_where_the_worktree_forked_from_its_base_branch() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let repo = tmp.path().join("repo");
+        std::fs::create_dir(&repo).unwrap();
+        run(&repo, &["init", "-q", "-b", "main"]);
+        std::fs::write(repo.join("kept.txt"), "one\n").unwrap();
+        run(&repo, &["add", "."]);
+        run(&repo, &["commit", "-qm", "initial"]);
+
+        // A pooled worktree may already be ahead of its base when a task
+        // starts in it.
+        run(&repo, &["checkout", "-qb", "work-env"]);
+        std::fs::write(repo.join("earlier.txt"), "e\n").unwrap();
+        run(&repo, &["add", "earlier.txt"]);
+        run(&repo, &["commit", "-qm", "earlier task"]);
+        let base = base(&repo, Some("main")).unwrap();
+        assert_eq!(base, "main");
+
+        std::fs::write(repo.join("task.txt"), "t\n").unwrap();
+        run(&repo, &["add", "task.txt"]);
+        run(&repo, &["commit", "-qm", "task commit"]);
+        run(&repo, &["checkout", "-q", "main"]);
+        std::fs::write(repo.join("upstream.txt"), "u\n").unwrap();
+        run(&repo, &["add", "upstream.txt"]);
+        run(&repo, &["commit", "-qm", "upstream"]);
+        run(&repo, &["checkout", "-q", "work-env"]);
+
+        let out = tmp.path().join("artifacts/patch.diff");
+        capture(&repo, &base, &out).unwrap();
+        let diff = std::fs::read_to_string(&out).unwrap();
+        assert!(diff.contains("+++ b/earlier.txt"), "{diff}");
+        assert!(diff.contains("+++ b/task.txt"), "{diff}");
+        assert!(!diff.contains("upstream.txt"), "{diff}");
+    }
+}
@@ -0,0 +1,140 @@
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use crate::config::{IoClass, TaskResources};
+
+/// The program and arguments to run for a task command, wrapped in a
+/// memory-capped systemd scope when `memory-max-mb` is set. `systemd-run
+/// --scope` execs the command itself, so the runner keeps the pid the daemon
+/// waits on.
+pub fn wrap(program: &str, args: &[String], resources: &TaskResources) -> (String, Vec<String>) {
+    let Some(mb) = resources
+        .memory_max_mb
+        .filter(|_| cfg!(target_os = "linux"))
+    else {
+        return (program.to_string(), args.to_vec());
+    };
+    let mut wrapped = vec![
+        "--user".to_string(),
+        "--scope".to_string(),
+        "--quiet".to_string(),
+        "--collect".to_string(),
+        "-p".to_string(),
+        format!("MemoryMax={mb}M"),
+        "--".to_string(),
+        program.to_string(),
+    ];
+    wrapped.extend_from_slice(args);
+    ("systemd-run".to_string(), wrapped)
+}
+
+/// Have `command`'s process lower its own CPU and disk priority before it
+/// execs. Children inherit both, so the whole runner tree stays below
+/// interactive work. If either can't be lowered, spawning the command
+/// fails, and the task records its step as one that couldn't start.
+pub fn lower_priority(command: &mut Command, resources: &TaskResources) {
+    let nice = resources.nice.filter(|nice| *nice > 0);
+    let io_idle = resources.io_class == Some(IoClass::Idle);
+    if nice.is_none() && !io_idle {
+        return;
+    }
+    // SAFETY: the hook only makes async-signal-safe system calls.
+    unsafe {
+        command.pre_exec(move || {
+            // Only ever lower priority: raising it back needs privileges a
+            // daemon started at a higher niceness doesn't have.
+            if let Some(nice) = nice
+                && libc::getpriority(libc::PRIO_PROCESS as _, 0) < nice
+                && libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            if io_idle {
+                set_io_idle()?;
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn set_io_idle() -> std::io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+    let ioprio = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+    if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn set_io_idle() -> std::io::Result<()> {
+    const IOPOL_TYPE_DISK: libc::c_int = 0;
+    const IOPOL_SCOPE_PROCESS: libc::c_int = 0;
+    const IOPOL_THROTTLE: libc::c_int = 3;
+    unsafe extern "C" {
+        fn setiopolicy_np(
+            iotype: libc::c_int,
+            scope: libc::c_int,
+            policy: libc::c_int,
+        ) -> libc::c_int;
+    }
+    if unsafe { setiopolicy_np(IOPOL_TYPE_DISK, IOPOL_SCOPE_PROCESS, IOPOL_THROTTLE) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+unsafe fn set_io_idle() -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runner_starts_at_the_configured_niceness() {
+        let resources = TaskResources {
+            nice: Some(7),
+            io_class: Some(IoClass::Idle),
+            memory_max_mb: None,
+        };
+        let mut command = Command::new("nice");
+        lower_priority(&mut command, &resources);
+        let output = command.output().unwrap();
+        assert!(output.status.success(), "{output:?}");
+        // A test run that is already niced further stays where it is.
+        // SAFETY: getpriority only reads this process's priority.
+        let current = unsafe { libc::getpriority(libc::PRIO_PROCESS as _, 0) };
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            current.max(7).to_string()
+        );
+    }
+
+    #[test]
+    fn memory_limit_wraps_the_command_in_a_scope_on_linux() {
+        let args = vec!["-c".to_string(), "make".to_string()];
+        assert_eq!(
+            wrap("sh", &args, &TaskResources::default()),
+            ("sh".to_string(), args.clone())
+        );
+
+        let resources = TaskResources {
+            memory_max_mb: Some(2048),
+            ..TaskResources::default()
+        };
+        let (program, wrapped) = wrap("sh", &args, &resources);
+        if cfg!(target_os = "linux") {
+            assert_eq!(program, "systemd-run");
+            assert!(wrapped.contains(&"MemoryMax=2048M".to_string()));
+            assert!(wrapped.ends_with(&["--".into(), "sh".into(), "-c".into(), "make".into()]));
+        } else {
+            assert_eq!(program, "sh");
+        }
+    }
+}
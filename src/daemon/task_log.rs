@@ -0,0 +1,481 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+
+use axum::body::Bytes;
+use tokio::sync::broadcast;
+
+use crate::log_crypt::EncryptedLog;
+use crate::log_stamp::{Stamper, Stream};
+use crate::redact::{LineRedactor, Redactor};
+
+/// How long a finished task waits for its output to drain. Background
+/// processes the runner left behind can hold the pipe open indefinitely.
+const DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Where a [`CappedLog`] keeps its output. Writes only ever go at the end.
+pub trait LogFile: Send {
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> std::io::Result<()>;
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<()>;
+    fn set_len(&mut self, len: u64) -> std::io::Result<()>;
+    fn flush(&mut self) -> std::io::Result<()>;
+}
+
+impl LogFile for File {
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> std::io::Result<()> {
+        self.write_all_at(buf, offset)
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+        FileExt::read_exact_at(self, buf, offset)
+    }
+
+    fn set_len(&mut self, len: u64) -> std::io::Result<()> {
+        File::set_len(self, len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Write::flush(self)
+    }
+}
+
+/// Offsets are into the plaintext, so an encrypted log is capped by how much
+/// output it holds. Each cut decrypts the log and rewrites its head.
+impl LogFile for EncryptedLog {
+    fn write_at(&mut self, buf: &[u8], _offset: u64) -> std::io::Result<()> {
+        self.write_all(buf)
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+        let plaintext = self.read_plaintext()?;
+        let range = offset as usize..offset as usize + buf.len();
+        let bytes = plaintext
+            .get(range)
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?;
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn set_len(&mut self, len: u64) -> std::io::Result<()> {
+        self.truncate(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Write::flush(self)
+    }
+}
+
+/// A task log that stays under a size limit by cutting out the middle.
+///
+/// Output is appended until the file passes `max_bytes`. From then on the
+/// first half of the limit is kept as written, followed by a marker counting
+/// the bytes dropped so far and the most recent output, which keeps growing
+/// until the next cut.
+pub struct CappedLog<F: LogFile = File> {
+    file: F,
+    max_bytes: u64,
+    len: u64,
+    /// End of the preserved head and length of the marker after it, once
+    /// the log has been cut.
+    cut: Option<(u64, u64)>,
+    omitted: u64,
+}
+
+impl CappedLog {
+    pub fn create(path: &Path, max_bytes: u64) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Self::new(file, max_bytes))
+    }
+}
+
+impl<F: LogFile> CappedLog<F> {
+    /// Cap `file`, a new, empty log.
+    pub fn new(file: F, max_bytes: u64) -> Self {
+        Self {
+            file,
+            max_bytes,
+            len: 0,
+            cut: None,
+            omitted: 0,
+        }
+    }
+
+    /// Drop the middle of the log, keeping a quarter of the limit of recent
+    /// output so the next cut is a while off.
+    fn cut(&mut self) -> std::io::Result<()> {
+        let (head_end, body_start) = match self.cut {
+            Some((head_end, marker_len)) => (head_end, head_end + marker_len),
+            None => (self.max_bytes / 2, self.max_bytes / 2),
+        };
+
+        let keep = (self.max_bytes / 4).min(self.len.saturating_sub(body_start));
+        let mut tail = vec![0; keep as usize];
+        self.file.read_exact_at(&mut tail, self.len - keep)?;
+        // Start the kept output on a line boundary when there is one.
+        let skip = tail
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(0, |newline| newline + 1);
+        let tail = &tail[skip..];
+
+        self.omitted += (self.len - tail.len() as u64).saturating_sub(body_start);
+        let marker = format!(
+            "\n[work: {} bytes of output omitted to stay under max-log-mb]\n",
+            self.omitted
+        );
+        self.file.set_len(head_end)?;
+        self.file.write_at(marker.as_bytes(), head_end)?;
+        self.file.write_at(tail, head_end + marker.len() as u64)?;
+        self.len = head_end + marker.len() as u64 + tail.len() as u64;
+        self.cut = Some((head_end, marker.len() as u64));
+        Ok(())
+    }
+}
+
+impl<F: LogFile> Write for CappedLog<F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write_at(buf, self.len)?;
+        self.len += buf.len() as u64;
+        if self.len > self.max_bytes {
+            self.cut()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Chunks of output kept for a follower that falls behind before it starts
+/// dropping them.
+const FOLLOW_BUFFER_CHUNKS: usize = 1024;
+
+/// Logs of tasks whose runners are going, by task ID.
+static LIVE_LOGS: LazyLock<Mutex<HashMap<String, Arc<Mutex<LiveLog>>>>> =
+    LazyLock::new(Default::default);
+
+/// A running task's log. Output, and lines [`append`]ed while the runner is
+/// going, are written through here so they reach followers as they land in
+/// the file, in the same order.
+struct LiveLog {
+    log: Box<dyn Write + Send>,
+    path: PathBuf,
+    followers: broadcast::Sender<Bytes>,
+    /// Prefixes output lines with `[tasks] log-timestamps`.
+    stamper: Option<Stamper>,
+    /// Takes `[redact] patterns` out of lines [`append`]ed to the log.
+    redactor: Option<Redactor>,
+    /// Readers still copying output in.
+    readers: usize,
+}
+
+impl LiveLog {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.log.write_all(buf)?;
+        self.log.flush()?;
+        // No followers is fine; the output is in the file.
+        let _ = self.followers.send(Bytes::copy_from_slice(buf));
+        Ok(())
+    }
+
+    fn write_output(&mut self, stream: Stream, buf: &[u8]) -> std::io::Result<()> {
+        match &mut self.stamper {
+            Some(stamper) => {
+                let stamped = stamper.push(stream, &crate::log_stamp::now(), buf);
+                self.write(&stamped)
+            }
+            None => self.write(buf),
+        }
+    }
+}
+
+/// Copy task output from each reader, a pipe or pty, into `log`, which is
+/// written to `log_path`, on background threads, passing each chunk on to
+/// [`follow`]ers as it's written. With `timestamps`, each line starts with
+/// the time it arrived and its reader's [`Stream`]. With a `redactor`, what
+/// it matches never reaches the file or followers. Returns a receiver that
+/// fires once all the output is copied.
+pub fn capture(
+    readers: Vec<(Box<dyn Read + Send>, Stream)>,
+    log: Box<dyn Write + Send>,
+    log_path: &Path,
+    task_id: &str,
+    timestamps: bool,
+    redactor: Option<Redactor>,
+) -> std::sync::mpsc::Receiver<()> {
+    let (done_tx, done_rx) = std::sync::mpsc::channel();
+    let live = Arc::new(Mutex::new(LiveLog {
+        log,
+        path: log_path.to_path_buf(),
+        followers: broadcast::channel(FOLLOW_BUFFER_CHUNKS).0,
+        stamper: timestamps.then(Stamper::default),
+        redactor: redactor.clone(),
+        readers: readers.len(),
+    }));
+    LIVE_LOGS
+        .lock()
+        .expect("live logs lock poisoned")
+        .insert(task_id.to_string(), live.clone());
+    for (reader, stream) in readers {
+        let task_id = task_id.to_string();
+        let live = live.clone();
+        let done_tx = done_tx.clone();
+        let lines = redactor.clone().map(LineRedactor::new);
+        std::thread::spawn(move || {
+            copy_output(reader, stream, lines, &live, &task_id);
+            let mut current = live.lock().expect("live log lock poisoned");
+            current.readers -= 1;
+            if current.readers > 0 {
+                return;
+            }
+            drop(current);
+            // Dropping the last sender ends every follower's stream.
+            let mut live_logs = LIVE_LOGS.lock().expect("live logs lock poisoned");
+            if live_logs
+                .get(&task_id)
+                .is_some_and(|current| Arc::ptr_eq(current, &live))
+            {
+                live_logs.remove(&task_id);
+            }
+            drop(live_logs);
+            let _ = done_tx.send(());
+        });
+    }
+    done_rx
+}
+
+fn copy_output(
+    mut reader: Box<dyn Read + Send>,
+    stream: Stream,
+    mut lines: Option<LineRedactor>,
+    live: &Mutex<LiveLog>,
+    task_id: &str,
+) {
+    let write = |buf: &[u8]| -> std::io::Result<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        live.lock()
+            .expect("live log lock poisoned")
+            .write_output(stream, buf)
+    };
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                let written = match &mut lines {
+                    Some(lines) => write(&lines.push(&buf[..n])),
+                    None => write(&buf[..n]),
+                };
+                if let Err(e) = written {
+                    tracing::warn!(task_id = %task_id, error = %e, "failed to write task log");
+                    break;
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) if super::pty::is_closed(&e) => break,
+            Err(e) => {
+                tracing::warn!(task_id = %task_id, error = %e, "failed to read task output");
+                break;
+            }
+        }
+    }
+    if let Some(lines) = &mut lines
+        && let Err(e) = write(&lines.finish())
+    {
+        tracing::warn!(task_id = %task_id, error = %e, "failed to write task log");
+    }
+}
+
+/// Start following a running task's output. Returns the log so far and a
+/// receiver for everything written after it, or `None` if the task's runner
+/// isn't going.
+pub fn follow(task_id: &str) -> std::io::Result<Option<(Vec<u8>, broadcast::Receiver<Bytes>)>> {
+    let Some(live) = LIVE_LOGS
+        .lock()
+        .expect("live logs lock poisoned")
+        .get(task_id)
+        .cloned()
+    else {
+        return Ok(None);
+    };
+    // Hold the log while reading it so no output lands between the read and
+    // the subscription.
+    let live = live.lock().expect("live log lock poisoned");
+    let so_far = crate::log_crypt::read(&live.path)?;
+    Ok(Some((so_far, live.followers.subscribe())))
+}
+
+/// Wait briefly for [`capture`] to finish after the runner exits.
+pub fn drain(done: &std::sync::mpsc::Receiver<()>) {
+    let _ = done.recv_timeout(DRAIN_TIMEOUT);
+}
+
+/// Append a line from `work` itself, marked `[work]` and stamped with `at`,
+/// to a task's log. Does nothing if the task has no log yet.
+pub fn append(task_id: &str, at: &str, line: &str) {
+    write_line(task_id, &format!("[work] {at} "), line);
+}
+
+/// Append a section marker, such as `::group::main`, to a task's log on a
+/// line of its own.
+pub fn mark(task_id: &str, marker: &str) {
+    write_line(task_id, "", marker);
+}
+
+fn write_line(task_id: &str, prefix: &str, line: &str) {
+    let live = LIVE_LOGS
+        .lock()
+        .expect("live logs lock poisoned")
+        .get(task_id)
+        .cloned();
+    if let Some(live) = live {
+        let mut live = live.lock().expect("live log lock poisoned");
+        let line = match &live.redactor {
+            Some(redactor) => redactor.redact_str(line).into_owned(),
+            None => line.to_string(),
+        };
+        let _ = live.write(format!("\n{prefix}{line}\n").as_bytes());
+        if let Some(stamper) = &mut live.stamper {
+            stamper.end_line();
+        }
+        return;
+    }
+    let Ok(log_path) = crate::paths::task_log_path(task_id) else {
+        return;
+    };
+    // Without the patterns there's no telling what to take out, so the
+    // whole line is.
+    let line = match crate::redact::configured() {
+        Ok(Some(redactor)) => redactor.redact_str(line).into_owned(),
+        Ok(None) => line.to_string(),
+        Err(_) => crate::redact::REDACTED.to_string(),
+    };
+    let _ = crate::log_crypt::append(&log_path, format!("\n{prefix}{line}\n").as_bytes());
+}
+
+/// Append an operator's note to a task's log.
+pub fn append_note(task_id: &str, note: &crate::db::TaskNote) {
+    append(
+        task_id,
+        &note.at,
+        &format!("note: {}", note.text.replace('\n', " ")),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_head_and_recent_output_under_the_limit() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("task.log");
+        let mut log = CappedLog::create(&path, 400).unwrap();
+
+        for i in 0..200 {
+            writeln!(log, "line {i:03}").unwrap();
+        }
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.len() <= 400 + 80, "log grew to {}", contents.len());
+        assert!(contents.starts_with("line 000\nline 001\n"));
+        assert!(contents.ends_with("line 199\n"));
+        let marker = contents
+            .lines()
+            .find(|line| line.starts_with("[work: "))
+            .unwrap();
+        assert_eq!(contents.matches("[work: ").count(), 1);
+
+        // Every byte written is either still in the log or counted as omitted.
+        let omitted: usize = marker
+            .trim_start_matches("[work: ")
+            .split(' ')
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let kept = contents.len() - marker.len() - 2;
+        assert_eq!(kept + omitted, 200 * "line 000\n".len());
+    }
+
+    #[test]
+    fn caps_encrypted_logs_by_the_output_they_hold() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("task.log");
+        let mut log = CappedLog::new(EncryptedLog::create_for_test(&path).unwrap(), 400);
+
+        for i in 0..200 {
+            writeln!(log, "line {i:03}").unwrap();
+        }
+
+        let contents = String::from_utf8(log.file.read_plaintext().unwrap()).unwrap();
+        assert!(contents.len() <= 400 + 80, "log grew to {}", contents.len());
+        assert!(contents.starts_with("line 000\nline 001\n"));
+        assert!(contents.ends_with("line 199\n"));
+        assert_eq!(contents.matches("[work: ").count(), 1);
+        assert!(crate::log_crypt::is_encrypted(&path));
+        let on_disk = std::fs::read(&path).unwrap();
+        assert!(!on_disk.windows(8).any(|w| w == b"line 199"));
+    }
+
+    #[test]
+    fn followers_get_the_log_so_far_then_everything_after() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("task.log");
+        let (reader, mut writer) = std::io::pipe().unwrap();
+        let log = Box::new(File::create(&path).unwrap());
+        let done = capture(
+            vec![(Box::new(reader), Stream::Output)],
+            log,
+            &path,
+            "t-follow",
+            false,
+            None,
+        );
+
+        writer.write_all(b"before\n").unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(2);
+        while std::fs::read(&path).unwrap().is_empty() {
+            assert!(std::time::Instant::now() < deadline, "output never written");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let (so_far, mut output) = follow("t-follow").unwrap().unwrap();
+        assert_eq!(so_far, b"before\n");
+
+        append("t-follow", "now", "note");
+        writer.write_all(b"after\n").unwrap();
+        drop(writer);
+        done.recv_timeout(DRAIN_TIMEOUT).unwrap();
+
+        let mut followed = Vec::new();
+        while let Ok(chunk) = output.try_recv() {
+            followed.extend_from_slice(&chunk);
+        }
+        assert_eq!(followed, b"\n[work] now note\nafter\n");
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            b"before\n\n[work] now note\nafter\n"
+        );
+        assert!(follow("t-follow").unwrap().is_none());
+    }
+
+    #[test]
+    fn small_logs_are_untouched() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("task.log");
+        let mut log = CappedLog::create(&path, 1024).unwrap();
+        log.write_all(b"hello\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello\n");
+    }
+}
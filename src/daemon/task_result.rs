@@ -0,0 +1,204 @@
+use std::path::Path;
+
+use crate::db::TaskCost;
+
+/// How many trailing bytes of the task log are inspected for a result line.
+const LOG_TAIL_BYTES: u64 = 64 * 1024;
+
+/// Key that marks a JSON line in the task log as a structured result.
+const RESULT_KEY: &str = "work_result";
+
+#[derive(Debug, PartialEq)]
+pub struct TaskResult {
+    pub summary: Option<String>,
+    pub data: Option<serde_json::Value>,
+    /// `tokens_in`, `tokens_out`, and `cost_usd`, as far as reported.
+    pub cost: Option<TaskCost>,
+}
+
+/// Read a structured result for a finished task.
+///
+/// A result file at `result_path` takes precedence. Otherwise the last line
+/// of output in the task log is checked for a `{"work_result": ...}`
+/// object. Lines `work` writes itself, `[work]` lines and section markers,
+/// don't count, and neither do the prefixes `[tasks] log-timestamps` adds.
+pub fn read(result_path: &Path, log_path: &Path) -> Option<TaskResult> {
+    if let Ok(contents) = std::fs::read_to_string(result_path) {
+        match serde_json::from_str::<serde_json::Value>(&contents) {
+            Ok(value) => return from_value(value),
+            Err(e) => {
+                tracing::warn!(path = %result_path.display(), error = %e, "ignoring invalid task result file");
+            }
+        }
+    }
+
+    let tail = read_log_tail(log_path)?;
+    let line = tail
+        .lines()
+        .rev()
+        .map(|line| crate::log_stamp::without_prefix(line).trim())
+        .find(|line| {
+            !line.is_empty()
+                && !line.starts_with("[work] ")
+                && !crate::log_sections::is_marker(line)
+        })?;
+    parse_result_line(line)
+}
+
+fn read_log_tail(log_path: &Path) -> Option<String> {
+    let (buf, _) = crate::log_crypt::read_tail(log_path, LOG_TAIL_BYTES).ok()?;
+    // Runners on a pty may color the result line.
+    Some(crate::ansi::strip(&String::from_utf8_lossy(&buf)))
+}
+
+fn parse_result_line(line: &str) -> Option<TaskResult> {
+    let value: serde_json::Value = serde_json::from_str(line.trim()).ok()?;
+    let result = value.as_object()?.get(RESULT_KEY)?.clone();
+    from_value(result)
+}
+
+fn from_value(value: serde_json::Value) -> Option<TaskResult> {
+    match value {
+        serde_json::Value::String(summary) => Some(TaskResult {
+            summary: Some(summary),
+            data: None,
+            cost: None,
+        }),
+        serde_json::Value::Object(mut obj) => {
+            // Result files may use the same envelope as log lines.
+            if let Some(inner) = obj.remove(RESULT_KEY) {
+                return from_value(inner);
+            }
+            let summary = obj
+                .get("summary")
+                .and_then(|v| v.as_str())
+                .map(str::to_string);
+            let data = obj.remove("data").filter(|v| !v.is_null());
+            let cost = TaskCost {
+                tokens_in: obj.get("tokens_in").and_then(|v| v.as_i64()),
+                tokens_out: obj.get("tokens_out").and_then(|v| v.as_i64()),
+                cost_usd: obj.get("cost_usd").and_then(|v| v.as_f64()),
+            }
+            .reported();
+            if summary.is_none() && data.is_none() && cost.is_none() {
+                return None;
+            }
+            Some(TaskResult {
+                summary,
+                data,
+                cost,
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parses_result_line_with_summary_and_data() {
+        let result = parse_result_line(
+            r#"{"work_result": {"summary": "fixed the bug", "data": {"files": 2}}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(result.summary.as_deref(), Some("fixed the bug"));
+        assert_eq!(result.data, Some(json!({"files": 2})));
+    }
+
+    #[test]
+    fn parses_string_result_as_summary() {
+        let result = parse_result_line(r#"{"work_result": "done"}"#).unwrap();
+
+        assert_eq!(result.summary.as_deref(), Some("done"));
+        assert_eq!(result.data, None);
+    }
+
+    #[test]
+    fn parses_reported_cost() {
+        let result = parse_result_line(
+            r#"{"work_result": {"summary": "done", "tokens_in": 1200, "tokens_out": 300, "cost_usd": 0.42}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            result.cost,
+            Some(TaskCost {
+                tokens_in: Some(1200),
+                tokens_out: Some(300),
+                cost_usd: Some(0.42),
+            })
+        );
+
+        // Cost alone is still a result; malformed parts are left out.
+        let result =
+            parse_result_line(r#"{"work_result": {"cost_usd": 3, "tokens_in": "lots"}}"#).unwrap();
+        assert_eq!(result.summary, None);
+        assert_eq!(
+            result.cost,
+            Some(TaskCost {
+                cost_usd: Some(3.0),
+                ..TaskCost::default()
+            })
+        );
+        assert_eq!(
+            parse_result_line(r#"{"work_result": {"summary": "free"}}"#)
+                .unwrap()
+                .cost,
+            None
+        );
+    }
+
+    #[test]
+    fn ignores_lines_without_result_key() {
+        assert_eq!(parse_result_line(r#"{"result": "done"}"#), None);
+        assert_eq!(parse_result_line("all done"), None);
+    }
+
+    #[test]
+    fn result_file_takes_precedence_over_log() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let result_path = dir.path().join("result.json");
+        let log_path = dir.path().join("task.log");
+        std::fs::write(&result_path, r#"{"summary": "from file"}"#).unwrap();
+        std::fs::write(&log_path, "output\n{\"work_result\": \"from log\"}\n\n").unwrap();
+
+        let result = read(&result_path, &log_path).unwrap();
+        assert_eq!(result.summary.as_deref(), Some("from file"));
+
+        std::fs::remove_file(&result_path).unwrap();
+        let result = read(&result_path, &log_path).unwrap();
+        assert_eq!(result.summary.as_deref(), Some("from log"));
+    }
+
+    #[test]
+    fn reads_a_stamped_result_line() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let log_path = dir.path().join("task.log");
+        std::fs::write(
+            &log_path,
+            "2026-01-02T03:04:05.678Z stdout working\n2026-01-02T03:04:05.679Z stdout {\"work_result\": \"stamped\"}\n",
+        )
+        .unwrap();
+
+        let result = read(&dir.path().join("result.json"), &log_path).unwrap();
+        assert_eq!(result.summary.as_deref(), Some("stamped"));
+    }
+
+    #[test]
+    fn skips_lines_work_wrote_after_the_result() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let log_path = dir.path().join("task.log");
+        std::fs::write(
+            &log_path,
+            "::group::command\n\n[work] 2026-01-02T03:04:05Z command: sh -c run\n{\"work_result\": \"chained\"}\n\n[work] 2026-01-02T03:04:06Z command: exit status: 0\n\n::endgroup::\n",
+        )
+        .unwrap();
+
+        let result = read(&dir.path().join("result.json"), &log_path).unwrap();
+        assert_eq!(result.summary.as_deref(), Some("chained"));
+    }
+}
@@ -0,0 +1,62 @@
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+/// Exit code `timeout(1)` and most runners use when a time limit is hit.
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Map how a task's runner ended to the task's terminal status.
+///
+/// `cancelled` is set when the daemon itself stopped the runner, which takes
+/// precedence over whatever signal the process died from.
+pub fn from_exit(status: ExitStatus, cancelled: bool) -> &'static str {
+    if cancelled {
+        return "cancelled";
+    }
+    if status.success() {
+        return "complete";
+    }
+    if status.code() == Some(TIMEOUT_EXIT_CODE) {
+        return "timed_out";
+    }
+    match status.signal() {
+        Some(libc::SIGALRM | libc::SIGXCPU) => "timed_out",
+        Some(libc::SIGINT | libc::SIGTERM | libc::SIGHUP | libc::SIGQUIT | libc::SIGKILL) => {
+            "interrupted"
+        }
+        _ => "failed",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exited(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    fn signaled(signal: i32) -> ExitStatus {
+        ExitStatus::from_raw(signal)
+    }
+
+    #[test]
+    fn classifies_exit_codes() {
+        assert_eq!(from_exit(exited(0), false), "complete");
+        assert_eq!(from_exit(exited(1), false), "failed");
+        assert_eq!(from_exit(exited(124), false), "timed_out");
+    }
+
+    #[test]
+    fn classifies_signals() {
+        assert_eq!(from_exit(signaled(libc::SIGTERM), false), "interrupted");
+        assert_eq!(from_exit(signaled(libc::SIGKILL), false), "interrupted");
+        assert_eq!(from_exit(signaled(libc::SIGXCPU), false), "timed_out");
+        assert_eq!(from_exit(signaled(libc::SIGSEGV), false), "failed");
+    }
+
+    #[test]
+    fn cancellation_takes_precedence() {
+        assert_eq!(from_exit(signaled(libc::SIGTERM), true), "cancelled");
+        assert_eq!(from_exit(exited(0), true), "cancelled");
+    }
+}
@@ -0,0 +1,501 @@
+//! `WORK_TRACE`: a hidden setting that records the order the job processor
+//! claims jobs in and how each one ends, so a run that hit an
+//! ordering-dependent bug can be played back with `work debug replay`.
+//!
+//! The daemon appends one JSON line per event to the file it names:
+//!
+//! - `claim`: a job was claimed, with the job and the project, environment,
+//!   and task rows its payload names, as they were at that moment, and how
+//!   many attempts its retry policy allowed.
+//! - `finish`: a claimed job ended. `outcome` is `complete`, `retry` (failed
+//!   and requeued), `dead`, `deferred` (put back before it ran), or
+//!   `interrupted` (the daemon stopped under it), with the error or reason.
+//!
+//! Every string is written with `[redact] patterns` taken out.
+//!
+//! Replaying runs the same jobs against mock providers in a scratch work
+//! home. Each job is queued and claimed where the trace claimed it, through
+//! the same claim the job processor makes, and replay waits for it where the
+//! trace saw it finish, so the same jobs overlap in the same order. A job
+//! that failed in the trace fails again with the recorded error, since a
+//! mock wouldn't, and is retried or given up on by the recorded policy, so a
+//! job reaching it on a different attempt ends differently. A deferred or
+//! interrupted job is put back without running; neither depends on the
+//! jobs before it. A job that ends differently than it did in the trace
+//! points at state that depended on timing rather than order.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use crate::db;
+
+/// Provider names replayed rows use, set up as mocks in the scratch config.
+const REPLAY_PROVIDER: &str = "replay";
+
+/// How long a job replay puts back waits, so the next claim takes the
+/// traced job instead. Its own claim in the trace queues it again.
+const PARKED_SECONDS: i64 = 24 * 60 * 60;
+
+const REPLAY_CONFIG: &str = r#"# Written by `work debug replay`. Every environment and task in the trace
+# runs on these mocks.
+[tasks.providers.replay]
+type = "mock"
+
+[environments.providers.replay]
+type = "mock"
+"#;
+
+static MODE: OnceLock<Mode> = OnceLock::new();
+
+enum Mode {
+    Record(Mutex<Recorder>),
+    Replay(Mutex<Replaying>),
+}
+
+struct Recorder {
+    file: std::fs::File,
+    seq: u64,
+}
+
+/// Failures to inject into the jobs running now, and how the finished ones
+/// ended, keyed by job ID.
+#[derive(Default)]
+struct Replaying {
+    failures: HashMap<String, RecordedFailure>,
+    outcomes: HashMap<String, Outcome>,
+}
+
+/// How a claimed job ended.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Complete,
+    Retry,
+    Dead,
+    Deferred,
+    Interrupted,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Complete => "complete",
+            Outcome::Retry => "retry",
+            Outcome::Dead => "dead",
+            Outcome::Deferred => "deferred",
+            Outcome::Interrupted => "interrupted",
+        }
+    }
+}
+
+/// The error a traced attempt failed with, and the most attempts its retry
+/// policy allowed, if the trace says.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedFailure {
+    pub error: String,
+    pub retry_limit: Option<i64>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Record {
+    seq: u64,
+    at: String,
+    #[serde(flatten)]
+    event: Event,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum Event {
+    Claim(Box<Claim>),
+    Finish {
+        job_id: String,
+        outcome: Outcome,
+        #[serde(default)]
+        error: Option<String>,
+    },
+}
+
+/// A claimed job and the rows its payload names, as they were then.
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Claim {
+    job: db::Job,
+    #[serde(default)]
+    project: Option<db::Project>,
+    #[serde(default)]
+    environment: Option<db::Environment>,
+    #[serde(default)]
+    task: Option<db::Task>,
+    #[serde(default)]
+    retry_limit: Option<i64>,
+}
+
+/// Read `WORK_TRACE` and start appending to the file it names.
+pub fn init() -> anyhow::Result<()> {
+    let Some(path) = std::env::var_os("WORK_TRACE").filter(|path| !path.is_empty()) else {
+        return Ok(());
+    };
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| anyhow::anyhow!("WORK_TRACE: could not open {}: {e}", path.display()))?;
+    tracing::warn!(path = %Path::new(&path).display(), "recording a job trace");
+    let _ = MODE.set(Mode::Record(Mutex::new(Recorder { file, seq: 0 })));
+    Ok(())
+}
+
+fn record(event: Event) {
+    let Some(Mode::Record(recorder)) = MODE.get() else {
+        return;
+    };
+    let mut recorder = recorder.lock().expect("trace lock poisoned");
+    recorder.seq += 1;
+    let record = Record {
+        seq: recorder.seq,
+        at: chrono::Utc::now().to_rfc3339(),
+        event,
+    };
+    let written = redacted_line(&record)
+        .map_err(std::io::Error::other)
+        .and_then(|line| writeln!(recorder.file, "{line}"));
+    if let Err(e) = written {
+        tracing::warn!(error = %e, "failed to write job trace");
+    }
+}
+
+/// `record` as a line of the trace, with `[redact] patterns` taken out of
+/// every string in it: descriptions, payloads, and errors alike. Without
+/// the patterns, nothing is written.
+fn redacted_line(record: &Record) -> anyhow::Result<String> {
+    let mut value = serde_json::to_value(record)?;
+    if let Some(redactor) = crate::redact::configured()? {
+        redactor.redact_json(&mut value);
+    }
+    Ok(value.to_string())
+}
+
+/// Record that `jobs` were claimed, with the rows they're about. Reading
+/// those is blocking work, so it happens on a blocking thread, and the
+/// claims are written before any of the jobs can start and finish.
+pub async fn claimed(jobs: &[db::Job]) {
+    if !matches!(MODE.get(), Some(Mode::Record(_))) {
+        return;
+    }
+    let jobs = jobs.to_vec();
+    if let Err(e) = tokio::task::spawn_blocking(move || record_claims(&jobs)).await {
+        tracing::warn!(error = %e, "failed to record job claims");
+    }
+}
+
+fn record_claims(jobs: &[db::Job]) {
+    // A config that doesn't load leaves the job processor the built-in
+    // policies too.
+    let config = crate::config::load().ok();
+    for job in jobs {
+        let environment = job.payload["env_id"]
+            .as_str()
+            .and_then(|id| db::get_environment(id).ok());
+        let task = job.payload["task_id"]
+            .as_str()
+            .and_then(|id| db::get_task(id).ok());
+        let project = task
+            .as_ref()
+            .map(|t| t.project_id.as_str())
+            .or(environment.as_ref().map(|e| e.project_id.as_str()))
+            .and_then(|id| db::get_project(id).ok());
+        let policy = match &config {
+            Some(config) => config.retry_policy(&job.job_type),
+            None => crate::config::RetryPolicy::default_for(&job.job_type),
+        };
+        record(Event::Claim(Box::new(Claim {
+            job: job.clone(),
+            project,
+            environment,
+            task,
+            retry_limit: Some(policy.limit),
+        })));
+    }
+}
+
+/// Record how `job` ended, or note it for the replay waiting on it.
+pub fn finished(job: &db::Job, outcome: Outcome, error: Option<&str>) {
+    match MODE.get() {
+        Some(Mode::Record(_)) => record(Event::Finish {
+            job_id: job.id.clone(),
+            outcome,
+            error: error.map(str::to_string),
+        }),
+        Some(Mode::Replay(replaying)) => {
+            let mut replaying = replaying.lock().expect("replay lock poisoned");
+            replaying.failures.remove(&job.id);
+            replaying.outcomes.insert(job.id.clone(), outcome);
+        }
+        None => {}
+    }
+}
+
+/// While replaying, how the traced attempt of `job_id` failed, if it did.
+pub fn recorded_failure(job_id: &str) -> Option<RecordedFailure> {
+    let Some(Mode::Replay(replaying)) = MODE.get() else {
+        return None;
+    };
+    replaying
+        .lock()
+        .expect("replay lock poisoned")
+        .failures
+        .get(job_id)
+        .cloned()
+}
+
+/// One claimed job in a replayed trace.
+#[derive(Debug, serde::Serialize)]
+pub struct ReplayedJob {
+    /// The claim's sequence number in the trace.
+    pub seq: u64,
+    pub job_id: String,
+    pub job_type: String,
+    /// How it ended in the trace, or `None` if the trace stops first.
+    pub recorded: Option<Outcome>,
+    /// How it ended in the replay, or `None` if it never finished.
+    pub replayed: Option<Outcome>,
+}
+
+impl ReplayedJob {
+    pub fn diverged(&self) -> bool {
+        self.recorded.is_some() && self.recorded != self.replayed
+    }
+}
+
+fn read_trace(path: &Path) -> anyhow::Result<Vec<Record>> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| anyhow::anyhow!("could not open {}: {e}", path.display()))?;
+    let mut records = Vec::new();
+    for (n, line) in std::io::BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line)
+            .map_err(|e| anyhow::anyhow!("{}:{}: {e}", path.display(), n + 1))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Point a traced environment at the mock provider, with the metadata the
+/// mock would have given it, so no step touches the recorded checkout.
+fn mock_environment(
+    mut env: db::Environment,
+    project: Option<&db::Project>,
+) -> anyhow::Result<db::Environment> {
+    env.provider = REPLAY_PROVIDER.to_string();
+    env.metadata = if env.status == "preparing" {
+        serde_json::json!({})
+    } else {
+        let path = crate::paths::data_dir()?
+            .join("mock-environments")
+            .join(&env.id);
+        std::fs::create_dir_all(&path)?;
+        serde_json::json!({
+            "project_path": project.map(|p| p.path.as_str()),
+            "worktree_path": path,
+            "mock": crate::config::MockFlags::default(),
+        })
+    };
+    Ok(env)
+}
+
+/// Give a traced project a directory of its own in the scratch work home.
+fn scratch_project(mut project: db::Project) -> anyhow::Result<db::Project> {
+    let path = crate::paths::data_dir()?
+        .join("projects")
+        .join(&project.name);
+    std::fs::create_dir_all(&path)?;
+    project.path = path.to_string_lossy().into_owned();
+    Ok(project)
+}
+
+/// Run the jobs in the trace at `path` again, in its order, against the
+/// current work home, which should be a scratch one.
+pub async fn replay(path: &Path) -> anyhow::Result<Vec<ReplayedJob>> {
+    let records = read_trace(path)?;
+    if MODE.set(Mode::Replay(Mutex::default())).is_err() {
+        anyhow::bail!("a job trace is already being recorded or replayed");
+    }
+    let Some(Mode::Replay(replaying)) = MODE.get() else {
+        unreachable!("replay mode was just set");
+    };
+
+    let config_path = crate::config::path()?;
+    if !config_path.exists() {
+        if let Some(dir) = config_path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&config_path, REPLAY_CONFIG)?;
+    }
+    db::initialize()?;
+
+    // Pair each claim with how the trace saw it end.
+    let mut jobs = Vec::new();
+    let mut open = HashMap::new();
+    let mut recorded_errors = HashMap::new();
+    let mut traced = HashSet::new();
+    for record in &records {
+        match &record.event {
+            Event::Claim(claim) => {
+                let job = &claim.job;
+                traced.insert(job.id.clone());
+                open.insert(job.id.clone(), jobs.len());
+                jobs.push(ReplayedJob {
+                    seq: record.seq,
+                    job_id: job.id.clone(),
+                    job_type: job.job_type.clone(),
+                    recorded: None,
+                    replayed: None,
+                });
+            }
+            Event::Finish {
+                job_id,
+                outcome,
+                error,
+            } => {
+                if let Some(index) = open.remove(job_id) {
+                    jobs[index].recorded = Some(*outcome);
+                    if let Some(error) = error {
+                        recorded_errors.insert(index, error.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    let mut next = 0;
+    let mut running: HashMap<String, (usize, tokio::task::JoinHandle<()>)> = HashMap::new();
+    for record in records {
+        match record.event {
+            Event::Claim(claim) => {
+                let Claim {
+                    job,
+                    project,
+                    environment,
+                    task,
+                    retry_limit,
+                } = *claim;
+                let index = next;
+                next += 1;
+
+                let project = project.map(scratch_project).transpose()?;
+                let environment = environment
+                    .map(|env| mock_environment(env, project.as_ref()))
+                    .transpose()?;
+                let task = task.map(|mut task| {
+                    task.provider = REPLAY_PROVIDER.to_string();
+                    task
+                });
+                db::restore_traced_job(
+                    &job,
+                    project.as_ref(),
+                    environment.as_ref(),
+                    task.as_ref(),
+                )?;
+                let job = claim_traced(&job.id, &traced)?;
+
+                let failure = match jobs[index].recorded {
+                    Some(outcome @ (Outcome::Deferred | Outcome::Interrupted)) => {
+                        let reason = recorded_errors.remove(&index).unwrap_or_default();
+                        db::defer_job(&job.id, &reason, PARKED_SECONDS)?;
+                        jobs[index].replayed = Some(outcome);
+                        continue;
+                    }
+                    Some(Outcome::Retry | Outcome::Dead) => Some(RecordedFailure {
+                        error: recorded_errors.remove(&index).unwrap_or_default(),
+                        retry_limit,
+                    }),
+                    Some(Outcome::Complete) | None => None,
+                };
+
+                {
+                    let mut replaying = replaying.lock().expect("replay lock poisoned");
+                    replaying.outcomes.remove(&job.id);
+                    match failure {
+                        Some(failure) => replaying.failures.insert(job.id.clone(), failure),
+                        None => replaying.failures.remove(&job.id),
+                    };
+                }
+                let job_id = job.id.clone();
+                let handle = tokio::spawn(super::jobs::process_job(job));
+                running.insert(job_id, (index, handle));
+            }
+            Event::Finish { job_id, .. } => {
+                if let Some((index, handle)) = running.remove(&job_id) {
+                    let _ = handle.await;
+                    jobs[index].replayed = take_outcome(replaying, &job_id);
+                }
+            }
+        }
+    }
+    for (job_id, (index, handle)) in running {
+        let _ = handle.await;
+        jobs[index].replayed = take_outcome(replaying, &job_id);
+    }
+    Ok(jobs)
+}
+
+/// Claim the job with ID `job_id` the way the job processor claims jobs.
+/// Jobs claimed ahead of it are put back: ones the replay queued on its
+/// own for good, since the trace has them under other IDs, and traced ones
+/// until the trace claims them.
+fn claim_traced(job_id: &str, traced: &HashSet<String>) -> anyhow::Result<db::Job> {
+    loop {
+        let Some(job) = db::claim_pending_jobs(1, super::jobs::JOB_LEASE_SECONDS)?.pop() else {
+            anyhow::bail!("job {job_id} from the trace wasn't ready to claim");
+        };
+        if job.id == job_id {
+            return Ok(job);
+        }
+        if traced.contains(&job.id) {
+            db::defer_job(
+                &job.id,
+                "waiting for its claim in the trace",
+                PARKED_SECONDS,
+            )?;
+        } else {
+            db::mark_job_complete(&job.id)?;
+        }
+    }
+}
+
+fn take_outcome(replaying: &Mutex<Replaying>, job_id: &str) -> Option<Outcome> {
+    replaying
+        .lock()
+        .expect("replay lock poisoned")
+        .outcomes
+        .remove(job_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_read_back_as_they_were_written() {
+        let line = r#"{"seq":2,"at":"2026-01-02T03:04:05Z","event":"finish","job_id":"j1","outcome":"retry","error":"mock claim failed"}"#;
+        let record: Record = serde_json::from_str(line).unwrap();
+        assert_eq!(record.seq, 2);
+        let Event::Finish {
+            job_id,
+            outcome,
+            error,
+        } = &record.event
+        else {
+            panic!("expected a finish event");
+        };
+        assert_eq!(job_id, "j1");
+        assert_eq!(*outcome, Outcome::Retry);
+        assert_eq!(error.as_deref(), Some("mock claim failed"));
+        assert_eq!(serde_json::to_string(&record).unwrap(), line);
+    }
+}
@@ -0,0 +1,454 @@
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Transaction};
+
+use super::state::METADATA_PATH_KEYS;
+use super::{
+    IMPORTED_ENVIRONMENT_PROVIDER, connect, db_path, insert_job_tx, now_rfc3339,
+    stage_remove_task_tx,
+};
+
+/// A row that points at something missing, or is stuck in a state nothing
+/// will move it out of. Foreign keys catch some of these, but not edits made
+/// with them off, files removed from disk, or jobs lost in a crash.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct IntegrityProblem {
+    pub kind: ProblemKind,
+    /// `project`, `environment`, `task`, or `group`.
+    pub subject: String,
+    pub id: String,
+    pub message: String,
+    /// What `work db repair` does about it, or `None` if it needs a person.
+    pub fix: Option<String>,
+}
+
+impl IntegrityProblem {
+    /// Names the problem from a check through to the repair that fixes it.
+    pub fn key(&self) -> String {
+        format!("{}:{}", self.kind, self.id)
+    }
+}
+
+/// What's wrong with a row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemKind {
+    ProjectPathMissing,
+    EnvironmentMissingProject,
+    TaskMissingEnvironment,
+    TaskMissingProject,
+    TaskMissingGroup,
+    GroupMissingProject,
+    EnvironmentPathMissing,
+    EnvironmentStuckRemoving,
+    EnvironmentStuckPreparing,
+}
+
+impl ProblemKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ProjectPathMissing => "project_path_missing",
+            Self::EnvironmentMissingProject => "environment_missing_project",
+            Self::TaskMissingEnvironment => "task_missing_environment",
+            Self::TaskMissingProject => "task_missing_project",
+            Self::TaskMissingGroup => "task_missing_group",
+            Self::GroupMissingProject => "group_missing_project",
+            Self::EnvironmentPathMissing => "environment_path_missing",
+            Self::EnvironmentStuckRemoving => "environment_stuck_removing",
+            Self::EnvironmentStuckPreparing => "environment_stuck_preparing",
+        }
+    }
+}
+
+impl std::fmt::Display for ProblemKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct RepairSummary {
+    /// Keys of the problems fixed.
+    pub fixed: Vec<String>,
+    /// Keys asked for that are gone or have no automatic fix.
+    pub skipped: Vec<String>,
+}
+
+/// Run SQLite's own `PRAGMA integrity_check` on the database file, opened
+/// read-only so a damaged file is never migrated. Returns what SQLite found
+/// wrong; empty means the file is sound.
+pub fn sqlite_integrity_check() -> Result<Vec<String>, anyhow::Error> {
+    let path = db_path()?;
+    let conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| anyhow::anyhow!("could not open {}: {e}", path.display()))?;
+    let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+    let lines = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(lines.into_iter().filter(|line| line != "ok").collect())
+}
+
+pub fn check_integrity() -> Result<Vec<IntegrityProblem>, anyhow::Error> {
+    let conn = connect()?;
+    find_problems(&conn)
+}
+
+/// Fix the problems named by `keys`, as found by [`check_integrity`], in one
+/// transaction. Problems are looked up again first, so one fixed since the
+/// check, or by an earlier fix in the same repair, is skipped. Files of
+/// deleted tasks are removed only once the transaction has committed.
+pub fn repair_integrity(keys: &[String]) -> Result<RepairSummary, anyhow::Error> {
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+    let mut summary = RepairSummary::default();
+    let mut deleted_tasks = Vec::new();
+    for key in keys {
+        let problem = find_problems(&tx)?
+            .into_iter()
+            .find(|p| p.fix.is_some() && &p.key() == key);
+        match problem {
+            Some(problem) => {
+                if problem.kind == ProblemKind::TaskMissingEnvironment {
+                    deleted_tasks.push(problem.id.clone());
+                }
+                fix(&tx, &problem)?;
+                summary.fixed.push(key.clone());
+            }
+            None => summary.skipped.push(key.clone()),
+        }
+    }
+    tx.commit()?;
+    for task_id in &deleted_tasks {
+        remove_task_files(task_id);
+    }
+    Ok(summary)
+}
+
+/// Remove the log, result, and artifacts a deleted task left on disk.
+fn remove_task_files(id: &str) {
+    for path in [
+        crate::paths::task_log_path(id),
+        crate::paths::task_result_path(id),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        let _ = std::fs::remove_file(path);
+    }
+    if let Ok(artifact_dir) = crate::paths::task_artifact_dir(id) {
+        let _ = std::fs::remove_dir_all(artifact_dir);
+    }
+}
+
+fn find_problems(conn: &Connection) -> Result<Vec<IntegrityProblem>, anyhow::Error> {
+    let mut problems = Vec::new();
+    let mut problem =
+        |kind: ProblemKind, subject: &str, id: String, message: String, fix: Option<&str>| {
+            problems.push(IntegrityProblem {
+                kind,
+                subject: subject.to_string(),
+                id,
+                message,
+                fix: fix.map(str::to_string),
+            });
+        };
+
+    for (id, path) in query_pairs(
+        conn,
+        "SELECT id, path FROM projects WHERE removing_at IS NULL ORDER BY name",
+    )? {
+        if !std::path::Path::new(&path).exists() {
+            problem(
+                ProblemKind::ProjectPathMissing,
+                "project",
+                id,
+                format!(
+                    "project path {path} does not exist; fix it with `work remap-paths` or remove the project"
+                ),
+                None,
+            );
+        }
+    }
+
+    for (id, project_id) in query_pairs(
+        conn,
+        "SELECT id, project_id FROM environments WHERE project_id NOT IN (SELECT id FROM projects) AND status != 'removing' ORDER BY created_at",
+    )? {
+        problem(
+            ProblemKind::EnvironmentMissingProject,
+            "environment",
+            id,
+            format!("environment refers to missing project {project_id}"),
+            Some("remove the environment, and its task if it has one"),
+        );
+    }
+
+    for (id, env_id) in query_pairs(
+        conn,
+        "SELECT id, environment_id FROM tasks WHERE environment_id NOT IN (SELECT id FROM environments) ORDER BY created_at",
+    )? {
+        problem(
+            ProblemKind::TaskMissingEnvironment,
+            "task",
+            id,
+            format!("task refers to missing environment {env_id}"),
+            Some("delete the task record and its log"),
+        );
+    }
+
+    for (id, project_id) in query_pairs(
+        conn,
+        "SELECT id, project_id FROM tasks WHERE project_id NOT IN (SELECT id FROM projects) AND environment_id IN (SELECT id FROM environments WHERE status != 'removing') ORDER BY created_at",
+    )? {
+        problem(
+            ProblemKind::TaskMissingProject,
+            "task",
+            id,
+            format!("task refers to missing project {project_id}"),
+            Some("remove the task and its environment"),
+        );
+    }
+
+    for (id, group_id) in query_pairs(
+        conn,
+        "SELECT id, group_id FROM tasks WHERE group_id IS NOT NULL AND group_id NOT IN (SELECT id FROM task_groups) ORDER BY created_at",
+    )? {
+        problem(
+            ProblemKind::TaskMissingGroup,
+            "task",
+            id,
+            format!("task refers to missing group {group_id}"),
+            Some("take the task out of the group"),
+        );
+    }
+
+    for (id, project_id) in query_pairs(
+        conn,
+        "SELECT id, project_id FROM task_groups WHERE project_id NOT IN (SELECT id FROM projects) ORDER BY created_at",
+    )? {
+        problem(
+            ProblemKind::GroupMissingProject,
+            "group",
+            id,
+            format!("group refers to missing project {project_id}"),
+            Some("delete the group, keeping its tasks"),
+        );
+    }
+
+    let environments: Vec<(String, String, String)> = conn
+        .prepare(
+            "SELECT id, status, metadata FROM environments WHERE provider != ?1 ORDER BY created_at",
+        )?
+        .query_map([IMPORTED_ENVIRONMENT_PROVIDER], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?
+        .collect::<Result<_, _>>()?;
+    for (id, status, metadata) in environments {
+        match status.as_str() {
+            "pool" | "in_use" | "degraded" => {
+                let metadata: serde_json::Value =
+                    serde_json::from_str(&metadata).unwrap_or_default();
+                let missing = METADATA_PATH_KEYS
+                    .iter()
+                    .filter_map(|key| metadata[key].as_str())
+                    .find(|path| !std::path::Path::new(path).exists());
+                if let Some(path) = missing {
+                    problem(
+                        ProblemKind::EnvironmentPathMissing,
+                        "environment",
+                        id,
+                        format!("{status} environment's path {path} does not exist"),
+                        Some("mark the environment failed"),
+                    );
+                }
+            }
+            "removing" if !has_live_job(conn, &["remove_environment", "remove_task"], &id)? => {
+                problem(
+                    ProblemKind::EnvironmentStuckRemoving,
+                    "environment",
+                    id,
+                    "environment is being removed, but no job is removing it".to_string(),
+                    Some("queue its removal again"),
+                );
+            }
+            "preparing" if !has_live_job(conn, &["prepare_environment"], &id)? => {
+                problem(
+                    ProblemKind::EnvironmentStuckPreparing,
+                    "environment",
+                    id,
+                    "environment is being prepared, but no job is preparing it".to_string(),
+                    Some("mark the environment failed"),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    Ok(problems)
+}
+
+fn fix(tx: &Transaction<'_>, problem: &IntegrityProblem) -> Result<(), anyhow::Error> {
+    let id = problem.id.as_str();
+    match problem.kind {
+        ProblemKind::EnvironmentMissingProject => {
+            let task_id: Option<String> = tx
+                .query_row(
+                    "SELECT id FROM tasks WHERE environment_id = ?1",
+                    [id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            match task_id {
+                Some(task_id) => stage_remove_task_tx(tx, &task_id)?,
+                None => queue_environment_removal(tx, id)?,
+            }
+        }
+        ProblemKind::TaskMissingEnvironment => {
+            tx.execute("DELETE FROM tasks WHERE id = ?1", [id])?;
+        }
+        ProblemKind::TaskMissingProject => stage_remove_task_tx(tx, id)?,
+        ProblemKind::TaskMissingGroup => {
+            tx.execute(
+                "UPDATE tasks SET group_id = NULL, updated_at = ?1 WHERE id = ?2",
+                rusqlite::params![now_rfc3339(), id],
+            )?;
+        }
+        ProblemKind::GroupMissingProject => {
+            tx.execute("UPDATE tasks SET group_id = NULL WHERE group_id = ?1", [id])?;
+            tx.execute("DELETE FROM task_groups WHERE id = ?1", [id])?;
+        }
+        ProblemKind::EnvironmentPathMissing | ProblemKind::EnvironmentStuckPreparing => {
+            tx.execute(
+                "UPDATE environments SET status = 'failed', progress = NULL, progress_message = NULL, updated_at = ?1 WHERE id = ?2",
+                rusqlite::params![now_rfc3339(), id],
+            )?;
+        }
+        ProblemKind::EnvironmentStuckRemoving => {
+            let task_id: Option<String> = tx
+                .query_row(
+                    "SELECT id FROM tasks WHERE environment_id = ?1",
+                    [id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            match task_id {
+                Some(task_id) => stage_remove_task_tx(tx, &task_id)?,
+                None => queue_environment_removal(tx, id)?,
+            }
+        }
+        ProblemKind::ProjectPathMissing => anyhow::bail!("no repair for {}", problem.kind),
+    }
+    Ok(())
+}
+
+/// Mark an environment removing and queue the job that removes it, whatever
+/// its status.
+fn queue_environment_removal(tx: &Transaction<'_>, id: &str) -> Result<(), anyhow::Error> {
+    tx.execute(
+        "UPDATE environments SET status = 'removing', updated_at = ?1 WHERE id = ?2",
+        rusqlite::params![now_rfc3339(), id],
+    )?;
+    let dedupe = format!("remove_environment:env:{id}");
+    insert_job_tx(
+        tx,
+        "remove_environment",
+        &serde_json::json!({ "env_id": id }),
+        Some(&dedupe),
+    )?;
+    Ok(())
+}
+
+/// Whether a pending or running job of one of `job_types` acts on `env_id`.
+fn has_live_job(
+    conn: &Connection,
+    job_types: &[&str],
+    env_id: &str,
+) -> Result<bool, anyhow::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT type FROM jobs WHERE status IN ('pending', 'running') AND json_extract(payload, '$.env_id') = ?1",
+    )?;
+    let types = stmt
+        .query_map([env_id], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(types.iter().any(|t| job_types.contains(&t.as_str())))
+}
+
+fn query_pairs(conn: &Connection, sql: &str) -> Result<Vec<(String, String)>, anyhow::Error> {
+    Ok(conn
+        .prepare(sql)?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A database with a project at `/`, an orphaned environment and task
+    /// added with foreign keys off, and a pooled environment whose worktree
+    /// is gone.
+    fn broken_database() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        super::super::migrations::run(&mut conn).unwrap();
+        conn.execute_batch(
+            r#"
+            PRAGMA foreign_keys = OFF;
+            INSERT INTO projects (id, name, path, created_at, updated_at)
+                VALUES ('p1', 'root', '/', '', '');
+            INSERT INTO environments (id, project_id, provider, status, metadata, created_at, updated_at)
+                VALUES ('e1', 'gone', 'git-worktree', 'pool', '{}', '1', '1'),
+                       ('e2', 'p1', 'git-worktree', 'pool', '{"worktree_path": "/nonexistent/e2"}', '2', '2');
+            INSERT INTO tasks (id, environment_id, project_id, provider, description, status, created_at, updated_at)
+                VALUES ('t1', 'missing', 'p1', 'echo', 'orphan', 'complete', '3', '3');
+            "#,
+        )
+        .unwrap();
+        conn
+    }
+
+    fn keys(conn: &Connection) -> Vec<String> {
+        find_problems(conn)
+            .unwrap()
+            .iter()
+            .map(IntegrityProblem::key)
+            .collect()
+    }
+
+    #[test]
+    fn finds_dangling_references_and_missing_paths() {
+        let conn = broken_database();
+        assert_eq!(
+            keys(&conn),
+            [
+                "environment_missing_project:e1",
+                "task_missing_environment:t1",
+                "environment_path_missing:e2",
+            ]
+        );
+    }
+
+    #[test]
+    fn fixes_leave_nothing_to_report_but_queued_removals() {
+        let mut conn = broken_database();
+        let tx = conn.transaction().unwrap();
+        for problem in find_problems(&tx).unwrap() {
+            fix(&tx, &problem).unwrap();
+        }
+        tx.commit().unwrap();
+
+        assert!(keys(&conn).is_empty(), "{:?}", keys(&conn));
+        let status = |id: &str| -> String {
+            conn.query_row(
+                "SELECT status FROM environments WHERE id = ?1",
+                [id],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(status("e1"), "removing");
+        assert_eq!(status("e2"), "failed");
+        assert!(has_live_job(&conn, &["remove_environment"], "e1").unwrap());
+        let tasks: i64 = conn
+            .query_row("SELECT COUNT(*) FROM tasks", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(tasks, 0);
+    }
+}
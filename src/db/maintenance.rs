@@ -0,0 +1,82 @@
+use rusqlite::Connection;
+
+use super::connect;
+
+/// What a maintenance pass did.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct MaintenanceSummary {
+    /// Completed jobs deleted for being past retention.
+    pub jobs_pruned: usize,
+    /// Events deleted for being past retention.
+    pub events_pruned: usize,
+    /// How much smaller the database file got.
+    pub bytes_freed: i64,
+    /// Whether the file was switched to incremental vacuum, which rewrites
+    /// it once.
+    pub converted: bool,
+    pub elapsed_ms: u64,
+}
+
+/// Delete completed jobs last updated before `job_cutoff` and events
+/// recorded before `event_cutoff`, hand the space they took back to the
+/// filesystem, and let SQLite refresh its query planner statistics.
+///
+/// Dead jobs are kept however old they are: they wait for someone to retry
+/// or purge them.
+pub fn maintain(
+    job_cutoff: chrono::DateTime<chrono::Utc>,
+    event_cutoff: chrono::DateTime<chrono::Utc>,
+) -> Result<MaintenanceSummary, anyhow::Error> {
+    let started = std::time::Instant::now();
+    let mut conn = connect()?;
+    let bytes_before = file_size(&conn)?;
+
+    let tx = conn.transaction()?;
+    let seq_before = super::latest_event_seq_with(&tx)?;
+    let jobs_pruned = tx.execute(
+        "DELETE FROM jobs WHERE status = 'complete' AND updated_at < ?1",
+        rusqlite::params![cutoff(job_cutoff)],
+    )?;
+    // Deleting a job records a `removed` event for it. Nobody is waiting to
+    // hear about jobs that finished a week ago, so those go too, rather
+    // than refilling the table this is emptying.
+    tx.execute(
+        "DELETE FROM events WHERE seq > ?1 AND kind = 'job' AND action = 'removed'",
+        rusqlite::params![seq_before],
+    )?;
+    let events_pruned = tx.execute(
+        "DELETE FROM events WHERE created_at < ?1",
+        rusqlite::params![cutoff(event_cutoff)],
+    )?;
+    tx.commit()?;
+
+    // A database created before maintenance existed doesn't track free
+    // pages, and only a full VACUUM can turn that on.
+    let converted = conn.query_row("PRAGMA auto_vacuum", [], |row| row.get::<_, i64>(0))? != 2;
+    if converted {
+        conn.execute_batch("PRAGMA auto_vacuum = INCREMENTAL; VACUUM;")?;
+    } else {
+        conn.execute_batch("PRAGMA incremental_vacuum;")?;
+    }
+    conn.execute_batch("PRAGMA optimize;")?;
+
+    Ok(MaintenanceSummary {
+        jobs_pruned,
+        events_pruned,
+        bytes_freed: bytes_before - file_size(&conn)?,
+        converted,
+        elapsed_ms: started.elapsed().as_millis() as u64,
+    })
+}
+
+fn cutoff(at: chrono::DateTime<chrono::Utc>) -> String {
+    at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+fn file_size(conn: &Connection) -> Result<i64, anyhow::Error> {
+    Ok(conn.query_row(
+        "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+        [],
+        |row| row.get(0),
+    )?)
+}
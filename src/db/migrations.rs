@@ -6,11 +6,182 @@ pub struct Migration {
     pub sql: &'static str,
 }
 
-pub const MIGRATIONS: &[Migration] = &[Migration {
-    version: 1,
-    name: "0001_init",
-    sql: include_str!("../../migrations/0001_init.sql"),
-}];
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "0001_init",
+        sql: include_str!("../../migrations/0001_init.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "0002_task_results",
+        sql: include_str!("../../migrations/0002_task_results.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "0003_task_resource_usage",
+        sql: include_str!("../../migrations/0003_task_resource_usage.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "0004_task_processes",
+        sql: include_str!("../../migrations/0004_task_processes.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "0005_task_search",
+        sql: include_str!("../../migrations/0005_task_search.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "0006_task_labels",
+        sql: include_str!("../../migrations/0006_task_labels.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "0007_task_status_taxonomy",
+        sql: include_str!("../../migrations/0007_task_status_taxonomy.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "0008_task_source_ref",
+        sql: include_str!("../../migrations/0008_task_source_ref.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "0009_environment_progress",
+        sql: include_str!("../../migrations/0009_environment_progress.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "0010_environment_degraded_status",
+        sql: include_str!("../../migrations/0010_environment_degraded_status.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "0011_task_requirements",
+        sql: include_str!("../../migrations/0011_task_requirements.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "0012_job_dead_letters",
+        sql: include_str!("../../migrations/0012_job_dead_letters.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "0013_task_groups",
+        sql: include_str!("../../migrations/0013_task_groups.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "0014_events",
+        sql: include_str!("../../migrations/0014_events.sql"),
+    },
+    Migration {
+        version: 15,
+        name: "0015_task_priority",
+        sql: include_str!("../../migrations/0015_task_priority.sql"),
+    },
+    Migration {
+        version: 16,
+        name: "0016_task_notes",
+        sql: include_str!("../../migrations/0016_task_notes.sql"),
+    },
+    Migration {
+        version: 17,
+        name: "0017_project_removal",
+        sql: include_str!("../../migrations/0017_project_removal.sql"),
+    },
+    Migration {
+        version: 18,
+        name: "0018_environment_status_history",
+        sql: include_str!("../../migrations/0018_environment_status_history.sql"),
+    },
+    Migration {
+        version: 19,
+        name: "0019_environment_claim_tokens",
+        sql: include_str!("../../migrations/0019_environment_claim_tokens.sql"),
+    },
+    Migration {
+        version: 20,
+        name: "0020_environment_prepare_outcomes",
+        sql: include_str!("../../migrations/0020_environment_prepare_outcomes.sql"),
+    },
+    Migration {
+        version: 21,
+        name: "0021_id_aliases",
+        sql: include_str!("../../migrations/0021_id_aliases.sql"),
+    },
+    Migration {
+        version: 22,
+        name: "0022_status_changed_at",
+        sql: include_str!("../../migrations/0022_status_changed_at.sql"),
+    },
+    Migration {
+        version: 23,
+        name: "0023_environment_outdated",
+        sql: include_str!("../../migrations/0023_environment_outdated.sql"),
+    },
+    Migration {
+        version: 24,
+        name: "0024_created_origin",
+        sql: include_str!("../../migrations/0024_created_origin.sql"),
+    },
+    Migration {
+        version: 25,
+        name: "0025_job_projects",
+        sql: include_str!("../../migrations/0025_job_projects.sql"),
+    },
+    Migration {
+        version: 26,
+        name: "0026_task_costs",
+        sql: include_str!("../../migrations/0026_task_costs.sql"),
+    },
+    Migration {
+        version: 27,
+        name: "0027_environment_snapshots",
+        sql: include_str!("../../migrations/0027_environment_snapshots.sql"),
+    },
+    Migration {
+        version: 28,
+        name: "0028_task_search_update",
+        sql: include_str!("../../migrations/0028_task_search_update.sql"),
+    },
+    Migration {
+        version: 29,
+        name: "0029_task_list_order",
+        sql: include_str!("../../migrations/0029_task_list_order.sql"),
+    },
+    Migration {
+        version: 30,
+        name: "0030_project_status_counts",
+        sql: include_str!("../../migrations/0030_project_status_counts.sql"),
+    },
+    Migration {
+        version: 31,
+        name: "0031_project_aliases",
+        sql: include_str!("../../migrations/0031_project_aliases.sql"),
+    },
+    Migration {
+        version: 32,
+        name: "0032_environment_locks",
+        sql: include_str!("../../migrations/0032_environment_locks.sql"),
+    },
+];
+
+/// Whether every migration has been applied, so queries can rely on the
+/// latest schema.
+pub fn is_current(conn: &Connection) -> Result<bool, anyhow::Error> {
+    let latest = MIGRATIONS.last().map_or(0, |m| m.version);
+    let current: i64 = conn
+        .query_row(
+            "SELECT COALESCE(MAX(version), 0) FROM migrations",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap_or(0);
+    Ok(current >= latest)
+}
 
 pub fn run(conn: &mut Connection) -> Result<(), anyhow::Error> {
     conn.execute_batch(
@@ -29,6 +200,20 @@ pub fn run(conn: &mut Connection) -> Result<(), anyhow::Error> {
         )
         .unwrap_or(0);
 
+    if current_version >= MIGRATIONS.last().map_or(0, |m| m.version) {
+        return Ok(());
+    }
+
+    // Changing a constraint means rebuilding the table, which breaks foreign
+    // keys that point at it until the rebuilt table takes the old name. They
+    // are off while migrating and checked before each migration commits.
+    conn.execute_batch("PRAGMA foreign_keys = OFF")?;
+    let result = apply(conn, current_version);
+    conn.execute_batch("PRAGMA foreign_keys = ON")?;
+    result
+}
+
+fn apply(conn: &mut Connection, current_version: i64) -> Result<(), anyhow::Error> {
     for migration in MIGRATIONS {
         if migration.version <= current_version {
             continue;
@@ -40,6 +225,16 @@ pub fn run(conn: &mut Connection) -> Result<(), anyhow::Error> {
 
         let tx = conn.transaction()?;
         tx.execute_batch(migration.sql)?;
+        let violations: i64 =
+            tx.query_row("SELECT COUNT(*) FROM pragma_foreign_key_check", [], |row| {
+                row.get(0)
+            })?;
+        if violations > 0 {
+            anyhow::bail!(
+                "migration {} left {violations} foreign key violations",
+                migration.name
+            );
+        }
         tx.execute(
             "INSERT INTO migrations (version, name, applied_at) VALUES (?1, ?2, ?3)",
             rusqlite::params![migration.version, migration.name, now],
@@ -1,12 +1,53 @@
+mod integrity;
+mod maintenance;
 mod migrations;
+mod search;
+mod state;
 
-use rusqlite::{Connection, OptionalExtension, Transaction};
+pub use integrity::{
+    IntegrityProblem, ProblemKind, RepairSummary, check_integrity, repair_integrity,
+    sqlite_integrity_check,
+};
 
-fn db_path() -> Result<std::path::PathBuf, anyhow::Error> {
+pub use maintenance::{MaintenanceSummary, maintain};
+
+pub use state::{
+    ExportedProject, ExportedTask, IMPORTED_ENVIRONMENT_PROVIDER, ImportConflict, ImportSummary,
+    PathMap, RemapSummary, STATE_FORMAT_VERSION, StateExport, export_state, import_state,
+    remap_path, remap_paths,
+};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Transaction};
+
+use crate::error::{Error, ErrorCode, bail_code};
+
+/// Where the database lives, whether or not it exists yet.
+pub fn db_path() -> Result<std::path::PathBuf, anyhow::Error> {
     Ok(crate::paths::data_dir()?.join("database.sqlite3"))
 }
 
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Open the database read-only from now on, without migrating it. For
+/// reading state while the daemon, which owns the database, is stopped.
+pub fn set_read_only() {
+    READ_ONLY.store(true, Ordering::Relaxed);
+}
+
 fn connect() -> Result<Connection, anyhow::Error> {
+    if READ_ONLY.load(Ordering::Relaxed) {
+        let path = db_path()?;
+        let conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|e| anyhow::anyhow!("could not open {}: {e}", path.display()))?;
+        if !migrations::is_current(&conn)? {
+            anyhow::bail!(
+                "the database predates this version of work; start the daemon to upgrade it"
+            );
+        }
+        return Ok(conn);
+    }
     let mut conn = Connection::open(db_path()?)?;
     conn.execute_batch("PRAGMA foreign_keys = ON;")?;
     migrations::run(&mut conn)?;
@@ -17,6 +58,78 @@ fn now_rfc3339() -> String {
     chrono::Utc::now().to_rfc3339()
 }
 
+/// A coded "not found" error carrying the missing ID in its details.
+fn not_found(code: ErrorCode, what: &str, id: &str) -> anyhow::Error {
+    Error::new(code, format!("{what} not found: {id}"))
+        .with_details(serde_json::json!({ "id": id }))
+        .into()
+}
+
+/// Turn what a user typed for a task or environment into its full ID:
+/// the ID itself, its alias (`t12`, `e7`), or a prefix of the ID that only
+/// one of them starts with.
+fn resolve_id(
+    what: &str,
+    alias_prefix: char,
+    code: ErrorCode,
+    reference: &str,
+) -> Result<String, anyhow::Error> {
+    let conn = connect()?;
+    let exists = conn
+        .query_row(
+            &format!("SELECT 1 FROM {what}s WHERE id = ?1"),
+            rusqlite::params![reference],
+            |_| Ok(()),
+        )
+        .optional()?;
+    if exists.is_some() {
+        return Ok(reference.to_string());
+    }
+
+    let alias = reference
+        .strip_prefix(alias_prefix)
+        .and_then(|n| n.parse::<i64>().ok());
+    if let Some(alias) = alias {
+        let id = conn
+            .query_row(
+                &format!("SELECT id FROM {what}_aliases WHERE alias = ?1"),
+                rusqlite::params![alias],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?;
+        return id.ok_or_else(|| not_found(code, what, reference));
+    }
+
+    // GLOB is case-sensitive, like IDs are; anything but letters and digits
+    // can't be part of one and would be taken as a pattern.
+    if reference.is_empty() || !reference.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(not_found(code, what, reference));
+    }
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id FROM {what}s WHERE id GLOB ?1 || '*' ORDER BY id LIMIT 6"
+    ))?;
+    let matches = stmt
+        .query_map(rusqlite::params![reference], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    match matches.as_slice() {
+        [] => Err(not_found(code, what, reference)),
+        [id] => Ok(id.clone()),
+        _ => Err(Error::new(
+            ErrorCode::AmbiguousId,
+            format!(
+                "{reference} matches more than one {what} ({}); type more of the ID",
+                matches.join(", ")
+            ),
+        )
+        .with_details(serde_json::json!({ "reference": reference, "matches": matches }))
+        .into()),
+    }
+}
+
+/// Queue a job, or return the pending or running one with the same
+/// `dedupe_key`. A job whose payload names a `task_id` takes that task's
+/// priority, and belongs to the project of that task or else of the
+/// environment its `env_id` names.
 fn insert_job_tx(
     tx: &Transaction<'_>,
     job_type: &str,
@@ -40,14 +153,17 @@ fn insert_job_tx(
 
         // Terminal jobs should not block replaying the same logical operation.
         tx.execute(
-            "UPDATE jobs SET dedupe_key = NULL WHERE dedupe_key = ?1 AND status IN ('complete', 'failed')",
+            "UPDATE jobs SET dedupe_key = NULL WHERE dedupe_key = ?1 AND status IN ('complete', 'dead')",
             rusqlite::params![dedupe_key],
         )?;
     }
 
     let id = crate::id::new_id();
     let insert_result = tx.execute(
-        "INSERT INTO jobs (id, type, payload, status, created_at, updated_at, dedupe_key, attempt, not_before, lease_expires_at, last_error) VALUES (?1, ?2, ?3, 'pending', ?4, ?5, ?6, 0, NULL, NULL, NULL)",
+        "INSERT INTO jobs (id, type, payload, status, created_at, updated_at, dedupe_key, attempt, not_before, lease_expires_at, last_error, priority, project_id) VALUES (?1, ?2, ?3, 'pending', ?4, ?5, ?6, 0, NULL, NULL, NULL,
+         COALESCE((SELECT CASE priority WHEN 'high' THEN 1 WHEN 'low' THEN -1 ELSE 0 END FROM tasks WHERE id = json_extract(?3, '$.task_id')), 0),
+         COALESCE((SELECT project_id FROM tasks WHERE id = json_extract(?3, '$.task_id')),
+                  (SELECT project_id FROM environments WHERE id = json_extract(?3, '$.env_id'))))",
         rusqlite::params![&id, job_type, payload_str, &now, &now, dedupe_key],
     );
 
@@ -88,6 +204,7 @@ pub fn reset() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+/// A registered project.
 #[derive(Clone, serde::Deserialize, serde::Serialize)]
 pub struct Project {
     pub id: String,
@@ -95,67 +212,397 @@ pub struct Project {
     pub path: String,
     pub created_at: String,
     pub updated_at: String,
+    /// Other names the project answers to, from adopting its path under them.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+impl Project {
+    /// Whether `name` is this project's name or one of its aliases.
+    pub fn answers_to(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
+}
+
+/// The columns [`row_to_project`] reads, in order.
+const PROJECT_COLUMNS: &str = "projects.id, projects.name, projects.path, projects.created_at, projects.updated_at, (SELECT json_group_array(alias) FROM (SELECT alias FROM project_aliases WHERE project_aliases.project_id = projects.id ORDER BY alias))";
+
+fn row_to_project(row: &rusqlite::Row) -> rusqlite::Result<Project> {
+    let aliases: String = row.get(5)?;
+    Ok(Project {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        path: row.get(2)?,
+        created_at: row.get(3)?,
+        updated_at: row.get(4)?,
+        aliases: serde_json::from_str(&aliases).unwrap_or_default(),
+    })
 }
 
 pub fn list_projects() -> Result<Vec<Project>, anyhow::Error> {
     let conn = connect()?;
-    let mut stmt =
-        conn.prepare("SELECT id, name, path, created_at, updated_at FROM projects ORDER BY name")?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {PROJECT_COLUMNS} FROM projects WHERE removing_at IS NULL ORDER BY name"
+    ))?;
     let projects = stmt
-        .query_map([], |row| {
-            Ok(Project {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                path: row.get(2)?,
-                created_at: row.get(3)?,
-                updated_at: row.get(4)?,
-            })
-        })?
+        .query_map([], row_to_project)?
         .collect::<Result<Vec<_>, _>>()?;
     Ok(projects)
 }
 
-pub fn delete_project(name: &str) -> Result<(), anyhow::Error> {
-    let conn = connect()?;
-    let rows = conn.execute(
-        "DELETE FROM projects WHERE name = ?1",
-        rusqlite::params![name],
+/// The project `dir` is in. With nested projects, the innermost one wins, so
+/// a directory resolves to the same project however they're listed.
+pub fn project_for_dir<'a>(projects: &'a [Project], dir: &std::path::Path) -> Option<&'a Project> {
+    projects
+        .iter()
+        .filter(|p| dir.starts_with(&p.path))
+        .max_by_key(|p| std::path::Path::new(&p.path).components().count())
+}
+
+/// What removing a project set in motion.
+#[derive(Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ProjectRemoval {
+    /// Whether the project was deleted right away. Otherwise it's hidden and
+    /// deleted once its environments have been removed.
+    pub removed: bool,
+    /// Environments queued for removal by their provider.
+    pub environment_ids: Vec<String>,
+    /// Tasks queued for removal, with `force`.
+    pub task_ids: Vec<String>,
+}
+
+/// Remove a project along with its environments. Pooled, failed, and other
+/// unattached environments are queued for removal by their provider. A
+/// project with tasks fails with [`ErrorCode::ProjectInUse`] unless `force`
+/// is set, which queues the tasks for removal too.
+pub fn delete_project(name: &str, force: bool) -> Result<ProjectRemoval, anyhow::Error> {
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+
+    let project_id: String = tx
+        .query_row(
+            "SELECT id FROM projects WHERE (name = ?1 OR id = (SELECT project_id FROM project_aliases WHERE alias = ?1)) AND removing_at IS NULL",
+            rusqlite::params![name],
+            |row| row.get(0),
+        )
+        .optional()?
+        .ok_or_else(|| not_found(ErrorCode::ProjectNotFound, "project", name))?;
+
+    let task_ids: Vec<String> = tx
+        .prepare(
+            "SELECT tasks.id FROM tasks JOIN environments ON environments.id = tasks.environment_id WHERE tasks.project_id = ?1 AND environments.status != 'removing' ORDER BY tasks.created_at",
+        )?
+        .query_map(rusqlite::params![&project_id], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    if !task_ids.is_empty() && !force {
+        return Err(Error::new(
+            ErrorCode::ProjectInUse,
+            format!(
+                "project {name} has {} task(s) using its environments; remove them too with --force",
+                task_ids.len()
+            ),
+        )
+        .with_details(serde_json::json!({ "name": name, "task_ids": task_ids }))
+        .into());
+    }
+    for task_id in &task_ids {
+        stage_remove_task_tx(&tx, task_id)?;
+    }
+
+    let environment_ids: Vec<String> = tx
+        .prepare(
+            "SELECT id FROM environments WHERE project_id = ?1 AND status != 'removing' AND id NOT IN (SELECT environment_id FROM tasks) ORDER BY created_at",
+        )?
+        .query_map(rusqlite::params![&project_id], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for env_id in &environment_ids {
+        stage_remove_environment_tx(&tx, env_id)?;
+    }
+
+    tx.execute(
+        "UPDATE tasks SET group_id = NULL WHERE project_id = ?1",
+        rusqlite::params![&project_id],
     )?;
-    if rows == 0 {
-        anyhow::bail!("project not found: {name}");
+    tx.execute(
+        "DELETE FROM task_groups WHERE project_id = ?1",
+        rusqlite::params![&project_id],
+    )?;
+    let removed = tx.execute(
+        "DELETE FROM projects WHERE id = ?1 AND id NOT IN (SELECT project_id FROM environments) AND id NOT IN (SELECT project_id FROM tasks)",
+        rusqlite::params![&project_id],
+    )? > 0;
+    if !removed {
+        tx.execute(
+            "UPDATE projects SET removing_at = ?1 WHERE id = ?2",
+            rusqlite::params![now_rfc3339(), &project_id],
+        )?;
     }
+
+    tx.commit()?;
+    Ok(ProjectRemoval {
+        removed,
+        environment_ids,
+        task_ids,
+    })
+}
+
+/// Give up removing the project `env_id` belongs to, once that
+/// environment's removal has died. The project shows up again, so `project
+/// rm` can be run once more to retry.
+pub fn abandon_project_removal(env_id: &str) -> Result<(), anyhow::Error> {
+    let conn = connect()?;
+    conn.execute(
+        "UPDATE projects SET removing_at = NULL WHERE removing_at IS NOT NULL AND id = (SELECT project_id FROM environments WHERE id = ?1)",
+        rusqlite::params![env_id],
+    )?;
+    Ok(())
+}
+
+/// Delete removed projects that no environment, task, or group refers to
+/// any more.
+fn delete_drained_projects(conn: &Connection) -> Result<(), anyhow::Error> {
+    conn.execute(
+        "DELETE FROM projects WHERE removing_at IS NOT NULL AND id NOT IN (SELECT project_id FROM environments) AND id NOT IN (SELECT project_id FROM tasks) AND id NOT IN (SELECT project_id FROM task_groups)",
+        [],
+    )?;
     Ok(())
 }
 
-pub fn create_project(name: &str, path: &std::path::Path) -> Result<(), anyhow::Error> {
+/// Register a project rooted at `path`. A path that resolves to a registered
+/// project's fails with [`ErrorCode::ProjectExists`], and one inside or
+/// around a registered project's with [`ErrorCode::ProjectPathOverlaps`]
+/// unless `nested` is set; either way `details` names the project.
+pub fn create_project(
+    name: &str,
+    path: &std::path::Path,
+    nested: bool,
+) -> Result<(), anyhow::Error> {
     let conn = connect()?;
+    let path = canonical_path(path);
+    let path = path.as_path();
+    if let Some((existing, overlap)) = conflicting_project(&conn, path, None, |_| nested)? {
+        let details = serde_json::json!({
+            "name": name,
+            "path": path,
+            "project": existing.name,
+            "project_path": existing.path,
+            "overlap": overlap,
+        });
+        match overlap {
+            PathOverlap::Same => {
+                return Err(Error::new(
+                    ErrorCode::ProjectExists,
+                    format!(
+                        "path {} is already registered as project {}",
+                        path.display(),
+                        existing.name
+                    ),
+                )
+                .with_details(details)
+                .into());
+            }
+            PathOverlap::Inside | PathOverlap::Contains if !nested => {
+                let relation = if overlap == PathOverlap::Inside {
+                    "inside"
+                } else {
+                    "contains"
+                };
+                return Err(Error::new(
+                    ErrorCode::ProjectPathOverlaps,
+                    format!(
+                        "path {} {relation} project {} ({}); register it anyway with --nested",
+                        path.display(),
+                        existing.name,
+                        existing.path
+                    ),
+                )
+                .with_details(details)
+                .into());
+            }
+            PathOverlap::Inside | PathOverlap::Contains => {}
+        }
+    }
+    if let Some(existing) = alias_owner(&conn, name)? {
+        return Err(Error::new(
+            ErrorCode::ProjectExists,
+            format!("{name} is already an alias of project {existing}"),
+        )
+        .with_details(serde_json::json!({ "name": name, "path": path }))
+        .into());
+    }
     let id = crate::id::new_id();
     let now = chrono::Utc::now().to_rfc3339();
-    conn.execute(
+    let result = conn.execute(
         "INSERT INTO projects (id, name, path, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
         rusqlite::params![id, name, path.to_string_lossy(), &now, &now],
-    )?;
+    );
+    if let Err(err) = result {
+        if err
+            .to_string()
+            .contains("UNIQUE constraint failed: projects.")
+        {
+            let removing = conn
+                .query_row(
+                    "SELECT 1 FROM projects WHERE (name = ?1 OR path = ?2) AND removing_at IS NOT NULL",
+                    rusqlite::params![name, path.to_string_lossy()],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some();
+            let message = if removing {
+                format!(
+                    "project {name} or path {} is still being removed",
+                    path.display()
+                )
+            } else {
+                format!(
+                    "project {name} or path {} is already registered",
+                    path.display()
+                )
+            };
+            return Err(Error::new(ErrorCode::ProjectExists, message)
+                .with_details(serde_json::json!({ "name": name, "path": path }))
+                .into());
+        }
+        return Err(err.into());
+    }
     Ok(())
 }
 
+/// How a new project's path relates to a registered project's.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PathOverlap {
+    /// Both resolve to the same directory.
+    Same,
+    /// The new path is inside the registered project.
+    Inside,
+    /// The registered project is inside the new path.
+    Contains,
+}
+
+impl PathOverlap {
+    /// How `path` relates to `existing`, both canonical, if they overlap.
+    fn of(path: &std::path::Path, existing: &std::path::Path) -> Option<Self> {
+        if existing == path {
+            Some(PathOverlap::Same)
+        } else if path.starts_with(existing) {
+            Some(PathOverlap::Inside)
+        } else if existing.starts_with(path) {
+            Some(PathOverlap::Contains)
+        } else {
+            None
+        }
+    }
+}
+
+/// `path` with symlinks and `..` resolved, or as it is if it doesn't exist.
+fn canonical_path(path: &std::path::Path) -> std::path::PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The project `name` is an alias of, if it's one.
+fn alias_owner(conn: &Connection, name: &str) -> Result<Option<String>, anyhow::Error> {
+    Ok(conn
+        .query_row(
+            "SELECT projects.name FROM project_aliases JOIN projects ON projects.id = project_aliases.project_id WHERE project_aliases.alias = ?1",
+            rusqlite::params![name],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+/// The registered project a project at `path`, which is canonical, can't
+/// share it with: one at the same path first, then the nearest one around
+/// or inside it that `nested` doesn't allow. The project with ID `except`,
+/// which is the one moving there, and projects being removed don't count.
+/// Every way of giving a project a path goes through this.
+fn conflicting_project(
+    conn: &Connection,
+    path: &std::path::Path,
+    except: Option<&str>,
+    nested: impl Fn(&Project) -> bool,
+) -> Result<Option<(Project, PathOverlap)>, anyhow::Error> {
+    let projects = conn
+        .prepare(&format!(
+            "SELECT {PROJECT_COLUMNS} FROM projects WHERE removing_at IS NULL"
+        ))?
+        .query_map([], row_to_project)?
+        .collect::<Result<Vec<_>, _>>()?;
+    let conflict = projects
+        .into_iter()
+        .filter(|project| except != Some(project.id.as_str()))
+        .filter_map(|project| {
+            let existing = canonical_path(std::path::Path::new(&project.path));
+            let overlap = PathOverlap::of(path, &existing)?;
+            if overlap != PathOverlap::Same && nested(&project) {
+                return None;
+            }
+            let distance = existing
+                .components()
+                .count()
+                .abs_diff(path.components().count());
+            Some((distance, project, overlap))
+        })
+        .min_by_key(|(distance, _, _)| *distance)
+        .map(|(_, project, overlap)| (project, overlap));
+    Ok(conflict)
+}
+
+/// Let the project named `name` answer to `alias` too, for a path that was
+/// registered again under another name. An alias already taken by a project
+/// or another alias fails with [`ErrorCode::ProjectExists`].
+pub fn add_project_alias(name: &str, alias: &str) -> Result<Project, anyhow::Error> {
+    let conn = connect()?;
+    let id: String = conn
+        .query_row(
+            "SELECT id FROM projects WHERE name = ?1 AND removing_at IS NULL",
+            rusqlite::params![name],
+            |row| row.get(0),
+        )
+        .optional()?
+        .ok_or_else(|| not_found(ErrorCode::ProjectNotFound, "project", name))?;
+    if alias != name {
+        let taken = conn
+            .query_row(
+                "SELECT 1 FROM projects WHERE name = ?1",
+                rusqlite::params![alias],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if taken {
+            bail_code!(ErrorCode::ProjectExists, "project {alias} already exists");
+        }
+        match alias_owner(&conn, alias)? {
+            Some(owner) if owner == name => {}
+            Some(owner) => bail_code!(
+                ErrorCode::ProjectExists,
+                "{alias} is already an alias of project {owner}"
+            ),
+            None => {
+                conn.execute(
+                    "INSERT INTO project_aliases (alias, project_id, created_at) VALUES (?1, ?2, ?3)",
+                    rusqlite::params![alias, id, now_rfc3339()],
+                )?;
+            }
+        }
+    }
+    get_project(&id)
+}
+
 pub fn get_project(id: &str) -> Result<Project, anyhow::Error> {
     let conn = connect()?;
-    let project = conn.query_row(
-        "SELECT id, name, path, created_at, updated_at FROM projects WHERE id = ?1",
+    conn.query_row(
+        &format!("SELECT {PROJECT_COLUMNS} FROM projects WHERE id = ?1"),
         rusqlite::params![id],
-        |row| {
-            Ok(Project {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                path: row.get(2)?,
-                created_at: row.get(3)?,
-                updated_at: row.get(4)?,
-            })
-        },
-    )?;
-    Ok(project)
+        row_to_project,
+    )
+    .optional()?
+    .ok_or_else(|| not_found(ErrorCode::ProjectNotFound, "project", id))
 }
 
+/// An isolated working copy of a project, managed by an environment provider.
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct Environment {
     pub id: String,
@@ -165,8 +612,138 @@ pub struct Environment {
     pub metadata: serde_json::Value,
     pub created_at: String,
     pub updated_at: String,
+    /// Fraction done (0.0 to 1.0) last reported by the provider while
+    /// preparing, updating, or claiming.
+    #[serde(default)]
+    pub progress: Option<f64>,
+    #[serde(default)]
+    pub progress_message: Option<String>,
+    /// Short number the environment also goes by, as `e<alias>`.
+    #[serde(default)]
+    pub alias: Option<i64>,
+    /// When `status` last changed.
+    #[serde(default)]
+    pub status_changed_at: Option<String>,
+    /// When the project watcher found this pooled environment missing the
+    /// commit a new one would start from. Cleared once it catches up.
+    #[serde(default)]
+    pub outdated_since: Option<String>,
+    /// Where the request that created the environment came from. None for
+    /// environments the daemon created on its own, such as pool refills.
+    #[serde(default)]
+    pub origin: Option<Origin>,
+}
+
+impl Environment {
+    /// Whether this is a pooled environment its base branch has moved past.
+    pub fn is_outdated(&self) -> bool {
+        self.status == "pool" && self.outdated_since.is_some()
+    }
+
+    /// The environment's alias as it's typed, e.g. `e7`.
+    pub fn short_id(&self) -> Option<String> {
+        self.alias.map(|n| format!("e{n}"))
+    }
+
+    /// The `display_name` the provider put in the metadata, such as a branch
+    /// or container name. Commands still take the ID.
+    pub fn display_name(&self) -> Option<&str> {
+        self.metadata["display_name"]
+            .as_str()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Reported progress for display, e.g. `40% cloning`.
+    pub fn progress_label(&self) -> Option<String> {
+        let percent = self.progress.map(|p| format!("{:.0}%", p * 100.0));
+        match (percent, self.progress_message.as_deref()) {
+            (Some(percent), Some(message)) => Some(format!("{percent} {message}")),
+            (percent, message) => percent.or(message.map(str::to_string)),
+        }
+    }
+
+    /// How long the environment has had its status, e.g. `3m`.
+    pub fn in_status_for(&self) -> Option<String> {
+        time_since(self.status_changed_at.as_deref()?, chrono::Utc::now())
+    }
+}
+
+/// Where a request that created a task or environment came from, as the
+/// client reported it.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Origin {
+    /// `cli`, `tui`, or `api` for a client that didn't say.
+    pub client: String,
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default)]
+    pub user: Option<String>,
+    /// The client's working directory.
+    #[serde(default)]
+    pub cwd: Option<String>,
+}
+
+impl Origin {
+    /// One line for display, e.g. `cli by jo@laptop in /src/app`.
+    pub fn describe(&self) -> String {
+        let mut out = self.client.clone();
+        match (&self.user, &self.host) {
+            (Some(user), Some(host)) => out.push_str(&format!(" by {user}@{host}")),
+            (Some(user), None) => out.push_str(&format!(" by {user}")),
+            (None, Some(host)) => out.push_str(&format!(" on {host}")),
+            (None, None) => {}
+        }
+        if let Some(cwd) = &self.cwd {
+            out.push_str(&format!(" in {cwd}"));
+        }
+        out
+    }
+}
+
+/// The [`Origin`] in the `created_via`, `created_host`, `created_user`, and
+/// `created_cwd` columns starting at `first`.
+fn row_to_origin(row: &rusqlite::Row, first: usize) -> rusqlite::Result<Option<Origin>> {
+    let Some(client) = row.get::<_, Option<String>>(first)? else {
+        return Ok(None);
+    };
+    Ok(Some(Origin {
+        client,
+        host: row.get(first + 1)?,
+        user: row.get(first + 2)?,
+        cwd: row.get(first + 3)?,
+    }))
+}
+
+/// The `created_*` column values for `origin`, in order.
+fn origin_columns(origin: Option<&Origin>) -> [Option<&str>; 4] {
+    match origin {
+        Some(o) => [
+            Some(o.client.as_str()),
+            o.host.as_deref(),
+            o.user.as_deref(),
+            o.cwd.as_deref(),
+        ],
+        None => [None; 4],
+    }
+}
+
+/// How long before `now` the timestamp `at` was, in its largest whole unit:
+/// `45s`, `3m`, `2h`, or `5d`.
+fn time_since(at: &str, now: chrono::DateTime<chrono::Utc>) -> Option<String> {
+    let at = chrono::DateTime::parse_from_rfc3339(at).ok()?;
+    let secs = (now - at.with_timezone(&chrono::Utc)).num_seconds().max(0);
+    Some(match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    })
 }
 
+/// The columns `row_to_environment` reads, in order.
+const ENVIRONMENT_COLUMNS: &str = "environments.id, environments.project_id, environments.provider, environments.status, environments.metadata, environments.created_at, environments.updated_at, environments.progress, environments.progress_message, (SELECT alias FROM environment_aliases WHERE environment_aliases.id = environments.id), (SELECT changed_at FROM environment_status_times WHERE environment_status_times.id = environments.id), environments.outdated_since, environments.created_via, environments.created_host, environments.created_user, environments.created_cwd";
+
 fn row_to_environment(row: &rusqlite::Row) -> rusqlite::Result<Environment> {
     let metadata_str: String = row.get(4)?;
     let metadata: serde_json::Value =
@@ -179,6 +756,12 @@ fn row_to_environment(row: &rusqlite::Row) -> rusqlite::Result<Environment> {
         metadata,
         created_at: row.get(5)?,
         updated_at: row.get(6)?,
+        progress: row.get(7)?,
+        progress_message: row.get(8)?,
+        alias: row.get(9)?,
+        status_changed_at: row.get(10)?,
+        outdated_since: row.get(11)?,
+        origin: row_to_origin(row, 12)?,
     })
 }
 
@@ -195,7 +778,7 @@ pub fn complete_preparing_environment(
     let now = now_rfc3339();
     let metadata_str = serde_json::to_string(metadata)?;
     let rows = conn.execute(
-        "UPDATE environments SET status = ?1, metadata = ?2, updated_at = ?3 WHERE id = ?4 AND status = 'preparing'",
+        "UPDATE environments SET status = ?1, metadata = ?2, updated_at = ?3, progress = NULL, progress_message = NULL WHERE id = ?4 AND status = 'preparing'",
         rusqlite::params![status, metadata_str, &now, id],
     )?;
     if rows == 0 {
@@ -206,92 +789,763 @@ pub fn complete_preparing_environment(
 
 pub fn get_environment(id: &str) -> Result<Environment, anyhow::Error> {
     let conn = connect()?;
-    let env = conn.query_row(
-        "SELECT id, project_id, provider, status, metadata, created_at, updated_at FROM environments WHERE id = ?1",
-        rusqlite::params![id],
-        row_to_environment,
-    )?;
+    let env = conn
+        .query_row(
+            &format!("SELECT {ENVIRONMENT_COLUMNS} FROM environments WHERE id = ?1"),
+            rusqlite::params![id],
+            row_to_environment,
+        )
+        .optional()?
+        .ok_or_else(|| not_found(ErrorCode::EnvNotFound, "environment", id))?;
     Ok(env)
 }
 
-pub fn list_environments() -> Result<Vec<Environment>, anyhow::Error> {
+/// The full ID of the environment `reference` names: its ID, its alias
+/// (`e7`), or an unambiguous prefix of its ID.
+pub fn resolve_environment_id(reference: &str) -> Result<String, anyhow::Error> {
+    resolve_id("environment", 'e', ErrorCode::EnvNotFound, reference)
+}
+
+/// One status an environment has held, recorded by a trigger whenever its
+/// status changes.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct EnvironmentStatusChange {
+    pub status: String,
+    /// `None` for the status the environment was created with.
+    pub previous_status: Option<String>,
+    /// The job that was running for the environment at the time, if any.
+    /// Changes made directly through the API, such as queuing a removal,
+    /// have none.
+    pub job_id: Option<String>,
+    pub created_at: String,
+}
+
+/// An environment's status changes, oldest first.
+pub fn environment_status_history(id: &str) -> Result<Vec<EnvironmentStatusChange>, anyhow::Error> {
     let conn = connect()?;
+    let exists = conn
+        .query_row("SELECT 1 FROM environments WHERE id = ?1", [id], |_| Ok(()))
+        .optional()?
+        .is_some();
+    if !exists {
+        return Err(not_found(ErrorCode::EnvNotFound, "environment", id));
+    }
     let mut stmt = conn.prepare(
-        "SELECT id, project_id, provider, status, metadata, created_at, updated_at FROM environments ORDER BY id",
+        "SELECT status, previous_status, job_id, created_at FROM environment_status_history WHERE environment_id = ?1 ORDER BY seq",
     )?;
-    let envs = stmt
-        .query_map([], row_to_environment)?
+    let changes = stmt
+        .query_map([id], |row| {
+            Ok(EnvironmentStatusChange {
+                status: row.get(0)?,
+                previous_status: row.get(1)?,
+                job_id: row.get(2)?,
+                created_at: row.get(3)?,
+            })
+        })?
         .collect::<Result<Vec<_>, _>>()?;
-    Ok(envs)
+    Ok(changes)
 }
 
-pub fn update_environment_metadata(
-    id: &str,
-    metadata: &serde_json::Value,
-) -> Result<(), anyhow::Error> {
-    let conn = connect()?;
-    let now = now_rfc3339();
-    let metadata_str = serde_json::to_string(metadata)?;
-    let rows = conn.execute(
-        "UPDATE environments SET metadata = ?1, updated_at = ?2 WHERE id = ?3",
-        rusqlite::params![metadata_str, &now, id],
-    )?;
-    if rows == 0 {
-        anyhow::bail!("environment not found: {id}");
-    }
-    Ok(())
+/// A copy of an environment's files, taken with `work env snapshot`.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct EnvironmentSnapshot {
+    pub id: String,
+    pub environment_id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    /// What the provider needs to restore it.
+    pub metadata: serde_json::Value,
+    #[serde(default)]
+    pub size_bytes: Option<u64>,
+    pub created_at: String,
+    /// When it was last restored, if ever.
+    #[serde(default)]
+    pub restored_at: Option<String>,
 }
 
-pub fn update_environment_status(id: &str, status: &str) -> Result<(), anyhow::Error> {
-    let conn = connect()?;
-    let now = now_rfc3339();
-    let rows = conn.execute(
-        "UPDATE environments SET status = ?1, updated_at = ?2 WHERE id = ?3",
-        rusqlite::params![status, &now, id],
-    )?;
-    if rows == 0 {
-        anyhow::bail!("environment not found: {id}");
+impl EnvironmentSnapshot {
+    /// The snapshot's name, or else the start of its ID.
+    pub fn label(&self) -> &str {
+        self.name
+            .as_deref()
+            .unwrap_or_else(|| &self.id[..self.id.len().min(8)])
     }
-    Ok(())
 }
 
-fn claim_environment_tx(tx: &Transaction<'_>, id: &str) -> Result<(), anyhow::Error> {
+const SNAPSHOT_COLUMNS: &str =
+    "id, environment_id, name, metadata, size_bytes, created_at, restored_at";
+
+fn row_to_snapshot(row: &rusqlite::Row) -> rusqlite::Result<EnvironmentSnapshot> {
+    let metadata: String = row.get(3)?;
+    Ok(EnvironmentSnapshot {
+        id: row.get(0)?,
+        environment_id: row.get(1)?,
+        name: row.get(2)?,
+        metadata: serde_json::from_str(&metadata).unwrap_or(serde_json::json!({})),
+        size_bytes: row.get(4)?,
+        created_at: row.get(5)?,
+        restored_at: row.get(6)?,
+    })
+}
+
+/// Record a snapshot the provider has taken of environment `env_id`.
+pub fn create_environment_snapshot(
+    id: &str,
+    env_id: &str,
+    name: Option<&str>,
+    metadata: &serde_json::Value,
+    size_bytes: Option<u64>,
+) -> Result<EnvironmentSnapshot, anyhow::Error> {
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
     let now = now_rfc3339();
+    // Touching the environment tells clients watching for events that its
+    // snapshots changed.
     let rows = tx.execute(
-        "UPDATE environments SET status = 'in_use', updated_at = ?1 WHERE id = ?2 AND status = 'pool'",
-        rusqlite::params![&now, id],
+        "UPDATE environments SET updated_at = ?1 WHERE id = ?2",
+        rusqlite::params![&now, env_id],
     )?;
     if rows == 0 {
-        anyhow::bail!("environment {id} is not in the pool (may not exist or already claimed)");
+        return Err(not_found(ErrorCode::EnvNotFound, "environment", env_id));
     }
-    Ok(())
+    tx.execute(
+        "INSERT INTO environment_snapshots (id, environment_id, name, metadata, size_bytes, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        rusqlite::params![id, env_id, name, metadata.to_string(), size_bytes, &now],
+    )?;
+    let snapshot = tx.query_row(
+        &format!("SELECT {SNAPSHOT_COLUMNS} FROM environment_snapshots WHERE id = ?1"),
+        [id],
+        row_to_snapshot,
+    )?;
+    tx.commit()?;
+    Ok(snapshot)
 }
 
-pub fn delete_environment(id: &str) -> Result<(), anyhow::Error> {
+/// An environment's snapshots, newest first.
+pub fn list_environment_snapshots(env_id: &str) -> Result<Vec<EnvironmentSnapshot>, anyhow::Error> {
     let conn = connect()?;
-    let rows = conn.execute(
-        "DELETE FROM environments WHERE id = ?1",
-        rusqlite::params![id],
-    )?;
-    if rows == 0 {
-        anyhow::bail!("environment not found: {id}");
+    let exists = conn
+        .query_row("SELECT 1 FROM environments WHERE id = ?1", [env_id], |_| {
+            Ok(())
+        })
+        .optional()?
+        .is_some();
+    if !exists {
+        return Err(not_found(ErrorCode::EnvNotFound, "environment", env_id));
     }
-    Ok(())
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {SNAPSHOT_COLUMNS} FROM environment_snapshots WHERE environment_id = ?1 ORDER BY created_at DESC, rowid DESC"
+    ))?;
+    let snapshots = stmt
+        .query_map([env_id], row_to_snapshot)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(snapshots)
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
-pub struct Task {
-    pub id: String,
-    pub environment_id: String,
-    pub project_id: String,
-    pub provider: String,
-    pub description: String,
-    pub status: String,
-    pub created_at: String,
-    pub updated_at: String,
+/// The snapshot of environment `env_id` that `reference` names: its ID, a
+/// prefix of it that only one snapshot starts with, or its name, taking the
+/// newest snapshot with that name.
+pub fn find_environment_snapshot(
+    env_id: &str,
+    reference: &str,
+) -> Result<EnvironmentSnapshot, anyhow::Error> {
+    let snapshots = list_environment_snapshots(env_id)?;
+    if let Some(snapshot) = snapshots.iter().find(|s| s.id == reference).or_else(|| {
+        snapshots
+            .iter()
+            .find(|s| s.name.as_deref() == Some(reference))
+    }) {
+        return Ok(snapshot.clone());
+    }
+    let matches: Vec<&EnvironmentSnapshot> = snapshots
+        .iter()
+        .filter(|s| !reference.is_empty() && s.id.starts_with(reference))
+        .collect();
+    match matches.as_slice() {
+        [] => Err(not_found(
+            ErrorCode::SnapshotNotFound,
+            "snapshot",
+            reference,
+        )),
+        [snapshot] => Ok((*snapshot).clone()),
+        _ => {
+            let ids: Vec<&str> = matches.iter().map(|s| s.id.as_str()).collect();
+            Err(Error::new(
+                ErrorCode::AmbiguousId,
+                format!(
+                    "{reference} matches more than one snapshot ({}); type more of the ID",
+                    ids.join(", ")
+                ),
+            )
+            .with_details(serde_json::json!({ "reference": reference, "matches": ids }))
+            .into())
+        }
+    }
 }
 
+/// Note that `snapshot` was put back into its environment.
+pub fn mark_snapshot_restored(
+    snapshot: &EnvironmentSnapshot,
+) -> Result<EnvironmentSnapshot, anyhow::Error> {
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+    let now = now_rfc3339();
+    tx.execute(
+        "UPDATE environments SET updated_at = ?1 WHERE id = ?2",
+        rusqlite::params![&now, &snapshot.environment_id],
+    )?;
+    tx.execute(
+        "UPDATE environment_snapshots SET restored_at = ?1 WHERE id = ?2",
+        rusqlite::params![&now, &snapshot.id],
+    )?;
+    tx.commit()?;
+    Ok(EnvironmentSnapshot {
+        restored_at: Some(now),
+        ..snapshot.clone()
+    })
+}
+
+/// A snapshot or restore's hold on an environment, from
+/// [`lock_environment`]. Dropping it lets go.
+pub struct EnvironmentLock {
+    env_id: String,
+}
+
+impl Drop for EnvironmentLock {
+    fn drop(&mut self) {
+        let released = connect().and_then(|conn| {
+            conn.execute(
+                "DELETE FROM environment_locks WHERE environment_id = ?1",
+                [&self.env_id],
+            )?;
+            Ok(())
+        });
+        if let Err(e) = released {
+            tracing::warn!(env_id = %self.env_id, error = %e, "failed to unlock environment");
+        }
+    }
+}
+
+/// Hold environment `env_id` for `operation`, `snapshot` or `restore`, if
+/// it's ready: in the pool, in use, or degraded. A restore also needs no
+/// task running in it. While the lock is held, tasks don't start in the
+/// environment and its jobs wait; another operation on it fails with
+/// [`ErrorCode::EnvBusy`].
+pub fn lock_environment(env_id: &str, operation: &str) -> Result<EnvironmentLock, anyhow::Error> {
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+    let status: String = tx
+        .query_row(
+            "SELECT status FROM environments WHERE id = ?1",
+            [env_id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .ok_or_else(|| not_found(ErrorCode::EnvNotFound, "environment", env_id))?;
+    match status.as_str() {
+        "pool" | "in_use" | "degraded" => {}
+        "removing" => bail_code!(
+            ErrorCode::EnvRemoving,
+            "environment {env_id} is being removed"
+        ),
+        status => bail_code!(
+            ErrorCode::InvalidRequest,
+            "environment {env_id} is {status}; only a ready environment can be snapshotted or restored"
+        ),
+    }
+    if operation == "restore"
+        && let Some(task_id) = tx
+            .query_row(
+                "SELECT id FROM tasks WHERE environment_id = ?1 AND status = 'started' LIMIT 1",
+                [env_id],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?
+    {
+        return Err(Error::new(
+            ErrorCode::EnvBusy,
+            format!("task {task_id} is running in environment {env_id}; stop it before restoring"),
+        )
+        .with_details(serde_json::json!({ "task_id": task_id }))
+        .into());
+    }
+    let locked = tx.execute(
+        "INSERT INTO environment_locks (environment_id, operation, locked_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT (environment_id) DO NOTHING",
+        rusqlite::params![env_id, operation, now_rfc3339()],
+    )?;
+    if locked == 0 {
+        let held = environment_lock_tx(&tx, env_id)?.unwrap_or_default();
+        bail_code!(
+            ErrorCode::EnvBusy,
+            "environment {env_id} is busy with a {held}; try again once it's done"
+        );
+    }
+    tx.commit()?;
+    Ok(EnvironmentLock {
+        env_id: env_id.to_string(),
+    })
+}
+
+/// The operation holding environment `env_id`, if one is.
+pub fn environment_lock(env_id: &str) -> Result<Option<String>, anyhow::Error> {
+    environment_lock_tx(&connect()?, env_id)
+}
+
+fn environment_lock_tx(conn: &Connection, env_id: &str) -> Result<Option<String>, anyhow::Error> {
+    Ok(conn
+        .query_row(
+            "SELECT operation FROM environment_locks WHERE environment_id = ?1",
+            [env_id],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+/// Let go of every environment lock, at startup, when nothing can hold one.
+pub fn clear_environment_locks() -> Result<usize, anyhow::Error> {
+    Ok(connect()?.execute("DELETE FROM environment_locks", [])?)
+}
+
+/// How a provider's environments stand and how its recent preparations went.
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct ProviderStats {
+    pub provider: String,
+    /// Current environments by status.
+    pub environments: std::collections::BTreeMap<String, i64>,
+    /// Preparations counted, at most the number asked for, most recent
+    /// first. They outlive the environments they prepared.
+    pub prepares: i64,
+    pub prepare_failures: i64,
+    /// `prepare_failures / prepares`, or `None` with no preparations.
+    pub failure_rate: Option<f64>,
+    /// Mean duration of the successful preparations, retries included.
+    pub average_prepare_ms: Option<i64>,
+}
+
+/// Reported task costs for a project in a month.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct CostStats {
+    /// Project name.
+    pub project: String,
+    /// `YYYY-MM`, by when the tasks were created, in UTC.
+    pub month: String,
+    /// Tasks that reported any cost.
+    pub tasks: i64,
+    pub tokens_in: i64,
+    pub tokens_out: i64,
+    pub cost_usd: f64,
+}
+
+/// Reported costs of current tasks by project and month, newest month
+/// first, for one project or all of them.
+pub fn cost_stats(project_id: Option<&str>) -> Result<Vec<CostStats>, anyhow::Error> {
+    let conn = connect()?;
+    let stats = conn
+        .prepare(
+            "SELECT projects.name, substr(tasks.created_at, 1, 7) AS month, COUNT(*), COALESCE(SUM(tasks.tokens_in), 0), COALESCE(SUM(tasks.tokens_out), 0), COALESCE(SUM(tasks.cost_usd), 0.0) FROM tasks JOIN projects ON projects.id = tasks.project_id WHERE (tasks.tokens_in IS NOT NULL OR tasks.tokens_out IS NOT NULL OR tasks.cost_usd IS NOT NULL) AND (?1 IS NULL OR tasks.project_id = ?1) GROUP BY projects.name, month ORDER BY month DESC, projects.name",
+        )?
+        .query_map(rusqlite::params![project_id], |row| {
+            Ok(CostStats {
+                project: row.get(0)?,
+                month: row.get(1)?,
+                tasks: row.get(2)?,
+                tokens_in: row.get(3)?,
+                tokens_out: row.get(4)?,
+                cost_usd: row.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(stats)
+}
+
+/// Stats for every provider with environments or finished preparations,
+/// counting each provider's last `last` preparations.
+pub fn provider_stats(last: usize) -> Result<Vec<ProviderStats>, anyhow::Error> {
+    fn entry(
+        stats: &mut std::collections::BTreeMap<String, ProviderStats>,
+        provider: String,
+    ) -> &mut ProviderStats {
+        stats
+            .entry(provider.clone())
+            .or_insert_with(|| ProviderStats {
+                provider,
+                ..Default::default()
+            })
+    }
+
+    let conn = connect()?;
+    let mut stats = std::collections::BTreeMap::new();
+
+    let mut stmt = conn
+        .prepare("SELECT provider, status, COUNT(*) FROM environments GROUP BY provider, status")?;
+    let counts = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (provider, status, count) in counts {
+        entry(&mut stats, provider)
+            .environments
+            .insert(status, count);
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT provider, COUNT(*), SUM(NOT succeeded), CAST(AVG(CASE WHEN succeeded THEN duration_ms END) AS INTEGER)
+         FROM (
+             SELECT provider, succeeded, duration_ms,
+                 ROW_NUMBER() OVER (PARTITION BY provider ORDER BY seq DESC) AS n
+             FROM environment_prepare_outcomes
+         )
+         WHERE n <= ?1
+         GROUP BY provider",
+    )?;
+    let prepares = stmt
+        .query_map([last as i64], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, Option<i64>>(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (provider, count, failures, average_ms) in prepares {
+        let s = entry(&mut stats, provider);
+        s.prepares = count;
+        s.prepare_failures = failures;
+        s.failure_rate = Some(failures as f64 / count as f64);
+        s.average_prepare_ms = average_ms;
+    }
+
+    Ok(stats.into_values().collect())
+}
+
+pub fn list_environments() -> Result<Vec<Environment>, anyhow::Error> {
+    let conn = connect()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {ENVIRONMENT_COLUMNS} FROM environments ORDER BY id"
+    ))?;
+    let envs = stmt
+        .query_map([], row_to_environment)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(envs)
+}
+
+pub fn update_environment_metadata(
+    id: &str,
+    metadata: &serde_json::Value,
+) -> Result<(), anyhow::Error> {
+    let conn = connect()?;
+    let now = now_rfc3339();
+    let metadata_str = serde_json::to_string(metadata)?;
+    let rows = conn.execute(
+        "UPDATE environments SET metadata = ?1, updated_at = ?2, progress = NULL, progress_message = NULL WHERE id = ?3",
+        rusqlite::params![metadata_str, &now, id],
+    )?;
+    if rows == 0 {
+        return Err(not_found(ErrorCode::EnvNotFound, "environment", id));
+    }
+    Ok(())
+}
+
+/// Mark an environment outdated, or current again. Returns whether that
+/// changed anything, so rechecking an environment that hasn't moved records
+/// no event.
+pub fn set_environment_outdated(id: &str, outdated: bool) -> Result<bool, anyhow::Error> {
+    let conn = connect()?;
+    let rows = conn.execute(
+        "UPDATE environments SET outdated_since = CASE WHEN ?2 THEN ?3 END WHERE id = ?1 AND (outdated_since IS NULL) = ?2",
+        rusqlite::params![id, outdated, now_rfc3339()],
+    )?;
+    Ok(rows > 0)
+}
+
+/// Clear every outdated mark, for when nothing is watching to keep them
+/// true.
+pub fn clear_outdated_environments() -> Result<usize, anyhow::Error> {
+    let conn = connect()?;
+    Ok(conn.execute(
+        "UPDATE environments SET outdated_since = NULL WHERE outdated_since IS NOT NULL",
+        [],
+    )?)
+}
+
+/// Record the latest progress a provider reported for an environment.
+/// Passing `None` for both clears it.
+pub fn set_environment_progress(
+    id: &str,
+    progress: Option<f64>,
+    message: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let conn = connect()?;
+    let rows = conn.execute(
+        "UPDATE environments SET progress = ?1, progress_message = ?2 WHERE id = ?3",
+        rusqlite::params![progress, message, id],
+    )?;
+    if rows == 0 {
+        return Err(not_found(ErrorCode::EnvNotFound, "environment", id));
+    }
+    Ok(())
+}
+
+pub fn update_environment_status(id: &str, status: &str) -> Result<(), anyhow::Error> {
+    let conn = connect()?;
+    let now = now_rfc3339();
+    let rows = conn.execute(
+        "UPDATE environments SET status = ?1, updated_at = ?2, progress = NULL, progress_message = NULL WHERE id = ?3",
+        rusqlite::params![status, &now, id],
+    )?;
+    if rows == 0 {
+        return Err(not_found(ErrorCode::EnvNotFound, "environment", id));
+    }
+    Ok(())
+}
+
+fn claim_environment_tx(tx: &Transaction<'_>, id: &str) -> Result<(), anyhow::Error> {
+    let now = now_rfc3339();
+    let rows = tx.execute(
+        "UPDATE environments SET status = 'in_use', updated_at = ?1 WHERE id = ?2 AND status = 'pool'",
+        rusqlite::params![&now, id],
+    )?;
+    if rows == 0 {
+        bail_code!(
+            ErrorCode::EnvNotInPool,
+            "environment {id} is not in the pool (may not exist or already claimed)"
+        );
+    }
+    Ok(())
+}
+
+pub fn delete_environment(id: &str) -> Result<(), anyhow::Error> {
+    let conn = connect()?;
+    let rows = conn.execute(
+        "DELETE FROM environments WHERE id = ?1",
+        rusqlite::params![id],
+    )?;
+    if rows == 0 {
+        return Err(not_found(ErrorCode::EnvNotFound, "environment", id));
+    }
+    delete_drained_projects(&conn)?;
+    Ok(())
+}
+
+/// A task run by a task provider inside an environment.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct Task {
+    pub id: String,
+    pub environment_id: String,
+    pub project_id: String,
+    pub provider: String,
+    pub description: String,
+    pub status: String,
+    #[serde(default)]
+    pub result_summary: Option<String>,
+    #[serde(default)]
+    pub result_data: Option<serde_json::Value>,
+    #[serde(default)]
+    pub resource_usage: Option<ResourceUsage>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Branch or commit the task's environment was based on, if not the
+    /// project's base branch.
+    #[serde(default)]
+    pub source_ref: Option<String>,
+    /// Capability tags the task's environment provider had to offer.
+    #[serde(default)]
+    pub requirements: Vec<String>,
+    /// Group the task belongs to, if any.
+    #[serde(default)]
+    pub group_id: Option<String>,
+    #[serde(default)]
+    pub priority: TaskPriority,
+    /// Notes added with `work task note`, oldest first.
+    #[serde(default)]
+    pub notes: Vec<TaskNote>,
+    /// Short number the task also goes by, as `t<alias>`.
+    #[serde(default)]
+    pub alias: Option<i64>,
+    /// When `status` last changed.
+    #[serde(default)]
+    pub status_changed_at: Option<String>,
+    /// Where the request that created the task came from.
+    #[serde(default)]
+    pub origin: Option<Origin>,
+    /// What the runner reported spending, if anything.
+    #[serde(default)]
+    pub cost: Option<TaskCost>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// What a task's runner reported spending in its result. Each part is
+/// optional, since runners report what they know.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct TaskCost {
+    #[serde(default)]
+    pub tokens_in: Option<i64>,
+    #[serde(default)]
+    pub tokens_out: Option<i64>,
+    #[serde(default)]
+    pub cost_usd: Option<f64>,
+}
+
+impl TaskCost {
+    /// The cost, or `None` if nothing was reported.
+    pub fn reported(self) -> Option<Self> {
+        (self != Self::default()).then_some(self)
+    }
+}
+
+impl std::fmt::Display for TaskCost {
+    /// E.g. `$0.42, 1200 tokens in, 300 out`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(usd) = self.cost_usd {
+            parts.push(format!("${usd:.2}"));
+        }
+        match (self.tokens_in, self.tokens_out) {
+            (Some(tokens_in), Some(tokens_out)) => {
+                parts.push(format!("{tokens_in} tokens in, {tokens_out} out"));
+            }
+            (Some(tokens_in), None) => parts.push(format!("{tokens_in} tokens in")),
+            (None, Some(tokens_out)) => parts.push(format!("{tokens_out} tokens out")),
+            (None, None) => {}
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// A note an operator added to a task, e.g. why it was retried.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct TaskNote {
+    pub at: String,
+    pub text: String,
+}
+
+/// How soon a task's jobs run relative to other queued work. Higher
+/// priority jobs are claimed first; within a priority, the oldest first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskPriority {
+    /// Ahead of everything else, including pool prewarming and updates.
+    High,
+    #[default]
+    Normal,
+    /// After everything else.
+    Low,
+}
+
+impl TaskPriority {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TaskPriority::High => "high",
+            TaskPriority::Normal => "normal",
+            TaskPriority::Low => "low",
+        }
+    }
+
+    fn from_name(name: &str) -> TaskPriority {
+        match name {
+            "high" => TaskPriority::High,
+            "low" => TaskPriority::Low,
+            _ => TaskPriority::Normal,
+        }
+    }
+}
+
+/// Statuses a task ends in:
+/// - `complete`: the runner exited 0.
+/// - `failed`: the runner exited non-zero or crashed.
+/// - `cancelled`: the daemon stopped the runner because the task was removed.
+/// - `timed_out`: the runner hit a time limit (exit code 124, SIGALRM, or SIGXCPU).
+/// - `interrupted`: the runner was killed by an outside signal, or the daemon
+///   restarted while it was running.
+pub const TERMINAL_TASK_STATUSES: &[&str] = &[
+    "complete",
+    "failed",
+    "cancelled",
+    "timed_out",
+    "interrupted",
+];
+
+impl Task {
+    /// Whether the task has finished and will not change status again.
+    pub fn is_terminal(&self) -> bool {
+        TERMINAL_TASK_STATUSES.contains(&self.status.as_str())
+    }
+
+    /// The first non-blank line of the description, for places that show a
+    /// task on one line. Descriptions are markdown and may run long.
+    pub fn title(&self) -> &str {
+        self.description
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or_default()
+    }
+
+    /// The task's alias as it's typed, e.g. `t12`.
+    pub fn short_id(&self) -> Option<String> {
+        self.alias.map(|n| format!("t{n}"))
+    }
+
+    /// Whether the description says more than its [`title`](Self::title).
+    pub fn has_long_description(&self) -> bool {
+        self.description.trim() != self.title()
+    }
+
+    /// How long the task has had its status, e.g. `3m`.
+    pub fn in_status_for(&self) -> Option<String> {
+        time_since(self.status_changed_at.as_deref()?, chrono::Utc::now())
+    }
+
+    /// The [`TaskPage::cursor`] for the page after one ending with this task.
+    pub fn cursor(&self) -> String {
+        format!("{}/{}", self.created_at, self.id)
+    }
+
+    /// How long the task ran, or has been running: the runner's wall time
+    /// once it's recorded, or the time since a started task started. What
+    /// [`TaskSort::Duration`] orders by.
+    pub fn run_ms(&self, now: chrono::DateTime<chrono::Utc>) -> Option<u64> {
+        if let Some(usage) = &self.resource_usage {
+            return Some(usage.wall_ms);
+        }
+        if self.status != "started" {
+            return None;
+        }
+        let since =
+            chrono::DateTime::parse_from_rfc3339(self.status_changed_at.as_deref()?).ok()?;
+        Some(
+            (now - since.with_timezone(&chrono::Utc))
+                .num_milliseconds()
+                .max(0) as u64,
+        )
+    }
+}
+
+/// [`Task::run_ms`] in SQL.
+const TASK_RUN_MS: &str = "COALESCE(json_extract(tasks.resource_usage, '$.wall_ms'), CASE WHEN tasks.status = 'started' THEN MAX(CAST((julianday('now') - julianday((SELECT changed_at FROM task_status_times WHERE task_status_times.id = tasks.id))) * 86400000 AS INTEGER), 0) END)";
+
+/// The columns `row_to_task` reads, in order.
+const TASK_COLUMNS: &str = "tasks.id, tasks.environment_id, tasks.project_id, tasks.provider, tasks.description, tasks.status, tasks.created_at, tasks.updated_at, tasks.result_summary, tasks.result_data, tasks.resource_usage, tasks.labels, tasks.source_ref, tasks.requirements, tasks.group_id, tasks.priority, tasks.notes, (SELECT alias FROM task_aliases WHERE task_aliases.id = tasks.id), (SELECT changed_at FROM task_status_times WHERE task_status_times.id = tasks.id), tasks.created_via, tasks.created_host, tasks.created_user, tasks.created_cwd, tasks.tokens_in, tasks.tokens_out, tasks.cost_usd";
+
 fn row_to_task(row: &rusqlite::Row) -> rusqlite::Result<Task> {
+    let result_data_str: Option<String> = row.get(9)?;
+    let result_data = result_data_str.and_then(|s| serde_json::from_str(&s).ok());
+    let resource_usage_str: Option<String> = row.get(10)?;
+    let resource_usage = resource_usage_str.and_then(|s| serde_json::from_str(&s).ok());
+    let labels_str: String = row.get(11)?;
+    let labels = serde_json::from_str(&labels_str).unwrap_or_default();
+    let requirements_str: String = row.get(13)?;
+    let requirements = serde_json::from_str(&requirements_str).unwrap_or_default();
+    let notes_str: String = row.get(16)?;
+    let notes = serde_json::from_str(&notes_str).unwrap_or_default();
     Ok(Task {
         id: row.get(0)?,
         environment_id: row.get(1)?,
@@ -299,6 +1553,24 @@ fn row_to_task(row: &rusqlite::Row) -> rusqlite::Result<Task> {
         provider: row.get(3)?,
         description: row.get(4)?,
         status: row.get(5)?,
+        result_summary: row.get(8)?,
+        result_data,
+        resource_usage,
+        labels,
+        source_ref: row.get(12)?,
+        requirements,
+        group_id: row.get(14)?,
+        priority: TaskPriority::from_name(&row.get::<_, String>(15)?),
+        notes,
+        alias: row.get(17)?,
+        status_changed_at: row.get(18)?,
+        origin: row_to_origin(row, 19)?,
+        cost: TaskCost {
+            tokens_in: row.get(23)?,
+            tokens_out: row.get(24)?,
+            cost_usd: row.get(25)?,
+        }
+        .reported(),
         created_at: row.get(6)?,
         updated_at: row.get(7)?,
     })
@@ -308,26 +1580,28 @@ pub fn stage_prepare_environment(
     project_id: &str,
     provider: &str,
     claim_after_prepare: bool,
+    origin: Option<&Origin>,
 ) -> Result<Environment, anyhow::Error> {
     let mut conn = connect()?;
     let tx = conn.transaction()?;
 
     let project_exists: Option<String> = tx
         .query_row(
-            "SELECT id FROM projects WHERE id = ?1",
+            "SELECT id FROM projects WHERE id = ?1 AND removing_at IS NULL",
             rusqlite::params![project_id],
             |row| row.get(0),
         )
         .optional()?;
     if project_exists.is_none() {
-        anyhow::bail!("project not found: {project_id}");
+        return Err(not_found(ErrorCode::ProjectNotFound, "project", project_id));
     }
 
     let env_id = crate::id::new_id();
     let now = now_rfc3339();
+    let [via, host, user, cwd] = origin_columns(origin);
     tx.execute(
-        "INSERT INTO environments (id, project_id, provider, status, metadata, created_at, updated_at) VALUES (?1, ?2, ?3, 'preparing', '{}', ?4, ?5)",
-        rusqlite::params![&env_id, project_id, provider, &now, &now],
+        "INSERT INTO environments (id, project_id, provider, status, metadata, created_at, updated_at, created_via, created_host, created_user, created_cwd) VALUES (?1, ?2, ?3, 'preparing', '{}', ?4, ?5, ?6, ?7, ?8, ?9)",
+        rusqlite::params![&env_id, project_id, provider, &now, &now, via, host, user, cwd],
     )?;
 
     let payload = serde_json::json!({
@@ -341,76 +1615,142 @@ pub fn stage_prepare_environment(
     get_environment(&env_id)
 }
 
-pub fn stage_task_create(
+/// Register an existing checkout as an `in_use` environment without
+/// running the provider's prepare step.
+pub fn adopt_environment(
     project_id: &str,
-    task_provider: &str,
-    env_provider: &str,
-    description: &str,
+    provider: &str,
+    metadata: &serde_json::Value,
+    origin: Option<&Origin>,
+) -> Result<Environment, anyhow::Error> {
+    let conn = connect()?;
+    let env_id = crate::id::new_id();
+    let now = now_rfc3339();
+    let metadata_str = serde_json::to_string(metadata)?;
+    let [via, host, user, cwd] = origin_columns(origin);
+    conn.execute(
+        "INSERT INTO environments (id, project_id, provider, status, metadata, created_at, updated_at, created_via, created_host, created_user, created_cwd) VALUES (?1, ?2, ?3, 'in_use', ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        rusqlite::params![&env_id, project_id, provider, metadata_str, &now, &now, via, host, user, cwd],
+    )?;
+    get_environment(&env_id)
+}
+
+/// A task to create, as sent to `POST /tasks`.
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct NewTask {
+    pub project_id: String,
+    pub provider: String,
+    /// Environment provider to run in. When unset, the daemon picks a
+    /// provider whose tags cover `requirements`.
+    #[serde(default)]
+    pub env_provider: Option<String>,
+    pub description: String,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Capability tags the environment provider must offer.
+    #[serde(default)]
+    pub requirements: Vec<String>,
+    /// Branch or commit to base the environment on instead of the base branch.
+    #[serde(default)]
+    pub source_ref: Option<String>,
+    /// Group to add the task to, which must belong to the same project.
+    #[serde(default)]
+    pub group_id: Option<String>,
+    #[serde(default)]
+    pub priority: TaskPriority,
+    /// Confirms running in the project checkout itself, which the `inplace`
+    /// environment provider refuses to do without.
+    #[serde(default)]
+    pub in_place: bool,
+}
+
+/// An environment provider a new task may run in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvironmentChoice {
+    pub provider: String,
+    /// Whether the provider's pool is shared across projects.
+    pub shared_pool: bool,
+}
+
+/// Create a task and queue the job that gives it an environment. The task
+/// claims the first pooled environment among `env_choices`, in order, or a
+/// new environment from the first choice when none are pooled.
+pub fn stage_task_create(
+    task: &NewTask,
+    env_choices: &[EnvironmentChoice],
+    origin: Option<&Origin>,
 ) -> Result<Task, anyhow::Error> {
+    let project_id = task.project_id.as_str();
+    let source_ref = task.source_ref.as_deref();
+    let labels_str = serde_json::to_string(&normalize_labels(task.labels.iter().cloned()))?;
+    let requirements_str = serde_json::to_string(&task.requirements)?;
     let mut conn = connect()?;
     let tx = conn.transaction()?;
 
     let project_exists: Option<String> = tx
         .query_row(
-            "SELECT id FROM projects WHERE id = ?1",
+            "SELECT id FROM projects WHERE id = ?1 AND removing_at IS NULL",
             rusqlite::params![project_id],
             |row| row.get(0),
         )
         .optional()?;
     if project_exists.is_none() {
-        anyhow::bail!("project not found: {project_id}");
+        return Err(not_found(ErrorCode::ProjectNotFound, "project", project_id));
     }
-
-    let task_id = crate::id::new_id();
-    let now = now_rfc3339();
-    let mut created_new_environment = false;
-
-    let env_id = {
-        let candidate_env_id: Option<String> = tx
+    if let Some(group_id) = &task.group_id {
+        let group_project: String = tx
             .query_row(
-                "SELECT id FROM environments WHERE provider = ?1 AND project_id = ?2 AND status = 'pool' ORDER BY created_at ASC LIMIT 1",
-                rusqlite::params![env_provider, project_id],
+                "SELECT project_id FROM task_groups WHERE id = ?1",
+                rusqlite::params![group_id],
                 |row| row.get(0),
             )
-            .optional()?;
-
-        if let Some(candidate_env_id) = candidate_env_id {
-            let claimed = tx.execute(
-                "UPDATE environments SET status = 'in_use', updated_at = ?1 WHERE id = ?2 AND status = 'pool'",
-                rusqlite::params![&now, &candidate_env_id],
-            )?;
-            if claimed == 1 {
-                candidate_env_id
-            } else {
-                created_new_environment = true;
-                let new_env_id = crate::id::new_id();
-                tx.execute(
-                    "INSERT INTO environments (id, project_id, provider, status, metadata, created_at, updated_at) VALUES (?1, ?2, ?3, 'preparing', '{}', ?4, ?5)",
-                    rusqlite::params![&new_env_id, project_id, env_provider, &now, &now],
-                )?;
-                new_env_id
-            }
-        } else {
-            created_new_environment = true;
-            let new_env_id = crate::id::new_id();
-            tx.execute(
-                "INSERT INTO environments (id, project_id, provider, status, metadata, created_at, updated_at) VALUES (?1, ?2, ?3, 'preparing', '{}', ?4, ?5)",
-                rusqlite::params![&new_env_id, project_id, env_provider, &now, &now],
-            )?;
-            new_env_id
+            .optional()?
+            .ok_or_else(|| not_found(ErrorCode::GroupNotFound, "group", group_id))?;
+        if group_project != project_id {
+            bail_code!(
+                ErrorCode::InvalidRequest,
+                "group {group_id} belongs to another project"
+            );
         }
-    };
+    }
+
+    if env_choices
+        .iter()
+        .any(|c| c.provider == crate::config::IN_PLACE_PROVIDER)
+        && let Some(busy_id) = in_place_task_tx(&tx, project_id)?
+    {
+        return Err(Error::new(
+            ErrorCode::InPlaceBusy,
+            format!(
+                "task {busy_id} is still running in the project checkout; wait for it or use another environment provider"
+            ),
+        )
+        .with_details(serde_json::json!({ "task_id": busy_id }))
+        .into());
+    }
+
+    let task_id = crate::id::new_id();
+    let now = now_rfc3339();
 
+    // Pooled environments sit on the base branch, so a task started from
+    // another ref always gets a fresh one.
+    let (env_id, created_new_environment) =
+        take_environment_tx(&tx, project_id, env_choices, source_ref.is_none(), origin)?;
+
+    let [via, host, user, cwd] = origin_columns(origin);
     tx.execute(
-        "INSERT INTO tasks (id, environment_id, project_id, provider, description, status, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, 'pending', ?6, ?7)",
-        rusqlite::params![&task_id, &env_id, project_id, task_provider, description, &now, &now],
+        "INSERT INTO tasks (id, environment_id, project_id, provider, description, status, created_at, updated_at, labels, source_ref, requirements, group_id, priority, created_via, created_host, created_user, created_cwd) VALUES (?1, ?2, ?3, ?4, ?5, 'pending', ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+        rusqlite::params![&task_id, &env_id, project_id, &task.provider, &task.description, &now, &now, &labels_str, source_ref, &requirements_str, &task.group_id, task.priority.as_str(), via, host, user, cwd],
     )?;
 
     if created_new_environment {
-        let payload = serde_json::json!({
+        let mut payload = serde_json::json!({
             "task_id": task_id,
             "env_id": env_id,
         });
+        if let Some(source_ref) = source_ref {
+            payload["source_ref"] = serde_json::json!(source_ref);
+        }
         let dedupe = format!("prepare_environment:env:{env_id}");
         let _ = insert_job_tx(&tx, "prepare_environment", &payload, Some(&dedupe))?;
     } else {
@@ -426,21 +1766,165 @@ pub fn stage_task_create(
     get_task(&task_id)
 }
 
-pub fn stage_update_environment(id: &str) -> Result<Environment, anyhow::Error> {
+/// A task of the project that hasn't finished and runs in an `inplace`
+/// environment, which has the project checkout to itself.
+fn in_place_task_tx(
+    tx: &Transaction<'_>,
+    project_id: &str,
+) -> Result<Option<String>, anyhow::Error> {
+    let placeholders = vec!["?"; TERMINAL_TASK_STATUSES.len()].join(", ");
+    let mut params: Vec<&dyn rusqlite::ToSql> =
+        vec![&project_id, &crate::config::IN_PLACE_PROVIDER];
+    params.extend(
+        TERMINAL_TASK_STATUSES
+            .iter()
+            .map(|s| s as &dyn rusqlite::ToSql),
+    );
+    Ok(tx
+        .query_row(
+            &format!(
+                "SELECT tasks.id FROM tasks JOIN environments ON environments.id = tasks.environment_id
+                 WHERE tasks.project_id = ?1 AND environments.provider = ?2 AND tasks.status NOT IN ({placeholders})
+                 ORDER BY tasks.created_at LIMIT 1"
+            ),
+            params.as_slice(),
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+/// Claim the next pooled environment for a task from the first of
+/// `env_choices` that has one, or insert a new one from the first choice to
+/// prepare when `use_pool` is false or the pools are empty, recording
+/// `origin` on it. Returns the environment ID and whether it is new.
+fn take_environment_tx(
+    tx: &Transaction<'_>,
+    project_id: &str,
+    env_choices: &[EnvironmentChoice],
+    use_pool: bool,
+    origin: Option<&Origin>,
+) -> Result<(String, bool), anyhow::Error> {
+    let Some(first) = env_choices.first() else {
+        bail_code!(
+            ErrorCode::InvalidRequest,
+            "no environment provider to create the task in"
+        );
+    };
+    let now = now_rfc3339();
+    for choice in env_choices {
+        if use_pool
+            && let Some(candidate_env_id) =
+                next_pool_environment_tx(tx, &choice.provider, project_id, choice.shared_pool)?
+        {
+            let claimed = tx.execute(
+                "UPDATE environments SET status = 'in_use', project_id = ?1, updated_at = ?2 WHERE id = ?3 AND status = 'pool'",
+                rusqlite::params![project_id, &now, &candidate_env_id],
+            )?;
+            if claimed == 1 {
+                return Ok((candidate_env_id, false));
+            }
+        }
+    }
+
+    let new_env_id = crate::id::new_id();
+    let [via, host, user, cwd] = origin_columns(origin);
+    tx.execute(
+        "INSERT INTO environments (id, project_id, provider, status, metadata, created_at, updated_at, created_via, created_host, created_user, created_cwd) VALUES (?1, ?2, ?3, 'preparing', '{}', ?4, ?5, ?6, ?7, ?8, ?9)",
+        rusqlite::params![&new_env_id, project_id, &first.provider, &now, &now, via, host, user, cwd],
+    )?;
+    Ok((new_env_id, true))
+}
+
+/// The environment a task was moved to by [`degrade_task_environment`].
+#[derive(Debug, PartialEq)]
+pub enum ReplacementEnvironment {
+    /// Claimed from the pool; the caller still has to check and claim it.
+    Pooled(String),
+    /// Newly inserted, with a prepare job queued that runs the task.
+    Preparing(String),
+}
+
+/// Take a task's environment out of service because `reason` makes it unsafe
+/// to reuse, and give the task the next pooled environment or a new one.
+/// The old environment is marked `degraded` and keeps `reason` in its
+/// metadata as `degraded_reason`.
+pub fn degrade_task_environment(
+    task_id: &str,
+    env_id: &str,
+    reason: &str,
+    shared_pool: bool,
+) -> Result<ReplacementEnvironment, anyhow::Error> {
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+
+    let env = tx
+        .query_row(
+            &format!("SELECT {ENVIRONMENT_COLUMNS} FROM environments WHERE id = ?1"),
+            rusqlite::params![env_id],
+            row_to_environment,
+        )
+        .optional()?
+        .ok_or_else(|| not_found(ErrorCode::EnvNotFound, "environment", env_id))?;
+    let project_id: String = tx
+        .query_row(
+            "SELECT project_id FROM tasks WHERE id = ?1 AND environment_id = ?2",
+            rusqlite::params![task_id, env_id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .ok_or_else(|| not_found(ErrorCode::TaskNotFound, "task", task_id))?;
+
+    let mut metadata = env.metadata;
+    metadata["degraded_reason"] = serde_json::json!(reason);
+    tx.execute(
+        "UPDATE environments SET status = 'degraded', metadata = ?1, updated_at = ?2, progress = NULL, progress_message = NULL WHERE id = ?3",
+        rusqlite::params![serde_json::to_string(&metadata)?, now_rfc3339(), env_id],
+    )?;
+
+    let choice = EnvironmentChoice {
+        provider: env.provider,
+        shared_pool,
+    };
+    let (new_env_id, created) = take_environment_tx(&tx, &project_id, &[choice], true, None)?;
+    tx.execute(
+        "UPDATE tasks SET environment_id = ?1, updated_at = ?2 WHERE id = ?3",
+        rusqlite::params![&new_env_id, now_rfc3339(), task_id],
+    )?;
+    if created {
+        let payload = serde_json::json!({
+            "task_id": task_id,
+            "env_id": new_env_id,
+        });
+        let dedupe = format!("prepare_environment:env:{new_env_id}");
+        let _ = insert_job_tx(&tx, "prepare_environment", &payload, Some(&dedupe))?;
+    }
+
+    tx.commit()?;
+    Ok(if created {
+        ReplacementEnvironment::Preparing(new_env_id)
+    } else {
+        ReplacementEnvironment::Pooled(new_env_id)
+    })
+}
+
+pub fn stage_update_environment(id: &str) -> Result<Environment, anyhow::Error> {
     let mut conn = connect()?;
     let tx = conn.transaction()?;
 
     let env: Option<Environment> = {
-        let mut stmt = tx.prepare(
-            "SELECT id, project_id, provider, status, metadata, created_at, updated_at FROM environments WHERE id = ?1",
-        )?;
+        let mut stmt = tx.prepare(&format!(
+            "SELECT {ENVIRONMENT_COLUMNS} FROM environments WHERE id = ?1"
+        ))?;
         stmt.query_row(rusqlite::params![id], row_to_environment)
             .optional()?
     };
 
-    let env = env.ok_or_else(|| anyhow::anyhow!("environment not found: {id}"))?;
+    let env = env.ok_or_else(|| not_found(ErrorCode::EnvNotFound, "environment", id))?;
     if env.status != "pool" {
-        anyhow::bail!("environment {id} is not in the pool");
+        bail_code!(
+            ErrorCode::EnvNotInPool,
+            "environment {id} is not in the pool"
+        );
     }
 
     let payload = serde_json::json!({ "env_id": id });
@@ -465,34 +1949,148 @@ pub fn stage_claim_environment(id: &str) -> Result<Environment, anyhow::Error> {
     get_environment(id)
 }
 
+/// The oldest pooled environment a project can claim. With `shared_pool`,
+/// environments prepared for other projects qualify too, after the
+/// project's own.
+fn next_pool_environment_tx(
+    tx: &Transaction<'_>,
+    provider: &str,
+    project_id: &str,
+    shared_pool: bool,
+) -> Result<Option<String>, anyhow::Error> {
+    Ok(tx
+        .query_row(
+            "SELECT id FROM environments WHERE provider = ?1 AND status = 'pool' AND (project_id = ?2 OR ?3) ORDER BY project_id = ?2 DESC, created_at ASC LIMIT 1",
+            rusqlite::params![provider, project_id, shared_pool],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
 pub fn stage_claim_next_environment(
     provider: &str,
     project_id: &str,
+    shared_pool: bool,
 ) -> Result<Environment, anyhow::Error> {
     let mut conn = connect()?;
     let tx = conn.transaction()?;
 
-    let id: String = tx
+    let id =
+        next_pool_environment_tx(&tx, provider, project_id, shared_pool)?.ok_or_else(|| {
+            Error::new(
+                ErrorCode::NoPoolEnv,
+                format!("no available environment for provider={provider} project_id={project_id}"),
+            )
+        })?;
+
+    claim_for_project_tx(&tx, &id, project_id)?;
+
+    tx.commit()?;
+    get_environment(&id)
+}
+
+/// Claim pooled environment `id` for `project_id` and queue the job that
+/// has its provider claim it.
+fn claim_for_project_tx(
+    tx: &Transaction<'_>,
+    id: &str,
+    project_id: &str,
+) -> Result<(), anyhow::Error> {
+    claim_environment_tx(tx, id)?;
+    // A shared environment now belongs to the project that claimed it.
+    tx.execute(
+        "UPDATE environments SET project_id = ?1 WHERE id = ?2",
+        rusqlite::params![project_id, id],
+    )?;
+    let payload = serde_json::json!({ "env_id": id });
+    let dedupe = format!("claim_environment:env:{id}");
+    let _ = insert_job_tx(tx, "claim_environment", &payload, Some(&dedupe))?;
+    Ok(())
+}
+
+/// The most environments one batch claim may take.
+pub const MAX_CLAIM_BATCH: usize = 64;
+
+/// Claim `count` environments for a project at once: pooled ones first, in
+/// the order [`stage_claim_next_environment`] would take them, then new ones
+/// prepared to be claimed for the shortfall. Either every environment is
+/// staged or none is.
+pub fn stage_claim_environments(
+    provider: &str,
+    project_id: &str,
+    shared_pool: bool,
+    count: usize,
+    origin: Option<&Origin>,
+) -> Result<Vec<Environment>, anyhow::Error> {
+    if count == 0 || count > MAX_CLAIM_BATCH {
+        bail_code!(
+            ErrorCode::InvalidRequest,
+            "count must be between 1 and {MAX_CLAIM_BATCH}"
+        );
+    }
+
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+
+    let project_exists: Option<String> = tx
         .query_row(
-            "SELECT id FROM environments WHERE provider = ?1 AND project_id = ?2 AND status = 'pool' ORDER BY created_at ASC LIMIT 1",
-            rusqlite::params![provider, project_id],
+            "SELECT id FROM projects WHERE id = ?1 AND removing_at IS NULL",
+            rusqlite::params![project_id],
             |row| row.get(0),
         )
-        .map_err(|_| anyhow::anyhow!("no available environment for provider={provider} project_id={project_id}"))?;
+        .optional()?;
+    if project_exists.is_none() {
+        return Err(not_found(ErrorCode::ProjectNotFound, "project", project_id));
+    }
 
-    claim_environment_tx(&tx, &id)?;
-    let payload = serde_json::json!({ "env_id": id });
-    let dedupe = format!("claim_environment:env:{id}");
-    let _ = insert_job_tx(&tx, "claim_environment", &payload, Some(&dedupe))?;
+    let mut ids = Vec::with_capacity(count);
+    while ids.len() < count {
+        let Some(id) = next_pool_environment_tx(&tx, provider, project_id, shared_pool)? else {
+            break;
+        };
+        claim_for_project_tx(&tx, &id, project_id)?;
+        ids.push(id);
+    }
+
+    let now = now_rfc3339();
+    let [via, host, user, cwd] = origin_columns(origin);
+    while ids.len() < count {
+        let id = crate::id::new_id();
+        tx.execute(
+            "INSERT INTO environments (id, project_id, provider, status, metadata, created_at, updated_at, created_via, created_host, created_user, created_cwd) VALUES (?1, ?2, ?3, 'preparing', '{}', ?4, ?5, ?6, ?7, ?8, ?9)",
+            rusqlite::params![&id, project_id, provider, &now, &now, via, host, user, cwd],
+        )?;
+        let payload = serde_json::json!({
+            "env_id": id,
+            "claim_after_prepare": true,
+        });
+        let dedupe = format!("prepare_environment:env:{id}");
+        let _ = insert_job_tx(&tx, "prepare_environment", &payload, Some(&dedupe))?;
+        ids.push(id);
+    }
 
     tx.commit()?;
-    get_environment(&id)
+    ids.iter().map(|id| get_environment(id)).collect()
+}
+
+fn attached_to_task(env_id: &str, task_id: &str) -> anyhow::Error {
+    Error::new(
+        ErrorCode::EnvAttachedToTask,
+        format!("environment {env_id} is attached to task {task_id}; remove the task instead"),
+    )
+    .with_details(serde_json::json!({ "id": env_id, "task_id": task_id }))
+    .into()
 }
 
 pub fn stage_remove_environment(id: &str) -> Result<(), anyhow::Error> {
     let mut conn = connect()?;
     let tx = conn.transaction()?;
+    stage_remove_environment_tx(&tx, id)?;
+    tx.commit()?;
+    Ok(())
+}
 
+fn stage_remove_environment_tx(tx: &Transaction<'_>, id: &str) -> Result<(), anyhow::Error> {
     let task_for_environment: Option<String> = tx
         .query_row(
             "SELECT id FROM tasks WHERE environment_id = ?1 LIMIT 1",
@@ -501,7 +2099,7 @@ pub fn stage_remove_environment(id: &str) -> Result<(), anyhow::Error> {
         )
         .optional()?;
     if let Some(task_id) = task_for_environment {
-        anyhow::bail!("environment {id} is attached to task {task_id}; remove the task instead");
+        return Err(attached_to_task(id, &task_id));
     }
 
     let status: Option<String> = tx
@@ -512,9 +2110,12 @@ pub fn stage_remove_environment(id: &str) -> Result<(), anyhow::Error> {
         )
         .optional()?;
 
-    let status = status.ok_or_else(|| anyhow::anyhow!("environment not found: {id}"))?;
+    let status = status.ok_or_else(|| not_found(ErrorCode::EnvNotFound, "environment", id))?;
     if status == "removing" {
-        anyhow::bail!("environment {id} is already being removed");
+        bail_code!(
+            ErrorCode::EnvRemoving,
+            "environment {id} is already being removed"
+        );
     }
 
     let now = now_rfc3339();
@@ -525,9 +2126,7 @@ pub fn stage_remove_environment(id: &str) -> Result<(), anyhow::Error> {
 
     let payload = serde_json::json!({ "env_id": id });
     let dedupe = format!("remove_environment:env:{id}");
-    let _ = insert_job_tx(&tx, "remove_environment", &payload, Some(&dedupe))?;
-
-    tx.commit()?;
+    let _ = insert_job_tx(tx, "remove_environment", &payload, Some(&dedupe))?;
     Ok(())
 }
 
@@ -543,137 +2142,801 @@ pub fn force_delete_environment(id: &str) -> Result<(), anyhow::Error> {
         )
         .optional()?;
     if let Some(task_id) = task_for_environment {
-        anyhow::bail!("environment {id} is attached to task {task_id}; remove the task instead");
+        return Err(attached_to_task(id, &task_id));
     }
 
     let rows = tx.execute(
         "DELETE FROM environments WHERE id = ?1",
         rusqlite::params![id],
     )?;
-    if rows == 0 {
-        anyhow::bail!("environment not found: {id}");
-    }
+    if rows == 0 {
+        return Err(not_found(ErrorCode::EnvNotFound, "environment", id));
+    }
+    delete_drained_projects(&tx)?;
+
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn stage_remove_task(task_id: &str) -> Result<(), anyhow::Error> {
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+    stage_remove_task_tx(&tx, task_id)?;
+    tx.commit()?;
+    Ok(())
+}
+
+fn stage_remove_task_tx(tx: &Transaction<'_>, task_id: &str) -> Result<(), anyhow::Error> {
+    let env_id: String = tx
+        .query_row(
+            "SELECT environment_id FROM tasks WHERE id = ?1",
+            rusqlite::params![task_id],
+            |row| row.get(0),
+        )
+        .map_err(|_| not_found(ErrorCode::TaskNotFound, "task", task_id))?;
+
+    let env_status: Option<String> = tx
+        .query_row(
+            "SELECT status FROM environments WHERE id = ?1",
+            rusqlite::params![&env_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let env_status =
+        env_status.ok_or_else(|| not_found(ErrorCode::EnvNotFound, "environment", &env_id))?;
+
+    if env_status != "removing" {
+        let now = now_rfc3339();
+        tx.execute(
+            "UPDATE environments SET status = 'removing', updated_at = ?1 WHERE id = ?2",
+            rusqlite::params![&now, &env_id],
+        )?;
+    }
+
+    let payload = serde_json::json!({
+        "task_id": task_id,
+        "env_id": env_id,
+    });
+    let dedupe = format!("remove_task:task:{task_id}");
+    let _ = insert_job_tx(tx, "remove_task", &payload, Some(&dedupe))?;
+    Ok(())
+}
+
+/// Delete a task whose environment is already gone, which
+/// [`stage_remove_task`] can't remove. A task with an environment is left
+/// alone.
+pub fn delete_task_without_environment(task_id: &str) -> Result<(), anyhow::Error> {
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+    let deleted = tx.execute(
+        "DELETE FROM tasks WHERE id = ?1
+         AND NOT EXISTS (SELECT 1 FROM environments WHERE environments.id = tasks.environment_id)",
+        rusqlite::params![task_id],
+    )?;
+    if deleted == 0 {
+        return Err(not_found(ErrorCode::TaskNotFound, "task", task_id));
+    }
+    delete_drained_projects(&tx)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Delete a task and its environment without going through the provider.
+/// Returns the environment's id.
+pub fn force_delete_task(task_id: &str) -> Result<String, anyhow::Error> {
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+    let env_id = force_delete_task_tx(&tx, task_id)?;
+    tx.commit()?;
+    Ok(env_id)
+}
+
+fn force_delete_task_tx(tx: &Transaction<'_>, task_id: &str) -> Result<String, anyhow::Error> {
+    let env_id: String = tx
+        .query_row(
+            "SELECT environment_id FROM tasks WHERE id = ?1",
+            rusqlite::params![task_id],
+            |row| row.get(0),
+        )
+        .map_err(|_| not_found(ErrorCode::TaskNotFound, "task", task_id))?;
+
+    tx.execute(
+        "DELETE FROM tasks WHERE id = ?1",
+        rusqlite::params![task_id],
+    )?;
+    let env_rows = tx.execute(
+        "DELETE FROM environments WHERE id = ?1",
+        rusqlite::params![&env_id],
+    )?;
+    if env_rows == 0 {
+        return Err(not_found(ErrorCode::EnvNotFound, "environment", &env_id));
+    }
+    delete_drained_projects(tx)?;
+    Ok(env_id)
+}
+
+/// Queue a `notify` job, which sends an event to a notification provider.
+/// `payload` names the provider and holds the event.
+pub fn queue_notification(payload: &serde_json::Value) -> Result<String, anyhow::Error> {
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+    let id = insert_job_tx(&tx, "notify", payload, None)?;
+    tx.commit()?;
+    Ok(id)
+}
+
+/// Queue the job that runs `task_id` in `env_id`, recording a new claim
+/// token on the environment for it. A `run_task` job already pending or
+/// running for the task is returned instead, and keeps its token.
+pub fn queue_run_task(task_id: &str, env_id: &str) -> Result<Job, anyhow::Error> {
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+    let dedupe = format!("run_task:task:{task_id}");
+    let existing = tx
+        .query_row(
+            "SELECT id FROM jobs WHERE dedupe_key = ?1 AND status IN ('pending', 'running') LIMIT 1",
+            rusqlite::params![&dedupe],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?;
+    let id = match existing {
+        Some(id) => id,
+        None => {
+            let claim_token = crate::id::new_id();
+            tx.execute(
+                "UPDATE environments SET claim_token = ?1 WHERE id = ?2",
+                rusqlite::params![&claim_token, env_id],
+            )?;
+            let payload = serde_json::json!({
+                "task_id": task_id,
+                "env_id": env_id,
+                "claim_token": claim_token,
+            });
+            insert_job_tx(&tx, "run_task", &payload, Some(&dedupe))?
+        }
+    };
+    tx.commit()?;
+    get_job(&id)
+}
+
+/// Start a pending task for the `run_task` job holding `claim_token`, using
+/// the token up so no other job can start the task again. Returns `None`,
+/// changing nothing, if the token is not the environment's current one or
+/// the task is no longer pending. Jobs queued before claim tokens existed
+/// pass `None` and match an environment without one. Fails with
+/// [`ErrorCode::EnvBusy`] while a snapshot or restore holds the environment.
+pub fn start_claimed_task(
+    task_id: &str,
+    env_id: &str,
+    claim_token: Option<&str>,
+) -> Result<Option<Task>, anyhow::Error> {
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+    let now = now_rfc3339();
+    if let Some(operation) = environment_lock_tx(&tx, env_id)? {
+        bail_code!(
+            ErrorCode::EnvBusy,
+            "environment {env_id} is busy with a {operation}"
+        );
+    }
+    let claimed = tx.execute(
+        "UPDATE environments SET claim_token = NULL WHERE id = ?1 AND status = 'in_use' AND claim_token IS ?2",
+        rusqlite::params![env_id, claim_token],
+    )?;
+    let started = tx.execute(
+        "UPDATE tasks SET status = 'started', updated_at = ?1 WHERE id = ?2 AND status = 'pending'",
+        rusqlite::params![&now, task_id],
+    )?;
+    if claimed == 0 || started == 0 {
+        return Ok(None);
+    }
+    tx.commit()?;
+    get_task(task_id).map(Some)
+}
+
+pub fn get_task(id: &str) -> Result<Task, anyhow::Error> {
+    let conn = connect()?;
+    let task = conn
+        .query_row(
+            &format!("SELECT {TASK_COLUMNS} FROM tasks WHERE id = ?1"),
+            rusqlite::params![id],
+            row_to_task,
+        )
+        .optional()?
+        .ok_or_else(|| not_found(ErrorCode::TaskNotFound, "task", id))?;
+    Ok(task)
+}
+
+/// The full ID of the task `reference` names: its ID, its alias (`t12`), or
+/// an unambiguous prefix of its ID.
+pub fn resolve_task_id(reference: &str) -> Result<String, anyhow::Error> {
+    resolve_id("task", 't', ErrorCode::TaskNotFound, reference)
+}
+
+pub fn list_tasks() -> Result<Vec<Task>, anyhow::Error> {
+    page_tasks(&TaskPage::default())
+}
+
+/// How many of a project's tasks and environments have each status.
+#[derive(Debug, Default, PartialEq)]
+pub struct StatusCounts {
+    pub tasks: std::collections::BTreeMap<String, i64>,
+    pub environments: std::collections::BTreeMap<String, i64>,
+}
+
+impl StatusCounts {
+    /// Tasks with any of `statuses`.
+    pub fn tasks_in(&self, statuses: &[&str]) -> i64 {
+        statuses.iter().filter_map(|s| self.tasks.get(*s)).sum()
+    }
+}
+
+/// Count the project's tasks and environments by status without loading
+/// them, for callers that must be quick, like a shell prompt.
+pub fn status_counts(project_id: &str) -> Result<StatusCounts, anyhow::Error> {
+    let conn = connect()?;
+    let count = |table: &str| -> Result<_, anyhow::Error> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT status, COUNT(*) FROM {table} WHERE project_id = ?1 GROUP BY status"
+        ))?;
+        let counts = stmt
+            .query_map([project_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(counts)
+    };
+    Ok(StatusCounts {
+        tasks: count("tasks")?,
+        environments: count("environments")?,
+    })
+}
+
+/// The order [`page_tasks`] lists tasks in. Ties go newest first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskSort {
+    /// Newest first.
+    #[default]
+    Created,
+    /// Running tasks first, then pending, then finished ones, with those
+    /// that went wrong before those that completed.
+    Status,
+    /// Longest run first, by [`Task::run_ms`]. Tasks that haven't run go
+    /// last.
+    Duration,
+}
+
+impl TaskSort {
+    /// Statuses in the order [`TaskSort::Status`] lists them.
+    pub fn status_order() -> impl Iterator<Item = &'static str> {
+        ["started", "pending"]
+            .into_iter()
+            .chain(
+                TERMINAL_TASK_STATUSES
+                    .iter()
+                    .copied()
+                    .filter(|status| *status != "complete"),
+            )
+            .chain(["complete"])
+    }
+
+    fn order_by(self) -> String {
+        const NEWEST: &str = "tasks.created_at DESC, tasks.id DESC";
+        match self {
+            TaskSort::Created => NEWEST.to_string(),
+            TaskSort::Status => {
+                let ranks: String = Self::status_order()
+                    .enumerate()
+                    .map(|(rank, status)| format!(" WHEN '{status}' THEN {rank}"))
+                    .collect();
+                format!(
+                    "CASE tasks.status{ranks} ELSE {} END, {NEWEST}",
+                    TERMINAL_TASK_STATUSES.len() + 2
+                )
+            }
+            TaskSort::Duration => {
+                format!("{TASK_RUN_MS} IS NULL, {TASK_RUN_MS} DESC, {NEWEST}")
+            }
+        }
+    }
+}
+
+/// Which part of the task list [`page_tasks`] returns.
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+pub struct TaskPage {
+    /// At most this many.
+    pub limit: Option<usize>,
+    /// Only tasks after the one this came from, by [`Task::cursor`]. The
+    /// cursor names a position rather than a task, so it still works after
+    /// that task is removed. Only for the [`TaskSort::Created`] order.
+    pub cursor: Option<String>,
+    /// Skip this many first, for orders a cursor can't page.
+    pub offset: Option<usize>,
+    /// Only tasks with one of these statuses, comma-separated.
+    pub status: Option<String>,
+    /// Only tasks in one of these projects, by name or alias,
+    /// comma-separated.
+    pub project: Option<String>,
+    #[serde(default)]
+    pub sort: TaskSort,
+}
+
+impl TaskPage {
+    /// Whether this asks for every task, newest first.
+    pub fn is_everything(&self) -> bool {
+        self.limit.is_none()
+            && self.cursor.is_none()
+            && self.offset.is_none()
+            && self.status.is_none()
+            && self.project.is_none()
+            && self.sort == TaskSort::Created
+    }
+}
+
+/// The part of the task list, in `page.sort` order, that `page` asks for.
+pub fn page_tasks(page: &TaskPage) -> Result<Vec<Task>, anyhow::Error> {
+    if page.cursor.is_some() && page.sort != TaskSort::Created {
+        bail_code!(
+            ErrorCode::InvalidRequest,
+            "a task cursor only pages tasks newest first; use offset for {:?}",
+            page.sort
+        );
+    }
+    let after = page
+        .cursor
+        .as_deref()
+        .map(|cursor| {
+            cursor.rsplit_once('/').ok_or_else(|| {
+                Error::new(
+                    ErrorCode::InvalidRequest,
+                    format!("invalid task cursor {cursor:?}"),
+                )
+            })
+        })
+        .transpose()?;
+    // Lists go in as JSON arrays, so the statement doesn't depend on their
+    // lengths.
+    let list = |names: Option<&str>| {
+        names.map(|names| {
+            let names: Vec<&str> = names.split(',').map(str::trim).collect();
+            serde_json::to_string(&names).unwrap_or_default()
+        })
+    };
+    let conn = connect()?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {TASK_COLUMNS} FROM tasks
+         WHERE (?1 IS NULL OR tasks.created_at < ?1 OR (tasks.created_at = ?1 AND tasks.id < ?2))
+           AND (?4 IS NULL OR tasks.status IN (SELECT value FROM json_each(?4)))
+           AND (?5 IS NULL OR tasks.project_id IN (
+             SELECT id FROM projects WHERE name IN (SELECT value FROM json_each(?5))
+             UNION SELECT project_id FROM project_aliases WHERE alias IN (SELECT value FROM json_each(?5))))
+         ORDER BY {} LIMIT ?3 OFFSET ?6",
+        page.sort.order_by()
+    ))?;
+    let limit = page.limit.map_or(-1, |limit| limit as i64);
+    let offset = page.offset.unwrap_or(0) as i64;
+    let (created_at, id) = after.unzip();
+    let tasks = stmt
+        .query_map(
+            rusqlite::params![
+                created_at,
+                id,
+                limit,
+                list(page.status.as_deref()),
+                list(page.project.as_deref()),
+                offset
+            ],
+            row_to_task,
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(tasks)
+}
+
+/// How many tasks there are, counted without loading them.
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct TaskCounts {
+    pub total: usize,
+    /// Keyed by project ID; projects without tasks are left out.
+    pub by_project: std::collections::BTreeMap<String, usize>,
+    /// Keyed by project ID, then status, for counting the tasks a filter
+    /// matches.
+    #[serde(default)]
+    pub by_status: std::collections::BTreeMap<String, std::collections::BTreeMap<String, usize>>,
+}
+
+pub fn count_tasks() -> Result<TaskCounts, anyhow::Error> {
+    let conn = connect()?;
+    let mut stmt =
+        conn.prepare("SELECT project_id, status, COUNT(*) FROM tasks GROUP BY project_id, status")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, usize>(2)?,
+        ))
+    })?;
+    let mut counts = TaskCounts::default();
+    for row in rows {
+        let (project_id, status, count) = row?;
+        counts.total += count;
+        *counts.by_project.entry(project_id.clone()).or_default() += count;
+        counts
+            .by_status
+            .entry(project_id)
+            .or_default()
+            .insert(status, count);
+    }
+    Ok(counts)
+}
+
+/// A set of related tasks in one project, shown and removed together.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct TaskGroup {
+    pub id: String,
+    pub project_id: String,
+    pub description: String,
+    /// Rolled up from the group's tasks; see [`TaskGroupCounts::status`].
+    pub status: String,
+    pub counts: TaskGroupCounts,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// How many of a group's tasks are in each state. `failed` covers every
+/// terminal status other than `complete`.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct TaskGroupCounts {
+    pub total: usize,
+    pub pending: usize,
+    pub running: usize,
+    pub complete: usize,
+    pub failed: usize,
+}
+
+impl TaskGroupCounts {
+    fn add(&mut self, status: &str) {
+        self.total += 1;
+        match status {
+            "pending" => self.pending += 1,
+            "started" => self.running += 1,
+            "complete" => self.complete += 1,
+            _ => self.failed += 1,
+        }
+    }
+
+    /// `running` while any task is, then `pending` while any has yet to
+    /// start, then `failed` if any failed, `complete` if all completed, and
+    /// `empty` for a group with no tasks.
+    pub fn status(&self) -> &'static str {
+        if self.running > 0 {
+            "running"
+        } else if self.pending > 0 {
+            "pending"
+        } else if self.failed > 0 {
+            "failed"
+        } else if self.complete > 0 {
+            "complete"
+        } else {
+            "empty"
+        }
+    }
+}
+
+/// Task statuses by group ID, for rolling up group status.
+fn group_task_statuses(conn: &Connection) -> Result<Vec<(String, String)>, anyhow::Error> {
+    let mut stmt = conn.prepare("SELECT group_id, status FROM tasks WHERE group_id IS NOT NULL")?;
+    let statuses = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(statuses)
+}
+
+fn row_to_task_group(row: &rusqlite::Row) -> rusqlite::Result<TaskGroup> {
+    Ok(TaskGroup {
+        id: row.get(0)?,
+        project_id: row.get(1)?,
+        description: row.get(2)?,
+        status: String::new(),
+        counts: TaskGroupCounts::default(),
+        created_at: row.get(3)?,
+        updated_at: row.get(4)?,
+    })
+}
+
+/// Fill in each group's counts and status from its tasks.
+fn roll_up_groups(conn: &Connection, groups: &mut [TaskGroup]) -> Result<(), anyhow::Error> {
+    for (group_id, status) in group_task_statuses(conn)? {
+        if let Some(group) = groups.iter_mut().find(|g| g.id == group_id) {
+            group.counts.add(&status);
+        }
+    }
+    for group in groups {
+        group.status = group.counts.status().to_string();
+    }
+    Ok(())
+}
+
+pub fn create_task_group(project_id: &str, description: &str) -> Result<TaskGroup, anyhow::Error> {
+    let conn = connect()?;
+    let project_exists: Option<String> = conn
+        .query_row(
+            "SELECT id FROM projects WHERE id = ?1 AND removing_at IS NULL",
+            rusqlite::params![project_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if project_exists.is_none() {
+        return Err(not_found(ErrorCode::ProjectNotFound, "project", project_id));
+    }
+
+    let id = crate::id::new_id();
+    let now = now_rfc3339();
+    conn.execute(
+        "INSERT INTO task_groups (id, project_id, description, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![&id, project_id, description, &now, &now],
+    )?;
+    drop(conn);
+    get_task_group(&id)
+}
+
+pub fn get_task_group(id: &str) -> Result<TaskGroup, anyhow::Error> {
+    let conn = connect()?;
+    let mut group = conn
+        .query_row(
+            "SELECT id, project_id, description, created_at, updated_at FROM task_groups WHERE id = ?1",
+            rusqlite::params![id],
+            row_to_task_group,
+        )
+        .optional()?
+        .ok_or_else(|| not_found(ErrorCode::GroupNotFound, "group", id))?;
+    roll_up_groups(&conn, std::slice::from_mut(&mut group))?;
+    Ok(group)
+}
+
+/// List task groups, newest first.
+pub fn list_task_groups() -> Result<Vec<TaskGroup>, anyhow::Error> {
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT id, project_id, description, created_at, updated_at FROM task_groups ORDER BY created_at DESC",
+    )?;
+    let mut groups = stmt
+        .query_map([], row_to_task_group)?
+        .collect::<Result<Vec<_>, _>>()?;
+    drop(stmt);
+    roll_up_groups(&conn, &mut groups)?;
+    Ok(groups)
+}
+
+/// Remove a group. A group that still has tasks is only removed with
+/// `cascade`, which removes the tasks too, through their providers unless
+/// `skip_provider` is set. Tasks still being removed are detached from the
+/// group so it can go right away. Returns the ids of the environments
+/// deleted on the spot, with `skip_provider`.
+pub fn remove_task_group(
+    id: &str,
+    cascade: bool,
+    skip_provider: bool,
+) -> Result<Vec<String>, anyhow::Error> {
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+
+    let exists = tx
+        .query_row(
+            "SELECT 1 FROM task_groups WHERE id = ?1",
+            rusqlite::params![id],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+    if !exists {
+        return Err(not_found(ErrorCode::GroupNotFound, "group", id));
+    }
+
+    let task_ids: Vec<String> = tx
+        .prepare("SELECT id FROM tasks WHERE group_id = ?1 ORDER BY created_at")?
+        .query_map(rusqlite::params![id], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    if !task_ids.is_empty() && !cascade {
+        return Err(Error::new(
+            ErrorCode::GroupNotEmpty,
+            format!(
+                "group {id} has {} task(s); remove them too with --cascade",
+                task_ids.len()
+            ),
+        )
+        .with_details(serde_json::json!({ "id": id, "task_ids": task_ids }))
+        .into());
+    }
+
+    let mut deleted = Vec::new();
+    for task_id in &task_ids {
+        if skip_provider {
+            deleted.push(force_delete_task_tx(&tx, task_id)?);
+        } else {
+            stage_remove_task_tx(&tx, task_id)?;
+        }
+    }
+    tx.execute(
+        "UPDATE tasks SET group_id = NULL WHERE group_id = ?1",
+        rusqlite::params![id],
+    )?;
+    tx.execute(
+        "DELETE FROM task_groups WHERE id = ?1",
+        rusqlite::params![id],
+    )?;
 
     tx.commit()?;
-    Ok(())
+    Ok(deleted)
 }
 
-pub fn stage_remove_task(task_id: &str) -> Result<(), anyhow::Error> {
-    let mut conn = connect()?;
-    let tx = conn.transaction()?;
-
-    let env_id: String = tx
-        .query_row(
-            "SELECT environment_id FROM tasks WHERE id = ?1",
-            rusqlite::params![task_id],
-            |row| row.get(0),
-        )
-        .map_err(|_| anyhow::anyhow!("task not found: {task_id}"))?;
+fn normalize_labels(labels: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut labels: Vec<String> = labels
+        .into_iter()
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect();
+    labels.sort();
+    labels.dedup();
+    labels
+}
 
-    let env_status: Option<String> = tx
-        .query_row(
-            "SELECT status FROM environments WHERE id = ?1",
-            rusqlite::params![&env_id],
-            |row| row.get(0),
-        )
-        .optional()?;
-    let env_status =
-        env_status.ok_or_else(|| anyhow::anyhow!("environment not found: {env_id}"))?;
+/// Add and remove labels on a task. Labels do not bump `updated_at`, so
+/// labelling a finished task does not reset its retention clock.
+pub fn update_task_labels(
+    id: &str,
+    add: &[String],
+    remove: &[String],
+) -> Result<Task, anyhow::Error> {
+    let task = get_task(id).map_err(|_| not_found(ErrorCode::TaskNotFound, "task", id))?;
+    let labels = normalize_labels(
+        task.labels
+            .into_iter()
+            .chain(add.iter().cloned())
+            .filter(|l| !remove.contains(l)),
+    );
+    let conn = connect()?;
+    conn.execute(
+        "UPDATE tasks SET labels = ?1 WHERE id = ?2",
+        rusqlite::params![serde_json::to_string(&labels)?, id],
+    )?;
+    drop(conn);
+    get_task(id)
+}
 
-    if env_status != "removing" {
-        let now = now_rfc3339();
-        tx.execute(
-            "UPDATE environments SET status = 'removing', updated_at = ?1 WHERE id = ?2",
-            rusqlite::params![&now, &env_id],
-        )?;
+/// Append a note to a task. Like labels, notes do not bump `updated_at`.
+pub fn add_task_note(id: &str, text: &str) -> Result<TaskNote, anyhow::Error> {
+    let text = text.trim();
+    if text.is_empty() {
+        bail_code!(ErrorCode::InvalidRequest, "note text is empty");
     }
-
-    let payload = serde_json::json!({
-        "task_id": task_id,
-        "env_id": env_id,
-    });
-    let dedupe = format!("remove_task:task:{task_id}");
-    let _ = insert_job_tx(&tx, "remove_task", &payload, Some(&dedupe))?;
-
-    tx.commit()?;
-    Ok(())
+    let note = TaskNote {
+        at: now_rfc3339(),
+        text: text.to_string(),
+    };
+    let conn = connect()?;
+    let rows = conn.execute(
+        "UPDATE tasks SET notes = json_insert(notes, '$[#]', json(?1)) WHERE id = ?2",
+        rusqlite::params![serde_json::to_string(&note)?, id],
+    )?;
+    if rows == 0 {
+        return Err(not_found(ErrorCode::TaskNotFound, "task", id));
+    }
+    Ok(note)
 }
 
-pub fn force_delete_task(task_id: &str) -> Result<(), anyhow::Error> {
+pub fn delete_task_and_environment(task_id: &str, env_id: &str) -> Result<(), anyhow::Error> {
     let mut conn = connect()?;
     let tx = conn.transaction()?;
-
-    let env_id: String = tx
-        .query_row(
-            "SELECT environment_id FROM tasks WHERE id = ?1",
-            rusqlite::params![task_id],
-            |row| row.get(0),
-        )
-        .map_err(|_| anyhow::anyhow!("task not found: {task_id}"))?;
-
     tx.execute(
         "DELETE FROM tasks WHERE id = ?1",
         rusqlite::params![task_id],
     )?;
-    let env_rows = tx.execute(
+    tx.execute(
         "DELETE FROM environments WHERE id = ?1",
-        rusqlite::params![&env_id],
+        rusqlite::params![env_id],
     )?;
-    if env_rows == 0 {
-        anyhow::bail!("environment not found: {env_id}");
-    }
-
+    delete_drained_projects(&tx)?;
     tx.commit()?;
     Ok(())
 }
 
-pub fn start_task(id: &str) -> Result<Task, anyhow::Error> {
+pub fn set_task_result(
+    id: &str,
+    summary: Option<&str>,
+    data: Option<&serde_json::Value>,
+    cost: Option<&TaskCost>,
+) -> Result<(), anyhow::Error> {
     let conn = connect()?;
     let now = now_rfc3339();
+    let data_str = data.map(serde_json::to_string).transpose()?;
+    let cost = cost.copied().unwrap_or_default();
     let rows = conn.execute(
-        "UPDATE tasks SET status = 'started', updated_at = ?1 WHERE id = ?2",
-        rusqlite::params![&now, id],
+        "UPDATE tasks SET result_summary = ?1, result_data = ?2, tokens_in = ?3, tokens_out = ?4, cost_usd = ?5, updated_at = ?6 WHERE id = ?7",
+        rusqlite::params![
+            summary,
+            data_str,
+            cost.tokens_in,
+            cost.tokens_out,
+            cost.cost_usd,
+            &now,
+            id
+        ],
     )?;
     if rows == 0 {
-        anyhow::bail!("task not found: {id}");
+        return Err(not_found(ErrorCode::TaskNotFound, "task", id));
     }
-    drop(conn);
-    get_task(id)
+    Ok(())
 }
 
-pub fn get_task(id: &str) -> Result<Task, anyhow::Error> {
+/// A running task process, recorded so orphans can be found after the task
+/// ends or the daemon restarts. Rows outlive task deletion on purpose.
+pub struct TaskProcess {
+    pub task_id: String,
+    pub pid: i64,
+    pub process_start: Option<String>,
+    pub daemon_pid: i64,
+}
+
+pub fn insert_task_process(
+    task_id: &str,
+    pid: u32,
+    process_start: Option<&str>,
+) -> Result<(), anyhow::Error> {
     let conn = connect()?;
-    let task = conn.query_row(
-        "SELECT id, environment_id, project_id, provider, description, status, created_at, updated_at FROM tasks WHERE id = ?1",
-        rusqlite::params![id],
-        row_to_task,
+    let now = now_rfc3339();
+    conn.execute(
+        "INSERT OR REPLACE INTO task_processes (task_id, pid, process_start, daemon_pid, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![task_id, pid, process_start, std::process::id(), &now],
     )?;
-    Ok(task)
+    Ok(())
 }
 
-pub fn list_tasks() -> Result<Vec<Task>, anyhow::Error> {
+pub fn delete_task_process(task_id: &str) -> Result<(), anyhow::Error> {
+    let conn = connect()?;
+    conn.execute(
+        "DELETE FROM task_processes WHERE task_id = ?1",
+        rusqlite::params![task_id],
+    )?;
+    Ok(())
+}
+
+pub fn list_task_processes() -> Result<Vec<TaskProcess>, anyhow::Error> {
     let conn = connect()?;
     let mut stmt = conn.prepare(
-        "SELECT id, environment_id, project_id, provider, description, status, created_at, updated_at FROM tasks ORDER BY created_at DESC",
+        "SELECT task_id, pid, process_start, daemon_pid FROM task_processes ORDER BY created_at",
     )?;
-    let tasks = stmt
-        .query_map([], row_to_task)?
+    let processes = stmt
+        .query_map([], |row| {
+            Ok(TaskProcess {
+                task_id: row.get(0)?,
+                pid: row.get(1)?,
+                process_start: row.get(2)?,
+                daemon_pid: row.get(3)?,
+            })
+        })?
         .collect::<Result<Vec<_>, _>>()?;
-    Ok(tasks)
+    Ok(processes)
 }
 
-pub fn delete_task_and_environment(task_id: &str, env_id: &str) -> Result<(), anyhow::Error> {
-    let mut conn = connect()?;
-    let tx = conn.transaction()?;
-    tx.execute(
-        "DELETE FROM tasks WHERE id = ?1",
-        rusqlite::params![task_id],
-    )?;
-    tx.execute(
-        "DELETE FROM environments WHERE id = ?1",
-        rusqlite::params![env_id],
+/// Resource usage of a finished task's runner process.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ResourceUsage {
+    pub wall_ms: u64,
+    pub user_cpu_ms: u64,
+    pub system_cpu_ms: u64,
+    pub peak_rss_kb: u64,
+}
+
+pub fn set_task_resource_usage(id: &str, usage: &ResourceUsage) -> Result<(), anyhow::Error> {
+    let conn = connect()?;
+    let now = now_rfc3339();
+    let usage_str = serde_json::to_string(usage)?;
+    let rows = conn.execute(
+        "UPDATE tasks SET resource_usage = ?1, updated_at = ?2 WHERE id = ?3",
+        rusqlite::params![usage_str, &now, id],
     )?;
-    tx.commit()?;
+    if rows == 0 {
+        return Err(not_found(ErrorCode::TaskNotFound, "task", id));
+    }
     Ok(())
 }
 
@@ -685,13 +2948,95 @@ pub fn update_task_status(id: &str, status: &str) -> Result<Task, anyhow::Error>
         rusqlite::params![status, &now, id],
     )?;
     if rows == 0 {
-        anyhow::bail!("task not found: {id}");
+        return Err(not_found(ErrorCode::TaskNotFound, "task", id));
     }
     drop(conn);
     get_task(id)
 }
 
+/// A task matched by a search, with a snippet of the matching text.
 #[derive(serde::Deserialize, serde::Serialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub task: Task,
+    /// The field the snippet was taken from: `description` or `log`.
+    pub matched: String,
+    pub snippet: String,
+    /// Character ranges within `snippet` that matched the query.
+    pub highlights: Vec<(usize, usize)>,
+}
+
+/// Search task descriptions, and task logs when `include_logs` is set, using
+/// the full-text index. Results are ordered by relevance.
+pub fn search_tasks(
+    query: &str,
+    include_logs: bool,
+    limit: usize,
+) -> Result<Vec<SearchHit>, anyhow::Error> {
+    let fts_query = search::fts_query(query, include_logs)
+        .ok_or_else(|| Error::new(ErrorCode::InvalidRequest, "search query is empty"))?;
+
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        &format!("SELECT {TASK_COLUMNS}, snippet(task_search, 1, char(2), char(3), '…', 16), snippet(task_search, 2, char(2), char(3), '…', 16) FROM task_search JOIN tasks ON tasks.id = task_search.task_id WHERE task_search MATCH ?1 ORDER BY rank LIMIT ?2"),
+    )?;
+    let hits = stmt
+        .query_map(rusqlite::params![fts_query, limit as i64], |row| {
+            let task = row_to_task(row)?;
+            let description: String = row.get(26)?;
+            let log: String = row.get(27)?;
+            Ok((task, description, log))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(hits
+        .into_iter()
+        .map(|(task, description, log)| {
+            let (matched, raw) = if description.contains(search::MATCH_START)
+                || !log.contains(search::MATCH_START)
+            {
+                ("description", description)
+            } else {
+                ("log", log)
+            };
+            let (snippet, highlights) = search::parse_snippet(&raw);
+            SearchHit {
+                task,
+                matched: matched.to_string(),
+                snippet,
+                highlights,
+            }
+        })
+        .collect())
+}
+
+/// Replace the indexed log text for a task.
+pub fn set_task_search_log(task_id: &str, log: &str) -> Result<(), anyhow::Error> {
+    let conn = connect()?;
+    conn.execute(
+        "UPDATE task_search SET log = ?1, log_indexed = 1 WHERE task_id = ?2",
+        rusqlite::params![log, task_id],
+    )?;
+    Ok(())
+}
+
+/// Finished tasks whose logs have not been added to the search index.
+pub fn list_unindexed_task_logs() -> Result<Vec<String>, anyhow::Error> {
+    let conn = connect()?;
+    let placeholders = vec!["?"; TERMINAL_TASK_STATUSES.len()].join(", ");
+    let mut stmt = conn.prepare(&format!(
+        "SELECT tasks.id FROM tasks JOIN task_search ON task_search.task_id = tasks.id WHERE task_search.log_indexed = 0 AND tasks.status IN ({placeholders})",
+    ))?;
+    let ids = stmt
+        .query_map(rusqlite::params_from_iter(TERMINAL_TASK_STATUSES), |row| {
+            row.get(0)
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ids)
+}
+
+/// A background job in the daemon's queue.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
 pub struct Job {
     pub id: String,
     #[serde(rename = "type")]
@@ -699,10 +3044,23 @@ pub struct Job {
     pub payload: serde_json::Value,
     pub status: String,
     pub attempt: i64,
+    pub not_before: Option<String>,
+    pub last_error: Option<String>,
+    /// Every failed attempt, oldest first.
+    #[serde(default)]
+    pub errors: Vec<JobError>,
     pub created_at: String,
     pub updated_at: String,
 }
 
+/// The error a job's attempt ended with.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct JobError {
+    pub attempt: i64,
+    pub error: String,
+    pub at: String,
+}
+
 fn row_to_job(row: &rusqlite::Row) -> rusqlite::Result<Job> {
     let payload_str: String = row.get(2)?;
     let payload: serde_json::Value =
@@ -713,6 +3071,9 @@ fn row_to_job(row: &rusqlite::Row) -> rusqlite::Result<Job> {
         payload,
         status: row.get(3)?,
         attempt: row.get(4)?,
+        not_before: row.get(7)?,
+        last_error: row.get(8)?,
+        errors: serde_json::from_str(&row.get::<_, String>(9)?).unwrap_or_default(),
         created_at: row.get(5)?,
         updated_at: row.get(6)?,
     })
@@ -732,12 +3093,32 @@ pub fn create_job_with_dedupe(
 
 pub fn get_job(id: &str) -> Result<Job, anyhow::Error> {
     let conn = connect()?;
-    let job = conn.query_row(
-        "SELECT id, type, payload, status, attempt, created_at, updated_at FROM jobs WHERE id = ?1",
+    conn.query_row(
+        "SELECT id, type, payload, status, attempt, created_at, updated_at, not_before, last_error, errors FROM jobs WHERE id = ?1",
         rusqlite::params![id],
         row_to_job,
+    )
+    .optional()?
+    .ok_or_else(|| not_found(ErrorCode::JobNotFound, "job", id))
+}
+
+/// A job together with the retry policy the daemon applies to it.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct JobDetail {
+    #[serde(flatten)]
+    pub job: Job,
+    pub retry_policy: crate::config::RetryPolicy,
+}
+
+pub fn list_jobs() -> Result<Vec<Job>, anyhow::Error> {
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT id, type, payload, status, attempt, created_at, updated_at, not_before, last_error, errors FROM jobs ORDER BY created_at DESC",
     )?;
-    Ok(job)
+    let jobs = stmt
+        .query_map([], row_to_job)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(jobs)
 }
 
 pub fn claim_pending_jobs(limit: usize, lease_seconds: i64) -> Result<Vec<Job>, anyhow::Error> {
@@ -751,16 +3132,33 @@ pub fn claim_pending_jobs(limit: usize, lease_seconds: i64) -> Result<Vec<Job>,
     let lease_expires_at =
         (chrono::Utc::now() + chrono::Duration::seconds(lease_seconds)).to_rfc3339();
 
+    // Within a priority, projects take turns: a job's turn is how many
+    // jobs its project already has running plus how many of its ready jobs
+    // are older, so a project queueing fifty prepares gets one slot in turn
+    // with every other project instead of all of them first.
     let mut jobs = {
         let mut stmt = tx.prepare(
-            "SELECT id, type, payload, status, attempt, created_at, updated_at
-             FROM jobs
-             WHERE (
-                 (status = 'pending' AND (not_before IS NULL OR not_before <= ?1))
-                 OR
-                 (status = 'running' AND lease_expires_at IS NOT NULL AND lease_expires_at <= ?1)
+            "WITH ready AS (
+                 SELECT id, type, payload, status, attempt, created_at, updated_at, not_before, last_error, errors, priority, project_id
+                 FROM jobs
+                 WHERE (
+                     (status = 'pending' AND (not_before IS NULL OR not_before <= ?1))
+                     OR
+                     (status = 'running' AND lease_expires_at IS NOT NULL AND lease_expires_at <= ?1)
+                 )
+             ),
+             busy AS (
+                 SELECT project_id, COUNT(*) AS running
+                 FROM jobs
+                 WHERE status = 'running' AND (lease_expires_at IS NULL OR lease_expires_at > ?1)
+                 GROUP BY project_id
              )
-             ORDER BY created_at ASC
+             SELECT ready.id, ready.type, ready.payload, ready.status, ready.attempt, ready.created_at, ready.updated_at, ready.not_before, ready.last_error, ready.errors
+             FROM ready LEFT JOIN busy ON busy.project_id IS ready.project_id
+             ORDER BY ready.priority DESC,
+                 COALESCE(busy.running, 0)
+                     + ROW_NUMBER() OVER (PARTITION BY ready.priority, ready.project_id ORDER BY ready.created_at) ASC,
+                 ready.created_at ASC
              LIMIT ?2",
         )?;
         stmt.query_map(rusqlite::params![&now, limit as i64], row_to_job)?
@@ -775,6 +3173,8 @@ pub fn claim_pending_jobs(limit: usize, lease_seconds: i64) -> Result<Vec<Job>,
         job.status = "running".to_string();
         job.updated_at = now.clone();
         job.attempt += 1;
+        job.not_before = None;
+        job.last_error = None;
     }
 
     tx.commit()?;
@@ -789,20 +3189,27 @@ pub fn mark_job_complete(id: &str) -> Result<(), anyhow::Error> {
         rusqlite::params![&now, id],
     )?;
     if rows == 0 {
-        anyhow::bail!("job not found: {id}");
+        return Err(not_found(ErrorCode::JobNotFound, "job", id));
     }
     Ok(())
 }
 
-pub fn mark_job_failed(id: &str, error: &str) -> Result<(), anyhow::Error> {
+/// An assignment that appends error `?1`, recorded at `?2`, to a job's history.
+const APPEND_JOB_ERROR: &str = "errors = json_insert(errors, '$[#]', json_object('attempt', attempt + 1, 'error', ?1, 'at', ?2))";
+
+/// Move a job that has exhausted its retries to the dead-letter queue, keeping
+/// its payload and error history for `work job retry`.
+pub fn mark_job_dead(id: &str, error: &str) -> Result<(), anyhow::Error> {
     let conn = connect()?;
     let now = now_rfc3339();
     let rows = conn.execute(
-        "UPDATE jobs SET status = 'failed', dedupe_key = NULL, not_before = NULL, lease_expires_at = NULL, last_error = ?1, updated_at = ?2 WHERE id = ?3",
+        &format!(
+            "UPDATE jobs SET status = 'dead', dedupe_key = NULL, not_before = NULL, lease_expires_at = NULL, last_error = ?1, {APPEND_JOB_ERROR}, updated_at = ?2 WHERE id = ?3"
+        ),
         rusqlite::params![error, &now, id],
     )?;
     if rows == 0 {
-        anyhow::bail!("job not found: {id}");
+        return Err(not_found(ErrorCode::JobNotFound, "job", id));
     }
     Ok(())
 }
@@ -812,15 +3219,213 @@ pub fn requeue_job(id: &str, error: &str, delay_seconds: i64) -> Result<(), anyh
     let now = chrono::Utc::now();
     let not_before = (now + chrono::Duration::seconds(delay_seconds)).to_rfc3339();
     let rows = conn.execute(
-        "UPDATE jobs SET status = 'pending', not_before = ?1, lease_expires_at = NULL, last_error = ?2, updated_at = ?3 WHERE id = ?4",
-        rusqlite::params![&not_before, error, &now.to_rfc3339(), id],
+        &format!(
+            "UPDATE jobs SET status = 'pending', not_before = ?3, lease_expires_at = NULL, last_error = ?1, {APPEND_JOB_ERROR}, updated_at = ?2 WHERE id = ?4"
+        ),
+        rusqlite::params![error, &now.to_rfc3339(), &not_before, id],
+    )?;
+    if rows == 0 {
+        return Err(not_found(ErrorCode::JobNotFound, "job", id));
+    }
+    Ok(())
+}
+
+/// Requeue a dead job for a fresh set of attempts. Its error history is kept.
+///
+/// Whatever the job marked failed when it died is put back the way the job
+/// expects to find it, so the retry runs as the original would have.
+pub fn retry_dead_job(id: &str) -> Result<Job, anyhow::Error> {
+    let job = get_job(id)?;
+    if job.status != "dead" {
+        return Err(Error::new(
+            ErrorCode::JobNotDead,
+            format!("job {id} is {}, not dead", job.status),
+        )
+        .with_details(serde_json::json!({ "id": id, "status": job.status }))
+        .into());
+    }
+
+    let (env_status, task_status) = match job.job_type.as_str() {
+        "prepare_environment" => (Some("preparing"), Some("pending")),
+        "claim_environment" | "run_task" => (Some("in_use"), Some("pending")),
+        "update_environment" => (Some("pool"), None),
+        _ => (None, None),
+    };
+
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+    let now = now_rfc3339();
+    if let (Some(status), Some(env_id)) = (env_status, job.payload["env_id"].as_str()) {
+        tx.execute(
+            "UPDATE environments SET status = ?1, updated_at = ?2 WHERE id = ?3 AND status = 'failed'",
+            rusqlite::params![status, &now, env_id],
+        )?;
+    }
+    if let (Some(status), Some(task_id)) = (task_status, job.payload["task_id"].as_str()) {
+        tx.execute(
+            "UPDATE tasks SET status = ?1, updated_at = ?2 WHERE id = ?3 AND status = 'failed'",
+            rusqlite::params![status, &now, task_id],
+        )?;
+    }
+    // The job may have used up its claim token before it died.
+    if job.job_type == "run_task"
+        && let (Some(env_id), Some(claim_token)) = (
+            job.payload["env_id"].as_str(),
+            job.payload["claim_token"].as_str(),
+        )
+    {
+        tx.execute(
+            "UPDATE environments SET claim_token = ?1 WHERE id = ?2",
+            rusqlite::params![claim_token, env_id],
+        )?;
+    }
+    tx.execute(
+        "UPDATE jobs SET status = 'pending', attempt = 0, not_before = NULL, lease_expires_at = NULL, last_error = NULL, updated_at = ?1 WHERE id = ?2",
+        rusqlite::params![&now, id],
+    )?;
+    tx.commit()?;
+    get_job(id)
+}
+
+/// Delete every dead job, returning how many there were.
+pub fn purge_dead_jobs() -> Result<usize, anyhow::Error> {
+    let conn = connect()?;
+    Ok(conn.execute("DELETE FROM jobs WHERE status = 'dead'", [])?)
+}
+
+/// Whether the daemon has work in flight: a queued or running job, or a task
+/// whose runner is still going.
+pub fn has_active_work() -> Result<bool, anyhow::Error> {
+    let conn = connect()?;
+    Ok(conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM jobs WHERE status IN ('pending', 'running'))
+             OR EXISTS (SELECT 1 FROM tasks WHERE status = 'started')",
+        [],
+        |row| row.get(0),
+    )?)
+}
+
+/// Put a claimed job back in the queue without counting the claim as an attempt.
+pub fn defer_job(id: &str, reason: &str, delay_seconds: i64) -> Result<(), anyhow::Error> {
+    let conn = connect()?;
+    let now = chrono::Utc::now();
+    let not_before = (now + chrono::Duration::seconds(delay_seconds)).to_rfc3339();
+    let rows = conn.execute(
+        "UPDATE jobs SET status = 'pending', attempt = MAX(attempt - 1, 0), not_before = ?1, lease_expires_at = NULL, last_error = ?2, updated_at = ?3 WHERE id = ?4",
+        rusqlite::params![&not_before, reason, &now.to_rfc3339(), id],
     )?;
     if rows == 0 {
-        anyhow::bail!("job not found: {id}");
+        return Err(not_found(ErrorCode::JobNotFound, "job", id));
     }
     Ok(())
 }
 
+/// Put a job the daemon is stopping under back in the queue, ready to run
+/// as soon as a daemon starts, instead of leaving it to wait out its lease.
+/// The interrupted run doesn't count as an attempt. Returns false if the job
+/// wasn't running any more.
+pub fn requeue_interrupted_job(id: &str, reason: &str) -> Result<bool, anyhow::Error> {
+    let conn = connect()?;
+    let rows = conn.execute(
+        "UPDATE jobs SET status = 'pending', attempt = MAX(attempt - 1, 0), not_before = NULL, lease_expires_at = NULL, last_error = ?1, updated_at = ?2 WHERE id = ?3 AND status = 'running'",
+        rusqlite::params![reason, now_rfc3339(), id],
+    )?;
+    Ok(rows > 0)
+}
+
+/// Put back the rows a job trace recorded for a claimed `job`, so `work
+/// debug replay` can claim and run it against a scratch database. Projects,
+/// environments, and tasks the database already has are left as they are,
+/// since earlier replayed jobs changed them. The job itself is queued, ready
+/// to claim, holding its environment's claim if one is outstanding. A job
+/// new to the database starts at the attempt before its recorded one; one
+/// the replay already ran keeps the attempts it used.
+pub fn restore_traced_job(
+    job: &Job,
+    project: Option<&Project>,
+    environment: Option<&Environment>,
+    task: Option<&Task>,
+) -> Result<(), anyhow::Error> {
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+    if let Some(project) = project {
+        tx.execute(
+            "INSERT OR IGNORE INTO projects (id, name, path, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![
+                project.id,
+                project.name,
+                project.path,
+                project.created_at,
+                project.updated_at
+            ],
+        )?;
+    }
+    if let Some(env) = environment {
+        tx.execute(
+            "INSERT OR IGNORE INTO environments (id, project_id, provider, status, metadata, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            rusqlite::params![
+                env.id,
+                env.project_id,
+                env.provider,
+                env.status,
+                env.metadata.to_string(),
+                env.created_at,
+                env.updated_at
+            ],
+        )?;
+    }
+    if let Some(task) = task {
+        tx.execute(
+            "INSERT OR IGNORE INTO tasks (id, environment_id, project_id, provider, description, status, labels, requirements, priority, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            rusqlite::params![
+                task.id,
+                task.environment_id,
+                task.project_id,
+                task.provider,
+                task.description,
+                task.status,
+                serde_json::to_string(&task.labels)?,
+                serde_json::to_string(&task.requirements)?,
+                task.priority.as_str(),
+                task.created_at,
+                task.updated_at
+            ],
+        )?;
+    }
+    // The replayed job queued for this one made a claim token of its own;
+    // the traced job takes it over if it's still outstanding.
+    if let (Some(env_id), Some(claim_token)) = (
+        job.payload["env_id"].as_str(),
+        job.payload["claim_token"].as_str(),
+    ) {
+        tx.execute(
+            "UPDATE environments SET claim_token = ?1 WHERE id = ?2 AND claim_token IS NOT NULL",
+            rusqlite::params![claim_token, env_id],
+        )?;
+    }
+    let project_id = task
+        .map(|t| &t.project_id)
+        .or(environment.map(|e| &e.project_id));
+    tx.execute(
+        "INSERT INTO jobs (id, type, payload, status, attempt, priority, project_id, created_at, updated_at)
+         VALUES (?1, ?2, ?3, 'pending', MAX(?4 - 1, 0),
+             COALESCE((SELECT CASE priority WHEN 'high' THEN 1 WHEN 'low' THEN -1 ELSE 0 END FROM tasks WHERE id = json_extract(?3, '$.task_id')), 0),
+             ?5, ?6, ?7)
+         ON CONFLICT(id) DO UPDATE SET status = 'pending', not_before = NULL, lease_expires_at = NULL, updated_at = excluded.updated_at",
+        rusqlite::params![
+            job.id,
+            job.job_type,
+            job.payload.to_string(),
+            job.attempt,
+            project_id,
+            job.created_at,
+            now_rfc3339()
+        ],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
 pub fn refresh_job_lease(id: &str, lease_seconds: i64) -> Result<bool, anyhow::Error> {
     let conn = connect()?;
     let now = chrono::Utc::now();
@@ -831,3 +3436,213 @@ pub fn refresh_job_lease(id: &str, lease_seconds: i64) -> Result<bool, anyhow::E
     )?;
     Ok(rows > 0)
 }
+
+/// A recorded change to a task, environment, project, group, or job.
+///
+/// Triggers write events in the same transaction as the change, so every
+/// committed change has one and `seq` orders them.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct Event {
+    pub seq: i64,
+    /// `task`, `environment`, `project`, `group`, or `job`.
+    pub kind: String,
+    pub subject_id: String,
+    /// `created`, `updated`, or `removed`.
+    pub action: String,
+    /// The subject's status after the change, for kinds that have one.
+    pub status: Option<String>,
+    pub created_at: String,
+}
+
+/// Which events [`list_events`] returns.
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+pub struct EventQuery {
+    /// Only events after this sequence number.
+    pub since: Option<i64>,
+    pub kind: Option<String>,
+    pub subject_id: Option<String>,
+    /// At most this many, the oldest first.
+    pub limit: Option<usize>,
+}
+
+/// Events matching `query`, oldest first.
+pub fn list_events(query: &EventQuery) -> Result<Vec<Event>, anyhow::Error> {
+    let conn = connect()?;
+    let mut stmt = conn.prepare(
+        "SELECT seq, kind, subject_id, action, status, created_at FROM events
+         WHERE seq > ?1 AND (?2 IS NULL OR kind = ?2) AND (?3 IS NULL OR subject_id = ?3)
+         ORDER BY seq LIMIT ?4",
+    )?;
+    let limit = query.limit.map_or(-1, |limit| limit as i64);
+    let events = stmt
+        .query_map(
+            rusqlite::params![
+                query.since.unwrap_or(0),
+                query.kind,
+                query.subject_id,
+                limit
+            ],
+            |row| {
+                Ok(Event {
+                    seq: row.get(0)?,
+                    kind: row.get(1)?,
+                    subject_id: row.get(2)?,
+                    action: row.get(3)?,
+                    status: row.get(4)?,
+                    created_at: row.get(5)?,
+                })
+            },
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(events)
+}
+
+/// The event about the same subject just before `event`, if it hasn't been
+/// pruned.
+pub fn previous_event(event: &Event) -> Result<Option<Event>, anyhow::Error> {
+    let conn = connect()?;
+    Ok(conn
+        .query_row(
+            "SELECT seq, kind, subject_id, action, status, created_at FROM events
+             WHERE kind = ?1 AND subject_id = ?2 AND seq < ?3
+             ORDER BY seq DESC LIMIT 1",
+            rusqlite::params![event.kind, event.subject_id, event.seq],
+            |row| {
+                Ok(Event {
+                    seq: row.get(0)?,
+                    kind: row.get(1)?,
+                    subject_id: row.get(2)?,
+                    action: row.get(3)?,
+                    status: row.get(4)?,
+                    created_at: row.get(5)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+/// The sequence number of the newest event ever recorded, or 0 if there
+/// have been none. Pruning doesn't lower it.
+pub fn latest_event_seq() -> Result<i64, anyhow::Error> {
+    let conn = connect()?;
+    latest_event_seq_with(&conn)
+}
+
+fn latest_event_seq_with(conn: &Connection) -> Result<i64, anyhow::Error> {
+    Ok(conn.query_row(
+        "SELECT COALESCE((SELECT seq FROM sqlite_sequence WHERE name = 'events'), 0)",
+        [],
+        |row| row.get(0),
+    )?)
+}
+
+/// Whether every event after `since` is still in the table, so a client
+/// that saw up to `since` can catch up by replaying them. A cursor from
+/// before the last prune, or from a database that has since been reset,
+/// can't be.
+pub fn can_replay_events_since(since: i64) -> Result<bool, anyhow::Error> {
+    let conn = connect()?;
+    let latest = latest_event_seq_with(&conn)?;
+    let oldest: Option<i64> =
+        conn.query_row("SELECT MIN(seq) FROM events", [], |row| row.get(0))?;
+    Ok(since <= latest && oldest.unwrap_or(latest + 1) <= since + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_since_uses_the_largest_whole_unit() {
+        let now = chrono::DateTime::parse_from_rfc3339("2026-01-02T03:04:05Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let since = |at: &str| time_since(at, now);
+        assert_eq!(since("2026-01-02T03:04:00Z").as_deref(), Some("5s"));
+        assert_eq!(since("2026-01-02T03:00:00+00:00").as_deref(), Some("4m"));
+        assert_eq!(since("2026-01-02T00:04:05.123Z").as_deref(), Some("2h"));
+        assert_eq!(since("2025-12-28T03:04:05Z").as_deref(), Some("5d"));
+        assert_eq!(since("2026-01-02T04:00:00Z").as_deref(), Some("0s"));
+        assert_eq!(since("yesterday"), None);
+    }
+
+    #[test]
+    fn innermost_project_owns_a_directory() {
+        let project = |name: &str, path: &str| Project {
+            id: name.to_string(),
+            name: name.to_string(),
+            path: path.to_string(),
+            created_at: String::new(),
+            updated_at: String::new(),
+            aliases: Vec::new(),
+        };
+        let projects = [
+            project("site", "/src/mono/site"),
+            project("mono", "/src/mono"),
+            project("monolith", "/src/monolith"),
+        ];
+        let owner = |dir: &str| {
+            project_for_dir(&projects, std::path::Path::new(dir)).map(|p| p.name.as_str())
+        };
+        assert_eq!(owner("/src/mono/site/pages"), Some("site"));
+        assert_eq!(owner("/src/mono/api"), Some("mono"));
+        assert_eq!(owner("/src/monolith"), Some("monolith"));
+        assert_eq!(owner("/src"), None);
+    }
+
+    #[test]
+    fn search_index_follows_description_changes() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::run(&mut conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO projects (id, name, path, created_at, updated_at) VALUES ('p1', 'repo', '/repo', '', '');
+             INSERT INTO environments (id, project_id, provider, status, created_at, updated_at) VALUES ('e1', 'p1', 'git-worktree', 'in_use', '', '');
+             INSERT INTO tasks (id, environment_id, project_id, provider, description, status, created_at, updated_at) VALUES ('t1', 'e1', 'p1', 'claude', 'fix the parser', 'pending', '', '');
+             UPDATE tasks SET description = 'rewrite the lexer' WHERE id = 't1';",
+        )
+        .unwrap();
+        let matches = |term: &str| -> i64 {
+            conn.query_row(
+                "SELECT COUNT(*) FROM task_search WHERE task_search MATCH ?1",
+                [term],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(matches("lexer"), 1);
+        assert_eq!(matches("parser"), 0);
+    }
+
+    #[test]
+    fn project_paths_overlap_when_one_is_inside_the_other() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::run(&mut conn).unwrap();
+        let repo = dir.path().join("repo");
+        std::fs::create_dir_all(repo.join("sub")).unwrap();
+        conn.execute(
+            "INSERT INTO projects (id, name, path, created_at, updated_at) VALUES ('p1', 'repo', ?1, '', '')",
+            rusqlite::params![repo.to_string_lossy()],
+        )
+        .unwrap();
+        let overlap = |path: &std::path::Path| {
+            conflicting_project(&conn, &canonical_path(path), None, |_| false)
+                .unwrap()
+                .map(|(project, overlap)| (project.name, overlap))
+        };
+
+        assert_eq!(
+            overlap(&repo.join("sub/..")),
+            Some(("repo".to_string(), PathOverlap::Same))
+        );
+        assert_eq!(
+            overlap(&repo.join("sub")),
+            Some(("repo".to_string(), PathOverlap::Inside))
+        );
+        assert_eq!(
+            overlap(dir.path()),
+            Some(("repo".to_string(), PathOverlap::Contains))
+        );
+        assert_eq!(overlap(&dir.path().join("repo-2")), None);
+    }
+}
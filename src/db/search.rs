@@ -0,0 +1,80 @@
+/// Marks the start and end of a match in FTS5 `snippet()` output. Control
+/// characters are stripped from indexed logs, so they are safe delimiters.
+pub(super) const MATCH_START: char = '\u{2}';
+pub(super) const MATCH_END: char = '\u{3}';
+
+/// Build an FTS5 query from free text. Each whitespace-separated term is
+/// quoted so user input is never parsed as query syntax; all terms must
+/// match. Returns `None` when there are no terms.
+pub(super) fn fts_query(input: &str, include_logs: bool) -> Option<String> {
+    let terms: Vec<String> = input
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+    if terms.is_empty() {
+        return None;
+    }
+    let terms = terms.join(" ");
+    if include_logs {
+        Some(format!("{{description log}} : ({terms})"))
+    } else {
+        Some(format!("description : ({terms})"))
+    }
+}
+
+/// Strip match markers from a snippet, returning the plain text and the
+/// character ranges of each highlighted match.
+pub(super) fn parse_snippet(raw: &str) -> (String, Vec<(usize, usize)>) {
+    let mut text = String::with_capacity(raw.len());
+    let mut highlights = Vec::new();
+    let mut start = None;
+    let mut len = 0;
+
+    for c in raw.chars() {
+        match c {
+            MATCH_START => start = Some(len),
+            MATCH_END => {
+                if let Some(start) = start.take() {
+                    highlights.push((start, len));
+                }
+            }
+            // Snippets of log output often span lines; keep them on one.
+            '\n' | '\r' | '\t' => {
+                text.push(' ');
+                len += 1;
+            }
+            c => {
+                text.push(c);
+                len += 1;
+            }
+        }
+    }
+
+    (text, highlights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_terms_and_scopes_columns() {
+        assert_eq!(
+            fts_query("auth module", false).as_deref(),
+            Some(r#"description : ("auth" "module")"#)
+        );
+        assert_eq!(
+            fts_query(r#"say "hi" OR"#, true).as_deref(),
+            Some(r#"{description log} : ("say" """hi""" "OR")"#)
+        );
+        assert_eq!(fts_query("   ", true), None);
+    }
+
+    #[test]
+    fn extracts_highlight_ranges() {
+        let raw = format!("…fix the {MATCH_START}auth{MATCH_END}\nmodule");
+        let (text, highlights) = parse_snippet(&raw);
+        assert_eq!(text, "…fix the auth module");
+        assert_eq!(highlights, vec![(9, 13)]);
+    }
+}
@@ -0,0 +1,606 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use rusqlite::{OptionalExtension, Transaction};
+
+use crate::error::{ErrorCode, bail_code};
+
+use super::{
+    PathOverlap, ResourceUsage, TERMINAL_TASK_STATUSES, TaskCost, TaskNote, TaskPriority,
+    alias_owner, canonical_path, conflicting_project, connect, normalize_labels, now_rfc3339,
+};
+
+/// Bumped when the export format changes incompatibly.
+pub const STATE_FORMAT_VERSION: u32 = 1;
+
+/// Provider recorded on the placeholder environments of imported tasks. Their
+/// checkouts stayed on the machine they were exported from, so removing one
+/// never calls a provider.
+pub const IMPORTED_ENVIRONMENT_PROVIDER: &str = "imported";
+
+/// Projects and, optionally, finished tasks, keyed by name rather than by
+/// database ID so they can be loaded into another daemon.
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct StateExport {
+    pub version: u32,
+    pub exported_at: String,
+    pub projects: Vec<ExportedProject>,
+    /// Groups the exported tasks belong to.
+    #[serde(default)]
+    pub groups: Vec<ExportedGroup>,
+    #[serde(default)]
+    pub tasks: Vec<ExportedTask>,
+}
+
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ExportedProject {
+    pub name: String,
+    pub path: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ExportedGroup {
+    pub id: String,
+    /// Project name.
+    pub project: String,
+    pub description: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ExportedTask {
+    pub id: String,
+    /// Project name.
+    pub project: String,
+    pub provider: String,
+    pub description: String,
+    pub status: String,
+    #[serde(default)]
+    pub result_summary: Option<String>,
+    #[serde(default)]
+    pub result_data: Option<serde_json::Value>,
+    #[serde(default)]
+    pub resource_usage: Option<ResourceUsage>,
+    #[serde(default)]
+    pub cost: Option<TaskCost>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    #[serde(default)]
+    pub source_ref: Option<String>,
+    #[serde(default)]
+    pub requirements: Vec<String>,
+    #[serde(default)]
+    pub priority: TaskPriority,
+    #[serde(default)]
+    pub notes: Vec<TaskNote>,
+    #[serde(default)]
+    pub group_id: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl StateExport {
+    /// Rewrite project paths with `maps`, for checkouts that live somewhere
+    /// else on the new machine. Returns how many paths changed.
+    pub fn remap_paths(&mut self, maps: &[PathMap]) -> usize {
+        let mut changed = 0;
+        for project in &mut self.projects {
+            if let Some(path) = remap_path(maps, &project.path) {
+                project.path = path;
+                changed += 1;
+            }
+        }
+        changed
+    }
+}
+
+/// A path prefix to replace, written `FROM=TO` on the command line.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct PathMap {
+    pub from: String,
+    pub to: String,
+}
+
+impl std::str::FromStr for PathMap {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("path map must be FROM=TO, got {s}"))?;
+        let from = from.trim_end_matches('/');
+        let to = to.trim_end_matches('/');
+        if !from.starts_with('/') || !to.starts_with('/') {
+            anyhow::bail!("path map must use absolute paths, got {s}");
+        }
+        Ok(Self {
+            from: from.to_string(),
+            to: to.to_string(),
+        })
+    }
+}
+
+/// Apply the first map whose `from` is a whole-component prefix of `path`.
+/// Returns `None` if no map applies.
+pub fn remap_path(maps: &[PathMap], path: &str) -> Option<String> {
+    maps.iter().find_map(|map| {
+        let rest = path.strip_prefix(map.from.trim_end_matches('/'))?;
+        (rest.is_empty() || rest.starts_with('/'))
+            .then(|| format!("{}{rest}", map.to.trim_end_matches('/')))
+    })
+}
+
+/// Metadata keys that hold host paths, shared by the built-in providers and
+/// the script provider protocol.
+pub(super) const METADATA_PATH_KEYS: &[&str] = &["project_path", "worktree_path"];
+
+#[derive(Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct RemapSummary {
+    pub projects: usize,
+    pub environments: usize,
+}
+
+/// Rewrite project paths and environment metadata paths in place, for a
+/// database copied from a machine with a different layout.
+pub fn remap_paths(maps: &[PathMap]) -> Result<RemapSummary, anyhow::Error> {
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+    let now = now_rfc3339();
+    let mut summary = RemapSummary::default();
+
+    let projects: Vec<(String, String)> = tx
+        .prepare("SELECT id, path FROM projects")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    let mut moved = Vec::new();
+    for (id, path) in &projects {
+        if let Some(path) = remap_path(maps, path) {
+            tx.execute(
+                "UPDATE projects SET path = ?1, updated_at = ?2 WHERE id = ?3",
+                rusqlite::params![path, &now, id],
+            )?;
+            moved.push((id, path));
+            summary.projects += 1;
+        }
+    }
+    // Moved projects get the same check as `project new`, except that ones
+    // already nested before the remap may stay nested.
+    let before: HashMap<&str, PathBuf> = projects
+        .iter()
+        .map(|(id, path)| (id.as_str(), canonical_path(Path::new(path))))
+        .collect();
+    for (id, path) in moved {
+        let was = &before[id.as_str()];
+        let nested = |other: &super::Project| {
+            before
+                .get(other.id.as_str())
+                .is_some_and(|other| PathOverlap::of(was, other).is_some())
+        };
+        if let Some((other, overlap)) =
+            conflicting_project(&tx, &canonical_path(Path::new(&path)), Some(id), nested)?
+        {
+            let (code, relation) = match overlap {
+                PathOverlap::Same => (ErrorCode::ProjectExists, "onto"),
+                PathOverlap::Inside => (ErrorCode::ProjectPathOverlaps, "inside"),
+                PathOverlap::Contains => (ErrorCode::ProjectPathOverlaps, "around"),
+            };
+            bail_code!(
+                code,
+                "remapping would move a project to {path}, {relation} project {}",
+                other.name
+            );
+        }
+    }
+
+    let environments: Vec<(String, String)> = tx
+        .prepare("SELECT id, metadata FROM environments")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    for (id, metadata_str) in environments {
+        let Ok(mut metadata) = serde_json::from_str::<serde_json::Value>(&metadata_str) else {
+            continue;
+        };
+        let mut changed = false;
+        for key in METADATA_PATH_KEYS {
+            if let Some(path) = metadata[key].as_str().and_then(|p| remap_path(maps, p)) {
+                metadata[key] = serde_json::Value::String(path);
+                changed = true;
+            }
+        }
+        if changed {
+            tx.execute(
+                "UPDATE environments SET metadata = ?1, updated_at = ?2 WHERE id = ?3",
+                rusqlite::params![serde_json::to_string(&metadata)?, &now, id],
+            )?;
+            summary.environments += 1;
+        }
+    }
+
+    tx.commit()?;
+    Ok(summary)
+}
+
+/// What to do when an imported project's name or path is already registered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportConflict {
+    /// Abort the import without changing anything.
+    #[default]
+    Fail,
+    /// Keep the existing project.
+    Skip,
+    /// Point the existing project at the imported path.
+    Replace,
+}
+
+#[derive(Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ImportSummary {
+    pub projects_created: usize,
+    pub projects_replaced: usize,
+    pub projects_skipped: usize,
+    pub tasks_imported: usize,
+    pub tasks_skipped: usize,
+}
+
+pub fn export_state(include_tasks: bool) -> Result<StateExport, anyhow::Error> {
+    let projects = super::list_projects()?;
+    let tasks = if include_tasks {
+        let names: HashMap<&str, &str> = projects
+            .iter()
+            .map(|p| (p.id.as_str(), p.name.as_str()))
+            .collect();
+        let mut tasks: Vec<ExportedTask> = super::list_tasks()?
+            .into_iter()
+            .filter(|task| task.is_terminal())
+            .filter_map(|task| {
+                Some(ExportedTask {
+                    project: names.get(task.project_id.as_str())?.to_string(),
+                    id: task.id,
+                    provider: task.provider,
+                    description: task.description,
+                    status: task.status,
+                    result_summary: task.result_summary,
+                    result_data: task.result_data,
+                    resource_usage: task.resource_usage,
+                    cost: task.cost,
+                    labels: task.labels,
+                    source_ref: task.source_ref,
+                    requirements: task.requirements,
+                    priority: task.priority,
+                    notes: task.notes,
+                    group_id: task.group_id,
+                    created_at: task.created_at,
+                    updated_at: task.updated_at,
+                })
+            })
+            .collect();
+        tasks.reverse();
+        tasks
+    } else {
+        Vec::new()
+    };
+    let groups = if tasks.is_empty() {
+        Vec::new()
+    } else {
+        let names: HashMap<&str, &str> = projects
+            .iter()
+            .map(|p| (p.id.as_str(), p.name.as_str()))
+            .collect();
+        let mut groups: Vec<ExportedGroup> = super::list_task_groups()?
+            .into_iter()
+            .filter(|group| {
+                tasks
+                    .iter()
+                    .any(|task| task.group_id.as_ref() == Some(&group.id))
+            })
+            .filter_map(|group| {
+                Some(ExportedGroup {
+                    project: names.get(group.project_id.as_str())?.to_string(),
+                    id: group.id,
+                    description: group.description,
+                    created_at: group.created_at,
+                    updated_at: group.updated_at,
+                })
+            })
+            .collect();
+        groups.reverse();
+        groups
+    };
+
+    Ok(StateExport {
+        version: STATE_FORMAT_VERSION,
+        exported_at: now_rfc3339(),
+        projects: projects
+            .into_iter()
+            .map(|p| ExportedProject {
+                name: p.name,
+                path: p.path,
+                created_at: p.created_at,
+            })
+            .collect(),
+        groups,
+        tasks,
+    })
+}
+
+/// Load an export in one transaction. Tasks are matched by ID, so importing
+/// the same file twice skips tasks that are already present.
+pub fn import_state(
+    state: &StateExport,
+    on_conflict: ImportConflict,
+) -> Result<ImportSummary, anyhow::Error> {
+    if state.version > STATE_FORMAT_VERSION {
+        bail_code!(
+            ErrorCode::UnsupportedExportVersion,
+            "unsupported export version {} (this daemon reads up to {STATE_FORMAT_VERSION})",
+            state.version
+        );
+    }
+
+    let mut conn = connect()?;
+    let tx = conn.transaction()?;
+    let mut summary = ImportSummary::default();
+
+    for project in &state.projects {
+        import_project_tx(&tx, project, &state.projects, on_conflict, &mut summary)?;
+    }
+    for group in &state.groups {
+        import_group_tx(&tx, group)?;
+    }
+    for task in &state.tasks {
+        if import_task_tx(&tx, task)? {
+            summary.tasks_imported += 1;
+        } else {
+            summary.tasks_skipped += 1;
+        }
+    }
+
+    tx.commit()?;
+    Ok(summary)
+}
+
+fn import_project_tx(
+    tx: &Transaction<'_>,
+    project: &ExportedProject,
+    exported: &[ExportedProject],
+    on_conflict: ImportConflict,
+    summary: &mut ImportSummary,
+) -> Result<(), anyhow::Error> {
+    let path = canonical_path(Path::new(&project.path));
+    let by_name: Option<(String, String)> = tx
+        .query_row(
+            "SELECT id, path FROM projects WHERE name = ?1",
+            rusqlite::params![project.name],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    if let Some((id, existing)) = &by_name {
+        if canonical_path(Path::new(existing)) == path {
+            summary.projects_skipped += 1;
+            return Ok(());
+        }
+        match on_conflict {
+            ImportConflict::Fail => bail_code!(
+                ErrorCode::ImportConflict,
+                "project {} already exists at {existing}; pass --on-conflict skip or replace",
+                project.name
+            ),
+            ImportConflict::Skip => {
+                summary.projects_skipped += 1;
+                return Ok(());
+            }
+            ImportConflict::Replace => {
+                if conflicts(tx, project, &path, Some(id), exported, on_conflict, summary)? {
+                    return Ok(());
+                }
+                tx.execute(
+                    "UPDATE projects SET path = ?1, updated_at = ?2 WHERE id = ?3",
+                    rusqlite::params![path.to_string_lossy(), now_rfc3339(), id],
+                )?;
+                summary.projects_replaced += 1;
+                return Ok(());
+            }
+        }
+    }
+
+    if let Some(owner) = alias_owner(tx, &project.name)? {
+        if on_conflict == ImportConflict::Skip {
+            summary.projects_skipped += 1;
+            return Ok(());
+        }
+        bail_code!(
+            ErrorCode::ImportConflict,
+            "project {} conflicts: it's already an alias of project {owner}",
+            project.name
+        );
+    }
+    if conflicts(tx, project, &path, None, exported, on_conflict, summary)? {
+        return Ok(());
+    }
+    tx.execute(
+        "INSERT INTO projects (id, name, path, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![
+            crate::id::new_id(),
+            project.name,
+            path.to_string_lossy(),
+            project.created_at,
+            now_rfc3339()
+        ],
+    )?;
+    summary.projects_created += 1;
+    Ok(())
+}
+
+/// Whether `project` is skipped because `path` is taken, the way `project
+/// new` checks it: a path can't be another project's, or inside or around
+/// one unless that one is nested with it in the export too. Fails instead
+/// unless `on_conflict` skips.
+fn conflicts(
+    tx: &Transaction<'_>,
+    project: &ExportedProject,
+    path: &Path,
+    except: Option<&str>,
+    exported: &[ExportedProject],
+    on_conflict: ImportConflict,
+    summary: &mut ImportSummary,
+) -> Result<bool, anyhow::Error> {
+    let in_export = |other: &super::Project| exported.iter().any(|p| p.name == other.name);
+    let Some((other, overlap)) = conflicting_project(tx, path, except, in_export)? else {
+        return Ok(false);
+    };
+    if on_conflict == ImportConflict::Skip {
+        summary.projects_skipped += 1;
+        return Ok(true);
+    }
+    let relation = match overlap {
+        PathOverlap::Same => "is already registered as",
+        PathOverlap::Inside => "is inside",
+        PathOverlap::Contains => "contains",
+    };
+    bail_code!(
+        ErrorCode::ImportConflict,
+        "project {} conflicts: {} {relation} project {}",
+        project.name,
+        project.path,
+        other.name
+    );
+}
+
+/// Insert a group unless it already exists or its project is not
+/// registered.
+fn import_group_tx(tx: &Transaction<'_>, group: &ExportedGroup) -> Result<(), anyhow::Error> {
+    tx.execute(
+        "INSERT OR IGNORE INTO task_groups (id, project_id, description, created_at, updated_at) SELECT ?1, id, ?3, ?4, ?5 FROM projects WHERE name = ?2",
+        rusqlite::params![
+            group.id,
+            group.project,
+            group.description,
+            group.created_at,
+            group.updated_at
+        ],
+    )?;
+    Ok(())
+}
+
+/// Insert a finished task with a placeholder environment. Returns false if
+/// the task already exists or its project is not registered.
+fn import_task_tx(tx: &Transaction<'_>, task: &ExportedTask) -> Result<bool, anyhow::Error> {
+    let exists = tx
+        .query_row(
+            "SELECT 1 FROM tasks WHERE id = ?1",
+            rusqlite::params![task.id],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some();
+    if exists {
+        return Ok(false);
+    }
+    let Some(project_id) = tx
+        .query_row(
+            "SELECT id FROM projects WHERE name = ?1",
+            rusqlite::params![task.project],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+    else {
+        return Ok(false);
+    };
+
+    // Hand-edited files may carry a task that never finished; it cannot
+    // resume here.
+    let status = if TERMINAL_TASK_STATUSES.contains(&task.status.as_str()) {
+        task.status.as_str()
+    } else {
+        "interrupted"
+    };
+
+    let env_id = crate::id::new_id();
+    tx.execute(
+        "INSERT INTO environments (id, project_id, provider, status, metadata, created_at, updated_at) VALUES (?1, ?2, ?3, 'failed', '{}', ?4, ?5)",
+        rusqlite::params![
+            env_id,
+            project_id,
+            IMPORTED_ENVIRONMENT_PROVIDER,
+            task.created_at,
+            task.updated_at
+        ],
+    )?;
+    tx.execute(
+        "INSERT INTO tasks (id, environment_id, project_id, provider, description, status, created_at, updated_at, result_summary, result_data, resource_usage, labels, source_ref, requirements, priority, notes, tokens_in, tokens_out, cost_usd, group_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, (SELECT id FROM task_groups WHERE id = ?20 AND project_id = ?3))",
+        rusqlite::params![
+            task.id,
+            env_id,
+            project_id,
+            task.provider,
+            task.description,
+            status,
+            task.created_at,
+            task.updated_at,
+            task.result_summary,
+            task.result_data
+                .as_ref()
+                .map(serde_json::to_string)
+                .transpose()?,
+            task.resource_usage
+                .as_ref()
+                .map(serde_json::to_string)
+                .transpose()?,
+            serde_json::to_string(&normalize_labels(task.labels.iter().cloned()))?,
+            task.source_ref,
+            serde_json::to_string(&task.requirements)?,
+            task.priority.as_str(),
+            serde_json::to_string(&task.notes)?,
+            task.cost.and_then(|c| c.tokens_in),
+            task.cost.and_then(|c| c.tokens_out),
+            task.cost.and_then(|c| c.cost_usd),
+            task.group_id,
+        ],
+    )?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export(paths: &[&str]) -> StateExport {
+        StateExport {
+            version: STATE_FORMAT_VERSION,
+            exported_at: String::new(),
+            projects: paths
+                .iter()
+                .map(|path| ExportedProject {
+                    name: path.rsplit('/').next().unwrap().to_string(),
+                    path: path.to_string(),
+                    created_at: String::new(),
+                })
+                .collect(),
+            groups: Vec::new(),
+            tasks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parses_path_maps() {
+        let map: PathMap = "/home/ann/=/Users/ann".parse().unwrap();
+        assert_eq!(map.from, "/home/ann");
+        assert_eq!(map.to, "/Users/ann");
+        assert!("/home/ann".parse::<PathMap>().is_err());
+        assert!("home=/Users/ann".parse::<PathMap>().is_err());
+    }
+
+    #[test]
+    fn remap_paths_replaces_whole_components() {
+        let mut state = export(&["/home/ann/src/app", "/home/ann/srcs/lib", "/opt/tool"]);
+        let maps = ["/home/ann/src/=/Users/ann/code".parse().unwrap()];
+        assert_eq!(state.remap_paths(&maps), 1);
+        let paths: Vec<&str> = state.projects.iter().map(|p| p.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            ["/Users/ann/code/app", "/home/ann/srcs/lib", "/opt/tool"]
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use std::path::Path;
+
+use work::client::DaemonClient;
+use work::daemon::Registration;
+use work::{config, daemon, db, paths};
+
+/// How long to wait for the daemon to answer before calling it unreachable.
+const DAEMON_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    /// Worth knowing about, but nothing is broken.
+    Warn,
+    Fail,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pass => "pass",
+            Self::Warn => "warn",
+            Self::Fail => "fail",
+        }
+    }
+}
+
+/// The outcome of one check, with what to do about it when it didn't pass.
+#[derive(Debug, serde::Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Pass,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Warn,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &'static str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Fail,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// Run every check. None of them stops the others, since whatever is broken
+/// may be what a later check needs.
+pub async fn run() -> Vec<Check> {
+    let mut checks = vec![check_daemon().await, check_database()];
+    checks.push(check_git());
+    checks.extend(check_config());
+    checks.extend(check_directories());
+    // `work daemon install` only registers with launchd, so elsewhere
+    // there's nothing to check.
+    if cfg!(target_os = "macos") {
+        checks.push(check_registration());
+    }
+    checks
+}
+
+async fn check_daemon() -> Check {
+    const NAME: &str = "daemon";
+    let client = match DaemonClient::new() {
+        Ok(client) => client,
+        Err(e) => return Check::fail(NAME, e.to_string(), "set WORK_HOME or XDG_RUNTIME_DIR"),
+    };
+    match tokio::time::timeout(DAEMON_TIMEOUT, client.daemon_version()).await {
+        Ok(Ok(Some(version))) if version == env!("CARGO_PKG_VERSION") => {
+            Check::pass(NAME, format!("running work {version}"))
+        }
+        Ok(Ok(version)) => Check::warn(
+            NAME,
+            format!(
+                "running work {}, but this is work {}",
+                version
+                    .filter(|v| !v.is_empty())
+                    .as_deref()
+                    .unwrap_or("(unknown)"),
+                env!("CARGO_PKG_VERSION")
+            ),
+            format!("to restart it, {}", daemon::restart_hint()),
+        ),
+        Ok(Err(e)) => Check::fail(
+            NAME,
+            // The client's error carries its own hint on later lines.
+            format!(
+                "not reachable: {}",
+                e.to_string().lines().next().unwrap_or_default()
+            ),
+            "start it with `work daemon start`, or `work daemon install` to keep it running",
+        ),
+        Err(_) => Check::fail(
+            NAME,
+            format!("did not answer within {}s", DAEMON_TIMEOUT.as_secs()),
+            format!("it may be stuck; to restart it, {}", daemon::restart_hint()),
+        ),
+    }
+}
+
+fn check_database() -> Check {
+    const NAME: &str = "database";
+    let path = match db::db_path() {
+        Ok(path) => path,
+        Err(e) => return Check::fail(NAME, e.to_string(), "set WORK_HOME or XDG_DATA_HOME"),
+    };
+    if !path.exists() {
+        return Check::warn(
+            NAME,
+            format!("{} does not exist yet", path.display()),
+            "the daemon creates it when it first starts",
+        );
+    }
+    match db::sqlite_integrity_check() {
+        Ok(problems) if problems.is_empty() => {
+            Check::pass(NAME, format!("{} passed integrity_check", path.display()))
+        }
+        Ok(problems) => Check::fail(
+            NAME,
+            format!(
+                "{} is damaged: {}",
+                path.display(),
+                problems.into_iter().take(3).collect::<Vec<_>>().join("; ")
+            ),
+            "stop the daemon, copy the file aside, then restore a backup or run `work reset-database`",
+        ),
+        Err(e) => Check::fail(
+            NAME,
+            e.to_string(),
+            format!("check the permissions on {}", path.display()),
+        ),
+    }
+}
+
+fn check_git() -> Check {
+    const NAME: &str = "git";
+    match std::process::Command::new("git").arg("--version").output() {
+        Ok(output) if output.status.success() => Check::pass(
+            NAME,
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        ),
+        Ok(output) => Check::fail(
+            NAME,
+            format!("git --version failed ({})", output.status),
+            "reinstall git",
+        ),
+        Err(e) => Check::fail(
+            NAME,
+            format!("could not run git: {e}"),
+            "install git and make sure it's on the daemon's PATH",
+        ),
+    }
+}
+
+/// Whether the config parses and its settings are usable, and whether each
+/// script provider can be run.
+fn check_config() -> Vec<Check> {
+    const NAME: &str = "config";
+    let path = match config::path() {
+        Ok(path) if !path.exists() => {
+            return vec![Check::pass(
+                NAME,
+                format!("no {}; using defaults", path.display()),
+            )];
+        }
+        Ok(path) => path.display().to_string(),
+        Err(e) => return vec![Check::fail(NAME, e.to_string(), "set WORK_HOME or HOME")],
+    };
+    let config = match config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            return vec![Check::fail(
+                NAME,
+                format!("{path}: {e}"),
+                "fix it with `work config edit`",
+            )];
+        }
+    };
+
+    // Script problems get their own checks, with a hint for each.
+    let mut scripts = Vec::new();
+    for (name, script) in config.script_providers() {
+        scripts.push(match config::executable_problem(script) {
+            None => Check::pass("provider", format!("{name}: {}", script.display())),
+            Some(problem) => Check::fail(
+                "provider",
+                format!("{name}: {problem}"),
+                if script.exists() {
+                    format!("chmod +x {}", script.display())
+                } else {
+                    "fix its path with `work config edit`".to_string()
+                },
+            ),
+        });
+    }
+    let problems: Vec<String> = config
+        .problems()
+        .into_iter()
+        .filter(|problem| {
+            !scripts
+                .iter()
+                .any(|s| problem == &format!("environment provider {}", s.message))
+        })
+        .collect();
+
+    let mut checks = vec![if problems.is_empty() {
+        Check::pass(NAME, format!("{path} is valid"))
+    } else {
+        Check::fail(
+            NAME,
+            problems.join("; "),
+            "fix it with `work config edit`, then `work daemon reload`",
+        )
+    }];
+    checks.extend(scripts);
+    checks
+}
+
+/// Whether each directory work writes to can be written.
+fn check_directories() -> Vec<Check> {
+    const NAME: &str = "directory";
+    let dirs = [
+        ("config", paths::config_dir()),
+        ("data", paths::data_dir()),
+        ("state", paths::state_dir()),
+        ("runtime", paths::runtime_dir()),
+    ];
+    dirs.into_iter()
+        .map(|(kind, dir)| match dir {
+            Ok(dir) => match writable(&dir) {
+                Ok(()) => Check::pass(NAME, format!("{kind}: {}", dir.display())),
+                Err(e) => Check::fail(
+                    NAME,
+                    format!("{kind}: {} is not writable: {e}", dir.display()),
+                    format!(
+                        "fix the permissions on {}, or point WORK_HOME somewhere writable",
+                        dir.display()
+                    ),
+                ),
+            },
+            Err(e) => Check::fail(NAME, format!("{kind}: {e}"), "set WORK_HOME"),
+        })
+        .collect()
+}
+
+fn writable(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let probe = dir.join(format!(".work-doctor-{}", std::process::id()));
+    std::fs::write(&probe, b"")?;
+    std::fs::remove_file(&probe)
+}
+
+fn check_registration() -> Check {
+    const NAME: &str = "service";
+    match daemon::registration() {
+        Ok(Registration::Loaded { plist }) => Check::pass(
+            NAME,
+            format!("launchd agent loaded from {}", plist.display()),
+        ),
+        Ok(Registration::NotLoaded { plist }) => Check::fail(
+            NAME,
+            format!("{} exists but launchd hasn't loaded it", plist.display()),
+            "run `work daemon uninstall` and then `work daemon install`",
+        ),
+        Ok(Registration::None) => Check::warn(
+            NAME,
+            "the daemon doesn't start on login",
+            "run `work daemon install` to keep it running",
+        ),
+        Err(e) => Check::warn(NAME, e.to_string(), "set HOME"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writable_leaves_nothing_behind() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let nested = dir.path().join("a/b");
+        writable(&nested).unwrap();
+        assert_eq!(std::fs::read_dir(&nested).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,47 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use work::config::{Config, EditorCommand};
+
+/// An editor that exits this quickly without touching the file most likely
+/// handed it to a background window instead of waiting for it to close.
+const DETACHED_WITHIN: Duration = Duration::from_secs(1);
+
+/// Open `path` in the configured editor and wait for it to exit.
+pub fn open(config: &Config, path: &Path) -> anyhow::Result<()> {
+    let editor = config.editor()?;
+    run(&editor, path)
+}
+
+/// Open `path` in the configured editor and return its contents afterwards.
+///
+/// Fails if the editor returned immediately without changing the file, since
+/// reading it back then would only see the original contents.
+pub fn edit(config: &Config, path: &Path) -> anyhow::Result<String> {
+    let editor = config.editor()?;
+    let before = std::fs::read(path).unwrap_or_default();
+    let started = Instant::now();
+    run(&editor, path)?;
+    let after = std::fs::read(path)?;
+
+    if after == before && started.elapsed() < DETACHED_WITHIN {
+        anyhow::bail!(
+            "{} returned immediately without saving; if it opens in the background, \
+             set [editor] args (for example [\"--wait\"]) in config",
+            editor.program
+        );
+    }
+    Ok(String::from_utf8_lossy(&after).into_owned())
+}
+
+fn run(editor: &EditorCommand, path: &Path) -> anyhow::Result<()> {
+    let status = std::process::Command::new(&editor.program)
+        .args(&editor.args)
+        .arg(path)
+        .status()
+        .map_err(|e| anyhow::anyhow!("failed to start {}: {e}", editor.program))?;
+    if !status.success() {
+        anyhow::bail!("{} exited with {status}", editor.program);
+    }
+    Ok(())
+}
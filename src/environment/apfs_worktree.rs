@@ -3,13 +3,18 @@ use std::process::Command;
 
 use serde_json::json;
 
+use crate::config::{PrepareOptions, ProcessEnv};
 use crate::db::Project;
 
-use super::{EnvironmentProvider, ProviderExecCommand, RunSpec};
+use super::{CacheHandle, EnvironmentPlan, EnvironmentProvider, ProviderExecCommand, RunSpec};
 
 const BASE_BRANCH: &str = "main";
 
-pub struct ApfsWorktreeProvider;
+#[derive(Default)]
+pub struct ApfsWorktreeProvider {
+    /// Variables for the `git` commands it runs.
+    pub env: ProcessEnv,
+}
 
 impl ApfsWorktreeProvider {
     fn metadata_string<'a>(
@@ -37,8 +42,11 @@ impl EnvironmentProvider for ApfsWorktreeProvider {
         &self,
         project: &Project,
         env_id: &str,
+        options: &PrepareOptions,
+        cache: &CacheHandle,
         _log_path: Option<&Path>,
     ) -> anyhow::Result<serde_json::Value> {
+        let base_branch = options.base_branch.as_deref().unwrap_or(BASE_BRANCH);
         let project_path = PathBuf::from(&project.path);
         let worktrees_dir = crate::paths::data_dir()?.join("worktrees");
         let worktree_path = worktrees_dir.join(env_id);
@@ -46,15 +54,24 @@ impl EnvironmentProvider for ApfsWorktreeProvider {
 
         std::fs::create_dir_all(&worktrees_dir)?;
 
-        Self::run_command(
-            Command::new("git")
-                .args(["fetch", "origin", BASE_BRANCH])
-                .current_dir(&project_path),
-            "git fetch failed",
-        )?;
+        let start_point = match options.source_ref.as_deref() {
+            Some(source_ref) => super::resolve_git_ref(&project_path, source_ref, &self.env)?,
+            None => {
+                Self::run_command(
+                    self.env
+                        .command("git")
+                        .args(["fetch", "origin", base_branch])
+                        .current_dir(&project_path),
+                    "git fetch failed",
+                )?;
+                format!("origin/{base_branch}")
+            }
+        };
 
-        let branch_output = Command::new("git")
-            .args(["branch", &branch, &format!("origin/{BASE_BRANCH}")])
+        let branch_output = self
+            .env
+            .command("git")
+            .args(["branch", &branch, &start_point])
             .current_dir(&project_path)
             .output()?;
         if !branch_output.status.success() {
@@ -65,7 +82,8 @@ impl EnvironmentProvider for ApfsWorktreeProvider {
         }
 
         Self::run_command(
-            Command::new("git")
+            self.env
+                .command("git")
                 .args([
                     "worktree",
                     "add",
@@ -87,7 +105,8 @@ impl EnvironmentProvider for ApfsWorktreeProvider {
             }
 
             Self::run_command(
-                Command::new("cp")
+                self.env
+                    .command("cp")
                     .arg("-cR")
                     .arg(entry.path())
                     .arg(&worktree_path),
@@ -96,23 +115,85 @@ impl EnvironmentProvider for ApfsWorktreeProvider {
         }
 
         Self::run_command(
-            Command::new("git")
+            self.env
+                .command("git")
                 .args(["reset", "--hard", &branch])
                 .current_dir(&worktree_path),
             "git reset --hard failed",
         )?;
+        cache.fill_cached_paths(&worktree_path, &options.cached_paths, &self.env)?;
 
-        Ok(json!({
+        let mut metadata = json!({
             "project_path": project.path,
             "worktree_path": worktree_path.to_string_lossy(),
             "branch": branch,
-            "base_branch": BASE_BRANCH,
-        }))
+            "base_branch": base_branch,
+        });
+        if let Some(source_ref) = &options.source_ref {
+            metadata["source_ref"] = json!(source_ref);
+            metadata["source_commit"] = json!(start_point);
+            metadata["display_name"] = json!(source_ref);
+        }
+        Ok(metadata)
+    }
+
+    fn plan(
+        &self,
+        project: &Project,
+        env_id: &str,
+        options: &PrepareOptions,
+    ) -> anyhow::Result<EnvironmentPlan> {
+        let base_branch = options.base_branch.as_deref().unwrap_or(BASE_BRANCH);
+        let project_path = PathBuf::from(&project.path);
+        let worktree_path = crate::paths::data_dir()?.join("worktrees").join(env_id);
+        let branch = format!("work-env-{env_id}");
+        let mut steps = Vec::new();
+
+        // Without fetching, the size is estimated from whatever copy of the
+        // start point is already local.
+        let (start_point, local_rev) = match options.source_ref.as_deref() {
+            Some(source_ref) => {
+                match super::resolve_local_git_ref(&project_path, source_ref, &self.env) {
+                    Some(commit) => (commit.clone(), Some(commit)),
+                    None => {
+                        steps.push(format!("git fetch origin {source_ref}"));
+                        (source_ref.to_string(), None)
+                    }
+                }
+            }
+            None => {
+                steps.push(format!("git fetch origin {base_branch}"));
+                let start_point = format!("origin/{base_branch}");
+                let local_rev = super::resolve_local_git_ref(&project_path, base_branch, &self.env);
+                (start_point, local_rev)
+            }
+        };
+
+        steps.push(format!("git branch {branch} {start_point}"));
+        steps.push(format!(
+            "git worktree add --no-checkout {} {branch}",
+            worktree_path.display()
+        ));
+        steps.push(format!(
+            "cp -cR each top-level entry of {} except .git and .worktrees",
+            project.path
+        ));
+        steps.push(format!("git reset --hard {branch}"));
+        steps.extend(super::cache::cached_path_steps(&options.cached_paths));
+
+        Ok(EnvironmentPlan {
+            steps,
+            paths: vec![worktree_path.to_string_lossy().into_owned()],
+            branch: Some(branch),
+            estimated_disk_bytes: local_rev
+                .and_then(|rev| super::git_tree_size(&project_path, &rev, &[], &self.env).ok()),
+        })
     }
 
     fn update(
         &self,
         metadata: &serde_json::Value,
+        _options: &PrepareOptions,
         _log_path: Option<&Path>,
     ) -> anyhow::Result<serde_json::Value> {
         let project_path = Self::metadata_string(metadata, "project_path")?;
@@ -120,14 +201,16 @@ impl EnvironmentProvider for ApfsWorktreeProvider {
         let base_branch = metadata["base_branch"].as_str().unwrap_or(BASE_BRANCH);
 
         Self::run_command(
-            Command::new("git")
+            self.env
+                .command("git")
                 .args(["fetch", "origin", base_branch])
                 .current_dir(project_path),
             "git fetch failed",
         )?;
 
         Self::run_command(
-            Command::new("git")
+            self.env
+                .command("git")
                 .args(["reset", "--hard", &format!("origin/{base_branch}")])
                 .current_dir(worktree_path),
             "git reset --hard origin failed",
@@ -144,11 +227,43 @@ impl EnvironmentProvider for ApfsWorktreeProvider {
         Ok(metadata.clone())
     }
 
+    fn claim_conflict(&self, metadata: &serde_json::Value) -> anyhow::Result<Option<String>> {
+        super::worktree_conflict(metadata, &self.env)
+    }
+
+    fn snapshot(
+        &self,
+        metadata: &serde_json::Value,
+        dest: &Path,
+    ) -> anyhow::Result<serde_json::Value> {
+        let worktree_path = Self::metadata_string(metadata, "worktree_path")?;
+        super::snapshot::take(
+            Path::new(worktree_path),
+            dest,
+            super::snapshot::Method::Clone,
+            &self.env,
+        )
+    }
+
+    fn restore(
+        &self,
+        metadata: &serde_json::Value,
+        snapshot: &serde_json::Value,
+    ) -> anyhow::Result<()> {
+        let worktree_path = Self::metadata_string(metadata, "worktree_path")?;
+        super::snapshot::restore(Path::new(worktree_path), snapshot, &self.env)
+    }
+
+    fn adopt(&self, project: &Project, path: &Path) -> anyhow::Result<serde_json::Value> {
+        super::adopt_git_checkout(project, path, &self.env)
+    }
+
     fn run(
         &self,
         metadata: &serde_json::Value,
         command: &str,
         args: &[String],
+        stdin: Option<&str>,
     ) -> anyhow::Result<RunSpec> {
         let worktree_path = Self::metadata_string(metadata, "worktree_path")?;
 
@@ -156,7 +271,7 @@ impl EnvironmentProvider for ApfsWorktreeProvider {
             program: command.to_string(),
             args: args.to_vec(),
             cwd: Some(PathBuf::from(worktree_path)),
-            stdin_data: None,
+            stdin_data: stdin.map(|s| s.as_bytes().to_vec()),
             env: Vec::new(),
         })
     }
@@ -204,7 +319,9 @@ impl EnvironmentProvider for ApfsWorktreeProvider {
         let worktree_path = Self::metadata_string(metadata, "worktree_path")?;
         let branch = Self::metadata_string(metadata, "branch")?;
 
-        let worktree_output = Command::new("git")
+        let worktree_output = self
+            .env
+            .command("git")
             .args(["worktree", "remove", "--force", worktree_path])
             .current_dir(project_path)
             .output()?;
@@ -215,7 +332,9 @@ impl EnvironmentProvider for ApfsWorktreeProvider {
             }
         }
 
-        let branch_output = Command::new("git")
+        let branch_output = self
+            .env
+            .command("git")
             .args(["branch", "-D", branch])
             .current_dir(project_path)
             .output()?;
@@ -236,7 +355,7 @@ mod tests {
 
     #[test]
     fn exec_commands_include_cd() {
-        let provider = ApfsWorktreeProvider;
+        let provider = ApfsWorktreeProvider::default();
         let commands = provider.exec_commands(&json!({})).unwrap();
 
         assert_eq!(commands.len(), 1);
@@ -245,7 +364,7 @@ mod tests {
 
     #[test]
     fn exec_cd_runs_shell_in_worktree() {
-        let provider = ApfsWorktreeProvider;
+        let provider = ApfsWorktreeProvider::default();
         let metadata = json!({ "worktree_path": "/tmp/worktree" });
 
         let run_spec = provider.exec(&metadata, "cd", &[]).unwrap();
@@ -260,7 +379,7 @@ mod tests {
 
     #[test]
     fn exec_non_cd_passthrough() {
-        let provider = ApfsWorktreeProvider;
+        let provider = ApfsWorktreeProvider::default();
         let metadata = json!({ "worktree_path": "/tmp/worktree" });
         let args = vec!["-la".to_string()];
 
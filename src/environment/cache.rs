@@ -0,0 +1,414 @@
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::config::{CachedPath, ProcessEnv};
+
+/// Partial entries older than this were left by a crashed `insert`.
+const STALE_TMP_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+const TMP_PREFIX: &str = ".tmp-";
+
+/// A content-addressed directory that providers share across `prepare`
+/// calls, for things like cloned dependencies or base images.
+///
+/// Each entry is a directory named by its key. Entries are never modified
+/// once inserted, and the daemon evicts the least recently used ones when the
+/// cache grows past `[environments] cache-max-gb`. A directory's mtime is its
+/// last use, so anything that reads an entry directly should `touch` it.
+#[derive(Clone, Debug)]
+pub struct CacheHandle {
+    root: PathBuf,
+}
+
+/// What an `evict` pass removed.
+#[derive(Debug, Default, PartialEq)]
+pub struct Eviction {
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+impl CacheHandle {
+    pub fn new(root: PathBuf) -> CacheHandle {
+        CacheHandle { root }
+    }
+
+    /// The cache in the `work` data directory.
+    pub fn open() -> anyhow::Result<CacheHandle> {
+        let root = crate::paths::cache_dir()?;
+        std::fs::create_dir_all(&root)?;
+        Ok(CacheHandle::new(root))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The entry for `key`, marked as just used, if it exists.
+    pub fn get(&self, key: &str) -> anyhow::Result<Option<PathBuf>> {
+        let path = self.entry_path(key)?;
+        if !path.is_dir() {
+            return Ok(None);
+        }
+        touch(&path)?;
+        Ok(Some(path))
+    }
+
+    /// The entry for `key`, created by `fill` if it doesn't exist yet.
+    ///
+    /// `fill` writes into an empty scratch directory that becomes the entry
+    /// only if it succeeds, so a failed or concurrent fill never leaves a
+    /// partial entry behind.
+    pub fn get_or_insert(
+        &self,
+        key: &str,
+        fill: impl FnOnce(&Path) -> anyhow::Result<()>,
+    ) -> anyhow::Result<PathBuf> {
+        if let Some(path) = self.get(key)? {
+            return Ok(path);
+        }
+        let path = self.entry_path(key)?;
+        std::fs::create_dir_all(&self.root)?;
+        let tmp = self
+            .root
+            .join(format!("{TMP_PREFIX}{key}-{}", crate::id::new_id()));
+        std::fs::create_dir(&tmp)?;
+        if let Err(e) = fill(&tmp) {
+            let _ = std::fs::remove_dir_all(&tmp);
+            return Err(e);
+        }
+        if let Err(e) = std::fs::rename(&tmp, &path) {
+            let _ = std::fs::remove_dir_all(&tmp);
+            // Another prepare filled the same key first.
+            if !path.is_dir() {
+                return Err(e.into());
+            }
+        }
+        touch(&path)?;
+        Ok(path)
+    }
+
+    /// Put each of `paths` into the checkout at `dir`: copied from the cache
+    /// while its key files are unchanged, otherwise built by its command and
+    /// saved for the next environment.
+    pub fn fill_cached_paths(
+        &self,
+        dir: &Path,
+        paths: &[CachedPath],
+        env: &ProcessEnv,
+    ) -> anyhow::Result<()> {
+        for cached in paths {
+            if !Path::new(&cached.path)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)))
+            {
+                anyhow::bail!("cached path {:?} must be inside the checkout", cached.path);
+            }
+            let target = dir.join(&cached.path);
+            let mut contents = vec![
+                cached.path.clone().into_bytes(),
+                cached.command.clone().into_bytes(),
+            ];
+            for file in &cached.key_files {
+                contents.push(
+                    std::fs::read(dir.join(file))
+                        .map_err(|e| anyhow::anyhow!("failed to read {file}: {e}"))?,
+                );
+            }
+            let parts: Vec<&[u8]> = contents.iter().map(Vec::as_slice).collect();
+
+            let mut built = false;
+            let entry = self.get_or_insert(&cache_key(&parts), |tmp| {
+                super::snapshot::run(
+                    env.command("sh")
+                        .arg("-c")
+                        .arg(&cached.command)
+                        .current_dir(dir),
+                    &format!("command for cached path {}", cached.path),
+                )?;
+                if !target.is_dir() {
+                    anyhow::bail!("command for cached path {} did not create it", cached.path);
+                }
+                built = true;
+                copy_dir(&target, &tmp.join("files"), env)
+            })?;
+            if !built {
+                copy_dir(&entry.join("files"), &target, env)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove least recently used entries until the cache holds at most
+    /// `max_bytes`, along with scratch directories abandoned by a crash.
+    pub fn evict(&self, max_bytes: u64) -> anyhow::Result<Eviction> {
+        let mut eviction = Eviction::default();
+        let read_dir = match std::fs::read_dir(&self.root) {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(eviction),
+            Err(e) => return Err(e.into()),
+        };
+
+        let now = SystemTime::now();
+        let mut entries = Vec::new();
+        let mut total = 0;
+        for dirent in read_dir {
+            let dirent = dirent?;
+            let path = dirent.path();
+            let found = dirent
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .map_err(anyhow::Error::from)
+                .and_then(|used| Ok((used, disk_size(&path)?)));
+            let (used, size) = match found {
+                Ok(found) => found,
+                // Removed since it was listed, by a fill that lost a race or
+                // another eviction.
+                Err(e) if is_not_found(&e) => continue,
+                Err(e) => return Err(e),
+            };
+            if dirent.file_name().to_string_lossy().starts_with(TMP_PREFIX) {
+                if now.duration_since(used).unwrap_or_default() > STALE_TMP_AGE {
+                    remove(&path)?;
+                    eviction.entries += 1;
+                    eviction.bytes += size;
+                } else {
+                    // Still being filled; counts toward the cap but isn't ours
+                    // to remove.
+                    total += size;
+                }
+                continue;
+            }
+            total += size;
+            entries.push((used, path, size));
+        }
+
+        entries.sort_by_key(|(used, _, _)| *used);
+        for (_, path, size) in entries {
+            if total <= max_bytes {
+                break;
+            }
+            remove(&path)?;
+            total -= size;
+            eviction.entries += 1;
+            eviction.bytes += size;
+        }
+        Ok(eviction)
+    }
+
+    fn entry_path(&self, key: &str) -> anyhow::Result<PathBuf> {
+        let valid = !key.is_empty()
+            && !key.starts_with('.')
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            anyhow::bail!("invalid cache key {key:?}: use letters, digits, '-', '_', and '.'");
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+/// A stable key for `parts`, such as a lockfile's contents and a tool
+/// version. Equal inputs give equal keys across runs and machines.
+pub fn cache_key(parts: &[&[u8]]) -> String {
+    // 128-bit FNV-1a. Parts are length-prefixed so ["ab", "c"] and
+    // ["a", "bc"] differ.
+    const OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+    let mut hash = OFFSET;
+    for part in parts {
+        for byte in (part.len() as u64).to_le_bytes().iter().chain(*part) {
+            hash ^= u128::from(*byte);
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+    format!("{hash:032x}")
+}
+
+/// A cache key for the contents of `paths`, e.g. a project's lockfiles.
+pub fn cache_key_for_files(paths: &[&Path]) -> anyhow::Result<String> {
+    let contents = paths
+        .iter()
+        .map(|path| {
+            std::fs::read(path)
+                .map_err(|e| anyhow::anyhow!("failed to read {}: {e}", path.display()))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let parts: Vec<&[u8]> = contents.iter().map(Vec::as_slice).collect();
+    Ok(cache_key(&parts))
+}
+
+/// What `fill_cached_paths` does, for `plan`.
+pub fn cached_path_steps(paths: &[CachedPath]) -> impl Iterator<Item = String> + '_ {
+    paths.iter().map(|cached| {
+        format!(
+            "copy {} from the provider cache, or create it with: {}",
+            cached.path, cached.command
+        )
+    })
+}
+
+/// Copy the contents of `src` into `dest`, creating it if needed.
+fn copy_dir(src: &Path, dest: &Path, env: &ProcessEnv) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dest)?;
+    super::snapshot::run(
+        env.command("cp").arg("-R").arg(src.join(".")).arg(dest),
+        "cache copy",
+    )
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+}
+
+fn touch(path: &Path) -> anyhow::Result<()> {
+    std::fs::File::open(path)?.set_modified(SystemTime::now())?;
+    Ok(())
+}
+
+fn remove(path: &Path) -> anyhow::Result<()> {
+    tracing::debug!(path = %path.display(), "evicting cache entry");
+    match std::fs::remove_dir_all(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Total size of the files under `path`, not following symlinks.
+pub(super) fn disk_size(path: &Path) -> anyhow::Result<u64> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for dirent in std::fs::read_dir(path)? {
+        size += disk_size(&dirent?.path())?;
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_used(path: &Path, secs_ago: u64) {
+        std::fs::File::open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(secs_ago))
+            .unwrap();
+    }
+
+    #[test]
+    fn keys_are_stable_and_length_prefixed() {
+        assert_eq!(cache_key(&[b"abc"]), cache_key(&[b"abc"]));
+        assert_ne!(cache_key(&[b"ab", b"c"]), cache_key(&[b"a", b"bc"]));
+        assert_eq!(cache_key(&[]).len(), 32);
+    }
+
+    #[test]
+    fn get_or_insert_fills_once_and_discards_failed_fills() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let cache = CacheHandle::new(dir.path().join("cache"));
+
+        let err = cache
+            .get_or_insert("deps", |tmp| {
+                std::fs::write(tmp.join("partial"), "x")?;
+                anyhow::bail!("clone failed")
+            })
+            .unwrap_err();
+        assert_eq!(err.to_string(), "clone failed");
+        assert_eq!(cache.get("deps").unwrap(), None);
+        assert_eq!(std::fs::read_dir(cache.root()).unwrap().count(), 0);
+
+        let path = cache
+            .get_or_insert("deps", |tmp| Ok(std::fs::write(tmp.join("lock"), "1")?))
+            .unwrap();
+        let again = cache
+            .get_or_insert("deps", |_| panic!("filled twice"))
+            .unwrap();
+        assert_eq!(path, again);
+        assert_eq!(std::fs::read_to_string(path.join("lock")).unwrap(), "1");
+
+        assert!(cache.get("../escape").is_err());
+        assert!(cache.get(".tmp-deps").is_err());
+    }
+
+    #[test]
+    fn evict_removes_least_recently_used_entries_over_the_cap() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let cache = CacheHandle::new(dir.path().to_path_buf());
+        for (key, secs_ago) in [("old", 300), ("mid", 200), ("new", 100)] {
+            let path = cache
+                .get_or_insert(key, |tmp| Ok(std::fs::write(tmp.join("data"), [0; 10])?))
+                .unwrap();
+            set_used(&path, secs_ago);
+        }
+        // Using "old" makes "mid" the least recently used.
+        cache.get("old").unwrap();
+        let abandoned = dir.path().join(".tmp-crashed-1");
+        std::fs::create_dir(&abandoned).unwrap();
+        std::fs::write(abandoned.join("data"), [0; 5]).unwrap();
+        set_used(&abandoned, 2 * 24 * 60 * 60);
+
+        let eviction = cache.evict(20).unwrap();
+        assert_eq!(
+            eviction,
+            Eviction {
+                entries: 2,
+                bytes: 15
+            }
+        );
+        assert!(cache.get("mid").unwrap().is_none());
+        assert!(cache.get("old").unwrap().is_some());
+        assert!(cache.get("new").unwrap().is_some());
+        assert!(!abandoned.exists());
+
+        assert_eq!(cache.evict(20).unwrap(), Eviction::default());
+    }
+
+    #[test]
+    fn cached_paths_are_built_once_per_key() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let cache = CacheHandle::new(dir.path().join("cache"));
+        let paths = [CachedPath {
+            path: "deps".to_string(),
+            key_files: vec!["lock".to_string()],
+            command: "mkdir deps && echo built > deps/lib && echo run >> ../runs".to_string(),
+        }];
+        let env = ProcessEnv::default();
+        let checkout = |name: &str, lock: &str| {
+            let path = dir.path().join(name);
+            std::fs::create_dir(&path).unwrap();
+            std::fs::write(path.join("lock"), lock).unwrap();
+            path
+        };
+        let runs = || {
+            std::fs::read_to_string(dir.path().join("runs"))
+                .unwrap()
+                .lines()
+                .count()
+        };
+
+        for name in ["first", "second"] {
+            let path = checkout(name, "1");
+            cache.fill_cached_paths(&path, &paths, &env).unwrap();
+            assert_eq!(
+                std::fs::read_to_string(path.join("deps/lib")).unwrap(),
+                "built\n"
+            );
+        }
+        assert_eq!(runs(), 1);
+
+        let changed = checkout("changed", "2");
+        cache.fill_cached_paths(&changed, &paths, &env).unwrap();
+        assert_eq!(runs(), 2);
+
+        let escape = [CachedPath {
+            path: "../deps".to_string(),
+            ..paths[0].clone()
+        }];
+        assert!(cache.fill_cached_paths(&changed, &escape, &env).is_err());
+    }
+}
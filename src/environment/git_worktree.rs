@@ -1,34 +1,57 @@
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
 
 use serde_json::json;
 
+use crate::config::{PrepareOptions, ProcessEnv};
 use crate::db::Project;
 
-use super::{EnvironmentProvider, ProviderExecCommand, RunSpec};
+use super::{CacheHandle, EnvironmentPlan, EnvironmentProvider, ProviderExecCommand, RunSpec};
 
-pub struct GitWorktreeProvider;
+#[derive(Default)]
+pub struct GitWorktreeProvider {
+    /// Variables for the `git` commands it runs.
+    pub env: ProcessEnv,
+}
 
 impl EnvironmentProvider for GitWorktreeProvider {
     fn prepare(
         &self,
         project: &Project,
         env_id: &str,
+        options: &PrepareOptions,
+        cache: &CacheHandle,
         _log_path: Option<&std::path::Path>,
     ) -> anyhow::Result<serde_json::Value> {
         let worktree_path = crate::paths::data_dir()?.join("worktrees").join(env_id);
         let branch = format!("work-env-{env_id}");
+        let sparse = !options.sparse_paths.is_empty();
 
         std::fs::create_dir_all(worktree_path.parent().unwrap())?;
 
-        let output = Command::new("git")
-            .args([
-                "worktree",
-                "add",
-                "-b",
-                &branch,
-                &worktree_path.to_string_lossy(),
-            ])
+        // Worktrees share the project's object store, so `depth` does not apply.
+        let mut args = vec!["worktree", "add"];
+        if sparse {
+            args.push("--no-checkout");
+        }
+        let worktree_arg = worktree_path.to_string_lossy().into_owned();
+        args.extend(["-b", branch.as_str(), worktree_arg.as_str()]);
+        let source_commit = options
+            .source_ref
+            .as_deref()
+            .map(|source_ref| {
+                super::resolve_git_ref(Path::new(&project.path), source_ref, &self.env)
+            })
+            .transpose()?;
+        if let Some(commit) = source_commit.as_deref() {
+            args.push(commit);
+        } else if let Some(base_branch) = options.base_branch.as_deref() {
+            args.push(base_branch);
+        }
+
+        let output = self
+            .env
+            .command("git")
+            .args(&args)
             .current_dir(&project.path)
             .output()?;
 
@@ -37,23 +60,110 @@ impl EnvironmentProvider for GitWorktreeProvider {
             anyhow::bail!("git worktree add failed: {stderr}");
         }
 
-        Ok(json!({
+        if sparse {
+            self.set_sparse_paths(&worktree_path, &options.sparse_paths)?;
+
+            let output = self
+                .env
+                .command("git")
+                .arg("checkout")
+                .current_dir(&worktree_path)
+                .output()?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                anyhow::bail!("git checkout failed: {stderr}");
+            }
+        }
+        cache.fill_cached_paths(&worktree_path, &options.cached_paths, &self.env)?;
+
+        let mut metadata = json!({
             "project_path": project.path,
             "worktree_path": worktree_path.to_string_lossy(),
             "branch": branch,
-        }))
+        });
+        if let Some(base_branch) = &options.base_branch {
+            metadata["base_branch"] = json!(base_branch);
+        }
+        if let (Some(source_ref), Some(commit)) = (&options.source_ref, &source_commit) {
+            metadata["source_ref"] = json!(source_ref);
+            metadata["source_commit"] = json!(commit);
+            metadata["display_name"] = json!(source_ref);
+        }
+        if sparse {
+            metadata["sparse_paths"] = json!(options.sparse_paths);
+        }
+        Ok(metadata)
+    }
+
+    fn plan(
+        &self,
+        project: &Project,
+        env_id: &str,
+        options: &PrepareOptions,
+    ) -> anyhow::Result<EnvironmentPlan> {
+        let repo = Path::new(&project.path);
+        let worktree_path = crate::paths::data_dir()?.join("worktrees").join(env_id);
+        let branch = format!("work-env-{env_id}");
+        let sparse = !options.sparse_paths.is_empty();
+        let mut steps = Vec::new();
+
+        let start_point = match (&options.source_ref, &options.base_branch) {
+            (Some(source_ref), _) => {
+                match super::resolve_local_git_ref(repo, source_ref, &self.env) {
+                    Some(commit) => Some(commit),
+                    None => {
+                        steps.push(format!("git fetch origin {source_ref}"));
+                        None
+                    }
+                }
+            }
+            (None, Some(base_branch)) => Some(base_branch.clone()),
+            (None, None) => Some("HEAD".to_string()),
+        };
+
+        let mut add = "git worktree add".to_string();
+        if sparse {
+            add.push_str(" --no-checkout");
+        }
+        add.push_str(&format!(" -b {branch} {}", worktree_path.display()));
+        if let Some(start_point) = &start_point {
+            add.push(' ');
+            add.push_str(start_point);
+        }
+        steps.push(add);
+        if sparse {
+            steps.push(format!(
+                "git sparse-checkout set --cone {}",
+                options.sparse_paths.join(" ")
+            ));
+            steps.push("git checkout".to_string());
+        }
+        steps.extend(super::cache::cached_path_steps(&options.cached_paths));
+
+        Ok(EnvironmentPlan {
+            steps,
+            paths: vec![worktree_path.to_string_lossy().into_owned()],
+            branch: Some(branch),
+            estimated_disk_bytes: start_point.and_then(|rev| {
+                super::git_tree_size(repo, &rev, &options.sparse_paths, &self.env).ok()
+            }),
+        })
     }
 
     fn update(
         &self,
         metadata: &serde_json::Value,
+        options: &PrepareOptions,
         _log_path: Option<&std::path::Path>,
     ) -> anyhow::Result<serde_json::Value> {
         let worktree_path = metadata["worktree_path"]
             .as_str()
             .ok_or_else(|| anyhow::anyhow!("missing worktree_path in metadata"))?;
 
-        let output = Command::new("git")
+        let output = self
+            .env
+            .command("git")
             .args(["fetch", "origin"])
             .current_dir(worktree_path)
             .output()?;
@@ -63,8 +173,13 @@ impl EnvironmentProvider for GitWorktreeProvider {
             anyhow::bail!("git fetch failed: {stderr}");
         }
 
-        let output = Command::new("git")
-            .args(["merge", "origin/HEAD"])
+        let upstream = metadata["base_branch"]
+            .as_str()
+            .map_or_else(|| "origin/HEAD".to_string(), |b| format!("origin/{b}"));
+        let output = self
+            .env
+            .command("git")
+            .args(["merge", &upstream])
             .current_dir(worktree_path)
             .output()?;
 
@@ -73,7 +188,19 @@ impl EnvironmentProvider for GitWorktreeProvider {
             anyhow::bail!("git merge failed: {stderr}");
         }
 
-        Ok(metadata.clone())
+        // Follow changes to the configured paths so pooled environments match
+        // ones prepared now.
+        let mut metadata = metadata.clone();
+        if sparse_paths(&metadata) != options.sparse_paths {
+            self.set_sparse_paths(Path::new(worktree_path), &options.sparse_paths)?;
+            match metadata.as_object_mut() {
+                Some(object) if options.sparse_paths.is_empty() => {
+                    object.remove("sparse_paths");
+                }
+                _ => metadata["sparse_paths"] = json!(options.sparse_paths),
+            }
+        }
+        Ok(metadata)
     }
 
     fn claim(
@@ -84,11 +211,47 @@ impl EnvironmentProvider for GitWorktreeProvider {
         Ok(metadata.clone())
     }
 
+    fn claim_conflict(&self, metadata: &serde_json::Value) -> anyhow::Result<Option<String>> {
+        super::worktree_conflict(metadata, &self.env)
+    }
+
+    fn snapshot(
+        &self,
+        metadata: &serde_json::Value,
+        dest: &Path,
+    ) -> anyhow::Result<serde_json::Value> {
+        let worktree_path = metadata["worktree_path"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("missing worktree_path in metadata"))?;
+        super::snapshot::take(
+            Path::new(worktree_path),
+            dest,
+            super::snapshot::Method::Tar,
+            &self.env,
+        )
+    }
+
+    fn restore(
+        &self,
+        metadata: &serde_json::Value,
+        snapshot: &serde_json::Value,
+    ) -> anyhow::Result<()> {
+        let worktree_path = metadata["worktree_path"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("missing worktree_path in metadata"))?;
+        super::snapshot::restore(Path::new(worktree_path), snapshot, &self.env)
+    }
+
+    fn adopt(&self, project: &Project, path: &Path) -> anyhow::Result<serde_json::Value> {
+        super::adopt_git_checkout(project, path, &self.env)
+    }
+
     fn run(
         &self,
         metadata: &serde_json::Value,
         command: &str,
         args: &[String],
+        stdin: Option<&str>,
     ) -> anyhow::Result<RunSpec> {
         let worktree_path = metadata["worktree_path"]
             .as_str()
@@ -98,7 +261,7 @@ impl EnvironmentProvider for GitWorktreeProvider {
             program: command.to_string(),
             args: args.to_vec(),
             cwd: Some(PathBuf::from(worktree_path)),
-            stdin_data: None,
+            stdin_data: stdin.map(|s| s.as_bytes().to_vec()),
             env: Vec::new(),
         })
     }
@@ -158,7 +321,9 @@ impl EnvironmentProvider for GitWorktreeProvider {
             .as_str()
             .ok_or_else(|| anyhow::anyhow!("missing branch in metadata"))?;
 
-        let output = Command::new("git")
+        let output = self
+            .env
+            .command("git")
             .args(["worktree", "remove", "--force", worktree_path])
             .current_dir(project_path)
             .output()?;
@@ -170,7 +335,9 @@ impl EnvironmentProvider for GitWorktreeProvider {
             }
         }
 
-        let output = Command::new("git")
+        let output = self
+            .env
+            .command("git")
             .args(["branch", "-D", branch])
             .current_dir(project_path)
             .output()?;
@@ -186,13 +353,48 @@ impl EnvironmentProvider for GitWorktreeProvider {
     }
 }
 
+impl GitWorktreeProvider {
+    /// Limit the worktree to `paths` with a cone-mode sparse checkout, or
+    /// check out everything again when `paths` is empty.
+    fn set_sparse_paths(&self, worktree_path: &Path, paths: &[String]) -> anyhow::Result<()> {
+        let mut command = self.env.command("git");
+        if paths.is_empty() {
+            command.args(["sparse-checkout", "disable"]);
+        } else {
+            command
+                .args(["sparse-checkout", "set", "--cone"])
+                .args(paths);
+        }
+        let output = command.current_dir(worktree_path).output()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("git sparse-checkout failed: {stderr}");
+        }
+        Ok(())
+    }
+}
+
+/// The sparse checkout paths recorded in an environment's metadata.
+fn sparse_paths(metadata: &serde_json::Value) -> Vec<String> {
+    metadata["sparse_paths"]
+        .as_array()
+        .map(|paths| {
+            paths
+                .iter()
+                .filter_map(|path| path.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn exec_commands_include_cd() {
-        let provider = GitWorktreeProvider;
+        let provider = GitWorktreeProvider::default();
         let commands = provider.exec_commands(&json!({})).unwrap();
 
         assert_eq!(commands.len(), 1);
@@ -201,7 +403,7 @@ mod tests {
 
     #[test]
     fn exec_cd_runs_shell_in_worktree() {
-        let provider = GitWorktreeProvider;
+        let provider = GitWorktreeProvider::default();
         let metadata = json!({ "worktree_path": "/tmp/worktree" });
 
         let run_spec = provider.exec(&metadata, "cd", &[]).unwrap();
@@ -216,7 +418,7 @@ mod tests {
 
     #[test]
     fn exec_non_cd_passthrough() {
-        let provider = GitWorktreeProvider;
+        let provider = GitWorktreeProvider::default();
         let metadata = json!({ "worktree_path": "/tmp/worktree" });
         let args = vec!["-la".to_string()];
 
@@ -226,4 +428,56 @@ mod tests {
         assert_eq!(run_spec.args, args);
         assert_eq!(run_spec.cwd, Some(PathBuf::from("/tmp/worktree")));
     }
+
+    #[test]
+    fn update_applies_changed_sparse_paths() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let git = |cwd: &Path, args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+                .args(args)
+                .current_dir(cwd)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {args:?} failed");
+        };
+        let origin = dir.path().join("origin");
+        std::fs::create_dir_all(origin.join("app")).unwrap();
+        std::fs::create_dir_all(origin.join("docs")).unwrap();
+        std::fs::write(origin.join("app/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(origin.join("docs/guide.md"), "# Guide").unwrap();
+        git(&origin, &["init", "-q", "-b", "main"]);
+        git(&origin, &["add", "."]);
+        git(&origin, &["commit", "-q", "-m", "init"]);
+        git(dir.path(), &["clone", "-q", "origin", "project"]);
+        let project = dir.path().join("project");
+        let worktree = dir.path().join("worktree");
+        git(
+            &project,
+            &["worktree", "add", "-q", "-b", "work-env-1", "../worktree"],
+        );
+
+        let provider = GitWorktreeProvider::default();
+        let metadata = json!({
+            "project_path": project,
+            "worktree_path": worktree,
+            "branch": "work-env-1",
+            "base_branch": "main",
+        });
+        let sparse = PrepareOptions {
+            sparse_paths: vec!["app".to_string()],
+            ..PrepareOptions::default()
+        };
+        let metadata = provider.update(&metadata, &sparse, None).unwrap();
+        assert_eq!(metadata["sparse_paths"], json!(["app"]));
+        assert!(worktree.join("app/main.rs").exists());
+        assert!(!worktree.join("docs/guide.md").exists());
+
+        let metadata = provider
+            .update(&metadata, &PrepareOptions::default(), None)
+            .unwrap();
+        assert!(metadata.get("sparse_paths").is_none());
+        assert!(worktree.join("docs/guide.md").exists());
+    }
 }
@@ -0,0 +1,142 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use crate::config::PrepareOptions;
+use crate::db::Project;
+
+use super::{CacheHandle, EnvironmentPlan, EnvironmentProvider, ProviderExecCommand, RunSpec};
+
+/// Runs tasks in the project checkout itself. Nothing is copied or branched,
+/// so preparing is instant, but the task sees and can change everything in
+/// the checkout, uncommitted work included. It suits quick read-only tasks.
+///
+/// The metadata has no `worktree_path`, so nothing that cleans up, merges,
+/// or diffs worktrees touches the checkout; removing the environment leaves
+/// it alone.
+pub struct InPlaceProvider;
+
+fn project_path(metadata: &serde_json::Value) -> anyhow::Result<PathBuf> {
+    metadata["project_path"]
+        .as_str()
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow::anyhow!("missing project_path in metadata"))
+}
+
+impl EnvironmentProvider for InPlaceProvider {
+    fn prepare(
+        &self,
+        project: &Project,
+        _env_id: &str,
+        _options: &PrepareOptions,
+        _cache: &CacheHandle,
+        _log_path: Option<&Path>,
+    ) -> anyhow::Result<serde_json::Value> {
+        if !Path::new(&project.path).is_dir() {
+            anyhow::bail!("project path {} is not a directory", project.path);
+        }
+        Ok(json!({
+            "project_path": project.path,
+            "in_place": true,
+        }))
+    }
+
+    fn plan(
+        &self,
+        project: &Project,
+        _env_id: &str,
+        _options: &PrepareOptions,
+    ) -> anyhow::Result<EnvironmentPlan> {
+        Ok(EnvironmentPlan {
+            steps: vec![format!("use {} as it is", project.path)],
+            paths: Vec::new(),
+            branch: None,
+            estimated_disk_bytes: Some(0),
+        })
+    }
+
+    fn update(
+        &self,
+        metadata: &serde_json::Value,
+        _options: &PrepareOptions,
+        _log_path: Option<&Path>,
+    ) -> anyhow::Result<serde_json::Value> {
+        Ok(metadata.clone())
+    }
+
+    fn claim(
+        &self,
+        metadata: &serde_json::Value,
+        _log_path: Option<&Path>,
+    ) -> anyhow::Result<serde_json::Value> {
+        Ok(metadata.clone())
+    }
+
+    fn remove(
+        &self,
+        _metadata: &serde_json::Value,
+        _log_path: Option<&Path>,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn adopt(&self, _project: &Project, path: &Path) -> anyhow::Result<serde_json::Value> {
+        anyhow::bail!(
+            "inplace environments are the project checkout; there is nothing to adopt at {}",
+            path.display()
+        )
+    }
+
+    fn run(
+        &self,
+        metadata: &serde_json::Value,
+        command: &str,
+        args: &[String],
+        stdin: Option<&str>,
+    ) -> anyhow::Result<RunSpec> {
+        Ok(RunSpec {
+            program: command.to_string(),
+            args: args.to_vec(),
+            cwd: Some(project_path(metadata)?),
+            stdin_data: stdin.map(|s| s.as_bytes().to_vec()),
+            env: Vec::new(),
+        })
+    }
+
+    fn exec_commands(
+        &self,
+        _metadata: &serde_json::Value,
+    ) -> anyhow::Result<Vec<ProviderExecCommand>> {
+        Ok(vec![ProviderExecCommand {
+            name: "cd".to_string(),
+            help: Some("Open a shell in the project checkout".to_string()),
+        }])
+    }
+
+    fn exec(
+        &self,
+        metadata: &serde_json::Value,
+        command: &str,
+        args: &[String],
+    ) -> anyhow::Result<RunSpec> {
+        let cwd = Some(project_path(metadata)?);
+        if command == "cd" {
+            let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
+            return Ok(RunSpec {
+                program: shell,
+                args: Vec::new(),
+                cwd,
+                stdin_data: None,
+                env: Vec::new(),
+            });
+        }
+
+        Ok(RunSpec {
+            program: command.to_string(),
+            args: args.to_vec(),
+            cwd,
+            stdin_data: None,
+            env: Vec::new(),
+        })
+    }
+}
@@ -0,0 +1,370 @@
+use std::path::Path;
+
+use crate::config::ProcessEnv;
+
+use super::{git_common_dir, git_stdout};
+
+/// How `work task merge` brings an environment's branch into the project.
+pub enum MergeMode {
+    /// Fast-forward when possible, otherwise make a merge commit.
+    Merge,
+    /// Make a single commit on the base branch with this message.
+    Squash { message: String },
+    /// Only bring the branch into the project's repository, leaving the
+    /// checkout alone.
+    FetchOnly,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MergeOutcome {
+    /// The base branch already had every commit on the environment's branch.
+    UpToDate,
+    FastForward,
+    Merged,
+    Squashed,
+    /// The branch is in the project's repository, unmerged.
+    Fetched,
+}
+
+impl MergeOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UpToDate => "up to date",
+            Self::FastForward => "fast-forward",
+            Self::Merged => "merge commit",
+            Self::Squashed => "squashed",
+            Self::Fetched => "fetched",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MergeReport {
+    pub branch: String,
+    /// Where the branch is in the project's repository: the branch itself
+    /// for a worktree of it, or `refs/work/<branch>` once fetched from a
+    /// clone.
+    pub git_ref: String,
+    /// Empty when only fetching.
+    pub base_branch: String,
+    /// The commit the environment's branch was at.
+    pub commit: String,
+    pub outcome: MergeOutcome,
+}
+
+/// Bring the branch of the environment described by `metadata` into the
+/// project checkout at `project_path`, onto its base branch.
+///
+/// The environment's worktree must be clean, so nothing the task left
+/// uncommitted is lost, and the project checkout must be clean and on the
+/// base branch, since that's where the merge happens. A merge that
+/// conflicts is aborted, leaving the branch in the project to merge by hand.
+///
+/// A clone's branch is fetched into `refs/work/<branch>` rather than a
+/// branch of the project, so it can't clobber one the user made.
+/// [`MergeMode::FetchOnly`] stops there.
+pub fn merge_environment(
+    project_path: &Path,
+    metadata: &serde_json::Value,
+    mode: &MergeMode,
+    env: &ProcessEnv,
+) -> anyhow::Result<MergeReport> {
+    let (Some(worktree), Some(branch)) = (
+        metadata["worktree_path"].as_str().map(Path::new),
+        metadata["branch"].as_str(),
+    ) else {
+        anyhow::bail!("the environment has no git branch to merge");
+    };
+    if !worktree.is_dir() {
+        anyhow::bail!("worktree {} is missing", worktree.display());
+    }
+
+    let changes = git_stdout(worktree, &["status", "--porcelain"], env)?;
+    if !changes.is_empty() {
+        let count = changes.lines().count();
+        anyhow::bail!(
+            "worktree {} has {count} uncommitted {}; commit or discard them first",
+            worktree.display(),
+            if count == 1 { "change" } else { "changes" }
+        );
+    }
+    let commit = git_stdout(worktree, &["rev-parse", "--verify", "HEAD"], env)?;
+
+    // A worktree of the project shares its branches; a clone has to be
+    // fetched from.
+    let git_ref = if git_common_dir(worktree, env)? == git_common_dir(project_path, env)? {
+        let git_ref = format!("refs/heads/{branch}");
+        let branch_commit = git_stdout(project_path, &["rev-parse", "--verify", &git_ref], env)?;
+        if branch_commit != commit {
+            anyhow::bail!(
+                "worktree {} isn't on branch {branch}; check it out there first",
+                worktree.display()
+            );
+        }
+        git_ref
+    } else {
+        let git_ref = format!("refs/work/{branch}");
+        let worktree_arg = worktree.to_string_lossy();
+        let refspec = format!("+{commit}:{git_ref}");
+        git_stdout(
+            project_path,
+            &["fetch", "--no-tags", &worktree_arg, &refspec],
+            env,
+        )?;
+        git_ref
+    };
+
+    if matches!(mode, MergeMode::FetchOnly) {
+        return Ok(MergeReport {
+            branch: branch.to_string(),
+            git_ref,
+            base_branch: String::new(),
+            commit,
+            outcome: MergeOutcome::Fetched,
+        });
+    }
+
+    let current = git_stdout(project_path, &["rev-parse", "--abbrev-ref", "HEAD"], env)?;
+    let base_branch = match metadata["base_branch"].as_str() {
+        Some(base) => base.to_string(),
+        None if current == "HEAD" => {
+            anyhow::bail!(
+                "{} has a detached HEAD; check out the branch to merge into",
+                project_path.display()
+            )
+        }
+        None => current.clone(),
+    };
+    if current != base_branch {
+        anyhow::bail!(
+            "{} is on {current}; check out {base_branch} there to merge {branch} into it",
+            project_path.display()
+        );
+    }
+    let changes = git_stdout(
+        project_path,
+        &["status", "--porcelain", "--untracked-files=no"],
+        env,
+    )?;
+    if !changes.is_empty() {
+        anyhow::bail!(
+            "{} has uncommitted changes; commit or stash them first",
+            project_path.display()
+        );
+    }
+
+    let is_ancestor = |ancestor: &str, of: &str| {
+        env.command("git")
+            .args(["merge-base", "--is-ancestor", ancestor, of])
+            .current_dir(project_path)
+            .status()
+            .is_ok_and(|status| status.success())
+    };
+    let outcome = if is_ancestor(&commit, "HEAD") {
+        MergeOutcome::UpToDate
+    } else {
+        match mode {
+            MergeMode::FetchOnly => unreachable!("returned after fetching"),
+            MergeMode::Squash { message } => {
+                merge_or_undo(
+                    project_path,
+                    &["merge", "--squash", &git_ref],
+                    &["reset", "--merge"],
+                    env,
+                )?;
+                git_stdout(project_path, &["commit", "-m", message], env)?;
+                MergeOutcome::Squashed
+            }
+            MergeMode::Merge => {
+                let outcome = if is_ancestor("HEAD", &commit) {
+                    MergeOutcome::FastForward
+                } else {
+                    MergeOutcome::Merged
+                };
+                merge_or_undo(
+                    project_path,
+                    &["merge", "--no-edit", &git_ref],
+                    &["merge", "--abort"],
+                    env,
+                )?;
+                outcome
+            }
+        }
+    };
+
+    Ok(MergeReport {
+        branch: branch.to_string(),
+        git_ref,
+        base_branch,
+        commit,
+        outcome,
+    })
+}
+
+/// Run a merge, and if it fails, run `undo` so the checkout isn't left
+/// mid-merge.
+fn merge_or_undo(
+    project_path: &Path,
+    merge: &[&str],
+    undo: &[&str],
+    env: &ProcessEnv,
+) -> anyhow::Result<()> {
+    if let Err(e) = git_stdout(project_path, merge, env) {
+        let _ = git_stdout(project_path, undo, env);
+        anyhow::bail!("{e}\nthe merge was undone; the branch is still there to merge by hand");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed");
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    /// A project on `main` with a worktree on `work-env-1`, where the task
+    /// committed `file`.
+    fn project_with_worktree(tmp: &Path, file: &str) -> (std::path::PathBuf, serde_json::Value) {
+        let project = tmp.join("project");
+        std::fs::create_dir(&project).unwrap();
+        git(&project, &["init", "-q", "-b", "main"]);
+        git(&project, &["commit", "-q", "--allow-empty", "-m", "init"]);
+        let worktree = tmp.join("worktree");
+        git(
+            &project,
+            &[
+                "worktree",
+                "add",
+                "-q",
+                "-b",
+                "work-env-1",
+                worktree.to_str().unwrap(),
+            ],
+        );
+        std::fs::write(worktree.join(file), "task\n").unwrap();
+        git(&worktree, &["add", file]);
+        git(&worktree, &["commit", "-q", "-m", "task work"]);
+        let metadata = serde_json::json!({
+            "worktree_path": worktree,
+            "branch": "work-env-1",
+        });
+        (project, metadata)
+    }
+
+    fn env() -> ProcessEnv {
+        let mut env = ProcessEnv::default();
+        for key in ["GIT_AUTHOR", "GIT_COMMITTER"] {
+            env.env.insert(format!("{key}_NAME"), "t".to_string());
+            env.env
+                .insert(format!("{key}_EMAIL"), "t@example.com".to_string());
+        }
+        env
+    }
+
+    #[test]
+    fn fast_forwards_then_reports_up_to_date() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let (project, metadata) = project_with_worktree(tmp.path(), "a.txt");
+
+        let report = merge_environment(&project, &metadata, &MergeMode::Merge, &env()).unwrap();
+        assert_eq!(report.outcome, MergeOutcome::FastForward);
+        assert_eq!(report.base_branch, "main");
+        assert_eq!(git(&project, &["rev-parse", "HEAD"]), report.commit);
+        assert!(project.join("a.txt").exists());
+
+        let report = merge_environment(&project, &metadata, &MergeMode::Merge, &env()).unwrap();
+        assert_eq!(report.outcome, MergeOutcome::UpToDate);
+    }
+
+    #[test]
+    fn squashes_into_one_commit_and_undoes_conflicts() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let (project, metadata) = project_with_worktree(tmp.path(), "a.txt");
+        std::fs::write(project.join("b.txt"), "main\n").unwrap();
+        git(&project, &["add", "b.txt"]);
+        git(&project, &["commit", "-q", "-m", "moved on"]);
+
+        let squash = MergeMode::Squash {
+            message: "Do the task".to_string(),
+        };
+        let report = merge_environment(&project, &metadata, &squash, &env()).unwrap();
+        assert_eq!(report.outcome, MergeOutcome::Squashed);
+        assert_eq!(git(&project, &["log", "-1", "--format=%s"]), "Do the task");
+        assert_eq!(git(&project, &["rev-list", "--count", "HEAD"]), "3");
+
+        // The squash commit isn't an ancestor of the branch, so the task
+        // changing the same file again conflicts with it.
+        let worktree = Path::new(metadata["worktree_path"].as_str().unwrap());
+        std::fs::write(worktree.join("a.txt"), "again\n").unwrap();
+        git(worktree, &["commit", "-qam", "more"]);
+        std::fs::write(project.join("a.txt"), "conflict\n").unwrap();
+        git(&project, &["commit", "-qam", "conflicting"]);
+        let err = merge_environment(&project, &metadata, &MergeMode::Merge, &env()).unwrap_err();
+        assert!(err.to_string().contains("merge by hand"), "{err}");
+        assert_eq!(git(&project, &["status", "--porcelain"]), "");
+    }
+
+    #[test]
+    fn fetches_a_clone_into_its_own_ref_before_merging() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let project = tmp.path().join("project");
+        std::fs::create_dir(&project).unwrap();
+        git(&project, &["init", "-q", "-b", "main"]);
+        git(&project, &["commit", "-q", "--allow-empty", "-m", "init"]);
+        let clone = tmp.path().join("clone");
+        git(
+            tmp.path(),
+            &["clone", "-q", project.to_str().unwrap(), "clone"],
+        );
+        git(&clone, &["checkout", "-q", "-b", "work-env-1"]);
+        std::fs::write(clone.join("a.txt"), "task\n").unwrap();
+        git(&clone, &["add", "a.txt"]);
+        git(&clone, &["commit", "-q", "-m", "task work"]);
+        let metadata = serde_json::json!({
+            "worktree_path": clone,
+            "branch": "work-env-1",
+        });
+        let before = git(&project, &["rev-parse", "HEAD"]);
+
+        let report = merge_environment(&project, &metadata, &MergeMode::FetchOnly, &env()).unwrap();
+        assert_eq!(report.outcome, MergeOutcome::Fetched);
+        assert_eq!(report.git_ref, "refs/work/work-env-1");
+        assert_eq!(
+            git(&project, &["rev-parse", &report.git_ref]),
+            report.commit
+        );
+        assert_eq!(git(&project, &["rev-parse", "HEAD"]), before);
+        assert_eq!(git(&project, &["branch", "--list", "work-env-1"]), "");
+
+        let report = merge_environment(&project, &metadata, &MergeMode::Merge, &env()).unwrap();
+        assert_eq!(report.outcome, MergeOutcome::FastForward);
+        assert!(project.join("a.txt").exists());
+    }
+
+    #[test]
+    fn refuses_uncommitted_work_and_the_wrong_checkout() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let (project, metadata) = project_with_worktree(tmp.path(), "a.txt");
+        let worktree = Path::new(metadata["worktree_path"].as_str().unwrap());
+
+        std::fs::write(worktree.join("loose.txt"), "x\n").unwrap();
+        let err = merge_environment(&project, &metadata, &MergeMode::Merge, &env()).unwrap_err();
+        assert!(err.to_string().contains("1 uncommitted change"), "{err}");
+        std::fs::remove_file(worktree.join("loose.txt")).unwrap();
+
+        git(&project, &["checkout", "-q", "-b", "elsewhere"]);
+        let mut on_main = metadata.clone();
+        on_main["base_branch"] = serde_json::json!("main");
+        let err = merge_environment(&project, &on_main, &MergeMode::Merge, &env()).unwrap_err();
+        assert!(err.to_string().contains("check out main"), "{err}");
+    }
+}
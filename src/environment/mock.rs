@@ -0,0 +1,244 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use crate::config::{MockFlags, PrepareOptions, ProcessEnv};
+use crate::db::Project;
+
+use super::{CacheHandle, EnvironmentPlan, EnvironmentProvider, RunSpec};
+
+/// Hands out empty directories under the data directory, so tests and demos
+/// can go through the environment lifecycle without git or a script. Each
+/// step sleeps for and fails according to the [`MockFlags`] it was prepared
+/// with.
+pub struct MockProvider {
+    pub flags: MockFlags,
+    /// Variables for commands run in the environment.
+    pub env: ProcessEnv,
+    /// Directory to create environments in.
+    pub root: PathBuf,
+}
+
+/// The flags an environment was prepared with.
+fn flags(metadata: &serde_json::Value) -> MockFlags {
+    serde_json::from_value(metadata["mock"].clone()).unwrap_or_default()
+}
+
+/// Wait out the flags' latency, then fail if they name `step`.
+fn perform(flags: &MockFlags, step: &str, log_path: Option<&Path>) -> anyhow::Result<()> {
+    std::thread::sleep(std::time::Duration::from_millis(flags.latency_ms));
+    let failed = flags.fail.iter().any(|s| s == step);
+    if let Some(log_path) = log_path {
+        use std::io::Write;
+        let line = if failed {
+            format!("mock: {step} failed\n")
+        } else {
+            format!("mock: {step}\n")
+        };
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path)?
+            .write_all(line.as_bytes())?;
+    }
+    if failed {
+        anyhow::bail!("mock {step} failed");
+    }
+    Ok(())
+}
+
+fn worktree_path(metadata: &serde_json::Value) -> anyhow::Result<PathBuf> {
+    metadata["worktree_path"]
+        .as_str()
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow::anyhow!("missing worktree_path in metadata"))
+}
+
+impl EnvironmentProvider for MockProvider {
+    fn prepare(
+        &self,
+        project: &Project,
+        env_id: &str,
+        _options: &PrepareOptions,
+        _cache: &CacheHandle,
+        log_path: Option<&Path>,
+    ) -> anyhow::Result<serde_json::Value> {
+        perform(&self.flags, "prepare", log_path)?;
+        let path = self.root.join(env_id);
+        std::fs::create_dir_all(&path)?;
+        Ok(json!({
+            "project_path": project.path,
+            "worktree_path": path,
+            "mock": self.flags,
+        }))
+    }
+
+    fn plan(
+        &self,
+        _project: &Project,
+        env_id: &str,
+        _options: &PrepareOptions,
+    ) -> anyhow::Result<EnvironmentPlan> {
+        let path = self.root.join(env_id);
+        Ok(EnvironmentPlan {
+            steps: vec![format!("mkdir -p {}", path.display())],
+            paths: vec![path.to_string_lossy().into_owned()],
+            branch: None,
+            estimated_disk_bytes: Some(0),
+        })
+    }
+
+    fn update(
+        &self,
+        metadata: &serde_json::Value,
+        _options: &PrepareOptions,
+        log_path: Option<&Path>,
+    ) -> anyhow::Result<serde_json::Value> {
+        perform(&flags(metadata), "update", log_path)?;
+        Ok(metadata.clone())
+    }
+
+    fn claim(
+        &self,
+        metadata: &serde_json::Value,
+        log_path: Option<&Path>,
+    ) -> anyhow::Result<serde_json::Value> {
+        perform(&flags(metadata), "claim", log_path)?;
+        Ok(metadata.clone())
+    }
+
+    fn remove(&self, metadata: &serde_json::Value, log_path: Option<&Path>) -> anyhow::Result<()> {
+        perform(&flags(metadata), "remove", log_path)?;
+        match std::fs::remove_dir_all(worktree_path(metadata)?) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn snapshot(
+        &self,
+        metadata: &serde_json::Value,
+        dest: &Path,
+    ) -> anyhow::Result<serde_json::Value> {
+        perform(&flags(metadata), "snapshot", None)?;
+        super::snapshot::take(
+            &worktree_path(metadata)?,
+            dest,
+            super::snapshot::Method::Tar,
+            &self.env,
+        )
+    }
+
+    fn restore(
+        &self,
+        metadata: &serde_json::Value,
+        snapshot: &serde_json::Value,
+    ) -> anyhow::Result<()> {
+        perform(&flags(metadata), "restore", None)?;
+        super::snapshot::restore(&worktree_path(metadata)?, snapshot, &self.env)
+    }
+
+    fn adopt(&self, project: &Project, path: &Path) -> anyhow::Result<serde_json::Value> {
+        if !path.is_dir() {
+            anyhow::bail!("path is not a directory: {}", path.display());
+        }
+        Ok(json!({
+            "project_path": project.path,
+            "worktree_path": path,
+            "mock": self.flags,
+        }))
+    }
+
+    fn run(
+        &self,
+        metadata: &serde_json::Value,
+        command: &str,
+        args: &[String],
+        stdin: Option<&str>,
+    ) -> anyhow::Result<RunSpec> {
+        perform(&flags(metadata), "run", None)?;
+        Ok(RunSpec {
+            program: command.to_string(),
+            args: args.to_vec(),
+            cwd: Some(worktree_path(metadata)?),
+            stdin_data: stdin.map(|s| s.as_bytes().to_vec()),
+            env: self.env.vars(),
+        })
+    }
+
+    fn exec(
+        &self,
+        metadata: &serde_json::Value,
+        command: &str,
+        args: &[String],
+    ) -> anyhow::Result<RunSpec> {
+        Ok(RunSpec {
+            program: command.to_string(),
+            args: args.to_vec(),
+            cwd: Some(worktree_path(metadata)?),
+            stdin_data: None,
+            env: self.env.vars(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project() -> Project {
+        Project {
+            id: "p".to_string(),
+            name: "project".to_string(),
+            path: "/nonexistent/project".to_string(),
+            created_at: String::new(),
+            updated_at: String::new(),
+            aliases: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn later_steps_follow_the_flags_in_metadata() {
+        let home = tempfile::TempDir::new().unwrap();
+        let provider = MockProvider {
+            flags: MockFlags {
+                latency_ms: 0,
+                fail: vec!["claim".to_string()],
+            },
+            env: ProcessEnv::default(),
+            root: home.path().join("mock"),
+        };
+        let cache = CacheHandle::new(home.path().join("cache"));
+        let log = home.path().join("env.log");
+
+        let metadata = provider
+            .prepare(
+                &project(),
+                "env1",
+                &PrepareOptions::default(),
+                &cache,
+                Some(&log),
+            )
+            .unwrap();
+        let path = worktree_path(&metadata).unwrap();
+        assert!(path.is_dir());
+
+        let err = provider.claim(&metadata, Some(&log)).unwrap_err();
+        assert_eq!(err.to_string(), "mock claim failed");
+
+        // Flags in the metadata win over the provider's own.
+        let mut healed = metadata.clone();
+        healed["mock"]["fail"] = json!([]);
+        provider.claim(&healed, Some(&log)).unwrap();
+        let run = provider.run(&healed, "true", &[], Some("input")).unwrap();
+        assert_eq!(run.cwd.as_deref(), Some(path.as_path()));
+
+        provider.remove(&healed, Some(&log)).unwrap();
+        assert!(!path.exists());
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            "mock: prepare\nmock: claim failed\nmock: claim\nmock: remove\n"
+        );
+    }
+}
@@ -1,9 +1,21 @@
 mod apfs_worktree;
+mod cache;
 mod git_worktree;
+mod inplace;
+mod merge;
+mod mock;
 mod script;
+mod snapshot;
+mod ssh;
+mod tmpfs;
+
+pub use cache::{CacheHandle, Eviction, cache_key, cache_key_for_files};
+pub use merge::{MergeMode, MergeOutcome, MergeReport, merge_environment};
+pub use snapshot::{remove_snapshots, snapshot_size};
 
 use std::path::{Path, PathBuf};
 
+use crate::config::{EnvironmentProviderEntry, PrepareOptions, ProcessEnv};
 use crate::db::Project;
 
 pub struct RunSpec {
@@ -14,21 +26,130 @@ pub struct RunSpec {
     pub env: Vec<(String, String)>,
 }
 
+impl RunSpec {
+    /// Add the variables every command in an environment gets, whether a
+    /// task runs it or `work env exec` does: `WORK_ENV_ID`, and
+    /// `WORK_PROJECT_PATH` for the project's checkout. Variables the provider
+    /// set take precedence.
+    pub fn in_environment(mut self, env_id: &str, project_path: &str) -> RunSpec {
+        let mut env = vec![
+            ("WORK_ENV_ID".to_string(), env_id.to_string()),
+            ("WORK_PROJECT_PATH".to_string(), project_path.to_string()),
+        ];
+        env.append(&mut self.env);
+        RunSpec { env, ..self }
+    }
+
+    /// Run the command under `script(1)`, which saves everything the session
+    /// prints, including echoed input, to `typescript`.
+    pub fn recorded(self, typescript: &Path) -> RunSpec {
+        let typescript = typescript.to_string_lossy().into_owned();
+        let args = if cfg!(target_os = "linux") {
+            // util-linux `script` takes the command as a single shell string.
+            let command: Vec<String> = std::iter::once(&self.program)
+                .chain(&self.args)
+                .map(|arg| shell_quote(arg))
+                .collect();
+            vec![
+                "-q".to_string(),
+                "-e".to_string(),
+                "-c".to_string(),
+                command.join(" "),
+                typescript,
+            ]
+        } else {
+            let mut args = vec!["-q".to_string(), typescript, self.program];
+            args.extend(self.args);
+            args
+        };
+        RunSpec {
+            program: "script".to_string(),
+            args,
+            ..self
+        }
+    }
+}
+
+/// Quote `arg` as a single word for a POSIX shell.
+pub fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Disk usage of `path` in KB as `du` counts it, or `None` if `du` fails.
+/// Large checkouts can take a while.
+pub fn disk_usage_kb(path: &str) -> Option<u64> {
+    let output = std::process::Command::new("du")
+        .args(["-sk", path])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
 pub struct ProviderExecCommand {
     pub name: String,
     pub help: Option<String>,
 }
 
+/// A progress update reported by a provider while it works.
+#[derive(Debug, PartialEq)]
+pub struct Progress {
+    /// Fraction done, from 0.0 to 1.0.
+    pub fraction: Option<f64>,
+    pub message: Option<String>,
+}
+
+/// What `prepare` would do, reported by `plan` without side effects.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct EnvironmentPlan {
+    /// Steps `prepare` would take, in order.
+    #[serde(default)]
+    pub steps: Vec<String>,
+    /// Paths `prepare` would create.
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Branch `prepare` would create.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// Rough size of the checkout. Copy-on-write providers may use less.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub estimated_disk_bytes: Option<u64>,
+}
+
+pub type ProgressSink = Box<dyn Fn(Progress) + Send + Sync>;
+
 pub trait EnvironmentProvider {
+    /// Send progress reported during later `prepare`, `update`, and `claim`
+    /// calls to `sink`. Providers that cannot report progress ignore it.
+    fn on_progress(&mut self, _sink: ProgressSink) {}
+    /// Prepare a new environment. Work that can be reused across
+    /// environments, such as fetched dependencies, belongs in `cache`.
     fn prepare(
         &self,
         project: &Project,
         env_id: &str,
+        options: &PrepareOptions,
+        cache: &CacheHandle,
         log_path: Option<&Path>,
     ) -> anyhow::Result<serde_json::Value>;
+    /// Describe what `prepare` would do for `env_id` without doing it.
+    fn plan(
+        &self,
+        project: &Project,
+        env_id: &str,
+        options: &PrepareOptions,
+    ) -> anyhow::Result<EnvironmentPlan>;
+    /// Bring a pooled environment up to date. `options` are what a new
+    /// environment would be prepared with now, so settings changed since
+    /// `prepare` can be applied.
     fn update(
         &self,
         metadata: &serde_json::Value,
+        options: &PrepareOptions,
         log_path: Option<&Path>,
     ) -> anyhow::Result<serde_json::Value>;
     fn claim(
@@ -37,11 +158,38 @@ pub trait EnvironmentProvider {
         log_path: Option<&Path>,
     ) -> anyhow::Result<serde_json::Value>;
     fn remove(&self, metadata: &serde_json::Value, log_path: Option<&Path>) -> anyhow::Result<()>;
+    /// Why a pooled environment should not be handed to a new task, such as
+    /// changes left behind by an earlier one. `None` means it is safe.
+    fn claim_conflict(&self, _metadata: &serde_json::Value) -> anyhow::Result<Option<String>> {
+        Ok(None)
+    }
+    /// Save the environment's files under `dest`, a directory of its own,
+    /// and return what `restore` needs to put them back.
+    fn snapshot(
+        &self,
+        _metadata: &serde_json::Value,
+        _dest: &Path,
+    ) -> anyhow::Result<serde_json::Value> {
+        Err(snapshot::unsupported())
+    }
+    /// Put the environment's files back as `snapshot` saved them.
+    fn restore(
+        &self,
+        _metadata: &serde_json::Value,
+        _snapshot: &serde_json::Value,
+    ) -> anyhow::Result<()> {
+        Err(snapshot::unsupported())
+    }
+    /// Build metadata for an existing checkout at `path` without preparing it.
+    fn adopt(&self, project: &Project, path: &Path) -> anyhow::Result<serde_json::Value> {
+        adopt_git_checkout(project, path, &ProcessEnv::default())
+    }
     fn run(
         &self,
         metadata: &serde_json::Value,
         command: &str,
         args: &[String],
+        stdin: Option<&str>,
     ) -> anyhow::Result<RunSpec>;
     fn exec_commands(
         &self,
@@ -57,29 +205,305 @@ pub trait EnvironmentProvider {
     ) -> anyhow::Result<RunSpec>;
 }
 
+/// Infer environment metadata from an existing git worktree of `project`.
+pub fn adopt_git_checkout(
+    project: &Project,
+    path: &Path,
+    env: &ProcessEnv,
+) -> anyhow::Result<serde_json::Value> {
+    if !path.is_dir() {
+        anyhow::bail!("path is not a directory: {}", path.display());
+    }
+    if path == Path::new(&project.path) {
+        anyhow::bail!("cannot adopt the project checkout itself");
+    }
+
+    let common_dir = git_common_dir(path, env)?;
+    if common_dir != git_common_dir(Path::new(&project.path), env)? {
+        anyhow::bail!(
+            "{} is not a worktree of project {}",
+            path.display(),
+            project.name
+        );
+    }
+
+    let branch = git_stdout(path, &["rev-parse", "--abbrev-ref", "HEAD"], env)?;
+    if branch == "HEAD" {
+        anyhow::bail!(
+            "{} has a detached HEAD; check out a branch first",
+            path.display()
+        );
+    }
+
+    Ok(serde_json::json!({
+        "project_path": project.path,
+        "worktree_path": path.to_string_lossy(),
+        "branch": branch,
+    }))
+}
+
+/// Check that a pooled git worktree is clean and still on its own branch.
+pub fn worktree_conflict(
+    metadata: &serde_json::Value,
+    env: &ProcessEnv,
+) -> anyhow::Result<Option<String>> {
+    let worktree_path = metadata["worktree_path"]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("missing worktree_path in metadata"))?;
+    let path = Path::new(worktree_path);
+    if !path.is_dir() {
+        return Ok(Some(format!("worktree {worktree_path} is missing")));
+    }
+
+    let changes = git_stdout(path, &["status", "--porcelain"], env)?;
+    if !changes.is_empty() {
+        let count = changes.lines().count();
+        return Ok(Some(format!(
+            "worktree has {count} uncommitted {}",
+            if count == 1 { "change" } else { "changes" }
+        )));
+    }
+
+    if let Some(expected) = metadata["branch"].as_str() {
+        let branch = git_stdout(path, &["rev-parse", "--abbrev-ref", "HEAD"], env)?;
+        if branch != expected {
+            return Ok(Some(format!(
+                "worktree is on branch {branch}, expected {expected}"
+            )));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Why a pooled git worktree is behind its project: the commit a new
+/// environment would start from, the tip of its base branch or else the
+/// project checkout's `HEAD`, isn't in its history. `None` means it's
+/// current, or isn't a git checkout this can tell about.
+pub fn worktree_outdated(
+    project_path: &Path,
+    metadata: &serde_json::Value,
+    env: &ProcessEnv,
+) -> anyhow::Result<Option<String>> {
+    let Some(worktree) = metadata["worktree_path"].as_str().map(Path::new) else {
+        return Ok(None);
+    };
+    if !worktree.is_dir() {
+        return Ok(None);
+    }
+    let base = metadata["base_branch"].as_str().unwrap_or("HEAD");
+    let start = if base == "HEAD" {
+        base.to_string()
+    } else {
+        format!("refs/heads/{base}")
+    };
+    let commit = git_stdout(
+        project_path,
+        &["rev-parse", "--verify", &format!("{start}^{{commit}}")],
+        env,
+    )?;
+
+    // A clone may not have the commit at all, which `--is-ancestor` reports
+    // as an error rather than a no; either way it's behind.
+    let current = env
+        .command("git")
+        .args(["merge-base", "--is-ancestor", &commit, "HEAD"])
+        .current_dir(worktree)
+        .output()?
+        .status
+        .success();
+    if current {
+        return Ok(None);
+    }
+    let short = &commit[..commit.len().min(7)];
+    Ok(Some(if base == "HEAD" {
+        format!("project moved to {short}")
+    } else {
+        format!("{base} moved to {short}")
+    }))
+}
+
+/// Resolve a branch or commit in the repository at `repo` to a commit id.
+///
+/// Local refs win; a branch that only exists on `origin` is fetched first.
+pub fn resolve_git_ref(repo: &Path, git_ref: &str, env: &ProcessEnv) -> anyhow::Result<String> {
+    if git_ref.is_empty() || git_ref.starts_with('-') {
+        anyhow::bail!("invalid ref: {git_ref:?}");
+    }
+
+    if let Some(commit) = resolve_local_git_ref(repo, git_ref, env) {
+        return Ok(commit);
+    }
+
+    git_stdout(repo, &["fetch", "origin", git_ref], env)
+        .map_err(|e| anyhow::anyhow!("ref not found: {git_ref} ({e})"))?;
+    verify_commit(repo, "FETCH_HEAD", env)
+        .ok_or_else(|| anyhow::anyhow!("ref not found: {git_ref}"))
+}
+
+/// Like [`resolve_git_ref`], but never fetches. `None` means the ref would
+/// have to be fetched from `origin` first.
+pub fn resolve_local_git_ref(repo: &Path, git_ref: &str, env: &ProcessEnv) -> Option<String> {
+    if git_ref.is_empty() || git_ref.starts_with('-') {
+        return None;
+    }
+    verify_commit(repo, git_ref, env)
+        .or_else(|| verify_commit(repo, &format!("origin/{git_ref}"), env))
+}
+
+fn verify_commit(repo: &Path, candidate: &str, env: &ProcessEnv) -> Option<String> {
+    git_stdout(
+        repo,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{candidate}^{{commit}}"),
+        ],
+        env,
+    )
+    .ok()
+}
+
+/// Total size of the files a checkout of `rev` would contain. With
+/// `sparse_paths`, only those directories and top-level files count, as in a
+/// cone-mode sparse checkout.
+pub fn git_tree_size(
+    repo: &Path,
+    rev: &str,
+    sparse_paths: &[String],
+    env: &ProcessEnv,
+) -> anyhow::Result<u64> {
+    let listing = git_stdout(repo, &["ls-tree", "-r", "-l", "-z", rev], env)?;
+    let mut total = 0;
+    for entry in listing.split('\0') {
+        // "<mode> <type> <object> <size>\t<path>"; submodules have size "-".
+        let Some((info, path)) = entry.split_once('\t') else {
+            continue;
+        };
+        let in_sparse_set = sparse_paths.is_empty()
+            || !path.contains('/')
+            || sparse_paths.iter().any(|dir| {
+                let dir = dir.trim_end_matches('/');
+                path.strip_prefix(dir)
+                    .is_some_and(|rest| rest.starts_with('/'))
+            });
+        if in_sparse_set {
+            total += info
+                .rsplit(' ')
+                .next()
+                .and_then(|size| size.trim().parse::<u64>().ok())
+                .unwrap_or(0);
+        }
+    }
+    Ok(total)
+}
+
+pub fn git_common_dir(dir: &Path, env: &ProcessEnv) -> anyhow::Result<PathBuf> {
+    let common_dir = PathBuf::from(git_stdout(dir, &["rev-parse", "--git-common-dir"], env)?);
+    Ok(dir.join(common_dir).canonicalize()?)
+}
+
+fn git_stdout(dir: &Path, args: &[&str], env: &ProcessEnv) -> anyhow::Result<String> {
+    let output = env.command("git").args(args).current_dir(dir).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("git {} failed: {stderr}", args.join(" "));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
 pub fn list_providers() -> Vec<String> {
-    let mut providers = vec!["git-worktree".to_string(), "apfs-worktree".to_string()];
+    let mut providers = vec![
+        "git-worktree".to_string(),
+        "apfs-worktree".to_string(),
+        "tmpfs".to_string(),
+        crate::config::IN_PLACE_PROVIDER.to_string(),
+    ];
 
     if let Ok(config) = crate::config::load()
         && let Some(envs) = &config.environments
     {
-        providers.extend(envs.providers.keys().cloned());
+        providers.extend(
+            envs.providers
+                .iter()
+                .filter(|(_, entry)| matches!(entry, EnvironmentProviderEntry::Custom(_)))
+                .map(|(name, _)| name.clone()),
+        );
     }
 
     providers
 }
 
+/// The config, if it has an `[environments.providers.NAME]` table for the
+/// built-in provider `name`. Without one the built-in runs with its defaults,
+/// so settings for other providers can't keep it from loading.
+fn built_in_config(name: &str) -> anyhow::Result<Option<crate::config::Config>> {
+    let table = match crate::config::accepted() {
+        Some(table) => table,
+        None => crate::config::load_table()?,
+    };
+    let configured = table
+        .get("environments")
+        .and_then(|e| e.get("providers"))
+        .and_then(|p| p.get(name))
+        .is_some();
+    if !configured {
+        return Ok(None);
+    }
+    Ok(Some(toml::Value::Table(table).try_into()?))
+}
+
 pub fn get_provider(name: &str) -> anyhow::Result<Box<dyn EnvironmentProvider>> {
     match name {
-        "git-worktree" => Ok(Box::new(git_worktree::GitWorktreeProvider)),
-        "apfs-worktree" => Ok(Box::new(apfs_worktree::ApfsWorktreeProvider)),
+        "git-worktree" => Ok(Box::new(git_worktree::GitWorktreeProvider {
+            env: built_in_config(name)?
+                .map(|config| config.provider_process_env(name))
+                .unwrap_or_default(),
+        })),
+        "apfs-worktree" => Ok(Box::new(apfs_worktree::ApfsWorktreeProvider {
+            env: built_in_config(name)?
+                .map(|config| config.provider_process_env(name))
+                .unwrap_or_default(),
+        })),
+        "tmpfs" => {
+            let config = built_in_config(name)?.unwrap_or_default();
+            let settings = config.built_in_provider(name);
+            Ok(Box::new(tmpfs::TmpfsProvider {
+                env: config.provider_process_env(name),
+                root: settings.and_then(|s| s.root.as_ref()).map(PathBuf::from),
+                max_bytes: settings
+                    .and_then(|s| s.max_size_mb)
+                    .map(|mb| mb * 1024 * 1024),
+            }))
+        }
+        crate::config::IN_PLACE_PROVIDER => Ok(Box::new(inplace::InPlaceProvider)),
         _ => {
             let config = crate::config::load()?;
             let env_config = config.get_environment_provider(name)?;
             match env_config {
-                crate::config::EnvironmentProviderConfig::Script { path } => {
-                    Ok(Box::new(script::ScriptProvider { path: path.clone() }))
-                }
+                crate::config::EnvironmentProviderConfig::Script {
+                    path, process_env, ..
+                } => Ok(Box::new(script::ScriptProvider {
+                    path: path.clone(),
+                    progress: None,
+                    env: process_env.clone(),
+                })),
+                crate::config::EnvironmentProviderConfig::Mock {
+                    flags, process_env, ..
+                } => Ok(Box::new(mock::MockProvider {
+                    flags: flags.clone(),
+                    env: process_env.clone(),
+                    root: crate::paths::data_dir()?.join("mock-environments"),
+                })),
+                crate::config::EnvironmentProviderConfig::Ssh {
+                    remote,
+                    process_env,
+                    ..
+                } => Ok(Box::new(ssh::SshProvider {
+                    remote: remote.clone(),
+                    env: process_env.clone(),
+                })),
             }
         }
     }
@@ -87,12 +511,264 @@ pub fn get_provider(name: &str) -> anyhow::Result<Box<dyn EnvironmentProvider>>
 
 #[cfg(test)]
 mod tests {
-    use super::list_providers;
+    use super::{
+        RunSpec, adopt_git_checkout, git_tree_size, list_providers, worktree_conflict,
+        worktree_outdated,
+    };
+    use crate::config::ProcessEnv;
+    use crate::db::Project;
 
     #[test]
     fn built_in_providers_are_listed() {
         let providers = list_providers();
         assert!(providers.contains(&"git-worktree".to_string()));
         assert!(providers.contains(&"apfs-worktree".to_string()));
+        assert!(providers.contains(&"tmpfs".to_string()));
+        assert!(providers.contains(&"inplace".to_string()));
+    }
+
+    #[test]
+    fn recorded_run_spec_wraps_command_in_script() {
+        let run_spec = RunSpec {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), "echo 'hi'".to_string()],
+            cwd: Some("/tmp/env".into()),
+            stdin_data: None,
+            env: vec![("A".to_string(), "1".to_string())],
+        }
+        .recorded(std::path::Path::new("/tmp/session.log"));
+
+        assert_eq!(run_spec.program, "script");
+        assert_eq!(run_spec.cwd, Some("/tmp/env".into()));
+        assert_eq!(run_spec.env, [("A".to_string(), "1".to_string())]);
+        if cfg!(target_os = "linux") {
+            assert_eq!(
+                run_spec.args,
+                [
+                    "-q",
+                    "-e",
+                    "-c",
+                    r"'sh' '-c' 'echo '\''hi'\'''",
+                    "/tmp/session.log"
+                ]
+            );
+        } else {
+            assert_eq!(
+                run_spec.args,
+                ["-q", "/tmp/session.log", "sh", "-c", "echo 'hi'"]
+            );
+        }
+    }
+
+    #[test]
+    fn in_environment_adds_standard_variables_before_provider_ones() {
+        let run_spec = RunSpec {
+            program: "true".to_string(),
+            args: Vec::new(),
+            cwd: None,
+            stdin_data: None,
+            env: vec![("WORK_PROJECT_PATH".to_string(), "/remote".to_string())],
+        }
+        .in_environment("env-1", "/src/app");
+
+        assert_eq!(
+            run_spec.env,
+            [
+                ("WORK_ENV_ID".to_string(), "env-1".to_string()),
+                ("WORK_PROJECT_PATH".to_string(), "/src/app".to_string()),
+                ("WORK_PROJECT_PATH".to_string(), "/remote".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn adopt_infers_branch_from_existing_worktree() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = dir.path().join("repo");
+        let worktree = dir.path().join("feature");
+        let git = |cwd: &std::path::Path, args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+                .args(args)
+                .current_dir(cwd)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {args:?} failed");
+        };
+        std::fs::create_dir(&repo).unwrap();
+        git(&repo, &["init", "-q"]);
+        git(&repo, &["commit", "-q", "--allow-empty", "-m", "init"]);
+        git(
+            &repo,
+            &[
+                "worktree",
+                "add",
+                "-q",
+                "-b",
+                "feature",
+                &worktree.to_string_lossy(),
+            ],
+        );
+
+        let project = Project {
+            id: "p1".to_string(),
+            name: "repo".to_string(),
+            path: repo.to_string_lossy().into_owned(),
+            created_at: String::new(),
+            updated_at: String::new(),
+            aliases: Vec::new(),
+        };
+
+        let metadata = adopt_git_checkout(&project, &worktree, &ProcessEnv::default()).unwrap();
+        assert_eq!(metadata["branch"], "feature");
+        assert_eq!(metadata["project_path"], project.path);
+        assert!(adopt_git_checkout(&project, &repo, &ProcessEnv::default()).is_err());
+    }
+
+    #[test]
+    fn worktree_conflict_flags_changes_and_wrong_branch() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = dir.path().join("repo");
+        let worktree = dir.path().join("env");
+        let git = |cwd: &std::path::Path, args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+                .args(args)
+                .current_dir(cwd)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {args:?} failed");
+        };
+        std::fs::create_dir(&repo).unwrap();
+        git(&repo, &["init", "-q"]);
+        git(&repo, &["commit", "-q", "--allow-empty", "-m", "init"]);
+        git(
+            &repo,
+            &[
+                "worktree",
+                "add",
+                "-q",
+                "-b",
+                "work-env-1",
+                &worktree.to_string_lossy(),
+            ],
+        );
+        let metadata = serde_json::json!({
+            "worktree_path": worktree.to_string_lossy(),
+            "branch": "work-env-1",
+        });
+
+        assert_eq!(
+            worktree_conflict(&metadata, &ProcessEnv::default()).unwrap(),
+            None
+        );
+
+        std::fs::write(worktree.join("scratch.txt"), "leftover").unwrap();
+        assert_eq!(
+            worktree_conflict(&metadata, &ProcessEnv::default())
+                .unwrap()
+                .as_deref(),
+            Some("worktree has 1 uncommitted change")
+        );
+
+        std::fs::remove_file(worktree.join("scratch.txt")).unwrap();
+        git(&worktree, &["checkout", "-q", "-b", "experiment"]);
+        assert_eq!(
+            worktree_conflict(&metadata, &ProcessEnv::default())
+                .unwrap()
+                .as_deref(),
+            Some("worktree is on branch experiment, expected work-env-1")
+        );
+    }
+
+    #[test]
+    fn worktree_outdated_once_the_base_branch_moves_past_it() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = dir.path().join("repo");
+        let worktree = dir.path().join("env");
+        let git = |cwd: &std::path::Path, args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+                .args(args)
+                .current_dir(cwd)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {args:?} failed");
+        };
+        std::fs::create_dir(&repo).unwrap();
+        git(&repo, &["init", "-q", "-b", "main"]);
+        git(&repo, &["commit", "-q", "--allow-empty", "-m", "init"]);
+        git(
+            &repo,
+            &[
+                "worktree",
+                "add",
+                "-q",
+                "-b",
+                "work-env-1",
+                &worktree.to_string_lossy(),
+            ],
+        );
+        let mut metadata = serde_json::json!({
+            "worktree_path": worktree.to_string_lossy(),
+            "branch": "work-env-1",
+        });
+        let outdated = |metadata: &serde_json::Value| {
+            worktree_outdated(&repo, metadata, &ProcessEnv::default()).unwrap()
+        };
+
+        assert_eq!(outdated(&metadata), None);
+        // Commits in the environment don't make it outdated.
+        git(&worktree, &["commit", "-q", "--allow-empty", "-m", "task"]);
+        assert_eq!(outdated(&metadata), None);
+
+        git(&repo, &["commit", "-q", "--allow-empty", "-m", "moved"]);
+        assert!(
+            outdated(&metadata)
+                .unwrap()
+                .starts_with("project moved to ")
+        );
+        metadata["base_branch"] = serde_json::json!("main");
+        assert!(outdated(&metadata).unwrap().starts_with("main moved to "));
+
+        git(&worktree, &["merge", "-q", "--no-edit", "main"]);
+        assert_eq!(outdated(&metadata), None);
+    }
+
+    #[test]
+    fn tree_size_honors_sparse_paths() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = dir.path();
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+                .args(args)
+                .current_dir(repo)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {args:?} failed");
+        };
+        git(&["init", "-q"]);
+        std::fs::create_dir_all(repo.join("app/src")).unwrap();
+        std::fs::create_dir(repo.join("docs")).unwrap();
+        std::fs::write(repo.join("app/src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(repo.join("docs/guide.md"), "# Guide").unwrap();
+        std::fs::write(repo.join("README"), "hi").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "init"]);
+
+        assert_eq!(
+            git_tree_size(repo, "HEAD", &[], &ProcessEnv::default()).unwrap(),
+            12 + 7 + 2
+        );
+        assert_eq!(
+            git_tree_size(repo, "HEAD", &["app/".to_string()], &ProcessEnv::default()).unwrap(),
+            12 + 2
+        );
+        assert!(git_tree_size(repo, "missing", &[], &ProcessEnv::default()).is_err());
     }
 }
@@ -1,14 +1,38 @@
 use std::path::Path;
-use std::process::{Command, Stdio};
+use std::process::Stdio;
 
 use serde_json::json;
 
+use crate::config::{PrepareOptions, ProcessEnv};
 use crate::db::Project;
 
-use super::{EnvironmentProvider, ProviderExecCommand, RunSpec};
+use super::{
+    CacheHandle, EnvironmentPlan, EnvironmentProvider, Progress, ProgressSink, ProviderExecCommand,
+    RunSpec,
+};
+
+/// Stderr lines starting with this carry a JSON progress update, e.g.
+/// `work-progress: {"progress": 0.4, "message": "cloning"}`.
+pub const PROGRESS_PREFIX: &str = "work-progress:";
 
 pub struct ScriptProvider {
     pub path: String,
+    pub progress: Option<ProgressSink>,
+    /// Variables for every invocation of the script.
+    pub env: ProcessEnv,
+}
+
+/// Parse a progress line written by a script. `progress` is clamped to
+/// 0.0..=1.0; lines with neither `progress` nor `message` are not progress.
+fn parse_progress_line(line: &str) -> Option<Progress> {
+    let value: serde_json::Value =
+        serde_json::from_str(line.strip_prefix(PROGRESS_PREFIX)?.trim()).ok()?;
+    let fraction = value["progress"].as_f64().map(|f| f.clamp(0.0, 1.0));
+    let message = value["message"].as_str().map(str::to_string);
+    if fraction.is_none() && message.is_none() {
+        return None;
+    }
+    Some(Progress { fraction, message })
 }
 
 impl ScriptProvider {
@@ -21,20 +45,30 @@ impl ScriptProvider {
     ) -> anyhow::Result<serde_json::Value> {
         let input_bytes = serde_json::to_vec(input)?;
 
-        let mut command = Command::new(&self.path);
+        let mut command = self.env.command(&self.path);
         command
             .arg(action)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped());
-        if let Some(path) = log_path {
-            if let Some(parent) = path.parent() {
-                std::fs::create_dir_all(parent)?;
+        let log_file = match log_path {
+            Some(path) => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                Some(
+                    std::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)?,
+                )
             }
-            let stderr_file = std::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(path)?;
-            command.stderr(Stdio::from(stderr_file));
+            None => None,
+        };
+        // Stderr is read line by line so progress lines can be picked out;
+        // everything else goes where it would have gone anyway.
+        let filter_stderr = log_file.is_some() || self.progress.is_some();
+        if filter_stderr {
+            command.stderr(Stdio::piped());
         } else if quiet_stderr {
             command.stderr(Stdio::null());
         } else {
@@ -43,14 +77,15 @@ impl ScriptProvider {
 
         let mut child = command.spawn()?;
 
-        use std::io::Write;
-        child
-            .stdin
-            .take()
-            .ok_or_else(|| anyhow::anyhow!("failed to open stdin"))?
-            .write_all(&input_bytes)?;
+        write_input(&mut child, &input_bytes)?;
 
-        let output = child.wait_with_output()?;
+        let stderr = child.stderr.take();
+        let output = std::thread::scope(|scope| {
+            if let Some(stderr) = stderr {
+                scope.spawn(|| self.forward_stderr(stderr, log_file, quiet_stderr));
+            }
+            child.wait_with_output()
+        })?;
 
         if !output.status.success() {
             anyhow::bail!(
@@ -65,6 +100,31 @@ impl ScriptProvider {
         Ok(serde_json::from_str(stdout.trim())?)
     }
 
+    fn forward_stderr(
+        &self,
+        stderr: std::process::ChildStderr,
+        mut log_file: Option<std::fs::File>,
+        quiet: bool,
+    ) {
+        use std::io::{BufRead, Write};
+        for line in std::io::BufReader::new(stderr).lines() {
+            let Ok(line) = line else { break };
+            if let Some(progress) = parse_progress_line(&line) {
+                if let Some(sink) = &self.progress {
+                    sink(progress);
+                }
+                continue;
+            }
+            match log_file.as_mut() {
+                Some(file) => {
+                    let _ = writeln!(file, "{line}");
+                }
+                None if !quiet => eprintln!("{line}"),
+                None => {}
+            }
+        }
+    }
+
     fn parse_exec_commands(value: serde_json::Value) -> anyhow::Result<Vec<ProviderExecCommand>> {
         if let Some(array) = value.as_array() {
             let mut commands = Vec::with_capacity(array.len());
@@ -113,10 +173,16 @@ impl ScriptProvider {
 }
 
 impl EnvironmentProvider for ScriptProvider {
+    fn on_progress(&mut self, sink: ProgressSink) {
+        self.progress = Some(sink);
+    }
+
     fn prepare(
         &self,
         project: &Project,
         env_id: &str,
+        options: &PrepareOptions,
+        cache: &CacheHandle,
         log_path: Option<&Path>,
     ) -> anyhow::Result<serde_json::Value> {
         self.call(
@@ -125,15 +191,41 @@ impl EnvironmentProvider for ScriptProvider {
                 "project_name": project.name,
                 "project_path": project.path,
                 "env_id": env_id,
+                "options": options,
+                "cache_dir": cache.root(),
             }),
             log_path,
             false,
         )
     }
 
+    fn plan(
+        &self,
+        project: &Project,
+        env_id: &str,
+        options: &PrepareOptions,
+    ) -> anyhow::Result<EnvironmentPlan> {
+        let output = self
+            .call(
+                "plan",
+                &json!({
+                    "project_name": project.name,
+                    "project_path": project.path,
+                    "env_id": env_id,
+                    "options": options,
+                }),
+                None,
+                false,
+            )
+            .map_err(|e| anyhow::anyhow!("{e} (plan is optional; does the script handle it?)"))?;
+        serde_json::from_value(output)
+            .map_err(|e| anyhow::anyhow!("{} plan returned an invalid plan: {e}", self.path))
+    }
+
     fn update(
         &self,
         metadata: &serde_json::Value,
+        _options: &PrepareOptions,
         log_path: Option<&Path>,
     ) -> anyhow::Result<serde_json::Value> {
         self.call("update", metadata, log_path, false)
@@ -147,11 +239,44 @@ impl EnvironmentProvider for ScriptProvider {
         self.call("claim", metadata, log_path, false)
     }
 
+    fn adopt(&self, project: &Project, path: &Path) -> anyhow::Result<serde_json::Value> {
+        super::adopt_git_checkout(project, path, &self.env)
+    }
+
+    fn snapshot(
+        &self,
+        metadata: &serde_json::Value,
+        dest: &Path,
+    ) -> anyhow::Result<serde_json::Value> {
+        self.call(
+            "snapshot",
+            &json!({ "metadata": metadata, "dest": dest }),
+            None,
+            false,
+        )
+        .map_err(|e| anyhow::anyhow!("{e} (snapshot is optional; does the script handle it?)"))
+    }
+
+    fn restore(
+        &self,
+        metadata: &serde_json::Value,
+        snapshot: &serde_json::Value,
+    ) -> anyhow::Result<()> {
+        self.call(
+            "restore",
+            &json!({ "metadata": metadata, "snapshot": snapshot }),
+            None,
+            false,
+        )
+        .map_err(|e| anyhow::anyhow!("{e} (restore is optional; does the script handle it?)"))?;
+        Ok(())
+    }
+
     fn remove(&self, metadata: &serde_json::Value, log_path: Option<&Path>) -> anyhow::Result<()> {
         let input = json!({ "metadata": metadata });
         let input_bytes = serde_json::to_vec(&input)?;
 
-        let mut command = Command::new(&self.path);
+        let mut command = self.env.command(&self.path);
         command.arg("remove").stdin(Stdio::piped());
         if let Some(path) = log_path {
             if let Some(parent) = path.parent() {
@@ -171,12 +296,7 @@ impl EnvironmentProvider for ScriptProvider {
 
         let mut child = command.spawn()?;
 
-        use std::io::Write;
-        child
-            .stdin
-            .take()
-            .ok_or_else(|| anyhow::anyhow!("failed to open stdin"))?
-            .write_all(&input_bytes)?;
+        write_input(&mut child, &input_bytes)?;
 
         let status = child.wait()?;
         if !status.success() {
@@ -191,19 +311,25 @@ impl EnvironmentProvider for ScriptProvider {
         metadata: &serde_json::Value,
         command: &str,
         args: &[String],
+        stdin: Option<&str>,
     ) -> anyhow::Result<RunSpec> {
-        let input = json!({
+        // The script's own stdin carries the request, so command input is
+        // forwarded as a field for the script to pipe into the command.
+        let mut input = json!({
             "metadata": metadata,
             "command": command,
             "args": args,
         });
+        if let Some(stdin) = stdin {
+            input["stdin"] = json!(stdin);
+        }
 
         Ok(RunSpec {
             program: self.path.clone(),
             args: vec!["run".to_string()],
             cwd: None,
             stdin_data: Some(serde_json::to_vec(&input)?),
-            env: Vec::new(),
+            env: self.env.vars(),
         })
     }
 
@@ -231,10 +357,174 @@ impl EnvironmentProvider for ScriptProvider {
             args: run_args,
             cwd: None,
             stdin_data: None,
-            env: vec![(
-                "WORK_ENV_METADATA".to_string(),
-                serde_json::to_string(metadata)?,
-            )],
+            env: self
+                .env
+                .vars()
+                .into_iter()
+                .chain([(
+                    "WORK_ENV_METADATA".to_string(),
+                    serde_json::to_string(metadata)?,
+                )])
+                .collect(),
         })
     }
 }
+
+/// Send the request on the script's stdin. A script that exits without
+/// reading it closes the pipe first, which is fine; its exit status and
+/// output are what count.
+fn write_input(child: &mut std::process::Child, input: &[u8]) -> anyhow::Result<()> {
+    use std::io::Write;
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow::anyhow!("failed to open stdin"))?;
+    match stdin.write_all(input) {
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_progress_lines() {
+        assert_eq!(
+            parse_progress_line(r#"work-progress: {"progress": 0.4, "message": "cloning"}"#),
+            Some(Progress {
+                fraction: Some(0.4),
+                message: Some("cloning".to_string()),
+            })
+        );
+        assert_eq!(
+            parse_progress_line(r#"work-progress:{"progress": 3}"#),
+            Some(Progress {
+                fraction: Some(1.0),
+                message: None,
+            })
+        );
+        assert_eq!(parse_progress_line(r#"{"progress": 0.4}"#), None);
+        assert_eq!(parse_progress_line("work-progress: {}"), None);
+        assert_eq!(parse_progress_line("work-progress: cloning"), None);
+    }
+
+    #[test]
+    fn forwards_progress_to_sink() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let script = dir.path().join("provider.sh");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\n\
+             echo 'work-progress: {\"progress\": 0.5, \"message\": \"half\"}' >&2\n\
+             echo 'plain log line' >&2\n\
+             echo '{\"ok\": true}'\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut provider = ScriptProvider {
+            path: script.to_string_lossy().into_owned(),
+            progress: None,
+            env: ProcessEnv::default(),
+        };
+        let sink_seen = seen.clone();
+        provider.on_progress(Box::new(move |p| sink_seen.lock().unwrap().push(p)));
+
+        let log = dir.path().join("env.log");
+        let output = provider
+            .update(&json!({}), &PrepareOptions::default(), Some(&log))
+            .unwrap();
+        assert_eq!(output, json!({"ok": true}));
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![Progress {
+                fraction: Some(0.5),
+                message: Some("half".to_string()),
+            }]
+        );
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "plain log line\n");
+    }
+
+    #[test]
+    fn runs_script_with_configured_env() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let script = dir.path().join("provider.sh");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\n\
+             cat >/dev/null\n\
+             printf '{\"greeting\": \"%s\", \"path\": \"%s\"}' \"$GREETING\" \"$PATH\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+
+        let provider = ScriptProvider {
+            path: script.to_string_lossy().into_owned(),
+            progress: None,
+            env: ProcessEnv {
+                env: [("GREETING".to_string(), "hi".to_string())].into(),
+                path_prepend: vec!["/opt/tools/bin".to_string()],
+            },
+        };
+        let output = provider
+            .update(&json!({}), &PrepareOptions::default(), None)
+            .unwrap();
+        assert_eq!(output["greeting"], "hi");
+        assert!(
+            output["path"]
+                .as_str()
+                .unwrap()
+                .starts_with("/opt/tools/bin:"),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn plan_reads_script_output() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let script = dir.path().join("provider.sh");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\n\
+             input=$(cat)\n\
+             case \"$1\" in\n\
+               plan) echo '{\"steps\": [\"create sandbox\"], \"estimated_disk_bytes\": 2048}' ;;\n\
+               *) exit 1 ;;\n\
+             esac\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+
+        let provider = ScriptProvider {
+            path: script.to_string_lossy().into_owned(),
+            progress: None,
+            env: ProcessEnv::default(),
+        };
+        let project = Project {
+            id: "p1".to_string(),
+            name: "repo".to_string(),
+            path: "/tmp/repo".to_string(),
+            created_at: String::new(),
+            updated_at: String::new(),
+            aliases: Vec::new(),
+        };
+        let plan = provider
+            .plan(&project, "env1", &PrepareOptions::default())
+            .unwrap();
+        assert_eq!(
+            plan,
+            EnvironmentPlan {
+                steps: vec!["create sandbox".to_string()],
+                paths: Vec::new(),
+                branch: None,
+                estimated_disk_bytes: Some(2048),
+            }
+        );
+    }
+}
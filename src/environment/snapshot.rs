@@ -0,0 +1,274 @@
+//! Snapshots for providers whose environments are directories on this
+//! machine. `tar` works anywhere. `clone` copies with `cp -c`, which APFS
+//! turns into copy-on-write clones that take no extra space until files
+//! change.
+
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use crate::config::ProcessEnv;
+use crate::error::{Error, ErrorCode};
+
+use super::git_stdout;
+
+pub enum Method {
+    Tar,
+    Clone,
+}
+
+/// What a provider without snapshots fails with.
+pub fn unsupported() -> anyhow::Error {
+    Error::new(
+        ErrorCode::SnapshotUnsupported,
+        "this environment's provider can't take or restore snapshots",
+    )
+    .into()
+}
+
+/// Bytes the files under `dest` add up to. Clones share space with the
+/// environment, so they may use less.
+pub fn snapshot_size(dest: &Path) -> Option<u64> {
+    super::cache::disk_size(dest).ok()
+}
+
+/// Delete every snapshot of `env_id`, once the environment itself is gone.
+pub fn remove_snapshots(env_id: &str) {
+    if let Ok(dir) = crate::paths::environment_snapshot_dir(env_id)
+        && let Err(e) = std::fs::remove_dir_all(&dir)
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        tracing::warn!(env_id = %env_id, error = %e, "failed to remove environment snapshots");
+    }
+}
+
+pub(super) fn run(command: &mut std::process::Command, what: &str) -> anyhow::Result<()> {
+    let output = command.output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("{what} failed: {stderr}");
+    }
+    Ok(())
+}
+
+/// Save everything in `dir` under `dest`. When `dir` is a git checkout, the
+/// commit it's on is recorded too, so `restore` can move its branch back.
+pub fn take(
+    dir: &Path,
+    dest: &Path,
+    method: Method,
+    env: &ProcessEnv,
+) -> anyhow::Result<serde_json::Value> {
+    if !dir.is_dir() {
+        anyhow::bail!("{} is not a directory", dir.display());
+    }
+    std::fs::create_dir_all(dest)?;
+    let (method, path) = match method {
+        Method::Tar => {
+            let path = dest.join("files.tar");
+            run(
+                env.command("tar")
+                    .arg("-cf")
+                    .arg(&path)
+                    .arg("-C")
+                    .arg(dir)
+                    .arg("."),
+                "tar",
+            )?;
+            ("tar", path)
+        }
+        Method::Clone => {
+            let path = dest.join("files");
+            run(
+                env.command("cp").arg("-cR").arg(dir).arg(&path),
+                "apfs clone copy",
+            )?;
+            ("clone", path)
+        }
+    };
+
+    let mut snapshot = json!({ "method": method, "path": path });
+    // Only a checkout of its own: a plain directory inside some other
+    // repository would otherwise record, and later reset, that one.
+    if dir.join(".git").exists() {
+        snapshot["head"] = json!(git_stdout(dir, &["rev-parse", "HEAD"], env)?);
+    }
+    Ok(snapshot)
+}
+
+/// Replace everything in `dir` with what `take` saved, and reset its
+/// branch to the commit it was on. Changes that were staged come back
+/// unstaged. The files are unpacked next to `dir` and swapped in only once
+/// that works, so a failed restore leaves `dir` as it was.
+pub fn restore(dir: &Path, snapshot: &serde_json::Value, env: &ProcessEnv) -> anyhow::Result<()> {
+    let path = snapshot["path"]
+        .as_str()
+        .map(Path::new)
+        .ok_or_else(|| anyhow::anyhow!("missing path in snapshot"))?;
+    if !path.exists() {
+        anyhow::bail!("snapshot files are missing from {}", path.display());
+    }
+    let method = match snapshot["method"].as_str() {
+        Some("tar") => Method::Tar,
+        Some("clone") => Method::Clone,
+        other => anyhow::bail!("unknown snapshot method {other:?}"),
+    };
+
+    let staging = sibling(dir, "restoring");
+    let replaced = sibling(dir, "replaced");
+    for leftover in [&staging, &replaced] {
+        remove_if_present(leftover)?;
+    }
+    if let Err(e) = unpack(path, &staging, method, env) {
+        remove_if_present(&staging).ok();
+        return Err(e);
+    }
+
+    std::fs::rename(dir, &replaced)?;
+    if let Err(e) = std::fs::rename(&staging, dir) {
+        std::fs::rename(&replaced, dir)?;
+        remove_if_present(&staging).ok();
+        return Err(e.into());
+    }
+    if let Some(head) = snapshot["head"].as_str()
+        && let Err(e) = git_stdout(dir, &["reset", "-q", head], env)
+    {
+        std::fs::rename(dir, &staging)?;
+        std::fs::rename(&replaced, dir)?;
+        remove_if_present(&staging).ok();
+        return Err(e);
+    }
+    if let Err(e) = std::fs::remove_dir_all(&replaced) {
+        tracing::warn!(path = %replaced.display(), error = %e, "failed to remove replaced environment files");
+    }
+    Ok(())
+}
+
+/// `dir` with `suffix` added to its name, on the same filesystem so it can
+/// be renamed into place.
+fn sibling(dir: &Path, suffix: &str) -> PathBuf {
+    let mut name = dir.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{suffix}"));
+    dir.with_file_name(name)
+}
+
+fn remove_if_present(dir: &Path) -> std::io::Result<()> {
+    match std::fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Unpack the snapshot at `path` into `staging`, which must not exist yet.
+fn unpack(path: &Path, staging: &Path, method: Method, env: &ProcessEnv) -> anyhow::Result<()> {
+    match method {
+        Method::Tar => {
+            std::fs::create_dir(staging)?;
+            run(
+                env.command("tar")
+                    .arg("-xf")
+                    .arg(path)
+                    .arg("-C")
+                    .arg(staging),
+                "tar",
+            )
+        }
+        Method::Clone => run(
+            env.command("cp").arg("-cR").arg(path).arg(staging),
+            "apfs clone copy",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git(cwd: &Path, args: &[&str]) -> String {
+        let output = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(cwd)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed");
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    #[test]
+    fn restores_files_and_branch_of_a_worktree() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let project = dir.path().join("project");
+        std::fs::create_dir_all(&project).unwrap();
+        std::fs::write(project.join("lib.rs"), "v1").unwrap();
+        git(&project, &["init", "-q", "-b", "main"]);
+        git(&project, &["add", "."]);
+        git(&project, &["commit", "-q", "-m", "init"]);
+        git(
+            &project,
+            &["worktree", "add", "-q", "-b", "work-env-1", "../worktree"],
+        );
+        let worktree = dir.path().join("worktree");
+        std::fs::write(worktree.join("notes.txt"), "untracked").unwrap();
+        let before = git(&worktree, &["rev-parse", "HEAD"]);
+
+        let env = ProcessEnv::default();
+        let dest = dir.path().join("snapshots/1");
+        let snapshot = take(&worktree, &dest, Method::Tar, &env).unwrap();
+        assert_eq!(snapshot["method"], "tar");
+        assert_eq!(snapshot["head"], before.as_str());
+        assert!(snapshot_size(&dest).unwrap() > 0);
+
+        std::fs::write(worktree.join("lib.rs"), "v2").unwrap();
+        git(&worktree, &["commit", "-q", "-am", "agent"]);
+        std::fs::write(worktree.join("scratch.txt"), "left behind").unwrap();
+        std::fs::remove_file(worktree.join("notes.txt")).unwrap();
+
+        restore(&worktree, &snapshot, &env).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(worktree.join("lib.rs")).unwrap(),
+            "v1"
+        );
+        assert!(worktree.join("notes.txt").exists());
+        assert!(!worktree.join("scratch.txt").exists());
+        assert_eq!(git(&worktree, &["rev-parse", "HEAD"]), before);
+        assert_eq!(git(&worktree, &["status", "--porcelain"]), "?? notes.txt");
+    }
+
+    #[test]
+    fn providers_without_snapshots_say_so() {
+        use super::super::EnvironmentProvider;
+
+        let provider = super::super::inplace::InPlaceProvider;
+        let err = provider
+            .snapshot(&json!({ "project_path": "/tmp" }), Path::new("/tmp/unused"))
+            .unwrap_err();
+        assert_eq!(Error::code_of(&err), ErrorCode::SnapshotUnsupported);
+    }
+
+    #[test]
+    fn refuses_a_snapshot_whose_files_are_gone() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let snapshot = json!({ "method": "tar", "path": dir.path().join("missing.tar") });
+        let err = restore(dir.path(), &snapshot, &ProcessEnv::default()).unwrap_err();
+        assert!(err.to_string().contains("missing"), "{err}");
+    }
+
+    #[test]
+    fn a_failed_restore_leaves_the_directory_alone() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let env_dir = dir.path().join("env");
+        std::fs::create_dir_all(&env_dir).unwrap();
+        std::fs::write(env_dir.join("work.txt"), "in progress").unwrap();
+        let corrupt = dir.path().join("files.tar");
+        std::fs::write(&corrupt, "not a tar file").unwrap();
+
+        let snapshot = json!({ "method": "tar", "path": corrupt });
+        restore(&env_dir, &snapshot, &ProcessEnv::default()).unwrap_err();
+        assert_eq!(
+            std::fs::read_to_string(env_dir.join("work.txt")).unwrap(),
+            "in progress"
+        );
+        assert!(!sibling(&env_dir, "restoring").exists());
+    }
+}
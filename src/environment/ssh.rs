@@ -0,0 +1,622 @@
+use std::path::Path;
+use std::process::Stdio;
+
+use serde_json::json;
+
+use crate::config::{DEFAULT_SSH_PATH, PrepareOptions, ProcessEnv, SshRemote};
+use crate::db::Project;
+
+use super::{
+    CacheHandle, EnvironmentPlan, EnvironmentProvider, ProviderExecCommand, RunSpec, shell_quote,
+};
+
+/// Clones projects on another machine and runs tasks there over `ssh`.
+/// Nothing is checked out locally, so the environment's metadata has a
+/// `remote_path` on `host` instead of a `worktree_path`.
+pub struct SshProvider {
+    pub remote: SshRemote,
+    /// Variables for the `ssh` and `git` commands it runs locally.
+    pub env: ProcessEnv,
+}
+
+impl SshProvider {
+    fn host(&self) -> anyhow::Result<&str> {
+        self.remote
+            .host
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("ssh providers need a host"))
+    }
+
+    /// Where `env_id`'s checkout goes on the remote, as configured: a
+    /// leading `~/` is left for the remote to expand.
+    fn remote_path(&self, project: &Project, env_id: &str) -> String {
+        self.remote
+            .path
+            .as_deref()
+            .unwrap_or(DEFAULT_SSH_PATH)
+            .replace("{project}", &project.name)
+            .replace("{env_id}", env_id)
+    }
+
+    /// The URL the remote clones from.
+    fn repository(&self, project: &Project) -> anyhow::Result<String> {
+        if let Some(repository) = &self.remote.repository {
+            return Ok(repository.clone());
+        }
+        super::git_stdout(
+            Path::new(&project.path),
+            &["remote", "get-url", "origin"],
+            &self.env,
+        )
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "project {} has no origin remote; set repository for the ssh provider",
+                project.name
+            )
+        })
+    }
+
+    /// What the new branch starts from, in the remote clone. A requested ref
+    /// is resolved locally, since the clone only has `origin/*` branches.
+    fn start_point(&self, project_path: &Path, options: &PrepareOptions) -> anyhow::Result<String> {
+        match (&options.source_ref, &options.base_branch) {
+            (Some(source_ref), _) => super::resolve_git_ref(project_path, source_ref, &self.env),
+            (None, Some(base_branch)) => Ok(format!("origin/{base_branch}")),
+            (None, None) => Ok("HEAD".to_string()),
+        }
+    }
+
+    /// `ssh` with the configured options, up to and including the host.
+    fn ssh_args(&self, tty: bool) -> anyhow::Result<Vec<String>> {
+        let mut args = self.remote.ssh_args.clone();
+        if tty {
+            args.push("-t".to_string());
+        }
+        args.push(self.host()?.to_string());
+        Ok(args)
+    }
+
+    /// Run `script` in the remote shell. Its output goes to `log_path`, or
+    /// into the error if it fails and there's no log.
+    fn ssh(&self, script: &str, log_path: Option<&Path>) -> anyhow::Result<()> {
+        let mut command = self.env.command("ssh");
+        command
+            .args(self.ssh_args(false)?)
+            .arg(script)
+            .stdin(Stdio::null());
+        if let Some(path) = log_path {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let log = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            command.stdout(log.try_clone()?).stderr(log);
+        }
+        let output = command.output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("ssh {} failed: {}", self.host()?, stderr.trim());
+        }
+        Ok(())
+    }
+
+    fn remote_spec(
+        &self,
+        remote_path: &str,
+        command: &str,
+        stdin: Option<&str>,
+    ) -> anyhow::Result<RunSpec> {
+        let mut args = self.ssh_args(true)?;
+        args.push(format!("cd {} && {command}", remote_word(remote_path)));
+        Ok(RunSpec {
+            program: "ssh".to_string(),
+            args,
+            cwd: None,
+            stdin_data: stdin.map(|s| s.as_bytes().to_vec()),
+            env: self.env.vars(),
+        })
+    }
+}
+
+/// Quote `path` for the remote shell, leaving a leading `~/` for it to
+/// expand to the remote user's home directory.
+fn remote_word(path: &str) -> String {
+    match path.strip_prefix("~/") {
+        Some(rest) => format!("\"$HOME\"/{}", shell_quote(rest)),
+        None => shell_quote(path),
+    }
+}
+
+/// The `git clone` that starts a checkout. A base branch is cloned by name:
+/// `--depth` clones a single branch, which would otherwise be the remote's
+/// default and leave `origin/<base>` missing.
+fn clone_command(options: &PrepareOptions) -> String {
+    let mut clone = "git clone --quiet --no-checkout".to_string();
+    if let Some(base_branch) = &options.base_branch {
+        clone.push_str(&format!(" --branch {}", shell_word(base_branch)));
+    }
+    if let Some(depth) = options.depth {
+        clone.push_str(&format!(" --depth {depth}"));
+    }
+    clone
+}
+
+/// The `git fetch` that brings `commit` into the clone when it isn't there.
+fn fetch_command(options: &PrepareOptions, commit: &str) -> String {
+    let mut fetch = "git fetch --quiet".to_string();
+    if let Some(depth) = options.depth {
+        fetch.push_str(&format!(" --depth {depth}"));
+    }
+    format!("{fetch} origin {}", shell_word(commit))
+}
+
+/// `word` for a shell: bare when the shell would read it back the same, as
+/// plans show it, and quoted otherwise.
+fn shell_word(word: &str) -> String {
+    let bare = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "@%+=:,./-_".contains(c));
+    if bare {
+        word.to_string()
+    } else {
+        shell_quote(word)
+    }
+}
+
+/// `command` and `args` as one line for the remote shell.
+fn remote_command(command: &str, args: &[String]) -> String {
+    std::iter::once(command)
+        .chain(args.iter().map(String::as_str))
+        .map(shell_quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn metadata_field<'a>(metadata: &'a serde_json::Value, field: &str) -> anyhow::Result<&'a str> {
+    metadata[field]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("missing {field} in metadata"))
+}
+
+impl EnvironmentProvider for SshProvider {
+    fn prepare(
+        &self,
+        project: &Project,
+        env_id: &str,
+        options: &PrepareOptions,
+        _cache: &CacheHandle,
+        log_path: Option<&Path>,
+    ) -> anyhow::Result<serde_json::Value> {
+        let host = self.host()?;
+        let remote_path = self.remote_path(project, env_id);
+        let path = remote_word(&remote_path);
+        let branch = format!("work-env-{env_id}");
+        let repository = self.repository(project)?;
+        let start_point = self.start_point(Path::new(&project.path), options)?;
+
+        let clone = clone_command(options);
+        // The trap removes a half-made checkout, but only once this script
+        // has made sure it isn't deleting something that was already there.
+        let mut script = vec![
+            "set -e".to_string(),
+            format!(
+                "if [ -e {path} ]; then echo {} >&2; exit 1; fi",
+                shell_quote(&format!("{remote_path} already exists"))
+            ),
+            format!("mkdir -p {path}"),
+            format!("trap 'rm -rf {}' EXIT", path.replace('\'', r"'\''")),
+            format!("{clone} {} {path}", shell_quote(&repository)),
+            format!("cd {path}"),
+        ];
+        if !options.sparse_paths.is_empty() {
+            script.push(format!(
+                "git sparse-checkout set --cone {}",
+                options
+                    .sparse_paths
+                    .iter()
+                    .map(|p| shell_quote(p))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ));
+        }
+        // A requested ref is a commit from the local checkout, which the
+        // clone only has if it was pushed, and a shallow clone may not have
+        // even then.
+        if let Some(source_ref) = &options.source_ref {
+            let commit = shell_quote(&start_point);
+            script.push(format!(
+                "git cat-file -e {commit}^{{commit}} 2>/dev/null || {} || {{ echo {} >&2; exit 1; }}",
+                fetch_command(options, &start_point),
+                shell_quote(&format!(
+                    "{source_ref} ({start_point}) isn't on {repository}; push it first"
+                ))
+            ));
+        }
+        script.push(format!(
+            "git checkout --quiet -b {} {}",
+            shell_quote(&branch),
+            shell_quote(&start_point)
+        ));
+        if let Some(setup) = &self.remote.setup {
+            script.push(format!("(\n{setup}\n)"));
+        }
+        script.push("trap - EXIT".to_string());
+        self.ssh(&script.join("\n"), log_path)?;
+
+        let mut metadata = json!({
+            "project_path": project.path,
+            "host": host,
+            "remote_path": remote_path,
+            "branch": branch,
+        });
+        if let Some(base_branch) = &options.base_branch {
+            metadata["base_branch"] = json!(base_branch);
+        }
+        if let Some(source_ref) = &options.source_ref {
+            metadata["source_ref"] = json!(source_ref);
+            metadata["source_commit"] = json!(start_point);
+        }
+        if !options.sparse_paths.is_empty() {
+            metadata["sparse_paths"] = json!(options.sparse_paths);
+        }
+        Ok(metadata)
+    }
+
+    fn plan(
+        &self,
+        project: &Project,
+        env_id: &str,
+        options: &PrepareOptions,
+    ) -> anyhow::Result<EnvironmentPlan> {
+        let host = self.host()?;
+        let remote_path = self.remote_path(project, env_id);
+        let branch = format!("work-env-{env_id}");
+        let repository = self
+            .repository(project)
+            .unwrap_or_else(|_| "<origin>".to_string());
+        let start_point = match (&options.source_ref, &options.base_branch) {
+            (Some(source_ref), _) => {
+                super::resolve_local_git_ref(Path::new(&project.path), source_ref, &self.env)
+                    .unwrap_or_else(|| source_ref.clone())
+            }
+            (None, Some(base_branch)) => format!("origin/{base_branch}"),
+            (None, None) => "HEAD".to_string(),
+        };
+
+        // Each step is an ssh command that could be pasted into a shell.
+        let ssh = |remote: String| format!("ssh {} {}", shell_word(host), shell_word(&remote));
+        let path = remote_word(&remote_path);
+        let mut steps = vec![ssh(format!(
+            "{} {} {path}",
+            clone_command(options),
+            shell_word(&repository)
+        ))];
+        if !options.sparse_paths.is_empty() {
+            steps.push(ssh(format!(
+                "cd {path} && git sparse-checkout set --cone {}",
+                options
+                    .sparse_paths
+                    .iter()
+                    .map(|p| shell_word(p))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            )));
+        }
+        if options.source_ref.is_some() {
+            steps.push(ssh(format!(
+                "cd {path} && {}",
+                fetch_command(options, &start_point)
+            )));
+        }
+        steps.push(ssh(format!(
+            "cd {path} && git checkout -b {} {}",
+            shell_word(&branch),
+            shell_word(&start_point)
+        )));
+        if let Some(setup) = &self.remote.setup {
+            steps.push(ssh(format!("cd {path} && {setup}")));
+        }
+
+        Ok(EnvironmentPlan {
+            steps,
+            paths: vec![format!("{host}:{remote_path}")],
+            branch: Some(branch),
+            estimated_disk_bytes: None,
+        })
+    }
+
+    fn update(
+        &self,
+        metadata: &serde_json::Value,
+        _options: &PrepareOptions,
+        log_path: Option<&Path>,
+    ) -> anyhow::Result<serde_json::Value> {
+        let path = remote_word(metadata_field(metadata, "remote_path")?);
+        let upstream = metadata["base_branch"]
+            .as_str()
+            .map_or_else(|| "origin/HEAD".to_string(), |b| format!("origin/{b}"));
+        self.ssh(
+            &format!(
+                "cd {path} && git fetch --quiet origin && git merge --quiet {}",
+                shell_quote(&upstream)
+            ),
+            log_path,
+        )?;
+        Ok(metadata.clone())
+    }
+
+    fn claim(
+        &self,
+        metadata: &serde_json::Value,
+        _log_path: Option<&Path>,
+    ) -> anyhow::Result<serde_json::Value> {
+        Ok(metadata.clone())
+    }
+
+    fn remove(&self, metadata: &serde_json::Value, log_path: Option<&Path>) -> anyhow::Result<()> {
+        let path = remote_word(metadata_field(metadata, "remote_path")?);
+        self.ssh(&format!("rm -rf {path}"), log_path)
+    }
+
+    fn adopt(&self, _project: &Project, path: &Path) -> anyhow::Result<serde_json::Value> {
+        anyhow::bail!(
+            "ssh environments live on {}; {} is a local checkout",
+            self.host()?,
+            path.display()
+        )
+    }
+
+    fn run(
+        &self,
+        metadata: &serde_json::Value,
+        command: &str,
+        args: &[String],
+        stdin: Option<&str>,
+    ) -> anyhow::Result<RunSpec> {
+        self.remote_spec(
+            metadata_field(metadata, "remote_path")?,
+            &format!("exec {}", remote_command(command, args)),
+            stdin,
+        )
+    }
+
+    fn exec_commands(
+        &self,
+        _metadata: &serde_json::Value,
+    ) -> anyhow::Result<Vec<ProviderExecCommand>> {
+        Ok(vec![ProviderExecCommand {
+            name: "cd".to_string(),
+            help: Some("Open a shell in the environment on the remote host".to_string()),
+        }])
+    }
+
+    fn exec(
+        &self,
+        metadata: &serde_json::Value,
+        command: &str,
+        args: &[String],
+    ) -> anyhow::Result<RunSpec> {
+        let remote_path = metadata_field(metadata, "remote_path")?;
+        if command == "cd" {
+            return self.remote_spec(remote_path, "exec \"${SHELL:-sh}\" -l", None);
+        }
+        self.remote_spec(
+            remote_path,
+            &format!("exec {}", remote_command(command, args)),
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    fn git(cwd: &Path, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(cwd)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    }
+
+    fn project(dir: &Path) -> Project {
+        let path = dir.join("project");
+        std::fs::create_dir_all(path.join("src")).unwrap();
+        std::fs::write(path.join("src/lib.rs"), "pub fn f() {}").unwrap();
+        git(&path, &["init", "-q", "-b", "main"]);
+        git(&path, &["add", "."]);
+        git(&path, &["commit", "-q", "-m", "init"]);
+        Project {
+            id: "p".to_string(),
+            name: "project".to_string(),
+            path: path.to_string_lossy().into_owned(),
+            created_at: String::new(),
+            updated_at: String::new(),
+            aliases: Vec::new(),
+        }
+    }
+
+    /// A provider whose `ssh` skips its options and host and runs the
+    /// command here, so the "remote" is a directory under `dir`.
+    fn provider(dir: &Path, project: &Project) -> SshProvider {
+        let bin = dir.join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        let ssh = bin.join("ssh");
+        std::fs::write(
+            &ssh,
+            "#!/bin/sh\nwhile [ \"${1#-}\" != \"$1\" ]; do shift; done\nshift\nexec sh -c \"$*\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+        SshProvider {
+            remote: SshRemote {
+                host: Some("devbox".to_string()),
+                path: Some(format!("{}/remote/{{project}}/{{env_id}}", dir.display())),
+                setup: Some("touch .setup-done".to_string()),
+                repository: Some(project.path.clone()),
+                ssh_args: Vec::new(),
+            },
+            env: ProcessEnv {
+                path_prepend: vec![bin.to_string_lossy().into_owned()],
+                ..ProcessEnv::default()
+            },
+        }
+    }
+
+    #[test]
+    fn prepares_a_remote_clone_and_removes_it() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let project = project(dir.path());
+        let provider = provider(dir.path(), &project);
+        let cache = CacheHandle::new(dir.path().join("cache"));
+        let options = PrepareOptions {
+            base_branch: Some("main".to_string()),
+            ..PrepareOptions::default()
+        };
+
+        let metadata = provider
+            .prepare(&project, "env1", &options, &cache, None)
+            .unwrap();
+        let remote = dir.path().join("remote/project/env1");
+        assert_eq!(metadata["host"], "devbox");
+        assert_eq!(metadata["remote_path"], json!(remote));
+        assert_eq!(metadata["branch"], "work-env-env1");
+        assert!(metadata.get("worktree_path").is_none());
+        assert!(remote.join("src/lib.rs").exists());
+        assert!(remote.join(".setup-done").exists());
+
+        // A second prepare into the same path must not clean up the first.
+        provider
+            .prepare(&project, "env1", &options, &cache, None)
+            .unwrap_err();
+        assert!(remote.join("src/lib.rs").exists());
+
+        provider.remove(&metadata, None).unwrap();
+        assert!(!remote.exists());
+    }
+
+    #[test]
+    fn failed_setup_removes_the_checkout() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let project = project(dir.path());
+        let mut provider = provider(dir.path(), &project);
+        provider.remote.setup = Some("echo no deps >&2; false".to_string());
+        let cache = CacheHandle::new(dir.path().join("cache"));
+
+        let err = provider
+            .prepare(&project, "env1", &PrepareOptions::default(), &cache, None)
+            .unwrap_err();
+        assert!(err.to_string().contains("no deps"), "{err}");
+        assert!(!dir.path().join("remote/project/env1").exists());
+    }
+
+    #[test]
+    fn shallow_clones_check_out_a_base_branch_and_refuse_unpushed_commits() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let project = project(dir.path());
+        let path = Path::new(&project.path);
+        git(path, &["checkout", "-q", "-b", "dev"]);
+        std::fs::write(path.join("dev.txt"), "dev").unwrap();
+        git(path, &["add", "."]);
+        git(path, &["commit", "-q", "-m", "dev"]);
+        git(path, &["checkout", "-q", "main"]);
+        let mut provider = provider(dir.path(), &project);
+        // Local clones ignore --depth unless they go through file://.
+        provider.remote.repository = Some(format!("file://{}", project.path));
+        let cache = CacheHandle::new(dir.path().join("cache"));
+
+        let options = PrepareOptions {
+            base_branch: Some("dev".to_string()),
+            depth: Some(1),
+            ..PrepareOptions::default()
+        };
+        let metadata = provider
+            .prepare(&project, "env1", &options, &cache, None)
+            .unwrap();
+        assert!(dir.path().join("remote/project/env1/dev.txt").exists());
+
+        // The first clone stands in for a remote the next commit was never
+        // pushed to.
+        let pushed = metadata["remote_path"].as_str().unwrap();
+        provider.remote.repository = Some(format!("file://{pushed}"));
+        std::fs::write(path.join("local.txt"), "unpushed").unwrap();
+        git(path, &["add", "."]);
+        git(path, &["commit", "-q", "-m", "local"]);
+        let options = PrepareOptions {
+            source_ref: Some("main".to_string()),
+            ..PrepareOptions::default()
+        };
+        let err = provider
+            .prepare(&project, "env2", &options, &cache, None)
+            .unwrap_err();
+        assert!(err.to_string().contains("push it first"), "{err}");
+        assert!(!dir.path().join("remote/project/env2").exists());
+    }
+
+    #[test]
+    fn plan_steps_can_be_pasted_into_a_shell() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let project = project(dir.path());
+        let provider = SshProvider {
+            remote: SshRemote {
+                host: Some("me@devbox".to_string()),
+                path: Some("~/envs/{env_id}".to_string()),
+                repository: Some("git@example.com:me/app.git".to_string()),
+                ..SshRemote::default()
+            },
+            env: ProcessEnv::default(),
+        };
+        let options = PrepareOptions {
+            sparse_paths: vec!["docs and notes".to_string()],
+            ..PrepareOptions::default()
+        };
+
+        let plan = provider.plan(&project, "env1", &options).unwrap();
+        assert_eq!(
+            plan.steps[0],
+            r#"ssh me@devbox 'git clone --quiet --no-checkout git@example.com:me/app.git "$HOME"/'\''envs/env1'\'''"#
+        );
+        assert_eq!(
+            plan.steps[1],
+            r#"ssh me@devbox 'cd "$HOME"/'\''envs/env1'\'' && git sparse-checkout set --cone '\''docs and notes'\'''"#
+        );
+    }
+
+    #[test]
+    fn run_wraps_the_command_in_ssh() {
+        let provider = SshProvider {
+            remote: SshRemote {
+                host: Some("me@devbox".to_string()),
+                ssh_args: vec!["-p".to_string(), "2222".to_string()],
+                ..SshRemote::default()
+            },
+            env: ProcessEnv::default(),
+        };
+        let metadata = json!({ "remote_path": "~/envs/it's" });
+
+        let spec = provider
+            .run(&metadata, "cargo", &["test".to_string()], Some("input"))
+            .unwrap();
+        assert_eq!(spec.program, "ssh");
+        assert_eq!(
+            spec.args,
+            [
+                "-p",
+                "2222",
+                "-t",
+                "me@devbox",
+                r#"cd "$HOME"/'envs/it'\''s' && exec 'cargo' 'test'"#
+            ]
+        );
+        assert_eq!(spec.stdin_data.as_deref(), Some(&b"input"[..]));
+    }
+}
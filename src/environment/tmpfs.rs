@@ -0,0 +1,402 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use crate::config::{PrepareOptions, ProcessEnv};
+use crate::db::Project;
+
+use super::{CacheHandle, EnvironmentPlan, EnvironmentProvider, ProviderExecCommand, RunSpec};
+
+/// Where environments go when `root` isn't configured, on systems that have it.
+const SHARED_MEMORY_DIR: &str = "/dev/shm";
+
+/// Clones projects into a RAM-backed directory. Environments are fast to
+/// create and remove, don't survive a reboot, and are removed when the daemon
+/// stops, so they suit short tasks like linting or running tests.
+#[derive(Default)]
+pub struct TmpfsProvider {
+    /// Variables for the `git` commands it runs.
+    pub env: ProcessEnv,
+    /// Directory to create environments in. Defaults to a directory under
+    /// `/dev/shm`.
+    pub root: Option<PathBuf>,
+    /// Most bytes all of the provider's environments may hold together.
+    pub max_bytes: Option<u64>,
+}
+
+impl TmpfsProvider {
+    fn root(&self) -> anyhow::Result<PathBuf> {
+        if let Some(root) = &self.root {
+            return Ok(root.clone());
+        }
+        let shm = Path::new(SHARED_MEMORY_DIR);
+        if !shm.is_dir() {
+            anyhow::bail!(
+                "{SHARED_MEMORY_DIR} does not exist; set root under [environments.providers.tmpfs] to a RAM-backed directory"
+            );
+        }
+        // SAFETY: getuid has no preconditions and cannot fail.
+        let uid = unsafe { libc::getuid() };
+        Ok(shm.join(format!("work-{uid}")))
+    }
+
+    /// Fail if `needed` more bytes would take the provider past `max_bytes`.
+    fn check_capacity(&self, root: &Path, needed: u64) -> anyhow::Result<()> {
+        let Some(max_bytes) = self.max_bytes else {
+            return Ok(());
+        };
+        let used = super::cache::disk_size(root)?;
+        if used + needed > max_bytes {
+            anyhow::bail!(
+                "tmpfs environments would use {} MB, over max-size-mb {}; remove some or raise the limit",
+                (used + needed).div_ceil(1024 * 1024),
+                max_bytes / (1024 * 1024)
+            );
+        }
+        Ok(())
+    }
+
+    /// The commit a new environment starts from, as resolved in the project.
+    fn start_point(&self, project_path: &Path, options: &PrepareOptions) -> anyhow::Result<String> {
+        match (&options.source_ref, &options.base_branch) {
+            (Some(source_ref), _) => super::resolve_git_ref(project_path, source_ref, &self.env),
+            (None, Some(base_branch)) => Ok(base_branch.clone()),
+            (None, None) => Ok("HEAD".to_string()),
+        }
+    }
+
+    fn git(&self, dir: &Path, args: &[&str]) -> anyhow::Result<()> {
+        let output = self
+            .env
+            .command("git")
+            .args(args)
+            .current_dir(dir)
+            .output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("git {} failed: {stderr}", args[0]);
+        }
+        Ok(())
+    }
+}
+
+fn metadata_path<'a>(metadata: &'a serde_json::Value, field: &str) -> anyhow::Result<&'a str> {
+    metadata[field]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("missing {field} in metadata"))
+}
+
+impl EnvironmentProvider for TmpfsProvider {
+    fn prepare(
+        &self,
+        project: &Project,
+        env_id: &str,
+        options: &PrepareOptions,
+        cache: &CacheHandle,
+        _log_path: Option<&Path>,
+    ) -> anyhow::Result<serde_json::Value> {
+        let project_path = Path::new(&project.path);
+        let root = self.root()?;
+        let worktree_path = root.join(env_id);
+        let branch = format!("work-env-{env_id}");
+        let start_point = self.start_point(project_path, options)?;
+
+        std::fs::create_dir_all(&root)?;
+        let needed =
+            super::git_tree_size(project_path, &start_point, &options.sparse_paths, &self.env)?;
+        self.check_capacity(&root, needed)?;
+
+        // A shared clone borrows the project's objects, so only the checkout
+        // itself takes up memory.
+        let worktree_arg = worktree_path.to_string_lossy().into_owned();
+        self.git(
+            project_path,
+            &[
+                "clone",
+                "--quiet",
+                "--shared",
+                "--no-checkout",
+                &project.path,
+                &worktree_arg,
+            ],
+        )?;
+        let result = (|| {
+            if !options.sparse_paths.is_empty() {
+                let mut args = vec!["sparse-checkout", "set", "--cone"];
+                args.extend(options.sparse_paths.iter().map(String::as_str));
+                self.git(&worktree_path, &args)?;
+            }
+            // Resolve in the project: its local branches are only
+            // `origin/*` in the clone.
+            let commit = super::git_stdout(
+                project_path,
+                &[
+                    "rev-parse",
+                    "--verify",
+                    &format!("{start_point}^{{commit}}"),
+                ],
+                &self.env,
+            )?;
+            self.git(
+                &worktree_path,
+                &["checkout", "--quiet", "-b", &branch, &commit],
+            )?;
+            cache.fill_cached_paths(&worktree_path, &options.cached_paths, &self.env)
+        })();
+        if let Err(e) = result {
+            let _ = std::fs::remove_dir_all(&worktree_path);
+            return Err(e);
+        }
+
+        let mut metadata = json!({
+            "project_path": project.path,
+            "worktree_path": worktree_arg,
+            "branch": branch,
+        });
+        if let Some(base_branch) = &options.base_branch {
+            metadata["base_branch"] = json!(base_branch);
+        }
+        if let Some(source_ref) = &options.source_ref {
+            metadata["source_ref"] = json!(source_ref);
+            metadata["source_commit"] = json!(start_point);
+        }
+        if !options.sparse_paths.is_empty() {
+            metadata["sparse_paths"] = json!(options.sparse_paths);
+        }
+        Ok(metadata)
+    }
+
+    fn plan(
+        &self,
+        project: &Project,
+        env_id: &str,
+        options: &PrepareOptions,
+    ) -> anyhow::Result<EnvironmentPlan> {
+        let project_path = Path::new(&project.path);
+        let worktree_path = self.root()?.join(env_id);
+        let branch = format!("work-env-{env_id}");
+        let start_point = match &options.source_ref {
+            Some(source_ref) => super::resolve_local_git_ref(project_path, source_ref, &self.env)
+                .unwrap_or_else(|| source_ref.clone()),
+            None => self.start_point(project_path, options)?,
+        };
+
+        let mut steps = vec![format!(
+            "git clone --shared --no-checkout {} {}",
+            project.path,
+            worktree_path.display()
+        )];
+        if !options.sparse_paths.is_empty() {
+            steps.push(format!(
+                "git sparse-checkout set --cone {}",
+                options.sparse_paths.join(" ")
+            ));
+        }
+        steps.push(format!("git checkout -b {branch} {start_point}"));
+        steps.extend(super::cache::cached_path_steps(&options.cached_paths));
+
+        Ok(EnvironmentPlan {
+            steps,
+            paths: vec![worktree_path.to_string_lossy().into_owned()],
+            branch: Some(branch),
+            estimated_disk_bytes: super::git_tree_size(
+                project_path,
+                &start_point,
+                &options.sparse_paths,
+                &self.env,
+            )
+            .ok(),
+        })
+    }
+
+    fn update(
+        &self,
+        metadata: &serde_json::Value,
+        _options: &PrepareOptions,
+        _log_path: Option<&Path>,
+    ) -> anyhow::Result<serde_json::Value> {
+        let worktree_path = Path::new(metadata_path(metadata, "worktree_path")?);
+        self.git(worktree_path, &["fetch", "--quiet", "origin"])?;
+        let upstream = metadata["base_branch"]
+            .as_str()
+            .map_or_else(|| "origin/HEAD".to_string(), |b| format!("origin/{b}"));
+        self.git(worktree_path, &["merge", "--quiet", &upstream])?;
+        Ok(metadata.clone())
+    }
+
+    fn claim(
+        &self,
+        metadata: &serde_json::Value,
+        _log_path: Option<&Path>,
+    ) -> anyhow::Result<serde_json::Value> {
+        Ok(metadata.clone())
+    }
+
+    fn claim_conflict(&self, metadata: &serde_json::Value) -> anyhow::Result<Option<String>> {
+        super::worktree_conflict(metadata, &self.env)
+    }
+
+    fn snapshot(
+        &self,
+        metadata: &serde_json::Value,
+        dest: &Path,
+    ) -> anyhow::Result<serde_json::Value> {
+        let worktree_path = metadata_path(metadata, "worktree_path")?;
+        super::snapshot::take(
+            Path::new(worktree_path),
+            dest,
+            super::snapshot::Method::Tar,
+            &self.env,
+        )
+    }
+
+    fn restore(
+        &self,
+        metadata: &serde_json::Value,
+        snapshot: &serde_json::Value,
+    ) -> anyhow::Result<()> {
+        let worktree_path = metadata_path(metadata, "worktree_path")?;
+        super::snapshot::restore(Path::new(worktree_path), snapshot, &self.env)
+    }
+
+    fn run(
+        &self,
+        metadata: &serde_json::Value,
+        command: &str,
+        args: &[String],
+        stdin: Option<&str>,
+    ) -> anyhow::Result<RunSpec> {
+        Ok(RunSpec {
+            program: command.to_string(),
+            args: args.to_vec(),
+            cwd: Some(PathBuf::from(metadata_path(metadata, "worktree_path")?)),
+            stdin_data: stdin.map(|s| s.as_bytes().to_vec()),
+            env: Vec::new(),
+        })
+    }
+
+    fn exec(
+        &self,
+        metadata: &serde_json::Value,
+        command: &str,
+        args: &[String],
+    ) -> anyhow::Result<RunSpec> {
+        let cwd = Some(PathBuf::from(metadata_path(metadata, "worktree_path")?));
+        if command == "cd" {
+            let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
+            return Ok(RunSpec {
+                program: shell,
+                args: Vec::new(),
+                cwd,
+                stdin_data: None,
+                env: Vec::new(),
+            });
+        }
+
+        Ok(RunSpec {
+            program: command.to_string(),
+            args: args.to_vec(),
+            cwd,
+            stdin_data: None,
+            env: Vec::new(),
+        })
+    }
+
+    fn exec_commands(
+        &self,
+        _metadata: &serde_json::Value,
+    ) -> anyhow::Result<Vec<ProviderExecCommand>> {
+        Ok(vec![ProviderExecCommand {
+            name: "cd".to_string(),
+            help: Some("Open a shell in the environment directory".to_string()),
+        }])
+    }
+
+    fn remove(&self, metadata: &serde_json::Value, _log_path: Option<&Path>) -> anyhow::Result<()> {
+        let worktree_path = metadata_path(metadata, "worktree_path")?;
+        match std::fs::remove_dir_all(worktree_path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(anyhow::anyhow!("failed to remove {worktree_path}: {e}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git(cwd: &Path, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(cwd)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    }
+
+    fn project(dir: &Path) -> Project {
+        let path = dir.join("project");
+        std::fs::create_dir_all(path.join("src")).unwrap();
+        std::fs::write(path.join("src/lib.rs"), "pub fn f() {}").unwrap();
+        git(&path, &["init", "-q", "-b", "main"]);
+        git(&path, &["add", "."]);
+        git(&path, &["commit", "-q", "-m", "init"]);
+        Project {
+            id: "p".to_string(),
+            name: "project".to_string(),
+            path: path.to_string_lossy().into_owned(),
+            created_at: String::new(),
+            updated_at: String::new(),
+            aliases: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn prepares_a_shared_clone_and_removes_it() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let project = project(dir.path());
+        let provider = TmpfsProvider {
+            root: Some(dir.path().join("ram")),
+            ..TmpfsProvider::default()
+        };
+        let cache = CacheHandle::new(dir.path().join("cache"));
+        let options = PrepareOptions {
+            base_branch: Some("main".to_string()),
+            ..PrepareOptions::default()
+        };
+
+        let metadata = provider
+            .prepare(&project, "env1", &options, &cache, None)
+            .unwrap();
+        let worktree = dir.path().join("ram/env1");
+        assert_eq!(metadata["worktree_path"], json!(worktree));
+        assert_eq!(metadata["branch"], "work-env-env1");
+        assert!(worktree.join("src/lib.rs").exists());
+        assert_eq!(provider.claim_conflict(&metadata).unwrap(), None);
+
+        provider.remove(&metadata, None).unwrap();
+        assert!(!worktree.exists());
+        provider.remove(&metadata, None).unwrap();
+    }
+
+    #[test]
+    fn prepare_refuses_to_exceed_the_size_limit() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let project = project(dir.path());
+        let provider = TmpfsProvider {
+            root: Some(dir.path().join("ram")),
+            max_bytes: Some(4),
+            ..TmpfsProvider::default()
+        };
+        let cache = CacheHandle::new(dir.path().join("cache"));
+
+        let err = provider
+            .prepare(&project, "env1", &PrepareOptions::default(), &cache, None)
+            .unwrap_err();
+        assert!(err.to_string().contains("max-size-mb"), "{err}");
+        assert!(!dir.path().join("ram/env1").exists());
+    }
+}
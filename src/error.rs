@@ -0,0 +1,202 @@
+//! Errors the daemon API reports with a stable code.
+//!
+//! The daemon answers failed requests with
+//! `{"code": "...", "message": "...", "details": {...}}` and an HTTP status
+//! derived from the code. [`client::DaemonClient`](crate::client::DaemonClient)
+//! turns those bodies back into an [`Error`], so callers can branch on
+//! [`Error::code`] instead of matching message text:
+//!
+//! ```no_run
+//! # async fn example(client: &work::client::DaemonClient) -> anyhow::Result<()> {
+//! use work::error::{Error, ErrorCode};
+//!
+//! match client.get_task("abc").await {
+//!     Err(e) if Error::code_of(&e) == ErrorCode::TaskNotFound => println!("gone"),
+//!     other => println!("{}", other?.status),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+
+/// Machine-readable reason a request failed. New codes may be added.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ErrorCode {
+    ProjectNotFound,
+    EnvNotFound,
+    TaskNotFound,
+    JobNotFound,
+    GroupNotFound,
+    /// No snapshot of the environment has that ID, prefix, or name.
+    SnapshotNotFound,
+    /// A project with the same name or path is already registered.
+    ProjectExists,
+    /// The path is inside or around a registered project's; register it
+    /// with `nested` to allow that.
+    ProjectPathOverlaps,
+    /// The environment is not in the pool, so it cannot be claimed.
+    EnvNotInPool,
+    /// No pooled environment is available for the provider and project.
+    NoPoolEnv,
+    /// The environment belongs to a task; remove the task instead.
+    EnvAttachedToTask,
+    /// The environment is already being removed.
+    EnvRemoving,
+    /// A task is running in the environment, so it can't be restored.
+    EnvBusy,
+    /// An imported project collides with a registered one.
+    ImportConflict,
+    /// Only dead jobs can be retried by hand.
+    JobNotDead,
+    /// The group still has tasks; remove it with `cascade` to remove them too.
+    GroupNotEmpty,
+    /// The project still has tasks; remove it with `force` to remove them too.
+    ProjectInUse,
+    /// Another task is still running in the project checkout; only one
+    /// `inplace` task per project runs at a time.
+    InPlaceBusy,
+    /// The export file was written by a newer version of work.
+    UnsupportedExportVersion,
+    /// An ID prefix matches more than one task or environment; `details`
+    /// lists the matches.
+    AmbiguousId,
+    /// The request was malformed or asked for something impossible.
+    InvalidRequest,
+    /// config.toml failed to parse or has unusable settings.
+    InvalidConfig,
+    /// The task or environment provider isn't in the daemon's config, which
+    /// may not be the file the CLI read.
+    UnknownProvider,
+    /// The project's config doesn't allow the task or environment provider.
+    ProviderNotAllowed,
+    /// The environment's provider can't snapshot or restore environments.
+    SnapshotUnsupported,
+    /// The peer made more requests than `[daemon.request-rate-limit]`
+    /// allows; `details.retry_after_ms` says when to try again.
+    RateLimited,
+    /// The daemon is still starting up.
+    NotReady,
+    /// Anything else; see the message.
+    #[serde(other)]
+    Internal,
+}
+
+impl ErrorCode {
+    /// The HTTP status the daemon responds with for this code.
+    pub fn http_status(self) -> u16 {
+        match self {
+            Self::ProjectNotFound
+            | Self::EnvNotFound
+            | Self::TaskNotFound
+            | Self::JobNotFound
+            | Self::GroupNotFound
+            | Self::SnapshotNotFound => 404,
+            Self::ProjectExists
+            | Self::ProjectPathOverlaps
+            | Self::EnvNotInPool
+            | Self::NoPoolEnv
+            | Self::EnvAttachedToTask
+            | Self::EnvRemoving
+            | Self::EnvBusy
+            | Self::ImportConflict
+            | Self::JobNotDead
+            | Self::GroupNotEmpty
+            | Self::ProjectInUse
+            | Self::InPlaceBusy => 409,
+            Self::UnsupportedExportVersion
+            | Self::AmbiguousId
+            | Self::InvalidRequest
+            | Self::InvalidConfig => 400,
+            Self::UnknownProvider | Self::SnapshotUnsupported => 422,
+            Self::ProviderNotAllowed => 403,
+            Self::RateLimited => 429,
+            Self::NotReady => 503,
+            Self::Internal => 500,
+        }
+    }
+}
+
+/// An error with a code, a human-readable message, and optional structured
+/// details such as the ID that was not found.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Error {
+    pub code: ErrorCode,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub details: serde_json::Value,
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            details: serde_json::Value::Null,
+        }
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = details;
+        self
+    }
+
+    /// The code of the first [`Error`] in `err`'s chain, or
+    /// [`ErrorCode::Internal`] if there is none.
+    pub fn code_of(err: &anyhow::Error) -> ErrorCode {
+        err.chain()
+            .find_map(|e| e.downcast_ref::<Self>())
+            .map_or(ErrorCode::Internal, |e| e.code)
+    }
+
+    /// Recover the coded error from `err`, treating anything uncoded as
+    /// internal.
+    pub fn from_anyhow(err: &anyhow::Error) -> Self {
+        match err.chain().find_map(|e| e.downcast_ref::<Self>()) {
+            Some(coded) => coded.clone(),
+            None => Self::new(ErrorCode::Internal, err.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Return early with a coded [`Error`], like `anyhow::bail!`.
+macro_rules! bail_code {
+    ($code:expr, $($arg:tt)+) => {
+        return Err($crate::error::Error::new($code, format!($($arg)+)).into())
+    };
+}
+pub(crate) use bail_code;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_survives_context() {
+        let err = anyhow::Error::from(Error::new(ErrorCode::EnvNotInPool, "busy"))
+            .context("claiming environment");
+        assert_eq!(Error::code_of(&err), ErrorCode::EnvNotInPool);
+        assert_eq!(
+            Error::code_of(&anyhow::anyhow!("boom")),
+            ErrorCode::Internal
+        );
+    }
+
+    #[test]
+    fn unknown_codes_deserialize_as_internal() {
+        let err: Error =
+            serde_json::from_str(r#"{"code": "from_the_future", "message": "hi"}"#).unwrap();
+        assert_eq!(err.code, ErrorCode::Internal);
+        assert_eq!(err.details, serde_json::Value::Null);
+    }
+}
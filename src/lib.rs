@@ -0,0 +1,44 @@
+//! Drive a `work` daemon from Rust.
+//!
+//! [`client::DaemonClient`] talks to a running daemon over its Unix socket and
+//! returns the [`models`] types the daemon stores. Failed requests carry an
+//! [`error::ErrorCode`]. Only [`client`], [`error`], and [`models`] are public
+//! API and follow semver; the other modules are shared with the `work` binary
+//! and may change in any release.
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! let client = work::client::DaemonClient::new()?;
+//! for task in client.list_tasks().await? {
+//!     println!("{} {}", task.id, task.status);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod client;
+pub mod error;
+pub mod models;
+
+#[doc(hidden)]
+pub mod ansi;
+#[doc(hidden)]
+pub mod config;
+#[doc(hidden)]
+pub mod daemon;
+#[doc(hidden)]
+pub mod db;
+#[doc(hidden)]
+pub mod environment;
+#[doc(hidden)]
+pub mod id;
+#[doc(hidden)]
+pub mod log_crypt;
+#[doc(hidden)]
+pub mod log_sections;
+#[doc(hidden)]
+pub mod log_stamp;
+#[doc(hidden)]
+pub mod paths;
+#[doc(hidden)]
+pub mod redact;
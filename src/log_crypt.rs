@@ -0,0 +1,395 @@
+//! Encryption of task logs at rest, turned on by `[tasks] encrypt-logs`.
+//!
+//! An encrypted log starts with [`MAGIC`], followed by one record per write:
+//! a 4-byte big-endian length, then a random 24-byte nonce and the
+//! XChaCha20-Poly1305 ciphertext it seals. Records are only ever appended, so
+//! a log can be read while the runner is still writing it. Readers tell
+//! encrypted logs apart by their header, so logs written before the setting
+//! changed stay readable either way.
+//!
+//! The key lives in the OS keychain (the login keychain on macOS, the Secret
+//! Service through `secret-tool` elsewhere) and is created the first time a
+//! log is encrypted. `WORK_LOG_KEY`, 64 hex digits, takes its place on
+//! machines without a keychain.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+use anyhow::Context;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+/// First bytes of every encrypted log.
+const MAGIC: &[u8; 8] = b"WORKENC1";
+
+const NONCE_LEN: usize = 24;
+
+const KEY_ENV: &str = "WORK_LOG_KEY";
+const KEYCHAIN_SERVICE: &str = "work";
+const KEYCHAIN_ACCOUNT: &str = "task-log-key";
+
+/// A task log that encrypts each write as its own record.
+pub struct EncryptedLog {
+    /// Opened for appending, so records [`append`]ed by others land whole
+    /// between this log's own.
+    file: File,
+    cipher: &'static XChaCha20Poly1305,
+}
+
+impl EncryptedLog {
+    /// Start a new log at `path`, replacing any earlier one. The header is
+    /// written before the log takes the path's place, so nothing appended
+    /// meanwhile can land ahead of it.
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        Self::create_with(path, cipher()?)
+    }
+
+    /// A log sealed with a fixed test key instead of the keychain's.
+    #[cfg(test)]
+    pub(crate) fn create_for_test(path: &Path) -> anyhow::Result<Self> {
+        static CIPHER: OnceLock<XChaCha20Poly1305> = OnceLock::new();
+        let cipher = CIPHER.get_or_init(|| XChaCha20Poly1305::new_from_slice(&[7; 32]).unwrap());
+        Self::create_with(path, cipher)
+    }
+
+    fn create_with(path: &Path, cipher: &'static XChaCha20Poly1305) -> anyhow::Result<Self> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .truncate(false)
+            .open(&tmp)?;
+        file.set_len(0)?;
+        file.write_all(MAGIC)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(Self { file, cipher })
+    }
+
+    /// Everything written so far, decrypted.
+    pub fn read_plaintext(&self) -> std::io::Result<Vec<u8>> {
+        let mut bytes = vec![0; self.file.metadata()?.len() as usize];
+        self.file.read_exact_at(&mut bytes, 0)?;
+        let records = bytes.get(MAGIC.len()..).unwrap_or_default();
+        Ok(open_records(self.cipher, records)?.0)
+    }
+
+    /// Keep only the first `len` bytes of plaintext, re-encrypted as one
+    /// record.
+    pub fn truncate(&mut self, len: u64) -> std::io::Result<()> {
+        let mut plaintext = self.read_plaintext()?;
+        plaintext.truncate(len as usize);
+        self.file.set_len(MAGIC.len() as u64)?;
+        if !plaintext.is_empty() {
+            self.file.write_all(&seal(self.cipher, &plaintext)?)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for EncryptedLog {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write_all(&seal(self.cipher, buf)?)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Whether the log at `path` is encrypted.
+pub fn is_encrypted(path: &Path) -> bool {
+    let mut header = [0; MAGIC.len()];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok()
+        && header == *MAGIC
+}
+
+/// The contents of the log at `path`, decrypted if it's encrypted.
+pub fn read(path: &Path) -> std::io::Result<Vec<u8>> {
+    let bytes = std::fs::read(path)?;
+    match bytes.strip_prefix(MAGIC.as_slice()) {
+        Some(records) => Ok(open_records(cipher_io()?, records)?.0),
+        None => Ok(bytes),
+    }
+}
+
+/// The last `max_bytes` of the log at `path`, decrypted if needed, and how
+/// many bytes of output come before them.
+pub fn read_tail(path: &Path, max_bytes: u64) -> std::io::Result<(Vec<u8>, u64)> {
+    if is_encrypted(path) {
+        let mut bytes = read(path)?;
+        let start = (bytes.len() as u64).saturating_sub(max_bytes);
+        bytes.drain(..start as usize);
+        return Ok((bytes, start));
+    }
+    let mut file = File::open(path)?;
+    let start = file.metadata()?.len().saturating_sub(max_bytes);
+    file.seek(SeekFrom::Start(start))?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    Ok((bytes, start))
+}
+
+/// Append `bytes` to the existing log at `path`, encrypting them if the log
+/// is encrypted.
+pub fn append(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let encrypted = is_encrypted(path);
+    let mut file = std::fs::OpenOptions::new().append(true).open(path)?;
+    if encrypted {
+        file.write_all(&seal(cipher_io()?, bytes)?)
+    } else {
+        file.write_all(bytes)
+    }
+}
+
+/// Encrypt `plaintext` as one length-prefixed record.
+fn seal(cipher: &XChaCha20Poly1305, plaintext: &[u8]) -> std::io::Result<Vec<u8>> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| std::io::Error::other("failed to encrypt task log"))?;
+    let len = u32::try_from(NONCE_LEN + ciphertext.len())
+        .map_err(|_| std::io::Error::other("task log write is too large to encrypt"))?;
+    let mut record = Vec::with_capacity(4 + len as usize);
+    record.extend_from_slice(&len.to_be_bytes());
+    record.extend_from_slice(&nonce);
+    record.extend_from_slice(&ciphertext);
+    Ok(record)
+}
+
+/// Decrypt the complete records at the start of `bytes`, returning their
+/// plaintext and how many bytes they took up. A partial record at the end is
+/// left for the next read.
+fn open_records(cipher: &XChaCha20Poly1305, bytes: &[u8]) -> std::io::Result<(Vec<u8>, usize)> {
+    let mut plaintext = Vec::new();
+    let mut pos = 0;
+    while let Some(header) = bytes.get(pos..pos + 4) {
+        let len = u32::from_be_bytes(header.try_into().expect("4-byte header")) as usize;
+        let Some(record) = bytes.get(pos + 4..pos + 4 + len) else {
+            break;
+        };
+        if len < NONCE_LEN {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "encrypted task log is corrupt",
+            ));
+        }
+        let (nonce, ciphertext) = record.split_at(NONCE_LEN);
+        let chunk = cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "task log failed to decrypt; it may have been written with another key",
+                )
+            })?;
+        plaintext.extend_from_slice(&chunk);
+        pos += 4 + len;
+    }
+    Ok((plaintext, pos))
+}
+
+/// The cipher for task logs, loading or creating the key on first use.
+fn cipher() -> anyhow::Result<&'static XChaCha20Poly1305> {
+    static CIPHER: OnceLock<XChaCha20Poly1305> = OnceLock::new();
+    // Tasks start concurrently; only one of them may create the key.
+    static LOADING: Mutex<()> = Mutex::new(());
+
+    if let Some(cipher) = CIPHER.get() {
+        return Ok(cipher);
+    }
+    let _loading = LOADING.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(cipher) = CIPHER.get() {
+        return Ok(cipher);
+    }
+    let key = load_or_create_key()?;
+    let cipher = XChaCha20Poly1305::new_from_slice(&key)
+        .map_err(|_| anyhow::anyhow!("task log key must be 32 bytes"))?;
+    Ok(CIPHER.get_or_init(|| cipher))
+}
+
+fn cipher_io() -> std::io::Result<&'static XChaCha20Poly1305> {
+    cipher().map_err(std::io::Error::other)
+}
+
+fn load_or_create_key() -> anyhow::Result<Vec<u8>> {
+    if let Ok(hex) = std::env::var(KEY_ENV) {
+        return decode_key(&hex).with_context(|| format!("{KEY_ENV} must be 64 hex digits"));
+    }
+    if let Some(hex) = keychain::get()? {
+        return decode_key(&hex).context("the task log key in the keychain is malformed");
+    }
+    let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+    keychain::set(&encode_key(&key))?;
+    tracing::info!("created task log encryption key in the keychain");
+    Ok(key.to_vec())
+}
+
+fn encode_key(key: &[u8]) -> String {
+    key.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_key(hex: &str) -> anyhow::Result<Vec<u8>> {
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        anyhow::bail!("expected 64 hex digits");
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(Into::into))
+        .collect()
+}
+
+#[cfg(target_os = "macos")]
+mod keychain {
+    use std::io::Write;
+
+    use super::{KEYCHAIN_ACCOUNT, KEYCHAIN_SERVICE};
+
+    pub fn get() -> anyhow::Result<Option<String>> {
+        let output = std::process::Command::new("security")
+            .args(["find-generic-password", "-s", KEYCHAIN_SERVICE])
+            .args(["-a", KEYCHAIN_ACCOUNT, "-w"])
+            .output()?;
+        // Exit status 44 means there is no such item yet.
+        if output.status.code() == Some(44) {
+            return Ok(None);
+        }
+        if !output.status.success() {
+            anyhow::bail!(
+                "failed to read the task log key from the keychain: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(Some(String::from_utf8(output.stdout)?))
+    }
+
+    pub fn set(secret: &str) -> anyhow::Result<()> {
+        // `security -i` reads the command from stdin, so the key never shows
+        // up in a process listing. The secret is hex and needs no quoting.
+        let mut child = std::process::Command::new("security")
+            .arg("-i")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped())
+            .spawn()?;
+        writeln!(
+            child.stdin.take().expect("piped stdin"),
+            "add-generic-password -s {KEYCHAIN_SERVICE} -a {KEYCHAIN_ACCOUNT} -w {secret}"
+        )?;
+        let output = child.wait_with_output()?;
+        // Interactive mode exits 0 whether or not the command worked, but
+        // reports failures on stderr.
+        if !output.status.success() || !output.stderr.is_empty() {
+            anyhow::bail!(
+                "failed to store the task log key in the keychain: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+mod keychain {
+    use std::io::Write;
+
+    use super::{KEYCHAIN_ACCOUNT, KEYCHAIN_SERVICE};
+
+    pub fn get() -> anyhow::Result<Option<String>> {
+        let output = std::process::Command::new("secret-tool")
+            .args(["lookup", "service", KEYCHAIN_SERVICE])
+            .args(["account", KEYCHAIN_ACCOUNT])
+            .output()
+            .map_err(|e| anyhow::anyhow!("failed to run secret-tool: {e} (set WORK_LOG_KEY on machines without a keychain)"))?;
+        if output.status.success() && !output.stdout.is_empty() {
+            return Ok(Some(String::from_utf8(output.stdout)?));
+        }
+        // It exits 1 with no output at all when there is no such item. Any
+        // other failure, such as no Secret Service to ask, must not look
+        // like a missing key, or a new one would replace it.
+        if output.status.code() == Some(1) && output.stdout.is_empty() && output.stderr.is_empty() {
+            return Ok(None);
+        }
+        anyhow::bail!(
+            "failed to read the task log key with secret-tool ({}): {} (set WORK_LOG_KEY on machines without a keychain)",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+
+    pub fn set(secret: &str) -> anyhow::Result<()> {
+        let mut child = std::process::Command::new("secret-tool")
+            .args(["store", "--label", "work task log key"])
+            .args(["service", KEYCHAIN_SERVICE, "account", KEYCHAIN_ACCOUNT])
+            .stdin(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()?;
+        // Passed on stdin so the key never shows up in a process listing.
+        child
+            .stdin
+            .take()
+            .expect("piped stdin")
+            .write_all(secret.as_bytes())?;
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "failed to store the task log key with secret-tool: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_cipher() -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new_from_slice(&[7; 32]).unwrap()
+    }
+
+    #[test]
+    fn records_decrypt_once_complete() {
+        let cipher = test_cipher();
+        let mut bytes = seal(&cipher, b"hello ").unwrap();
+        let second = seal(&cipher, b"world\n").unwrap();
+        assert!(!bytes.windows(5).any(|w| w == b"hello"));
+
+        // Half of the second record hasn't been written yet.
+        bytes.extend_from_slice(&second[..second.len() / 2]);
+        let (plaintext, used) = open_records(&cipher, &bytes).unwrap();
+        assert_eq!(plaintext, b"hello ");
+
+        bytes.extend_from_slice(&second[second.len() / 2..]);
+        let (plaintext, rest) = open_records(&cipher, &bytes[used..]).unwrap();
+        assert_eq!(plaintext, b"world\n");
+        assert_eq!(used + rest, bytes.len());
+    }
+
+    #[test]
+    fn another_key_fails_to_decrypt() {
+        let bytes = seal(&test_cipher(), b"secret output").unwrap();
+        let other = XChaCha20Poly1305::new_from_slice(&[8; 32]).unwrap();
+        let err = open_records(&other, &bytes).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn keys_round_trip_through_hex() {
+        let key: Vec<u8> = (0..32).collect();
+        let hex = encode_key(&key);
+        assert_eq!(hex.len(), 64);
+        assert_eq!(decode_key(&format!("{hex}\n")).unwrap(), key);
+        assert!(decode_key("abc").is_err());
+        assert!(decode_key(&"zz".repeat(32)).is_err());
+    }
+}
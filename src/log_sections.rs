@@ -0,0 +1,149 @@
+//! Sections in task and environment logs.
+//!
+//! Output between a `::group::NAME` line and an `::endgroup::` line forms a
+//! section named NAME, the convention CI runners use for folding logs. A
+//! script marks a noisy step, such as installing dependencies, by printing
+//! the markers around it; the TUI shows finished sections folded. `work`
+//! marks each step of a task with `pre` or `post` commands the same way.
+//! Sections don't nest: a `::group::` line inside a section ends it and
+//! starts the next one.
+
+pub const GROUP: &str = "::group::";
+pub const END_GROUP: &str = "::endgroup::";
+
+/// One section of a log, by line number from 0.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct LogSection {
+    pub name: String,
+    /// The `::group::` line.
+    pub start: usize,
+    /// The line after the section's last line: its `::endgroup::` line, or
+    /// the `::group::` line that cut it short. None while the section is
+    /// still open at the end of the log.
+    #[serde(default)]
+    pub end: Option<usize>,
+}
+
+impl LogSection {
+    /// Lines of output in the section, not counting its markers, in a log
+    /// of `total_lines`.
+    pub fn len(&self, total_lines: usize) -> usize {
+        self.end
+            .unwrap_or(total_lines)
+            .saturating_sub(self.start + 1)
+    }
+
+    /// Whether the section has no output between its markers.
+    pub fn is_empty(&self, total_lines: usize) -> bool {
+        self.len(total_lines) == 0
+    }
+}
+
+/// What a log line says about sections.
+enum Marker {
+    Group(String),
+    EndGroup,
+}
+
+/// The marker `line` is, looking past color codes and the prefix
+/// `[tasks] log-timestamps` adds.
+fn marker(line: &str) -> Option<Marker> {
+    let plain = if line.contains('\x1b') {
+        std::borrow::Cow::Owned(crate::ansi::strip(line))
+    } else {
+        std::borrow::Cow::Borrowed(line)
+    };
+    let line = crate::log_stamp::without_prefix(&plain).trim();
+    if let Some(name) = line.strip_prefix(GROUP) {
+        Some(Marker::Group(name.trim().to_string()))
+    } else if line == END_GROUP {
+        Some(Marker::EndGroup)
+    } else {
+        None
+    }
+}
+
+/// Whether `line` starts or ends a section.
+pub fn is_marker(line: &str) -> bool {
+    marker(line).is_some()
+}
+
+/// The sections of `log`, in order. Lines are numbered as [`str::lines`]
+/// splits them.
+pub fn index(log: &str) -> Vec<LogSection> {
+    let mut sections: Vec<LogSection> = Vec::new();
+    for (number, line) in log.lines().enumerate() {
+        let Some(marker) = marker(line) else {
+            continue;
+        };
+        if let Some(open) = sections.last_mut().filter(|s| s.end.is_none()) {
+            open.end = Some(number);
+        }
+        if let Marker::Group(name) = marker {
+            sections.push(LogSection {
+                name,
+                start: number,
+                end: None,
+            });
+        }
+    }
+    sections
+}
+
+/// The sections of the log of the task with ID `task_id`. A task that
+/// hasn't started has no log, and so no sections.
+pub fn of_task(task_id: &str) -> anyhow::Result<Vec<LogSection>> {
+    crate::db::get_task(task_id)?;
+    match crate::log_crypt::read(&crate::paths::task_log_path(task_id)?) {
+        Ok(log) => Ok(index(&String::from_utf8_lossy(&crate::redact::log(log)?))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexes_sections_between_markers() {
+        let log = "setup\n::group::Install deps\nnpm i\nadded 900\n::endgroup::\nrunning\n::group::Build\nbuilding\n";
+        let sections = index(log);
+        assert_eq!(
+            sections,
+            [
+                LogSection {
+                    name: "Install deps".to_string(),
+                    start: 1,
+                    end: Some(4),
+                },
+                LogSection {
+                    name: "Build".to_string(),
+                    start: 6,
+                    end: None,
+                },
+            ]
+        );
+        let total = log.lines().count();
+        assert_eq!(sections[0].len(total), 2);
+        assert_eq!(sections[1].len(total), 1);
+    }
+
+    #[test]
+    fn a_new_group_ends_the_open_one_and_stray_ends_are_ignored() {
+        let log = "::endgroup::\n::group::one\na\n::group::two\n::endgroup::\n";
+        let sections = index(log);
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].end, Some(3));
+        assert!(sections[1].is_empty(log.lines().count()));
+    }
+
+    #[test]
+    fn sees_markers_past_timestamps_and_colors() {
+        let log = "2026-01-02T03:04:05.678Z stdout ::group::Fetch\r\n\x1b[2m::endgroup::\x1b[0m\n";
+        let sections = index(log);
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].name, "Fetch");
+        assert_eq!(sections[0].end, Some(1));
+    }
+}
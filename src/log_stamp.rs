@@ -0,0 +1,170 @@
+//! Timestamp and stream prefixes on task log lines.
+//!
+//! With `[tasks] log-timestamps`, the daemon starts each line of task output
+//! with the time it arrived and the stream it came from, e.g.
+//! `2026-01-02T03:04:05.678Z stderr warning: unused import`. Readers that
+//! want the output as the runner wrote it, like `work task logs --raw`,
+//! take the prefixes off here.
+
+/// Where a line of task output came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+    /// A pty, which carries stdout and stderr together.
+    Output,
+}
+
+impl Stream {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Stdout => "stdout",
+            Self::Stderr => "stderr",
+            Self::Output => "output",
+        }
+    }
+}
+
+/// The timestamp format the prefixes use, in UTC with milliseconds.
+pub fn now() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+/// Prefixes lines of output from one or more streams writing to the same
+/// log. A line one stream leaves unfinished is ended before another stream's
+/// line starts, so every line carries one prefix.
+#[derive(Default)]
+pub struct Stamper {
+    /// The stream whose line was last left without a newline.
+    open_line: Option<Stream>,
+}
+
+impl Stamper {
+    pub fn push(&mut self, stream: Stream, at: &str, chunk: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(chunk.len() + 32);
+        for segment in chunk.split_inclusive(|&b| b == b'\n') {
+            if self.open_line != Some(stream) {
+                if self.open_line.is_some() {
+                    out.push(b'\n');
+                }
+                out.extend_from_slice(format!("{at} {} ", stream.as_str()).as_bytes());
+            }
+            out.extend_from_slice(segment);
+            self.open_line = (!segment.ends_with(b"\n")).then_some(stream);
+        }
+        out
+    }
+
+    /// Note that something other than stream output ended the current line.
+    pub fn end_line(&mut self) {
+        self.open_line = None;
+    }
+}
+
+/// A prefix's timestamp, with `0` for each digit.
+const TIMESTAMP: &[u8] = b"0000-00-00T00:00:00.000Z";
+/// Timestamp, space, six-letter stream name, space.
+const PREFIX_LEN: usize = TIMESTAMP.len() + 8;
+
+/// Whether `bytes` begins a prefix, or is one if `bytes` is [`PREFIX_LEN`]
+/// long.
+fn could_be_prefix(bytes: &[u8]) -> bool {
+    if bytes.len() > PREFIX_LEN {
+        return false;
+    }
+    let timestamp = bytes.iter().zip(TIMESTAMP).all(|(&b, &t)| match t {
+        b'0' => b.is_ascii_digit(),
+        t => b == t,
+    });
+    let rest = bytes.get(TIMESTAMP.len()..).unwrap_or_default();
+    timestamp
+        && [Stream::Stdout, Stream::Stderr, Stream::Output]
+            .iter()
+            .any(|s| format!(" {} ", s.as_str()).as_bytes().starts_with(rest))
+}
+
+/// `line` without its prefix, if it has one.
+pub fn without_prefix(line: &str) -> &str {
+    match line.get(..PREFIX_LEN) {
+        Some(prefix) if could_be_prefix(prefix.as_bytes()) => &line[PREFIX_LEN..],
+        _ => line,
+    }
+}
+
+/// Takes the prefixes off output that arrives in chunks. Lines without one,
+/// such as `[work]` lines, pass through as they are.
+#[derive(Default)]
+pub struct Unstamper {
+    /// The start of a line that may yet turn out to be a prefix.
+    pending: Vec<u8>,
+    mid_line: bool,
+}
+
+impl Unstamper {
+    pub fn push(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(input.len());
+        for &byte in input {
+            if self.mid_line {
+                out.push(byte);
+                self.mid_line = byte != b'\n';
+                continue;
+            }
+            self.pending.push(byte);
+            if !could_be_prefix(&self.pending) {
+                out.append(&mut self.pending);
+                self.mid_line = byte != b'\n';
+            } else if self.pending.len() == PREFIX_LEN {
+                self.pending.clear();
+                self.mid_line = true;
+            }
+        }
+        out
+    }
+
+    /// Whatever was held back as a possible prefix when the output ended.
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AT: &str = "2026-01-02T03:04:05.678Z";
+
+    #[test]
+    fn prefixes_each_line_and_ends_lines_cut_off_by_another_stream() {
+        let mut stamper = Stamper::default();
+        let mut log = stamper.push(Stream::Stdout, AT, b"one\ntw");
+        log.extend(stamper.push(Stream::Stdout, AT, b"o\n"));
+        log.extend(stamper.push(Stream::Stdout, AT, b"progress"));
+        log.extend(stamper.push(Stream::Stderr, AT, b"oops\n"));
+        assert_eq!(
+            String::from_utf8(log).unwrap(),
+            format!("{AT} stdout one\n{AT} stdout two\n{AT} stdout progress\n{AT} stderr oops\n")
+        );
+    }
+
+    #[test]
+    fn unstamping_restores_the_output_across_chunk_boundaries() {
+        let mut stamper = Stamper::default();
+        let mut log = stamper.push(Stream::Output, AT, b"hello\nworld\n");
+        log.extend_from_slice(b"\n[work] 2026-01-02T03:04:06Z note: hi\n");
+        stamper.end_line();
+        log.extend(stamper.push(Stream::Output, AT, b"2026 was a year\nbye"));
+
+        for chunk_size in [1, 7, log.len()] {
+            let mut unstamper = Unstamper::default();
+            let mut out = Vec::new();
+            for chunk in log.chunks(chunk_size) {
+                out.extend(unstamper.push(chunk));
+            }
+            out.extend(unstamper.finish());
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                "hello\nworld\n\n[work] 2026-01-02T03:04:06Z note: hi\n2026 was a year\nbye"
+            );
+        }
+    }
+}
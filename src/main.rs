@@ -1,15 +1,24 @@
-use clap::{CommandFactory, Parser, Subcommand};
-use clap_complete::engine::{ArgValueCompleter, CompletionCandidate};
+use std::io::IsTerminal;
 
-mod client;
-mod config;
-mod daemon;
-mod db;
-mod environment;
-mod id;
-mod paths;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::engine::{ArgValueCompleter, CompletionCandidate};
+use output::{Cell, Style, Table};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use work::error::{Error, ErrorCode};
+use work::{client, config, daemon, db, environment, paths};
+
+mod bundle;
+mod doctor;
+mod editor;
+mod output;
+mod self_update;
 mod tui;
 
+/// How long to wait for a daemon started in the background to be ready,
+/// unless `--wait-daemon` says otherwise.
+const DAEMON_START_TIMEOUT_SECS: u64 = 30;
+
 struct FileOrSinkWriter {
     file: Option<std::fs::File>,
 }
@@ -41,6 +50,23 @@ struct Cli {
     #[arg(long, global = true, env = "WORK_HOME")]
     work_home: Option<std::path::PathBuf>,
 
+    /// Use the named profile, with its own daemon, database, and config
+    #[arg(long, global = true, value_name = "NAME", env = "WORK_PROFILE")]
+    profile: Option<String>,
+
+    /// Disable colored output (also honors NO_COLOR)
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Wait up to SECS for the daemon to be ready before running the command
+    #[arg(long, global = true, value_name = "SECS", env = "WORK_WAIT_DAEMON")]
+    wait_daemon: Option<u64>,
+
+    /// Manage the daemon on HOST over SSH: a `[hosts]` profile or an SSH
+    /// destination such as user@devbox
+    #[arg(long, global = true, value_name = "HOST", env = "WORK_HOST")]
+    host: Option<String>,
+
     #[command(subcommand)]
     command: Option<Command>,
 }
@@ -49,7 +75,11 @@ struct Cli {
 enum Command {
     /// Reset the database (destroys all data)
     #[command(hide = true)]
-    ResetDatabase,
+    ResetDatabase {
+        /// Skip the confirmation prompt
+        #[arg(long, short)]
+        yes: bool,
+    },
 
     /// Manage projects
     Project {
@@ -65,7 +95,7 @@ enum Command {
 
     /// Alias for `task new`
     New {
-        /// Task description
+        /// Task description, as markdown; `-` reads it from stdin
         description: String,
 
         /// Project name (defaults to project matching current directory)
@@ -80,12 +110,39 @@ enum Command {
         #[arg(long, add = ArgValueCompleter::new(complete_env_providers))]
         env_provider: Option<String>,
 
+        /// Label the task (repeatable)
+        #[arg(long = "label", value_name = "LABEL")]
+        labels: Vec<String>,
+
+        /// Run in an environment provider tagged with TAG (repeatable)
+        #[arg(long = "require", value_name = "TAG")]
+        requirements: Vec<String>,
+
+        /// Base the task's environment on this branch or commit instead of
+        /// the base branch
+        #[arg(long, value_name = "REF")]
+        from_ref: Option<String>,
+
+        /// Add the task to a group made with `work group new`
+        #[arg(long, value_name = "GROUP", add = ArgValueCompleter::new(complete_group_ids))]
+        parent: Option<String>,
+
+        /// Run the task's jobs ahead of (or behind) other queued work
+        #[arg(long, value_enum, default_value = "normal")]
+        priority: Priority,
+
+        /// Run in the project checkout itself rather than an environment of
+        /// its own (the `inplace` provider); one such task per project at a
+        /// time
+        #[arg(long, conflicts_with = "requirements")]
+        in_place: bool,
+
         /// Follow task logs after creation
         #[arg(short, long)]
         attach: bool,
 
         /// Output format
-        #[arg(long, default_value = "human")]
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
         format: OutputFormat,
     },
 
@@ -99,14 +156,22 @@ enum Command {
         /// Skip provider cleanup and remove only database records
         #[arg(long)]
         skip_provider: bool,
+
+        /// Skip the confirmation prompt that --skip-provider asks for
+        #[arg(long, short)]
+        yes: bool,
     },
 
     /// Alias for `task list`
     #[command(alias = "ls")]
     List {
         /// Output format
-        #[arg(long, default_value = "human")]
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
         format: OutputFormat,
+
+        /// Order to list tasks in
+        #[arg(long, default_value = "created")]
+        sort: TaskSort,
     },
 
     /// Alias for `task logs`
@@ -118,6 +183,19 @@ enum Command {
         /// Follow log output in realtime
         #[arg(short = 'f', long = "follow")]
         follow: bool,
+
+        /// Strip color and other terminal escape sequences
+        #[arg(long)]
+        plain: bool,
+
+        /// Leave out the timestamp and stream that `[tasks] log-timestamps`
+        /// puts before each line
+        #[arg(long)]
+        raw: bool,
+
+        /// List the log's `::group::` sections instead of printing it
+        #[arg(long, conflicts_with = "follow")]
+        sections: bool,
     },
 
     /// Alias for `task exec`
@@ -127,6 +205,10 @@ enum Command {
         #[arg(add = ArgValueCompleter::new(complete_task_ids))]
         id: String,
 
+        /// Record the session to the environment's log directory
+        #[arg(long)]
+        record: bool,
+
         /// Provider command name
         #[arg(add = ArgValueCompleter::new(complete_task_exec_commands))]
         command: String,
@@ -143,6 +225,125 @@ enum Command {
         command: EnvironmentCommand,
     },
 
+    /// Inspect background jobs
+    Job {
+        #[command(subcommand)]
+        command: JobCommand,
+    },
+
+    /// Manage task groups
+    Group {
+        #[command(subcommand)]
+        command: GroupCommand,
+    },
+
+    /// Search task descriptions and, with --logs, task output
+    Search {
+        /// Words to search for (all must match)
+        #[arg(required = true, num_args = 1..)]
+        query: Vec<String>,
+
+        /// Also search task logs
+        #[arg(long)]
+        logs: bool,
+
+        /// Maximum number of results
+        #[arg(long)]
+        limit: Option<usize>,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Total the tokens and cost task runners reported, by project and month
+    Stats {
+        /// Only this project
+        #[arg(long)]
+        project: Option<String>,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Show recorded changes to tasks, environments, projects, groups, and jobs
+    Events {
+        /// Only events after this sequence number
+        #[arg(long, value_name = "SEQ")]
+        since: Option<i64>,
+
+        /// Only events for this task
+        #[arg(long, value_name = "ID", conflicts_with = "env", add = ArgValueCompleter::new(complete_task_ids))]
+        task: Option<String>,
+
+        /// Only events for this environment
+        #[arg(long, value_name = "ID", add = ArgValueCompleter::new(complete_env_ids))]
+        env: Option<String>,
+
+        /// Maximum number of events
+        #[arg(long)]
+        limit: Option<usize>,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Write projects (and optionally finished tasks) as JSON for `work import`
+    Export {
+        /// Include finished tasks
+        #[arg(long)]
+        tasks: bool,
+
+        /// Write to a file instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<std::path::PathBuf>,
+    },
+
+    /// Load projects and tasks written by `work export`
+    Import {
+        /// Export file, or - for stdin
+        file: std::path::PathBuf,
+
+        /// What to do when a project name or path is already registered
+        #[arg(long, value_enum, default_value = "fail")]
+        on_conflict: OnConflict,
+
+        /// Rewrite project paths starting with FROM to start with TO
+        /// (repeatable)
+        #[arg(long = "map", alias = "rewrite-path", value_name = "FROM=TO")]
+        maps: Vec<db::PathMap>,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Rewrite stored project and environment paths, e.g. after restoring
+    /// a database on a machine with a different home directory
+    RemapPaths {
+        /// Replace the FROM path prefix with TO (repeatable)
+        #[arg(long = "map", value_name = "FROM=TO", required = true)]
+        maps: Vec<db::PathMap>,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Check the database for inconsistent records and repair them
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+
+    /// List profiles, each with its own daemon and data
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommand,
+    },
+
     /// Manage configuration
     Config {
         #[command(subcommand)]
@@ -155,9 +356,45 @@ enum Command {
         command: DaemonCommand,
     },
 
+    /// Collect diagnostics for bug reports
+    Debug {
+        #[command(subcommand)]
+        command: DebugCommand,
+    },
+
+    /// Check the daemon, database, git, config, directories, and service
+    /// registration, with a hint for each problem; exits nonzero if any fail
+    Doctor {
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Count the tasks and pooled environments of the project in the
+    /// current directory, read straight from the database; prints nothing
+    /// outside a project
+    Status {
+        /// One line of `key=value` counts, quick and stable enough for a
+        /// shell prompt
+        #[arg(long)]
+        porcelain: bool,
+    },
+
     /// Print version information
     Version,
 
+    /// Update work to the latest release, or hand off to Homebrew or mise
+    /// if they installed it
+    SelfUpdate {
+        /// Only report whether a newer release exists
+        #[arg(long)]
+        check: bool,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
     /// Open the terminal UI
     Tui,
 
@@ -175,6 +412,51 @@ enum OutputFormat {
     Json,
 }
 
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum OnConflict {
+    /// Abort the import
+    Fail,
+    /// Keep the existing project
+    Skip,
+    /// Point the existing project at the imported path
+    Replace,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum TaskSort {
+    /// Newest first
+    Created,
+    /// Unfinished tasks first, the longest in their status first
+    InStatus,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Priority {
+    High,
+    Normal,
+    Low,
+}
+
+impl From<Priority> for db::TaskPriority {
+    fn from(value: Priority) -> Self {
+        match value {
+            Priority::High => Self::High,
+            Priority::Normal => Self::Normal,
+            Priority::Low => Self::Low,
+        }
+    }
+}
+
+impl From<OnConflict> for db::ImportConflict {
+    fn from(value: OnConflict) -> Self {
+        match value {
+            OnConflict::Fail => Self::Fail,
+            OnConflict::Skip => Self::Skip,
+            OnConflict::Replace => Self::Replace,
+        }
+    }
+}
+
 #[derive(Subcommand)]
 enum ProjectCommand {
     /// Create a new project
@@ -185,20 +467,37 @@ enum ProjectCommand {
         /// Project path (defaults to current working directory)
         #[arg(long)]
         path: Option<std::path::PathBuf>,
+
+        /// If the path is already registered, use that project instead of
+        /// failing
+        #[arg(long)]
+        adopt: bool,
+
+        /// Allow a path inside or around another project's
+        #[arg(long)]
+        nested: bool,
     },
 
-    /// Remove a project
+    /// Remove a project and its environments
     #[command(alias = "rm")]
     Remove {
         /// Project name
         name: String,
+
+        /// Also remove the project's tasks and their environments
+        #[arg(long)]
+        force: bool,
+
+        /// Skip the confirmation prompt that --force asks for
+        #[arg(long, short)]
+        yes: bool,
     },
 
     /// List all projects
     #[command(alias = "ls")]
     List {
         /// Output format
-        #[arg(long, default_value = "human")]
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
         format: OutputFormat,
     },
 }
@@ -215,7 +514,7 @@ enum EnvironmentCommand {
         provider: Option<String>,
 
         /// Output format
-        #[arg(long, default_value = "human")]
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
         format: OutputFormat,
     },
 
@@ -229,7 +528,41 @@ enum EnvironmentCommand {
         provider: Option<String>,
 
         /// Output format
-        #[arg(long, default_value = "human")]
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Show what preparing an environment would do, without doing it
+    Plan {
+        /// Project name (defaults to project matching current directory)
+        #[arg(long)]
+        project: Option<String>,
+
+        /// Provider (uses config default if not specified)
+        #[arg(long, add = ArgValueCompleter::new(complete_env_providers))]
+        provider: Option<String>,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Register an existing checkout as an in-use environment
+    Adopt {
+        /// Project name (defaults to project matching current directory)
+        #[arg(long)]
+        project: Option<String>,
+
+        /// Path to the existing checkout
+        #[arg(long)]
+        path: std::path::PathBuf,
+
+        /// Provider (uses config default if not specified)
+        #[arg(long, add = ArgValueCompleter::new(complete_env_providers))]
+        provider: Option<String>,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
         format: OutputFormat,
     },
 
@@ -239,7 +572,7 @@ enum EnvironmentCommand {
         id: String,
 
         /// Output format
-        #[arg(long, default_value = "human")]
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
         format: OutputFormat,
     },
 
@@ -256,8 +589,13 @@ enum EnvironmentCommand {
         #[arg(long)]
         project: Option<String>,
 
+        /// Claim this many environments at once, preparing any the pool
+        /// lacks
+        #[arg(long, conflicts_with = "id")]
+        count: Option<usize>,
+
         /// Output format
-        #[arg(long, default_value = "human")]
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
         format: OutputFormat,
     },
 
@@ -271,13 +609,62 @@ enum EnvironmentCommand {
         /// Skip provider cleanup and remove only database records
         #[arg(long)]
         skip_provider: bool,
+
+        /// Skip the confirmation prompt that --skip-provider asks for
+        #[arg(long, short)]
+        yes: bool,
     },
 
     /// List environments
     #[command(alias = "ls")]
     List {
         /// Output format
-        #[arg(long, default_value = "human")]
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Show an environment and its recorded exec sessions
+    Show {
+        /// Environment ID
+        #[arg(add = ArgValueCompleter::new(complete_env_ids))]
+        id: String,
+
+        /// Also list every status the environment has had, with the job
+        /// that changed it
+        #[arg(long)]
+        history: bool,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Save an environment's files so they can be restored later
+    Snapshot {
+        /// Environment ID
+        #[arg(add = ArgValueCompleter::new(complete_env_ids))]
+        id: String,
+
+        /// Name to restore the snapshot by
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Put an environment's files back as a snapshot saved them
+    Restore {
+        /// Environment ID
+        #[arg(add = ArgValueCompleter::new(complete_env_ids))]
+        id: String,
+
+        /// Snapshot ID, ID prefix, or name
+        snapshot: String,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
         format: OutputFormat,
     },
 
@@ -287,9 +674,13 @@ enum EnvironmentCommand {
         #[arg(add = ArgValueCompleter::new(complete_env_ids))]
         id: String,
 
-        /// Follow log output in realtime
+        /// Follow log output in realtime until the environment is removed
         #[arg(short = 'f', long = "follow")]
         follow: bool,
+
+        /// Strip color and other terminal escape sequences
+        #[arg(long)]
+        plain: bool,
     },
 
     /// Execute a provider-defined environment command
@@ -299,6 +690,10 @@ enum EnvironmentCommand {
         #[arg(add = ArgValueCompleter::new(complete_env_ids))]
         id: String,
 
+        /// Record the session to the environment's log directory
+        #[arg(long)]
+        record: bool,
+
         /// Provider command name
         #[arg(add = ArgValueCompleter::new(complete_env_exec_commands))]
         command: String,
@@ -317,16 +712,24 @@ enum EnvironmentCommand {
 
 #[derive(Subcommand)]
 enum ProviderCommand {
-    /// List available providers
+    /// List available providers with stats on their environments
     #[command(alias = "ls")]
-    List,
+    List {
+        /// Number of recent preparations per provider to average over
+        #[arg(long, default_value_t = 20)]
+        last: usize,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
 }
 
 #[derive(Subcommand)]
 enum TaskCommand {
     /// Create a new task
     New {
-        /// Task description
+        /// Task description, as markdown; `-` reads it from stdin
         description: String,
 
         /// Project name (defaults to project matching current directory)
@@ -341,15 +744,114 @@ enum TaskCommand {
         #[arg(long, add = ArgValueCompleter::new(complete_env_providers))]
         env_provider: Option<String>,
 
+        /// Label the task (repeatable)
+        #[arg(long = "label", value_name = "LABEL")]
+        labels: Vec<String>,
+
+        /// Run in an environment provider tagged with TAG (repeatable)
+        #[arg(long = "require", value_name = "TAG")]
+        requirements: Vec<String>,
+
+        /// Base the task's environment on this branch or commit instead of
+        /// the base branch
+        #[arg(long, value_name = "REF")]
+        from_ref: Option<String>,
+
+        /// Add the task to a group made with `work group new`
+        #[arg(long, value_name = "GROUP", add = ArgValueCompleter::new(complete_group_ids))]
+        parent: Option<String>,
+
+        /// Run the task's jobs ahead of (or behind) other queued work
+        #[arg(long, value_enum, default_value = "normal")]
+        priority: Priority,
+
+        /// Run in the project checkout itself rather than an environment of
+        /// its own (the `inplace` provider); one such task per project at a
+        /// time
+        #[arg(long, conflicts_with = "requirements")]
+        in_place: bool,
+
+        /// Follow task logs after creation
+        #[arg(short, long)]
+        attach: bool,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Create a new task with the same project, providers, description, labels, starting ref, group, and priority as an existing one
+    Duplicate {
+        /// Task ID to duplicate
+        #[arg(add = ArgValueCompleter::new(complete_task_ids))]
+        id: String,
+
+        /// Edit the description in your editor before creating the task
+        #[arg(short, long)]
+        edit: bool,
+
         /// Follow task logs after creation
         #[arg(short, long)]
         attach: bool,
 
         /// Output format
-        #[arg(long, default_value = "human")]
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Add a note to a task and its log, e.g. why it was retried
+    Note {
+        /// Task ID
+        #[arg(add = ArgValueCompleter::new(complete_task_ids))]
+        id: String,
+
+        /// Note text
+        text: String,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Add or remove task labels
+    Label {
+        /// Task ID
+        #[arg(add = ArgValueCompleter::new(complete_task_ids))]
+        id: String,
+
+        /// Labels to add
+        labels: Vec<String>,
+
+        /// Labels to remove (repeatable)
+        #[arg(long, value_name = "LABEL")]
+        remove: Vec<String>,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
         format: OutputFormat,
     },
 
+    /// Merge a complete task's branch into its project's base branch
+    Merge {
+        /// Task ID
+        #[arg(add = ArgValueCompleter::new(complete_task_ids))]
+        id: String,
+
+        /// Make one commit from the task's changes instead of merging its
+        /// commits
+        #[arg(long)]
+        squash: bool,
+
+        /// Only fetch the task's branch into the project's repository,
+        /// without merging it
+        #[arg(long, conflicts_with_all = ["squash", "delete_env"])]
+        fetch_only: bool,
+
+        /// Remove the task and its environment once merged
+        #[arg(long)]
+        delete_env: bool,
+    },
+
     /// Remove a task and its environment
     #[command(alias = "rm")]
     Remove {
@@ -360,13 +862,32 @@ enum TaskCommand {
         /// Skip provider cleanup and remove only database records
         #[arg(long)]
         skip_provider: bool,
+
+        /// Skip the confirmation prompt that --skip-provider asks for
+        #[arg(long, short)]
+        yes: bool,
     },
 
     /// List tasks
     #[command(alias = "ls")]
     List {
         /// Output format
-        #[arg(long, default_value = "human")]
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+
+        /// Order to list tasks in
+        #[arg(long, default_value = "created")]
+        sort: TaskSort,
+    },
+
+    /// Show a task and its result
+    Show {
+        /// Task ID
+        #[arg(add = ArgValueCompleter::new(complete_task_ids))]
+        id: String,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
         format: OutputFormat,
     },
 
@@ -379,6 +900,19 @@ enum TaskCommand {
         /// Follow log output in realtime
         #[arg(short = 'f', long = "follow")]
         follow: bool,
+
+        /// Strip color and other terminal escape sequences
+        #[arg(long)]
+        plain: bool,
+
+        /// Leave out the timestamp and stream that `[tasks] log-timestamps`
+        /// puts before each line
+        #[arg(long)]
+        raw: bool,
+
+        /// List the log's `::group::` sections instead of printing it
+        #[arg(long, conflicts_with = "follow")]
+        sections: bool,
     },
 
     /// Execute a provider-defined environment command for a task's environment
@@ -388,6 +922,10 @@ enum TaskCommand {
         #[arg(add = ArgValueCompleter::new(complete_task_ids))]
         id: String,
 
+        /// Record the session to the environment's log directory
+        #[arg(long)]
+        record: bool,
+
         /// Provider command name
         #[arg(add = ArgValueCompleter::new(complete_task_exec_commands))]
         command: String,
@@ -399,18 +937,204 @@ enum TaskCommand {
 }
 
 #[derive(Subcommand)]
-enum ConfigCommand {
-    /// Open the config file in $EDITOR
-    Edit,
-}
+enum JobCommand {
+    /// List jobs
+    #[command(alias = "ls")]
+    List {
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
 
-#[derive(Subcommand)]
+    /// Show a job and its effective retry policy
+    Show {
+        /// Job ID
+        id: String,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Requeue a dead job for a fresh set of attempts
+    Retry {
+        /// Job ID
+        id: String,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Delete all dead jobs
+    PurgeDead {
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+enum GroupCommand {
+    /// Create a task group; add tasks with `work task new --parent`
+    New {
+        /// Group description
+        description: String,
+
+        /// Project name (defaults to project matching current directory)
+        #[arg(long)]
+        project: Option<String>,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// List task groups and their status
+    #[command(alias = "ls")]
+    List {
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Show a group and its tasks
+    Show {
+        /// Group ID
+        #[arg(add = ArgValueCompleter::new(complete_group_ids))]
+        id: String,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Remove a group
+    #[command(alias = "rm")]
+    Remove {
+        /// Group ID
+        #[arg(add = ArgValueCompleter::new(complete_group_ids))]
+        id: String,
+
+        /// Also remove the group's tasks and their environments
+        #[arg(long)]
+        cascade: bool,
+
+        /// Skip provider cleanup for removed tasks and remove only database
+        /// records
+        #[arg(long)]
+        skip_provider: bool,
+
+        /// Skip the confirmation prompt that --cascade asks for
+        #[arg(long, short)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Open the config file in your editor ([editor] command or $EDITOR)
+    Edit,
+
+    /// Show effective project settings and where each one comes from
+    Show {
+        /// Project name (defaults to the project containing the current directory)
+        #[arg(long)]
+        project: Option<String>,
+
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProfileCommand {
+    /// List the default profile and every named profile that has been used
+    List {
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbCommand {
+    /// List records that refer to missing projects, environments, groups,
+    /// or paths, or are stuck mid-change; exits nonzero if there are any
+    Check {
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Offer a fix for each problem `work db check` finds
+    Repair {
+        /// Apply every available fix without asking
+        #[arg(long, short)]
+        yes: bool,
+    },
+
+    /// Prune old completed jobs and events and compact the database now,
+    /// rather than at the daemon's next maintenance pass
+    Maintain {
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+enum DebugCommand {
+    /// Bundle daemon logs, state, job history, redacted config, and log
+    /// tails into a tar.gz to attach to a bug report
+    Bundle {
+        /// Only this task's state and logs
+        #[arg(long, value_name = "ID", add = ArgValueCompleter::new(complete_task_ids))]
+        task: Option<String>,
+
+        /// Where to write the bundle (default: work-debug-TIMESTAMP.tar.gz)
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Run the jobs in a trace recorded with WORK_TRACE again, in the same
+    /// order, against mock providers in a scratch work home
+    Replay {
+        /// Trace file the daemon wrote
+        trace: std::path::PathBuf,
+
+        /// Scratch work home to replay in; must be new or empty (default: a
+        /// new directory under the system temp directory)
+        #[arg(long, value_name = "DIR")]
+        home: Option<std::path::PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
 enum DaemonCommand {
     /// Start the daemon
     Start {
         /// Remove existing runtime files before starting
         #[arg(long)]
         force: bool,
+
+        /// Start in the background and exit once the daemon is ready
+        #[arg(long)]
+        wait_ready: bool,
+    },
+
+    /// Re-read config and log what changed, like sending the daemon SIGHUP
+    Reload {
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
+    },
+
+    /// Show how many requests each peer has made since the daemon started
+    Peers {
+        /// Output format
+        #[arg(long, default_value = "human", env = "WORK_FORMAT")]
+        format: OutputFormat,
     },
 
     /// Install the daemon as a launchd LaunchAgent
@@ -418,6 +1142,10 @@ enum DaemonCommand {
 
     /// Uninstall the daemon LaunchAgent
     Uninstall,
+
+    /// Relay stdin and stdout to the daemon socket (used by --host)
+    #[command(hide = true)]
+    Proxy,
 }
 
 #[tokio::main]
@@ -473,7 +1201,7 @@ fn complete_env_ids(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
             let projects = client.list_projects().await?;
 
             let cwd = std::env::current_dir()?.canonicalize()?;
-            let current_project = projects.iter().find(|p| cwd.starts_with(&p.path));
+            let current_project = db::project_for_dir(&projects, &cwd);
 
             let candidates = envs
                 .iter()
@@ -501,6 +1229,33 @@ fn complete_env_ids(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
     result.ok().and_then(|r| r.ok()).unwrap_or_default()
 }
 
+fn complete_group_ids(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
+    let current = current.to_str().unwrap_or_default().to_owned();
+
+    let result = std::thread::spawn(move || -> anyhow::Result<Vec<CompletionCandidate>> {
+        let rt = tokio::runtime::Runtime::new()?;
+        rt.block_on(async {
+            paths::init(None);
+            let client = client::DaemonClient::new()?;
+            let groups = client.list_task_groups().await?;
+
+            let candidates = groups
+                .iter()
+                .filter(|g| g.id.starts_with(&current))
+                .map(|g| {
+                    let help = format!("{} ({})", g.description, g.status);
+                    CompletionCandidate::new(g.id.to_string()).help(Some(help.into()))
+                })
+                .collect();
+
+            Ok(candidates)
+        })
+    })
+    .join();
+
+    result.ok().and_then(|r| r.ok()).unwrap_or_default()
+}
+
 fn complete_task_ids(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
     let current = current.to_str().unwrap_or_default().to_owned();
 
@@ -515,7 +1270,7 @@ fn complete_task_ids(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
                 .iter()
                 .filter(|t| t.id.starts_with(&current))
                 .map(|t| {
-                    let help = format!("{} ({})", t.description, t.status);
+                    let help = format!("{} ({})", t.title(), t.status);
                     CompletionCandidate::new(t.id.to_string()).help(Some(help.into()))
                 })
                 .collect();
@@ -665,20 +1420,172 @@ fn print_env(env: &db::Environment, format: &OutputFormat) -> anyhow::Result<()>
             let path = env.metadata["worktree_path"].as_str().unwrap_or("-");
             let branch = env.metadata["branch"].as_str().unwrap_or("-");
             println!(
-                "\x1b[1;32m{}\x1b[0m \x1b[2m(id: {})\x1b[0m",
-                env.status, env.id
+                "{} {}",
+                output::paint(&env.status, Style::BoldGreen),
+                output::paint(&id_label(&env.id, env.short_id()), Style::Dim)
+            );
+            if let Some(name) = env.display_name() {
+                println!("  {}      {name}", output::paint("name:", Style::Bold));
+            }
+            println!(
+                "  {}  {}",
+                output::paint("provider:", Style::Bold),
+                env.provider
+            );
+            println!(
+                "  {}   {}",
+                output::paint("project:", Style::Bold),
+                env.project_id
             );
-            println!("  \x1b[1mprovider:\x1b[0m  {}", env.provider);
-            println!("  \x1b[1mproject:\x1b[0m   {}", env.project_id);
-            println!("  \x1b[1mbranch:\x1b[0m    {}", branch);
-            println!("  \x1b[1mpath:\x1b[0m      {}", path);
+            println!("  {}    {}", output::paint("branch:", Style::Bold), branch);
+            println!("  {}      {}", output::paint("path:", Style::Bold), path);
+            if let Some(progress) = env.progress_label() {
+                println!(
+                    "  {}  {}",
+                    output::paint("progress:", Style::Bold),
+                    progress
+                );
+            }
         }
         OutputFormat::Plain => {
             let path = env.metadata["worktree_path"].as_str().unwrap_or("");
             println!("{}\t{}\t{}\t{}", env.id, env.provider, env.status, path);
         }
         OutputFormat::Json => {
-            println!("{}", serde_json::to_string(env)?);
+            output::print_json(std::slice::from_ref(env))?;
+        }
+    }
+    Ok(())
+}
+
+fn print_plan(plan: &environment::EnvironmentPlan, format: &OutputFormat) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Human => {
+            for (i, step) in plan.steps.iter().enumerate() {
+                println!("{}. {step}", i + 1);
+            }
+            if let Some(branch) = &plan.branch {
+                println!("  {}    {}", output::paint("branch:", Style::Bold), branch);
+            }
+            for path in &plan.paths {
+                println!("  {}      {}", output::paint("path:", Style::Bold), path);
+            }
+            if let Some(bytes) = plan.estimated_disk_bytes {
+                println!(
+                    "  {}      ~{}",
+                    output::paint("disk:", Style::Bold),
+                    format_bytes(bytes)
+                );
+            }
+        }
+        OutputFormat::Plain => {
+            for step in &plan.steps {
+                println!("step\t{step}");
+            }
+            if let Some(branch) = &plan.branch {
+                println!("branch\t{branch}");
+            }
+            for path in &plan.paths {
+                println!("path\t{path}");
+            }
+            if let Some(bytes) = plan.estimated_disk_bytes {
+                println!("disk\t{bytes}");
+            }
+        }
+        OutputFormat::Json => {
+            output::print_json(std::slice::from_ref(plan))?;
+        }
+    }
+    Ok(())
+}
+
+/// `(id: ID, alias: t12)`, after the status in `show` output.
+fn id_label(id: &str, alias: Option<String>) -> String {
+    match alias {
+        Some(alias) => format!("(id: {id}, alias: {alias})"),
+        None => format!("(id: {id})"),
+    }
+}
+
+fn print_snapshot(
+    snapshot: &db::EnvironmentSnapshot,
+    verb: &str,
+    format: &OutputFormat,
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Json => output::print_json(&[snapshot])?,
+        OutputFormat::Human => {
+            println!(
+                "{} snapshot {} of environment {}",
+                output::paint(verb, Style::BoldGreen),
+                snapshot.label(),
+                snapshot.environment_id
+            );
+            print_snapshot_line(snapshot);
+        }
+        OutputFormat::Plain => println!(
+            "{}\t{}\t{}",
+            snapshot.id,
+            snapshot.name.as_deref().unwrap_or(""),
+            snapshot.created_at
+        ),
+    }
+    Ok(())
+}
+
+/// One snapshot, indented under an environment.
+fn print_snapshot_line(snapshot: &db::EnvironmentSnapshot) {
+    let size = snapshot
+        .size_bytes
+        .map_or(String::new(), |bytes| format!("  {}", format_bytes(bytes)));
+    let restored = snapshot.restored_at.as_deref().map_or(String::new(), |at| {
+        output::paint(&format!("  (restored {at})"), Style::Dim)
+    });
+    println!(
+        "    {}  {}{size}{restored}",
+        snapshot.label(),
+        output::paint(&snapshot.created_at, Style::Dim)
+    );
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+fn print_import_summary(summary: &db::ImportSummary, format: &OutputFormat) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Human => {
+            println!(
+                "projects: {} created, {} replaced, {} skipped",
+                summary.projects_created, summary.projects_replaced, summary.projects_skipped
+            );
+            println!(
+                "tasks:    {} imported, {} skipped",
+                summary.tasks_imported, summary.tasks_skipped
+            );
+        }
+        OutputFormat::Plain => {
+            println!(
+                "{}\t{}\t{}\t{}\t{}",
+                summary.projects_created,
+                summary.projects_replaced,
+                summary.projects_skipped,
+                summary.tasks_imported,
+                summary.tasks_skipped
+            );
+        }
+        OutputFormat::Json => {
+            output::print_json(std::slice::from_ref(summary))?;
         }
     }
     Ok(())
@@ -688,67 +1595,900 @@ fn print_task(task: &db::Task, format: &OutputFormat) -> anyhow::Result<()> {
     match format {
         OutputFormat::Human => {
             println!(
-                "\x1b[1;32m{}\x1b[0m \x1b[2m(id: {})\x1b[0m",
-                task.status, task.id
+                "{} {}",
+                output::paint(&task.status, Style::BoldGreen),
+                output::paint(&id_label(&task.id, task.short_id()), Style::Dim)
+            );
+            println!(
+                "  {}      {}",
+                output::paint("provider:", Style::Bold),
+                task.provider
+            );
+            println!(
+                "  {}       {}",
+                output::paint("project:", Style::Bold),
+                task.project_id
+            );
+            println!(
+                "  {}   {}",
+                output::paint("environment:", Style::Bold),
+                task.environment_id
+            );
+            if let Some(since) = task.in_status_for() {
+                println!("  {}     {since}", output::paint("in status:", Style::Bold));
+            }
+            if let Some(origin) = &task.origin {
+                println!(
+                    "  {}    {}",
+                    output::paint("created by:", Style::Bold),
+                    origin.describe()
+                );
+            }
+            // Continuation lines line up under the first.
+            let mut lines = task.description.lines();
+            println!(
+                "  {}   {}",
+                output::paint("description:", Style::Bold),
+                lines.next().unwrap_or_default()
+            );
+            for line in lines {
+                println!("{:17}{line}", "");
+            }
+            if let Some(group_id) = &task.group_id {
+                println!(
+                    "  {}         {group_id}",
+                    output::paint("group:", Style::Bold)
+                );
+            }
+            if let Some(source_ref) = &task.source_ref {
+                println!(
+                    "  {}      {source_ref}",
+                    output::paint("from ref:", Style::Bold)
+                );
+            }
+            if task.priority != db::TaskPriority::Normal {
+                println!(
+                    "  {}      {}",
+                    output::paint("priority:", Style::Bold),
+                    task.priority.as_str()
+                );
+            }
+            if !task.requirements.is_empty() {
+                println!(
+                    "  {}      {}",
+                    output::paint("requires:", Style::Bold),
+                    task.requirements.join(", ")
+                );
+            }
+            if let Some(summary) = &task.result_summary {
+                println!(
+                    "  {}        {summary}",
+                    output::paint("result:", Style::Bold)
+                );
+            }
+            if let Some(data) = &task.result_data {
+                println!(
+                    "  {}   {}",
+                    output::paint("result data:", Style::Bold),
+                    serde_json::to_string(data)?
+                );
+            }
+            if let Some(usage) = &task.resource_usage {
+                println!(
+                    "  {}         {}",
+                    output::paint("usage:", Style::Bold),
+                    format_resource_usage(usage)
+                );
+            }
+            if let Some(cost) = &task.cost {
+                println!("  {}          {cost}", output::paint("cost:", Style::Bold));
+            }
+            if let Ok(diff_path) = paths::task_diff_path(&task.id)
+                && diff_path.exists()
+            {
+                println!(
+                    "  {}          {}",
+                    output::paint("diff:", Style::Bold),
+                    diff_path.display()
+                );
+            }
+            if !task.labels.is_empty() {
+                println!(
+                    "  {}        {}",
+                    output::paint("labels:", Style::Bold),
+                    task.labels.join(", ")
+                );
+            }
+            for (i, note) in task.notes.iter().enumerate() {
+                let heading = if i == 0 { "notes:" } else { "" };
+                println!(
+                    "  {}{}{} {}",
+                    output::paint(heading, Style::Bold),
+                    " ".repeat(15 - heading.len()),
+                    output::paint(&note.at, Style::Dim),
+                    note.text
+                );
+            }
+        }
+        OutputFormat::Plain => {
+            println!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                task.id,
+                task.provider,
+                task.status,
+                task.environment_id,
+                task.group_id.as_deref().unwrap_or_default(),
+                task.priority.as_str(),
+                output::plain_field(&task.description)
+            );
+        }
+        OutputFormat::Json => {
+            output::print_json(std::slice::from_ref(task))?;
+        }
+    }
+    Ok(())
+}
+
+fn print_job(detail: &db::JobDetail, format: &OutputFormat) -> anyhow::Result<()> {
+    let job = &detail.job;
+    let policy = &detail.retry_policy;
+    match format {
+        OutputFormat::Human => {
+            println!(
+                "{} {}",
+                output::paint(&job.status, Style::BoldGreen),
+                output::paint(&format!("(id: {})", job.id), Style::Dim)
+            );
+            println!(
+                "  {}        {}",
+                output::paint("type:", Style::Bold),
+                job.job_type
+            );
+            println!(
+                "  {}     {} of {}",
+                output::paint("attempt:", Style::Bold),
+                job.attempt + 1,
+                policy.limit + 1
+            );
+            println!(
+                "  {}     {} (backoff up to {}s)",
+                output::paint("retries:", Style::Bold),
+                policy.limit,
+                policy.max_delay_seconds
+            );
+            if let Some(not_before) = &job.not_before {
+                println!(
+                    "  {}  {not_before}",
+                    output::paint("not before:", Style::Bold)
+                );
+            }
+            if let Some(error) = &job.last_error {
+                println!("  {}  {error}", output::paint("last error:", Style::Bold));
+            }
+            if !job.errors.is_empty() {
+                println!("  {}", output::paint("errors:", Style::Bold));
+                for error in &job.errors {
+                    println!(
+                        "    {} {}",
+                        output::paint(
+                            &format!("attempt {} at {}:", error.attempt, error.at),
+                            Style::Dim
+                        ),
+                        error.error
+                    );
+                }
+            }
+            println!(
+                "  {}     {}",
+                output::paint("payload:", Style::Bold),
+                serde_json::to_string(&job.payload)?
             );
-            println!("  \x1b[1mprovider:\x1b[0m      {}", task.provider);
-            println!("  \x1b[1mproject:\x1b[0m       {}", task.project_id);
-            println!("  \x1b[1menvironment:\x1b[0m   {}", task.environment_id);
-            println!("  \x1b[1mdescription:\x1b[0m   {}", task.description);
         }
         OutputFormat::Plain => {
             println!(
-                "{}\t{}\t{}\t{}",
-                task.id, task.provider, task.status, task.description
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                job.id,
+                job.job_type,
+                job.status,
+                job.attempt,
+                policy.limit,
+                policy.max_delay_seconds
             );
         }
         OutputFormat::Json => {
-            println!("{}", serde_json::to_string(task)?);
+            output::print_json(std::slice::from_ref(detail))?;
         }
     }
     Ok(())
 }
 
+fn format_resource_usage(usage: &db::ResourceUsage) -> String {
+    format!(
+        "wall {:.1}s, cpu {:.1}s user / {:.1}s sys, peak rss {:.1} MB",
+        usage.wall_ms as f64 / 1000.0,
+        usage.user_cpu_ms as f64 / 1000.0,
+        usage.system_cpu_ms as f64 / 1000.0,
+        usage.peak_rss_kb as f64 / 1024.0
+    )
+}
+
 fn resolve_project(projects: &[db::Project], name: Option<String>) -> anyhow::Result<&db::Project> {
     if let Some(name) = name {
         return projects
             .iter()
-            .find(|p| p.name == name)
+            .find(|p| p.answers_to(&name))
             .ok_or_else(|| anyhow::anyhow!("project not found: {name}"));
     }
 
     let cwd = std::env::current_dir()?;
     let cwd = cwd.canonicalize()?;
 
-    projects
-        .iter()
-        .find(|p| cwd.starts_with(&p.path))
-        .ok_or_else(|| {
-            anyhow::anyhow!(
-                "could not determine project from current directory; specify a project name"
-            )
-        })
+    db::project_for_dir(projects, &cwd).ok_or_else(|| {
+        anyhow::anyhow!(
+            "could not determine project from current directory; specify a project name"
+        )
+    })
 }
 
-async fn follow_task_logs(client: &client::DaemonClient, task_id: &str) -> anyhow::Result<()> {
-    use std::io::Write;
+/// Counts for the project the current directory is in. The database is
+/// read directly, skipping the daemon, so this stays fast enough to run
+/// for every shell prompt.
+fn print_status(porcelain: bool) -> anyhow::Result<()> {
+    if !db::db_path()?.exists() {
+        return Ok(());
+    }
+    db::set_read_only();
+    let projects = db::list_projects()?;
+    let Ok(project) = resolve_project(&projects, None) else {
+        return Ok(());
+    };
+    let counts = db::status_counts(&project.id)?;
+    let running = counts.tasks_in(&["started"]);
+    let pending = counts.tasks_in(&["pending"]);
+    let failed = counts.tasks_in(&["failed", "timed_out", "interrupted"]);
+    let pool = counts.environments.get("pool").copied().unwrap_or(0);
+    if porcelain {
+        println!(
+            "project={} running={running} pending={pending} failed={failed} pool={pool}",
+            project.name
+        );
+    } else {
+        println!(
+            "{}: {running} running, {pending} pending, {failed} failed; {pool} pooled {}",
+            project.name,
+            if pool == 1 {
+                "environment"
+            } else {
+                "environments"
+            }
+        );
+    }
+    Ok(())
+}
 
-    client
-        .tail_task_logs(task_id, |chunk| {
-            let _ = std::io::stdout().write_all(chunk);
-        })
-        .await
+fn print_events(events: &[db::Event], format: &OutputFormat) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Human => {
+            if events.is_empty() {
+                return Ok(());
+            }
+            let mut table = Table::new(&["SEQ", "TIME", "KIND", "ID", "ACTION", "STATUS"]);
+            for event in events {
+                let status = event.status.as_deref().unwrap_or("");
+                table.row(vec![
+                    event.seq.to_string().into(),
+                    event.created_at.as_str().into(),
+                    event.kind.as_str().into(),
+                    event.subject_id.as_str().into(),
+                    event.action.as_str().into(),
+                    Cell::styled(status, output::status_style(status)),
+                ]);
+            }
+            table.print();
+        }
+        OutputFormat::Plain => {
+            for event in events {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    event.seq,
+                    event.created_at,
+                    event.kind,
+                    event.subject_id,
+                    event.action,
+                    event.status.as_deref().unwrap_or("")
+                );
+            }
+        }
+        OutputFormat::Json => output::print_json(events)?,
+    }
+    Ok(())
+}
+
+fn print_search_hits(hits: &[db::SearchHit], format: &OutputFormat) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Human => {
+            for hit in hits {
+                let description = hit.task.title();
+                println!(
+                    "{}  {}  {description}",
+                    output::paint(&hit.task.id, Style::Dim),
+                    output::paint(
+                        &hit.task.status,
+                        output::status_style(&hit.task.status).unwrap_or(Style::Bold)
+                    ),
+                );
+                println!(
+                    "  {} {}",
+                    output::paint(&format!("{}:", hit.matched), Style::Dim),
+                    highlight_snippet(&hit.snippet, &hit.highlights)
+                );
+            }
+        }
+        OutputFormat::Plain => {
+            for hit in hits {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    hit.task.id, hit.task.status, hit.matched, hit.snippet
+                );
+            }
+        }
+        OutputFormat::Json => {
+            output::print_json(hits)?;
+        }
+    }
+    Ok(())
+}
+
+fn highlight_snippet(snippet: &str, highlights: &[(usize, usize)]) -> String {
+    let chars: Vec<char> = snippet.chars().collect();
+    let mut out = String::new();
+    let mut pos = 0;
+    for &(start, end) in highlights {
+        let (start, end) = (start.clamp(pos, chars.len()), end.min(chars.len()));
+        if start >= end {
+            continue;
+        }
+        out.extend(&chars[pos..start]);
+        let matched: String = chars[start..end].iter().collect();
+        out.push_str(&output::paint(&matched, Style::BoldRed));
+        pos = end;
+    }
+    out.extend(&chars[pos..]);
+    out
+}
+
+#[derive(serde::Serialize)]
+struct ProfileSummary {
+    name: String,
+    current: bool,
+    daemon: &'static str,
+    data_dir: std::path::PathBuf,
+}
+
+#[derive(serde::Serialize)]
+struct UpdateCheck<'a> {
+    current: &'a str,
+    latest: &'a str,
+    update_available: bool,
+    url: &'a str,
+    upgrade_command: Option<&'a str>,
+}
+
+#[derive(serde::Serialize)]
+struct ConfigSetting {
+    key: &'static str,
+    value: Option<String>,
+    source: Option<config::ConfigSource>,
+}
+
+fn config_settings(config: &config::Config, project_name: Option<&str>) -> Vec<ConfigSetting> {
+    let setting = |key, found: Option<(String, config::ConfigSource)>| {
+        let (value, source) = found.unzip();
+        ConfigSetting { key, value, source }
+    };
+    let global = |value: &Option<String>| {
+        value
+            .clone()
+            .map(|value| (value, config::ConfigSource::Global))
+    };
+
+    let Some(name) = project_name else {
+        return vec![
+            setting("task-provider", global(&config.task_provider)),
+            setting("environment-provider", global(&config.environment_provider)),
+        ];
+    };
+
+    let env_provider = config.environment_provider_setting(name);
+    let base_branch = env_provider
+        .as_ref()
+        .and_then(|(provider, _)| config.base_branch_setting(name, provider));
+    let sparse_paths = env_provider
+        .as_ref()
+        .and_then(|(provider, _)| config.sparse_paths_setting(name, provider))
+        .map(|(paths, source)| (paths.join(" "), source));
+    vec![
+        setting("task-provider", config.task_provider_setting(name)),
+        setting("environment-provider", env_provider),
+        setting("base-branch", base_branch),
+        setting("sparse-paths", sparse_paths),
+    ]
+}
+
+fn source_label(
+    config: &config::Config,
+    project_name: Option<&str>,
+    setting: &ConfigSetting,
+) -> String {
+    match &setting.source {
+        Some(config::ConfigSource::Project) => {
+            format!(
+                "config.toml [projects.{}]",
+                project_name.unwrap_or_default()
+            )
+        }
+        Some(config::ConfigSource::Repository) => config
+            .repository
+            .as_ref()
+            .map(|r| r.path.display().to_string())
+            .unwrap_or_default(),
+        Some(config::ConfigSource::Global) => "config.toml".to_string(),
+        Some(config::ConfigSource::Provider) => format!(
+            "config.toml [environments.providers.{}]",
+            project_name
+                .and_then(|name| config.default_environment_provider_for_project(name))
+                .unwrap_or_default()
+        ),
+        None => "-".to_string(),
+    }
+}
+
+fn print_config(
+    config: &config::Config,
+    project_name: Option<&str>,
+    format: &OutputFormat,
+) -> anyhow::Result<()> {
+    let settings = config_settings(config, project_name);
+    let repository_path = config.repository.as_ref().map(|r| r.path.clone());
+
+    match format {
+        OutputFormat::Human => {
+            println!(
+                "{} {}",
+                output::paint("config:    ", Style::Bold),
+                config::path()?.display()
+            );
+            if let Some(name) = project_name {
+                println!("{} {name}", output::paint("project:   ", Style::Bold));
+            }
+            if let Some(path) = &repository_path {
+                println!(
+                    "{} {}",
+                    output::paint("repository:", Style::Bold),
+                    path.display()
+                );
+            }
+            println!();
+
+            let mut table = Table::new(&["SETTING", "VALUE", "SOURCE"]);
+            for setting in &settings {
+                table.row(vec![
+                    setting.key.into(),
+                    setting.value.as_deref().unwrap_or("-").into(),
+                    Cell::styled(
+                        source_label(config, project_name, setting),
+                        Some(Style::Dim),
+                    ),
+                ]);
+            }
+            table.print();
+        }
+        OutputFormat::Plain => {
+            for setting in &settings {
+                println!(
+                    "{}\t{}\t{}",
+                    setting.key,
+                    setting.value.as_deref().unwrap_or(""),
+                    source_label(config, project_name, setting)
+                );
+            }
+        }
+        OutputFormat::Json => {
+            output::print_json(&[serde_json::json!({
+                "config_path": config::path()?,
+                "project": project_name,
+                "repository_path": repository_path,
+                "settings": settings,
+            })])?;
+        }
+    }
+    Ok(())
+}
+
+/// Read a task description piped to `work task new -`, keeping its lines.
+fn read_description_from_stdin() -> anyhow::Result<String> {
+    use std::io::Read;
+
+    let mut description = String::new();
+    std::io::stdin().read_to_string(&mut description)?;
+    let description = description.trim_end().to_string();
+    if description.trim().is_empty() {
+        anyhow::bail!("task description is empty");
+    }
+    Ok(description)
+}
+
+/// Let the user edit a task description in their editor. An empty result aborts.
+fn edit_description(initial: &str) -> anyhow::Result<String> {
+    let config = config::load()?;
+    let path = std::env::temp_dir().join(format!("work-task-{}.md", std::process::id()));
+    std::fs::write(&path, initial)?;
+    let edited = editor::edit(&config, &path);
+    let _ = std::fs::remove_file(&path);
+
+    let description = edited?.trim_end().to_string();
+    if description.trim().is_empty() {
+        anyhow::bail!("aborting: task description is empty");
+    }
+    Ok(description)
+}
+
+/// A daemon client for this machine or `host`, first waiting up to
+/// `wait_secs` for the daemon to be ready. With `auto-start-daemon`, a local
+/// daemon that isn't running is started by the first request.
+async fn connect(
+    config: &config::Config,
+    host: Option<&str>,
+    wait_secs: Option<u64>,
+) -> anyhow::Result<client::DaemonClient> {
+    let client = match host {
+        Some(host) => client::DaemonClient::over_ssh(config.host(host)),
+        None if config.auto_start_daemon => client::DaemonClient::new()?.auto_start(
+            std::time::Duration::from_secs(wait_secs.unwrap_or(DAEMON_START_TIMEOUT_SECS)),
+            |message| eprintln!("{message}"),
+        ),
+        None => client::DaemonClient::new()?,
+    }
+    .origin("cli");
+    // The restart hint is for the daemon on this machine, so only it is
+    // checked.
+    let client = if host.is_none() {
+        client.on_version(warn_if_daemon_differs)
+    } else {
+        client
+    };
+    if let Some(secs) = wait_secs {
+        client
+            .wait_until_ready(std::time::Duration::from_secs(secs))
+            .await?;
+    }
+    Ok(client)
+}
+
+/// Warn when the local daemon runs a different version of `work` than this
+/// one, as a daemon left running across an upgrade does.
+fn warn_if_daemon_differs(version: &str) {
+    if version == self_update::CURRENT_VERSION {
+        return;
+    }
+    let running = if version.is_empty() {
+        "an older work".to_string()
+    } else {
+        format!("work {version}")
+    };
+    eprintln!(
+        "{} the daemon is running {running} but this is work {}; to restart it, {}",
+        output::paint("warning:", Style::Yellow),
+        self_update::CURRENT_VERSION,
+        daemon::restart_hint()
+    );
+}
+
+/// A client for the local daemon or, if it isn't running, one that reads
+/// the database directly. Only for commands that change nothing. With
+/// `auto-start-daemon`, the daemon is started instead.
+async fn connect_or_read_database(config: &config::Config) -> anyhow::Result<client::DaemonClient> {
+    if config.auto_start_daemon {
+        return connect(config, None, None).await;
+    }
+    let client = client::DaemonClient::new()?.on_version(warn_if_daemon_differs);
+    if client.ready().await.is_ok() {
+        return Ok(client);
+    }
+    eprintln!(
+        "{} the daemon is not running; showing state read directly from the database (read-only)",
+        output::paint("warning:", Style::Yellow)
+    );
+    Ok(client::DaemonClient::read_only())
+}
+
+/// Check GitHub for a newer release and, unless `check_only`, install it.
+/// Installs managed by Homebrew or mise are left to them.
+fn self_update(check_only: bool, format: &OutputFormat) -> anyhow::Result<()> {
+    let current = self_update::CURRENT_VERSION;
+    let release = self_update::latest_release()?;
+    let latest = release.version();
+    let update_available = self_update::is_newer(latest, current);
+    let method = self_update::InstallMethod::detect()?;
+
+    if check_only || !update_available {
+        let check = UpdateCheck {
+            current,
+            latest,
+            update_available,
+            url: &release.html_url,
+            upgrade_command: method.upgrade_command(),
+        };
+        match format {
+            OutputFormat::Human => {
+                if update_available {
+                    println!("work {latest} is available (this is {current})");
+                    println!("{}", release.html_url);
+                    match method.upgrade_command() {
+                        Some(command) => println!("upgrade with `{command}`"),
+                        None => println!("upgrade with `work self-update`"),
+                    }
+                } else {
+                    println!("work {current} is up to date");
+                }
+            }
+            OutputFormat::Plain => {
+                println!("{current}\t{latest}\t{update_available}");
+            }
+            OutputFormat::Json => output::print_json(&[check])?,
+        }
+        return Ok(());
+    }
+
+    let exe = match &method {
+        self_update::InstallMethod::Standalone(exe) => exe,
+        _ => anyhow::bail!(
+            "work {latest} is available, but this work is managed by a package manager; run `{}`",
+            method.upgrade_command().unwrap_or_default()
+        ),
+    };
+    self_update::install(&release, exe)?;
+    println!("updated work {current} -> {latest}");
+    if daemon::is_running(&paths::runtime_dir()?) {
+        println!(
+            "the running daemon still uses work {current}; to restart it, {}",
+            daemon::restart_hint()
+        );
+    }
+    Ok(())
+}
+
+fn print_doctor(checks: &[doctor::Check], format: &OutputFormat) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Human => {
+            for check in checks {
+                let (mark, style) = match check.status {
+                    doctor::Status::Pass => ("ok", Style::Green),
+                    doctor::Status::Warn => ("warn", Style::Yellow),
+                    doctor::Status::Fail => ("FAIL", Style::BoldRed),
+                };
+                println!(
+                    "{} {} {}",
+                    output::paint(&format!("{mark:<4}"), style),
+                    output::paint(&format!("{:<9}", check.name), Style::Bold),
+                    check.message
+                );
+                if let Some(hint) = &check.hint {
+                    println!("               {}", output::paint(hint, Style::Dim));
+                }
+            }
+        }
+        OutputFormat::Plain => {
+            for check in checks {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    check.name,
+                    check.status.as_str(),
+                    check.message,
+                    check.hint.as_deref().unwrap_or("")
+                );
+            }
+        }
+        OutputFormat::Json => output::print_json(checks)?,
+    }
+    Ok(())
+}
+
+/// Whether `command` only lists or shows state, so it can run without the
+/// daemon.
+fn reads_only(command: &Command) -> bool {
+    match command {
+        Command::Project { command } => matches!(command, ProjectCommand::List { .. }),
+        Command::Environment { command } => matches!(
+            command,
+            EnvironmentCommand::List { .. }
+                | EnvironmentCommand::Show { .. }
+                | EnvironmentCommand::Logs { follow: false, .. }
+                | EnvironmentCommand::Provider { .. }
+        ),
+        Command::Task { command } => matches!(
+            command,
+            TaskCommand::List { .. }
+                | TaskCommand::Show { .. }
+                | TaskCommand::Logs { follow: false, .. }
+        ),
+        Command::Job { command } => {
+            matches!(command, JobCommand::List { .. } | JobCommand::Show { .. })
+        }
+        Command::Group { command } => {
+            matches!(
+                command,
+                GroupCommand::List { .. } | GroupCommand::Show { .. }
+            )
+        }
+        Command::Db { command } => matches!(command, DbCommand::Check { .. }),
+        Command::Events { .. }
+        | Command::Stats { .. }
+        | Command::List { .. }
+        | Command::Logs { follow: false, .. } => true,
+        _ => false,
+    }
+}
+
+/// The global config with the project's `.work.toml` layered in.
+fn project_config(project: &db::Project) -> anyhow::Result<config::Config> {
+    config::load_for_project(&project.name, std::path::Path::new(&project.path))
+}
+
+/// What `--raw` and `--plain` take out of a log as it's printed.
+struct LogFilter {
+    unstamper: Option<work::log_stamp::Unstamper>,
+    stripper: Option<work::ansi::Stripper>,
+}
+
+impl LogFilter {
+    fn new(plain: bool, raw: bool) -> Self {
+        Self {
+            unstamper: raw.then(work::log_stamp::Unstamper::default),
+            stripper: plain.then(work::ansi::Stripper::default),
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let chunk = match &mut self.unstamper {
+            Some(unstamper) => unstamper.push(chunk),
+            None => chunk.to_vec(),
+        };
+        match &mut self.stripper {
+            Some(stripper) => stripper.push(&chunk),
+            None => chunk,
+        }
+    }
+
+    /// The end of a log whose last line was too short to tell if it had a
+    /// prefix.
+    fn finish(&mut self) -> Vec<u8> {
+        let rest = self
+            .unstamper
+            .as_mut()
+            .map(work::log_stamp::Unstamper::finish)
+            .unwrap_or_default();
+        match &mut self.stripper {
+            Some(stripper) => stripper.push(&rest),
+            None => rest,
+        }
+    }
+}
+
+async fn follow_task_logs(
+    client: &client::DaemonClient,
+    task_id: &str,
+    plain: bool,
+    raw: bool,
+) -> anyhow::Result<()> {
+    use std::io::Write;
+
+    let mut filter = LogFilter::new(plain, raw);
+    client
+        .tail_task_logs(task_id, |chunk| {
+            let _ = std::io::stdout().write_all(&filter.push(chunk));
+        })
+        .await?;
+    let _ = std::io::stdout().write_all(&filter.finish());
+    Ok(())
+}
+
+/// Ask for `expected`, such as the name of what is being removed, to be typed
+/// back before going ahead with something destructive that `warning`
+/// describes. `yes` skips the prompt, and is required without a terminal.
+fn confirm_by_typing(warning: &str, expected: &str, yes: bool) -> anyhow::Result<()> {
+    use std::io::Write;
+
+    if yes {
+        return Ok(());
+    }
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!("{warning}; pass --yes to confirm without a terminal");
+    }
+    eprintln!("{} {warning}", output::paint("warning:", Style::Bold));
+    eprint!("type {} to confirm: ", output::paint(expected, Style::Bold));
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if answer.trim() != expected {
+        anyhow::bail!("confirmation did not match {expected:?}; nothing was changed");
+    }
+    Ok(())
+}
+
+/// Walk through each problem `work db check` finds, asking before applying
+/// its fix unless `yes` is set.
+async fn repair_database(client: &client::DaemonClient, yes: bool) -> anyhow::Result<()> {
+    use std::io::Write;
+
+    let problems = client.check_integrity().await?;
+    if problems.is_empty() {
+        println!("no problems found");
+        return Ok(());
+    }
+    if !yes && !std::io::stdin().is_terminal() {
+        anyhow::bail!("stdin is not a terminal; pass --yes to apply every fix");
+    }
+
+    let mut keys = Vec::new();
+    let mut manual = Vec::new();
+    for problem in &problems {
+        println!("{} {}: {}", problem.kind, problem.id, problem.message);
+        let Some(fix) = &problem.fix else {
+            println!("  no automatic fix");
+            manual.push(problem);
+            continue;
+        };
+        if yes {
+            println!("  fix: {fix}");
+            keys.push(problem.key());
+            continue;
+        }
+        print!("  fix: {fix}? [y/N] ");
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if matches!(answer.trim(), "y" | "Y" | "yes") {
+            keys.push(problem.key());
+        }
+    }
+
+    if !keys.is_empty() {
+        let summary = client.repair_integrity(&keys).await?;
+        println!(
+            "fixed {} problem(s){}",
+            summary.fixed.len(),
+            match summary.skipped.len() {
+                0 => String::new(),
+                n => format!("; {n} were already gone"),
+            }
+        );
+    }
+    let left = problems.len() - keys.len();
+    if left > 0 {
+        anyhow::bail!(
+            "{left} problem(s) left{}",
+            if manual.is_empty() {
+                ""
+            } else {
+                "; some need fixing by hand"
+            }
+        );
+    }
+    Ok(())
 }
 
 async fn follow_environment_logs(
     client: &client::DaemonClient,
     env_id: &str,
+    plain: bool,
 ) -> anyhow::Result<()> {
     use std::io::Write;
 
+    let mut filter = LogFilter::new(plain, false);
     client
         .tail_environment_logs(env_id, |chunk| {
-            let _ = std::io::stdout().write_all(chunk);
+            let _ = std::io::stdout().write_all(&filter.push(chunk));
         })
         .await
 }
@@ -796,34 +2536,404 @@ fn execute_run_spec(run_spec: environment::RunSpec) -> anyhow::Result<()> {
     anyhow::bail!("process {program} terminated by signal");
 }
 
+/// Run `work ARGS` on `host` over SSH, with a terminal if this one has one.
+/// For commands that act on files only the host has.
+fn run_on_host(host: &config::HostConfig, args: &[&str]) -> anyhow::Result<()> {
+    let args: Vec<String> = args.iter().map(|a| environment::shell_quote(a)).collect();
+    let tty = if std::io::stdin().is_terminal() {
+        "-t"
+    } else {
+        "-T"
+    };
+    execute_run_spec(environment::RunSpec {
+        program: "ssh".to_string(),
+        args: vec![
+            tty.to_string(),
+            host.ssh.clone(),
+            host.remote_command(&args.join(" ")),
+        ],
+        cwd: None,
+        stdin_data: None,
+        env: Vec::new(),
+    })
+}
+
 async fn exec_environment_command(
     client: &client::DaemonClient,
+    config: &config::Config,
     env_id: &str,
     provider_command: &str,
     provider_args: &[String],
+    record: bool,
 ) -> anyhow::Result<()> {
+    // Provider commands act on the host's checkouts, so run them there.
+    if let Some(host) = client.host() {
+        let mut args = vec!["env", "exec"];
+        if record {
+            args.push("--record");
+        }
+        args.extend([env_id, provider_command]);
+        args.extend(provider_args.iter().map(String::as_str));
+        return run_on_host(host, &args);
+    }
+
     let env = client.get_environment(env_id).await?;
+    let projects = client.list_projects().await?;
+    let project = projects
+        .iter()
+        .find(|p| p.id == env.project_id)
+        .ok_or_else(|| anyhow::anyhow!("project {} not found", env.project_id))?;
     let provider = environment::get_provider(&env.provider)?;
-    let run_spec = provider.exec(&env.metadata, provider_command, provider_args)?;
+    let mut run_spec = provider
+        .exec(&env.metadata, provider_command, provider_args)?
+        .in_environment(&env.id, &project.path);
+
+    // Only sessions someone types into are worth recording; piped and
+    // scripted commands have their input on hand already.
+    let interactive = run_spec.stdin_data.is_none() && std::io::stdin().is_terminal();
+    if (record || config.records_sessions()) && interactive {
+        let dir = paths::environment_session_dir(&env.id)?;
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(format!(
+            "{}-{provider_command}.log",
+            chrono::Utc::now().format("%Y%m%dT%H%M%SZ")
+        ));
+        eprintln!("recording session to {}", path.display());
+        run_spec = run_spec.recorded(&path);
+    } else if record {
+        eprintln!("not recording: --record needs an interactive terminal");
+    }
     execute_run_spec(run_spec)
 }
 
 async fn exec_task_command(
     client: &client::DaemonClient,
+    config: &config::Config,
     task_id: &str,
     provider_command: &str,
     provider_args: &[String],
+    record: bool,
 ) -> anyhow::Result<()> {
     let task = client.get_task(task_id).await?;
     exec_environment_command(
         client,
+        config,
         &task.environment_id,
         provider_command,
         provider_args,
+        record,
     )
     .await
 }
 
+/// Recorded exec sessions for an environment, oldest first.
+fn session_recordings(env_id: &str) -> anyhow::Result<Vec<std::path::PathBuf>> {
+    let dir = paths::environment_session_dir(env_id)?;
+    let mut recordings = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    recordings.sort();
+    Ok(recordings)
+}
+
+/// Headers for the ID columns of a task or environment table, which
+/// `id-format` picks.
+fn id_headers(format: config::IdFormat) -> &'static [&'static str] {
+    match format {
+        config::IdFormat::Full => &["ALIAS", "ID"],
+        config::IdFormat::Alias => &["ID"],
+    }
+}
+
+/// Cells under [`id_headers`]. Without an alias, as from an older daemon,
+/// the full ID is shown either way.
+fn id_cells(format: config::IdFormat, id: &str, alias: Option<String>) -> Vec<Cell> {
+    match (format, alias) {
+        (config::IdFormat::Full, alias) => vec![
+            Cell::styled(alias.unwrap_or_else(|| "-".to_string()), Some(Style::Dim)),
+            id.into(),
+        ],
+        (config::IdFormat::Alias, Some(alias)) => vec![alias.into()],
+        (config::IdFormat::Alias, None) => vec![id.into()],
+    }
+}
+
+/// A provider's environments by status, e.g. `2 pool, 1 in_use`.
+fn format_environment_counts(counts: &std::collections::BTreeMap<String, i64>) -> String {
+    if counts.is_empty() {
+        return "none".to_string();
+    }
+    counts
+        .iter()
+        .map(|(status, n)| format!("{n} {status}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn print_provider_stats(
+    providers: &[db::ProviderStats],
+    format: &OutputFormat,
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Human => {
+            let mut table = Table::new(&[
+                "PROVIDER",
+                "PREPARES",
+                "FAILED",
+                "AVG PREPARE",
+                "ENVIRONMENTS",
+            ])
+            .truncate("ENVIRONMENTS");
+            for p in providers {
+                let failed = match p.failure_rate {
+                    Some(rate) => format!("{} ({:.0}%)", p.prepare_failures, rate * 100.0),
+                    None => "-".to_string(),
+                };
+                let average = match p.average_prepare_ms {
+                    Some(ms) => format!("{:.1}s", ms as f64 / 1000.0),
+                    None => "-".to_string(),
+                };
+                table.row(vec![
+                    p.provider.as_str().into(),
+                    p.prepares.to_string().into(),
+                    failed.into(),
+                    average.into(),
+                    format_environment_counts(&p.environments).into(),
+                ]);
+            }
+            table.print();
+        }
+        OutputFormat::Plain => {
+            for p in providers {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    p.provider,
+                    p.prepares,
+                    p.prepare_failures,
+                    p.average_prepare_ms
+                        .map(|ms| ms.to_string())
+                        .unwrap_or_default()
+                );
+            }
+        }
+        OutputFormat::Json => {
+            output::print_json(providers)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_cost_stats(stats: &[db::CostStats], format: &OutputFormat) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Human => {
+            let mut table = Table::new(&[
+                "PROJECT",
+                "MONTH",
+                "TASKS",
+                "TOKENS IN",
+                "TOKENS OUT",
+                "COST",
+            ]);
+            for s in stats {
+                table.row(vec![
+                    s.project.as_str().into(),
+                    s.month.as_str().into(),
+                    s.tasks.to_string().into(),
+                    s.tokens_in.to_string().into(),
+                    s.tokens_out.to_string().into(),
+                    format!("${:.2}", s.cost_usd).into(),
+                ]);
+            }
+            table.print();
+        }
+        OutputFormat::Plain => {
+            for s in stats {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{:.4}",
+                    s.project, s.month, s.tasks, s.tokens_in, s.tokens_out, s.cost_usd
+                );
+            }
+        }
+        OutputFormat::Json => {
+            output::print_json(stats)?;
+        }
+    }
+    Ok(())
+}
+
+/// A group's task counts, e.g. `1 running, 2 complete`.
+fn format_group_counts(counts: &db::TaskGroupCounts) -> String {
+    let parts: Vec<String> = [
+        (counts.running, "running"),
+        (counts.pending, "pending"),
+        (counts.failed, "failed"),
+        (counts.complete, "complete"),
+    ]
+    .into_iter()
+    .filter(|(n, _)| *n > 0)
+    .map(|(n, label)| format!("{n} {label}"))
+    .collect();
+    if parts.is_empty() {
+        "no tasks".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+fn print_task_group(
+    group: &db::TaskGroup,
+    tasks: &[db::Task],
+    format: &OutputFormat,
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Human => {
+            println!(
+                "{} {}",
+                output::paint(&group.status, Style::BoldGreen),
+                output::paint(&format!("(id: {})", group.id), Style::Dim)
+            );
+            println!(
+                "  {}       {}",
+                output::paint("project:", Style::Bold),
+                group.project_id
+            );
+            println!(
+                "  {}   {}",
+                output::paint("description:", Style::Bold),
+                group.description
+            );
+            println!(
+                "  {}         {}",
+                output::paint("tasks:", Style::Bold),
+                format_group_counts(&group.counts)
+            );
+            if !tasks.is_empty() {
+                println!();
+                let mut table =
+                    Table::new(&["ID", "STATUS", "DESCRIPTION"]).truncate("DESCRIPTION");
+                for t in tasks {
+                    table.row(vec![
+                        Cell::from(t.id.as_str()),
+                        Cell::styled(&t.status, output::status_style(&t.status)),
+                        Cell::from(t.title()),
+                    ]);
+                }
+                table.print();
+            }
+        }
+        OutputFormat::Plain => {
+            println!(
+                "{}\t{}\t{}\t{}",
+                group.id, group.status, group.counts.total, group.description
+            );
+            for t in tasks {
+                println!("{}\t{}\t{}", t.id, t.status, t.title());
+            }
+        }
+        OutputFormat::Json => {
+            let mut value = serde_json::to_value(group)?;
+            value["tasks"] = serde_json::to_value(tasks)?;
+            output::print_json(&[value])?;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_group_command(
+    client: &client::DaemonClient,
+    command: GroupCommand,
+) -> anyhow::Result<()> {
+    match command {
+        GroupCommand::New {
+            description,
+            project,
+            format,
+        } => {
+            let projects = client.list_projects().await?;
+            let proj = resolve_project(&projects, project)?;
+            let group = client.create_task_group(&proj.id, &description).await?;
+            print_task_group(&group, &[], &format)?;
+        }
+        GroupCommand::List { format } => {
+            let groups = client.list_task_groups().await?;
+            match format {
+                OutputFormat::Human => {
+                    if groups.is_empty() {
+                        return Ok(());
+                    }
+                    let mut table = Table::new(&["ID", "STATUS", "TASKS", "DESCRIPTION"])
+                        .truncate("DESCRIPTION");
+                    for g in &groups {
+                        table.row(vec![
+                            Cell::from(g.id.as_str()),
+                            Cell::styled(&g.status, output::status_style(&g.status)),
+                            format_group_counts(&g.counts).into(),
+                            Cell::from(g.description.as_str()),
+                        ]);
+                    }
+                    table.print();
+                }
+                OutputFormat::Plain => {
+                    for g in &groups {
+                        println!(
+                            "{}\t{}\t{}\t{}\t{}\t{}",
+                            g.id,
+                            g.status,
+                            g.counts.total,
+                            g.counts.running,
+                            g.counts.failed,
+                            g.description
+                        );
+                    }
+                }
+                OutputFormat::Json => {
+                    output::print_json(&groups)?;
+                }
+            }
+        }
+        GroupCommand::Show { id, format } => {
+            let group = client.get_task_group(&id).await?;
+            let tasks: Vec<db::Task> = client
+                .list_tasks()
+                .await?
+                .into_iter()
+                .filter(|t| t.group_id.as_deref() == Some(group.id.as_str()))
+                .collect();
+            print_task_group(&group, &tasks, &format)?;
+        }
+        GroupCommand::Remove {
+            id,
+            cascade,
+            skip_provider,
+            yes,
+        } => {
+            // Try without cascading first so only a group that still has
+            // tasks asks for confirmation.
+            match client.remove_task_group(&id, false, skip_provider).await {
+                Err(e) if cascade && Error::code_of(&e) == ErrorCode::GroupNotEmpty => {
+                    let tasks = Error::from_anyhow(&e).details["task_ids"]
+                        .as_array()
+                        .map_or(0, Vec::len);
+                    confirm_by_typing(
+                        &format!(
+                            "group {id} has {tasks} task(s); removing it also removes them and their environments"
+                        ),
+                        &id,
+                        yes,
+                    )?;
+                    client.remove_task_group(&id, true, skip_provider).await?;
+                }
+                result => result?,
+            }
+        }
+    }
+    Ok(())
+}
+
 async fn handle_task_command(
     client: &client::DaemonClient,
     config: &config::Config,
@@ -835,97 +2945,495 @@ async fn handle_task_command(
             project,
             provider,
             env_provider,
+            labels,
+            requirements,
+            from_ref,
+            parent,
+            priority,
+            in_place,
             attach,
             format,
         } => {
+            if from_ref.as_deref().is_some_and(|r| r.trim().is_empty()) {
+                anyhow::bail!("--from-ref cannot be empty");
+            }
+            let description = if description == "-" {
+                read_description_from_stdin()?
+            } else {
+                description
+            };
             let projects = client.list_projects().await?;
-            let proj = resolve_project(&projects, project)?;
+            // A grouped task goes in its group's project unless told otherwise.
+            let proj = match (&parent, project) {
+                (Some(group_id), None) => {
+                    let group = client.get_task_group(group_id).await?;
+                    projects
+                        .iter()
+                        .find(|p| p.id == group.project_id)
+                        .ok_or_else(|| anyhow::anyhow!("project not found: {}", group.project_id))?
+                }
+                (_, project) => resolve_project(&projects, project)?,
+            };
+            let config = project_config(proj)?;
             let task_provider_name = provider
                 .or(config.default_task_provider_for_project(&proj.name))
                 .ok_or_else(|| {
                     anyhow::anyhow!("--provider is required (or set task-provider in config)")
                 })?;
-            let env_provider = env_provider
-                .or(config.default_environment_provider_for_project(&proj.name))
-                .ok_or_else(|| {
-                    anyhow::anyhow!(
-                        "--env-provider is required (or set environment-provider in config)"
-                    )
-                })?;
+            // With requirements, the daemon picks among the providers tagged
+            // with them, preferring the project's default.
+            let env_provider = match env_provider {
+                Some(env_provider) if in_place && env_provider != config::IN_PLACE_PROVIDER => {
+                    anyhow::bail!(
+                        "--in-place runs in the {} provider, not {env_provider}",
+                        config::IN_PLACE_PROVIDER
+                    );
+                }
+                Some(env_provider) => Some(env_provider),
+                None if in_place => Some(config::IN_PLACE_PROVIDER.to_string()),
+                None if !requirements.is_empty() => None,
+                None => Some(
+                    config
+                        .default_environment_provider_for_project(&proj.name)
+                        .ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "--env-provider is required (or set environment-provider in config)"
+                            )
+                        })?,
+                ),
+            };
 
             config.get_task_provider(&task_provider_name)?;
 
             let task = client
-                .create_task(&proj.id, &task_provider_name, &env_provider, &description)
+                .create_task(&db::NewTask {
+                    project_id: proj.id.clone(),
+                    provider: task_provider_name,
+                    env_provider,
+                    description,
+                    labels,
+                    requirements,
+                    source_ref: from_ref,
+                    group_id: parent,
+                    priority: priority.into(),
+                    in_place,
+                })
+                .await?;
+
+            print_task(&task, &format)?;
+
+            if attach {
+                follow_task_logs(client, &task.id, false, false).await?;
+            }
+        }
+        TaskCommand::Duplicate {
+            id,
+            edit,
+            attach,
+            format,
+        } => {
+            let source = client.get_task(&id).await?;
+            config.get_task_provider(&source.provider)?;
+
+            // The source environment may have been removed since the task ran;
+            // fall back to the project's default environment provider.
+            let env_provider = match client.get_environment(&source.environment_id).await {
+                Ok(env) => env.provider,
+                Err(_) => {
+                    let projects = client.list_projects().await?;
+                    let proj = projects
+                        .iter()
+                        .find(|p| p.id == source.project_id)
+                        .ok_or_else(|| {
+                            anyhow::anyhow!("project not found: {}", source.project_id)
+                        })?;
+                    project_config(proj)?
+                        .default_environment_provider_for_project(&proj.name)
+                        .ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "environment for task {id} no longer exists (set environment-provider in config)"
+                            )
+                        })?
+                }
+            };
+
+            let description = if edit {
+                edit_description(&source.description)?
+            } else {
+                source.description
+            };
+
+            let task = client
+                .create_task(&db::NewTask {
+                    project_id: source.project_id,
+                    provider: source.provider,
+                    description,
+                    labels: source.labels,
+                    requirements: source.requirements,
+                    source_ref: source.source_ref,
+                    group_id: source.group_id,
+                    priority: source.priority,
+                    // The original was confirmed to run in the checkout.
+                    in_place: env_provider == config::IN_PLACE_PROVIDER,
+                    env_provider: Some(env_provider),
+                })
                 .await?;
 
             print_task(&task, &format)?;
 
             if attach {
-                follow_task_logs(client, &task.id).await?;
+                follow_task_logs(client, &task.id, false, false).await?;
             }
         }
-        TaskCommand::Remove { id, skip_provider } => {
+        TaskCommand::Label {
+            id,
+            labels,
+            remove,
+            format,
+        } => {
+            if labels.is_empty() && remove.is_empty() {
+                anyhow::bail!("specify labels to add or --remove LABEL");
+            }
+            let task = client.update_task_labels(&id, &labels, &remove).await?;
+            print_task(&task, &format)?;
+        }
+        TaskCommand::Note { id, text, format } => {
+            let task = client.add_task_note(&id, &text).await?;
+            print_task(&task, &format)?;
+        }
+        TaskCommand::Remove {
+            id,
+            skip_provider,
+            yes,
+        } => {
+            if skip_provider {
+                confirm_by_typing(
+                    &format!(
+                        "--skip-provider deletes task {id} and its environment without provider cleanup, which can leave files and processes behind"
+                    ),
+                    &id,
+                    yes,
+                )?;
+            }
             client.remove_task(&id, skip_provider).await?;
         }
-        TaskCommand::List { format } => {
-            let tasks = client.list_tasks().await?;
+        TaskCommand::List { format, sort } => {
+            let mut tasks = client.list_tasks().await?;
+            if let TaskSort::InStatus = sort {
+                // Timestamps sort as text, and the sort is stable, so
+                // finished tasks stay newest first after the rest.
+                tasks.sort_by_key(|t| {
+                    let finished = t.is_terminal();
+                    (finished, (!finished).then(|| t.status_changed_at.clone()))
+                });
+            }
             match format {
                 OutputFormat::Human => {
                     if tasks.is_empty() {
                         return Ok(());
                     }
-                    println!(
-                        "{:<22}  {:<12}  {:<10}  DESCRIPTION",
-                        "ID", "PROVIDER", "STATUS"
-                    );
+                    let with_results = tasks.iter().any(|t| t.result_summary.is_some());
+                    let columns: &[&str] = if with_results {
+                        &["PROVIDER", "STATUS", "IN STATUS", "DESCRIPTION", "RESULT"]
+                    } else {
+                        &["PROVIDER", "STATUS", "IN STATUS", "DESCRIPTION"]
+                    };
+                    let mut table = Table::new(&[id_headers(config.id_format), columns].concat())
+                        .truncate("DESCRIPTION")
+                        .truncate("RESULT");
                     for t in &tasks {
-                        println!(
-                            "{:<22}  {:<12}  {:<10}  {}",
-                            t.id, t.provider, t.status, t.description
-                        );
+                        let mut row = id_cells(config.id_format, &t.id, t.short_id());
+                        row.extend([
+                            Cell::from(t.provider.as_str()),
+                            Cell::styled(&t.status, output::status_style(&t.status)),
+                            Cell::from(t.in_status_for().unwrap_or_else(|| "-".to_string())),
+                            Cell::from(t.title()),
+                        ]);
+                        if with_results {
+                            row.push(Cell::styled(
+                                t.result_summary.as_deref().unwrap_or(""),
+                                Some(Style::Dim),
+                            ));
+                        }
+                        table.row(row);
                     }
+                    table.print();
                 }
                 OutputFormat::Plain => {
                     for t in &tasks {
-                        println!("{}\t{}\t{}\t{}", t.id, t.provider, t.status, t.description);
+                        println!(
+                            "{}\t{}\t{}\t{}\t{}",
+                            t.id,
+                            t.provider,
+                            t.status,
+                            t.title(),
+                            t.result_summary.as_deref().unwrap_or("")
+                        );
                     }
                 }
                 OutputFormat::Json => {
-                    println!("{}", serde_json::to_string(&tasks)?);
+                    output::print_json(&tasks)?;
+                }
+            }
+        }
+        TaskCommand::Show { id, format } => {
+            let task = client.get_task(&id).await?;
+            print_task(&task, &format)?;
+        }
+        TaskCommand::Logs {
+            id,
+            follow,
+            plain,
+            raw,
+            sections,
+        } => {
+            if sections {
+                print_log_sections(&client.task_log_sections(&id).await?);
+            } else if follow {
+                follow_task_logs(client, &id, plain, raw).await?;
+            } else if let Some(host) = client.host() {
+                let mut args = vec!["task", "logs", id.as_str()];
+                if plain {
+                    args.push("--plain");
+                }
+                if raw {
+                    args.push("--raw");
                 }
+                run_on_host(host, &args)?;
+            } else {
+                // The daemon redacts with the patterns it enforces, so the
+                // log comes through it rather than off the disk.
+                let contents = client.task_log(&id).await?;
+                let mut filter = LogFilter::new(plain, raw);
+                let mut out = filter.push(&contents);
+                out.extend(filter.finish());
+                std::io::Write::write_all(&mut std::io::stdout(), &out)?;
             }
         }
-        TaskCommand::Logs { id, follow } => {
-            if follow {
-                follow_task_logs(client, &id).await?;
-            } else {
-                let log_path = paths::task_log_path(&id)?;
-                if !log_path.exists() {
-                    anyhow::bail!("no logs found for task {id}");
-                }
-                let contents = std::fs::read_to_string(&log_path)?;
-                print!("{contents}");
-            }
+        TaskCommand::Exec {
+            id,
+            record,
+            command,
+            args,
+        } => {
+            exec_task_command(client, config, &id, &command, &args, record).await?;
+        }
+        TaskCommand::Merge {
+            id,
+            squash,
+            fetch_only,
+            delete_env,
+        } => {
+            merge_task(client, config, &id, squash, fetch_only, delete_env).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A table of log sections, by line number from 1 as editors count them.
+fn print_log_sections(sections: &[work::models::LogSection]) {
+    if sections.is_empty() {
+        println!("no sections");
+        return;
+    }
+    let mut table = Table::new(&["LINES", "LENGTH", "NAME"]).truncate("NAME");
+    for section in sections {
+        let (lines, length) = match section.end {
+            Some(end) => (
+                format!("{}-{}", section.start + 1, end + 1),
+                format!("{} lines", section.len(end)),
+            ),
+            None => (format!("{}-", section.start + 1), "open".to_string()),
+        };
+        table.row(vec![
+            lines.into(),
+            length.into(),
+            section.name.as_str().into(),
+        ]);
+    }
+    table.print();
+}
+
+async fn merge_task(
+    client: &client::DaemonClient,
+    config: &config::Config,
+    id: &str,
+    squash: bool,
+    fetch_only: bool,
+    delete_env: bool,
+) -> anyhow::Result<()> {
+    // The project checkout is on the host, so merge there.
+    if let Some(host) = client.host() {
+        let mut args = vec!["task", "merge", id];
+        if squash {
+            args.push("--squash");
+        }
+        if fetch_only {
+            args.push("--fetch-only");
+        }
+        if delete_env {
+            args.push("--delete-env");
         }
-        TaskCommand::Exec { id, command, args } => {
-            exec_task_command(client, &id, &command, &args).await?;
+        return run_on_host(host, &args);
+    }
+
+    let task = client.get_task(id).await?;
+    if task.status != "complete" {
+        anyhow::bail!(
+            "task {id} is {}; only complete tasks can be merged",
+            task.status
+        );
+    }
+    let env = client.get_environment(&task.environment_id).await?;
+    let project = client
+        .list_projects()
+        .await?
+        .into_iter()
+        .find(|p| p.id == task.project_id)
+        .ok_or_else(|| anyhow::anyhow!("project not found: {}", task.project_id))?;
+
+    let mode = if fetch_only {
+        environment::MergeMode::FetchOnly
+    } else if squash {
+        environment::MergeMode::Squash {
+            message: task.title().to_string(),
         }
+    } else {
+        environment::MergeMode::Merge
+    };
+    // Run git the way the environment's provider does, e.g. with its
+    // `path-prepend`.
+    let report = environment::merge_environment(
+        std::path::Path::new(&project.path),
+        &env.metadata,
+        &mode,
+        &config.provider_process_env(&env.provider),
+    )?;
+    if report.outcome == environment::MergeOutcome::Fetched {
+        println!(
+            "{} {} to {}",
+            output::paint("fetched", Style::BoldGreen),
+            report.branch,
+            report.git_ref
+        );
+    } else if report.outcome == environment::MergeOutcome::UpToDate {
+        println!("{} is already in {}", report.branch, report.base_branch);
+    } else {
+        println!(
+            "{} {} into {} ({})",
+            output::paint("merged", Style::BoldGreen),
+            report.branch,
+            report.base_branch,
+            report.outcome.as_str()
+        );
     }
 
+    if delete_env {
+        client.remove_task(id, false).await?;
+        println!("removing task {id} and environment {}", env.id);
+    }
     Ok(())
 }
 
+/// Parse the command line again with each command group's default format
+/// from config in place of `human`, if the config sets any. `--format` and
+/// `WORK_FORMAT` still win.
+fn with_default_formats(cli: Cli, config: &config::Config) -> anyhow::Result<Cli> {
+    if config.default_format.is_none() && config.formats.is_empty() {
+        return Ok(cli);
+    }
+    let mut command = Cli::command();
+    let groups: Vec<String> = command
+        .get_subcommands()
+        .map(|group| group.get_name().to_string())
+        .collect();
+    for group in groups {
+        if let Some(format) = config.default_format(&group)? {
+            command = command.mut_subcommand(&group, |group| set_default_format(group, format));
+        }
+    }
+    Ok(Cli::from_arg_matches(&command.get_matches()).unwrap_or_else(|e| e.exit()))
+}
+
+fn set_default_format(command: clap::Command, format: &'static str) -> clap::Command {
+    let command = if command.get_arguments().any(|arg| arg.get_id() == "format") {
+        command.mut_arg("format", |arg| arg.default_value(format))
+    } else {
+        command
+    };
+    command.mut_subcommands(|command| set_default_format(command, format))
+}
+
+/// The scratch work home for `work debug replay`: `home`, which must be new
+/// or empty, or a new directory under the temp directory.
+fn replay_home(home: Option<std::path::PathBuf>) -> anyhow::Result<std::path::PathBuf> {
+    let home = home.unwrap_or_else(|| {
+        std::env::temp_dir().join(format!(
+            "work-replay-{}-{}",
+            chrono::Utc::now().format("%Y%m%d-%H%M%S"),
+            std::process::id()
+        ))
+    });
+    if home
+        .read_dir()
+        .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        anyhow::bail!(
+            "{} isn't empty; a replay needs a scratch work home",
+            home.display()
+        );
+    }
+    std::fs::create_dir_all(&home)?;
+    Ok(home)
+}
+
 async fn run() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
+    output::init_color(cli.no_color);
+
     let is_daemon = matches!(cli.command, Some(Command::Daemon { .. }));
     let is_tui = matches!(cli.command, Some(Command::Tui));
 
-    paths::init(cli.work_home);
+    // A replay runs in a scratch work home of its own, never a real one.
+    let (work_home, profile) = match &cli.command {
+        Some(Command::Debug {
+            command: DebugCommand::Replay { home, .. },
+        }) => (
+            Some(replay_home(home.clone())?),
+            Some("default".to_string()),
+        ),
+        _ => (cli.work_home.clone(), cli.profile.clone()),
+    };
+    paths::init(work_home.clone());
+    paths::init_profile(profile)?;
+
+    // The doctor is for when something is broken, a config that doesn't
+    // parse or a directory that can't be created included, so it runs
+    // before either is needed.
+    if let Some(Command::Doctor { format }) = &cli.command {
+        if cli.host.is_some() {
+            anyhow::bail!("`work doctor` checks this machine; run it on the host itself");
+        }
+        let checks = doctor::run().await;
+        print_doctor(&checks, format)?;
+        let failed = checks
+            .iter()
+            .filter(|c| c.status == doctor::Status::Fail)
+            .count();
+        if failed > 0 {
+            anyhow::bail!("{failed} check(s) failed");
+        }
+        return Ok(());
+    }
+
     paths::ensure_dirs()?;
 
     let config = config::load()?;
+    // The config can only be found once the flags naming the work home and
+    // profile are parsed, so its formats apply on a second pass.
+    let cli = with_default_formats(cli, &config)?;
 
     let config_debug = config.daemon.as_ref().is_some_and(|d| d.debug);
 
@@ -952,6 +3460,16 @@ async fn run() -> anyhow::Result<()> {
             })
             .with_max_level(max_level)
             .init();
+    } else if is_daemon {
+        // Config reloads can change the level, so it sits behind a handle.
+        let (level, handle) = tracing_subscriber::reload::Layer::new(
+            tracing_subscriber::filter::LevelFilter::from_level(max_level),
+        );
+        tracing_subscriber::registry()
+            .with(level)
+            .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+            .init();
+        daemon::set_log_level_handle(handle, cli.debug);
     } else {
         tracing_subscriber::fmt()
             .with_writer(std::io::stderr)
@@ -960,26 +3478,214 @@ async fn run() -> anyhow::Result<()> {
     }
 
     match cli.command {
+        Some(Command::Daemon {
+            command: DaemonCommand::Start { .. } | DaemonCommand::Install | DaemonCommand::Uninstall,
+        }) if cli.host.is_some() => {
+            anyhow::bail!(
+                "--host manages a daemon that is already running; start or install it on the host itself"
+            );
+        }
         Some(Command::Daemon { command }) => match command {
-            DaemonCommand::Start { force } => daemon::start(force).await?,
+            DaemonCommand::Start {
+                force,
+                wait_ready: false,
+            } => daemon::start(force).await?,
+            DaemonCommand::Start {
+                force,
+                wait_ready: true,
+            } => {
+                // `--wait-daemon` doubles as the startup timeout here.
+                let timeout = std::time::Duration::from_secs(
+                    cli.wait_daemon.unwrap_or(DAEMON_START_TIMEOUT_SECS),
+                );
+                let pid = daemon::start_detached(force, timeout).await?;
+                println!("daemon ready (pid {pid})");
+            }
+            DaemonCommand::Reload { format } => {
+                let client = connect(&config, cli.host.as_deref(), cli.wait_daemon).await?;
+                let summary = client.reload_config().await?;
+                match format {
+                    OutputFormat::Human => {
+                        if summary.changes.is_empty() {
+                            println!("config reloaded, no changes");
+                        }
+                        for change in &summary.changes {
+                            println!(
+                                "{} {} -> {}",
+                                output::paint(&change.setting, Style::Bold),
+                                change.old.as_deref().unwrap_or("(unset)"),
+                                change.new.as_deref().unwrap_or("(unset)")
+                            );
+                        }
+                    }
+                    OutputFormat::Plain => {
+                        for change in &summary.changes {
+                            println!(
+                                "{}\t{}\t{}",
+                                change.setting,
+                                change.old.as_deref().unwrap_or(""),
+                                change.new.as_deref().unwrap_or("")
+                            );
+                        }
+                    }
+                    OutputFormat::Json => output::print_json(&[summary])?,
+                }
+            }
+            DaemonCommand::Peers { format } => {
+                let client = connect(&config, cli.host.as_deref(), cli.wait_daemon).await?;
+                let peers = client.list_peers().await?;
+                match format {
+                    OutputFormat::Human => {
+                        let mut table = Table::new(&[
+                            "PEER",
+                            "REQUESTS",
+                            "LIMITED",
+                            "ERRORS",
+                            "LAST PID",
+                            "LAST SEEN",
+                        ]);
+                        for p in &peers {
+                            table.row(vec![
+                                p.peer.as_str().into(),
+                                p.requests.to_string().into(),
+                                p.rate_limited.to_string().into(),
+                                p.errors.to_string().into(),
+                                p.last_pid
+                                    .map_or("-".to_string(), |pid| pid.to_string())
+                                    .into(),
+                                p.last_seen_at.as_deref().unwrap_or("-").into(),
+                            ]);
+                        }
+                        table.print();
+                    }
+                    OutputFormat::Plain => {
+                        for p in &peers {
+                            println!(
+                                "{}\t{}\t{}\t{}",
+                                p.peer, p.requests, p.rate_limited, p.errors
+                            );
+                        }
+                    }
+                    OutputFormat::Json => output::print_json(&peers)?,
+                }
+            }
             DaemonCommand::Install => daemon::install()?,
             DaemonCommand::Uninstall => daemon::uninstall()?,
+            DaemonCommand::Proxy => daemon::proxy().await?,
         },
+        Some(Command::Profile {
+            command: ProfileCommand::List { format },
+        }) => {
+            let current = paths::profile().unwrap_or_else(|| "default".to_string());
+            let profiles: Vec<_> = paths::list_profiles()?
+                .into_iter()
+                .map(|p| ProfileSummary {
+                    current: p.name == current,
+                    daemon: if daemon::is_running(&p.runtime_dir) {
+                        "running"
+                    } else {
+                        "stopped"
+                    },
+                    name: p.name,
+                    data_dir: p.data_dir,
+                })
+                .collect();
+            match format {
+                OutputFormat::Human => {
+                    let mut table = Table::new(&["", "NAME", "DAEMON", "DATA"]);
+                    for p in &profiles {
+                        table.row(vec![
+                            if p.current { "*" } else { "" }.into(),
+                            p.name.as_str().into(),
+                            Cell::styled(p.daemon, output::status_style(p.daemon)),
+                            p.data_dir.display().to_string().into(),
+                        ]);
+                    }
+                    table.print();
+                }
+                OutputFormat::Plain => {
+                    for p in &profiles {
+                        println!("{}\t{}\t{}", p.name, p.daemon, p.data_dir.display());
+                    }
+                }
+                OutputFormat::Json => output::print_json(&profiles)?,
+            }
+        }
         Some(Command::Config { command }) => match command {
             ConfigCommand::Edit => {
-                let editor =
-                    std::env::var("EDITOR").map_err(|_| anyhow::anyhow!("$EDITOR is not set"))?;
-                let path = paths::config_dir()?.join("config.toml");
+                let path = config::path()?;
                 std::fs::create_dir_all(path.parent().unwrap())?;
-                let status = std::process::Command::new(&editor).arg(&path).status()?;
-                if !status.success() {
-                    anyhow::bail!("{editor} exited with {status}");
-                }
+                // A broken config is what this command is for fixing, so
+                // fall back to $EDITOR rather than refusing to open it.
+                let config = config::load().unwrap_or_default();
+                editor::open(&config, &path)?;
+            }
+            ConfigCommand::Show { project, format } => {
+                let client = connect(&config, cli.host.as_deref(), cli.wait_daemon).await?;
+                let projects = client.list_projects().await?;
+                let proj = match project {
+                    Some(name) => Some(resolve_project(&projects, Some(name))?),
+                    None => resolve_project(&projects, None).ok(),
+                };
+                let config = match proj {
+                    Some(proj) => project_config(proj)?,
+                    None => config,
+                };
+                print_config(&config, proj.map(|p| p.name.as_str()), &format)?;
             }
         },
+        Some(Command::Debug {
+            command: DebugCommand::Bundle { .. },
+        }) if cli.host.is_some() => {
+            anyhow::bail!(
+                "a debug bundle reads the daemon's log files; run `work debug bundle` on the host itself"
+            );
+        }
+        Some(Command::Debug {
+            command: DebugCommand::Bundle { task, output },
+        }) => {
+            let client = if cli.wait_daemon.is_some() {
+                connect(&config, None, cli.wait_daemon).await?
+            } else {
+                connect_or_read_database(&config).await?
+            };
+            let output = output.unwrap_or_else(bundle::default_output);
+            bundle::create(&client, task.as_deref(), &output).await?;
+            println!("wrote {}", output.display());
+        }
+        Some(Command::Debug {
+            command: DebugCommand::Replay { trace, .. },
+        }) => {
+            let jobs = work::daemon::replay(&trace).await?;
+            let outcome = |o: Option<work::daemon::Outcome>| o.map_or("-", |o| o.as_str());
+            for job in &jobs {
+                println!(
+                    "{:>5}  {:<20} {}  {} -> {}{}",
+                    job.seq,
+                    job.job_type,
+                    job.job_id,
+                    outcome(job.recorded),
+                    outcome(job.replayed),
+                    if job.diverged() { "  (differs)" } else { "" }
+                );
+            }
+            if let Some(home) = &work_home {
+                println!("replayed {} job(s) in {}", jobs.len(), home.display());
+            }
+            let diverged = jobs.iter().filter(|job| job.diverged()).count();
+            if diverged > 0 {
+                anyhow::bail!("{diverged} job(s) ended differently than in the trace");
+            }
+        }
+        Some(Command::Doctor { .. }) => unreachable!("the doctor runs before the config loads"),
+        Some(Command::Status { .. }) if cli.host.is_some() => {
+            anyhow::bail!("`work status` reads this machine's database; run it on the host itself");
+        }
+        Some(Command::Status { porcelain }) => print_status(porcelain)?,
         Some(Command::Version) => {
             println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
         }
+        Some(Command::SelfUpdate { check, format }) => self_update(check, &format)?,
         Some(Command::Completions { shell }) => {
             let status = std::process::Command::new(std::env::current_exe()?)
                 .env("COMPLETE", shell.to_string())
@@ -987,9 +3693,20 @@ async fn run() -> anyhow::Result<()> {
             std::process::exit(status.code().unwrap_or(1));
         }
         Some(cmd) => {
-            let client = client::DaemonClient::new()?;
+            let client = if cli.host.is_none() && cli.wait_daemon.is_none() && reads_only(&cmd) {
+                connect_or_read_database(&config).await?
+            } else {
+                connect(&config, cli.host.as_deref(), cli.wait_daemon).await?
+            };
             match cmd {
-                Command::ResetDatabase => client.reset_database().await?,
+                Command::ResetDatabase { yes } => {
+                    confirm_by_typing(
+                        "this deletes every project, task, environment, and job",
+                        "reset-database",
+                        yes,
+                    )?;
+                    client.reset_database().await?
+                }
                 Command::Project { command } => match command {
                     ProjectCommand::List { format } => {
                         let projects = client.list_projects().await?;
@@ -998,12 +3715,11 @@ async fn run() -> anyhow::Result<()> {
                                 if projects.is_empty() {
                                     return Ok(());
                                 }
-                                let max_name = projects.iter().map(|p| p.name.len()).max().unwrap();
-                                let name_width = max_name.max(4);
-                                println!("{:<name_width$}  PATH", "NAME");
+                                let mut table = Table::new(&["NAME", "PATH"]).truncate("PATH");
                                 for p in &projects {
-                                    println!("{:<name_width$}  {}", p.name, p.path);
+                                    table.row(vec![p.name.as_str().into(), p.path.as_str().into()]);
                                 }
+                                table.print();
                             }
                             OutputFormat::Plain => {
                                 for p in &projects {
@@ -1011,14 +3727,43 @@ async fn run() -> anyhow::Result<()> {
                                 }
                             }
                             OutputFormat::Json => {
-                                println!("{}", serde_json::to_string(&projects)?);
+                                output::print_json(&projects)?;
                             }
                         }
                     }
-                    ProjectCommand::Remove { name } => {
-                        client.delete_project(&name).await?;
+                    ProjectCommand::Remove { name, force, yes } => {
+                        // Try without forcing first so only a project that
+                        // still has tasks asks for confirmation.
+                        let removal = match client.delete_project(&name, false).await {
+                            Err(e) if force && Error::code_of(&e) == ErrorCode::ProjectInUse => {
+                                let tasks = Error::from_anyhow(&e).details["task_ids"]
+                                    .as_array()
+                                    .map_or(0, Vec::len);
+                                confirm_by_typing(
+                                    &format!(
+                                        "{name} has {tasks} task(s); removing it also removes them and their environments"
+                                    ),
+                                    &name,
+                                    yes,
+                                )?;
+                                client.delete_project(&name, true).await?
+                            }
+                            result => result?,
+                        };
+                        if !removal.removed {
+                            println!(
+                                "removing {} environment(s) and {} task(s); {name} is removed once they are gone",
+                                removal.environment_ids.len(),
+                                removal.task_ids.len()
+                            );
+                        }
                     }
-                    ProjectCommand::New { name, path } => {
+                    ProjectCommand::New {
+                        name,
+                        path,
+                        adopt,
+                        nested,
+                    } => {
                         let path = match path {
                             Some(p) => p,
                             None => std::env::current_dir()?,
@@ -1037,9 +3782,33 @@ async fn run() -> anyhow::Result<()> {
                                 .into_owned(),
                         };
 
-                        client
-                            .create_project(&name, &path.to_string_lossy())
-                            .await?;
+                        match client
+                            .create_project(&name, &path.to_string_lossy(), nested)
+                            .await
+                        {
+                            Err(e) if Error::code_of(&e) == ErrorCode::ProjectExists => {
+                                let details = &Error::from_anyhow(&e).details;
+                                // Only a path conflict names the project
+                                // that has it.
+                                let Some(existing) = details["project"].as_str() else {
+                                    return Err(e);
+                                };
+                                if !adopt {
+                                    anyhow::bail!(
+                                        "{} is already project {existing}; use it with --adopt",
+                                        path.display()
+                                    );
+                                }
+                                if existing != name {
+                                    client.add_project_alias(existing, &name).await?;
+                                    eprintln!(
+                                        "{} is already project {existing}; {name} is now an alias of it",
+                                        path.display()
+                                    );
+                                }
+                            }
+                            result => result?,
+                        }
                     }
                 },
                 Command::Environment { command } => match command {
@@ -1050,6 +3819,7 @@ async fn run() -> anyhow::Result<()> {
                     } => {
                         let projects = client.list_projects().await?;
                         let proj = resolve_project(&projects, project)?;
+                        let config = project_config(proj)?;
                         let provider = provider
                             .or(config.default_environment_provider_for_project(&proj.name))
                             .ok_or_else(|| {
@@ -1069,6 +3839,7 @@ async fn run() -> anyhow::Result<()> {
                     } => {
                         let projects = client.list_projects().await?;
                         let proj = resolve_project(&projects, project)?;
+                        let config = project_config(proj)?;
                         let provider = provider
                             .or(config.default_environment_provider_for_project(&proj.name))
                             .ok_or_else(|| {
@@ -1081,6 +3852,46 @@ async fn run() -> anyhow::Result<()> {
                             .await?;
                         print_env(&env, &format)?;
                     }
+                    EnvironmentCommand::Plan {
+                        project,
+                        provider,
+                        format,
+                    } => {
+                        let projects = client.list_projects().await?;
+                        let proj = resolve_project(&projects, project)?;
+                        let config = project_config(proj)?;
+                        let provider = provider
+                            .or(config.default_environment_provider_for_project(&proj.name))
+                            .ok_or_else(|| {
+                                anyhow::anyhow!(
+                                    "--provider is required (or set environment-provider in config)"
+                                )
+                            })?;
+                        let plan = client.plan_environment(&proj.id, &provider).await?;
+                        print_plan(&plan, &format)?;
+                    }
+                    EnvironmentCommand::Adopt {
+                        project,
+                        path,
+                        provider,
+                        format,
+                    } => {
+                        let projects = client.list_projects().await?;
+                        let proj = resolve_project(&projects, project)?;
+                        let config = project_config(proj)?;
+                        let provider = provider
+                            .or(config.default_environment_provider_for_project(&proj.name))
+                            .ok_or_else(|| {
+                                anyhow::anyhow!(
+                                    "--provider is required (or set environment-provider in config)"
+                                )
+                            })?;
+                        let path = path.canonicalize()?;
+                        let env = client
+                            .adopt_environment(&proj.id, &provider, &path.to_string_lossy())
+                            .await?;
+                        print_env(&env, &format)?;
+                    }
                     EnvironmentCommand::Update { id, format } => {
                         let env = client.update_environment(&id).await?;
                         print_env(&env, &format)?;
@@ -1089,6 +3900,7 @@ async fn run() -> anyhow::Result<()> {
                         id,
                         provider,
                         project,
+                        count,
                         format,
                     } => {
                         let env = if let Some(id) = id {
@@ -1100,10 +3912,11 @@ async fn run() -> anyhow::Result<()> {
                             let projects = client.list_projects().await?;
                             let proj = projects
                                 .iter()
-                                .find(|p| p.name == project_name)
+                                .find(|p| p.answers_to(&project_name))
                                 .ok_or_else(|| {
                                     anyhow::anyhow!("project not found: {project_name}")
                                 })?;
+                            let config = project_config(proj)?;
                             let provider = provider
                                 .or(config.default_environment_provider_for_project(&proj.name))
                                 .ok_or_else(|| {
@@ -1111,11 +3924,37 @@ async fn run() -> anyhow::Result<()> {
                                         "--provider is required when no id is given (or set environment-provider in config)"
                                     )
                                 })?;
+                            if let Some(count) = count {
+                                let envs = client
+                                    .claim_environments(&provider, &proj.id, count)
+                                    .await?;
+                                if matches!(format, OutputFormat::Json) {
+                                    output::print_json(&envs)?;
+                                } else {
+                                    for env in &envs {
+                                        print_env(env, &format)?;
+                                    }
+                                }
+                                return Ok(());
+                            }
                             client.claim_next_environment(&provider, &proj.id).await?
                         };
                         print_env(&env, &format)?;
                     }
-                    EnvironmentCommand::Remove { id, skip_provider } => {
+                    EnvironmentCommand::Remove {
+                        id,
+                        skip_provider,
+                        yes,
+                    } => {
+                        if skip_provider {
+                            confirm_by_typing(
+                                &format!(
+                                    "--skip-provider deletes environment {id} without provider cleanup, which can leave its files behind"
+                                ),
+                                &id,
+                                yes,
+                            )?;
+                        }
                         client.remove_environment(&id, skip_provider).await?;
                     }
                     EnvironmentCommand::List { format } => {
@@ -1125,60 +3964,423 @@ async fn run() -> anyhow::Result<()> {
                                 if envs.is_empty() {
                                     return Ok(());
                                 }
-                                println!(
-                                    "{:<22}  {:<12}  {:<14}  {:<22}  PATH",
-                                    "ID", "PROVIDER", "STATUS", "PROJ"
-                                );
+                                let columns =
+                                    ["NAME", "PROVIDER", "STATUS", "IN STATUS", "PROJ", "PATH"];
+                                let mut table =
+                                    Table::new(&[id_headers(config.id_format), &columns].concat())
+                                        .truncate("PATH");
                                 for e in &envs {
                                     let path = e.metadata["worktree_path"].as_str().unwrap_or("-");
-                                    println!(
-                                        "{:<22}  {:<12}  {:<14}  {:<22}  {}",
-                                        e.id, e.provider, e.status, e.project_id, path
-                                    );
+                                    let status = match e.progress_label() {
+                                        Some(progress) => format!("{} ({progress})", e.status),
+                                        None if e.is_outdated() => {
+                                            format!("{} (outdated)", e.status)
+                                        }
+                                        None => e.status.clone(),
+                                    };
+                                    let mut row = id_cells(config.id_format, &e.id, e.short_id());
+                                    row.extend([
+                                        e.display_name().unwrap_or("-").into(),
+                                        e.provider.as_str().into(),
+                                        Cell::styled(&status, output::status_style(&e.status)),
+                                        e.in_status_for().unwrap_or_else(|| "-".to_string()).into(),
+                                        e.project_id.as_str().into(),
+                                        path.into(),
+                                    ]);
+                                    table.row(row);
                                 }
+                                table.print();
                             }
                             OutputFormat::Plain => {
                                 for e in &envs {
                                     println!(
-                                        "{}\t{}\t{}\t{}",
-                                        e.id, e.provider, e.status, e.project_id
+                                        "{}\t{}\t{}\t{}\t{}",
+                                        e.id,
+                                        e.provider,
+                                        e.status,
+                                        e.project_id,
+                                        e.display_name().unwrap_or("")
                                     );
                                 }
                             }
                             OutputFormat::Json => {
-                                println!("{}", serde_json::to_string(&envs)?);
+                                output::print_json(&envs)?;
+                            }
+                        }
+                    }
+                    EnvironmentCommand::Show {
+                        id,
+                        history,
+                        format,
+                    } => {
+                        let env = client.get_environment(&id).await?;
+                        let sessions = session_recordings(&env.id)?;
+                        let snapshots = client.list_environment_snapshots(&env.id).await?;
+                        let history = if history {
+                            Some(client.environment_status_history(&env.id).await?)
+                        } else {
+                            None
+                        };
+                        match format {
+                            OutputFormat::Json => {
+                                let mut value = serde_json::to_value(&env)?;
+                                value["sessions"] = serde_json::json!(sessions);
+                                value["snapshots"] = serde_json::json!(snapshots);
+                                if let Some(history) = &history {
+                                    value["history"] = serde_json::json!(history);
+                                }
+                                output::print_json(&[value])?;
+                            }
+                            OutputFormat::Human => {
+                                print_env(&env, &format)?;
+                                if !sessions.is_empty() {
+                                    println!("  {}", output::paint("sessions:", Style::Bold));
+                                    for session in &sessions {
+                                        println!("    {}", session.display());
+                                    }
+                                }
+                                if !snapshots.is_empty() {
+                                    println!("  {}", output::paint("snapshots:", Style::Bold));
+                                    for snapshot in &snapshots {
+                                        print_snapshot_line(snapshot);
+                                    }
+                                }
+                                if let Some(history) = &history {
+                                    println!("  {}", output::paint("history:", Style::Bold));
+                                    for change in history {
+                                        let status = output::paint(
+                                            &change.status,
+                                            output::status_style(&change.status)
+                                                .unwrap_or(Style::Bold),
+                                        );
+                                        let transition = match &change.previous_status {
+                                            Some(previous) => format!("{previous} -> {status}"),
+                                            None => status,
+                                        };
+                                        let job = change.job_id.as_deref().map_or(
+                                            String::new(),
+                                            |job_id| {
+                                                output::paint(
+                                                    &format!("  (job {job_id})"),
+                                                    Style::Dim,
+                                                )
+                                            },
+                                        );
+                                        println!(
+                                            "    {}  {transition}{job}",
+                                            output::paint(&change.created_at, Style::Dim)
+                                        );
+                                    }
+                                }
+                            }
+                            OutputFormat::Plain => {
+                                print_env(&env, &format)?;
+                                for change in history.iter().flatten() {
+                                    println!(
+                                        "{}\t{}\t{}\t{}",
+                                        change.created_at,
+                                        change.previous_status.as_deref().unwrap_or(""),
+                                        change.status,
+                                        change.job_id.as_deref().unwrap_or("")
+                                    );
+                                }
                             }
                         }
                     }
-                    EnvironmentCommand::Logs { id, follow } => {
+                    EnvironmentCommand::Snapshot { id, name, format } => {
+                        let snapshot = client.snapshot_environment(&id, name.as_deref()).await?;
+                        print_snapshot(&snapshot, "saved", &format)?;
+                    }
+                    EnvironmentCommand::Restore {
+                        id,
+                        snapshot,
+                        format,
+                    } => {
+                        let snapshot = client.restore_environment(&id, &snapshot).await?;
+                        print_snapshot(&snapshot, "restored", &format)?;
+                    }
+                    EnvironmentCommand::Logs { id, follow, plain } => {
                         if follow {
-                            follow_environment_logs(&client, &id).await?;
+                            follow_environment_logs(&client, &id, plain).await?;
+                        } else if let Some(host) = client.host() {
+                            let mut args = vec!["env", "logs", id.as_str()];
+                            if plain {
+                                args.push("--plain");
+                            }
+                            run_on_host(host, &args)?;
                         } else {
+                            let id = client.get_environment(&id).await?.id;
                             let log_path = paths::environment_log_path(&id)?;
                             if !log_path.exists() {
                                 anyhow::bail!("no logs found for environment {id}");
                             }
-                            let contents = std::fs::read_to_string(&log_path)?;
-                            print!("{contents}");
+                            let contents = std::fs::read(&log_path)?;
+                            let contents = LogFilter::new(plain, false).push(&contents);
+                            std::io::Write::write_all(&mut std::io::stdout(), &contents)?;
                         }
                     }
-                    EnvironmentCommand::Exec { id, command, args } => {
-                        exec_environment_command(&client, &id, &command, &args).await?;
+                    EnvironmentCommand::Exec {
+                        id,
+                        record,
+                        command,
+                        args,
+                    } => {
+                        exec_environment_command(&client, &config, &id, &command, &args, record)
+                            .await?;
                     }
                     EnvironmentCommand::Provider { command } => match command {
-                        ProviderCommand::List => {
-                            for name in &environment::list_providers() {
-                                println!("{name}");
-                            }
+                        ProviderCommand::List { last, format } => {
+                            let mut stats = client.provider_stats(last).await?;
+                            // Providers in the config come first, used or not;
+                            // ones only the database remembers follow.
+                            let mut providers: Vec<db::ProviderStats> =
+                                environment::list_providers()
+                                    .into_iter()
+                                    .map(|name| {
+                                        match stats.iter().position(|s| s.provider == name) {
+                                            Some(i) => stats.remove(i),
+                                            None => db::ProviderStats {
+                                                provider: name,
+                                                ..Default::default()
+                                            },
+                                        }
+                                    })
+                                    .collect();
+                            providers.append(&mut stats);
+                            print_provider_stats(&providers, &format)?;
                         }
                     },
                 },
+                Command::Job { command } => match command {
+                    JobCommand::List { format } => {
+                        let jobs = client.list_jobs().await?;
+                        match format {
+                            OutputFormat::Human => {
+                                if jobs.is_empty() {
+                                    return Ok(());
+                                }
+                                let mut table =
+                                    Table::new(&["ID", "TYPE", "STATUS", "ATTEMPT", "NOTE"])
+                                        .truncate("NOTE");
+                                for j in &jobs {
+                                    let note = match (&j.last_error, &j.not_before) {
+                                        (Some(err), Some(not_before)) => {
+                                            format!("{err} (until {not_before})")
+                                        }
+                                        (Some(err), None) => err.clone(),
+                                        (None, _) => String::new(),
+                                    };
+                                    table.row(vec![
+                                        j.id.as_str().into(),
+                                        j.job_type.as_str().into(),
+                                        Cell::styled(&j.status, output::status_style(&j.status)),
+                                        j.attempt.to_string().into(),
+                                        note.into(),
+                                    ]);
+                                }
+                                table.print();
+                            }
+                            OutputFormat::Plain => {
+                                for j in &jobs {
+                                    println!(
+                                        "{}\t{}\t{}\t{}\t{}",
+                                        j.id,
+                                        j.job_type,
+                                        j.status,
+                                        j.attempt,
+                                        j.last_error.as_deref().unwrap_or("")
+                                    );
+                                }
+                            }
+                            OutputFormat::Json => {
+                                output::print_json(&jobs)?;
+                            }
+                        }
+                    }
+                    JobCommand::Show { id, format } => {
+                        let detail = client.get_job(&id).await?;
+                        print_job(&detail, &format)?;
+                    }
+                    JobCommand::Retry { id, format } => {
+                        let job = client.retry_job(&id).await?;
+                        match format {
+                            OutputFormat::Human => println!(
+                                "requeued {} job {}",
+                                job.job_type,
+                                output::paint(&job.id, Style::Bold)
+                            ),
+                            OutputFormat::Plain => println!("{}", job.id),
+                            OutputFormat::Json => output::print_json(&[job])?,
+                        }
+                    }
+                    JobCommand::PurgeDead { format } => {
+                        let purged = client.purge_dead_jobs().await?;
+                        match format {
+                            OutputFormat::Human => println!("purged {purged} dead jobs"),
+                            OutputFormat::Plain => println!("{purged}"),
+                            OutputFormat::Json => {
+                                output::print_json(&[serde_json::json!({ "purged": purged })])?
+                            }
+                        }
+                    }
+                },
+                Command::Search {
+                    query,
+                    logs,
+                    limit,
+                    format,
+                } => {
+                    let hits = client.search_tasks(&query.join(" "), logs, limit).await?;
+                    print_search_hits(&hits, &format)?;
+                }
+                Command::Stats { project, format } => {
+                    let project_id = match project {
+                        Some(name) => {
+                            let projects = client.list_projects().await?;
+                            Some(resolve_project(&projects, Some(name))?.id.clone())
+                        }
+                        None => None,
+                    };
+                    let stats = client.cost_stats(project_id.as_deref()).await?;
+                    print_cost_stats(&stats, &format)?;
+                }
+                Command::Events {
+                    since,
+                    task,
+                    env,
+                    limit,
+                    format,
+                } => {
+                    let (kind, subject_id) = match (task, env) {
+                        (Some(id), _) => (Some("task".to_string()), Some(id)),
+                        (None, Some(id)) => (Some("environment".to_string()), Some(id)),
+                        (None, None) => (None, None),
+                    };
+                    let events = client
+                        .list_events(&db::EventQuery {
+                            since,
+                            kind,
+                            subject_id,
+                            limit,
+                        })
+                        .await?;
+                    print_events(&events, &format)?;
+                }
+                Command::Export { tasks, output } => {
+                    let state = client.export_state(tasks).await?;
+                    let json = serde_json::to_string_pretty(&state)?;
+                    match output {
+                        Some(path) => std::fs::write(&path, json + "\n")?,
+                        None => println!("{json}"),
+                    }
+                }
+                Command::Import {
+                    file,
+                    on_conflict,
+                    maps,
+                    format,
+                } => {
+                    let contents = if file.as_os_str() == "-" {
+                        std::io::read_to_string(std::io::stdin())?
+                    } else {
+                        std::fs::read_to_string(&file)
+                            .map_err(|e| anyhow::anyhow!("{}: {e}", file.display()))?
+                    };
+                    let mut state: db::StateExport = serde_json::from_str(&contents)
+                        .map_err(|e| anyhow::anyhow!("invalid export file: {e}"))?;
+                    state.remap_paths(&maps);
+                    let summary = client.import_state(&state, on_conflict.into()).await?;
+                    print_import_summary(&summary, &format)?;
+                }
+                Command::Db { command } => match command {
+                    DbCommand::Check { format } => {
+                        let problems = client.check_integrity().await?;
+                        match format {
+                            OutputFormat::Human => {
+                                if problems.is_empty() {
+                                    println!("no problems found");
+                                } else {
+                                    let mut table = Table::new(&["KIND", "ID", "PROBLEM", "FIX"])
+                                        .truncate("PROBLEM");
+                                    for p in &problems {
+                                        table.row(vec![
+                                            p.kind.as_str().into(),
+                                            p.id.as_str().into(),
+                                            p.message.as_str().into(),
+                                            p.fix.as_deref().unwrap_or("-").into(),
+                                        ]);
+                                    }
+                                    table.print();
+                                }
+                            }
+                            OutputFormat::Plain => {
+                                for p in &problems {
+                                    println!(
+                                        "{}\t{}\t{}\t{}",
+                                        p.kind,
+                                        p.id,
+                                        p.message,
+                                        p.fix.as_deref().unwrap_or("")
+                                    );
+                                }
+                            }
+                            OutputFormat::Json => output::print_json(&problems)?,
+                        }
+                        if !problems.is_empty() {
+                            anyhow::bail!(
+                                "{} problem(s) found; `work db repair` offers fixes",
+                                problems.len()
+                            );
+                        }
+                    }
+                    DbCommand::Repair { yes } => repair_database(&client, yes).await?,
+                    DbCommand::Maintain { format } => {
+                        let summary = client.maintain_database().await?;
+                        match format {
+                            OutputFormat::Human => println!(
+                                "pruned {} jobs and {} events, freed {} in {}ms",
+                                summary.jobs_pruned,
+                                summary.events_pruned,
+                                format_bytes(summary.bytes_freed.max(0) as u64),
+                                summary.elapsed_ms
+                            ),
+                            OutputFormat::Plain => println!(
+                                "{}\t{}\t{}\t{}",
+                                summary.jobs_pruned,
+                                summary.events_pruned,
+                                summary.bytes_freed,
+                                summary.elapsed_ms
+                            ),
+                            OutputFormat::Json => output::print_json(&[summary])?,
+                        }
+                    }
+                },
+                Command::RemapPaths { maps, format } => {
+                    let summary = client.remap_paths(&maps).await?;
+                    match format {
+                        OutputFormat::Human => println!(
+                            "remapped {} projects and {} environments",
+                            summary.projects, summary.environments
+                        ),
+                        OutputFormat::Plain => {
+                            println!("{}\t{}", summary.projects, summary.environments)
+                        }
+                        OutputFormat::Json => output::print_json(&[summary])?,
+                    }
+                }
                 Command::Task { command } => handle_task_command(&client, &config, command).await?,
+                Command::Group { command } => handle_group_command(&client, command).await?,
                 Command::New {
                     description,
                     project,
                     provider,
                     env_provider,
+                    labels,
+                    requirements,
+                    from_ref,
+                    parent,
+                    priority,
+                    in_place,
                     attach,
                     format,
                 } => {
@@ -1190,34 +4392,85 @@ async fn run() -> anyhow::Result<()> {
                             project,
                             provider,
                             env_provider,
+                            labels,
+                            requirements,
+                            from_ref,
+                            parent,
+                            priority,
+                            in_place,
                             attach,
                             format,
                         },
                     )
                     .await?;
                 }
-                Command::Remove { id, skip_provider } => {
+                Command::Remove {
+                    id,
+                    skip_provider,
+                    yes,
+                } => {
                     handle_task_command(
                         &client,
                         &config,
-                        TaskCommand::Remove { id, skip_provider },
+                        TaskCommand::Remove {
+                            id,
+                            skip_provider,
+                            yes,
+                        },
                     )
                     .await?;
                 }
-                Command::List { format } => {
-                    handle_task_command(&client, &config, TaskCommand::List { format }).await?;
+                Command::List { format, sort } => {
+                    handle_task_command(&client, &config, TaskCommand::List { format, sort })
+                        .await?;
                 }
-                Command::Logs { id, follow } => {
-                    handle_task_command(&client, &config, TaskCommand::Logs { id, follow }).await?;
+                Command::Logs {
+                    id,
+                    follow,
+                    plain,
+                    raw,
+                    sections,
+                } => {
+                    handle_task_command(
+                        &client,
+                        &config,
+                        TaskCommand::Logs {
+                            id,
+                            follow,
+                            plain,
+                            raw,
+                            sections,
+                        },
+                    )
+                    .await?;
                 }
-                Command::Exec { id, command, args } => {
-                    handle_task_command(&client, &config, TaskCommand::Exec { id, command, args })
-                        .await?;
+                Command::Exec {
+                    id,
+                    record,
+                    command,
+                    args,
+                } => {
+                    handle_task_command(
+                        &client,
+                        &config,
+                        TaskCommand::Exec {
+                            id,
+                            record,
+                            command,
+                            args,
+                        },
+                    )
+                    .await?;
                 }
-                Command::Tui => tui::run(client).await?,
+                Command::Tui => tui::run(client.origin("tui")).await?,
                 Command::Config { .. }
                 | Command::Daemon { .. }
+                | Command::Profile { .. }
+                | Command::Debug { .. }
+                | Command::Doctor { .. }
+                | Command::Status { .. }
                 | Command::Completions { .. }
+                | Command::SelfUpdate { .. }
                 | Command::Version => {
                     unreachable!()
                 }
@@ -0,0 +1,13 @@
+//! Records returned by the daemon API.
+
+pub use crate::config::{ConfigChange, ReloadSummary, RetryPolicy};
+pub use crate::daemon::PeerStats;
+pub use crate::db::{
+    CostStats, Environment, EnvironmentSnapshot, EnvironmentStatusChange, Event, EventQuery,
+    ExportedProject, ExportedTask, ImportConflict, ImportSummary, IntegrityProblem, Job, JobDetail,
+    JobError, MaintenanceSummary, NewTask, Origin, PathMap, Project, ProjectRemoval, ProviderStats,
+    RemapSummary, RepairSummary, ResourceUsage, SearchHit, StateExport, Task, TaskCost, TaskCounts,
+    TaskGroup, TaskGroupCounts, TaskNote, TaskPage, TaskPriority, TaskSort,
+};
+pub use crate::environment::EnvironmentPlan;
+pub use crate::log_sections::LogSection;
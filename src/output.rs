@@ -0,0 +1,312 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+const COLUMN_GAP: &str = "  ";
+const ELLIPSIS: char = '…';
+const MIN_TRUNCATED_WIDTH: usize = 8;
+
+/// Decide whether ANSI styling is used for human output. Color is disabled by
+/// `--no-color`, a non-empty `NO_COLOR`, or when stdout is not a terminal.
+pub fn init_color(no_color: bool) {
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let enabled = !no_color && !no_color_env && std::io::stdout().is_terminal();
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+pub fn color_enabled() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+#[derive(Clone, Copy)]
+pub enum Style {
+    Bold,
+    Dim,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    BoldGreen,
+    BoldRed,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Style::Bold => "1",
+            Style::Dim => "2",
+            Style::Red => "31",
+            Style::Green => "32",
+            Style::Yellow => "33",
+            Style::Blue => "34",
+            Style::BoldGreen => "1;32",
+            Style::BoldRed => "1;31",
+        }
+    }
+}
+
+pub fn paint(text: &str, style: Style) -> String {
+    if color_enabled() {
+        format!("\x1b[{}m{text}\x1b[0m", style.code())
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn status_style(status: &str) -> Option<Style> {
+    match status {
+        "complete" | "pool" => Some(Style::Green),
+        "failed" | "timed_out" | "dead" => Some(Style::Red),
+        "pending" | "preparing" | "removing" | "interrupted" | "degraded" => Some(Style::Yellow),
+        "cancelled" | "empty" => Some(Style::Dim),
+        "started" | "running" | "in_use" => Some(Style::Blue),
+        _ => None,
+    }
+}
+
+pub struct Cell {
+    text: String,
+    style: Option<Style>,
+}
+
+impl Cell {
+    pub fn styled(text: impl Into<String>, style: Option<Style>) -> Self {
+        Self {
+            text: text.into(),
+            style,
+        }
+    }
+}
+
+impl From<String> for Cell {
+    fn from(text: String) -> Self {
+        Self::styled(text, None)
+    }
+}
+
+impl From<&str> for Cell {
+    fn from(text: &str) -> Self {
+        Self::styled(text, None)
+    }
+}
+
+struct Column {
+    header: &'static str,
+    truncate: bool,
+}
+
+/// A left-aligned table for human output. Columns marked as truncatable
+/// shrink (with an ellipsis) to fit the terminal width.
+pub struct Table {
+    columns: Vec<Column>,
+    rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    pub fn new(headers: &[&'static str]) -> Self {
+        Self {
+            columns: headers
+                .iter()
+                .map(|&header| Column {
+                    header,
+                    truncate: false,
+                })
+                .collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn truncate(mut self, header: &str) -> Self {
+        if let Some(column) = self.columns.iter_mut().find(|c| c.header == header) {
+            column.truncate = true;
+        }
+        self
+    }
+
+    pub fn row(&mut self, cells: Vec<Cell>) {
+        self.rows.push(cells);
+    }
+
+    pub fn print(&self) {
+        let max_width = if std::io::stdout().is_terminal() {
+            crossterm::terminal::size()
+                .ok()
+                .map(|(cols, _)| cols as usize)
+        } else {
+            None
+        };
+        for line in self.render(max_width) {
+            println!("{line}");
+        }
+    }
+
+    fn render(&self, max_width: Option<usize>) -> Vec<String> {
+        let widths = self.column_widths(max_width);
+        let last = self.columns.len().saturating_sub(1);
+
+        let mut lines = Vec::with_capacity(self.rows.len() + 1);
+        let header_cells: Vec<Cell> = self
+            .columns
+            .iter()
+            .map(|c| Cell::styled(c.header, Some(Style::Bold)))
+            .collect();
+        for cells in std::iter::once(&header_cells).chain(self.rows.iter()) {
+            let mut line = String::new();
+            for (i, cell) in cells.iter().enumerate().take(self.columns.len()) {
+                if i > 0 {
+                    line.push_str(COLUMN_GAP);
+                }
+                let text = truncate(&cell.text, widths[i]);
+                let padding = if i == last {
+                    0
+                } else {
+                    widths[i] - text.chars().count()
+                };
+                match cell.style {
+                    Some(style) => line.push_str(&paint(&text, style)),
+                    None => line.push_str(&text),
+                }
+                line.extend(std::iter::repeat_n(' ', padding));
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    fn column_widths(&self, max_width: Option<usize>) -> Vec<usize> {
+        let mut widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                self.rows
+                    .iter()
+                    .filter_map(|row| row.get(i))
+                    .map(|cell| cell.text.chars().count())
+                    .chain(std::iter::once(column.header.len()))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let Some(max_width) = max_width else {
+            return widths;
+        };
+
+        let gaps = COLUMN_GAP.len() * self.columns.len().saturating_sub(1);
+        let mut total: usize = widths.iter().sum::<usize>() + gaps;
+
+        // Shrink truncatable columns, rightmost first, until the table fits.
+        for i in (0..self.columns.len()).rev() {
+            if total <= max_width {
+                break;
+            }
+            if !self.columns[i].truncate {
+                continue;
+            }
+            let min = self.columns[i].header.len().max(MIN_TRUNCATED_WIDTH);
+            let shrinkable = widths[i].saturating_sub(min);
+            let shrink = shrinkable.min(total - max_width);
+            widths[i] -= shrink;
+            total -= shrink;
+        }
+
+        widths
+    }
+}
+
+/// Version of the `--format json` envelope and the records in it. Fields may
+/// be added at any time; removing, renaming, or changing the meaning of one
+/// bumps this.
+pub const JSON_API_VERSION: u32 = 1;
+
+/// Wrap records in the `--format json` envelope. Commands that show a single
+/// record still return a list, so every command has the same shape.
+pub fn json_envelope<T: serde::Serialize>(items: &[T]) -> anyhow::Result<serde_json::Value> {
+    Ok(serde_json::json!({
+        "api_version": JSON_API_VERSION,
+        "items": serde_json::to_value(items)?,
+    }))
+}
+
+/// Print records as `--format json` output.
+pub fn print_json<T: serde::Serialize>(items: &[T]) -> anyhow::Result<()> {
+    println!("{}", json_envelope(items)?);
+    Ok(())
+}
+
+/// Escape a value for one `--format plain` field, so tabs and newlines in it
+/// can't split the record.
+pub fn plain_field(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(width.saturating_sub(1)).collect();
+    truncated.push(ELLIPSIS);
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_output_is_wrapped_in_a_versioned_envelope() {
+        let value = json_envelope(&[serde_json::json!({ "id": "a" })]).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({ "api_version": 1, "items": [{ "id": "a" }] })
+        );
+        let empty = json_envelope::<serde_json::Value>(&[]).unwrap();
+        assert_eq!(empty["items"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn plain_fields_escape_separators() {
+        assert_eq!(plain_field("a\tb\nc\\d"), "a\\tb\\nc\\\\d");
+    }
+
+    #[test]
+    fn pads_columns_to_widest_cell() {
+        let mut table = Table::new(&["ID", "DESCRIPTION"]);
+        table.row(vec!["abc".into(), "first".into()]);
+        table.row(vec!["a".into(), "second".into()]);
+
+        let lines = table.render(None);
+        assert_eq!(lines[0], "ID   DESCRIPTION");
+        assert_eq!(lines[1], "abc  first");
+        assert_eq!(lines[2], "a    second");
+    }
+
+    #[test]
+    fn truncates_marked_columns_to_fit_width() {
+        let mut table = Table::new(&["ID", "DESCRIPTION"]).truncate("DESCRIPTION");
+        table.row(vec![
+            "abc".into(),
+            "a very long description that will not fit".into(),
+        ]);
+
+        let lines = table.render(Some(20));
+        assert_eq!(lines[1], "abc  a very long de…");
+        assert_eq!(lines[1].chars().count(), 20);
+    }
+
+    #[test]
+    fn leaves_unmarked_columns_untouched() {
+        let mut table = Table::new(&["ID", "PATH"]);
+        table.row(vec![
+            "abc".into(),
+            "/a/very/long/path/that/overflows".into(),
+        ]);
+
+        let lines = table.render(Some(10));
+        assert_eq!(lines[1], "abc  /a/very/long/path/that/overflows");
+    }
+}
@@ -2,6 +2,7 @@ use std::path::PathBuf;
 use std::sync::OnceLock;
 
 static WORK_HOME: OnceLock<Option<PathBuf>> = OnceLock::new();
+static PROFILE: OnceLock<Option<String>> = OnceLock::new();
 
 /// Store the work home override from the CLI flag.
 /// Falls through to `WORK_HOME` env var if `None`.
@@ -9,7 +10,37 @@ pub fn init(work_home: Option<PathBuf>) {
     let _ = WORK_HOME.set(work_home);
 }
 
-fn work_home() -> Option<PathBuf> {
+/// Store the profile from the `--profile` flag. Falls through to the
+/// `WORK_PROFILE` env var if `None`; `default` selects no profile.
+pub fn init_profile(profile: Option<String>) -> anyhow::Result<()> {
+    if let Some(name) = &profile {
+        validate_profile_name(name)?;
+    }
+    let _ = PROFILE.set(profile);
+    Ok(())
+}
+
+/// The selected profile, or `None` for the default one.
+pub fn profile() -> Option<String> {
+    PROFILE
+        .get()
+        .and_then(|p| p.clone())
+        .or_else(|| std::env::var("WORK_PROFILE").ok())
+        .filter(|name| !name.is_empty() && name != "default" && validate_profile_name(name).is_ok())
+}
+
+fn validate_profile_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        anyhow::bail!("invalid profile name {name:?}: use letters, digits, '-', and '_'");
+    }
+    Ok(())
+}
+
+fn base_work_home() -> Option<PathBuf> {
     WORK_HOME.get().and_then(|p| p.clone()).or_else(|| {
         std::env::var("WORK_HOME")
             .ok()
@@ -18,8 +49,71 @@ fn work_home() -> Option<PathBuf> {
     })
 }
 
+/// A named profile is a work home of its own under [`profiles_dir`], so its
+/// daemon, socket, database, and config are separate from every other one.
+fn work_home() -> Option<PathBuf> {
+    match profile() {
+        Some(name) => profiles_dir().ok().map(|dir| dir.join(name)),
+        None => base_work_home(),
+    }
+}
+
+/// Where named profiles keep their work homes: `profiles/` in the default
+/// profile's data directory.
+pub fn profiles_dir() -> Result<PathBuf, anyhow::Error> {
+    Ok(data_dir_in(base_work_home())?.join("profiles"))
+}
+
+/// A profile and the directories its daemon runs from.
+pub struct ProfileDirs {
+    /// `default` for the profile used without `--profile`.
+    pub name: String,
+    pub data_dir: PathBuf,
+    pub runtime_dir: PathBuf,
+}
+
+/// The default profile followed by every named profile that has been used,
+/// sorted by name.
+pub fn list_profiles() -> Result<Vec<ProfileDirs>, anyhow::Error> {
+    let mut profiles = vec![ProfileDirs {
+        name: "default".to_string(),
+        data_dir: data_dir_in(base_work_home())?,
+        runtime_dir: runtime_dir_in(base_work_home())?,
+    }];
+    let mut names = Vec::new();
+    match std::fs::read_dir(profiles_dir()?) {
+        Ok(entries) => {
+            for entry in entries {
+                let entry = entry?;
+                if entry.file_type()?.is_dir()
+                    && let Some(name) = entry.file_name().to_str()
+                    && validate_profile_name(name).is_ok()
+                {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    names.sort();
+    for name in names {
+        let home = profiles_dir()?.join(&name);
+        profiles.push(ProfileDirs {
+            name,
+            data_dir: data_dir_in(Some(home.clone()))?,
+            runtime_dir: runtime_dir_in(Some(home))?,
+        });
+    }
+    Ok(profiles)
+}
+
 pub fn data_dir() -> Result<PathBuf, anyhow::Error> {
-    if let Some(wp) = work_home() {
+    data_dir_in(work_home())
+}
+
+fn data_dir_in(work_home: Option<PathBuf>) -> Result<PathBuf, anyhow::Error> {
+    if let Some(wp) = work_home {
         return Ok(wp.join("data"));
     }
 
@@ -36,7 +130,11 @@ pub fn data_dir() -> Result<PathBuf, anyhow::Error> {
 }
 
 pub fn runtime_dir() -> Result<PathBuf, anyhow::Error> {
-    if let Some(wp) = work_home() {
+    runtime_dir_in(work_home())
+}
+
+fn runtime_dir_in(work_home: Option<PathBuf>) -> Result<PathBuf, anyhow::Error> {
+    if let Some(wp) = work_home {
         return Ok(wp.join("runtime"));
     }
 
@@ -90,6 +188,30 @@ pub fn task_log_path(task_id: &str) -> Result<PathBuf, anyhow::Error> {
     Ok(task_log_dir()?.join(format!("{task_id}.log")))
 }
 
+pub fn task_result_dir() -> Result<PathBuf, anyhow::Error> {
+    Ok(data_dir()?.join("results").join("tasks"))
+}
+
+pub fn task_result_path(task_id: &str) -> Result<PathBuf, anyhow::Error> {
+    Ok(task_result_dir()?.join(format!("{task_id}.json")))
+}
+
+/// Created when a running task is cancelled; runners get it as
+/// `WORK_CANCEL_FILE`.
+pub fn task_cancel_path(task_id: &str) -> Result<PathBuf, anyhow::Error> {
+    Ok(task_result_dir()?.join(format!("{task_id}.cancel")))
+}
+
+/// Files a task leaves behind besides its log and result, such as
+/// `patch.diff`.
+pub fn task_artifact_dir(task_id: &str) -> Result<PathBuf, anyhow::Error> {
+    Ok(data_dir()?.join("artifacts").join("tasks").join(task_id))
+}
+
+pub fn task_diff_path(task_id: &str) -> Result<PathBuf, anyhow::Error> {
+    Ok(task_artifact_dir(task_id)?.join("patch.diff"))
+}
+
 pub fn environment_log_dir() -> Result<PathBuf, anyhow::Error> {
     Ok(data_dir()?.join("logs").join("environments"))
 }
@@ -98,6 +220,26 @@ pub fn environment_log_path(env_id: &str) -> Result<PathBuf, anyhow::Error> {
     Ok(environment_log_dir()?.join(format!("{env_id}.log")))
 }
 
+/// Directory holding recorded `work env exec` sessions for an environment.
+pub fn environment_session_dir(env_id: &str) -> Result<PathBuf, anyhow::Error> {
+    Ok(environment_log_dir()?.join("sessions").join(env_id))
+}
+
+/// Parent of every environment's snapshot directory.
+pub fn snapshots_dir() -> Result<PathBuf, anyhow::Error> {
+    Ok(data_dir()?.join("snapshots"))
+}
+
+/// Snapshots taken of an environment, one directory each, removed with it.
+pub fn environment_snapshot_dir(env_id: &str) -> Result<PathBuf, anyhow::Error> {
+    Ok(snapshots_dir()?.join(env_id))
+}
+
+/// Content-addressed cache shared by environment providers.
+pub fn cache_dir() -> Result<PathBuf, anyhow::Error> {
+    Ok(data_dir()?.join("cache"))
+}
+
 pub fn tui_log_path() -> Result<PathBuf, anyhow::Error> {
     Ok(state_dir()?.join("tui.log"))
 }
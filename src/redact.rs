@@ -0,0 +1,232 @@
+//! Redaction of secrets from task logs and descriptions.
+//!
+//! `[redact] patterns` are regular expressions for text that mustn't be kept
+//! or shown, such as a token an agent echoes. The daemon replaces matches in
+//! task output as it writes the log, and again in everything it serves that
+//! holds task or provider text (logs, tasks, search results, and exports),
+//! which covers anything written before a pattern was added. Output is
+//! matched a line at a time, so a pattern can't span lines.
+
+use std::borrow::Cow;
+use std::sync::Mutex;
+
+use regex::bytes::Regex;
+
+use crate::db::Task;
+use crate::error::{Error, ErrorCode};
+
+/// What a match is replaced with.
+pub const REDACTED: &str = "[redacted]";
+
+/// A partial line held back longer than this is redacted and written as it
+/// is, so output without newlines still shows up.
+const MAX_HELD_BYTES: usize = 16 * 1024;
+
+#[derive(Clone, Debug)]
+pub struct Redactor {
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    /// A redactor for `patterns`, or `None` when there are none.
+    pub fn new(patterns: &[String]) -> Result<Option<Self>, regex::Error> {
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+        let patterns = patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<_, _>>()?;
+        Ok(Some(Self { patterns }))
+    }
+
+    pub fn redact<'a>(&self, bytes: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut out = Cow::Borrowed(bytes);
+        for pattern in &self.patterns {
+            if let Cow::Owned(replaced) = pattern.replace_all(&out, REDACTED.as_bytes()) {
+                out = Cow::Owned(replaced);
+            }
+        }
+        out
+    }
+
+    pub fn redact_str<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self.redact(text.as_bytes()) {
+            Cow::Borrowed(_) => Cow::Borrowed(text),
+            Cow::Owned(bytes) => Cow::Owned(String::from_utf8_lossy(&bytes).into_owned()),
+        }
+    }
+
+    /// Redact a task's description, result, and notes.
+    pub fn redact_task(&self, task: &mut Task) {
+        self.redact_string(&mut task.description);
+        if let Some(summary) = &mut task.result_summary {
+            self.redact_string(summary);
+        }
+        if let Some(data) = &mut task.result_data {
+            self.redact_json(data);
+        }
+        for note in &mut task.notes {
+            self.redact_string(&mut note.text);
+        }
+    }
+
+    /// Redact `text` in place.
+    pub fn redact_string(&self, text: &mut String) {
+        if let Cow::Owned(redacted) = self.redact_str(text) {
+            *text = redacted;
+        }
+    }
+
+    /// Redact every string in `value`, leaving object keys as they are.
+    pub fn redact_json(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(text) => self.redact_string(text),
+            serde_json::Value::Array(items) => {
+                for item in items {
+                    self.redact_json(item);
+                }
+            }
+            serde_json::Value::Object(fields) => {
+                for field in fields.values_mut() {
+                    self.redact_json(field);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The redactor loaded from config.toml, or why it couldn't be.
+static CONFIGURED: Mutex<Option<Result<Option<Redactor>, String>>> = Mutex::new(None);
+
+/// The redactor `config.toml` sets up, if any. It's loaded once, and the
+/// daemon replaces it when it reloads its config. A config that doesn't
+/// load, or has a pattern that doesn't compile, is an error rather than
+/// nothing to redact, so callers hold back what they would have shown;
+/// `work doctor` reports why.
+pub fn configured() -> anyhow::Result<Option<Redactor>> {
+    let mut configured = CONFIGURED.lock().unwrap_or_else(|e| e.into_inner());
+    let loaded = configured.get_or_insert_with(|| {
+        crate::config::load()
+            .and_then(|config| config.redactor())
+            .map_err(|e| format!("{e:#}"))
+    });
+    loaded.clone().map_err(|e| {
+        Error::new(
+            ErrorCode::InvalidConfig,
+            format!("can't redact without valid [redact] patterns: {e}"),
+        )
+        .into()
+    })
+}
+
+/// Use `redactor` from now on, for a config the daemon has just accepted.
+pub fn set_configured(redactor: Option<Redactor>) {
+    *CONFIGURED.lock().unwrap_or_else(|e| e.into_inner()) = Some(Ok(redactor));
+}
+
+/// `contents` of a log with the configured patterns taken out, including
+/// any written before a pattern was added.
+pub fn log(contents: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    Ok(match configured()? {
+        Some(redactor) => redactor.redact(&contents).into_owned(),
+        None => contents,
+    })
+}
+
+/// `task` with the configured patterns taken out of what it says.
+pub fn task(mut task: Task) -> anyhow::Result<Task> {
+    if let Some(redactor) = configured()? {
+        redactor.redact_task(&mut task);
+    }
+    Ok(task)
+}
+
+/// Redacts a stream of output a line at a time, holding back a partial line
+/// until the rest of it arrives so a secret split across reads still
+/// matches. A carriage return ends a line too, for progress output.
+pub struct LineRedactor {
+    redactor: Redactor,
+    held: Vec<u8>,
+}
+
+impl LineRedactor {
+    pub fn new(redactor: Redactor) -> Self {
+        Self {
+            redactor,
+            held: Vec::new(),
+        }
+    }
+
+    /// Redacted output ready to write after `chunk`: every line it
+    /// completes.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.held.extend_from_slice(chunk);
+        let end = match self.held.iter().rposition(|&b| b == b'\n' || b == b'\r') {
+            Some(end) => end + 1,
+            None if self.held.len() > MAX_HELD_BYTES => self.held.len(),
+            None => return Vec::new(),
+        };
+        let lines: Vec<u8> = self.held.drain(..end).collect();
+        self.redactor.redact(&lines).into_owned()
+    }
+
+    /// The partial line left at the end of the output, redacted.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let rest = std::mem::take(&mut self.held);
+        self.redactor.redact(&rest).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor() -> Redactor {
+        Redactor::new(&["ghp_[A-Za-z0-9]+".to_string(), "hunter2".to_string()])
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn replaces_every_match_of_every_pattern() {
+        assert_eq!(
+            redactor().redact_str("token ghp_abc123 and ghp_XYZ, password hunter2"),
+            "token [redacted] and [redacted], password [redacted]"
+        );
+        assert!(matches!(
+            redactor().redact_str("nothing to see"),
+            Cow::Borrowed(_)
+        ));
+        assert!(Redactor::new(&[]).unwrap().is_none());
+        assert!(Redactor::new(&["(".to_string()]).is_err());
+    }
+
+    #[test]
+    fn redacts_strings_throughout_json() {
+        let mut value = serde_json::json!({
+            "description": "use ghp_abc123",
+            "notes": [{ "text": "hunter2" }],
+            "hunter2": 2,
+        });
+        redactor().redact_json(&mut value);
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "description": "use [redacted]",
+                "notes": [{ "text": "[redacted]" }],
+                "hunter2": 2,
+            })
+        );
+    }
+
+    #[test]
+    fn holds_partial_lines_so_split_secrets_still_match() {
+        let mut lines = LineRedactor::new(redactor());
+        assert_eq!(lines.push(b"cloning with ghp_ab"), b"");
+        assert_eq!(lines.push(b"c123\nstill "), b"cloning with [redacted]\n");
+        assert_eq!(lines.push(b"going\rhunt"), b"still going\r");
+        assert_eq!(lines.finish(), b"hunt");
+    }
+}
@@ -0,0 +1,268 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+const REPO: &str = "jclem/work";
+
+/// The version of this binary.
+pub const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The newest published release.
+#[derive(Debug, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    pub html_url: String,
+    #[serde(default)]
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    /// The release's version, without the tag's `v` prefix.
+    pub fn version(&self) -> &str {
+        self.tag_name.strip_prefix('v').unwrap_or(&self.tag_name)
+    }
+
+    fn asset_url(&self, name: &str) -> Option<&str> {
+        self.assets
+            .iter()
+            .find(|a| a.name == name)
+            .map(|a| a.browser_download_url.as_str())
+    }
+}
+
+/// How the running binary was installed, which decides who gets to replace
+/// it. Package managers track the files they install, so swapping the binary
+/// under them would leave them confused about what's installed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum InstallMethod {
+    Homebrew,
+    Mise,
+    Standalone(PathBuf),
+}
+
+impl InstallMethod {
+    /// Work out how the running binary was installed from where it lives.
+    pub fn detect() -> anyhow::Result<Self> {
+        let exe = std::env::current_exe()?;
+        let exe = exe.canonicalize().unwrap_or(exe);
+        Ok(Self::from_path(exe))
+    }
+
+    fn from_path(exe: PathBuf) -> Self {
+        let path = exe.to_string_lossy();
+        if path.contains("/Cellar/") || path.contains("/homebrew/") || path.contains("/linuxbrew/")
+        {
+            Self::Homebrew
+        } else if path.contains("/mise/installs/") {
+            Self::Mise
+        } else {
+            Self::Standalone(exe)
+        }
+    }
+
+    /// The command that upgrades a package-managed install, if any.
+    pub fn upgrade_command(&self) -> Option<&'static str> {
+        match self {
+            Self::Homebrew => Some("brew upgrade jclem/tap/work"),
+            Self::Mise => Some("mise upgrade github:jclem/work"),
+            Self::Standalone(_) => None,
+        }
+    }
+}
+
+/// Ask GitHub for the latest release. Prereleases are never returned.
+pub fn latest_release() -> anyhow::Result<Release> {
+    let url = format!("https://api.github.com/repos/{REPO}/releases/latest");
+    let body = curl(&["-H", "Accept: application/vnd.github+json", &url])?;
+    serde_json::from_slice(&body)
+        .map_err(|e| anyhow::anyhow!("unexpected response from {url}: {e}"))
+}
+
+/// Whether `candidate` is a newer version than `current`. Versions are
+/// compared numerically, component by component; anything after a `-` or
+/// `+` is ignored.
+pub fn is_newer(candidate: &str, current: &str) -> bool {
+    fn parts(version: &str) -> Vec<u64> {
+        let version = version.strip_prefix('v').unwrap_or(version);
+        let core = version.split(['-', '+']).next().unwrap_or_default();
+        core.split('.').map(|p| p.parse().unwrap_or(0)).collect()
+    }
+    let (candidate, current) = (parts(candidate), parts(current));
+    let len = candidate.len().max(current.len());
+    let pad = |v: &[u64]| {
+        (0..len)
+            .map(|i| v.get(i).copied().unwrap_or(0))
+            .collect::<Vec<_>>()
+    };
+    pad(&candidate) > pad(&current)
+}
+
+/// The release archive built for this machine.
+fn archive_name() -> anyhow::Result<String> {
+    let target = match (std::env::consts::ARCH, std::env::consts::OS) {
+        ("aarch64", "macos") => "aarch64-apple-darwin",
+        ("x86_64", "macos") => "x86_64-apple-darwin",
+        ("aarch64", "linux") => "aarch64-unknown-linux-gnu",
+        ("x86_64", "linux") => "x86_64-unknown-linux-gnu",
+        (arch, os) => anyhow::bail!("no release builds for {arch} {os}"),
+    };
+    Ok(format!("work-{target}.tar.xz"))
+}
+
+/// Download `release`'s archive for this machine, check it against the
+/// release's checksums, and replace the binary at `exe` with the one inside.
+pub fn install(release: &Release, exe: &Path) -> anyhow::Result<()> {
+    let archive = archive_name()?;
+    let archive_url = release
+        .asset_url(&archive)
+        .ok_or_else(|| anyhow::anyhow!("release {} has no {archive}", release.tag_name))?;
+    let sums_url = release
+        .asset_url("sha256.sum")
+        .ok_or_else(|| anyhow::anyhow!("release {} has no sha256.sum", release.tag_name))?;
+
+    let staging = std::env::temp_dir().join(format!("work-update-{}", std::process::id()));
+    std::fs::create_dir_all(&staging)?;
+    let result = (|| {
+        let archive_path = staging.join(&archive);
+        std::fs::write(&archive_path, curl(&[archive_url])?)?;
+        let sums = String::from_utf8(curl(&[sums_url])?)?;
+        verify_checksum(&archive_path, &archive, &sums)?;
+
+        let status = Command::new("tar")
+            .arg("-xJf")
+            .arg(&archive_path)
+            .arg("-C")
+            .arg(&staging)
+            .status()?;
+        if !status.success() {
+            anyhow::bail!("tar failed ({status})");
+        }
+        let binary = find_binary(&staging)?
+            .ok_or_else(|| anyhow::anyhow!("{archive} has no work binary"))?;
+        replace(&binary, exe)
+    })();
+    let _ = std::fs::remove_dir_all(&staging);
+    result
+}
+
+fn verify_checksum(path: &Path, name: &str, sums: &str) -> anyhow::Result<()> {
+    let expected = sums
+        .lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(_, file)| file.trim().trim_start_matches('*') == name)
+        .map(|(sum, _)| sum.to_ascii_lowercase())
+        .ok_or_else(|| anyhow::anyhow!("sha256.sum has no entry for {name}"))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    let actual = format!("{:x}", hasher.finalize());
+    if actual != expected {
+        anyhow::bail!("checksum mismatch for {name}: expected {expected}, got {actual}");
+    }
+    Ok(())
+}
+
+fn find_binary(dir: &Path) -> anyhow::Result<Option<PathBuf>> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if let Some(found) = find_binary(&path)? {
+                return Ok(Some(found));
+            }
+        } else if path.file_name().is_some_and(|n| n == "work") {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Swap `binary` in for `exe` with a rename, so anything running `exe`
+/// keeps its old copy and nothing ever sees a half-written file.
+fn replace(binary: &Path, exe: &Path) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = exe
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("{} has no parent directory", exe.display()))?;
+    let incoming = dir.join(format!(".work-update-{}", std::process::id()));
+    std::fs::copy(binary, &incoming)
+        .map_err(|e| anyhow::anyhow!("could not write to {}: {e}", dir.display()))?;
+    std::fs::set_permissions(&incoming, std::fs::Permissions::from_mode(0o755))?;
+    if let Err(e) = std::fs::rename(&incoming, exe) {
+        let _ = std::fs::remove_file(&incoming);
+        anyhow::bail!("could not replace {}: {e}", exe.display());
+    }
+    Ok(())
+}
+
+fn curl(args: &[&str]) -> anyhow::Result<Vec<u8>> {
+    let output = Command::new("curl")
+        .args(["-fsSL", "--proto", "=https"])
+        .args(args)
+        .output()
+        .map_err(|e| anyhow::anyhow!("could not run curl: {e}"))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "curl {} failed: {}",
+            args.last().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_versions_numerically() {
+        assert!(is_newer("v0.1.10", "0.1.9"));
+        assert!(is_newer("0.2.0", "0.1.6"));
+        assert!(is_newer("1.0", "0.9.9"));
+        assert!(!is_newer("0.1.6", "0.1.6"));
+        assert!(!is_newer("0.1.6-rc.1", "0.1.6"));
+        assert!(!is_newer("0.1.5", "0.1.6"));
+    }
+
+    #[test]
+    fn checks_archives_against_their_sums() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("work.tar.xz");
+        std::fs::write(&path, "hello\n").unwrap();
+        let sums =
+            "5891B5B522D5DF086D0FF0B110FBD9D21BB4FC7163AF34D08286A2E846F6BE03 *work.tar.xz\n";
+        verify_checksum(&path, "work.tar.xz", sums).unwrap();
+
+        std::fs::write(&path, "tampered\n").unwrap();
+        let err = verify_checksum(&path, "work.tar.xz", sums).unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"), "{err}");
+        let err = verify_checksum(&path, "other.tar.xz", sums).unwrap_err();
+        assert!(err.to_string().contains("no entry"), "{err}");
+    }
+
+    #[test]
+    fn detects_package_managed_installs() {
+        assert_eq!(
+            InstallMethod::from_path("/opt/homebrew/Cellar/work/0.1.6/bin/work".into()),
+            InstallMethod::Homebrew
+        );
+        assert_eq!(
+            InstallMethod::from_path(
+                "/Users/me/.local/share/mise/installs/github-jclem-work/0.1.6/work".into()
+            ),
+            InstallMethod::Mise
+        );
+        assert_eq!(
+            InstallMethod::from_path("/usr/local/bin/work".into()),
+            InstallMethod::Standalone("/usr/local/bin/work".into())
+        );
+    }
+}
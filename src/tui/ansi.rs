@@ -0,0 +1,156 @@
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span, Text};
+use work::ansi::Piece;
+
+/// Render log output with its SGR color and style codes as styled spans.
+/// Other escape sequences and control characters are dropped, as
+/// `work::ansi::strip` drops them.
+pub fn to_text(log: &str) -> Text<'static> {
+    let mut lines = Vec::new();
+    let mut spans = Vec::new();
+    let mut style = Style::default();
+
+    for piece in work::ansi::pieces(log) {
+        match piece {
+            Piece::Sgr(params) => style = apply_sgr(style, &params),
+            Piece::Text(text) => {
+                let mut parts = text.split('\n').peekable();
+                while let Some(part) = parts.next() {
+                    if !part.is_empty() {
+                        spans.push(Span::styled(part.to_string(), style));
+                    }
+                    if parts.peek().is_some() {
+                        lines.push(Line::from(std::mem::take(&mut spans)));
+                    }
+                }
+            }
+        }
+    }
+    if !spans.is_empty() {
+        lines.push(Line::from(spans));
+    }
+    Text::from(lines)
+}
+
+/// Apply the `;`-separated codes of an `ESC [ ... m` sequence to `style`.
+fn apply_sgr(mut style: Style, params: &str) -> Style {
+    let codes: Vec<u16> = params
+        .split([';', ':'])
+        .map(|code| code.parse().unwrap_or(0))
+        .collect();
+    let mut codes = codes.into_iter();
+    while let Some(code) = codes.next() {
+        match code {
+            0 => style = Style::default(),
+            1 => style = style.add_modifier(Modifier::BOLD),
+            2 => style = style.add_modifier(Modifier::DIM),
+            3 => style = style.add_modifier(Modifier::ITALIC),
+            4 => style = style.add_modifier(Modifier::UNDERLINED),
+            5 => style = style.add_modifier(Modifier::SLOW_BLINK),
+            7 => style = style.add_modifier(Modifier::REVERSED),
+            9 => style = style.add_modifier(Modifier::CROSSED_OUT),
+            22 => style = style.remove_modifier(Modifier::BOLD | Modifier::DIM),
+            23 => style = style.remove_modifier(Modifier::ITALIC),
+            24 => style = style.remove_modifier(Modifier::UNDERLINED),
+            25 => style = style.remove_modifier(Modifier::SLOW_BLINK),
+            27 => style = style.remove_modifier(Modifier::REVERSED),
+            29 => style = style.remove_modifier(Modifier::CROSSED_OUT),
+            30..=37 => style.fg = Some(basic_color(code - 30)),
+            38 => style.fg = extended_color(&mut codes).or(style.fg),
+            39 => style.fg = None,
+            40..=47 => style.bg = Some(basic_color(code - 40)),
+            48 => style.bg = extended_color(&mut codes).or(style.bg),
+            49 => style.bg = None,
+            90..=97 => style.fg = Some(bright_color(code - 90)),
+            100..=107 => style.bg = Some(bright_color(code - 100)),
+            _ => {}
+        }
+    }
+    style
+}
+
+/// The color after a 38 or 48 code: `5;N` for the 256-color palette or
+/// `2;R;G;B` for true color.
+fn extended_color(codes: &mut impl Iterator<Item = u16>) -> Option<Color> {
+    let byte = |code: Option<u16>| code.and_then(|c| u8::try_from(c).ok());
+    match codes.next()? {
+        5 => byte(codes.next()).map(Color::Indexed),
+        2 => {
+            let (r, g, b) = (byte(codes.next()), byte(codes.next()), byte(codes.next()));
+            Some(Color::Rgb(r?, g?, b?))
+        }
+        _ => None,
+    }
+}
+
+fn basic_color(index: u16) -> Color {
+    match index {
+        0 => Color::Black,
+        1 => Color::Red,
+        2 => Color::Green,
+        3 => Color::Yellow,
+        4 => Color::Blue,
+        5 => Color::Magenta,
+        6 => Color::Cyan,
+        _ => Color::Gray,
+    }
+}
+
+fn bright_color(index: u16) -> Color {
+    match index {
+        0 => Color::DarkGray,
+        1 => Color::LightRed,
+        2 => Color::LightGreen,
+        3 => Color::LightYellow,
+        4 => Color::LightBlue,
+        5 => Color::LightMagenta,
+        6 => Color::LightCyan,
+        _ => Color::White,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_become_styled_spans_that_carry_across_lines() {
+        let text = to_text("\x1b]0;title\x07plain \x1b[1;32mok\r\nstill\x1b[0m\tdone\n");
+        assert_eq!(
+            text.lines,
+            vec![
+                Line::from(vec![
+                    Span::raw("plain "),
+                    Span::styled(
+                        "ok",
+                        Style::default()
+                            .fg(Color::Green)
+                            .add_modifier(Modifier::BOLD)
+                    ),
+                ]),
+                Line::from(vec![
+                    Span::styled(
+                        "still",
+                        Style::default()
+                            .fg(Color::Green)
+                            .add_modifier(Modifier::BOLD)
+                    ),
+                    Span::raw("\tdone"),
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn extended_colors_and_resets() {
+        let style = apply_sgr(Style::default(), "38;5;208;48;2;1;2;3;4");
+        assert_eq!(style.fg, Some(Color::Indexed(208)));
+        assert_eq!(style.bg, Some(Color::Rgb(1, 2, 3)));
+        assert_eq!(style.add_modifier, Modifier::UNDERLINED);
+
+        let style = apply_sgr(style, "39;24");
+        assert_eq!(style.fg, None);
+        assert_eq!(style.bg, Some(Color::Rgb(1, 2, 3)));
+        assert!(style.add_modifier.is_empty());
+    }
+}
@@ -1,8 +1,18 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 
-use crate::client::DaemonClient;
-use crate::db::{Environment, Project, Task};
-use crate::paths;
+use ratatui::text::{Line, Text};
+
+use work::client::DaemonClient;
+use work::db::{
+    Environment, EnvironmentSnapshot, EnvironmentStatusChange, Project, SearchHit,
+    TERMINAL_TASK_STATUSES, Task, TaskCounts, TaskGroup, TaskPage, TaskSort,
+};
+use work::log_sections::LogSection;
+use work::paths;
+
+use super::diagnostics::{self, ConfigReport};
+use super::keys::{Context, Keymap};
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Tab {
@@ -11,15 +21,17 @@ pub enum Tab {
     Environments,
     Daemon,
     Logs,
+    Config,
 }
 
 impl Tab {
-    pub const ALL: [Tab; 5] = [
+    pub const ALL: [Tab; 6] = [
         Tab::Tasks,
         Tab::Projects,
         Tab::Environments,
         Tab::Daemon,
         Tab::Logs,
+        Tab::Config,
     ];
 
     pub fn label(self) -> &'static str {
@@ -29,6 +41,7 @@ impl Tab {
             Tab::Environments => "Environments",
             Tab::Daemon => "Daemon",
             Tab::Logs => "Logs",
+            Tab::Config => "Config",
         }
     }
 
@@ -39,26 +52,129 @@ impl Tab {
             Tab::Environments => 2,
             Tab::Daemon => 3,
             Tab::Logs => 4,
+            Tab::Config => 5,
         }
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum TaskViewMode {
+    #[default]
     Flat,
     Tree,
 }
 
+/// The sort after `sort`, cycled with `s`.
+fn next_sort(sort: TaskSort) -> TaskSort {
+    match sort {
+        TaskSort::Created => TaskSort::Status,
+        TaskSort::Status => TaskSort::Duration,
+        TaskSort::Duration => TaskSort::Created,
+    }
+}
+
+pub fn sort_label(sort: TaskSort) -> &'static str {
+    match sort {
+        TaskSort::Created => "newest first",
+        TaskSort::Status => "by status",
+        TaskSort::Duration => "longest first",
+    }
+}
+
+/// Order `rows`, indices into `tasks`, longest run first as of `now`, the
+/// way the daemon did as of the last poll. Running tasks keep running in
+/// between. Ties keep their order.
+fn sort_by_run(tasks: &[Task], rows: &mut [usize], now: chrono::DateTime<chrono::Utc>) {
+    rows.sort_by_key(|&ti| std::cmp::Reverse(tasks[ti].run_ms(now)));
+}
+
+/// A quick filter for the Tasks tab, typed after `f`: words naming task
+/// statuses or projects, e.g. `failed timed_out api`. `status:` or
+/// `project:` says which a word is when it could be either. A task shows
+/// when its status is one of the statuses named, if any, and its project
+/// one of the projects named, if any.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TaskFilter {
+    pub text: String,
+    statuses: Vec<String>,
+    projects: Vec<String>,
+}
+
+impl TaskFilter {
+    /// The filter `text` describes, or `None` for a blank one.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut filter = TaskFilter {
+            text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+            ..TaskFilter::default()
+        };
+        for word in text.split_whitespace() {
+            if let Some(status) = word.strip_prefix("status:") {
+                filter.statuses.push(status.to_string());
+            } else if let Some(project) = word.strip_prefix("project:") {
+                filter.projects.push(project.to_string());
+            } else if word == "pending"
+                || word == "started"
+                || TERMINAL_TASK_STATUSES.contains(&word)
+            {
+                filter.statuses.push(word.to_string());
+            } else {
+                filter.projects.push(word.to_string());
+            }
+        }
+        (!filter.text.is_empty()).then_some(filter)
+    }
+
+    /// Ask for only the tasks this matches.
+    fn narrow(&self, page: &mut TaskPage) {
+        page.status = (!self.statuses.is_empty()).then(|| self.statuses.join(","));
+        page.project = (!self.projects.is_empty()).then(|| self.projects.join(","));
+    }
+
+    /// How many tasks this matches, loaded or not.
+    pub fn count(&self, counts: &TaskCounts, projects: &[Project]) -> usize {
+        counts
+            .by_status
+            .iter()
+            .filter(|(project_id, _)| {
+                self.projects.is_empty()
+                    || projects.iter().any(|project| {
+                        project.id == **project_id
+                            && self.projects.iter().any(|name| project.answers_to(name))
+                    })
+            })
+            .flat_map(|(_, statuses)| statuses)
+            .filter(|(status, _)| self.statuses.is_empty() || self.statuses.contains(status))
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
 pub enum TreeRow {
     Project(usize),
+    Group(usize),
     Task(usize),
     TaskEnvironment(usize),
 }
 
+/// A row of the log view.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogRow {
+    /// A line of the log, by number from 0.
+    Line(usize),
+    /// The heading of a section, by index into [`App::log_sections`].
+    Section(usize),
+}
+
 pub enum DetailView {
-    TaskLog { task_id: String },
-    EnvironmentLog { env_id: String },
+    TaskLog {
+        task_id: String,
+    },
+    EnvironmentLog {
+        env_id: String,
+        /// Filled in by a background `du` once it finishes.
+        disk_usage: Arc<Mutex<Option<String>>>,
+    },
 }
 
 pub enum Confirm {
@@ -66,8 +182,16 @@ pub enum Confirm {
         task_id: String,
         skip_provider: bool,
     },
+    /// Removing a project removes its environments and tasks too.
     Project {
         project_name: String,
+        tasks: usize,
+    },
+    /// Removing a group removes its tasks too.
+    Group {
+        group_id: String,
+        tasks: usize,
+        skip_provider: bool,
     },
     Environment {
         env_id: String,
@@ -79,57 +203,179 @@ pub struct CreateTaskPrompt {
     pub selected_project: usize,
 }
 
+/// A note being typed after `N` for the selected or open task.
+pub struct NotePrompt {
+    pub task_id: String,
+    pub text: String,
+}
+
+/// Maximum number of results fetched for a Tasks tab search.
+const SEARCH_LIMIT: usize = 100;
+
+/// Tasks fetched at a time. More are loaded as the list is scrolled, so an
+/// install with thousands of tasks doesn't hold them all.
+const TASK_PAGE_SIZE: usize = 200;
+
+/// Search results shown in place of the Tasks list until cleared.
+pub struct TaskSearch {
+    pub query: String,
+    pub hits: Vec<SearchHit>,
+}
+
 pub struct App {
     pub should_quit: bool,
     pub tab: Tab,
     pub detail: Option<DetailView>,
     pub confirm: Option<Confirm>,
     pub create_task_prompt: Option<CreateTaskPrompt>,
+    /// Query being typed after `/` on the Tasks tab.
+    pub search_prompt: Option<String>,
+    /// Filter being typed after `f` on the Tasks tab.
+    pub filter_prompt: Option<String>,
+    pub search: Option<TaskSearch>,
+    pub note_prompt: Option<NotePrompt>,
+    /// The first `task_limit` tasks the list shows, or fewer once there are
+    /// no more.
     pub tasks: Vec<Task>,
+    task_limit: usize,
+    more_tasks: bool,
+    /// Every task, counted by the daemon rather than from those loaded.
+    pub task_counts: TaskCounts,
+    pub groups: Vec<TaskGroup>,
     pub projects: Vec<Project>,
     pub environments: Vec<Environment>,
+    /// Status changes of the environment open in the detail view.
+    pub environment_history: Vec<EnvironmentStatusChange>,
+    /// Snapshots of the environment open in the detail view, newest first.
+    pub environment_snapshots: Vec<EnvironmentSnapshot>,
     pub selected: usize,
     pub log_content: String,
+    /// `log_content` parsed for display, as plain or colored text.
+    pub log_lines: Vec<Line<'static>>,
+    /// The `::group::` sections of `log_content`.
+    pub log_sections: Vec<LogSection>,
+    /// Sections folded or unfolded by hand, by their first line. The rest
+    /// are folded once they end.
+    log_folds: HashMap<usize, bool>,
+    /// The row of [`log_rows`](Self::log_rows) at the bottom of the view.
     pub log_scroll: usize,
+    /// Show logs with escape sequences stripped instead of rendered.
+    pub log_plain: bool,
     pub error: Option<String>,
+    /// Shown in the status bar until the next key press, such as problems
+    /// with `[tui.keys]` found at startup.
+    pub notice: Option<String>,
+    pub keys: Keymap,
+    /// The help overlay listing the keys for what's on screen.
+    pub show_help: bool,
     pub daemon_connected: bool,
+    /// Jobs that exhausted their retries and await `work job retry`.
+    pub dead_jobs: usize,
     pub tui_log_content: String,
     pub tui_log_scroll: usize,
     pub task_view_mode: TaskViewMode,
+    pub task_sort: TaskSort,
+    pub task_filter: Option<TaskFilter>,
+    /// The tasks the list shows, filtered and sorted, as indices into
+    /// `tasks`.
+    pub task_rows: Vec<usize>,
     pub tree_rows: Vec<TreeRow>,
     pub collapsed_projects: HashSet<usize>,
+    pub collapsed_groups: HashSet<usize>,
     pub collapsed_tasks: HashSet<usize>,
+    /// Built when the Config tab is opened or reloaded with `r`.
+    pub config_report: Option<ConfigReport>,
+    pub config_scroll: usize,
 }
 
 impl App {
     pub fn new() -> Self {
-        let task_view_mode = load_task_view_mode();
+        let state = load_state();
+        let (keys, key_problems) = load_keymap();
         Self {
             should_quit: false,
             tab: Tab::Tasks,
             detail: None,
             confirm: None,
             create_task_prompt: None,
+            search_prompt: None,
+            filter_prompt: None,
+            search: None,
+            note_prompt: None,
             tasks: Vec::new(),
+            task_limit: TASK_PAGE_SIZE,
+            more_tasks: false,
+            task_counts: TaskCounts::default(),
+            groups: Vec::new(),
             projects: Vec::new(),
             environments: Vec::new(),
+            environment_history: Vec::new(),
+            environment_snapshots: Vec::new(),
             selected: 0,
             log_content: String::new(),
+            log_lines: Vec::new(),
+            log_sections: Vec::new(),
+            log_folds: HashMap::new(),
             log_scroll: 0,
+            log_plain: false,
             error: None,
+            notice: (!key_problems.is_empty()).then(|| key_problems.join("; ")),
+            keys,
+            show_help: false,
             daemon_connected: false,
+            dead_jobs: 0,
             tui_log_content: String::new(),
             tui_log_scroll: 0,
-            task_view_mode,
+            task_view_mode: state.task_view_mode,
+            task_sort: state.task_sort,
+            task_filter: TaskFilter::parse(&state.task_filter),
+            task_rows: Vec::new(),
             tree_rows: Vec::new(),
             collapsed_projects: HashSet::new(),
+            collapsed_groups: HashSet::new(),
             collapsed_tasks: HashSet::new(),
+            config_report: None,
+            config_scroll: 0,
         }
     }
 
     pub async fn poll(&mut self, client: &DaemonClient) {
-        match client.list_tasks().await {
+        self.load_tasks(client).await;
+
+        if let Ok(projects) = client.list_projects().await {
+            self.projects = projects;
+        }
+
+        if let Ok(groups) = client.list_task_groups().await {
+            self.groups = groups;
+        }
+
+        if let Ok(environments) = client.list_environments().await {
+            self.environments = environments;
+        }
+
+        if let Ok(jobs) = client.list_jobs().await {
+            self.dead_jobs = jobs.iter().filter(|j| j.status == "dead").count();
+        }
+
+        self.refresh_environment_history(client).await;
+        self.load_more_tasks(client).await;
+
+        self.rebuild_tree();
+        self.clamp_selected();
+        self.refresh_tui_logs();
+        self.refresh_detail_logs(client).await;
+    }
+
+    /// Fetch the tasks the list shows, filtered and sorted by the daemon,
+    /// and how many there are.
+    async fn load_tasks(&mut self, client: &DaemonClient) {
+        match client
+            .list_tasks_page(&self.task_page(self.task_limit))
+            .await
+        {
             Ok(tasks) => {
+                self.more_tasks = tasks.len() == self.task_limit;
                 self.tasks = tasks;
                 self.error = None;
                 self.daemon_connected = true;
@@ -140,56 +386,162 @@ impl App {
             }
         }
 
-        if let Ok(projects) = client.list_projects().await {
-            self.projects = projects;
+        if let Ok(counts) = client.count_tasks().await {
+            self.task_counts = counts;
         }
+    }
 
-        if let Ok(environments) = client.list_environments().await {
-            self.environments = environments;
+    /// The first `limit` tasks the list shows.
+    fn task_page(&self, limit: usize) -> TaskPage {
+        let mut page = TaskPage {
+            limit: Some(limit),
+            sort: self.task_sort,
+            ..TaskPage::default()
+        };
+        if let Some(filter) = &self.task_filter {
+            filter.narrow(&mut page);
         }
+        page
+    }
 
+    /// Fetch the next page of tasks once the selection nears the end of the
+    /// ones loaded, so the list grows as it's scrolled.
+    async fn load_more_tasks(&mut self, client: &DaemonClient) {
+        if !self.more_tasks
+            || self.tab != Tab::Tasks
+            || self.search.is_some()
+            || self.selected + TASK_PAGE_SIZE / 4 < self.list_len()
+        {
+            return;
+        }
+        let Some(last) = self.tasks.last() else {
+            return;
+        };
+        let mut next = self.task_page(TASK_PAGE_SIZE);
+        // A cursor only pages tasks newest first; other orders skip the ones
+        // loaded.
+        if self.task_sort == TaskSort::Created {
+            next.cursor = Some(last.cursor());
+        } else {
+            next.offset = Some(self.tasks.len());
+        }
+        if let Ok(tasks) = client.list_tasks_page(&next).await {
+            self.more_tasks = tasks.len() == TASK_PAGE_SIZE;
+            self.task_limit += TASK_PAGE_SIZE;
+            self.tasks.extend(tasks);
+        }
+    }
+
+    /// The tasks loaded, in the order the daemon sent them, except that
+    /// running tasks move up as they run longer.
+    fn rebuild_task_rows(&mut self) {
+        let mut rows: Vec<usize> = (0..self.tasks.len()).collect();
+        if self.task_sort == TaskSort::Duration {
+            sort_by_run(&self.tasks, &mut rows, chrono::Utc::now());
+        }
+        self.task_rows = rows;
+    }
+
+    /// Re-sort the list by how long tasks have run by now, keeping the
+    /// selected task selected, when the list is by duration and some task
+    /// is still running. Called every tick, since nothing else changes.
+    pub fn refresh_task_order(&mut self) {
+        if self.task_sort != TaskSort::Duration
+            || self.search.is_some()
+            || !self.tasks.iter().any(|task| task.status == "started")
+        {
+            return;
+        }
+        let selected = self.selected_task_index();
         self.rebuild_tree();
-        self.clamp_selected();
-        self.refresh_tui_logs();
-        self.refresh_detail_logs();
+        if let Some(ti) = selected {
+            let row = match self.task_view_mode {
+                TaskViewMode::Flat => self.task_rows.iter().position(|&row| row == ti),
+                TaskViewMode::Tree => self
+                    .tree_rows
+                    .iter()
+                    .position(|row| matches!(row, TreeRow::Task(row) if *row == ti)),
+            };
+            if let Some(row) = row {
+                self.selected = row;
+            }
+        }
     }
 
     pub fn rebuild_tree(&mut self) {
+        self.rebuild_task_rows();
         self.tree_rows.clear();
 
-        // Group tasks by project, preserving project order.
+        // Group tasks by project, preserving project order. Within a project,
+        // task groups come first with their tasks under them. A filter hides
+        // groups with none of its tasks.
         for (pi, project) in self.projects.iter().enumerate() {
+            let project_groups: Vec<usize> =
+                self.groups
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, g)| g.project_id == project.id)
+                    .filter(|(_, g)| {
+                        self.task_filter.is_none()
+                            || self.task_rows.iter().any(|&ti| {
+                                self.tasks[ti].group_id.as_deref() == Some(g.id.as_str())
+                            })
+                    })
+                    .map(|(i, _)| i)
+                    .collect();
             let project_tasks: Vec<usize> = self
-                .tasks
+                .task_rows
                 .iter()
-                .enumerate()
-                .filter(|(_, t)| t.project_id == project.id)
-                .map(|(i, _)| i)
+                .copied()
+                .filter(|&ti| {
+                    let t = &self.tasks[ti];
+                    t.project_id == project.id && self.task_group(t).is_none()
+                })
                 .collect();
 
-            if project_tasks.is_empty() {
+            if project_groups.is_empty() && project_tasks.is_empty() {
                 continue;
             }
 
             self.tree_rows.push(TreeRow::Project(pi));
 
-            if !self.collapsed_projects.contains(&pi) {
-                for ti in project_tasks {
-                    self.tree_rows.push(TreeRow::Task(ti));
-                    if !self.collapsed_tasks.contains(&ti) {
-                        self.tree_rows.push(TreeRow::TaskEnvironment(ti));
+            if self.collapsed_projects.contains(&pi) {
+                continue;
+            }
+            for gi in project_groups {
+                self.tree_rows.push(TreeRow::Group(gi));
+                if self.collapsed_groups.contains(&gi) {
+                    continue;
+                }
+                let group_id = self.groups[gi].id.as_str();
+                for &ti in &self.task_rows {
+                    if self.tasks[ti].group_id.as_deref() == Some(group_id) {
+                        self.tree_rows.push(TreeRow::Task(ti));
+                        if !self.collapsed_tasks.contains(&ti) {
+                            self.tree_rows.push(TreeRow::TaskEnvironment(ti));
+                        }
                     }
                 }
             }
+            for ti in project_tasks {
+                self.tree_rows.push(TreeRow::Task(ti));
+                if !self.collapsed_tasks.contains(&ti) {
+                    self.tree_rows.push(TreeRow::TaskEnvironment(ti));
+                }
+            }
         }
 
         // Tasks with no matching project.
         let orphan_tasks: Vec<usize> = self
-            .tasks
+            .task_rows
             .iter()
-            .enumerate()
-            .filter(|(_, t)| !self.projects.iter().any(|p| p.id == t.project_id))
-            .map(|(i, _)| i)
+            .copied()
+            .filter(|&ti| {
+                !self
+                    .projects
+                    .iter()
+                    .any(|p| p.id == self.tasks[ti].project_id)
+            })
             .collect();
 
         if !orphan_tasks.is_empty() {
@@ -213,6 +565,12 @@ impl App {
                 self.rebuild_tree();
                 self.move_selected_to_project(pi);
             }
+            Some(TreeRow::Group(gi)) => {
+                let gi = *gi;
+                self.collapsed_groups.insert(gi);
+                self.rebuild_tree();
+                self.clamp_selected();
+            }
             Some(TreeRow::Task(ti)) => {
                 let ti = *ti;
                 // Task has children — collapse it.
@@ -249,6 +607,13 @@ impl App {
                     self.clamp_selected();
                 }
             }
+            Some(TreeRow::Group(gi)) => {
+                let gi = *gi;
+                if self.collapsed_groups.remove(&gi) {
+                    self.rebuild_tree();
+                    self.clamp_selected();
+                }
+            }
             Some(TreeRow::Task(ti)) => {
                 let ti = *ti;
                 if self.collapsed_tasks.remove(&ti) {
@@ -267,6 +632,9 @@ impl App {
         for pi in 0..self.projects.len() {
             self.collapsed_projects.insert(pi);
         }
+        for gi in 0..self.groups.len() {
+            self.collapsed_groups.insert(gi);
+        }
         for ti in 0..self.tasks.len() {
             self.collapsed_tasks.insert(ti);
         }
@@ -279,6 +647,7 @@ impl App {
             return;
         }
         self.collapsed_projects.clear();
+        self.collapsed_groups.clear();
         self.collapsed_tasks.clear();
         self.rebuild_tree();
         self.clamp_selected();
@@ -298,10 +667,20 @@ impl App {
         self.collapsed_projects.contains(&project_index)
     }
 
+    pub fn is_group_collapsed(&self, group_index: usize) -> bool {
+        self.collapsed_groups.contains(&group_index)
+    }
+
     pub fn is_task_collapsed(&self, task_index: usize) -> bool {
         self.collapsed_tasks.contains(&task_index)
     }
 
+    /// The group `task` belongs to, if it is still listed.
+    pub fn task_group(&self, task: &Task) -> Option<&TaskGroup> {
+        let group_id = task.group_id.as_deref()?;
+        self.groups.iter().find(|g| g.id == group_id)
+    }
+
     fn clamp_selected(&mut self) {
         let len = self.list_len();
         if len == 0 {
@@ -313,14 +692,16 @@ impl App {
 
     fn list_len(&self) -> usize {
         match self.tab {
+            Tab::Tasks if self.search.is_some() => self.search.as_ref().map_or(0, |s| s.hits.len()),
             Tab::Tasks => match self.task_view_mode {
-                TaskViewMode::Flat => self.tasks.len(),
+                TaskViewMode::Flat => self.task_rows.len(),
                 TaskViewMode::Tree => self.tree_rows.len(),
             },
             Tab::Projects => self.projects.len(),
             Tab::Environments => self.environments.len(),
             Tab::Daemon => 0,
             Tab::Logs => 0,
+            Tab::Config => 0,
         }
     }
 
@@ -330,28 +711,119 @@ impl App {
             TaskViewMode::Tree => TaskViewMode::Flat,
         };
         self.selected = 0;
-        save_task_view_mode(self.task_view_mode);
+        self.save_state();
+    }
+
+    pub async fn cycle_task_sort(&mut self, client: &DaemonClient) {
+        if self.tab != Tab::Tasks {
+            return;
+        }
+        self.task_sort = next_sort(self.task_sort);
+        self.save_state();
+        self.reload_tasks(client).await;
+    }
+
+    /// Load the list again from the top after its filter or sort changed.
+    async fn reload_tasks(&mut self, client: &DaemonClient) {
+        self.selected = 0;
+        self.load_tasks(client).await;
+        self.rebuild_tree();
+        self.clamp_selected();
+    }
+
+    pub fn begin_filter(&mut self) {
+        if self.tab != Tab::Tasks {
+            return;
+        }
+        let text = self.task_filter.as_ref().map(|f| f.text.clone());
+        self.filter_prompt = Some(text.unwrap_or_default());
+    }
+
+    pub fn filter_prompt_push(&mut self, c: char) {
+        if let Some(prompt) = self.filter_prompt.as_mut() {
+            prompt.push(c);
+        }
+    }
+
+    pub fn filter_prompt_pop(&mut self) {
+        if let Some(prompt) = self.filter_prompt.as_mut() {
+            prompt.pop();
+        }
+    }
+
+    pub fn cancel_filter_prompt(&mut self) {
+        self.filter_prompt = None;
+    }
+
+    /// Show only the tasks the typed filter matches. An empty filter shows
+    /// them all again.
+    pub async fn submit_filter(&mut self, client: &DaemonClient) {
+        let Some(text) = self.filter_prompt.take() else {
+            return;
+        };
+        self.task_filter = TaskFilter::parse(&text);
+        self.save_state();
+        self.reload_tasks(client).await;
+    }
+
+    fn save_state(&self) {
+        save_state(&TuiState {
+            task_view_mode: self.task_view_mode,
+            task_sort: self.task_sort,
+            task_filter: self
+                .task_filter
+                .as_ref()
+                .map(|f| f.text.clone())
+                .unwrap_or_default(),
+        });
     }
 
     pub fn next_tab(&mut self) {
         let idx = (self.tab.index() + 1) % Tab::ALL.len();
-        self.tab = Tab::ALL[idx];
-        self.selected = 0;
+        self.select_tab(idx);
     }
 
     pub fn prev_tab(&mut self) {
         let idx = (self.tab.index() + Tab::ALL.len() - 1) % Tab::ALL.len();
-        self.tab = Tab::ALL[idx];
-        self.selected = 0;
+        self.select_tab(idx);
+    }
+
+    /// Where keys are read when no log, prompt, or dialog is open.
+    pub fn context(&self) -> Context {
+        match self.tab {
+            Tab::Tasks => Context::Tasks,
+            Tab::Projects => Context::Projects,
+            Tab::Environments => Context::Environments,
+            Tab::Daemon => Context::Daemon,
+            Tab::Logs => Context::Logs,
+            Tab::Config => Context::Config,
+        }
     }
 
     pub fn select_tab(&mut self, idx: usize) {
         if let Some(&tab) = Tab::ALL.get(idx) {
             self.tab = tab;
             self.selected = 0;
+            if tab == Tab::Config {
+                self.reload_config_report();
+            }
         }
     }
 
+    /// Re-read the config file and re-run provider checks.
+    pub fn reload_config_report(&mut self) {
+        self.config_report = Some(diagnostics::build(&self.projects));
+        self.config_scroll = 0;
+    }
+
+    pub fn scroll_config_down(&mut self, n: usize) {
+        self.config_scroll = self.config_scroll.saturating_add(n);
+    }
+
+    pub fn scroll_config_up(&mut self, n: usize) {
+        self.config_scroll = self.config_scroll.saturating_sub(n);
+    }
+
     pub fn select_next(&mut self) {
         let len = self.list_len();
         if len > 0 && self.selected < len - 1 {
@@ -367,14 +839,12 @@ impl App {
 
     /// Returns the task index for the currently selected row, if it points to a task.
     fn selected_task_index(&self) -> Option<usize> {
+        if let Some(search) = &self.search {
+            let hit = search.hits.get(self.selected)?;
+            return self.tasks.iter().position(|t| t.id == hit.task.id);
+        }
         match self.task_view_mode {
-            TaskViewMode::Flat => {
-                if self.selected < self.tasks.len() {
-                    Some(self.selected)
-                } else {
-                    None
-                }
-            }
+            TaskViewMode::Flat => self.task_rows.get(self.selected).copied(),
             TaskViewMode::Tree => match self.tree_rows.get(self.selected) {
                 Some(TreeRow::Task(ti)) => Some(*ti),
                 _ => None,
@@ -382,20 +852,38 @@ impl App {
         }
     }
 
+    /// The group on the selected row, in tree view.
+    fn selected_group(&self) -> Option<&TaskGroup> {
+        if self.search.is_some() || self.task_view_mode != TaskViewMode::Tree {
+            return None;
+        }
+        match self.tree_rows.get(self.selected) {
+            Some(TreeRow::Group(gi)) => self.groups.get(*gi),
+            _ => None,
+        }
+    }
+
     fn default_project_id_for_new_task(&self) -> Option<&str> {
         if self.tab != Tab::Tasks {
             return None;
         }
+        if let Some(search) = &self.search {
+            return search
+                .hits
+                .get(self.selected)
+                .map(|hit| hit.task.project_id.as_str());
+        }
 
         match self.task_view_mode {
             TaskViewMode::Flat => self
-                .tasks
+                .task_rows
                 .get(self.selected)
-                .map(|task| task.project_id.as_str()),
+                .map(|&ti| self.tasks[ti].project_id.as_str()),
             TaskViewMode::Tree => match self.tree_rows.get(self.selected) {
                 Some(TreeRow::Project(pi)) => {
                     self.projects.get(*pi).map(|project| project.id.as_str())
                 }
+                Some(TreeRow::Group(gi)) => self.groups.get(*gi).map(|g| g.project_id.as_str()),
                 Some(TreeRow::Task(ti) | TreeRow::TaskEnvironment(ti)) => {
                     self.tasks.get(*ti).map(|task| task.project_id.as_str())
                 }
@@ -450,36 +938,263 @@ impl App {
             .and_then(|prompt| self.projects.get(prompt.selected_project))
     }
 
-    pub fn enter_detail(&mut self) {
+    pub async fn enter_detail(&mut self, client: &DaemonClient) {
         match self.tab {
             Tab::Tasks => {
                 if let Some(ti) = self.selected_task_index() {
                     let task_id = self.tasks[ti].id.clone();
-                    self.log_content = read_task_log(&task_id);
-                    self.log_scroll = self.log_content.lines().count().saturating_sub(1);
+                    self.set_log_content(read_task_log(client, &task_id).await);
+                    self.scroll_log_bottom();
                     self.detail = Some(DetailView::TaskLog { task_id });
                 }
             }
             Tab::Environments => {
                 if let Some(env) = self.environments.get(self.selected) {
                     let env_id = env.id.clone();
-                    self.log_content = read_environment_log(&env_id);
-                    self.log_scroll = self.log_content.lines().count().saturating_sub(1);
-                    self.detail = Some(DetailView::EnvironmentLog { env_id });
+                    let disk_usage = spawn_disk_usage(client, &env_id);
+                    self.set_log_content(read_environment_log(client, &env_id).await);
+                    self.scroll_log_bottom();
+                    self.detail = Some(DetailView::EnvironmentLog { env_id, disk_usage });
                 }
             }
             _ => {}
         }
     }
 
+    pub fn begin_search(&mut self) {
+        if self.tab != Tab::Tasks {
+            return;
+        }
+        let query = self.search.as_ref().map(|s| s.query.clone());
+        self.search_prompt = Some(query.unwrap_or_default());
+    }
+
+    pub fn search_prompt_push(&mut self, c: char) {
+        if let Some(prompt) = self.search_prompt.as_mut() {
+            prompt.push(c);
+        }
+    }
+
+    pub fn search_prompt_pop(&mut self) {
+        if let Some(prompt) = self.search_prompt.as_mut() {
+            prompt.pop();
+        }
+    }
+
+    pub fn cancel_search_prompt(&mut self) {
+        self.search_prompt = None;
+    }
+
+    /// Run the typed query against task descriptions and logs. An empty
+    /// query clears the current search.
+    pub async fn submit_search(&mut self, client: &DaemonClient) {
+        let Some(query) = self.search_prompt.take() else {
+            return;
+        };
+        if query.trim().is_empty() {
+            self.clear_search();
+            return;
+        }
+        match client.search_tasks(&query, true, Some(SEARCH_LIMIT)).await {
+            Ok(hits) => {
+                self.search = Some(TaskSearch { query, hits });
+                self.selected = 0;
+                self.error = None;
+            }
+            Err(e) => self.error = Some(format!("search failed: {e}")),
+        }
+    }
+
+    pub fn clear_search(&mut self) {
+        if self.search.take().is_some() {
+            self.selected = 0;
+        }
+    }
+
+    /// Start a note for the task whose log is open, or else the selected
+    /// task.
+    pub fn begin_note(&mut self) {
+        let task_id = match &self.detail {
+            Some(DetailView::TaskLog { task_id }) => Some(task_id.clone()),
+            Some(DetailView::EnvironmentLog { .. }) => None,
+            None if self.tab == Tab::Tasks => self
+                .selected_task_index()
+                .map(|ti| self.tasks[ti].id.clone()),
+            None => None,
+        };
+        if let Some(task_id) = task_id {
+            self.note_prompt = Some(NotePrompt {
+                task_id,
+                text: String::new(),
+            });
+        }
+    }
+
+    pub fn note_prompt_push(&mut self, c: char) {
+        if let Some(prompt) = self.note_prompt.as_mut() {
+            prompt.text.push(c);
+        }
+    }
+
+    pub fn note_prompt_pop(&mut self) {
+        if let Some(prompt) = self.note_prompt.as_mut() {
+            prompt.text.pop();
+        }
+    }
+
+    pub fn cancel_note_prompt(&mut self) {
+        self.note_prompt = None;
+    }
+
+    /// Add the typed note. An empty note is dropped.
+    pub async fn submit_note(&mut self, client: &DaemonClient) {
+        let Some(prompt) = self.note_prompt.take() else {
+            return;
+        };
+        if prompt.text.trim().is_empty() {
+            return;
+        }
+        match client.add_task_note(&prompt.task_id, &prompt.text).await {
+            Ok(_) => {
+                self.error = None;
+                if matches!(&self.detail, Some(DetailView::TaskLog { task_id }) if *task_id == prompt.task_id)
+                {
+                    self.set_log_content(read_task_log(client, &prompt.task_id).await);
+                    self.scroll_log_bottom();
+                }
+            }
+            Err(e) => self.error = Some(format!("failed to add note: {e}")),
+        }
+    }
+
+    /// Fetch the status history and snapshots of the environment open in the
+    /// detail view.
+    pub async fn refresh_environment_history(&mut self, client: &DaemonClient) {
+        let Some(DetailView::EnvironmentLog { env_id, .. }) = self.detail.as_ref() else {
+            self.environment_history.clear();
+            self.environment_snapshots.clear();
+            return;
+        };
+        if let Ok(history) = client.environment_status_history(env_id).await {
+            self.environment_history = history;
+        }
+        if let Ok(snapshots) = client.list_environment_snapshots(env_id).await {
+            self.environment_snapshots = snapshots;
+        }
+    }
+
     pub fn exit_detail(&mut self) {
         self.detail = None;
-        self.log_content.clear();
+        self.environment_history.clear();
+        self.environment_snapshots.clear();
+        self.set_log_content(String::new());
+        self.log_folds.clear();
         self.log_scroll = 0;
     }
 
+    /// Replace the log shown, parsing it only if it changed, since the
+    /// view refreshes it several times a second.
+    fn set_log_content(&mut self, content: String) {
+        if content == self.log_content {
+            return;
+        }
+        self.log_sections = work::log_sections::index(&content);
+        self.log_content = content;
+        self.parse_log_lines();
+    }
+
+    fn parse_log_lines(&mut self) {
+        // Runner output may be colored; render it unless asked for plain text.
+        self.log_lines = if self.log_plain {
+            Text::raw(work::ansi::strip(&self.log_content)).lines
+        } else {
+            super::ansi::to_text(&self.log_content).lines
+        };
+    }
+
+    /// Whether `section` shows as one line instead of its output.
+    pub fn is_log_section_folded(&self, section: &LogSection) -> bool {
+        self.log_folds
+            .get(&section.start)
+            .copied()
+            .unwrap_or(section.end.is_some())
+    }
+
+    /// The rows the log view shows: lines of the log, with each section's
+    /// `::group::` line as its heading and its `::endgroup::` line left out.
+    pub fn log_rows(&self) -> Vec<LogRow> {
+        let total = self.log_content.lines().count();
+        let mut rows = Vec::with_capacity(total);
+        let mut line = 0;
+        for (i, section) in self.log_sections.iter().enumerate() {
+            rows.extend((line..section.start).map(LogRow::Line));
+            rows.push(LogRow::Section(i));
+            let body_end = section.end.unwrap_or(total);
+            if !self.is_log_section_folded(section) {
+                rows.extend((section.start + 1..body_end).map(LogRow::Line));
+            }
+            // A section cut short by the next one's `::group::` line has
+            // no `::endgroup::` line to skip.
+            let cut_short = self
+                .log_sections
+                .get(i + 1)
+                .is_some_and(|next| Some(next.start) == section.end);
+            line = if cut_short { body_end } else { body_end + 1 };
+        }
+        rows.extend((line..total).map(LogRow::Line));
+        rows
+    }
+
+    /// The section at the bottom row of the view, or holding it, and that
+    /// section's heading row.
+    fn current_log_section(&self) -> Option<(usize, usize)> {
+        let rows = self.log_rows();
+        let line = match *rows.get(self.log_scroll)? {
+            LogRow::Section(i) => return Some((i, self.log_scroll)),
+            LogRow::Line(line) => line,
+        };
+        let (i, row) = rows[..self.log_scroll]
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(row, r)| match *r {
+                LogRow::Section(i) => Some((i, row)),
+                LogRow::Line(_) => None,
+            })?;
+        let end = self.log_sections[i].end;
+        end.is_none_or(|end| line < end).then_some((i, row))
+    }
+
+    /// Fold or unfold the section at the bottom of the view.
+    pub fn fold_log_section(&mut self, fold: bool) {
+        let Some((i, row)) = self.current_log_section() else {
+            return;
+        };
+        self.log_folds.insert(self.log_sections[i].start, fold);
+        self.log_scroll = row;
+    }
+
+    /// Fold or unfold every section.
+    pub fn fold_all_log_sections(&mut self, fold: bool) {
+        let was_at_bottom = self.log_scroll + 1 >= self.log_rows().len();
+        for section in &self.log_sections {
+            self.log_folds.insert(section.start, fold);
+        }
+        let row_count = self.log_rows().len();
+        self.log_scroll = if was_at_bottom {
+            row_count.saturating_sub(1)
+        } else {
+            self.log_scroll.min(row_count.saturating_sub(1))
+        };
+    }
+
+    pub fn toggle_log_plain(&mut self) {
+        self.log_plain = !self.log_plain;
+        self.parse_log_lines();
+    }
+
     pub fn scroll_log_down(&mut self, amount: usize) {
-        let line_count = self.log_content.lines().count();
+        let line_count = self.log_rows().len();
         self.log_scroll = self
             .log_scroll
             .saturating_add(amount)
@@ -495,21 +1210,23 @@ impl App {
     }
 
     pub fn scroll_log_bottom(&mut self) {
-        let line_count = self.log_content.lines().count();
+        let line_count = self.log_rows().len();
         self.log_scroll = line_count.saturating_sub(1);
     }
 
-    pub fn refresh_detail_logs(&mut self) {
-        let old_line_count = self.log_content.lines().count();
+    pub async fn refresh_detail_logs(&mut self, client: &DaemonClient) {
+        let old_line_count = self.log_rows().len();
         let was_at_bottom = self.log_scroll >= old_line_count.saturating_sub(1);
 
         let new_content = match self.detail.as_ref() {
-            Some(DetailView::TaskLog { task_id }) => read_task_log(task_id),
-            Some(DetailView::EnvironmentLog { env_id }) => read_environment_log(env_id),
+            Some(DetailView::TaskLog { task_id }) => read_task_log(client, task_id).await,
+            Some(DetailView::EnvironmentLog { env_id, .. }) => {
+                read_environment_log(client, env_id).await
+            }
             None => return,
         };
-        self.log_content = new_content;
-        let new_line_count = self.log_content.lines().count();
+        self.set_log_content(new_content);
+        let new_line_count = self.log_rows().len();
         if was_at_bottom {
             self.log_scroll = new_line_count.saturating_sub(1);
         } else {
@@ -551,6 +1268,44 @@ impl App {
         self.tui_log_scroll = line_count.saturating_sub(1);
     }
 
+    pub fn detail_environment(&self) -> Option<&Environment> {
+        match self.detail.as_ref() {
+            Some(DetailView::EnvironmentLog { env_id, .. }) => self.find_environment(env_id),
+            _ => None,
+        }
+    }
+
+    pub async fn update_detail_environment(&mut self, client: &DaemonClient) {
+        let Some(env_id) = self.detail_environment().map(|e| e.id.clone()) else {
+            return;
+        };
+        match client.update_environment(&env_id).await {
+            Ok(_) => self.error = None,
+            Err(e) => self.error = Some(format!("update failed: {e}")),
+        }
+        self.poll(client).await;
+    }
+
+    pub async fn claim_detail_environment(&mut self, client: &DaemonClient) {
+        let Some(env_id) = self.detail_environment().map(|e| e.id.clone()) else {
+            return;
+        };
+        match client.claim_environment(&env_id).await {
+            Ok(_) => self.error = None,
+            Err(e) => self.error = Some(format!("claim failed: {e}")),
+        }
+        self.poll(client).await;
+    }
+
+    pub fn prompt_delete_detail_environment(&mut self, skip_provider: bool) {
+        if let Some(env_id) = self.detail_environment().map(|e| e.id.clone()) {
+            self.confirm = Some(Confirm::Environment {
+                env_id,
+                skip_provider,
+            });
+        }
+    }
+
     pub fn prompt_delete(&mut self) {
         self.prompt_delete_with_options(false);
     }
@@ -562,7 +1317,13 @@ impl App {
     fn prompt_delete_with_options(&mut self, skip_provider: bool) {
         match self.tab {
             Tab::Tasks => {
-                if let Some(ti) = self.selected_task_index() {
+                if let Some(group) = self.selected_group() {
+                    self.confirm = Some(Confirm::Group {
+                        group_id: group.id.clone(),
+                        tasks: group.counts.total,
+                        skip_provider,
+                    });
+                } else if let Some(ti) = self.selected_task_index() {
                     self.confirm = Some(Confirm::Task {
                         task_id: self.tasks[ti].id.clone(),
                         skip_provider,
@@ -573,6 +1334,12 @@ impl App {
                 if let Some(project) = self.projects.get(self.selected) {
                     self.confirm = Some(Confirm::Project {
                         project_name: project.name.clone(),
+                        tasks: self
+                            .task_counts
+                            .by_project
+                            .get(&project.id)
+                            .copied()
+                            .unwrap_or_default(),
                     });
                 }
             }
@@ -600,20 +1367,40 @@ impl App {
                     Err(e) => self.error = Some(format!("delete failed: {e}")),
                 }
             }
-            Some(Confirm::Project { project_name }) => {
-                let name = project_name.clone();
-                match client.delete_project(&name).await {
+            Some(Confirm::Group {
+                group_id,
+                skip_provider,
+                ..
+            }) => {
+                let group_id = group_id.clone();
+                match client
+                    .remove_task_group(&group_id, true, *skip_provider)
+                    .await
+                {
                     Ok(()) => self.error = None,
                     Err(e) => self.error = Some(format!("delete failed: {e}")),
                 }
             }
+            Some(Confirm::Project { project_name, .. }) => {
+                let name = project_name.clone();
+                // The dialog already warned that the project's tasks go too.
+                match client.delete_project(&name, true).await {
+                    Ok(_) => self.error = None,
+                    Err(e) => self.error = Some(format!("delete failed: {e}")),
+                }
+            }
             Some(Confirm::Environment {
                 env_id,
                 skip_provider,
             }) => {
                 let env_id = env_id.clone();
                 match client.remove_environment(&env_id, *skip_provider).await {
-                    Ok(()) => self.error = None,
+                    Ok(()) => {
+                        self.error = None;
+                        if self.detail_environment().is_some_and(|e| e.id == env_id) {
+                            self.exit_detail();
+                        }
+                    }
                     Err(e) => self.error = Some(format!("delete failed: {e}")),
                 }
             }
@@ -628,6 +1415,13 @@ impl App {
         self.error = Some("daemon disconnected".to_string());
     }
 
+    pub fn set_connected(&mut self) {
+        if !self.daemon_connected {
+            self.daemon_connected = true;
+            self.error = None;
+        }
+    }
+
     pub fn cancel_confirm(&mut self) {
         self.confirm = None;
     }
@@ -649,50 +1443,193 @@ fn state_file_path() -> Option<std::path::PathBuf> {
     paths::state_dir().ok().map(|d| d.join("tui.json"))
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(Default, serde::Serialize, serde::Deserialize)]
 struct TuiState {
+    #[serde(default)]
     task_view_mode: TaskViewMode,
+    #[serde(default)]
+    task_sort: TaskSort,
+    /// As typed after `f`.
+    #[serde(default)]
+    task_filter: String,
+}
+
+/// The keymap from `[tui.keys]`, and what's wrong with it. A config that
+/// can't be loaded leaves the defaults; the Config tab reports why.
+fn load_keymap() -> (Keymap, Vec<String>) {
+    let config = work::config::load().ok();
+    Keymap::load(config.as_ref().and_then(|config| config.tui.as_ref()))
 }
 
-fn load_task_view_mode() -> TaskViewMode {
+fn load_state() -> TuiState {
     state_file_path()
         .and_then(|p| std::fs::read_to_string(p).ok())
         .and_then(|s| serde_json::from_str::<TuiState>(&s).ok())
-        .map(|s| s.task_view_mode)
-        .unwrap_or(TaskViewMode::Flat)
+        .unwrap_or_default()
 }
 
-fn save_task_view_mode(mode: TaskViewMode) {
+fn save_state(state: &TuiState) {
     if let Some(path) = state_file_path() {
         if let Some(parent) = path.parent() {
             let _ = std::fs::create_dir_all(parent);
         }
-        let state = TuiState {
-            task_view_mode: mode,
-        };
-        if let Ok(json) = serde_json::to_string(&state) {
+        if let Ok(json) = serde_json::to_string(state) {
             let _ = std::fs::write(path, json);
         }
     }
 }
 
-fn read_task_log(task_id: &str) -> String {
-    paths::task_log_path(task_id)
-        .ok()
-        .and_then(|p| std::fs::read_to_string(p).ok())
+/// Logs come from the daemon, which may be on another host.
+async fn read_task_log(client: &DaemonClient, task_id: &str) -> String {
+    client
+        .task_log(task_id)
+        .await
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
         .unwrap_or_default()
 }
 
-fn read_environment_log(env_id: &str) -> String {
-    paths::environment_log_path(env_id)
-        .ok()
-        .and_then(|p| std::fs::read_to_string(p).ok())
+async fn read_environment_log(client: &DaemonClient, env_id: &str) -> String {
+    client
+        .environment_log(env_id)
+        .await
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
         .unwrap_or_default()
 }
 
+/// Have the daemon measure a worktree in the background; large checkouts
+/// can take a while and must not stall the UI.
+fn spawn_disk_usage(client: &DaemonClient, env_id: &str) -> Arc<Mutex<Option<String>>> {
+    let result = Arc::new(Mutex::new(None));
+    let slot = result.clone();
+    let client = client.clone();
+    let env_id = env_id.to_string();
+    tokio::spawn(async move {
+        let usage = match client.environment_disk_usage(&env_id).await {
+            Ok(Some(kb)) => format_kb(kb),
+            _ => "-".to_string(),
+        };
+        *slot.lock().expect("disk usage lock poisoned") = Some(usage);
+    });
+    result
+}
+
+pub fn format_kb(kb: u64) -> String {
+    let kb = kb as f64;
+    if kb >= 1024.0 * 1024.0 {
+        format!("{:.1} GB", kb / (1024.0 * 1024.0))
+    } else if kb >= 1024.0 {
+        format!("{:.1} MB", kb / 1024.0)
+    } else {
+        format!("{kb:.0} KB")
+    }
+}
+
 fn read_tui_log() -> String {
     paths::tui_log_path()
         .ok()
         .and_then(|p| std::fs::read_to_string(p).ok())
         .unwrap_or_default()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(id: &str, status: &str, wall_ms: Option<u64>) -> Task {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "environment_id": "e",
+            "project_id": "p",
+            "provider": "claude",
+            "description": id,
+            "status": status,
+            "resource_usage": wall_ms.map(|wall_ms| serde_json::json!({
+                "wall_ms": wall_ms,
+                "user_cpu_ms": 0,
+                "system_cpu_ms": 0,
+                "peak_rss_kb": 0,
+            })),
+            "status_changed_at": "2026-01-02T03:00:00Z",
+            "created_at": "2026-01-02T03:00:00Z",
+            "updated_at": "2026-01-02T03:00:00Z",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn filters_name_statuses_and_projects() {
+        assert_eq!(TaskFilter::parse("  "), None);
+        let filter = TaskFilter::parse("failed  api status:started project:failed").unwrap();
+        assert_eq!(filter.text, "failed api status:started project:failed");
+        let mut page = TaskPage::default();
+        filter.narrow(&mut page);
+        assert_eq!(page.status.as_deref(), Some("failed,started"));
+        assert_eq!(page.project.as_deref(), Some("api,failed"));
+
+        let any_project = TaskFilter::parse("pending").unwrap();
+        let mut page = TaskPage::default();
+        any_project.narrow(&mut page);
+        assert_eq!(page.status.as_deref(), Some("pending"));
+        assert_eq!(page.project, None);
+    }
+
+    #[test]
+    fn filters_count_tasks_not_loaded_yet() {
+        let projects: Vec<Project> = serde_json::from_value(serde_json::json!([
+            { "id": "p1", "name": "api", "path": "/api", "created_at": "", "updated_at": "", "aliases": ["backend"] },
+            { "id": "p2", "name": "web", "path": "/web", "created_at": "", "updated_at": "" },
+        ]))
+        .unwrap();
+        let counts: TaskCounts = serde_json::from_value(serde_json::json!({
+            "total": 60,
+            "by_project": { "p1": 35, "p2": 25 },
+            "by_status": {
+                "p1": { "failed": 5, "complete": 30 },
+                "p2": { "failed": 2, "pending": 23 },
+            },
+        }))
+        .unwrap();
+
+        let count = |text: &str| TaskFilter::parse(text).unwrap().count(&counts, &projects);
+        assert_eq!(count("failed"), 7);
+        assert_eq!(count("failed backend"), 5);
+        assert_eq!(count("web"), 25);
+        assert_eq!(count("pending complete"), 53);
+    }
+
+    #[test]
+    fn running_tasks_move_up_as_they_run_longer() {
+        let tasks = [
+            task("done", "complete", Some(1_000)),
+            task("running", "started", None),
+            task("broke", "failed", Some(900_000)),
+            task("waiting", "pending", None),
+            task("older-done", "complete", Some(5_000)),
+        ];
+        let sorted = |now: &str| {
+            let now = chrono::DateTime::parse_from_rfc3339(now)
+                .unwrap()
+                .with_timezone(&chrono::Utc);
+            let mut rows: Vec<usize> = (0..tasks.len()).collect();
+            sort_by_run(&tasks, &mut rows, now);
+            rows.iter()
+                .map(|&ti| tasks[ti].id.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        // The started task has run for two seconds, then ten minutes, then
+        // an hour.
+        assert_eq!(
+            sorted("2026-01-02T03:00:02Z"),
+            ["broke", "older-done", "running", "done", "waiting"]
+        );
+        assert_eq!(
+            sorted("2026-01-02T03:10:00Z"),
+            ["broke", "running", "older-done", "done", "waiting"]
+        );
+        assert_eq!(
+            sorted("2026-01-02T04:00:00Z"),
+            ["running", "broke", "older-done", "done", "waiting"]
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use std::path::Path;
+
+use work::config::{
+    self, BUILT_IN_ENVIRONMENT_PROVIDERS, Config, EnvironmentProviderConfig,
+    EnvironmentProviderEntry, TaskProviderConfig, executable_problem,
+};
+use work::db::Project;
+
+use super::keys::Keymap;
+
+/// Top-level keys the config loader reads. Anything else is silently ignored,
+/// which usually means a typo.
+const KNOWN_KEYS: &[&str] = &[
+    "daemon",
+    "environment-provider",
+    "default-environment-provider",
+    "task-provider",
+    "default-task-provider",
+    "default-format",
+    "formats",
+    "projects",
+    "tasks",
+    "environments",
+    "editor",
+    "notifications",
+    "tui",
+    "hosts",
+    "auto-start-daemon",
+    "id-format",
+    "redact",
+];
+
+/// A configured provider and what is wrong with it, if anything.
+pub struct ProviderCheck {
+    pub name: String,
+    pub detail: String,
+    pub problem: Option<String>,
+}
+
+/// The providers a project falls back to, and problems resolving them.
+pub struct ProjectDefaults {
+    pub name: String,
+    pub task_provider: Option<String>,
+    pub environment_provider: Option<String>,
+    pub problems: Vec<String>,
+}
+
+/// Snapshot of the config file as the daemon and CLI would load it.
+pub struct ConfigReport {
+    pub path: String,
+    /// Set when the config file cannot be read or parsed at all.
+    pub error: Option<String>,
+    pub task_providers: Vec<ProviderCheck>,
+    pub environment_providers: Vec<ProviderCheck>,
+    pub projects: Vec<ProjectDefaults>,
+    pub warnings: Vec<String>,
+}
+
+impl ConfigReport {
+    /// Number of problems worth flagging in the tab title.
+    pub fn problem_count(&self) -> usize {
+        usize::from(self.error.is_some())
+            + self
+                .task_providers
+                .iter()
+                .chain(&self.environment_providers)
+                .filter(|p| p.problem.is_some())
+                .count()
+            + self
+                .projects
+                .iter()
+                .map(|p| p.problems.len())
+                .sum::<usize>()
+            + self.warnings.len()
+    }
+}
+
+pub fn build(projects: &[Project]) -> ConfigReport {
+    let mut report = ConfigReport {
+        path: "-".to_string(),
+        error: None,
+        task_providers: Vec::new(),
+        environment_providers: Vec::new(),
+        projects: Vec::new(),
+        warnings: Vec::new(),
+    };
+
+    let path = match config::path() {
+        Ok(path) => path,
+        Err(e) => {
+            report.error = Some(e.to_string());
+            return report;
+        }
+    };
+    report.path = path.display().to_string();
+
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            report
+                .warnings
+                .push("no config file; using built-in defaults".to_string());
+            String::new()
+        }
+        Err(e) => {
+            report.error = Some(format!("cannot read config: {e}"));
+            return report;
+        }
+    };
+
+    let table: toml::Table = match toml::from_str(&contents) {
+        Ok(table) => table,
+        Err(e) => {
+            report.error = Some(format!("invalid TOML: {e}"));
+            return report;
+        }
+    };
+    for key in table.keys().filter(|k| !KNOWN_KEYS.contains(&k.as_str())) {
+        report
+            .warnings
+            .push(format!("unknown key {key:?} is ignored"));
+    }
+
+    let config: Config = match toml::from_str(&contents) {
+        Ok(config) => config,
+        Err(e) => {
+            report.error = Some(format!("invalid config: {e}"));
+            return report;
+        }
+    };
+
+    report.task_providers = task_provider_checks(&config);
+    report.environment_providers = environment_provider_checks(&config);
+    report.warnings.extend(setting_warnings(&config));
+    report.projects = projects
+        .iter()
+        .map(|project| project_defaults(&config, project))
+        .collect();
+    report
+}
+
+fn task_provider_checks(config: &Config) -> Vec<ProviderCheck> {
+    let mut checks: Vec<ProviderCheck> = config
+        .tasks
+        .iter()
+        .flat_map(|tasks| &tasks.providers)
+        .map(|(name, provider)| match provider {
+            TaskProviderConfig::Command { command, .. } => ProviderCheck {
+                name: name.clone(),
+                detail: format!("command {command}"),
+                problem: command_problem(command),
+            },
+            TaskProviderConfig::Mock { .. } => ProviderCheck {
+                name: name.clone(),
+                detail: "mock".to_string(),
+                problem: None,
+            },
+        })
+        .collect();
+    checks.sort_by(|a, b| a.name.cmp(&b.name));
+    checks
+}
+
+fn environment_provider_checks(config: &Config) -> Vec<ProviderCheck> {
+    let entries = config.environments.as_ref().map(|e| &e.providers);
+
+    let mut checks: Vec<ProviderCheck> = BUILT_IN_ENVIRONMENT_PROVIDERS
+        .iter()
+        .map(|name| ProviderCheck {
+            name: name.to_string(),
+            detail: if entries.is_some_and(|e| e.contains_key(*name)) {
+                "built-in (options set)".to_string()
+            } else {
+                "built-in".to_string()
+            },
+            problem: None,
+        })
+        .collect();
+
+    let mut custom: Vec<ProviderCheck> = entries
+        .into_iter()
+        .flatten()
+        .filter(|(name, _)| !BUILT_IN_ENVIRONMENT_PROVIDERS.contains(&name.as_str()))
+        .map(|(name, entry)| match entry {
+            EnvironmentProviderEntry::Custom(EnvironmentProviderConfig::Script {
+                path, ..
+            }) => ProviderCheck {
+                name: name.clone(),
+                detail: format!("script {path}"),
+                problem: executable_problem(Path::new(path)),
+            },
+            EnvironmentProviderEntry::Custom(EnvironmentProviderConfig::Mock { .. }) => {
+                ProviderCheck {
+                    name: name.clone(),
+                    detail: "mock".to_string(),
+                    problem: None,
+                }
+            }
+            EnvironmentProviderEntry::Custom(EnvironmentProviderConfig::Ssh { remote, .. }) => {
+                ProviderCheck {
+                    name: name.clone(),
+                    detail: format!("ssh {}", remote.host.as_deref().unwrap_or("-")),
+                    problem: remote
+                        .host
+                        .is_none()
+                        .then(|| "ssh providers need a host".to_string()),
+                }
+            }
+            EnvironmentProviderEntry::BuiltIn(_) => ProviderCheck {
+                name: name.clone(),
+                detail: "options only".to_string(),
+                problem: Some(
+                    "missing `type`; only built-in providers take option-only tables".to_string(),
+                ),
+            },
+        })
+        .collect();
+    custom.sort_by(|a, b| a.name.cmp(&b.name));
+    checks.extend(custom);
+    checks
+}
+
+/// Settings that only fail when a job or command first uses them.
+fn setting_warnings(config: &Config) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    let mut rate_limited: Vec<&String> = config
+        .daemon
+        .iter()
+        .flat_map(|d| d.rate_limits.keys())
+        .collect();
+    rate_limited.sort();
+    for provider in rate_limited {
+        if let Err(e) = config.rate_limit(provider) {
+            warnings.push(e.to_string());
+        }
+    }
+
+    if let Err(e) = config.editor() {
+        warnings.push(e.to_string());
+    }
+    warnings.extend(Keymap::load(config.tui.as_ref()).1);
+    warnings
+}
+
+fn project_defaults(global: &Config, project: &Project) -> ProjectDefaults {
+    let mut problems = Vec::new();
+    let loaded = match config::load_for_project(&project.name, Path::new(&project.path)) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            problems.push(format!("{}: {e}", config::PROJECT_CONFIG_FILE));
+            None
+        }
+    };
+    let config = loaded.as_ref().unwrap_or(global);
+
+    let task_provider = config.default_task_provider_for_project(&project.name);
+    match task_provider.as_deref() {
+        None => problems.push("no task provider set".to_string()),
+        Some(name) if config.get_task_provider(name).is_err() => {
+            problems.push(format!("task provider {name} is not configured"));
+        }
+        Some(_) => {}
+    }
+
+    let environment_provider = config.default_environment_provider_for_project(&project.name);
+    match environment_provider.as_deref() {
+        None => problems.push("no environment provider set".to_string()),
+        Some(name)
+            if !BUILT_IN_ENVIRONMENT_PROVIDERS.contains(&name)
+                && config.get_environment_provider(name).is_err() =>
+        {
+            problems.push(format!("environment provider {name} is not configured"));
+        }
+        Some(_) => {}
+    }
+
+    if let Err(e) = config.task_retention(&project.name) {
+        problems.push(e.to_string());
+    }
+
+    ProjectDefaults {
+        name: project.name.clone(),
+        task_provider,
+        environment_provider,
+        problems,
+    }
+}
+
+/// Why `command` cannot be run, resolving bare names against `PATH`.
+fn command_problem(command: &str) -> Option<String> {
+    if command.contains('/') {
+        return executable_problem(Path::new(command));
+    }
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let found =
+        std::env::split_paths(&path).any(|dir| executable_problem(&dir.join(command)).is_none());
+    (!found).then(|| format!("{command} not found on PATH"))
+}
@@ -0,0 +1,675 @@
+//! Key bindings. Every action has default keys, and an entry under
+//! `[tui.keys]` replaces them for that action, e.g. `delete = "x"` or
+//! `scroll_down = ["n", "down"]`. The confirm dialog and the text prompts
+//! (search, filter, note) read keys literally.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use work::config::TuiConfig;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Action {
+    Quit,
+    Help,
+    NextTab,
+    PrevTab,
+    TasksTab,
+    ProjectsTab,
+    EnvironmentsTab,
+    DaemonTab,
+    LogsTab,
+    ConfigTab,
+    ScrollDown,
+    ScrollUp,
+    PageDown,
+    PageUp,
+    Top,
+    Bottom,
+    Open,
+    Back,
+    Collapse,
+    Expand,
+    CollapseAll,
+    ExpandAll,
+    Delete,
+    ForceDelete,
+    NewTask,
+    Note,
+    ToggleView,
+    Sort,
+    Filter,
+    Search,
+    ClearSearch,
+    TogglePlain,
+    ReloadConfig,
+    UpdateEnvironment,
+    ClaimEnvironment,
+    RemoveEnvironment,
+    ForceRemoveEnvironment,
+}
+
+impl Action {
+    pub const ALL: &[Action] = &[
+        Action::Quit,
+        Action::Help,
+        Action::NextTab,
+        Action::PrevTab,
+        Action::TasksTab,
+        Action::ProjectsTab,
+        Action::EnvironmentsTab,
+        Action::DaemonTab,
+        Action::LogsTab,
+        Action::ConfigTab,
+        Action::ScrollDown,
+        Action::ScrollUp,
+        Action::PageDown,
+        Action::PageUp,
+        Action::Top,
+        Action::Bottom,
+        Action::Open,
+        Action::Back,
+        Action::Collapse,
+        Action::Expand,
+        Action::CollapseAll,
+        Action::ExpandAll,
+        Action::Delete,
+        Action::ForceDelete,
+        Action::NewTask,
+        Action::Note,
+        Action::ToggleView,
+        Action::Sort,
+        Action::Filter,
+        Action::Search,
+        Action::ClearSearch,
+        Action::TogglePlain,
+        Action::ReloadConfig,
+        Action::UpdateEnvironment,
+        Action::ClaimEnvironment,
+        Action::RemoveEnvironment,
+        Action::ForceRemoveEnvironment,
+    ];
+
+    /// The name `[tui.keys]` knows it by.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Help => "help",
+            Action::NextTab => "next_tab",
+            Action::PrevTab => "prev_tab",
+            Action::TasksTab => "tasks_tab",
+            Action::ProjectsTab => "projects_tab",
+            Action::EnvironmentsTab => "environments_tab",
+            Action::DaemonTab => "daemon_tab",
+            Action::LogsTab => "logs_tab",
+            Action::ConfigTab => "config_tab",
+            Action::ScrollDown => "scroll_down",
+            Action::ScrollUp => "scroll_up",
+            Action::PageDown => "page_down",
+            Action::PageUp => "page_up",
+            Action::Top => "top",
+            Action::Bottom => "bottom",
+            Action::Open => "open",
+            Action::Back => "back",
+            Action::Collapse => "collapse",
+            Action::Expand => "expand",
+            Action::CollapseAll => "collapse_all",
+            Action::ExpandAll => "expand_all",
+            Action::Delete => "delete",
+            Action::ForceDelete => "force_delete",
+            Action::NewTask => "new_task",
+            Action::Note => "note",
+            Action::ToggleView => "toggle_view",
+            Action::Sort => "sort",
+            Action::Filter => "filter",
+            Action::Search => "search",
+            Action::ClearSearch => "clear_search",
+            Action::TogglePlain => "toggle_plain",
+            Action::ReloadConfig => "reload_config",
+            Action::UpdateEnvironment => "update_environment",
+            Action::ClaimEnvironment => "claim_environment",
+            Action::RemoveEnvironment => "remove_environment",
+            Action::ForceRemoveEnvironment => "force_remove_environment",
+        }
+    }
+
+    /// What it does, for the help overlay.
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Help => "show these keys",
+            Action::NextTab => "next tab",
+            Action::PrevTab => "previous tab",
+            Action::TasksTab => "Tasks tab",
+            Action::ProjectsTab => "Projects tab",
+            Action::EnvironmentsTab => "Environments tab",
+            Action::DaemonTab => "Daemon tab",
+            Action::LogsTab => "Logs tab",
+            Action::ConfigTab => "Config tab",
+            Action::ScrollDown => "down",
+            Action::ScrollUp => "up",
+            Action::PageDown => "half a page down",
+            Action::PageUp => "half a page up",
+            Action::Top => "top",
+            Action::Bottom => "bottom",
+            Action::Open => "open",
+            Action::Back => "back",
+            Action::Collapse => "collapse",
+            Action::Expand => "expand",
+            Action::CollapseAll => "collapse all",
+            Action::ExpandAll => "expand all",
+            Action::Delete => "delete",
+            Action::ForceDelete => "force delete",
+            Action::NewTask => "new task",
+            Action::Note => "add a note",
+            Action::ToggleView => "flat/tree view",
+            Action::Sort => "sort by created/status/duration",
+            Action::Filter => "filter by status or project",
+            Action::Search => "search",
+            Action::ClearSearch => "clear search",
+            Action::TogglePlain => "plain/color",
+            Action::ReloadConfig => "reload",
+            Action::UpdateEnvironment => "update environment",
+            Action::ClaimEnvironment => "claim environment",
+            Action::RemoveEnvironment => "remove environment",
+            Action::ForceRemoveEnvironment => "force remove environment",
+        }
+    }
+
+    fn default_keys(self) -> &'static [&'static str] {
+        match self {
+            Action::Quit => &["q", "ctrl-c"],
+            Action::Help => &["?"],
+            Action::NextTab => &["tab"],
+            Action::PrevTab => &["backtab"],
+            Action::TasksTab => &["1"],
+            Action::ProjectsTab => &["2"],
+            Action::EnvironmentsTab => &["3"],
+            Action::DaemonTab => &["4"],
+            Action::LogsTab => &["5"],
+            Action::ConfigTab => &["6"],
+            Action::ScrollDown => &["j", "down"],
+            Action::ScrollUp => &["k", "up"],
+            Action::PageDown => &["d"],
+            Action::PageUp => &["u"],
+            Action::Top => &["g"],
+            Action::Bottom => &["G"],
+            Action::Open => &["enter"],
+            Action::Back => &["q", "esc"],
+            Action::Collapse => &["h", "left"],
+            Action::Expand => &["l", "right"],
+            Action::CollapseAll => &["H"],
+            Action::ExpandAll => &["L"],
+            Action::Delete => &["d"],
+            Action::ForceDelete => &["D"],
+            Action::NewTask => &["n"],
+            Action::Note => &["N"],
+            Action::ToggleView => &["`"],
+            Action::Sort => &["s"],
+            Action::Filter => &["f"],
+            Action::Search => &["/"],
+            Action::ClearSearch => &["esc"],
+            Action::TogglePlain => &["p"],
+            Action::ReloadConfig => &["r"],
+            Action::UpdateEnvironment => &["U"],
+            Action::ClaimEnvironment => &["c"],
+            Action::RemoveEnvironment => &["x"],
+            Action::ForceRemoveEnvironment => &["X"],
+        }
+    }
+}
+
+/// Where keys are read. Each context has its own actions, so a key can do
+/// different things in different places but only one thing in each.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Context {
+    Tasks,
+    Projects,
+    Environments,
+    Daemon,
+    Logs,
+    Config,
+    /// A task's or environment's log.
+    Detail,
+    /// Choosing a project for a new task.
+    ProjectPrompt,
+}
+
+/// Actions on every tab, checked before the tab's own.
+const GLOBAL: &[Action] = &[
+    Action::Quit,
+    Action::Help,
+    Action::NextTab,
+    Action::PrevTab,
+    Action::TasksTab,
+    Action::ProjectsTab,
+    Action::EnvironmentsTab,
+    Action::DaemonTab,
+    Action::LogsTab,
+    Action::ConfigTab,
+];
+
+impl Context {
+    pub const ALL: &[Context] = &[
+        Context::Tasks,
+        Context::Projects,
+        Context::Environments,
+        Context::Daemon,
+        Context::Logs,
+        Context::Config,
+        Context::Detail,
+        Context::ProjectPrompt,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Context::Tasks => "the Tasks tab",
+            Context::Projects => "the Projects tab",
+            Context::Environments => "the Environments tab",
+            Context::Daemon => "the Daemon tab",
+            Context::Logs => "the Logs tab",
+            Context::Config => "the Config tab",
+            Context::Detail => "the log view",
+            Context::ProjectPrompt => "the project prompt",
+        }
+    }
+
+    fn own_actions(self) -> &'static [Action] {
+        match self {
+            Context::Tasks => &[
+                Action::ScrollDown,
+                Action::ScrollUp,
+                Action::Collapse,
+                Action::Expand,
+                Action::CollapseAll,
+                Action::ExpandAll,
+                Action::Open,
+                Action::Delete,
+                Action::ForceDelete,
+                Action::NewTask,
+                Action::Note,
+                Action::ToggleView,
+                Action::Sort,
+                Action::Filter,
+                Action::Search,
+                Action::ClearSearch,
+            ],
+            Context::Projects => &[
+                Action::ScrollDown,
+                Action::ScrollUp,
+                Action::Delete,
+                Action::ForceDelete,
+            ],
+            Context::Environments => &[
+                Action::ScrollDown,
+                Action::ScrollUp,
+                Action::Open,
+                Action::Delete,
+                Action::ForceDelete,
+            ],
+            Context::Daemon => &[],
+            Context::Logs => &[
+                Action::ScrollDown,
+                Action::ScrollUp,
+                Action::Top,
+                Action::Bottom,
+                Action::PageDown,
+                Action::PageUp,
+            ],
+            Context::Config => &[
+                Action::ScrollDown,
+                Action::ScrollUp,
+                Action::PageDown,
+                Action::PageUp,
+                Action::ReloadConfig,
+            ],
+            Context::Detail => &[
+                Action::Back,
+                Action::Help,
+                Action::ScrollDown,
+                Action::ScrollUp,
+                Action::Top,
+                Action::Bottom,
+                Action::PageDown,
+                Action::PageUp,
+                Action::Collapse,
+                Action::Expand,
+                Action::CollapseAll,
+                Action::ExpandAll,
+                Action::TogglePlain,
+                Action::Note,
+                Action::UpdateEnvironment,
+                Action::ClaimEnvironment,
+                Action::RemoveEnvironment,
+                Action::ForceRemoveEnvironment,
+            ],
+            Context::ProjectPrompt => &[
+                Action::ScrollDown,
+                Action::ScrollUp,
+                Action::Open,
+                Action::Back,
+            ],
+        }
+    }
+
+    /// The context's actions, in the order keys are matched against them.
+    pub fn actions(self) -> Vec<Action> {
+        match self {
+            Context::Detail | Context::ProjectPrompt => self.own_actions().to_vec(),
+            _ => GLOBAL.iter().chain(self.own_actions()).copied().collect(),
+        }
+    }
+}
+
+/// A key, with Ctrl or Alt held or not. Shift is part of the character, so
+/// `G` is Shift-g.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Key {
+    ctrl: bool,
+    alt: bool,
+    code: KeyCodeOrd,
+}
+
+/// `KeyCode` isn't `Ord`; this is the subset of it that bindings can name.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum KeyCodeOrd {
+    Char(char),
+    F(u8),
+    Named(&'static str),
+}
+
+/// Keys with names, as written in config and shown in hints.
+const NAMED_KEYS: &[(&str, KeyCode)] = &[
+    ("enter", KeyCode::Enter),
+    ("esc", KeyCode::Esc),
+    ("tab", KeyCode::Tab),
+    ("backtab", KeyCode::BackTab),
+    ("backspace", KeyCode::Backspace),
+    ("delete", KeyCode::Delete),
+    ("insert", KeyCode::Insert),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+];
+
+impl Key {
+    /// Parse a key such as `j`, `G`, `enter`, `space`, `f5`, or `ctrl-d`.
+    pub fn parse(s: &str) -> Result<Key, String> {
+        let (mut ctrl, mut alt) = (false, false);
+        let mut rest = s;
+        loop {
+            let lower = rest.to_ascii_lowercase();
+            if lower.len() > 5 && lower.starts_with("ctrl-") {
+                ctrl = true;
+                rest = &rest[5..];
+            } else if lower.len() > 4 && lower.starts_with("alt-") {
+                alt = true;
+                rest = &rest[4..];
+            } else {
+                break;
+            }
+        }
+
+        let mut chars = rest.chars();
+        let code = if let (Some(c), None) = (chars.next(), chars.next()) {
+            KeyCodeOrd::Char(c)
+        } else {
+            let lower = rest.to_ascii_lowercase();
+            match NAMED_KEYS.iter().find(|(name, _)| *name == lower) {
+                Some((name, _)) => KeyCodeOrd::Named(name),
+                None if lower == "space" => KeyCodeOrd::Char(' '),
+                None if lower == "shift-tab" => KeyCodeOrd::Named("backtab"),
+                None => match lower.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                    Some(n @ 1..=12) => KeyCodeOrd::F(n),
+                    _ => return Err(format!("unknown key {s:?}")),
+                },
+            }
+        };
+        Ok(Key { ctrl, alt, code })
+    }
+
+    fn matches(&self, event: &KeyEvent) -> bool {
+        if self.ctrl != event.modifiers.contains(KeyModifiers::CONTROL)
+            || self.alt != event.modifiers.contains(KeyModifiers::ALT)
+        {
+            return false;
+        }
+        match (self.code, event.code) {
+            (KeyCodeOrd::Char(c), KeyCode::Char(pressed)) => c == pressed,
+            (KeyCodeOrd::F(n), KeyCode::F(pressed)) => n == pressed,
+            (KeyCodeOrd::Named(name), code) => NAMED_KEYS
+                .iter()
+                .any(|(named, named_code)| *named == name && *named_code == code),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ctrl {
+            f.write_str("ctrl-")?;
+        }
+        if self.alt {
+            f.write_str("alt-")?;
+        }
+        match self.code {
+            KeyCodeOrd::Char(' ') => f.write_str("space"),
+            KeyCodeOrd::Char(c) => write!(f, "{c}"),
+            KeyCodeOrd::F(n) => write!(f, "f{n}"),
+            KeyCodeOrd::Named(name) => f.write_str(name),
+        }
+    }
+}
+
+/// The keys for every action: the defaults with `[tui.keys]` applied.
+pub struct Keymap {
+    keys: BTreeMap<Action, Vec<Key>>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::load(None).0
+    }
+}
+
+impl Keymap {
+    /// Build the keymap from `[tui.keys]`, and list what's wrong with it. An
+    /// entry with a key that can't be parsed is ignored, leaving that
+    /// action's defaults; when a key does two things in one place, the one
+    /// listed first in the help overlay wins.
+    pub fn load(config: Option<&TuiConfig>) -> (Self, Vec<String>) {
+        let mut keys: BTreeMap<Action, Vec<Key>> = Action::ALL
+            .iter()
+            .map(|action| {
+                let defaults = action
+                    .default_keys()
+                    .iter()
+                    .map(|key| Key::parse(key).expect("default keys parse"))
+                    .collect();
+                (*action, defaults)
+            })
+            .collect();
+        let mut problems = Vec::new();
+
+        for (name, binding) in config.iter().flat_map(|tui| &tui.keys) {
+            let Some(action) = Action::ALL.iter().find(|a| a.name() == name) else {
+                problems.push(format!("[tui.keys] unknown action {name:?}"));
+                continue;
+            };
+            match binding.keys().iter().map(|key| Key::parse(key)).collect() {
+                Ok(parsed) => {
+                    keys.insert(*action, parsed);
+                }
+                Err(e) => problems.push(format!("[tui.keys] {name}: {e}")),
+            }
+        }
+
+        let keymap = Keymap { keys };
+        problems.extend(keymap.conflicts());
+        (keymap, problems)
+    }
+
+    /// Keys that do two things in the same place, one line per pair of
+    /// actions, naming every place they clash.
+    fn conflicts(&self) -> Vec<String> {
+        let mut clashes: BTreeMap<(Key, Action, Action), Vec<&str>> = BTreeMap::new();
+        for context in Context::ALL {
+            let actions = context.actions();
+            for (i, first) in actions.iter().enumerate() {
+                for second in &actions[i + 1..] {
+                    for key in &self.keys[first] {
+                        if self.keys[second].contains(key) {
+                            clashes
+                                .entry((*key, *first, *second))
+                                .or_default()
+                                .push(context.label());
+                        }
+                    }
+                }
+            }
+        }
+        clashes
+            .into_iter()
+            .map(|((key, first, second), places)| {
+                format!(
+                    "[tui.keys] \"{key}\" is bound to both {} and {} in {}; {} wins",
+                    first.name(),
+                    second.name(),
+                    places.join(", "),
+                    first.name(),
+                )
+            })
+            .collect()
+    }
+
+    /// The action `event` triggers in `context`, if any.
+    pub fn action(&self, context: Context, event: &KeyEvent) -> Option<Action> {
+        context
+            .actions()
+            .into_iter()
+            .find(|action| self.keys[action].iter().any(|key| key.matches(event)))
+    }
+
+    /// An action's keys for display, e.g. `j, down`, or `none`.
+    pub fn describe(&self, action: Action) -> String {
+        let keys = &self.keys[&action];
+        if keys.is_empty() {
+            return "none".to_string();
+        }
+        keys.iter()
+            .map(Key::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// A status bar hint: the first key of each action, joined with `/`,
+    /// then `label`. Empty when an action has no keys.
+    pub fn hint(&self, actions: &[Action], label: &str) -> Option<String> {
+        let keys: Option<Vec<String>> = actions
+            .iter()
+            .map(|action| self.keys[action].first().map(Key::to_string))
+            .collect();
+        keys.map(|keys| format!("{}: {label}", keys.join("/")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn parses_and_matches_keys() {
+        let ctrl_d = Key::parse("Ctrl-d").unwrap();
+        assert_eq!(ctrl_d.to_string(), "ctrl-d");
+        assert!(ctrl_d.matches(&press(KeyCode::Char('d'), KeyModifiers::CONTROL)));
+        assert!(!ctrl_d.matches(&press(KeyCode::Char('d'), KeyModifiers::NONE)));
+
+        // Terminals report Shift with capitals; the capital is the key.
+        let big_g = Key::parse("G").unwrap();
+        assert!(big_g.matches(&press(KeyCode::Char('G'), KeyModifiers::SHIFT)));
+        assert!(
+            Key::parse("shift-tab")
+                .unwrap()
+                .matches(&press(KeyCode::BackTab, KeyModifiers::SHIFT))
+        );
+        assert!(
+            Key::parse("PageDown")
+                .unwrap()
+                .matches(&press(KeyCode::PageDown, KeyModifiers::NONE))
+        );
+        assert_eq!(Key::parse("space").unwrap().to_string(), "space");
+        assert_eq!(Key::parse("f5").unwrap().to_string(), "f5");
+        assert_eq!(Key::parse("-").unwrap().to_string(), "-");
+
+        assert!(Key::parse("jj").is_err());
+        assert!(Key::parse("f13").is_err());
+        assert!(Key::parse("").is_err());
+    }
+
+    #[test]
+    fn defaults_have_no_conflicts() {
+        let (keymap, problems) = Keymap::load(None);
+        assert!(problems.is_empty(), "{problems:?}");
+        let j = press(KeyCode::Char('j'), KeyModifiers::NONE);
+        assert_eq!(keymap.action(Context::Tasks, &j), Some(Action::ScrollDown));
+        // `q` quits from a tab but backs out of a log.
+        let q = press(KeyCode::Char('q'), KeyModifiers::NONE);
+        assert_eq!(keymap.action(Context::Tasks, &q), Some(Action::Quit));
+        assert_eq!(keymap.action(Context::Detail, &q), Some(Action::Back));
+        assert_eq!(keymap.action(Context::Daemon, &j), None);
+    }
+
+    #[test]
+    fn config_replaces_defaults_and_reports_problems() {
+        let config: work::config::Config = toml::from_str(
+            r#"
+[tui.keys]
+scroll_down = ["n", "down"]
+new_task = "a"
+delete = "x"
+claim_environment = "nope"
+explode = "e"
+toggle_plain = "g"
+"#,
+        )
+        .unwrap();
+        let (keymap, problems) = Keymap::load(config.tui.as_ref());
+
+        let n = press(KeyCode::Char('n'), KeyModifiers::NONE);
+        assert_eq!(keymap.action(Context::Tasks, &n), Some(Action::ScrollDown));
+        assert_eq!(
+            keymap.action(
+                Context::Tasks,
+                &press(KeyCode::Char('j'), KeyModifiers::NONE)
+            ),
+            None
+        );
+        assert_eq!(keymap.describe(Action::ClaimEnvironment), "c");
+        assert_eq!(
+            keymap
+                .hint(&[Action::ScrollDown, Action::ScrollUp], "navigate")
+                .unwrap(),
+            "n/k: navigate"
+        );
+
+        assert_eq!(
+            problems,
+            [
+                "[tui.keys] claim_environment: unknown key \"nope\"",
+                "[tui.keys] unknown action \"explode\"",
+                "[tui.keys] \"g\" is bound to both top and toggle_plain in the log view; top wins",
+            ],
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span, Text};
+
+/// Render a task description's markdown well enough to read in a terminal:
+/// headings are bold, list items get bullets, fenced code blocks and `code`
+/// spans are colored, and `**strong**` text is bold. Anything else is shown
+/// as written.
+pub fn to_text(markdown: &str) -> Text<'static> {
+    let code = Style::default().fg(Color::Cyan);
+    let mut lines = Vec::new();
+    let mut in_fence = false;
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            lines.push(Line::from(Span::styled(format!("  {line}"), code)));
+            continue;
+        }
+
+        let indent = &line[..line.len() - trimmed.len()];
+        if let Some(heading) = heading(trimmed) {
+            lines.push(Line::from(Span::styled(
+                heading.to_string(),
+                Style::default().add_modifier(Modifier::BOLD),
+            )));
+        } else if let Some(item) = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|marker| trimmed.strip_prefix(marker))
+        {
+            let mut spans = vec![Span::raw(format!("{indent}• "))];
+            spans.extend(inline(item));
+            lines.push(Line::from(spans));
+        } else {
+            let mut spans = vec![Span::raw(indent.to_string())];
+            spans.extend(inline(trimmed));
+            lines.push(Line::from(spans));
+        }
+    }
+    Text::from(lines)
+}
+
+fn heading(line: &str) -> Option<&str> {
+    let text = line.trim_start_matches('#');
+    let level = line.len() - text.len();
+    ((1..=6).contains(&level) && text.starts_with(' ')).then(|| text.trim())
+}
+
+/// Style `code` spans and `**strong**` text within one line. An unclosed
+/// marker is left as written.
+fn inline(text: &str) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut plain = String::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let styled = if let Some(after) = rest.strip_prefix('`') {
+            after.find('`').map(|end| {
+                (
+                    &after[..end],
+                    Style::default().fg(Color::Cyan),
+                    &after[end + 1..],
+                )
+            })
+        } else if let Some(after) = rest.strip_prefix("**") {
+            after.find("**").map(|end| {
+                (
+                    &after[..end],
+                    Style::default().add_modifier(Modifier::BOLD),
+                    &after[end + 2..],
+                )
+            })
+        } else {
+            None
+        };
+        match styled {
+            Some((content, style, after)) if !content.is_empty() => {
+                if !plain.is_empty() {
+                    spans.push(Span::raw(std::mem::take(&mut plain)));
+                }
+                spans.push(Span::styled(content.to_string(), style));
+                rest = after;
+            }
+            _ => {
+                let c = rest.chars().next().unwrap_or_default();
+                plain.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    if !plain.is_empty() {
+        spans.push(Span::raw(plain));
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_headings_bullets_code_and_strong_text() {
+        let text = to_text(
+            "# Fix the build\n\nSteps:\n  - run `cargo test`\n* keep **all** tests\n```\nlet x = 1;\n```\nan `unclosed span",
+        );
+        let code = Style::default().fg(Color::Cyan);
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        assert_eq!(
+            text.lines,
+            vec![
+                Line::from(Span::styled("Fix the build", bold)),
+                Line::from(vec![Span::raw("")]),
+                Line::from(vec![Span::raw(""), Span::raw("Steps:")]),
+                Line::from(vec![
+                    Span::raw("  • "),
+                    Span::raw("run "),
+                    Span::styled("cargo test", code),
+                ]),
+                Line::from(vec![
+                    Span::raw("• "),
+                    Span::raw("keep "),
+                    Span::styled("all", bold),
+                    Span::raw(" tests"),
+                ]),
+                Line::from(Span::styled("  let x = 1;", code)),
+                Line::from(vec![Span::raw(""), Span::raw("an `unclosed span")]),
+            ]
+        );
+    }
+}
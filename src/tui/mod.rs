@@ -1,18 +1,24 @@
+mod ansi;
 mod app;
+mod diagnostics;
+mod keys;
+mod markdown;
 mod ui;
 
 use std::io;
+use std::path::Path;
 use std::sync::{Arc, Condvar, Mutex};
 
-use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
 use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
 use ratatui::Terminal;
 use ratatui::backend::CrosstermBackend;
 
-use crate::client::{DaemonClient, DaemonEvent};
-use crate::db::Project;
+use work::client::{DaemonClient, DaemonEvent};
+use work::db::{NewTask, Project};
 
 use app::{App, Tab};
+use keys::{Action, Context};
 
 enum EditorOutcome {
     Submitted(String),
@@ -187,19 +193,27 @@ pub async fn run(client: DaemonClient) -> anyhow::Result<()> {
             _ = tick_interval.tick() => {
                 tick_count = tick_count.wrapping_add(1);
                 if app.detail.is_some() {
-                    app.refresh_detail_logs();
+                    app.refresh_detail_logs(&client).await;
                 }
                 if app.tab == Tab::Logs {
                     app.refresh_tui_logs();
                 }
+                if app.tab == Tab::Tasks {
+                    app.refresh_task_order();
+                }
             }
             result = events_rx.recv() => {
                 match result {
-                    Some(DaemonEvent::Connected | DaemonEvent::Updated) => {
+                    Some(DaemonEvent::Connected | DaemonEvent::Updated(_)) => {
                         // Drain any buffered events to avoid redundant polls.
                         while events_rx.try_recv().is_ok() {}
                         app.poll(&client).await;
                     }
+                    Some(DaemonEvent::Resumed) => {
+                        // Anything missed while disconnected follows as
+                        // updates, so there is nothing to refetch yet.
+                        app.set_connected();
+                    }
                     Some(DaemonEvent::Disconnected) => {
                         app.set_disconnected();
                     }
@@ -246,6 +260,8 @@ async fn handle_key(
     key: event::KeyEvent,
     input_gate: &InputGate,
 ) -> bool {
+    app.notice = None;
+
     // Confirm dialog takes priority.
     if app.confirm.is_some() {
         match key.code {
@@ -256,117 +272,152 @@ async fn handle_key(
         return false;
     }
 
-    if app.create_task_prompt.is_some() {
+    if app.search_prompt.is_some() {
         match key.code {
-            KeyCode::Char('j') | KeyCode::Down => app.create_task_prompt_select_next(),
-            KeyCode::Char('k') | KeyCode::Up => app.create_task_prompt_select_prev(),
-            KeyCode::Enter => {
-                confirm_create_task_prompt(app, client, input_gate).await;
-                return true;
-            }
-            KeyCode::Char('q') | KeyCode::Esc => app.cancel_create_task_prompt(),
+            KeyCode::Enter => app.submit_search(client).await,
+            KeyCode::Esc => app.cancel_search_prompt(),
+            KeyCode::Backspace => app.search_prompt_pop(),
+            KeyCode::Char(c) => app.search_prompt_push(c),
             _ => {}
         }
         return false;
     }
 
-    // Detail view (e.g. log view) takes priority over tab content.
-    if app.detail.is_some() {
+    if app.filter_prompt.is_some() {
         match key.code {
-            KeyCode::Char('q') | KeyCode::Esc => app.exit_detail(),
-            KeyCode::Char('j') | KeyCode::Down => app.scroll_log_down(1),
-            KeyCode::Char('k') | KeyCode::Up => app.scroll_log_up(1),
-            KeyCode::Char('g') => app.scroll_log_top(),
-            KeyCode::Char('G') => app.scroll_log_bottom(),
-            KeyCode::Char('d') => app.scroll_log_down(20),
-            KeyCode::Char('u') => app.scroll_log_up(20),
+            KeyCode::Enter => app.submit_filter(client).await,
+            KeyCode::Esc => app.cancel_filter_prompt(),
+            KeyCode::Backspace => app.filter_prompt_pop(),
+            KeyCode::Char(c) => app.filter_prompt_push(c),
             _ => {}
         }
         return false;
     }
 
-    // Global keys (when no detail view is open).
-    match key.code {
-        KeyCode::Char('q') => {
-            app.should_quit = true;
-            return false;
-        }
-        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-            app.should_quit = true;
-            return false;
-        }
-        KeyCode::Tab => {
-            app.next_tab();
-            return false;
-        }
-        KeyCode::BackTab => {
-            app.prev_tab();
-            return false;
-        }
-        KeyCode::Char('1') => {
-            app.select_tab(0);
-            return false;
-        }
-        KeyCode::Char('2') => {
-            app.select_tab(1);
-            return false;
-        }
-        KeyCode::Char('3') => {
-            app.select_tab(2);
-            return false;
-        }
-        KeyCode::Char('4') => {
-            app.select_tab(3);
-            return false;
-        }
-        KeyCode::Char('5') => {
-            app.select_tab(4);
-            return false;
+    if app.note_prompt.is_some() {
+        match key.code {
+            KeyCode::Enter => app.submit_note(client).await,
+            KeyCode::Esc => app.cancel_note_prompt(),
+            KeyCode::Backspace => app.note_prompt_pop(),
+            KeyCode::Char(c) => app.note_prompt_push(c),
+            _ => {}
         }
-        _ => {}
+        return false;
     }
 
-    // Tab-specific keys.
-    match app.tab {
-        Tab::Tasks => match key.code {
-            KeyCode::Char('j') | KeyCode::Down => app.select_next(),
-            KeyCode::Char('k') | KeyCode::Up => app.select_prev(),
-            KeyCode::Char('h') | KeyCode::Left => app.collapse_section(),
-            KeyCode::Char('l') | KeyCode::Right => app.expand_section(),
-            KeyCode::Char('H') => app.collapse_all(),
-            KeyCode::Char('L') => app.expand_all(),
-            KeyCode::Enter => app.enter_detail(),
-            KeyCode::Char('d') => app.prompt_delete(),
-            KeyCode::Char('D') => app.prompt_force_delete(),
-            KeyCode::Char('n') => app.begin_create_task_prompt(),
-            KeyCode::Char('`') => app.toggle_task_view_mode(),
-            _ => {}
-        },
-        Tab::Projects => match key.code {
-            KeyCode::Char('j') | KeyCode::Down => app.select_next(),
-            KeyCode::Char('k') | KeyCode::Up => app.select_prev(),
-            KeyCode::Char('d') => app.prompt_delete(),
-            KeyCode::Char('D') => app.prompt_force_delete(),
+    if app.show_help {
+        app.show_help = false;
+        return false;
+    }
+
+    if app.create_task_prompt.is_some() {
+        match app.keys.action(Context::ProjectPrompt, &key) {
+            Some(Action::ScrollDown) => app.create_task_prompt_select_next(),
+            Some(Action::ScrollUp) => app.create_task_prompt_select_prev(),
+            Some(Action::Open) => {
+                confirm_create_task_prompt(app, client, input_gate).await;
+                return true;
+            }
+            Some(Action::Back) => app.cancel_create_task_prompt(),
             _ => {}
-        },
-        Tab::Environments => match key.code {
-            KeyCode::Char('j') | KeyCode::Down => app.select_next(),
-            KeyCode::Char('k') | KeyCode::Up => app.select_prev(),
-            KeyCode::Enter => app.enter_detail(),
-            KeyCode::Char('d') => app.prompt_delete(),
-            KeyCode::Char('D') => app.prompt_force_delete(),
+        }
+        return false;
+    }
+
+    // Detail view (e.g. log view) takes priority over tab content.
+    if app.detail.is_some() {
+        let env_detail = app.detail_environment().is_some();
+        match app.keys.action(Context::Detail, &key) {
+            Some(Action::Back) => app.exit_detail(),
+            Some(Action::Help) => app.show_help = true,
+            Some(Action::UpdateEnvironment) if env_detail => {
+                app.update_detail_environment(client).await
+            }
+            Some(Action::ClaimEnvironment) if env_detail => {
+                app.claim_detail_environment(client).await
+            }
+            Some(Action::RemoveEnvironment) if env_detail => {
+                app.prompt_delete_detail_environment(false)
+            }
+            Some(Action::ForceRemoveEnvironment) if env_detail => {
+                app.prompt_delete_detail_environment(true)
+            }
+            Some(Action::ScrollDown) => app.scroll_log_down(1),
+            Some(Action::ScrollUp) => app.scroll_log_up(1),
+            Some(Action::Top) => app.scroll_log_top(),
+            Some(Action::Bottom) => app.scroll_log_bottom(),
+            Some(Action::PageDown) => app.scroll_log_down(20),
+            Some(Action::PageUp) => app.scroll_log_up(20),
+            Some(Action::Collapse) => app.fold_log_section(true),
+            Some(Action::Expand) => app.fold_log_section(false),
+            Some(Action::CollapseAll) => app.fold_all_log_sections(true),
+            Some(Action::ExpandAll) => app.fold_all_log_sections(false),
+            Some(Action::TogglePlain) => app.toggle_log_plain(),
+            Some(Action::Note) => app.begin_note(),
             _ => {}
+        }
+        return false;
+    }
+
+    let Some(action) = app.keys.action(app.context(), &key) else {
+        return false;
+    };
+    match action {
+        // Global actions (when no detail view is open).
+        Action::Quit => app.should_quit = true,
+        Action::Help => app.show_help = true,
+        Action::NextTab => app.next_tab(),
+        Action::PrevTab => app.prev_tab(),
+        Action::TasksTab => app.select_tab(0),
+        Action::ProjectsTab => app.select_tab(1),
+        Action::EnvironmentsTab => app.select_tab(2),
+        Action::DaemonTab => app.select_tab(3),
+        Action::LogsTab => app.select_tab(4),
+        Action::ConfigTab => app.select_tab(5),
+
+        // Tab-specific actions.
+        Action::ScrollDown => match app.tab {
+            Tab::Config => app.scroll_config_down(1),
+            Tab::Logs => app.scroll_tui_log_down(1),
+            _ => app.select_next(),
         },
-        Tab::Daemon => {}
-        Tab::Logs => match key.code {
-            KeyCode::Char('j') | KeyCode::Down => app.scroll_tui_log_down(1),
-            KeyCode::Char('k') | KeyCode::Up => app.scroll_tui_log_up(1),
-            KeyCode::Char('g') => app.scroll_tui_log_top(),
-            KeyCode::Char('G') => app.scroll_tui_log_bottom(),
-            KeyCode::Char('d') => app.scroll_tui_log_down(20),
-            KeyCode::Char('u') => app.scroll_tui_log_up(20),
-            _ => {}
+        Action::ScrollUp => match app.tab {
+            Tab::Config => app.scroll_config_up(1),
+            Tab::Logs => app.scroll_tui_log_up(1),
+            _ => app.select_prev(),
         },
+        Action::PageDown if app.tab == Tab::Config => app.scroll_config_down(20),
+        Action::PageDown => app.scroll_tui_log_down(20),
+        Action::PageUp if app.tab == Tab::Config => app.scroll_config_up(20),
+        Action::PageUp => app.scroll_tui_log_up(20),
+        Action::Top => app.scroll_tui_log_top(),
+        Action::Bottom => app.scroll_tui_log_bottom(),
+        Action::Collapse => app.collapse_section(),
+        Action::Expand => app.expand_section(),
+        Action::CollapseAll => app.collapse_all(),
+        Action::ExpandAll => app.expand_all(),
+        Action::Open => {
+            app.enter_detail(client).await;
+            if app.tab == Tab::Environments {
+                app.refresh_environment_history(client).await;
+            }
+        }
+        Action::Delete => app.prompt_delete(),
+        Action::ForceDelete => app.prompt_force_delete(),
+        Action::NewTask => app.begin_create_task_prompt(),
+        Action::Note => app.begin_note(),
+        Action::ToggleView => app.toggle_task_view_mode(),
+        Action::Sort => app.cycle_task_sort(client).await,
+        Action::Filter => app.begin_filter(),
+        Action::Search => app.begin_search(),
+        Action::ClearSearch => app.clear_search(),
+        Action::ReloadConfig => app.reload_config_report(),
+        Action::Back
+        | Action::TogglePlain
+        | Action::UpdateEnvironment
+        | Action::ClaimEnvironment
+        | Action::RemoveEnvironment
+        | Action::ForceRemoveEnvironment => {}
     }
 
     false
@@ -401,7 +452,7 @@ async fn create_task_for_project(
     project: &Project,
     description: &str,
 ) {
-    let config = match crate::config::load() {
+    let config = match work::config::load_for_project(&project.name, Path::new(&project.path)) {
         Ok(config) => config,
         Err(e) => {
             app.error = Some(format!("task creation failed: {e}"));
@@ -425,7 +476,13 @@ async fn create_task_for_project(
     }
 
     match client
-        .create_task(&project.id, &task_provider, &env_provider, description)
+        .create_task(&NewTask {
+            project_id: project.id.clone(),
+            provider: task_provider,
+            env_provider: Some(env_provider),
+            description: description.to_string(),
+            ..Default::default()
+        })
         .await
     {
         Ok(_) => {
@@ -441,29 +498,30 @@ async fn create_task_for_project(
 fn edit_task_description(input_gate: &InputGate) -> anyhow::Result<EditorOutcome> {
     let _input_pause = input_gate.pause_guard();
 
-    let editor = std::env::var("EDITOR").map_err(|_| anyhow::anyhow!("$EDITOR is not set"))?;
-    let path = std::env::temp_dir().join(format!("work-task-{}.txt", crate::id::new_id()));
+    let config = work::config::load()?;
+    let path = std::env::temp_dir().join(format!("work-task-{}.txt", work::id::new_id()));
     std::fs::write(&path, "")?;
 
     terminal::disable_raw_mode()?;
     crossterm::execute!(io::stdout(), LeaveAlternateScreen)?;
 
-    let status_result = std::process::Command::new(&editor).arg(&path).status();
+    let edit_result = crate::editor::edit(&config, &path);
     let restore_screen_result = crossterm::execute!(io::stdout(), EnterAlternateScreen);
     let restore_raw_result = terminal::enable_raw_mode();
 
-    let contents = std::fs::read_to_string(&path).unwrap_or_default();
     let _ = std::fs::remove_file(&path);
 
     restore_screen_result?;
     restore_raw_result?;
 
-    let status = status_result?;
-    if !status.success() {
-        return Ok(EditorOutcome::Cancelled(format!(
-            "task creation cancelled ({editor} exited with {status})"
-        )));
-    }
+    let contents = match edit_result {
+        Ok(contents) => contents,
+        Err(e) => {
+            return Ok(EditorOutcome::Cancelled(format!(
+                "task creation cancelled ({e})"
+            )));
+        }
+    };
 
     let description = contents.trim().to_string();
     if description.is_empty() {
@@ -1,10 +1,15 @@
 use ratatui::Frame;
 use ratatui::layout::{Constraint, Layout, Rect};
 use ratatui::style::{Color, Modifier, Style};
-use ratatui::text::{Line, Span};
+use ratatui::text::{Line, Span, Text};
 use ratatui::widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Table, Tabs, Wrap};
 
-use super::app::{App, Confirm, DetailView, Tab, TaskViewMode, TreeRow};
+use super::app::{self, App, Confirm, DetailView, LogRow, Tab, TaskViewMode, TreeRow};
+use super::keys::{Action, Context};
+use work::db::{
+    Environment, EnvironmentSnapshot, EnvironmentStatusChange, Task, TaskGroupCounts, TaskSort,
+};
+use work::log_sections::LogSection;
 
 const SPINNER_FRAMES: &[&str] = &["◐", "◓", "◑", "◒"];
 
@@ -24,6 +29,9 @@ pub fn draw(frame: &mut Frame, app: &App, tick_count: usize) {
             draw_log_view(frame, app, chunks[1])
         }
         None => match app.tab {
+            Tab::Tasks if app.search.is_some() => {
+                draw_task_search(frame, app, tick_count, chunks[1])
+            }
             Tab::Tasks => match app.task_view_mode {
                 TaskViewMode::Flat => draw_task_list_flat(frame, app, tick_count, chunks[1]),
                 TaskViewMode::Tree => draw_task_list_tree(frame, app, tick_count, chunks[1]),
@@ -32,6 +40,7 @@ pub fn draw(frame: &mut Frame, app: &App, tick_count: usize) {
             Tab::Environments => draw_environment_list(frame, app, tick_count, chunks[1]),
             Tab::Daemon => draw_daemon_view(frame, app, tick_count, chunks[1]),
             Tab::Logs => draw_tui_logs_view(frame, app, chunks[1]),
+            Tab::Config => draw_config_view(frame, app, chunks[1]),
         },
     }
 
@@ -44,13 +53,17 @@ pub fn draw(frame: &mut Frame, app: &App, tick_count: usize) {
     if app.confirm.is_some() {
         draw_confirm_dialog(frame, app);
     }
+
+    if app.show_help {
+        draw_help(frame, app);
+    }
 }
 
 fn draw_tab_bar(frame: &mut Frame, app: &App, area: Rect) {
-    let titles: Vec<Span> = Tab::ALL
+    let titles: Vec<Line> = Tab::ALL
         .iter()
         .map(|t| {
-            if *t == app.tab {
+            let label = if *t == app.tab {
                 Span::styled(
                     t.label(),
                     Style::default()
@@ -59,7 +72,23 @@ fn draw_tab_bar(frame: &mut Frame, app: &App, area: Rect) {
                 )
             } else {
                 Span::styled(t.label(), Style::default().fg(Color::DarkGray))
+            };
+            let mut spans = vec![label];
+            // Dead jobs need a manual retry or purge, so flag them from any tab.
+            if *t == Tab::Daemon && app.dead_jobs > 0 {
+                spans.push(Span::styled(
+                    format!(" ✗{}", app.dead_jobs),
+                    Style::default().fg(Color::Red),
+                ));
+            }
+            let outdated = app.environments.iter().filter(|e| e.is_outdated()).count();
+            if *t == Tab::Environments && outdated > 0 {
+                spans.push(Span::styled(
+                    format!(" ⟳{outdated}"),
+                    Style::default().fg(Color::Yellow),
+                ));
             }
+            Line::from(spans)
         })
         .collect();
 
@@ -76,42 +105,166 @@ fn draw_tab_bar(frame: &mut Frame, app: &App, area: Rect) {
 }
 
 fn draw_status_bar(frame: &mut Frame, app: &App, area: Rect) {
-    let line = if let Some(ref err) = app.error {
+    let line = if let Some(query) = &app.search_prompt {
+        Line::from(vec![
+            Span::styled(" /", Style::default().add_modifier(Modifier::BOLD)),
+            Span::raw(query.clone()),
+            Span::styled("█", Style::default().add_modifier(Modifier::DIM)),
+            Span::styled(
+                "  Enter: search | Esc: cancel",
+                Style::default().add_modifier(Modifier::DIM),
+            ),
+        ])
+    } else if let Some(text) = &app.filter_prompt {
+        Line::from(vec![
+            Span::styled(" filter: ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::raw(text.clone()),
+            Span::styled("█", Style::default().add_modifier(Modifier::DIM)),
+            Span::styled(
+                "  statuses and projects | Enter: apply (empty clears) | Esc: cancel",
+                Style::default().add_modifier(Modifier::DIM),
+            ),
+        ])
+    } else if let Some(prompt) = &app.note_prompt {
+        Line::from(vec![
+            Span::styled(" note: ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::raw(prompt.text.clone()),
+            Span::styled("█", Style::default().add_modifier(Modifier::DIM)),
+            Span::styled(
+                "  Enter: add | Esc: cancel",
+                Style::default().add_modifier(Modifier::DIM),
+            ),
+        ])
+    } else if let Some(ref err) = app.error {
         Line::from(vec![Span::styled(
             err.as_str(),
             Style::default().fg(Color::Red),
         )])
-    } else if app.create_task_prompt.is_some() {
+    } else if let Some(ref notice) = app.notice {
         Line::from(vec![Span::styled(
-            " j/k: choose project | Enter: open editor | q/Esc: cancel",
-            Style::default().add_modifier(Modifier::DIM),
+            format!(" {notice}"),
+            Style::default().fg(Color::Yellow),
         )])
     } else {
-        let hints = match app.detail {
-            Some(DetailView::TaskLog { .. } | DetailView::EnvironmentLog { .. }) => {
-                " q/Esc: back | j/k: scroll | g/G: top/bottom | d/u: half-page"
-            }
-            None => match app.tab {
-                Tab::Tasks => match app.task_view_mode {
-                    TaskViewMode::Flat => {
-                        " Tab: tabs | j/k: navigate | Enter: logs | n: new | d: delete | D: force delete | `: flat/tree | q: quit"
-                    }
-                    TaskViewMode::Tree => {
-                        " Tab: tabs | j/k: navigate | h/l: collapse/expand | Enter: logs | n: new | d: delete | D: force delete | `: flat/tree | q: quit"
-                    }
+        use Action::*;
+        let hints: &[(&[Action], &str)] = if app.create_task_prompt.is_some() {
+            &[
+                (&[ScrollDown, ScrollUp], "choose project"),
+                (&[Open], "open editor"),
+                (&[Back], "cancel"),
+            ]
+        } else {
+            match app.detail {
+                Some(DetailView::TaskLog { .. }) => &[
+                    (&[Back], "back"),
+                    (&[ScrollDown, ScrollUp], "scroll"),
+                    (&[Top, Bottom], "top/bottom"),
+                    (&[PageDown, PageUp], "half-page"),
+                    (&[Collapse, Expand], "fold/unfold"),
+                    (&[TogglePlain], "plain/color"),
+                    (&[Note], "note"),
+                    (&[Help], "keys"),
+                ],
+                Some(DetailView::EnvironmentLog { .. }) => &[
+                    (&[Back], "back"),
+                    (&[ScrollDown, ScrollUp], "scroll"),
+                    (&[Top, Bottom], "top/bottom"),
+                    (&[PageDown, PageUp], "half-page"),
+                    (&[Collapse, Expand], "fold/unfold"),
+                    (&[TogglePlain], "plain/color"),
+                    (&[UpdateEnvironment], "update"),
+                    (&[ClaimEnvironment], "claim"),
+                    (&[RemoveEnvironment], "remove"),
+                    (&[ForceRemoveEnvironment], "force remove"),
+                    (&[Help], "keys"),
+                ],
+                None => match app.tab {
+                    Tab::Tasks if app.search.is_some() => &[
+                        (&[NextTab], "tabs"),
+                        (&[ScrollDown, ScrollUp], "navigate"),
+                        (&[Open], "logs"),
+                        (&[Note], "note"),
+                        (&[Search], "new search"),
+                        (&[ClearSearch], "clear search"),
+                        (&[Delete], "delete"),
+                        (&[Help], "keys"),
+                        (&[Quit], "quit"),
+                    ],
+                    Tab::Tasks => match app.task_view_mode {
+                        TaskViewMode::Flat => &[
+                            (&[NextTab], "tabs"),
+                            (&[ScrollDown, ScrollUp], "navigate"),
+                            (&[Open], "logs"),
+                            (&[NewTask], "new"),
+                            (&[Note], "note"),
+                            (&[Search], "search"),
+                            (&[Sort], "sort"),
+                            (&[Filter], "filter"),
+                            (&[Delete], "delete"),
+                            (&[ForceDelete], "force delete"),
+                            (&[ToggleView], "flat/tree"),
+                            (&[Help], "keys"),
+                            (&[Quit], "quit"),
+                        ],
+                        TaskViewMode::Tree => &[
+                            (&[NextTab], "tabs"),
+                            (&[ScrollDown, ScrollUp], "navigate"),
+                            (&[Collapse, Expand], "collapse/expand"),
+                            (&[Open], "logs"),
+                            (&[NewTask], "new"),
+                            (&[Note], "note"),
+                            (&[Search], "search"),
+                            (&[Sort], "sort"),
+                            (&[Filter], "filter"),
+                            (&[Delete], "delete"),
+                            (&[ForceDelete], "force delete"),
+                            (&[ToggleView], "flat/tree"),
+                            (&[Help], "keys"),
+                            (&[Quit], "quit"),
+                        ],
+                    },
+                    Tab::Projects => &[
+                        (&[NextTab], "tabs"),
+                        (&[ScrollDown, ScrollUp], "navigate"),
+                        (&[Delete, ForceDelete], "delete"),
+                        (&[Help], "keys"),
+                        (&[Quit], "quit"),
+                    ],
+                    Tab::Environments => &[
+                        (&[NextTab], "tabs"),
+                        (&[ScrollDown, ScrollUp], "navigate"),
+                        (&[Open], "logs"),
+                        (&[Delete], "delete"),
+                        (&[ForceDelete], "force delete"),
+                        (&[Help], "keys"),
+                        (&[Quit], "quit"),
+                    ],
+                    Tab::Daemon => &[(&[NextTab], "tabs"), (&[Help], "keys"), (&[Quit], "quit")],
+                    Tab::Config => &[
+                        (&[NextTab], "tabs"),
+                        (&[ScrollDown, ScrollUp], "scroll"),
+                        (&[PageDown, PageUp], "half-page"),
+                        (&[ReloadConfig], "reload"),
+                        (&[Help], "keys"),
+                        (&[Quit], "quit"),
+                    ],
+                    Tab::Logs => &[
+                        (&[NextTab], "tabs"),
+                        (&[ScrollDown, ScrollUp], "scroll"),
+                        (&[Top, Bottom], "top/bottom"),
+                        (&[PageDown, PageUp], "half-page"),
+                        (&[Help], "keys"),
+                        (&[Quit], "quit"),
+                    ],
                 },
-                Tab::Projects => " Tab: tabs | j/k: navigate | d/D: delete | q: quit",
-                Tab::Environments => {
-                    " Tab: tabs | j/k: navigate | Enter: logs | d: delete | D: force delete | q: quit"
-                }
-                Tab::Daemon => " Tab: tabs | q: quit",
-                Tab::Logs => {
-                    " Tab: tabs | j/k: scroll | g/G: top/bottom | d/u: half-page | q: quit"
-                }
-            },
+            }
         };
+        let hints: Vec<String> = hints
+            .iter()
+            .filter_map(|(actions, label)| app.keys.hint(actions, label))
+            .collect();
         Line::from(vec![Span::styled(
-            hints,
+            format!(" {}", hints.join(" | ")),
             Style::default().add_modifier(Modifier::DIM),
         )])
     };
@@ -119,10 +272,18 @@ fn draw_status_bar(frame: &mut Frame, app: &App, area: Rect) {
     frame.render_widget(Paragraph::new(line), area);
 }
 
+/// How long something has had its status, e.g. `3m`, dimmed beside it.
+fn in_status_span(since: Option<String>) -> Span<'static> {
+    Span::styled(
+        since.unwrap_or_default(),
+        Style::default().fg(Color::DarkGray),
+    )
+}
+
 fn status_span(status: &str, tick_count: usize) -> Span<'static> {
     match status {
         "pending" => Span::styled(format!("● {status}"), Style::default().fg(Color::Yellow)),
-        "started" => {
+        "started" | "running" => {
             let spinner = SPINNER_FRAMES[tick_count % SPINNER_FRAMES.len()];
             Span::styled(
                 format!("{spinner} {status}"),
@@ -131,14 +292,42 @@ fn status_span(status: &str, tick_count: usize) -> Span<'static> {
         }
         "complete" => Span::styled(format!("✓ {status}"), Style::default().fg(Color::Green)),
         "failed" => Span::styled(format!("✗ {status}"), Style::default().fg(Color::Red)),
+        "timed_out" => Span::styled(format!("⧗ {status}"), Style::default().fg(Color::Red)),
+        "interrupted" | "degraded" => {
+            Span::styled(format!("! {status}"), Style::default().fg(Color::Yellow))
+        }
+        "cancelled" => Span::styled(format!("⊘ {status}"), Style::default().fg(Color::DarkGray)),
+        "empty" => Span::styled(format!("○ {status}"), Style::default().fg(Color::DarkGray)),
         _ => Span::raw(status.to_string()),
     }
 }
 
+fn task_description_line(task: &Task) -> Line<'static> {
+    let mut spans = vec![Span::raw(task.title().to_string())];
+    if let Some(summary) = &task.result_summary {
+        spans.push(Span::styled(
+            format!("  ↳ {summary}"),
+            Style::default().fg(Color::DarkGray),
+        ));
+    }
+    Line::from(spans)
+}
+
 fn short_id(id: &str) -> &str {
     if id.len() > 8 { &id[..8] } else { id }
 }
 
+/// The provider's display name for an environment, or its short ID.
+fn env_label(env: &Environment) -> &str {
+    env.display_name().unwrap_or_else(|| short_id(&env.id))
+}
+
+/// [`env_label`] for an environment that may not be loaded.
+fn env_label_by_id<'a>(app: &'a App, env_id: &'a str) -> &'a str {
+    app.find_environment(env_id)
+        .map_or_else(|| short_id(env_id), env_label)
+}
+
 fn scroll_top_for_bottom_follow(line_index: usize, area: Rect) -> u16 {
     // Paragraph text area excludes top/bottom borders.
     let visible_lines = area.height.saturating_sub(2) as usize;
@@ -158,24 +347,122 @@ fn row_style(selected: bool) -> Style {
     }
 }
 
+/// The Tasks list's title: its order, and its filter if it has one.
+fn task_list_title(app: &App) -> String {
+    match &app.task_filter {
+        Some(filter) => format!(
+            " {} · filter: {} ({} of {}) ",
+            app::sort_label(app.task_sort),
+            filter.text,
+            filter.count(&app.task_counts, &app.projects),
+            app.task_counts.total
+        ),
+        None if app.task_sort == TaskSort::Created => String::new(),
+        None => format!(" {} ", app::sort_label(app.task_sort)),
+    }
+}
+
+/// A run time such as `45s`, `12m 3s`, or `2h 5m`.
+fn format_run_ms(ms: u64) -> String {
+    let secs = ms / 1000;
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
 fn draw_task_list_flat(frame: &mut Frame, app: &App, tick_count: usize, area: Rect) {
-    let header = Row::new(["TASK", "PROJECT", "STATUS", "DESCRIPTION"])
-        .style(Style::default().add_modifier(Modifier::BOLD | Modifier::DIM));
+    let header = Row::new([
+        "TASK",
+        "PROJECT",
+        "STATUS",
+        "IN STATUS",
+        "DURATION",
+        "DESCRIPTION",
+    ])
+    .style(Style::default().add_modifier(Modifier::BOLD | Modifier::DIM));
 
+    let now = chrono::Utc::now();
     let rows: Vec<Row> = app
-        .tasks
+        .task_rows
         .iter()
         .enumerate()
-        .map(|(i, task)| {
+        .map(|(i, &ti)| {
+            let task = &app.tasks[ti];
             let project = app.project_name(&task.project_id);
             let status = status_span(&task.status, tick_count);
+            let duration = task.run_ms(now).map(format_run_ms);
 
             Row::new(vec![
                 Cell::from(short_id(&task.id).to_string()),
                 Cell::from(project.to_string()),
                 Cell::from(status),
-                Cell::from(task.description.clone()),
+                Cell::from(in_status_span(task.in_status_for())),
+                Cell::from(Span::styled(
+                    duration.unwrap_or_default(),
+                    Style::default().fg(Color::DarkGray),
+                )),
+                Cell::from(task_description_line(task)),
+            ])
+            .style(row_style(i == app.selected))
+        })
+        .collect();
+
+    let widths = [
+        Constraint::Length(10),
+        Constraint::Length(14),
+        Constraint::Length(12),
+        Constraint::Length(10),
+        Constraint::Length(9),
+        Constraint::Fill(1),
+    ];
+
+    let table = Table::new(rows, widths).header(header).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(task_list_title(app)),
+    );
+
+    frame.render_widget(table, area);
+}
+
+fn draw_task_search(frame: &mut Frame, app: &App, tick_count: usize, area: Rect) {
+    let Some(search) = &app.search else {
+        return;
+    };
+
+    let header = Row::new(["TASK", "PROJECT", "STATUS", "MATCH"])
+        .style(Style::default().add_modifier(Modifier::BOLD | Modifier::DIM));
+
+    let rows: Vec<Row> = search
+        .hits
+        .iter()
+        .enumerate()
+        .map(|(i, hit)| {
+            // Prefer the live task so status reflects updates since the search.
+            let task = app
+                .tasks
+                .iter()
+                .find(|t| t.id == hit.task.id)
+                .unwrap_or(&hit.task);
+            let description = task.title();
+            let mut snippet = vec![Span::styled(
+                format!("{}: ", hit.matched),
+                Style::default().fg(Color::DarkGray),
+            )];
+            snippet.extend(highlighted_spans(&hit.snippet, &hit.highlights));
+
+            Row::new(vec![
+                Cell::from(short_id(&task.id).to_string()),
+                Cell::from(app.project_name(&task.project_id).to_string()),
+                Cell::from(status_span(&task.status, tick_count)),
+                Cell::from(Text::from(vec![
+                    Line::from(description.to_string()),
+                    Line::from(snippet),
+                ])),
             ])
+            .height(2)
             .style(row_style(i == app.selected))
         })
         .collect();
@@ -187,13 +474,45 @@ fn draw_task_list_flat(frame: &mut Frame, app: &App, tick_count: usize, area: Re
         Constraint::Fill(1),
     ];
 
+    let title = format!(
+        " search: {} ({} {}) ",
+        search.query,
+        search.hits.len(),
+        if search.hits.len() == 1 {
+            "match"
+        } else {
+            "matches"
+        }
+    );
     let table = Table::new(rows, widths)
         .header(header)
-        .block(Block::default().borders(Borders::ALL));
+        .block(Block::default().borders(Borders::ALL).title(title));
 
     frame.render_widget(table, area);
 }
 
+fn highlighted_spans(snippet: &str, highlights: &[(usize, usize)]) -> Vec<Span<'static>> {
+    let chars: Vec<char> = snippet.chars().collect();
+    let mut spans = Vec::new();
+    let mut pos = 0;
+    for &(start, end) in highlights {
+        let (start, end) = (start.clamp(pos, chars.len()), end.min(chars.len()));
+        if start >= end {
+            continue;
+        }
+        spans.push(Span::raw(chars[pos..start].iter().collect::<String>()));
+        spans.push(Span::styled(
+            chars[start..end].iter().collect::<String>(),
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        ));
+        pos = end;
+    }
+    spans.push(Span::raw(chars[pos..].iter().collect::<String>()));
+    spans
+}
+
 fn draw_task_list_tree(frame: &mut Frame, app: &App, tick_count: usize, area: Rect) {
     let rows: Vec<Row> = app
         .tree_rows
@@ -215,28 +534,63 @@ fn draw_task_list_tree(frame: &mut Frame, app: &App, tick_count: usize, area: Re
                     ]))])
                     .style(style)
                 }
-                TreeRow::Task(ti) => {
-                    let task = &app.tasks[*ti];
-                    let status = status_span(&task.status, tick_count);
-                    let prefix = if app.is_task_collapsed(*ti) {
+                TreeRow::Group(gi) => {
+                    let group = &app.groups[*gi];
+                    let prefix = if app.is_group_collapsed(*gi) {
                         "  ├▶"
                     } else {
                         "  ├▼"
                     };
                     Row::new(vec![Cell::from(Line::from(vec![
                         Span::styled(prefix, Style::default().fg(Color::DarkGray)),
-                        Span::raw(format!("{} ", short_id(&task.id))),
-                        status,
-                        Span::raw(format!("  {}", task.description)),
+                        Span::raw(format!("{} ", short_id(&group.id))),
+                        status_span(&group.status, tick_count),
+                        Span::raw("  "),
+                        Span::styled(
+                            group.description.clone(),
+                            Style::default().add_modifier(Modifier::BOLD),
+                        ),
+                        Span::styled(
+                            format!("  ({})", group_counts_label(&group.counts)),
+                            Style::default().fg(Color::DarkGray),
+                        ),
                     ]))])
                     .style(style)
                 }
+                TreeRow::Task(ti) => {
+                    let task = &app.tasks[*ti];
+                    let status = status_span(&task.status, tick_count);
+                    // Tasks in a group sit one level further in.
+                    let indent = if app.task_group(task).is_some() {
+                        "  │ "
+                    } else {
+                        "  "
+                    };
+                    let arrow = if app.is_task_collapsed(*ti) {
+                        "├▶"
+                    } else {
+                        "├▼"
+                    };
+                    let mut spans = vec![
+                        Span::styled(
+                            format!("{indent}{arrow}"),
+                            Style::default().fg(Color::DarkGray),
+                        ),
+                        Span::raw(format!("{} ", short_id(&task.id))),
+                        status,
+                        Span::raw(" "),
+                        in_status_span(task.in_status_for()),
+                        Span::raw("  "),
+                    ];
+                    spans.extend(task_description_line(task).spans);
+                    Row::new(vec![Cell::from(Line::from(spans))]).style(style)
+                }
                 TreeRow::TaskEnvironment(ti) => {
                     let task = &app.tasks[*ti];
                     let (env_id_str, env_status) =
                         if let Some(env) = app.find_environment(&task.environment_id) {
                             (
-                                short_id(&env.id).to_string(),
+                                env_label(env).to_string(),
                                 status_span(&env.status, tick_count),
                             )
                         } else {
@@ -245,8 +599,13 @@ fn draw_task_list_tree(frame: &mut Frame, app: &App, tick_count: usize, area: Re
                                 Span::styled("?", Style::default().fg(Color::DarkGray)),
                             )
                         };
+                    let branch = if app.task_group(task).is_some() {
+                        "  │ │ └ "
+                    } else {
+                        "  │ └ "
+                    };
                     Row::new(vec![Cell::from(Line::from(vec![
-                        Span::styled("  │ └ ", Style::default().fg(Color::DarkGray)),
+                        Span::styled(branch, Style::default().fg(Color::DarkGray)),
                         Span::styled(
                             format!("env {env_id_str} "),
                             Style::default().fg(Color::DarkGray),
@@ -261,11 +620,31 @@ fn draw_task_list_tree(frame: &mut Frame, app: &App, tick_count: usize, area: Re
 
     let widths = [Constraint::Fill(1)];
 
-    let table = Table::new(rows, widths).block(Block::default().borders(Borders::ALL));
+    let table = Table::new(rows, widths).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(task_list_title(app)),
+    );
 
     frame.render_widget(table, area);
 }
 
+/// A group's task counts for the tree, e.g. `1 running, 1 failed, 3 tasks`.
+fn group_counts_label(counts: &TaskGroupCounts) -> String {
+    let mut parts = Vec::new();
+    if counts.running > 0 {
+        parts.push(format!("{} running", counts.running));
+    }
+    if counts.failed > 0 {
+        parts.push(format!("{} failed", counts.failed));
+    }
+    parts.push(match counts.total {
+        1 => "1 task".to_string(),
+        n => format!("{n} tasks"),
+    });
+    parts.join(", ")
+}
+
 fn draw_project_list(frame: &mut Frame, app: &App, area: Rect) {
     let header = Row::new(["NAME", "PATH"])
         .style(Style::default().add_modifier(Modifier::BOLD | Modifier::DIM));
@@ -293,7 +672,7 @@ fn draw_project_list(frame: &mut Frame, app: &App, area: Rect) {
 }
 
 fn draw_environment_list(frame: &mut Frame, app: &App, tick_count: usize, area: Rect) {
-    let header = Row::new(["ID", "PROJECT", "PROVIDER", "STATUS"])
+    let header = Row::new(["NAME", "PROJECT", "PROVIDER", "STATUS", "IN STATUS", ""])
         .style(Style::default().add_modifier(Modifier::BOLD | Modifier::DIM));
 
     let rows: Vec<Row> = app
@@ -302,22 +681,44 @@ fn draw_environment_list(frame: &mut Frame, app: &App, tick_count: usize, area:
         .enumerate()
         .map(|(i, env)| {
             let project = app.project_name(&env.project_id);
-            let status = status_span(&env.status, tick_count);
+            let mut status = status_span(&env.status, tick_count);
+            let mut notes = Vec::new();
+            if let Some(progress) = env.progress_label() {
+                let spinner = SPINNER_FRAMES[tick_count % SPINNER_FRAMES.len()];
+                status = Span::styled(
+                    format!("{spinner} {}", env.status),
+                    Style::default().fg(Color::Yellow),
+                );
+                notes.push(Span::styled(
+                    format!("{progress}  "),
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+            if env.is_outdated() {
+                notes.push(Span::styled(
+                    "pool outdated",
+                    Style::default().fg(Color::Yellow),
+                ));
+            }
 
             Row::new(vec![
-                Cell::from(short_id(&env.id).to_string()),
+                Cell::from(env_label(env).to_string()),
                 Cell::from(project.to_string()),
                 Cell::from(env.provider.clone()),
                 Cell::from(status),
+                Cell::from(in_status_span(env.in_status_for())),
+                Cell::from(Line::from(notes)),
             ])
             .style(row_style(i == app.selected))
         })
         .collect();
 
     let widths = [
-        Constraint::Length(10),
+        Constraint::Length(20),
         Constraint::Length(14),
         Constraint::Length(14),
+        Constraint::Length(12),
+        Constraint::Length(10),
         Constraint::Fill(1),
     ];
 
@@ -342,7 +743,7 @@ fn draw_daemon_view(frame: &mut Frame, app: &App, tick_count: usize, area: Rect)
         )])
     };
 
-    let runtime_dir = crate::paths::runtime_dir().ok();
+    let runtime_dir = work::paths::runtime_dir().ok();
     let socket_path = runtime_dir
         .as_ref()
         .map(|d| d.join("work.sock").display().to_string())
@@ -366,42 +767,271 @@ fn draw_daemon_view(frame: &mut Frame, app: &App, tick_count: usize, area: Rect)
         ]),
         Line::from(vec![
             Span::styled(" tasks:  ", Style::default().add_modifier(Modifier::BOLD)),
-            Span::raw(app.tasks.len().to_string()),
+            Span::raw(app.task_counts.total.to_string()),
         ]),
         Line::from(vec![
             Span::styled(" envs:   ", Style::default().add_modifier(Modifier::BOLD)),
             Span::raw(app.environments.len().to_string()),
         ]),
+        Line::from(vec![
+            Span::styled(" dead:   ", Style::default().add_modifier(Modifier::BOLD)),
+            if app.dead_jobs > 0 {
+                Span::styled(
+                    format!(
+                        "{} (work job retry ID | work job purge-dead)",
+                        app.dead_jobs
+                    ),
+                    Style::default().fg(Color::Red),
+                )
+            } else {
+                Span::raw("0")
+            },
+        ]),
     ];
 
     let paragraph = Paragraph::new(lines).block(Block::default().borders(Borders::ALL));
     frame.render_widget(paragraph, area);
 }
 
+/// Status changes shown in the environment panel's history.
+const ENVIRONMENT_HISTORY_LINES: usize = 5;
+
+/// Snapshots shown in the environment panel, newest first.
+const ENVIRONMENT_SNAPSHOT_LINES: usize = 5;
+
+fn draw_environment_panel(
+    frame: &mut Frame,
+    app: &App,
+    env_id: &str,
+    disk_usage: &str,
+    area: Rect,
+) {
+    let label =
+        |text: &'static str| Span::styled(text, Style::default().add_modifier(Modifier::BOLD));
+    let Some(env) = app.find_environment(env_id) else {
+        let paragraph = Paragraph::new(" environment no longer exists")
+            .block(Block::default().borders(Borders::ALL));
+        frame.render_widget(paragraph, area);
+        return;
+    };
+
+    let mut lines = vec![
+        Line::from(vec![
+            label(" status:   "),
+            status_span(&env.status, 0),
+            Span::raw("   "),
+            label("provider: "),
+            Span::raw(env.provider.clone()),
+            Span::raw("   "),
+            label("project: "),
+            Span::raw(app.project_name(&env.project_id).to_string()),
+        ]),
+        Line::from(vec![
+            label(" disk:     "),
+            Span::raw(disk_usage.to_string()),
+        ]),
+    ];
+    if let Some(progress) = env.progress_label() {
+        lines.push(Line::from(vec![label(" progress: "), Span::raw(progress)]));
+    }
+
+    if let Some(metadata) = env.metadata.as_object() {
+        for (key, value) in metadata {
+            let value = match value {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            lines.push(Line::from(vec![
+                Span::styled(
+                    format!(" {key}: "),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(value),
+            ]));
+        }
+    }
+
+    let history = recent_environment_history(app);
+    if !history.is_empty() {
+        lines.push(Line::from(label(" history:")));
+        for change in history {
+            let mut spans = vec![Span::styled(
+                format!("   {} ", change.created_at),
+                Style::default().fg(Color::DarkGray),
+            )];
+            if let Some(previous) = &change.previous_status {
+                spans.push(status_span(previous, 0));
+                spans.push(Span::raw(" -> "));
+            }
+            spans.push(status_span(&change.status, 0));
+            if let Some(job_id) = &change.job_id {
+                spans.push(Span::styled(
+                    format!("  job {job_id}"),
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+            lines.push(Line::from(spans));
+        }
+    }
+
+    let snapshots = recent_environment_snapshots(app);
+    if !snapshots.is_empty() {
+        lines.push(Line::from(label(" snapshots:")));
+        for snapshot in snapshots {
+            let mut spans = vec![
+                Span::raw(format!("   {} ", snapshot.label())),
+                Span::styled(
+                    snapshot.created_at.clone(),
+                    Style::default().fg(Color::DarkGray),
+                ),
+            ];
+            if let Some(bytes) = snapshot.size_bytes {
+                spans.push(Span::raw(format!(
+                    "  {}",
+                    super::app::format_kb(bytes.div_ceil(1024))
+                )));
+            }
+            if let Some(restored_at) = &snapshot.restored_at {
+                spans.push(Span::styled(
+                    format!("  restored {restored_at}"),
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+            lines.push(Line::from(spans));
+        }
+    }
+
+    let paragraph = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!(" env {} ", env_label_by_id(app, env_id))),
+    );
+    frame.render_widget(paragraph, area);
+}
+
+/// The most recent status changes of the environment in the detail view.
+fn recent_environment_history(app: &App) -> &[EnvironmentStatusChange] {
+    let history = &app.environment_history;
+    &history[history.len().saturating_sub(ENVIRONMENT_HISTORY_LINES)..]
+}
+
+fn recent_environment_snapshots(app: &App) -> &[EnvironmentSnapshot] {
+    let snapshots = &app.environment_snapshots;
+    &snapshots[..snapshots.len().min(ENVIRONMENT_SNAPSHOT_LINES)]
+}
+
+fn environment_panel_height(app: &App, env_id: &str) -> u16 {
+    let metadata_lines = app
+        .find_environment(env_id)
+        .and_then(|e| e.metadata.as_object())
+        .map_or(0, |m| m.len());
+    let history_lines = match recent_environment_history(app).len() {
+        0 => 0,
+        n => n + 1,
+    };
+    let snapshot_lines = match recent_environment_snapshots(app).len() {
+        0 => 0,
+        n => n + 1,
+    };
+    // Status and disk rows, plus the top and bottom borders.
+    u16::try_from(metadata_lines + history_lines + snapshot_lines + 4).unwrap_or(u16::MAX)
+}
+
+/// The row a log section shows as: its name, and when folded, how much
+/// output it hides.
+fn log_section_heading(section: &LogSection, folded: bool, total_lines: usize) -> Line<'static> {
+    let style = Style::default().add_modifier(Modifier::BOLD);
+    if !folded {
+        return Line::from(Span::styled(format!("▼ {}", section.name), style));
+    }
+    let len = section.len(total_lines);
+    Line::from(vec![
+        Span::styled(format!("▶ {}", section.name), style),
+        Span::styled(
+            format!("  {len} {}", if len == 1 { "line" } else { "lines" }),
+            Style::default().fg(Color::DarkGray),
+        ),
+    ])
+}
+
 fn draw_log_view(frame: &mut Frame, app: &App, area: Rect) {
+    let area = match app.detail.as_ref() {
+        Some(DetailView::EnvironmentLog { env_id, disk_usage }) => {
+            let disk_usage = disk_usage
+                .lock()
+                .ok()
+                .and_then(|d| d.clone())
+                .unwrap_or_else(|| "measuring…".to_string());
+            let height = environment_panel_height(app, env_id).min(area.height / 2);
+            let chunks =
+                Layout::vertical([Constraint::Length(height), Constraint::Min(0)]).split(area);
+            draw_environment_panel(frame, app, env_id, &disk_usage, chunks[0]);
+            chunks[1]
+        }
+        // A one-line description already fits in the title.
+        Some(DetailView::TaskLog { task_id }) => match app
+            .tasks
+            .iter()
+            .find(|t| t.id == *task_id && t.has_long_description())
+        {
+            Some(task) => {
+                let text = super::markdown::to_text(&task.description);
+                let height = u16::try_from(text.lines.len() + 2)
+                    .unwrap_or(u16::MAX)
+                    .min(area.height / 3);
+                let chunks =
+                    Layout::vertical([Constraint::Length(height), Constraint::Min(0)]).split(area);
+                let panel = Paragraph::new(text)
+                    .block(
+                        Block::default()
+                            .borders(Borders::ALL)
+                            .title(" description "),
+                    )
+                    .wrap(Wrap { trim: false });
+                frame.render_widget(panel, chunks[0]);
+                chunks[1]
+            }
+            None => area,
+        },
+        None => area,
+    };
+
     let title = match app.detail.as_ref() {
         Some(DetailView::TaskLog { task_id }) => {
             let desc = app
                 .tasks
                 .iter()
                 .find(|t| t.id == *task_id)
-                .map(|t| t.description.as_str())
+                .map(Task::title)
                 .unwrap_or("");
             format!(" task {} - {desc} ", short_id(task_id))
         }
-        Some(DetailView::EnvironmentLog { env_id }) => {
+        Some(DetailView::EnvironmentLog { env_id, .. }) => {
             let provider = app
                 .environments
                 .iter()
                 .find(|e| e.id == *env_id)
                 .map(|e| e.provider.as_str())
                 .unwrap_or("-");
-            format!(" env {} - {provider} ", short_id(env_id))
+            format!(" env {} - {provider} ", env_label_by_id(app, env_id))
         }
         None => " logs ".to_string(),
     };
 
-    let log = Paragraph::new(app.log_content.as_str())
+    let total = app.log_lines.len();
+    let text: Vec<Line> = app
+        .log_rows()
+        .into_iter()
+        .map(|row| match row {
+            LogRow::Line(n) => app.log_lines.get(n).cloned().unwrap_or_default(),
+            LogRow::Section(i) => log_section_heading(
+                &app.log_sections[i],
+                app.is_log_section_folded(&app.log_sections[i]),
+                total,
+            ),
+        })
+        .collect();
+    let log = Paragraph::new(text)
         .block(Block::default().borders(Borders::ALL).title(title))
         .scroll((scroll_top_for_bottom_follow(app.log_scroll, area), 0))
         .wrap(Wrap { trim: false });
@@ -409,6 +1039,111 @@ fn draw_log_view(frame: &mut Frame, app: &App, area: Rect) {
     frame.render_widget(log, area);
 }
 
+fn draw_config_view(frame: &mut Frame, app: &App, area: Rect) {
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let dim = Style::default().fg(Color::DarkGray);
+    let ok = |text: String| Span::styled(format!("  ✓ {text}"), Style::default().fg(Color::Green));
+    let bad = |text: String| Span::styled(format!("  ✗ {text}"), Style::default().fg(Color::Red));
+    let warn = |text: &str| {
+        Line::from(Span::styled(
+            format!("    ! {text}"),
+            Style::default().fg(Color::Yellow),
+        ))
+    };
+
+    let Some(report) = &app.config_report else {
+        frame.render_widget(
+            Paragraph::new(" loading config…").block(Block::default().borders(Borders::ALL)),
+            area,
+        );
+        return;
+    };
+
+    let mut lines = vec![Line::from(vec![
+        Span::styled(" file: ", bold),
+        Span::raw(report.path.clone()),
+    ])];
+    if let Some(error) = &report.error {
+        lines.push(Line::from(bad(error.clone())));
+    }
+
+    let providers = |title: &'static str, checks: &[super::diagnostics::ProviderCheck]| {
+        let mut lines = vec![
+            Line::default(),
+            Line::from(Span::styled(format!(" {title}"), bold)),
+        ];
+        if checks.is_empty() {
+            lines.push(Line::from(Span::styled("  none configured", dim)));
+        }
+        for check in checks {
+            let name = if check.problem.is_some() {
+                bad(check.name.clone())
+            } else {
+                ok(check.name.clone())
+            };
+            lines.push(Line::from(vec![
+                name,
+                Span::styled(format!("  {}", check.detail), dim),
+            ]));
+            if let Some(problem) = &check.problem {
+                lines.push(warn(problem));
+            }
+        }
+        lines
+    };
+    lines.extend(providers("Task providers", &report.task_providers));
+    lines.extend(providers(
+        "Environment providers",
+        &report.environment_providers,
+    ));
+
+    lines.push(Line::default());
+    lines.push(Line::from(Span::styled(" Project defaults", bold)));
+    if report.projects.is_empty() {
+        lines.push(Line::from(Span::styled("  no projects", dim)));
+    }
+    for project in &report.projects {
+        let name = if project.problems.is_empty() {
+            ok(project.name.clone())
+        } else {
+            bad(project.name.clone())
+        };
+        lines.push(Line::from(vec![
+            name,
+            Span::styled(
+                format!(
+                    "  task: {}  env: {}",
+                    project.task_provider.as_deref().unwrap_or("-"),
+                    project.environment_provider.as_deref().unwrap_or("-")
+                ),
+                dim,
+            ),
+        ]));
+        for problem in &project.problems {
+            lines.push(warn(problem));
+        }
+    }
+
+    if !report.warnings.is_empty() {
+        lines.push(Line::default());
+        lines.push(Line::from(Span::styled(" Warnings", bold)));
+        for warning in &report.warnings {
+            lines.push(warn(warning));
+        }
+    }
+
+    let title = match report.problem_count() {
+        0 => " Config ".to_string(),
+        1 => " Config (1 problem) ".to_string(),
+        n => format!(" Config ({n} problems) "),
+    };
+    let scroll = app.config_scroll.min(lines.len().saturating_sub(1));
+    let paragraph = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .scroll((scroll as u16, 0));
+    frame.render_widget(paragraph, area);
+}
+
 fn draw_tui_logs_view(frame: &mut Frame, app: &App, area: Rect) {
     let log = Paragraph::new(app.tui_log_content.as_str())
         .block(Block::default().borders(Borders::ALL).title(" TUI Logs "))
@@ -443,7 +1178,7 @@ fn draw_create_task_prompt(frame: &mut Frame, app: &App) {
                 .add_modifier(Modifier::BOLD),
         )]),
         Line::from(vec![Span::styled(
-            "A new task prompt opens in $EDITOR after confirmation.",
+            "A new task prompt opens in your editor after confirmation.",
             Style::default().fg(Color::DarkGray),
         )]),
         Line::default(),
@@ -519,25 +1254,45 @@ fn draw_create_task_prompt(frame: &mut Frame, app: &App) {
 }
 
 fn draw_confirm_dialog(frame: &mut Frame, app: &App) {
+    let mut cascade_tasks = 0;
     let (target_label, target_value, skip_provider) = match app.confirm {
         Some(Confirm::Task {
             ref task_id,
             skip_provider,
         }) => ("Task", short_id(task_id).to_string(), skip_provider),
-        Some(Confirm::Project { ref project_name }) => ("Project", project_name.clone(), false),
+        Some(Confirm::Project {
+            ref project_name,
+            tasks,
+        }) => {
+            cascade_tasks = tasks;
+            ("Project", project_name.clone(), false)
+        }
+        Some(Confirm::Group {
+            ref group_id,
+            tasks,
+            skip_provider,
+        }) => {
+            cascade_tasks = tasks;
+            ("Group", short_id(group_id).to_string(), skip_provider)
+        }
         Some(Confirm::Environment {
             ref env_id,
             skip_provider,
-        }) => ("Environment", short_id(env_id).to_string(), skip_provider),
+        }) => (
+            "Environment",
+            env_label_by_id(app, env_id).to_string(),
+            skip_provider,
+        ),
         None => {
             return;
         }
     };
 
+    let cascade_lines = if cascade_tasks > 0 { 2 } else { 0 };
     let area = if skip_provider {
-        centered_rect(70, 11, frame.area())
+        centered_rect(70, 11 + cascade_lines, frame.area())
     } else {
-        centered_rect(62, 9, frame.area())
+        centered_rect(62, 9 + cascade_lines, frame.area())
     };
     frame.render_widget(Clear, area);
 
@@ -569,6 +1324,18 @@ fn draw_confirm_dialog(frame: &mut Frame, app: &App) {
         ]),
     ];
 
+    if cascade_tasks > 0 {
+        let tasks = match cascade_tasks {
+            1 => "its task".to_string(),
+            n => format!("all {n} of its tasks"),
+        };
+        body.push(Line::default());
+        body.push(Line::from(vec![Span::styled(
+            format!("This also removes {tasks} and their environments."),
+            Style::default().fg(Color::LightRed),
+        )]));
+    }
+
     if skip_provider {
         body.push(Line::default());
         body.push(Line::from(vec![Span::styled(
@@ -605,6 +1372,63 @@ fn draw_confirm_dialog(frame: &mut Frame, app: &App) {
     frame.render_widget(dialog, area);
 }
 
+/// Every key for what's on screen, as `[tui.keys]` left them.
+fn draw_help(frame: &mut Frame, app: &App) {
+    let context = if app.detail.is_some() {
+        Context::Detail
+    } else {
+        app.context()
+    };
+    let env_detail = matches!(app.detail, Some(DetailView::EnvironmentLog { .. }));
+    let rows: Vec<(String, &str)> = context
+        .actions()
+        .into_iter()
+        .filter(|action| {
+            env_detail
+                || !matches!(
+                    action,
+                    Action::UpdateEnvironment
+                        | Action::ClaimEnvironment
+                        | Action::RemoveEnvironment
+                        | Action::ForceRemoveEnvironment
+                )
+        })
+        .map(|action| (app.keys.describe(action), action.description()))
+        .collect();
+    let key_width = rows.iter().map(|(keys, _)| keys.len()).max().unwrap_or(0);
+
+    let mut lines: Vec<Line> = rows
+        .into_iter()
+        .map(|(keys, description)| {
+            Line::from(vec![
+                Span::styled(
+                    format!(" {keys:<key_width$}  "),
+                    Style::default()
+                        .fg(Color::White)
+                        .add_modifier(Modifier::BOLD),
+                ),
+                Span::styled(description, Style::default().fg(Color::Gray)),
+            ])
+        })
+        .collect();
+    lines.push(Line::default());
+    lines.push(Line::from(vec![Span::styled(
+        " Change these under [tui.keys] in config.toml. Press any key to close.",
+        Style::default().fg(Color::DarkGray),
+    )]));
+
+    let height = u16::try_from(lines.len() + 2).unwrap_or(u16::MAX);
+    let area = centered_rect(76, height, frame.area());
+    frame.render_widget(Clear, area);
+    let help = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(" Keys ")
+            .border_style(Style::default().fg(Color::Gray)),
+    );
+    frame.render_widget(help, area);
+}
+
 fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
     let x = area.x + area.width.saturating_sub(width) / 2;
     let y = area.y + area.height.saturating_sub(height) / 2;
@@ -33,6 +33,39 @@ pub fn wait_for_path_removed(path: &Path, timeout: Duration) -> bool {
     false
 }
 
+/// The records in `--format json` output, checking the envelope around them.
+pub fn json_items(stdout: &[u8]) -> Vec<serde_json::Value> {
+    let mut envelope: serde_json::Value = serde_json::from_slice(stdout).unwrap();
+    assert_eq!(
+        envelope["api_version"], 1,
+        "unexpected envelope: {envelope}"
+    );
+    match envelope["items"].take() {
+        serde_json::Value::Array(items) => items,
+        other => panic!("items is not a list: {other}"),
+    }
+}
+
+/// The record in `--format json` output from a command that shows one.
+pub fn json_item(stdout: &[u8]) -> serde_json::Value {
+    let mut items = json_items(stdout);
+    assert_eq!(items.len(), 1, "expected one item: {items:?}");
+    items.remove(0)
+}
+
+fn spawn_daemon(work_home: &Path, vars: &[(&str, &str)]) -> std::process::Child {
+    std::process::Command::new(work_bin())
+        .env("WORK_HOME", work_home)
+        .envs(vars.iter().copied())
+        .env_remove("XDG_DATA_HOME")
+        .env_remove("XDG_RUNTIME_DIR")
+        .args(["daemon", "start"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("failed to spawn daemon")
+}
+
 /// A running daemon process backed by a temporary directory.
 /// Sends SIGTERM and waits for exit on drop.
 pub struct DaemonFixture {
@@ -42,30 +75,41 @@ pub struct DaemonFixture {
 
 impl DaemonFixture {
     pub fn start() -> Self {
-        let tmp = TempDir::new().unwrap();
-
-        let child = std::process::Command::new(work_bin())
-            .env("WORK_HOME", tmp.path())
-            .env_remove("XDG_DATA_HOME")
-            .env_remove("XDG_RUNTIME_DIR")
-            .args(["daemon", "start"])
-            .stdout(std::process::Stdio::null())
-            .stderr(std::process::Stdio::null())
-            .spawn()
-            .expect("failed to spawn daemon");
+        Self::start_with_env(&[])
+    }
 
+    /// Start a daemon with extra environment variables, such as `WORK_CHAOS`.
+    pub fn start_with_env(vars: &[(&str, &str)]) -> Self {
+        let tmp = TempDir::new().unwrap();
+        let child = spawn_daemon(tmp.path(), vars);
         let fixture = Self {
             child,
             work_dir: tmp,
         };
+        fixture.wait_ready();
+        fixture
+    }
 
-        let sock = fixture.socket_path();
-        assert!(
-            wait_for_path(&sock, Duration::from_secs(5)),
-            "daemon socket not created"
-        );
+    /// Stop the daemon and start a new one on the same data, which runs
+    /// its startup passes, such as task retention, again.
+    pub fn restart(&mut self) {
+        let _ = signal::kill(Pid::from_raw(self.child.id() as i32), Signal::SIGTERM);
+        let _ = self.child.wait();
+        self.child = spawn_daemon(self.work_dir.path(), &[]);
+        self.wait_ready();
+    }
 
-        fixture
+    fn wait_ready(&self) {
+        // The socket appears before the job processor is running, so wait on
+        // the readiness probe rather than the socket file.
+        let ready = self
+            .cmd()
+            .args(["--wait-daemon", "5", "project", "list"])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .expect("failed to wait for daemon");
+        assert!(ready.success(), "daemon did not become ready");
     }
 
     pub fn socket_path(&self) -> std::path::PathBuf {
@@ -89,6 +133,18 @@ impl DaemonFixture {
         cmd
     }
 
+    /// Write config.toml and have the daemon reload it, since the daemon
+    /// keeps the config it accepted until then.
+    pub fn write_config(&self, contents: impl AsRef<str>) {
+        let config_dir = self.work_dir.path().join("config");
+        std::fs::create_dir_all(&config_dir).unwrap();
+        std::fs::write(config_dir.join("config.toml"), contents.as_ref()).unwrap();
+        self.assert_cmd()
+            .args(["daemon", "reload"])
+            .assert()
+            .success();
+    }
+
     /// Build an assert_cmd::Command that talks to this daemon's work home.
     pub fn assert_cmd(&self) -> assert_cmd::Command {
         let mut cmd = assert_cmd::Command::new(work_bin());
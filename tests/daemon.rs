@@ -9,7 +9,7 @@ use nix::unistd::Pid;
 use rusqlite::Connection;
 use tempfile::TempDir;
 
-use common::{DaemonFixture, wait_for_path, wait_for_path_removed};
+use common::{DaemonFixture, json_item, wait_for_path, wait_for_path_removed};
 
 fn work_bin() -> &'static str {
     env!("CARGO_BIN_EXE_work")
@@ -26,6 +26,24 @@ fn http_request(sock: &std::path::Path, request: &str) -> String {
     response
 }
 
+/// Send `request` and read the response until it contains `needle`, for
+/// streaming responses that don't end.
+fn http_read_until(sock: &std::path::Path, request: &str, needle: &str) -> String {
+    let mut stream = UnixStream::connect(sock).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    let mut buf = [0; 4096];
+    while !response.contains(needle) {
+        let n = stream.read(&mut buf).unwrap();
+        assert!(n > 0, "stream ended before {needle:?}: {response}");
+        response.push_str(&String::from_utf8_lossy(&buf[..n]));
+    }
+    response
+}
+
 // --- Lifecycle tests ---
 
 #[test]
@@ -135,9 +153,746 @@ fn api_health() {
     );
     assert!(resp.contains("200"), "expected 200, got: {resp}");
     assert!(
-        resp.contains(r#"{"status":"ok"}"#),
+        resp.contains(r#""status":"ok""#),
         "expected health JSON, got: {resp}"
     );
+    assert!(
+        resp.contains(&format!(r#""version":"{}""#, env!("CARGO_PKG_VERSION"))),
+        "expected daemon version, got: {resp}"
+    );
+    // Every response names the version, so clients need not ask for it.
+    assert!(
+        resp.contains(&format!(
+            "x-work-version: {}\r\n",
+            env!("CARGO_PKG_VERSION")
+        )),
+        "expected version header, got: {resp}"
+    );
+}
+
+#[test]
+fn api_ready() {
+    let d = DaemonFixture::start();
+    let resp = http_request(
+        &d.socket_path(),
+        "GET /ready HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    assert!(resp.contains("200"), "expected 200, got: {resp}");
+    assert!(
+        resp.contains(r#"{"status":"ready"}"#),
+        "expected ready JSON, got: {resp}"
+    );
+}
+
+#[test]
+fn api_serves_the_dashboard() {
+    let d = DaemonFixture::start();
+    let request = "GET /ui HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    let resp = http_request(&d.socket_path(), request);
+    assert!(resp.contains("404"), "expected 404, got: {resp}");
+
+    d.write_config("[daemon]\ndashboard = true\n");
+    let resp = http_request(&d.socket_path(), request);
+    assert!(resp.contains("200"), "expected 200, got: {resp}");
+    assert!(resp.contains("content-type: text/html"), "got: {resp}");
+    assert!(
+        resp.contains(r#"new EventSource("/events")"#),
+        "got: {resp}"
+    );
+}
+
+#[test]
+fn api_errors_carry_codes() {
+    let d = DaemonFixture::start();
+    let resp = http_request(
+        &d.socket_path(),
+        "GET /tasks/missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    assert!(resp.contains("404"), "expected 404, got: {resp}");
+    assert!(
+        resp.contains(r#""code":"task_not_found""#),
+        "expected task_not_found, got: {resp}"
+    );
+    assert!(
+        resp.contains(r#""details":{"id":"missing"}"#),
+        "expected id in details, got: {resp}"
+    );
+}
+
+#[test]
+fn api_create_task_rejects_providers_missing_from_daemon_config() {
+    let d = DaemonFixture::start();
+    let sock = d.socket_path();
+    let project_dir = TempDir::new().unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "p", "--path"])
+        .arg(project_dir.path())
+        .assert()
+        .success();
+    let resp = http_request(
+        &sock,
+        "GET /projects HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    let body = &resp[resp.find("\r\n\r\n").unwrap() + 4..];
+    let projects: serde_json::Value = serde_json::from_str(body).unwrap();
+    let project_id = projects[0]["id"].as_str().unwrap();
+
+    let create = |provider: &str, env_provider: &str| {
+        let body = serde_json::json!({
+            "project_id": project_id,
+            "provider": provider,
+            "env_provider": env_provider,
+            "description": "x",
+        })
+        .to_string();
+        http_request(
+            &sock,
+            &format!(
+                "POST /tasks HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            ),
+        )
+    };
+
+    let resp = create("ghost", "git-worktree");
+    assert!(resp.contains("422"), "expected 422, got: {resp}");
+    assert!(
+        resp.contains(r#""code":"unknown_provider""#),
+        "expected unknown_provider, got: {resp}"
+    );
+    assert!(resp.contains(r#""kind":"task""#), "got: {resp}");
+    assert!(resp.contains(r#""provider":"ghost""#), "got: {resp}");
+
+    let config_dir = d.work_dir.path().join("config");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(
+        config_dir.join("config.toml"),
+        "[tasks.providers.noop]\ntype = \"command\"\ncommand = \"true\"\n",
+    )
+    .unwrap();
+    // The daemon keeps the config it accepted until it is told to reload.
+    let resp = create("noop", "git-worktree");
+    assert!(resp.contains(r#""provider":"noop""#), "got: {resp}");
+    d.assert_cmd().args(["daemon", "reload"]).assert().success();
+    let resp = create("noop", "ghost-env");
+    assert!(resp.contains("422"), "expected 422, got: {resp}");
+    assert!(resp.contains(r#""kind":"environment""#), "got: {resp}");
+    assert!(resp.contains(r#""provider":"ghost-env""#), "got: {resp}");
+}
+
+#[test]
+fn api_pages_through_tasks_by_cursor() {
+    let d = DaemonFixture::start();
+    let sock = d.socket_path();
+    d.write_config("[tasks.providers.fake]\ntype = \"mock\"\n\n[environments.providers.sandbox]\ntype = \"mock\"\n");
+    let project_dir = TempDir::new().unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "p", "--path"])
+        .arg(project_dir.path())
+        .assert()
+        .success();
+    for description in ["one", "two", "three", "four", "five"] {
+        d.assert_cmd()
+            .args(["task", "new", description, "--project", "p"])
+            .args(["--provider", "fake", "--env-provider", "sandbox"])
+            .assert()
+            .success();
+    }
+
+    let get = |query: &str| {
+        let resp = http_request(
+            &sock,
+            &format!("GET /tasks{query} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"),
+        );
+        let body = resp[resp.find("\r\n\r\n").unwrap() + 4..].to_string();
+        (resp, body)
+    };
+    let ids = |body: &str| -> Vec<String> {
+        let tasks: serde_json::Value = serde_json::from_str(body).unwrap();
+        tasks
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["id"].as_str().unwrap().to_string())
+            .collect()
+    };
+    let cursor_after = |body: &str| {
+        let tasks: serde_json::Value = serde_json::from_str(body).unwrap();
+        let last = tasks.as_array().unwrap().last().unwrap().clone();
+        let cursor = format!(
+            "{}/{}",
+            last["created_at"].as_str().unwrap(),
+            last["id"].as_str().unwrap()
+        );
+        cursor
+            .bytes()
+            .map(|b| {
+                if b.is_ascii_alphanumeric() {
+                    (b as char).to_string()
+                } else {
+                    format!("%{b:02X}")
+                }
+            })
+            .collect::<String>()
+    };
+
+    let (_, everything) = get("");
+    let all = ids(&everything);
+    assert_eq!(all.len(), 5);
+
+    let mut paged = Vec::new();
+    let (_, mut body) = get("?limit=2");
+    loop {
+        let page = ids(&body);
+        assert!(page.len() <= 2, "page too long: {body}");
+        paged.extend(page.clone());
+        if page.len() < 2 {
+            break;
+        }
+        body = get(&format!("?limit=2&cursor={}", cursor_after(&body))).1;
+    }
+    assert_eq!(paged, all);
+
+    let resp = http_request(
+        &sock,
+        "GET /tasks/counts HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    let counts: serde_json::Value =
+        serde_json::from_str(&resp[resp.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+    assert_eq!(counts["total"], 5);
+    assert_eq!(counts["by_project"].as_object().unwrap().len(), 1);
+
+    let (resp, _) = get("?cursor=nonsense");
+    assert!(resp.contains("400"), "expected 400, got: {resp}");
+    assert!(resp.contains(r#""code":"invalid_request""#), "got: {resp}");
+
+    // Other orders and filters are applied before paging, and page by
+    // offset.
+    let tasks = |body: &str| -> Vec<serde_json::Value> {
+        serde_json::from_str::<serde_json::Value>(body)
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .clone()
+    };
+    // Once they've settled, so counts and pages agree.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while tasks(&get("").1)
+        .iter()
+        .any(|t| t["status"] == "pending" || t["status"] == "started")
+    {
+        assert!(Instant::now() < deadline, "tasks never finished");
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    let resp = http_request(
+        &sock,
+        "GET /tasks/counts HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    let counts: serde_json::Value =
+        serde_json::from_str(&resp[resp.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+    let rank = |task: &serde_json::Value| {
+        [
+            "started",
+            "pending",
+            "failed",
+            "cancelled",
+            "timed_out",
+            "interrupted",
+            "complete",
+        ]
+        .iter()
+        .position(|s| task["status"] == *s)
+        .unwrap()
+    };
+    let by_status = tasks(&get("?sort=status").1);
+    assert_eq!(by_status.len(), 5);
+    assert!(
+        by_status.windows(2).all(|w| rank(&w[0]) <= rank(&w[1])),
+        "{by_status:?}"
+    );
+    let mut paged = Vec::new();
+    for offset in [0, 2, 4] {
+        let page = tasks(&get(&format!("?sort=status&limit=2&offset={offset}")).1);
+        paged.extend(page.iter().map(|t| t["id"].clone()));
+    }
+    let ids_by_status: Vec<_> = by_status.iter().map(|t| t["id"].clone()).collect();
+    assert_eq!(paged, ids_by_status);
+    let (resp, _) = get(&format!(
+        "?sort=status&cursor={}",
+        cursor_after(&everything)
+    ));
+    assert!(resp.contains("400"), "expected 400, got: {resp}");
+
+    let status = by_status[0]["status"].as_str().unwrap();
+    let matching = tasks(&get(&format!("?status={status},nonsense&project=p")).1);
+    assert!(!matching.is_empty());
+    assert!(matching.iter().all(|t| t["status"] == status));
+    let project_id = by_status[0]["project_id"].as_str().unwrap();
+    assert_eq!(
+        counts["by_status"][project_id][status].as_u64().unwrap() as usize,
+        matching.len()
+    );
+    assert!(tasks(&get("?project=elsewhere").1).is_empty());
+
+    let run_ms = |task: &serde_json::Value| task["resource_usage"]["wall_ms"].as_u64();
+    let by_duration = tasks(&get("?sort=duration").1);
+    assert_eq!(by_duration.len(), 5);
+    let finished: Vec<_> = by_duration
+        .iter()
+        .filter(|t| t["status"] != "started")
+        .map(run_ms)
+        .collect();
+    assert!(
+        finished.windows(2).all(|w| w[1].is_none() || w[0] >= w[1]),
+        "{by_duration:?}"
+    );
+}
+
+#[test]
+fn api_reads_environment_logs_and_disk_usage_without_following() {
+    let d = DaemonFixture::start();
+    let sock = d.socket_path();
+    d.write_config("[environments.providers.sandbox]\ntype = \"mock\"\n");
+    let project_dir = TempDir::new().unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "p", "--path"])
+        .arg(project_dir.path())
+        .assert()
+        .success();
+    let out = d
+        .assert_cmd()
+        .args([
+            "env",
+            "prepare",
+            "p",
+            "--provider",
+            "sandbox",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let env_id = json_item(&out)["id"].as_str().unwrap().to_string();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let out = d
+            .assert_cmd()
+            .args(["env", "show", &env_id, "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        if json_item(&out)["status"] == "pool" {
+            break;
+        }
+        assert!(Instant::now() < deadline, "environment never became pool");
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    // Without follow=false this would stream until the environment is gone.
+    let resp = http_request(
+        &sock,
+        &format!(
+            "GET /environments/{env_id}/logs?follow=false HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        ),
+    );
+    assert!(resp.contains("200 OK"), "expected 200, got: {resp}");
+    assert!(resp.contains("mock: prepare"), "got: {resp}");
+
+    let resp = http_request(
+        &sock,
+        &format!(
+            "GET /environments/{env_id}/disk-usage HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        ),
+    );
+    assert!(resp.contains("200 OK"), "expected 200, got: {resp}");
+    assert!(resp.contains(r#""kb":"#), "got: {resp}");
+}
+
+#[test]
+fn tasks_record_the_client_that_created_them() {
+    let d = DaemonFixture::start();
+    let sock = d.socket_path();
+    d.write_config("[tasks.providers.fake]\ntype = \"mock\"\n\n[environments.providers.sandbox]\ntype = \"mock\"\n");
+    let project_dir = TempDir::new().unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "p", "--path"])
+        .arg(project_dir.path())
+        .assert()
+        .success();
+
+    let output = d
+        .assert_cmd()
+        .current_dir(project_dir.path())
+        .env("USER", "jo")
+        .args([
+            "task",
+            "new",
+            "from the cli",
+            "--project",
+            "p",
+            "--format",
+            "json",
+        ])
+        .args(["--provider", "fake", "--env-provider", "sandbox"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&output);
+    // The user and directory are the process's own, whatever its
+    // environment claims.
+    let user = std::process::Command::new("id")
+        .arg("-un")
+        .output()
+        .unwrap();
+    let user = String::from_utf8(user.stdout).unwrap().trim().to_string();
+    assert_eq!(task["origin"]["client"], "cli", "{task}");
+    assert_eq!(task["origin"]["user"], user.as_str(), "{task}");
+    let cwd = std::fs::canonicalize(project_dir.path()).unwrap();
+    assert_eq!(task["origin"]["cwd"], cwd.to_str().unwrap(), "{task}");
+
+    let output = d
+        .assert_cmd()
+        .args(["task", "show", task["id"].as_str().unwrap()])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let shown = String::from_utf8(output).unwrap();
+    assert!(shown.contains("created by:"), "{shown}");
+    assert!(shown.contains(&format!("cli by {user}@")), "{shown}");
+
+    let output = d
+        .assert_cmd()
+        .args(["env", "list", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let envs = common::json_items(&output);
+    assert_eq!(envs[0]["origin"]["client"], "cli", "{envs:?}");
+
+    // A request that doesn't say which client it's from is from the API,
+    // and headers can't claim someone else made it.
+    let body = serde_json::json!({
+        "project_id": task["project_id"],
+        "provider": "fake",
+        "env_provider": "sandbox",
+        "description": "from the api",
+    })
+    .to_string();
+    let resp = http_request(
+        &sock,
+        &format!(
+            "POST /tasks HTTP/1.1\r\nHost: localhost\r\nX-Work-User: mallory\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        ),
+    );
+    let created: serde_json::Value =
+        serde_json::from_str(&resp[resp.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+    assert_eq!(created["origin"]["client"], "api", "{created}");
+    assert_eq!(created["origin"]["user"], user.as_str(), "{created}");
+}
+
+#[test]
+fn daemon_proxy_relays_stdio_to_socket() {
+    let d = DaemonFixture::start();
+    let out = d
+        .assert_cmd()
+        .args(["daemon", "proxy"])
+        .write_stdin("GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .timeout(Duration::from_secs(10))
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let resp = String::from_utf8_lossy(&out);
+    assert!(resp.contains("200"), "expected 200, got: {resp}");
+    assert!(
+        resp.contains(r#""status":"ok""#),
+        "expected health JSON, got: {resp}"
+    );
+
+    // A remote daemon is started on its own machine, never through --host.
+    let out = d
+        .assert_cmd()
+        .args(["--host", "devbox", "daemon", "start"])
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .clone();
+    assert!(String::from_utf8_lossy(&out).contains("start or install it on the host itself"));
+}
+
+#[test]
+fn config_reload_reports_changes_and_rejects_problems() {
+    let d = DaemonFixture::start();
+    let config_path = d.work_dir.path().join("config/config.toml");
+    std::fs::create_dir_all(config_path.parent().unwrap()).unwrap();
+    std::fs::write(&config_path, "[daemon.rate-limits.git-worktree]\nmax = 2\n").unwrap();
+
+    let out = d
+        .assert_cmd()
+        .args(["daemon", "reload", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let summary = json_item(&out);
+    assert_eq!(
+        summary["changes"],
+        serde_json::json!([{
+            "setting": "daemon.rate-limits.git-worktree.max",
+            "old": null,
+            "new": "2",
+        }])
+    );
+
+    // SIGHUP reloads in place rather than stopping the daemon.
+    signal::kill(Pid::from_raw(d.pid()), Signal::SIGHUP).unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    let resp = http_request(
+        &d.socket_path(),
+        "GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    assert!(resp.contains("200"), "expected 200, got: {resp}");
+
+    std::fs::write(
+        &config_path,
+        "[daemon.rate-limits.git-worktree]\nmax = 2\nper = \"soon\"\n",
+    )
+    .unwrap();
+    let resp = http_request(
+        &d.socket_path(),
+        "POST /config/reload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+    );
+    assert!(resp.contains("400"), "expected 400, got: {resp}");
+    assert!(
+        resp.contains(r#""code":"invalid_config""#),
+        "expected invalid_config, got: {resp}"
+    );
+}
+
+#[test]
+fn requests_are_logged_counted_and_rate_limited_per_peer() {
+    let d = DaemonFixture::start();
+    let get = |path: &str| {
+        http_request(
+            &d.socket_path(),
+            &format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"),
+        )
+    };
+
+    let resp = get("/projects");
+    assert!(resp.contains("200 OK"), "expected 200, got: {resp}");
+    let out = d
+        .assert_cmd()
+        .args(["daemon", "peers", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let peers = common::json_items(&out);
+    let uid = std::os::unix::fs::MetadataExt::uid(&std::fs::metadata(&d.work_dir).unwrap());
+    // This test and the CLI it ran are separate processes, so separate peers.
+    let key = format!("uid:{uid}/pid:{}", std::process::id());
+    assert!(peers.len() >= 2, "{peers:?}");
+    let this = peers.iter().find(|p| p["peer"] == key.as_str()).unwrap();
+    assert_eq!(this["requests"], 1);
+    assert_eq!(this["rate_limited"], 0);
+
+    let config_path = d.work_dir.path().join("config/config.toml");
+    std::fs::create_dir_all(config_path.parent().unwrap()).unwrap();
+    std::fs::write(
+        &config_path,
+        "[daemon.request-rate-limit]\nmax = 2\nper = \"1h\"\n",
+    )
+    .unwrap();
+    d.assert_cmd().args(["daemon", "reload"]).assert().success();
+
+    assert!(get("/projects").contains("200 OK"));
+    assert!(get("/tasks").contains("200 OK"));
+    let resp = get("/projects");
+    assert!(resp.contains("429"), "expected 429, got: {resp}");
+    assert!(resp.contains(r#""code":"rate_limited""#), "{resp}");
+    assert!(resp.to_lowercase().contains("retry-after:"), "{resp}");
+    // Waiting for the daemon isn't held up by the limit.
+    assert!(get("/ready").contains("200 OK"));
+
+    // The log is written off the request path, so it can trail a little.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let entries = loop {
+        let log =
+            std::fs::read_to_string(d.work_dir.path().join("state/access.log")).unwrap_or_default();
+        let entries: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        if entries.iter().any(|e| e["path"] == "/ready") || Instant::now() > deadline {
+            break entries;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    let limited = entries
+        .iter()
+        .find(|e| e["status"] == 429)
+        .unwrap_or_else(|| panic!("no rate limited request logged: {entries:?}"));
+    assert_eq!(limited["peer"], key);
+    assert_eq!(limited["method"], "GET");
+    assert_eq!(limited["path"], "/projects");
+    assert_eq!(limited["client"], "api");
+    assert!(
+        entries
+            .iter()
+            .any(|e| e["path"] == "/peers" && e["client"] == "cli"),
+        "{entries:?}"
+    );
+}
+
+#[test]
+fn daemon_refuses_to_start_with_a_bad_chaos_rule() {
+    let tmp = TempDir::new().unwrap();
+
+    let output = std::process::Command::new(work_bin())
+        .env("WORK_HOME", tmp.path())
+        .env("WORK_CHAOS", "run_task:often")
+        .env_remove("XDG_DATA_HOME")
+        .env_remove("XDG_RUNTIME_DIR")
+        .args(["daemon", "start"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("needs a rate from 0 to 1"),
+        "stderr: {stderr}"
+    );
+    assert!(!tmp.path().join("runtime/work.sock").exists());
+}
+
+#[test]
+fn daemon_start_wait_ready_backgrounds_daemon() {
+    let tmp = TempDir::new().unwrap();
+
+    let output = std::process::Command::new(work_bin())
+        .env("WORK_HOME", tmp.path())
+        .env_remove("XDG_DATA_HOME")
+        .env_remove("XDG_RUNTIME_DIR")
+        .args(["daemon", "start", "--wait-ready"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("daemon ready"));
+
+    let sock = tmp.path().join("runtime/work.sock");
+    let pid: i32 = std::fs::read_to_string(tmp.path().join("runtime/work.pid"))
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    let resp = http_request(
+        &sock,
+        "GET /ready HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    assert!(resp.contains("200"), "expected 200, got: {resp}");
+
+    signal::kill(Pid::from_raw(pid), Signal::SIGTERM).unwrap();
+    assert!(
+        wait_for_path_removed(&sock, Duration::from_secs(5)),
+        "background daemon did not shut down"
+    );
+}
+
+#[test]
+fn daemon_start_wait_ready_stops_a_daemon_that_is_not_ready_in_time() {
+    let tmp = TempDir::new().unwrap();
+
+    let output = std::process::Command::new(work_bin())
+        .env("WORK_HOME", tmp.path())
+        .env_remove("XDG_DATA_HOME")
+        .env_remove("XDG_RUNTIME_DIR")
+        .args(["--wait-daemon", "0", "daemon", "start", "--wait-ready"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("not ready"),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // The daemon that was started is stopped rather than left to come up
+    // after the command gave up on it.
+    assert!(!tmp.path().join("runtime/work.sock").exists());
+    assert!(!tmp.path().join("runtime/work.pid").exists());
+}
+
+#[test]
+fn auto_started_daemon_exits_when_idle() {
+    let tmp = TempDir::new().unwrap();
+    std::fs::create_dir_all(tmp.path().join("config")).unwrap();
+    std::fs::write(
+        tmp.path().join("config/config.toml"),
+        "auto-start-daemon = true\n\n[daemon]\nidle-timeout = \"1s\"\n",
+    )
+    .unwrap();
+    let sock = tmp.path().join("runtime/work.sock");
+    let list_projects = || {
+        let output = std::process::Command::new(work_bin())
+            .env("WORK_HOME", tmp.path())
+            .env_remove("XDG_DATA_HOME")
+            .env_remove("XDG_RUNTIME_DIR")
+            .args(["project", "list"])
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "stderr: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        // Started on demand rather than read from the database.
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("the daemon is not running; starting it"),
+            "{stderr}"
+        );
+        assert!(stderr.contains("daemon started (pid "), "{stderr}");
+        assert!(!stderr.contains("read-only"), "{stderr}");
+    };
+
+    list_projects();
+    assert!(sock.exists(), "command did not start the daemon");
+
+    // A connection held open keeps it running past the timeout.
+    let held = UnixStream::connect(&sock).unwrap();
+    std::thread::sleep(Duration::from_secs(3));
+    assert!(sock.exists(), "daemon exited with a connection open");
+    drop(held);
+
+    assert!(
+        wait_for_path_removed(&sock, Duration::from_secs(10)),
+        "idle daemon did not shut down"
+    );
+
+    // The next command starts it again, and it exits again once idle.
+    list_projects();
+    assert!(wait_for_path_removed(&sock, Duration::from_secs(10)));
 }
 
 #[test]
@@ -176,7 +931,11 @@ fn api_projects_crud() {
         &sock,
         "DELETE /projects/myproj HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
     );
-    assert!(resp.contains("204"), "expected 204, got: {resp}");
+    assert!(resp.contains("200"), "expected 200, got: {resp}");
+    assert!(
+        resp.contains(r#""removed":true"#),
+        "expected removal summary, got: {resp}"
+    );
 
     // List should be empty again.
     let resp = http_request(
@@ -278,7 +1037,7 @@ fn running_job_with_expired_lease_is_reclaimed() {
             .unwrap();
         drop(conn);
 
-        if status == "failed" {
+        if status == "dead" {
             break;
         }
 
@@ -288,3 +1047,393 @@ fn running_job_with_expired_lease_is_reclaimed() {
         std::thread::sleep(Duration::from_millis(100));
     }
 }
+
+#[test]
+fn state_changes_are_recorded_as_events() {
+    let d = DaemonFixture::start();
+    let project_dir = TempDir::new().unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "evented"])
+        .arg("--path")
+        .arg(project_dir.path())
+        .assert()
+        .success();
+    d.assert_cmd()
+        .args(["project", "rm", "evented"])
+        .assert()
+        .success();
+
+    let output = d
+        .cmd()
+        .args(["events", "--format", "plain"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let actions: Vec<(&str, &str)> = stdout
+        .lines()
+        .map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            (fields[2], fields[4])
+        })
+        .collect();
+    assert_eq!(actions, [("project", "created"), ("project", "removed")]);
+
+    // History after a sequence number skips what the caller has seen.
+    let resp = http_request(
+        &d.socket_path(),
+        "GET /events/history?since=1 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    assert!(resp.contains("\"removed\""), "got: {resp}");
+    assert!(!resp.contains("\"created\""), "got: {resp}");
+
+    // A stream resumed from a cursor replays the events after it.
+    let resp = http_read_until(
+        &d.socket_path(),
+        "GET /events HTTP/1.1\r\nHost: localhost\r\nLast-Event-ID: 1\r\n\r\n",
+        "\"removed\"",
+    );
+    assert!(resp.contains("event: resume\nid: 1\n"), "got: {resp}");
+    assert!(!resp.contains("\"created\""), "got: {resp}");
+
+    // One from a cursor the daemon never issued starts over.
+    let resp = http_read_until(
+        &d.socket_path(),
+        "GET /events?since=99 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        "\n\n",
+    );
+    assert!(resp.contains("event: reset\nid: 2\n"), "got: {resp}");
+}
+
+#[test]
+fn shutdown_requeues_jobs_that_outlast_the_grace_period() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let d = DaemonFixture::start();
+    let script = d.work_dir.path().join("slow-notify.sh");
+    std::fs::write(&script, "#!/bin/sh\nsleep 30\n").unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    d.write_config(
+        format!(
+            "[daemon]\nshutdown-grace = \"1s\"\n\n[notifications.providers.slow]\ntype = \"script\"\npath = \"{}\"\nevents = [\"project.created\"]\n",
+            script.display()
+        ),
+    );
+
+    let project_dir = TempDir::new().unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "graceful"])
+        .arg("--path")
+        .arg(project_dir.path())
+        .assert()
+        .success();
+
+    let conn = Connection::open(d.work_dir.path().join("data/database.sqlite3")).unwrap();
+    conn.busy_timeout(Duration::from_secs(5)).unwrap();
+    let job = || -> Option<(String, i64, Option<String>)> {
+        conn.query_row(
+            "SELECT status, attempt, last_error FROM jobs WHERE type = 'notify'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .ok()
+    };
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while job().is_none_or(|(status, _, _)| status != "running") {
+        assert!(Instant::now() < deadline, "notify job never started");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    let stopping = Instant::now();
+    signal::kill(Pid::from_raw(d.pid()), Signal::SIGTERM).unwrap();
+    assert!(
+        wait_for_path_removed(&d.socket_path(), Duration::from_secs(10)),
+        "daemon did not shut down"
+    );
+    assert!(stopping.elapsed() < Duration::from_secs(10));
+
+    let (status, attempt, last_error) = job().unwrap();
+    assert_eq!(status, "pending");
+    assert_eq!(attempt, 0);
+    assert_eq!(
+        last_error.as_deref(),
+        Some("daemon stopped before the job finished")
+    );
+}
+
+#[test]
+fn shutdown_interrupts_tasks_that_outlast_the_grace_period() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let trace_dir = TempDir::new().unwrap();
+    let trace = trace_dir.path().join("trace.jsonl");
+    let d = DaemonFixture::start_with_env(&[("WORK_TRACE", trace.to_str().unwrap())]);
+    let out = d.work_dir.path().join("agent-out");
+    std::fs::create_dir(&out).unwrap();
+    let agent = d.work_dir.path().join("agent.sh");
+    std::fs::write(
+        &agent,
+        format!(
+            "#!/bin/sh\necho $$ > \"{}/pid\"\nexec sleep 30\n",
+            out.display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&agent, std::fs::Permissions::from_mode(0o755)).unwrap();
+    d.write_config(format!(
+        "[daemon]\nshutdown-grace = \"1s\"\n\n[tasks.providers.agent]\ntype = \"command\"\ncommand = \"{}\"\ncancel = {{ grace = \"1s\" }}\n\n[environments.providers.sandbox]\ntype = \"mock\"\n",
+        agent.display()
+    ));
+    let project_dir = TempDir::new().unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "graceful", "--path"])
+        .arg(project_dir.path())
+        .assert()
+        .success();
+    let created = d
+        .assert_cmd()
+        .args(["task", "new", "long job", "--project", "graceful"])
+        .args(["--provider", "agent", "--env-provider", "sandbox"])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task_id = json_item(&created)["id"].as_str().unwrap().to_string();
+    assert!(wait_for_path(&out.join("pid"), Duration::from_secs(20)));
+    let runner: i32 = std::fs::read_to_string(out.join("pid"))
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+
+    signal::kill(Pid::from_raw(d.pid()), Signal::SIGTERM).unwrap();
+    assert!(
+        wait_for_path_removed(&d.socket_path(), Duration::from_secs(15)),
+        "daemon did not shut down"
+    );
+
+    // The runner is stopped with the daemon rather than left to a requeued
+    // job that could never start it again.
+    assert!(
+        signal::kill(Pid::from_raw(runner), None).is_err(),
+        "runner outlived the daemon"
+    );
+    let conn = Connection::open(d.work_dir.path().join("data/database.sqlite3")).unwrap();
+    conn.busy_timeout(Duration::from_secs(5)).unwrap();
+    let status: String = conn
+        .query_row(
+            "SELECT status FROM tasks WHERE id = ?1",
+            [&task_id],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(status, "interrupted");
+    let job: String = conn
+        .query_row(
+            "SELECT status FROM jobs WHERE type = 'run_task' AND json_extract(payload, '$.task_id') = ?1",
+            [&task_id],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(job, "complete");
+
+    // A job trace says so, rather than leaving the job claimed forever.
+    let trace = std::fs::read_to_string(&trace).unwrap();
+    let interrupted = trace
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .filter(|r| r["event"] == "finish" && r["outcome"] == "interrupted")
+        .count();
+    assert_eq!(interrupted, 1, "{trace}");
+}
+
+#[test]
+fn list_and_show_read_the_database_while_the_daemon_is_stopped() {
+    let d = DaemonFixture::start();
+    let project_dir = TempDir::new().unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "offline"])
+        .arg("--path")
+        .arg(project_dir.path())
+        .assert()
+        .success();
+
+    signal::kill(Pid::from_raw(d.pid()), Signal::SIGTERM).unwrap();
+    assert!(
+        wait_for_path_removed(&d.socket_path(), Duration::from_secs(5)),
+        "daemon did not shut down"
+    );
+
+    let output = d
+        .cmd()
+        .args(["project", "list", "--format", "plain"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("offline\t"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("read-only"));
+
+    d.assert_cmd()
+        .args(["task", "show", "missing"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("task not found: missing"));
+
+    // Mutations still need the daemon.
+    d.assert_cmd()
+        .args(["project", "rm", "offline"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("work daemon start"));
+}
+
+#[test]
+fn profile_runs_its_own_daemon_and_data() {
+    let d = DaemonFixture::start();
+    let project_dir = TempDir::new().unwrap();
+
+    d.assert_cmd()
+        .args(["--profile", "sandbox", "daemon", "start", "--wait-ready"])
+        .assert()
+        .success();
+    let sandbox_home = d.work_dir.path().join("data/profiles/sandbox");
+    let pid: i32 = std::fs::read_to_string(sandbox_home.join("runtime/work.pid"))
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+
+    d.assert_cmd()
+        .args(["--profile", "sandbox", "project", "new", "risky"])
+        .arg("--path")
+        .arg(project_dir.path())
+        .assert()
+        .success();
+    d.assert_cmd()
+        .args(["project", "list", "--format", "plain"])
+        .assert()
+        .success()
+        .stdout("");
+
+    let out = d
+        .assert_cmd()
+        .args([
+            "--profile",
+            "sandbox",
+            "profile",
+            "list",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let profiles = common::json_items(&out);
+    let summary: Vec<_> = profiles
+        .iter()
+        .map(|p| {
+            (
+                p["name"].as_str().unwrap(),
+                p["current"].as_bool().unwrap(),
+                p["daemon"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [("default", false, "running"), ("sandbox", true, "running")]
+    );
+
+    d.assert_cmd()
+        .args(["--profile", "../escape", "project", "list"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("invalid profile name"));
+
+    let sock = sandbox_home.join("runtime/work.sock");
+    signal::kill(Pid::from_raw(pid), Signal::SIGTERM).unwrap();
+    assert!(
+        wait_for_path_removed(&sock, Duration::from_secs(5)),
+        "sandbox daemon did not shut down"
+    );
+}
+
+#[test]
+fn doctor_reports_each_check_and_fails_on_unusable_provider_scripts() {
+    let d = DaemonFixture::start();
+
+    let out = d
+        .assert_cmd()
+        .args(["doctor", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let checks = common::json_items(&out);
+    let status = |name: &str| {
+        checks
+            .iter()
+            .find(|c| c["name"] == name)
+            .unwrap_or_else(|| panic!("no {name} check: {checks:?}"))["status"]
+            .clone()
+    };
+    assert_eq!(status("daemon"), "pass");
+    assert_eq!(status("database"), "pass");
+    assert_eq!(status("config"), "pass");
+    assert_eq!(status("directory"), "pass");
+
+    let script = d.work_dir.path().join("provider.sh");
+    std::fs::write(&script, "#!/bin/sh\n").unwrap();
+    let config_dir = d.work_dir.path().join("config");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(
+        config_dir.join("config.toml"),
+        format!(
+            "[environments.providers.sandbox]\ntype = \"script\"\npath = \"{}\"\n",
+            script.display()
+        ),
+    )
+    .unwrap();
+
+    let out = d
+        .assert_cmd()
+        .args(["doctor", "--format", "json"])
+        .assert()
+        .failure()
+        .get_output()
+        .stdout
+        .clone();
+    let checks = common::json_items(&out);
+    let provider = checks.iter().find(|c| c["name"] == "provider").unwrap();
+    assert_eq!(provider["status"], "fail");
+    assert_eq!(
+        provider["hint"].as_str(),
+        Some(format!("chmod +x {}", script.display()).as_str())
+    );
+    let config = checks.iter().find(|c| c["name"] == "config").unwrap();
+    assert_eq!(config["status"], "pass", "script problem reported twice");
+
+    // A config that doesn't parse is reported rather than stopping the
+    // doctor before it starts.
+    std::fs::write(config_dir.join("config.toml"), "not = [valid\n").unwrap();
+    let out = d
+        .assert_cmd()
+        .args(["doctor", "--format", "json"])
+        .assert()
+        .failure()
+        .get_output()
+        .stdout
+        .clone();
+    let checks = common::json_items(&out);
+    let config = checks.iter().find(|c| c["name"] == "config").unwrap();
+    assert_eq!(config["status"], "fail", "{checks:?}");
+}
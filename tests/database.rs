@@ -5,7 +5,7 @@ use std::{path::Path, thread};
 
 use predicates::prelude::*;
 
-use common::DaemonFixture;
+use common::{DaemonFixture, json_item, json_items};
 
 fn write_executable_script(path: &Path, contents: &str) {
     std::fs::write(path, contents).unwrap();
@@ -29,7 +29,7 @@ fn wait_for_env_status(d: &DaemonFixture, env_id: &str, expected_status: &str, t
             .get_output()
             .stdout
             .clone();
-        let envs: Vec<serde_json::Value> = serde_json::from_slice(&env_list_out).unwrap();
+        let envs = json_items(&env_list_out);
         if let Some(env) = envs
             .iter()
             .find(|candidate| candidate["id"].as_str() == Some(env_id))
@@ -56,7 +56,7 @@ fn wait_for_task_terminal_status(d: &DaemonFixture, task_id: &str, timeout: Dura
             .get_output()
             .stdout
             .clone();
-        let tasks: Vec<serde_json::Value> = serde_json::from_slice(&task_list_out).unwrap();
+        let tasks = json_items(&task_list_out);
         let status = tasks
             .iter()
             .find(|candidate| candidate["id"].as_str() == Some(task_id))
@@ -64,7 +64,7 @@ fn wait_for_task_terminal_status(d: &DaemonFixture, task_id: &str, timeout: Dura
             .unwrap_or_default()
             .to_string();
 
-        if status == "complete" || status == "failed" {
+        if work::db::TERMINAL_TASK_STATUSES.contains(&status.as_str()) {
             return status;
         }
 
@@ -122,7 +122,22 @@ fn reset_database_clears_data() {
         .assert()
         .success();
 
-    d.assert_cmd().arg("reset-database").assert().success();
+    // Without a terminal to type the confirmation into, --yes is required.
+    d.assert_cmd()
+        .arg("reset-database")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--yes"));
+    d.assert_cmd()
+        .args(["project", "list", "--format", "plain"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("test-proj"));
+
+    d.assert_cmd()
+        .args(["reset-database", "--yes"])
+        .assert()
+        .success();
 
     d.assert_cmd()
         .args(["project", "list"])
@@ -131,6 +146,35 @@ fn reset_database_clears_data() {
         .stdout(predicate::str::is_empty());
 }
 
+#[test]
+fn lists_kept_in_memory_see_changes_made_outside_the_daemon() {
+    let d = DaemonFixture::start();
+    let proj = d.work_dir.path().join("cached-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "cached-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+    d.assert_cmd()
+        .args(["project", "list", "--format", "plain"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("cached-proj"));
+
+    // The write records an event like any other, which is what tells the
+    // daemon its copy of the list is out of date.
+    let conn = rusqlite::Connection::open(d.work_dir.path().join("data/database.sqlite3")).unwrap();
+    conn.busy_timeout(Duration::from_secs(5)).unwrap();
+    conn.execute("UPDATE projects SET name = 'renamed-proj'", [])
+        .unwrap();
+    d.assert_cmd()
+        .args(["project", "list", "--format", "plain"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("renamed-proj"));
+}
+
 // --- Project new ---
 
 #[test]
@@ -152,7 +196,7 @@ fn project_new_creates_project_with_explicit_name_and_path() {
         .success();
 
     let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
-    let parsed: Vec<serde_json::Value> = serde_json::from_str(&stdout).unwrap();
+    let parsed = json_items(stdout.as_bytes());
     assert_eq!(parsed.len(), 1);
     assert_eq!(parsed[0]["name"], "my-project");
     assert_eq!(
@@ -184,7 +228,7 @@ fn project_new_defaults_name_to_directory_basename() {
         .success();
 
     let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
-    let parsed: Vec<serde_json::Value> = serde_json::from_str(&stdout).unwrap();
+    let parsed = json_items(stdout.as_bytes());
     assert_eq!(parsed[0]["name"], "cool-project");
 }
 
@@ -207,7 +251,7 @@ fn project_new_defaults_path_to_current_dir() {
         .success();
 
     let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
-    let parsed: Vec<serde_json::Value> = serde_json::from_str(&stdout).unwrap();
+    let parsed = json_items(stdout.as_bytes());
     assert_eq!(parsed[0]["name"], "from-cwd");
     assert_eq!(
         parsed[0]["path"],
@@ -240,6 +284,96 @@ fn project_new_rejects_duplicate_name() {
         .failure();
 }
 
+#[test]
+fn project_new_adopts_registered_paths_and_needs_nested_for_overlaps() {
+    let d = DaemonFixture::start();
+    let repo = d.work_dir.path().join("repo");
+    let site = repo.join("site");
+    std::fs::create_dir_all(&site).unwrap();
+    let link = d.work_dir.path().join("repo-link");
+    std::os::unix::fs::symlink(&repo, &link).unwrap();
+
+    d.assert_cmd()
+        .args(["project", "new", "repo", "--path"])
+        .arg(&repo)
+        .assert()
+        .success();
+
+    // The same checkout by another route is the same project.
+    d.assert_cmd()
+        .args(["project", "new", "repo-again", "--path"])
+        .arg(&link)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already project repo"))
+        .stderr(predicate::str::contains("--adopt"));
+    d.assert_cmd()
+        .args(["project", "new", "repo-again", "--adopt", "--path"])
+        .arg(&link)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("repo-again is now an alias of it"));
+
+    d.assert_cmd()
+        .args(["project", "new", "site", "--path"])
+        .arg(&site)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("inside project repo"))
+        .stderr(predicate::str::contains("--nested"));
+    d.assert_cmd()
+        .args(["project", "new", "everything", "--path"])
+        .arg(d.work_dir.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("contains project repo"));
+    d.assert_cmd()
+        .args(["project", "new", "site", "--nested", "--path"])
+        .arg(&site)
+        .assert()
+        .success();
+
+    let out = d
+        .assert_cmd()
+        .args(["project", "list", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let names: Vec<_> = json_items(&out)
+        .iter()
+        .map(|p| p["name"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(names, ["repo", "site"]);
+    assert_eq!(
+        json_items(&out)[0]["aliases"],
+        serde_json::json!(["repo-again"])
+    );
+    let elsewhere = d.work_dir.path().join("elsewhere");
+    std::fs::create_dir_all(&elsewhere).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "repo-again", "--path"])
+        .arg(&elsewhere)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("alias of project repo"));
+
+    // Inside both, the nested project is the one a directory belongs to.
+    d.assert_cmd()
+        .current_dir(&site)
+        .args(["status", "--porcelain"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("project=site "));
+    d.assert_cmd()
+        .current_dir(&repo)
+        .args(["status", "--porcelain"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("project=repo "));
+}
+
 // --- Project list ---
 
 #[test]
@@ -327,7 +461,7 @@ fn project_list_json_format() {
         .success();
 
     let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
-    let parsed: Vec<serde_json::Value> = serde_json::from_str(&stdout).unwrap();
+    let parsed = json_items(stdout.as_bytes());
     assert_eq!(parsed.len(), 1);
     assert_eq!(parsed[0]["name"], "jsonproj");
     assert_eq!(
@@ -336,6 +470,49 @@ fn project_list_json_format() {
     );
 }
 
+#[test]
+fn config_and_work_format_set_the_default_format() {
+    let d = DaemonFixture::start();
+    let proj = d.work_dir.path().join("fmtproj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "fmtproj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    d.write_config("default-format = \"plain\"\n\n[formats]\nproject = \"json\"\n");
+
+    let out = d
+        .assert_cmd()
+        .args(["project", "list"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(json_items(&out)[0]["name"], "fmtproj");
+    d.assert_cmd()
+        .args(["env", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+
+    // WORK_FORMAT beats the config, and --format beats both.
+    d.assert_cmd()
+        .env("WORK_FORMAT", "plain")
+        .args(["project", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("fmtproj\t"));
+    d.assert_cmd()
+        .env("WORK_FORMAT", "plain")
+        .args(["project", "list", "--format", "human"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("NAME"));
+}
+
 #[test]
 fn project_list_ls_alias() {
     let d = DaemonFixture::start();
@@ -378,6 +555,200 @@ fn project_remove_fails_for_nonexistent_project() {
         .failure();
 }
 
+#[test]
+fn project_remove_drains_environments_and_needs_force_for_tasks() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.echo]
+type = "command"
+command = "echo"
+args = ["done"]
+"#,
+    );
+
+    let proj = d.work_dir.path().join("drained");
+    std::fs::create_dir(&proj).unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(&proj)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&["init", "-q"]);
+    git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+    d.assert_cmd()
+        .args(["project", "new", "drained", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let task_out = d
+        .assert_cmd()
+        .args([
+            "task",
+            "new",
+            "finish quickly",
+            "--project",
+            "drained",
+            "--provider",
+            "echo",
+            "--env-provider",
+            "git-worktree",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task_id = json_item(&task_out)["id"].as_str().unwrap().to_string();
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+        "complete"
+    );
+
+    // The task's environment is still in use, so removal needs --force.
+    d.assert_cmd()
+        .args(["project", "rm", "drained"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--force"));
+    d.assert_cmd()
+        .args(["project", "list", "--format", "plain"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("drained"));
+
+    d.assert_cmd()
+        .args(["project", "rm", "drained", "--force", "--yes"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "removing 0 environment(s) and 1 task(s)",
+        ));
+    // Hidden at once, even while its environments are being removed.
+    d.assert_cmd()
+        .args(["project", "list", "--format", "plain"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+
+    let deadline = Instant::now() + Duration::from_secs(20);
+    loop {
+        let out = d
+            .assert_cmd()
+            .args(["events", "--format", "plain"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let removed = String::from_utf8_lossy(&out).lines().any(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            fields[2] == "project" && fields[4] == "removed"
+        });
+        if removed {
+            break;
+        }
+        assert!(Instant::now() < deadline, "project was never deleted");
+        thread::sleep(Duration::from_millis(200));
+    }
+    let envs = d
+        .assert_cmd()
+        .args(["environment", "list", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert!(json_items(&envs).is_empty());
+
+    // The name is free again.
+    d.assert_cmd()
+        .args(["project", "new", "drained", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+}
+
+#[test]
+fn project_remove_can_be_retried_once_an_environment_removal_dies() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[daemon.retries.remove_task]
+limit = 0
+
+[tasks.providers.fake]
+type = "mock"
+
+[environments.providers.sticky]
+type = "mock"
+fail = ["remove"]
+"#,
+    );
+
+    let proj = d.work_dir.path().join("sticky-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "sticky-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+    let task_out = d
+        .assert_cmd()
+        .args(["task", "new", "stay put", "--project", "sticky-proj"])
+        .args(["--provider", "fake", "--env-provider", "sticky"])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task_id = json_item(&task_out)["id"].as_str().unwrap().to_string();
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+        "complete"
+    );
+
+    d.assert_cmd()
+        .args(["project", "rm", "sticky-proj", "--force", "--yes"])
+        .assert()
+        .success();
+
+    // The environment can't be removed, so the project comes back rather
+    // than staying hidden for good.
+    let deadline = Instant::now() + Duration::from_secs(20);
+    loop {
+        let out = d
+            .assert_cmd()
+            .args(["project", "list", "--format", "plain"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        if String::from_utf8_lossy(&out).contains("sticky-proj") {
+            break;
+        }
+        assert!(Instant::now() < deadline, "project never came back");
+        thread::sleep(Duration::from_millis(200));
+    }
+    d.assert_cmd()
+        .args(["project", "rm", "sticky-proj", "--force", "--yes"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "removing 0 environment(s) and 1 task(s)",
+        ));
+}
+
 #[test]
 fn task_creation_failure_persists_failed_environment() {
     let d = DaemonFixture::start();
@@ -416,12 +787,11 @@ esac
         std::fs::set_permissions(&provider_script, perms).unwrap();
     }
 
-    let config_dir = d.work_dir.path().join("config");
-    std::fs::create_dir_all(&config_dir).unwrap();
-    std::fs::write(
-        config_dir.join("config.toml"),
-        format!(
-            r#"[tasks.providers.noop]
+    d.write_config(format!(
+        r#"[daemon.retries.prepare_environment]
+limit = 0
+
+[tasks.providers.noop]
 type = "command"
 command = "sh"
 args = ["-c", "true"]
@@ -430,10 +800,8 @@ args = ["-c", "true"]
 type = "script"
 path = "{}"
 "#,
-            provider_script.to_string_lossy()
-        ),
-    )
-    .unwrap();
+        provider_script.to_string_lossy()
+    ));
 
     let proj = d.work_dir.path().join("task-proj");
     std::fs::create_dir(&proj).unwrap();
@@ -461,7 +829,7 @@ path = "{}"
         .assert()
         .success();
     let task_json = String::from_utf8(task_create.get_output().stdout.clone()).unwrap();
-    let task: serde_json::Value = serde_json::from_str(&task_json).unwrap();
+    let task = json_item(task_json.as_bytes());
     let task_id = task["id"].as_str().unwrap().to_string();
 
     let deadline = Instant::now() + Duration::from_secs(8);
@@ -474,7 +842,7 @@ path = "{}"
             .get_output()
             .stdout
             .clone();
-        let tasks: Vec<serde_json::Value> = serde_json::from_slice(&task_list_out).unwrap();
+        let tasks = json_items(&task_list_out);
         let Some(task) = tasks
             .iter()
             .find(|candidate| candidate["id"].as_str() == Some(&task_id))
@@ -508,7 +876,7 @@ path = "{}"
             .get_output()
             .stdout
             .clone();
-        let envs: Vec<serde_json::Value> = serde_json::from_slice(&env_list_out).unwrap();
+        let envs = json_items(&env_list_out);
         let Some(env) = envs
             .iter()
             .find(|candidate| candidate["id"].as_str() == Some(env_id.as_str()))
@@ -530,6 +898,35 @@ path = "{}"
     assert_eq!(task_status, "failed");
     assert!(!task_env_id.is_empty());
     assert_eq!(env_status, "failed");
+
+    let show_out = d
+        .assert_cmd()
+        .args(["env", "show", &task_env_id, "--history", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let history = json_item(&show_out)["history"].as_array().unwrap().clone();
+    let transitions: Vec<_> = history
+        .iter()
+        .map(|change| {
+            (
+                change["previous_status"].as_str(),
+                change["status"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        transitions,
+        [(None, "preparing"), (Some("preparing"), "failed")]
+    );
+    let failing_job = history[1]["job_id"].as_str().unwrap();
+    d.assert_cmd()
+        .args(["job", "show", failing_job])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("prepare_environment"));
 }
 
 #[test]
@@ -563,19 +960,13 @@ esac
 "#,
     );
 
-    let config_dir = d.work_dir.path().join("config");
-    std::fs::create_dir_all(&config_dir).unwrap();
-    std::fs::write(
-        config_dir.join("config.toml"),
-        format!(
-            r#"[environments.providers.slow]
+    d.write_config(format!(
+        r#"[environments.providers.slow]
 type = "script"
 path = "{}"
 "#,
-            provider_script.to_string_lossy()
-        ),
-    )
-    .unwrap();
+        provider_script.to_string_lossy()
+    ));
 
     let proj = d.work_dir.path().join("async-env-proj");
     std::fs::create_dir(&proj).unwrap();
@@ -601,7 +992,7 @@ path = "{}"
         .get_output()
         .stdout
         .clone();
-    let env: serde_json::Value = serde_json::from_slice(&create_out).unwrap();
+    let env = json_item(&create_out);
     let env_id = env["id"].as_str().unwrap().to_string();
     assert_eq!(env["status"], "preparing");
 
@@ -609,113 +1000,266 @@ path = "{}"
 }
 
 #[test]
-fn environment_prepare_writes_lifecycle_log_file() {
+fn inplace_tasks_need_confirming_and_take_turns_in_the_checkout() {
     let d = DaemonFixture::start();
 
-    let provider_script = d.work_dir.path().join("logging-env-provider.sh");
-    write_executable_script(
-        &provider_script,
-        r#"#!/bin/sh
-set -eu
-action="$1"
-case "$action" in
-  prepare|update|claim)
-    echo "provider-output: action=$action" >&2
-    echo '{}'
-    ;;
-  remove)
-    exit 0
-    ;;
-  run)
-    exit 0
-    ;;
-  *)
-    exit 1
-    ;;
-esac
+    d.write_config(
+        r#"[tasks.providers.look]
+type = "command"
+command = "sh"
+args = ["-c", "touch ran-here; sleep 30"]
 "#,
     );
 
-    let config_dir = d.work_dir.path().join("config");
-    std::fs::create_dir_all(&config_dir).unwrap();
-    std::fs::write(
-        config_dir.join("config.toml"),
-        format!(
-            r#"[environments.providers.logging]
-type = "script"
-path = "{}"
-"#,
-            provider_script.to_string_lossy()
-        ),
-    )
-    .unwrap();
-
-    let proj = d.work_dir.path().join("logging-env-proj");
+    let proj = d.work_dir.path().join("checkout");
     std::fs::create_dir(&proj).unwrap();
+    std::fs::write(proj.join("notes.txt"), "uncommitted").unwrap();
     d.assert_cmd()
-        .args(["project", "new", "logging-env-proj", "--path"])
+        .args(["project", "new", "checkout", "--path"])
         .arg(&proj)
         .assert()
         .success();
 
-    let prepare_out = d
-        .assert_cmd()
-        .args([
-            "environment",
-            "prepare",
-            "logging-env-proj",
+    let new_task = || {
+        let mut cmd = d.assert_cmd();
+        cmd.args([
+            "task",
+            "new",
+            "read the code",
+            "--project",
+            "checkout",
             "--provider",
-            "logging",
-            "--format",
-            "json",
-        ])
+            "look",
+        ]);
+        cmd
+    };
+
+    new_task()
+        .args(["--env-provider", "inplace"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--in-place"));
+    d.assert_cmd()
+        .args(["env", "prepare", "checkout", "--provider", "inplace"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--in-place"));
+
+    let out = new_task()
+        .args(["--in-place", "--format", "json"])
         .assert()
         .success()
         .get_output()
         .stdout
         .clone();
-    let env: serde_json::Value = serde_json::from_slice(&prepare_out).unwrap();
-    let env_id = env["id"].as_str().unwrap().to_string();
+    let task_id = json_item(&out)["id"].as_str().unwrap().to_string();
+    assert!(
+        common::wait_for_path(&proj.join("ran-here"), Duration::from_secs(10)),
+        "task did not run in the project checkout"
+    );
 
-    wait_for_env_status(&d, &env_id, "pool", Duration::from_secs(8));
+    let out = d
+        .assert_cmd()
+        .args(["task", "show", &task_id, "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let env_id = json_item(&out)["environment_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let out = d
+        .assert_cmd()
+        .args(["environment", "show", &env_id, "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let env = json_item(&out);
+    assert_eq!(env["provider"], "inplace");
+    assert_eq!(env["metadata"]["project_path"], proj.to_str().unwrap());
+    assert!(env["metadata"].get("worktree_path").is_none());
 
-    let log_path = d
-        .work_dir
-        .path()
-        .join("data/logs/environments")
-        .join(format!("{env_id}.log"));
-    let deadline = Instant::now() + Duration::from_secs(8);
+    new_task()
+        .arg("--in-place")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!(
+            "task {task_id} is still running in the project checkout"
+        )));
+
+    // Removing the task leaves the checkout as it was, and frees it up.
+    d.assert_cmd()
+        .args(["task", "remove", &task_id])
+        .assert()
+        .success();
+    let deadline = Instant::now() + Duration::from_secs(12);
     loop {
-        let contents = std::fs::read_to_string(&log_path).unwrap_or_default();
-        if contents.contains("job=prepare_environment")
-            && contents.contains("phase=complete")
-            && contents.contains("provider-output: action=prepare")
-        {
+        let out = d
+            .assert_cmd()
+            .args(["task", "list", "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        if json_items(&out).is_empty() {
             break;
         }
-
-        if Instant::now() >= deadline {
-            panic!(
-                "timed out waiting for lifecycle log content at {}: {}",
-                log_path.display(),
-                contents
-            );
-        }
+        assert!(Instant::now() < deadline, "task was never removed");
         thread::sleep(Duration::from_millis(100));
     }
+    assert_eq!(
+        std::fs::read_to_string(proj.join("notes.txt")).unwrap(),
+        "uncommitted"
+    );
+    new_task().arg("--in-place").assert().success();
+}
+
+#[test]
+fn task_and_exec_commands_see_their_environment_and_project() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.where]
+type = "command"
+command = "sh"
+args = ["-c", "printf '%s %s' \"$WORK_ENV_ID\" \"$WORK_PROJECT_PATH\" > seen"]
+"#,
+    );
 
+    let proj = d.work_dir.path().join("where-proj");
+    std::fs::create_dir(&proj).unwrap();
     d.assert_cmd()
-        .args(["environment", "logs", &env_id])
+        .args(["project", "new", "where-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "new", "where am i", "--project", "where-proj"])
+        .args(["--provider", "where", "--in-place", "--format", "json"])
         .assert()
         .success()
-        .stdout(predicate::str::contains("provider-output: action=prepare"));
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&out);
+    let task_id = task["id"].as_str().unwrap().to_string();
+    let env_id = task["environment_id"].as_str().unwrap().to_string();
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+        "complete"
+    );
+    assert_eq!(
+        std::fs::read_to_string(proj.join("seen")).unwrap(),
+        format!("{env_id} {}", proj.display())
+    );
+
+    d.assert_cmd()
+        .args(["environment", "exec", &env_id, "sh", "-c"])
+        .arg("echo \"$WORK_ENV_ID $WORK_PROJECT_PATH\"")
+        .assert()
+        .success()
+        .stdout(format!("{env_id} {}\n", proj.display()));
 }
 
 #[test]
-fn environment_update_is_queued_and_failure_happens_async() {
+fn projects_take_turns_when_one_floods_the_job_queue() {
     let d = DaemonFixture::start();
 
-    let provider_script = d.work_dir.path().join("failing-update-provider.sh");
+    // Each prepare logs its project, then holds its job slot until it is the
+    // one to take the release file, or the test is over.
+    let dir = d.work_dir.path();
+    let starts = dir.join("starts.log");
+    let release = dir.join("release");
+    let stop = dir.join("stop");
+    let provider_script = dir.join("gated-provider.sh");
+    write_executable_script(
+        &provider_script,
+        &format!(
+            r#"#!/bin/sh
+case "$1" in
+  prepare)
+    case "$(cat)" in
+      *'"project_name":"quiet"'*) echo quiet >> '{starts}' ;;
+      *) echo flood >> '{starts}' ;;
+    esac
+    until rm '{release}' 2>/dev/null || [ -e '{stop}' ]; do sleep 0.05; done
+    echo '{{}}'
+    ;;
+  *)
+    cat > /dev/null
+    echo '{{}}'
+    ;;
+esac
+"#,
+            starts = starts.display(),
+            release = release.display(),
+            stop = stop.display(),
+        ),
+    );
+
+    d.write_config(format!(
+        "[daemon]\nshutdown-grace = \"0s\"\n\n[environments.providers.gated]\ntype = \"script\"\npath = \"{}\"\n",
+        provider_script.display()
+    ));
+
+    for name in ["flood", "quiet"] {
+        let proj = dir.join(name);
+        std::fs::create_dir(&proj).unwrap();
+        d.assert_cmd()
+            .args(["project", "new", name, "--path"])
+            .arg(&proj)
+            .assert()
+            .success();
+    }
+    let prepare = |project: &str| {
+        d.assert_cmd()
+            .args(["environment", "prepare", project, "--provider", "gated"])
+            .assert()
+            .success();
+    };
+    let started = || -> Vec<String> {
+        std::fs::read_to_string(&starts)
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    };
+    let wait_for_starts = |count: usize| {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while started().len() < count {
+            assert!(Instant::now() < deadline, "only {:?} started", started());
+            thread::sleep(Duration::from_millis(50));
+        }
+    };
+
+    // Fill every job slot, and queue two more behind them.
+    for _ in 0..10 {
+        prepare("flood");
+    }
+    wait_for_starts(8);
+    prepare("quiet");
+
+    // The quiet project's prepare is newer than the flood's queued ones, but
+    // the flood already holds every slot, so it goes first when one frees.
+    std::fs::write(&release, "").unwrap();
+    wait_for_starts(9);
+    let order = started();
+    std::fs::write(&stop, "").unwrap();
+    assert_eq!(order[8], "quiet", "{order:?}");
+}
+
+#[test]
+fn environment_claim_count_takes_pooled_and_prepares_the_shortfall() {
+    let d = DaemonFixture::start();
+
+    let provider_script = d.work_dir.path().join("batch-provider.sh");
     write_executable_script(
         &provider_script,
         r#"#!/bin/sh
@@ -725,16 +1269,10 @@ case "$action" in
   prepare)
     echo '{}'
     ;;
-  update)
-    exit 1
-    ;;
-  claim)
+  update|claim)
     echo '{}'
     ;;
-  remove)
-    exit 0
-    ;;
-  run)
+  remove|run)
     exit 0
     ;;
   *)
@@ -744,24 +1282,18 @@ esac
 "#,
     );
 
-    let config_dir = d.work_dir.path().join("config");
-    std::fs::create_dir_all(&config_dir).unwrap();
-    std::fs::write(
-        config_dir.join("config.toml"),
-        format!(
-            r#"[environments.providers.updatefail]
+    d.write_config(format!(
+        r#"[environments.providers.batch]
 type = "script"
 path = "{}"
 "#,
-            provider_script.to_string_lossy()
-        ),
-    )
-    .unwrap();
+        provider_script.to_string_lossy()
+    ));
 
-    let proj = d.work_dir.path().join("update-env-proj");
+    let proj = d.work_dir.path().join("batch-proj");
     std::fs::create_dir(&proj).unwrap();
     d.assert_cmd()
-        .args(["project", "new", "update-env-proj", "--path"])
+        .args(["project", "new", "batch-proj", "--path"])
         .arg(&proj)
         .assert()
         .success();
@@ -771,9 +1303,9 @@ path = "{}"
         .args([
             "environment",
             "prepare",
-            "update-env-proj",
+            "batch-proj",
             "--provider",
-            "updatefail",
+            "batch",
             "--format",
             "json",
         ])
@@ -782,40 +1314,76 @@ path = "{}"
         .get_output()
         .stdout
         .clone();
-    let env: serde_json::Value = serde_json::from_slice(&prepare_out).unwrap();
-    let env_id = env["id"].as_str().unwrap().to_string();
-    wait_for_env_status(&d, &env_id, "pool", Duration::from_secs(8));
+    let pooled_id = json_item(&prepare_out)["id"].as_str().unwrap().to_string();
+    wait_for_env_status(&d, &pooled_id, "pool", Duration::from_secs(8));
 
-    d.assert_cmd()
-        .args(["environment", "update", &env_id, "--format", "json"])
+    let claim_out = d
+        .assert_cmd()
+        .args([
+            "environment",
+            "claim",
+            "--project",
+            "batch-proj",
+            "--provider",
+            "batch",
+            "--count",
+            "3",
+            "--format",
+            "json",
+        ])
         .assert()
-        .success();
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let envs = json_items(&claim_out);
+    assert_eq!(envs.len(), 3);
+    assert_eq!(envs[0]["id"].as_str(), Some(pooled_id.as_str()));
+    assert_eq!(envs[0]["status"], "in_use");
+    for env in &envs[1..] {
+        assert_eq!(env["status"], "preparing");
+    }
+    for env in &envs {
+        wait_for_env_status(
+            &d,
+            env["id"].as_str().unwrap(),
+            "in_use",
+            Duration::from_secs(8),
+        );
+    }
 
-    wait_for_env_status(&d, &env_id, "failed", Duration::from_secs(8));
+    d.assert_cmd()
+        .args([
+            "environment",
+            "claim",
+            "--project",
+            "batch-proj",
+            "--provider",
+            "batch",
+            "--count",
+            "0",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("count must be between 1 and"));
 }
 
 #[test]
-fn task_remove_keeps_task_until_environment_cleanup_succeeds() {
+fn environment_prepare_writes_lifecycle_log_file() {
     let d = DaemonFixture::start();
-    let remove_fail_flag = d.work_dir.path().join("remove-fail.flag");
-    std::fs::write(&remove_fail_flag, "1").unwrap();
 
-    let provider_script = d.work_dir.path().join("failing-remove-provider.sh");
+    let provider_script = d.work_dir.path().join("logging-env-provider.sh");
     write_executable_script(
         &provider_script,
-        &format!(
-            r#"#!/bin/sh
+        r#"#!/bin/sh
 set -eu
 action="$1"
-fail_flag="{}"
 case "$action" in
   prepare|update|claim)
-    echo '{{}}'
+    echo "provider-output: action=$action" >&2
+    echo '{}'
     ;;
   remove)
-    if [ -f "$fail_flag" ]; then
-      exit 1
-    fi
     exit 0
     ;;
   run)
@@ -826,49 +1394,32 @@ case "$action" in
     ;;
 esac
 "#,
-            remove_fail_flag.to_string_lossy()
-        ),
     );
 
-    let config_dir = d.work_dir.path().join("config");
-    std::fs::create_dir_all(&config_dir).unwrap();
-    std::fs::write(
-        config_dir.join("config.toml"),
-        format!(
-            r#"[tasks.providers.noop]
-type = "command"
-command = "sh"
-args = ["-c", "true"]
-
-[environments.providers.removefail]
+    d.write_config(format!(
+        r#"[environments.providers.logging]
 type = "script"
 path = "{}"
 "#,
-            provider_script.to_string_lossy()
-        ),
-    )
-    .unwrap();
+        provider_script.to_string_lossy()
+    ));
 
-    let proj = d.work_dir.path().join("remove-task-proj");
+    let proj = d.work_dir.path().join("logging-env-proj");
     std::fs::create_dir(&proj).unwrap();
     d.assert_cmd()
-        .args(["project", "new", "remove-task-proj", "--path"])
+        .args(["project", "new", "logging-env-proj", "--path"])
         .arg(&proj)
         .assert()
         .success();
 
-    let task_create = d
+    let prepare_out = d
         .assert_cmd()
         .args([
-            "task",
-            "new",
-            "remove-failure-case",
-            "--project",
-            "remove-task-proj",
+            "environment",
+            "prepare",
+            "logging-env-proj",
             "--provider",
-            "noop",
-            "--env-provider",
-            "removefail",
+            "logging",
             "--format",
             "json",
         ])
@@ -877,123 +1428,57 @@ path = "{}"
         .get_output()
         .stdout
         .clone();
-    let task: serde_json::Value = serde_json::from_slice(&task_create).unwrap();
-    let task_id = task["id"].as_str().unwrap().to_string();
-    let env_id = task["environment_id"].as_str().unwrap().to_string();
+    let env = json_item(&prepare_out);
+    let env_id = env["id"].as_str().unwrap().to_string();
+
+    wait_for_env_status(&d, &env_id, "pool", Duration::from_secs(8));
 
+    let log_path = d
+        .work_dir
+        .path()
+        .join("data/logs/environments")
+        .join(format!("{env_id}.log"));
     let deadline = Instant::now() + Duration::from_secs(8);
     loop {
-        let tasks_out = d
-            .assert_cmd()
-            .args(["task", "list", "--format", "json"])
-            .assert()
-            .success()
-            .get_output()
-            .stdout
-            .clone();
-        let tasks: Vec<serde_json::Value> = serde_json::from_slice(&tasks_out).unwrap();
-        let status = tasks
-            .iter()
-            .find(|candidate| candidate["id"].as_str() == Some(task_id.as_str()))
-            .and_then(|t| t["status"].as_str())
-            .unwrap_or_default();
-        if status == "complete" || status == "failed" {
-            break;
-        }
-        if Instant::now() >= deadline {
-            panic!("timed out waiting for task completion before remove");
-        }
-        thread::sleep(Duration::from_millis(100));
-    }
-
-    d.assert_cmd()
-        .args(["task", "logs", &task_id])
-        .assert()
-        .success();
-    d.assert_cmd()
-        .args(["task", "logs", &task_id, "--follow"])
-        .assert()
-        .success();
-
-    d.assert_cmd()
-        .args(["task", "remove", &task_id])
-        .assert()
-        .success();
-
-    wait_for_env_status(&d, &env_id, "failed", Duration::from_secs(12));
-
-    let deadline = Instant::now() + Duration::from_secs(8);
-    loop {
-        let tasks_out = d
-            .assert_cmd()
-            .args(["task", "list", "--format", "json"])
-            .assert()
-            .success()
-            .get_output()
-            .stdout
-            .clone();
-        let tasks: Vec<serde_json::Value> = serde_json::from_slice(&tasks_out).unwrap();
-        if tasks
-            .iter()
-            .any(|candidate| candidate["id"].as_str() == Some(task_id.as_str()))
+        let contents = std::fs::read_to_string(&log_path).unwrap_or_default();
+        if contents.contains("job=prepare_environment")
+            && contents.contains("phase=complete")
+            && contents.contains("provider-output: action=prepare")
         {
             break;
         }
+
         if Instant::now() >= deadline {
-            panic!("task was deleted even though environment removal failed");
+            panic!(
+                "timed out waiting for lifecycle log content at {}: {}",
+                log_path.display(),
+                contents
+            );
         }
         thread::sleep(Duration::from_millis(100));
     }
 
-    std::fs::remove_file(&remove_fail_flag).unwrap();
-
     d.assert_cmd()
-        .args(["task", "remove", &task_id])
+        .args(["environment", "logs", &env_id])
         .assert()
-        .success();
-
-    let deadline = Instant::now() + Duration::from_secs(12);
-    loop {
-        let tasks_out = d
-            .assert_cmd()
-            .args(["task", "list", "--format", "json"])
-            .assert()
-            .success()
-            .get_output()
-            .stdout
-            .clone();
-        let tasks: Vec<serde_json::Value> = serde_json::from_slice(&tasks_out).unwrap();
-        if tasks
-            .iter()
-            .all(|candidate| candidate["id"].as_str() != Some(task_id.as_str()))
-        {
-            break;
-        }
-        if Instant::now() >= deadline {
-            panic!("task was not deleted after retrying removal");
-        }
-        thread::sleep(Duration::from_millis(100));
-    }
+        .success()
+        .stdout(predicate::str::contains("provider-output: action=prepare"));
 }
 
 #[test]
-fn environment_remove_skip_provider_force_deletes_after_provider_failure() {
+fn environment_lists_show_provider_display_name() {
     let d = DaemonFixture::start();
 
-    let provider_script = d.work_dir.path().join("always-fail-remove-env.sh");
+    let provider_script = d.work_dir.path().join("named-env-provider.sh");
     write_executable_script(
         &provider_script,
         r#"#!/bin/sh
 set -eu
-action="$1"
-case "$action" in
-  prepare|update|claim)
-    echo '{}'
+case "$1" in
+  prepare)
+    echo '{"display_name": "auth-fix"}'
     ;;
   remove)
-    exit 1
-    ;;
-  run)
     exit 0
     ;;
   *)
@@ -1003,24 +1488,18 @@ esac
 "#,
     );
 
-    let config_dir = d.work_dir.path().join("config");
-    std::fs::create_dir_all(&config_dir).unwrap();
-    std::fs::write(
-        config_dir.join("config.toml"),
-        format!(
-            r#"[environments.providers.removefail]
+    d.write_config(format!(
+        r#"[environments.providers.named]
 type = "script"
 path = "{}"
 "#,
-            provider_script.to_string_lossy()
-        ),
-    )
-    .unwrap();
+        provider_script.to_string_lossy()
+    ));
 
-    let proj = d.work_dir.path().join("remove-env-skip-provider-proj");
+    let proj = d.work_dir.path().join("named-env-proj");
     std::fs::create_dir(&proj).unwrap();
     d.assert_cmd()
-        .args(["project", "new", "remove-env-skip-provider-proj", "--path"])
+        .args(["project", "new", "named-env-proj", "--path"])
         .arg(&proj)
         .assert()
         .success();
@@ -1028,11 +1507,11 @@ path = "{}"
     let prepare_out = d
         .assert_cmd()
         .args([
-            "env",
+            "environment",
             "prepare",
-            "remove-env-skip-provider-proj",
+            "named-env-proj",
             "--provider",
-            "removefail",
+            "named",
             "--format",
             "json",
         ])
@@ -1041,111 +1520,72 @@ path = "{}"
         .get_output()
         .stdout
         .clone();
-    let env: serde_json::Value = serde_json::from_slice(&prepare_out).unwrap();
-    let env_id = env["id"].as_str().unwrap().to_string();
+    let env_id = json_item(&prepare_out)["id"].as_str().unwrap().to_string();
     wait_for_env_status(&d, &env_id, "pool", Duration::from_secs(8));
 
     d.assert_cmd()
-        .args(["env", "rm", &env_id])
+        .args(["environment", "list", "--format", "plain"])
         .assert()
-        .success();
-    wait_for_env_status(&d, &env_id, "failed", Duration::from_secs(12));
-
+        .success()
+        .stdout(predicate::str::contains(format!("{env_id}\tnamed\tpool\t")))
+        .stdout(predicate::str::contains("\tauth-fix\n"));
     d.assert_cmd()
-        .args(["env", "rm", &env_id, "--skip-provider"])
+        .args(["environment", "show", &env_id])
         .assert()
-        .success();
-
-    let deadline = Instant::now() + Duration::from_secs(8);
-    loop {
-        let envs_out = d
-            .assert_cmd()
-            .args(["env", "ls", "--format", "json"])
-            .assert()
-            .success()
-            .get_output()
-            .stdout
-            .clone();
-        let envs: Vec<serde_json::Value> = serde_json::from_slice(&envs_out).unwrap();
-        if envs
-            .iter()
-            .all(|candidate| candidate["id"].as_str() != Some(env_id.as_str()))
-        {
-            break;
-        }
-        if Instant::now() >= deadline {
-            panic!("environment not force-deleted with --skip-provider");
-        }
-        thread::sleep(Duration::from_millis(100));
-    }
+        .success()
+        .stdout(predicate::str::contains("auth-fix"));
 }
 
 #[test]
-fn task_remove_skip_provider_force_deletes_after_provider_failure() {
+fn environment_prepare_reports_script_progress() {
     let d = DaemonFixture::start();
 
-    let provider_script = d.work_dir.path().join("always-fail-remove-task-env.sh");
+    let release = d.work_dir.path().join("release-prepare");
+    let provider_script = d.work_dir.path().join("progress-env-provider.sh");
     write_executable_script(
         &provider_script,
-        r#"#!/bin/sh
+        &format!(
+            r#"#!/bin/sh
 set -eu
-action="$1"
-case "$action" in
-  prepare|update|claim)
-    echo '{}'
-    ;;
-  remove)
-    exit 1
-    ;;
-  run)
-    exit 0
+case "$1" in
+  prepare)
+    echo 'work-progress: {{"progress": 0.4, "message": "cloning"}}' >&2
+    while [ ! -e "{}" ]; do sleep 0.05; done
+    echo '{{}}'
     ;;
   *)
-    exit 1
+    echo '{{}}'
     ;;
 esac
 "#,
+            release.to_string_lossy()
+        ),
     );
 
-    let config_dir = d.work_dir.path().join("config");
-    std::fs::create_dir_all(&config_dir).unwrap();
-    std::fs::write(
-        config_dir.join("config.toml"),
-        format!(
-            r#"[tasks.providers.noop]
-type = "command"
-command = "sh"
-args = ["-c", "true"]
-
-[environments.providers.removefail]
+    d.write_config(format!(
+        r#"[environments.providers.progress]
 type = "script"
 path = "{}"
 "#,
-            provider_script.to_string_lossy()
-        ),
-    )
-    .unwrap();
+        provider_script.to_string_lossy()
+    ));
 
-    let proj = d.work_dir.path().join("remove-task-skip-provider-proj");
+    let proj = d.work_dir.path().join("progress-proj");
     std::fs::create_dir(&proj).unwrap();
     d.assert_cmd()
-        .args(["project", "new", "remove-task-skip-provider-proj", "--path"])
+        .args(["project", "new", "progress-proj", "--path"])
         .arg(&proj)
         .assert()
         .success();
 
-    let task_create = d
+    let prepare_out = d
         .assert_cmd()
         .args([
-            "task",
-            "new",
-            "remove-skip-provider-case",
-            "--project",
-            "remove-task-skip-provider-proj",
+            "environment",
+            "prepare",
+            "progress-proj",
             "--provider",
-            "noop",
-            "--env-provider",
-            "removefail",
+            "progress",
             "--format",
             "json",
         ])
@@ -1154,106 +1594,59 @@ path = "{}"
         .get_output()
         .stdout
         .clone();
-    let task: serde_json::Value = serde_json::from_slice(&task_create).unwrap();
-    let task_id = task["id"].as_str().unwrap().to_string();
-    let env_id = task["environment_id"].as_str().unwrap().to_string();
+    let env = json_item(&prepare_out);
+    let env_id = env["id"].as_str().unwrap().to_string();
 
-    let deadline = Instant::now() + Duration::from_secs(8);
-    loop {
-        let tasks_out = d
+    let show_env = || -> serde_json::Value {
+        let out = d
             .assert_cmd()
-            .args(["task", "list", "--format", "json"])
+            .args(["environment", "list", "--format", "json"])
             .assert()
             .success()
             .get_output()
             .stdout
             .clone();
-        let tasks: Vec<serde_json::Value> = serde_json::from_slice(&tasks_out).unwrap();
-        let status = tasks
-            .iter()
-            .find(|candidate| candidate["id"].as_str() == Some(task_id.as_str()))
-            .and_then(|t| t["status"].as_str())
-            .unwrap_or_default();
-        if status == "complete" || status == "failed" {
-            break;
-        }
-        if Instant::now() >= deadline {
-            panic!("timed out waiting for task completion before remove");
-        }
-        thread::sleep(Duration::from_millis(100));
-    }
-
-    d.assert_cmd()
-        .args(["task", "rm", &task_id])
-        .assert()
-        .success();
-    wait_for_env_status(&d, &env_id, "failed", Duration::from_secs(12));
-
-    d.assert_cmd()
-        .args(["task", "rm", &task_id, "--skip-provider"])
-        .assert()
-        .success();
+        let envs = json_items(&out);
+        envs.into_iter()
+            .find(|env| env["id"].as_str() == Some(env_id.as_str()))
+            .unwrap()
+    };
 
     let deadline = Instant::now() + Duration::from_secs(8);
     loop {
-        let tasks_out = d
-            .assert_cmd()
-            .args(["task", "ls", "--format", "json"])
-            .assert()
-            .success()
-            .get_output()
-            .stdout
-            .clone();
-        let tasks: Vec<serde_json::Value> = serde_json::from_slice(&tasks_out).unwrap();
-        let task_present = tasks
-            .iter()
-            .any(|candidate| candidate["id"].as_str() == Some(task_id.as_str()));
-
-        let envs_out = d
-            .assert_cmd()
-            .args(["env", "ls", "--format", "json"])
-            .assert()
-            .success()
-            .get_output()
-            .stdout
-            .clone();
-        let envs: Vec<serde_json::Value> = serde_json::from_slice(&envs_out).unwrap();
-        let env_present = envs
-            .iter()
-            .any(|candidate| candidate["id"].as_str() == Some(env_id.as_str()));
-
-        if !task_present && !env_present {
+        let env = show_env();
+        if env["progress"].as_f64() == Some(0.4) {
+            assert_eq!(env["status"], "preparing");
+            assert_eq!(env["progress_message"], "cloning");
             break;
         }
         if Instant::now() >= deadline {
-            panic!("task/environment not force-deleted with --skip-provider");
+            panic!("timed out waiting for progress: {env}");
         }
         thread::sleep(Duration::from_millis(100));
     }
+
+    std::fs::write(&release, "").unwrap();
+    wait_for_env_status(&d, &env_id, "pool", Duration::from_secs(8));
+    let env = show_env();
+    assert!(env["progress"].is_null());
+    assert!(env["progress_message"].is_null());
 }
 
 #[test]
-fn task_new_claims_pool_environment_and_task_remove_deletes_paired_environment() {
+fn environment_plan_runs_script_plan_without_preparing() {
     let d = DaemonFixture::start();
 
-    let provider_script = d.work_dir.path().join("pool-reuse-provider.sh");
+    let provider_script = d.work_dir.path().join("plan-env-provider.sh");
     write_executable_script(
         &provider_script,
         r#"#!/bin/sh
 set -eu
-action="$1"
-case "$action" in
-  prepare)
-    echo '{"worktree_path":"/tmp/fake","prepared":true}'
-    ;;
-  update|claim)
-    echo '{}'
-    ;;
-  remove)
-    exit 0
-    ;;
-  run)
-    exit 0
+input=$(cat)
+case "$1" in
+  plan)
+    env_id=$(printf '%s' "$input" | sed 's/.*"env_id":"\([^"]*\)".*/\1/')
+    printf '{"steps": ["create sandbox %s"], "paths": ["/sandboxes/%s"], "estimated_disk_bytes": 4096}\n' "$env_id" "$env_id"
     ;;
   *)
     exit 1
@@ -1262,41 +1655,31 @@ esac
 "#,
     );
 
-    let config_dir = d.work_dir.path().join("config");
-    std::fs::create_dir_all(&config_dir).unwrap();
-    std::fs::write(
-        config_dir.join("config.toml"),
-        format!(
-            r#"[tasks.providers.noop]
-type = "command"
-command = "sh"
-args = ["-c", "true"]
-
-[environments.providers.poolreuse]
+    d.write_config(format!(
+        r#"[environments.providers.planner]
 type = "script"
 path = "{}"
 "#,
-            provider_script.to_string_lossy()
-        ),
-    )
-    .unwrap();
+        provider_script.to_string_lossy()
+    ));
 
-    let proj = d.work_dir.path().join("pool-reuse-proj");
+    let proj = d.work_dir.path().join("plan-proj");
     std::fs::create_dir(&proj).unwrap();
     d.assert_cmd()
-        .args(["project", "new", "pool-reuse-proj", "--path"])
+        .args(["project", "new", "plan-proj", "--path"])
         .arg(&proj)
         .assert()
         .success();
 
-    let prepare_out = d
+    let out = d
         .assert_cmd()
         .args([
             "environment",
-            "prepare",
-            "pool-reuse-proj",
+            "plan",
+            "--project",
+            "plan-proj",
             "--provider",
-            "poolreuse",
+            "planner",
             "--format",
             "json",
         ])
@@ -1305,110 +1688,39 @@ path = "{}"
         .get_output()
         .stdout
         .clone();
-    let prepared_env: serde_json::Value = serde_json::from_slice(&prepare_out).unwrap();
-    let pool_env_id = prepared_env["id"].as_str().unwrap().to_string();
-    wait_for_env_status(&d, &pool_env_id, "pool", Duration::from_secs(8));
+    let plan = json_item(&out);
+    let step = plan["steps"][0].as_str().unwrap();
+    let env_id = step.strip_prefix("create sandbox ").unwrap();
+    assert!(!env_id.is_empty());
+    assert_eq!(plan["paths"][0], format!("/sandboxes/{env_id}"));
+    assert_eq!(plan["estimated_disk_bytes"], 4096);
 
-    let task_out = d
-        .assert_cmd()
+    d.assert_cmd()
         .args([
-            "task",
-            "new",
-            "reuse pooled env",
+            "environment",
+            "plan",
             "--project",
-            "pool-reuse-proj",
+            "plan-proj",
             "--provider",
-            "noop",
-            "--env-provider",
-            "poolreuse",
-            "--format",
-            "json",
+            "planner",
         ])
         .assert()
         .success()
-        .get_output()
-        .stdout
-        .clone();
-    let task: serde_json::Value = serde_json::from_slice(&task_out).unwrap();
-    let task_id = task["id"].as_str().unwrap().to_string();
-    let task_env_id = task["environment_id"].as_str().unwrap().to_string();
-    assert_eq!(task_env_id, pool_env_id);
-
-    wait_for_env_status(&d, &pool_env_id, "in_use", Duration::from_secs(8));
-
-    let deadline = Instant::now() + Duration::from_secs(8);
-    loop {
-        let tasks_out = d
-            .assert_cmd()
-            .args(["task", "list", "--format", "json"])
-            .assert()
-            .success()
-            .get_output()
-            .stdout
-            .clone();
-        let tasks: Vec<serde_json::Value> = serde_json::from_slice(&tasks_out).unwrap();
-        let status = tasks
-            .iter()
-            .find(|candidate| candidate["id"].as_str() == Some(task_id.as_str()))
-            .and_then(|t| t["status"].as_str())
-            .unwrap_or_default();
-        if status == "complete" || status == "failed" {
-            break;
-        }
-        if Instant::now() >= deadline {
-            panic!("timed out waiting for task completion");
-        }
-        thread::sleep(Duration::from_millis(100));
-    }
+        .stdout(predicate::str::contains("1. create sandbox"))
+        .stdout(predicate::str::contains("~4.0 KB"));
 
     d.assert_cmd()
-        .args(["task", "remove", &task_id])
+        .args(["environment", "list", "--format", "json"])
         .assert()
-        .success();
-
-    let deadline = Instant::now() + Duration::from_secs(12);
-    loop {
-        let tasks_out = d
-            .assert_cmd()
-            .args(["task", "list", "--format", "json"])
-            .assert()
-            .success()
-            .get_output()
-            .stdout
-            .clone();
-        let tasks: Vec<serde_json::Value> = serde_json::from_slice(&tasks_out).unwrap();
-        let task_present = tasks
-            .iter()
-            .any(|candidate| candidate["id"].as_str() == Some(task_id.as_str()));
-
-        let envs_out = d
-            .assert_cmd()
-            .args(["environment", "list", "--format", "json"])
-            .assert()
-            .success()
-            .get_output()
-            .stdout
-            .clone();
-        let envs: Vec<serde_json::Value> = serde_json::from_slice(&envs_out).unwrap();
-        let env_present = envs
-            .iter()
-            .any(|candidate| candidate["id"].as_str() == Some(pool_env_id.as_str()));
-
-        if !task_present && !env_present {
-            break;
-        }
-        if Instant::now() >= deadline {
-            panic!("timed out waiting for task and paired environment removal");
-        }
-        thread::sleep(Duration::from_millis(100));
-    }
+        .success()
+        .stdout(predicate::str::contains(r#""items":[]"#));
 }
 
 #[test]
-fn environment_exec_alias_runs_provider_exec_action_and_completes_provider_commands() {
+fn environment_update_is_queued_and_failure_happens_async() {
     let d = DaemonFixture::start();
 
-    let provider_script = d.work_dir.path().join("execable-env-provider.sh");
+    let provider_script = d.work_dir.path().join("failing-update-provider.sh");
     write_executable_script(
         &provider_script,
         r#"#!/bin/sh
@@ -1416,10 +1728,13 @@ set -eu
 action="$1"
 case "$action" in
   prepare)
-    echo '{"sandbox_id":"sbx-test"}'
+    echo '{}'
     ;;
-  update|claim)
-    cat
+  update)
+    exit 1
+    ;;
+  claim)
+    echo '{}'
     ;;
   remove)
     exit 0
@@ -1427,14 +1742,6 @@ case "$action" in
   run)
     exit 0
     ;;
-  commands)
-    echo '[{"name":"ssh","description":"Connect to sandbox"},{"name":"logs"}]'
-    ;;
-  exec)
-    command="$2"
-    shift 2
-    printf 'exec-action command=%s args=%s metadata=%s\n' "$command" "$*" "${WORK_ENV_METADATA:-}"
-    ;;
   *)
     exit 1
     ;;
@@ -1442,36 +1749,30 @@ esac
 "#,
     );
 
-    let config_dir = d.work_dir.path().join("config");
-    std::fs::create_dir_all(&config_dir).unwrap();
-    std::fs::write(
-        config_dir.join("config.toml"),
-        format!(
-            r#"[environments.providers.execable]
+    d.write_config(format!(
+        r#"[environments.providers.updatefail]
 type = "script"
 path = "{}"
 "#,
-            provider_script.to_string_lossy()
-        ),
-    )
-    .unwrap();
+        provider_script.to_string_lossy()
+    ));
 
-    let proj = d.work_dir.path().join("exec-env-proj");
+    let proj = d.work_dir.path().join("update-env-proj");
     std::fs::create_dir(&proj).unwrap();
     d.assert_cmd()
-        .args(["project", "new", "exec-env-proj", "--path"])
+        .args(["project", "new", "update-env-proj", "--path"])
         .arg(&proj)
         .assert()
         .success();
 
-    let create_out = d
+    let prepare_out = d
         .assert_cmd()
         .args([
             "environment",
-            "create",
-            "exec-env-proj",
+            "prepare",
+            "update-env-proj",
             "--provider",
-            "execable",
+            "updatefail",
             "--format",
             "json",
         ])
@@ -1480,110 +1781,87 @@ path = "{}"
         .get_output()
         .stdout
         .clone();
-    let env: serde_json::Value = serde_json::from_slice(&create_out).unwrap();
+    let env = json_item(&prepare_out);
     let env_id = env["id"].as_str().unwrap().to_string();
-    wait_for_env_status(&d, &env_id, "in_use", Duration::from_secs(8));
-
-    let completion_out = d
-        .assert_cmd()
-        .env("COMPLETE", "bash")
-        .env("_CLAP_COMPLETE_INDEX", "4")
-        .args(["--", "work", "env", "x", &env_id, "s"])
-        .assert()
-        .success()
-        .get_output()
-        .stdout
-        .clone();
-    let completion_text = String::from_utf8(completion_out).unwrap();
-    assert!(
-        completion_text.lines().any(|line| line == "ssh"),
-        "expected env exec completion to include ssh, got: {completion_text:?}"
-    );
+    wait_for_env_status(&d, &env_id, "pool", Duration::from_secs(8));
 
     d.assert_cmd()
-        .args(["env", "x", &env_id, "ssh"])
+        .args(["environment", "update", &env_id, "--format", "json"])
         .assert()
-        .success()
-        .stdout(predicate::str::contains("exec-action command=ssh"))
-        .stdout(predicate::str::contains("\"sandbox_id\":\"sbx-test\""));
+        .success();
+
+    wait_for_env_status(&d, &env_id, "failed", Duration::from_secs(8));
 }
 
 #[test]
-fn task_top_level_aliases_run_task_commands_and_complete_exec_commands() {
+fn task_remove_keeps_task_until_environment_cleanup_succeeds() {
     let d = DaemonFixture::start();
+    let remove_fail_flag = d.work_dir.path().join("remove-fail.flag");
+    std::fs::write(&remove_fail_flag, "1").unwrap();
 
-    let provider_script = d.work_dir.path().join("execable-task-env-provider.sh");
+    let provider_script = d.work_dir.path().join("failing-remove-provider.sh");
     write_executable_script(
         &provider_script,
-        r#"#!/bin/sh
+        &format!(
+            r#"#!/bin/sh
 set -eu
 action="$1"
+fail_flag="{}"
 case "$action" in
-  prepare)
-    echo '{"sandbox_id":"sbx-task"}'
-    ;;
-  update|claim)
-    cat
+  prepare|update|claim)
+    echo '{{}}'
     ;;
   remove)
+    if [ -f "$fail_flag" ]; then
+      exit 1
+    fi
     exit 0
     ;;
   run)
     exit 0
     ;;
-  commands)
-    echo '["ssh","logs"]'
-    ;;
-  exec)
-    command="$2"
-    shift 2
-    printf 'exec-action command=%s args=%s metadata=%s\n' "$command" "$*" "${WORK_ENV_METADATA:-}"
-    ;;
   *)
     exit 1
     ;;
 esac
 "#,
+            remove_fail_flag.to_string_lossy()
+        ),
     );
 
-    let config_dir = d.work_dir.path().join("config");
-    std::fs::create_dir_all(&config_dir).unwrap();
-    std::fs::write(
-        config_dir.join("config.toml"),
-        format!(
-            r#"[tasks.providers.noop]
+    d.write_config(format!(
+        r#"[tasks.providers.noop]
 type = "command"
 command = "sh"
 args = ["-c", "true"]
 
-[environments.providers.execable]
+[environments.providers.removefail]
 type = "script"
 path = "{}"
 "#,
-            provider_script.to_string_lossy()
-        ),
-    )
-    .unwrap();
+        provider_script.to_string_lossy()
+    ));
 
-    let proj = d.work_dir.path().join("exec-task-proj");
+    let proj = d.work_dir.path().join("remove-task-proj");
     std::fs::create_dir(&proj).unwrap();
     d.assert_cmd()
-        .args(["project", "new", "exec-task-proj", "--path"])
+        .args(["project", "new", "remove-task-proj", "--path"])
         .arg(&proj)
         .assert()
         .success();
 
-    let task_out = d
+    let task_create = d
         .assert_cmd()
         .args([
+            "task",
             "new",
-            "task exec flow",
+            "remove-failure-case",
             "--project",
-            "exec-task-proj",
+            "remove-task-proj",
             "--provider",
             "noop",
             "--env-provider",
-            "execable",
+            "removefail",
             "--format",
             "json",
         ])
@@ -1592,90 +1870,4984 @@ path = "{}"
         .get_output()
         .stdout
         .clone();
-    let task: serde_json::Value = serde_json::from_slice(&task_out).unwrap();
+    let task = json_item(&task_create);
     let task_id = task["id"].as_str().unwrap().to_string();
+    let env_id = task["environment_id"].as_str().unwrap().to_string();
 
-    let _ = wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(8));
-
-    let task_list_out = d
-        .assert_cmd()
-        .args(["list", "--format", "json"])
-        .assert()
-        .success()
-        .get_output()
-        .stdout
-        .clone();
-    let tasks: Vec<serde_json::Value> = serde_json::from_slice(&task_list_out).unwrap();
-    assert!(
-        tasks
+    let deadline = Instant::now() + Duration::from_secs(8);
+    loop {
+        let tasks_out = d
+            .assert_cmd()
+            .args(["task", "list", "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let tasks = json_items(&tasks_out);
+        let status = tasks
             .iter()
-            .any(|candidate| candidate["id"].as_str() == Some(task_id.as_str())),
-        "expected top-level list to include task {task_id}"
-    );
+            .find(|candidate| candidate["id"].as_str() == Some(task_id.as_str()))
+            .and_then(|t| t["status"].as_str())
+            .unwrap_or_default();
+        if work::db::TERMINAL_TASK_STATUSES.contains(&status) {
+            break;
+        }
+        if Instant::now() >= deadline {
+            panic!("timed out waiting for task completion before remove");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
 
-    d.assert_cmd().args(["logs", &task_id]).assert().success();
+    d.assert_cmd()
+        .args(["task", "logs", &task_id])
+        .assert()
+        .success();
+    d.assert_cmd()
+        .args(["task", "logs", &task_id, "--follow"])
+        .assert()
+        .success();
 
-    let completion_out = d
-        .assert_cmd()
-        .env("COMPLETE", "bash")
-        .env("_CLAP_COMPLETE_INDEX", "4")
-        .args(["--", "work", "task", "x", &task_id, "s"])
+    d.assert_cmd()
+        .args(["task", "remove", &task_id])
         .assert()
-        .success()
-        .get_output()
-        .stdout
-        .clone();
-    let completion_text = String::from_utf8(completion_out).unwrap();
-    assert!(
-        completion_text.lines().any(|line| line == "ssh"),
-        "expected task exec completion to include ssh, got: {completion_text:?}"
+        .success();
+
+    wait_for_env_status(&d, &env_id, "failed", Duration::from_secs(12));
+
+    let deadline = Instant::now() + Duration::from_secs(8);
+    loop {
+        let tasks_out = d
+            .assert_cmd()
+            .args(["task", "list", "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let tasks = json_items(&tasks_out);
+        if tasks
+            .iter()
+            .any(|candidate| candidate["id"].as_str() == Some(task_id.as_str()))
+        {
+            break;
+        }
+        if Instant::now() >= deadline {
+            panic!("task was deleted even though environment removal failed");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    std::fs::remove_file(&remove_fail_flag).unwrap();
+
+    d.assert_cmd()
+        .args(["task", "remove", &task_id])
+        .assert()
+        .success();
+
+    let deadline = Instant::now() + Duration::from_secs(12);
+    loop {
+        let tasks_out = d
+            .assert_cmd()
+            .args(["task", "list", "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let tasks = json_items(&tasks_out);
+        if tasks
+            .iter()
+            .all(|candidate| candidate["id"].as_str() != Some(task_id.as_str()))
+        {
+            break;
+        }
+        if Instant::now() >= deadline {
+            panic!("task was not deleted after retrying removal");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn environment_remove_skip_provider_force_deletes_after_provider_failure() {
+    let d = DaemonFixture::start();
+
+    let provider_script = d.work_dir.path().join("always-fail-remove-env.sh");
+    write_executable_script(
+        &provider_script,
+        r#"#!/bin/sh
+set -eu
+action="$1"
+case "$action" in
+  prepare|update|claim)
+    echo '{}'
+    ;;
+  remove)
+    exit 1
+    ;;
+  run)
+    exit 0
+    ;;
+  *)
+    exit 1
+    ;;
+esac
+"#,
     );
 
-    let top_level_completion_out = d
+    d.write_config(format!(
+        r#"[environments.providers.removefail]
+type = "script"
+path = "{}"
+"#,
+        provider_script.to_string_lossy()
+    ));
+
+    let proj = d.work_dir.path().join("remove-env-skip-provider-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "remove-env-skip-provider-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let prepare_out = d
         .assert_cmd()
-        .env("COMPLETE", "bash")
-        .env("_CLAP_COMPLETE_INDEX", "3")
-        .args(["--", "work", "exec", &task_id, "s"])
+        .args([
+            "env",
+            "prepare",
+            "remove-env-skip-provider-proj",
+            "--provider",
+            "removefail",
+            "--format",
+            "json",
+        ])
         .assert()
         .success()
         .get_output()
         .stdout
         .clone();
-    let top_level_completion_text = String::from_utf8(top_level_completion_out).unwrap();
-    assert!(
-        top_level_completion_text.lines().any(|line| line == "ssh"),
-        "expected top-level exec completion to include ssh, got: {top_level_completion_text:?}"
+    let env = json_item(&prepare_out);
+    let env_id = env["id"].as_str().unwrap().to_string();
+    wait_for_env_status(&d, &env_id, "pool", Duration::from_secs(8));
+
+    d.assert_cmd()
+        .args(["env", "rm", &env_id])
+        .assert()
+        .success();
+    wait_for_env_status(&d, &env_id, "failed", Duration::from_secs(12));
+
+    d.assert_cmd()
+        .args(["env", "rm", &env_id, "--skip-provider", "--yes"])
+        .assert()
+        .success();
+
+    let deadline = Instant::now() + Duration::from_secs(8);
+    loop {
+        let envs_out = d
+            .assert_cmd()
+            .args(["env", "ls", "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let envs = json_items(&envs_out);
+        if envs
+            .iter()
+            .all(|candidate| candidate["id"].as_str() != Some(env_id.as_str()))
+        {
+            break;
+        }
+        if Instant::now() >= deadline {
+            panic!("environment not force-deleted with --skip-provider");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn task_remove_skip_provider_force_deletes_after_provider_failure() {
+    let d = DaemonFixture::start();
+
+    let provider_script = d.work_dir.path().join("always-fail-remove-task-env.sh");
+    write_executable_script(
+        &provider_script,
+        r#"#!/bin/sh
+set -eu
+action="$1"
+case "$action" in
+  prepare|update|claim)
+    echo '{}'
+    ;;
+  remove)
+    exit 1
+    ;;
+  run)
+    exit 0
+    ;;
+  *)
+    exit 1
+    ;;
+esac
+"#,
     );
 
+    d.write_config(format!(
+        r#"[tasks.providers.noop]
+type = "command"
+command = "sh"
+args = ["-c", "true"]
+
+[environments.providers.removefail]
+type = "script"
+path = "{}"
+"#,
+        provider_script.to_string_lossy()
+    ));
+
+    let proj = d.work_dir.path().join("remove-task-skip-provider-proj");
+    std::fs::create_dir(&proj).unwrap();
     d.assert_cmd()
-        .args(["exec", &task_id, "ssh"])
+        .args(["project", "new", "remove-task-skip-provider-proj", "--path"])
+        .arg(&proj)
         .assert()
-        .success()
-        .stdout(predicate::str::contains("exec-action command=ssh"))
-        .stdout(predicate::str::contains("\"sandbox_id\":\"sbx-task\""));
+        .success();
 
-    d.assert_cmd().args(["remove", &task_id]).assert().success();
+    let task_create = d
+        .assert_cmd()
+        .args([
+            "task",
+            "new",
+            "remove-skip-provider-case",
+            "--project",
+            "remove-task-skip-provider-proj",
+            "--provider",
+            "noop",
+            "--env-provider",
+            "removefail",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&task_create);
+    let task_id = task["id"].as_str().unwrap().to_string();
+    let env_id = task["environment_id"].as_str().unwrap().to_string();
 
-    let deadline = Instant::now() + Duration::from_secs(12);
+    let deadline = Instant::now() + Duration::from_secs(8);
     loop {
         let tasks_out = d
             .assert_cmd()
-            .args(["list", "--format", "json"])
+            .args(["task", "list", "--format", "json"])
             .assert()
             .success()
             .get_output()
             .stdout
             .clone();
-        let tasks: Vec<serde_json::Value> = serde_json::from_slice(&tasks_out).unwrap();
-        if tasks
+        let tasks = json_items(&tasks_out);
+        let status = tasks
             .iter()
-            .all(|candidate| candidate["id"].as_str() != Some(task_id.as_str()))
-        {
+            .find(|candidate| candidate["id"].as_str() == Some(task_id.as_str()))
+            .and_then(|t| t["status"].as_str())
+            .unwrap_or_default();
+        if work::db::TERMINAL_TASK_STATUSES.contains(&status) {
             break;
         }
         if Instant::now() >= deadline {
-            panic!("task was not deleted by top-level remove alias");
+            panic!("timed out waiting for task completion before remove");
         }
         thread::sleep(Duration::from_millis(100));
     }
+
+    d.assert_cmd()
+        .args(["task", "rm", &task_id])
+        .assert()
+        .success();
+    wait_for_env_status(&d, &env_id, "failed", Duration::from_secs(12));
+
+    d.assert_cmd()
+        .args(["task", "rm", &task_id, "--skip-provider", "--yes"])
+        .assert()
+        .success();
+
+    let deadline = Instant::now() + Duration::from_secs(8);
+    loop {
+        let tasks_out = d
+            .assert_cmd()
+            .args(["task", "ls", "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let tasks = json_items(&tasks_out);
+        let task_present = tasks
+            .iter()
+            .any(|candidate| candidate["id"].as_str() == Some(task_id.as_str()));
+
+        let envs_out = d
+            .assert_cmd()
+            .args(["env", "ls", "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let envs = json_items(&envs_out);
+        let env_present = envs
+            .iter()
+            .any(|candidate| candidate["id"].as_str() == Some(env_id.as_str()));
+
+        if !task_present && !env_present {
+            break;
+        }
+        if Instant::now() >= deadline {
+            panic!("task/environment not force-deleted with --skip-provider");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn task_new_claims_pool_environment_and_task_remove_deletes_paired_environment() {
+    let d = DaemonFixture::start();
+
+    let provider_script = d.work_dir.path().join("pool-reuse-provider.sh");
+    write_executable_script(
+        &provider_script,
+        r#"#!/bin/sh
+set -eu
+action="$1"
+case "$action" in
+  prepare)
+    echo '{"worktree_path":"/tmp/fake","prepared":true}'
+    ;;
+  update|claim)
+    echo '{}'
+    ;;
+  remove)
+    exit 0
+    ;;
+  run)
+    exit 0
+    ;;
+  *)
+    exit 1
+    ;;
+esac
+"#,
+    );
+
+    d.write_config(format!(
+        r#"[tasks.providers.noop]
+type = "command"
+command = "sh"
+args = ["-c", "true"]
+
+[environments.providers.poolreuse]
+type = "script"
+path = "{}"
+"#,
+        provider_script.to_string_lossy()
+    ));
+
+    let proj = d.work_dir.path().join("pool-reuse-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "pool-reuse-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let prepare_out = d
+        .assert_cmd()
+        .args([
+            "environment",
+            "prepare",
+            "pool-reuse-proj",
+            "--provider",
+            "poolreuse",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let prepared_env = json_item(&prepare_out);
+    let pool_env_id = prepared_env["id"].as_str().unwrap().to_string();
+    wait_for_env_status(&d, &pool_env_id, "pool", Duration::from_secs(8));
+
+    let task_out = d
+        .assert_cmd()
+        .args([
+            "task",
+            "new",
+            "reuse pooled env",
+            "--project",
+            "pool-reuse-proj",
+            "--provider",
+            "noop",
+            "--env-provider",
+            "poolreuse",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&task_out);
+    let task_id = task["id"].as_str().unwrap().to_string();
+    let task_env_id = task["environment_id"].as_str().unwrap().to_string();
+    assert_eq!(task_env_id, pool_env_id);
+
+    wait_for_env_status(&d, &pool_env_id, "in_use", Duration::from_secs(8));
+
+    let deadline = Instant::now() + Duration::from_secs(8);
+    loop {
+        let tasks_out = d
+            .assert_cmd()
+            .args(["task", "list", "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let tasks = json_items(&tasks_out);
+        let status = tasks
+            .iter()
+            .find(|candidate| candidate["id"].as_str() == Some(task_id.as_str()))
+            .and_then(|t| t["status"].as_str())
+            .unwrap_or_default();
+        if work::db::TERMINAL_TASK_STATUSES.contains(&status) {
+            break;
+        }
+        if Instant::now() >= deadline {
+            panic!("timed out waiting for task completion");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    d.assert_cmd()
+        .args(["task", "remove", &task_id])
+        .assert()
+        .success();
+
+    let deadline = Instant::now() + Duration::from_secs(12);
+    loop {
+        let tasks_out = d
+            .assert_cmd()
+            .args(["task", "list", "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let tasks = json_items(&tasks_out);
+        let task_present = tasks
+            .iter()
+            .any(|candidate| candidate["id"].as_str() == Some(task_id.as_str()));
+
+        let envs_out = d
+            .assert_cmd()
+            .args(["environment", "list", "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let envs = json_items(&envs_out);
+        let env_present = envs
+            .iter()
+            .any(|candidate| candidate["id"].as_str() == Some(pool_env_id.as_str()));
+
+        if !task_present && !env_present {
+            break;
+        }
+        if Instant::now() >= deadline {
+            panic!("timed out waiting for task and paired environment removal");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn task_new_skips_dirty_pooled_worktree() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.noop]
+type = "command"
+command = "sh"
+args = ["-c", "true"]
+"#,
+    );
+
+    let proj = d.work_dir.path().join("dirty-pool-proj");
+    std::fs::create_dir(&proj).unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(&proj)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&["init", "-q"]);
+    git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+    d.assert_cmd()
+        .args(["project", "new", "dirty-pool-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let prepare_out = d
+        .assert_cmd()
+        .args([
+            "environment",
+            "prepare",
+            "dirty-pool-proj",
+            "--provider",
+            "git-worktree",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let prepared_env = json_item(&prepare_out);
+    let pool_env_id = prepared_env["id"].as_str().unwrap().to_string();
+    wait_for_env_status(&d, &pool_env_id, "pool", Duration::from_secs(8));
+
+    let list_envs = || -> Vec<serde_json::Value> {
+        let out = d
+            .assert_cmd()
+            .args(["environment", "list", "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        json_items(&out)
+    };
+    let find_env = |id: &str| {
+        list_envs()
+            .into_iter()
+            .find(|env| env["id"].as_str() == Some(id))
+            .unwrap()
+    };
+    let worktree = find_env(&pool_env_id)["metadata"]["worktree_path"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    std::fs::write(Path::new(&worktree).join("leftover.txt"), "stale").unwrap();
+
+    let task_out = d
+        .assert_cmd()
+        .args([
+            "task",
+            "new",
+            "start clean",
+            "--project",
+            "dirty-pool-proj",
+            "--provider",
+            "noop",
+            "--env-provider",
+            "git-worktree",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&task_out);
+    let task_id = task["id"].as_str().unwrap().to_string();
+    assert_eq!(task["environment_id"], pool_env_id.as_str());
+
+    wait_for_env_status(&d, &pool_env_id, "degraded", Duration::from_secs(8));
+    let degraded = find_env(&pool_env_id);
+    assert_eq!(
+        degraded["metadata"]["degraded_reason"],
+        "worktree has 1 uncommitted change"
+    );
+
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(12)),
+        "complete"
+    );
+    let out = d
+        .assert_cmd()
+        .args(["task", "show", &task_id, "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&out);
+    let new_env_id = task["environment_id"].as_str().unwrap();
+    assert_ne!(new_env_id, pool_env_id);
+    let new_worktree = find_env(new_env_id)["metadata"]["worktree_path"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(!Path::new(&new_worktree).join("leftover.txt").exists());
+}
+
+#[test]
+fn watched_projects_mark_pooled_environments_outdated_when_the_base_branch_moves() {
+    let d = DaemonFixture::start();
+
+    d.write_config("[daemon]\nwatch-projects = true\n");
+
+    let proj = d.work_dir.path().join("watched-proj");
+    std::fs::create_dir(&proj).unwrap();
+    let git = |cwd: &Path, args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(cwd)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&proj, &["init", "-q", "-b", "main"]);
+    git(&proj, &["commit", "-q", "--allow-empty", "-m", "init"]);
+    d.assert_cmd()
+        .args(["project", "new", "watched-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let prepare_out = d
+        .assert_cmd()
+        .args([
+            "environment",
+            "prepare",
+            "watched-proj",
+            "--provider",
+            "git-worktree",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let env_id = json_item(&prepare_out)["id"].as_str().unwrap().to_string();
+    wait_for_env_status(&d, &env_id, "pool", Duration::from_secs(8));
+
+    let find_env = || {
+        let out = d
+            .assert_cmd()
+            .args(["environment", "list", "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        json_items(&out)
+            .into_iter()
+            .find(|env| env["id"].as_str() == Some(env_id.as_str()))
+            .unwrap()
+    };
+    let wait_for_outdated = |outdated: bool| {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let env = find_env();
+            if env["outdated_since"].is_string() == outdated {
+                return env;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "environment never became {}: {env}",
+                if outdated { "outdated" } else { "current" }
+            );
+            thread::sleep(Duration::from_millis(200));
+        }
+    };
+    assert!(find_env()["outdated_since"].is_null());
+
+    git(&proj, &["commit", "-q", "--allow-empty", "-m", "moved on"]);
+    wait_for_outdated(true);
+    d.assert_cmd()
+        .args(["environment", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("pool (outdated)"));
+    let events = d
+        .assert_cmd()
+        .args(["events", "--format", "plain"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert!(
+        String::from_utf8_lossy(&events).lines().any(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            fields[3] == env_id && fields[4] == "outdated"
+        }),
+        "no outdated event"
+    );
+
+    // Catching up moves the environment's own branch, which is watched too.
+    let worktree = find_env()["metadata"]["worktree_path"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    git(Path::new(&worktree), &["merge", "-q", "--no-edit", "main"]);
+    wait_for_outdated(false);
+}
+
+#[test]
+fn expired_task_whose_environment_is_gone_is_auto_removed() {
+    let mut d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks]
+auto-remove-after = "1d"
+
+[tasks.providers.quick]
+type = "command"
+command = "true"
+
+[environments.providers.sandbox]
+type = "mock"
+"#,
+    );
+    let proj = tempfile::TempDir::new().unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "expiring-proj", "--path"])
+        .arg(proj.path())
+        .assert()
+        .success();
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "new", "old news", "--project", "expiring-proj"])
+        .args(["--provider", "quick", "--env-provider", "sandbox"])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&out);
+    let task_id = task["id"].as_str().unwrap().to_string();
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+        "complete"
+    );
+
+    // Finished long ago, and its environment went missing since.
+    let conn = rusqlite::Connection::open(d.work_dir.path().join("data/database.sqlite3")).unwrap();
+    conn.execute_batch("PRAGMA foreign_keys = OFF").unwrap();
+    conn.execute(
+        "DELETE FROM environments WHERE id = ?1",
+        [task["environment_id"].as_str().unwrap()],
+    )
+    .unwrap();
+    conn.execute(
+        "UPDATE task_status_times SET changed_at = '2020-01-01T00:00:00+00:00' WHERE id = ?1",
+        [&task_id],
+    )
+    .unwrap();
+
+    // Retention runs when the daemon starts.
+    d.restart();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let shown = d.cmd().args(["task", "show", &task_id]).output().unwrap();
+        if !shown.status.success() {
+            break;
+        }
+        assert!(Instant::now() < deadline, "expired task was not removed");
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn task_log_is_capped_with_truncation_marker() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks]
+max-log-mb = 0.01
+
+[tasks.providers.chatty]
+type = "command"
+command = "sh"
+args = ["-c", "i=0; while [ $i -lt 5000 ]; do echo \"line $i\"; i=$((i+1)); done; echo 'all done' >&2"]
+"#,
+    );
+
+    let proj = d.work_dir.path().join("chatty-proj");
+    std::fs::create_dir(&proj).unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(&proj)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&["init", "-q"]);
+    git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+    d.assert_cmd()
+        .args(["project", "new", "chatty-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let task_out = d
+        .assert_cmd()
+        .args([
+            "task",
+            "new",
+            "print a lot",
+            "--project",
+            "chatty-proj",
+            "--provider",
+            "chatty",
+            "--env-provider",
+            "git-worktree",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&task_out);
+    let task_id = task["id"].as_str().unwrap().to_string();
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+        "complete"
+    );
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "logs", &task_id])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let log = String::from_utf8(out).unwrap();
+    assert!(log.len() <= 10_485 + 100, "log is {} bytes", log.len());
+    assert!(log.starts_with("line 0\nline 1\n"));
+    assert!(log.contains("bytes of output omitted to stay under max-log-mb"));
+    assert!(log.contains("line 4999\n"));
+    assert!(log.ends_with("all done\n"));
+}
+
+#[test]
+fn redact_patterns_keep_secrets_out_of_task_logs_and_descriptions() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[redact]
+patterns = ["ghp_[A-Za-z0-9]+"]
+
+[tasks.providers.leaky]
+type = "command"
+command = "sh"
+args = ["-c", "printf 'cloning with ghp_'; sleep 0.2; echo 'secret123 done'"]
+"#,
+    );
+
+    let proj = d.work_dir.path().join("leaky-proj");
+    std::fs::create_dir(&proj).unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(&proj)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&["init", "-q"]);
+    git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+    d.assert_cmd()
+        .args(["project", "new", "leaky-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let task_out = d
+        .assert_cmd()
+        .args([
+            "task",
+            "new",
+            "push with ghp_abc123",
+            "--project",
+            "leaky-proj",
+            "--provider",
+            "leaky",
+            "--env-provider",
+            "git-worktree",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task_id = json_item(&task_out)["id"].as_str().unwrap().to_string();
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+        "complete"
+    );
+
+    // The secret was split across two writes, and still never reached the
+    // file on disk.
+    let log_path = d
+        .work_dir
+        .path()
+        .join("data/logs/tasks")
+        .join(format!("{task_id}.log"));
+    let raw = std::fs::read_to_string(&log_path).unwrap();
+    assert!(raw.contains("cloning with [redacted] done\n"), "{raw}");
+    assert!(!raw.contains("secret123"), "{raw}");
+
+    // Served logs are redacted too, which covers anything written before the
+    // pattern was added.
+    std::fs::write(&log_path, "old ghp_older1 output\n").unwrap();
+    let out = d
+        .assert_cmd()
+        .args(["task", "logs", &task_id])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(String::from_utf8(out).unwrap(), "old [redacted] output\n");
+
+    // The daemon's patterns apply, not whatever config.toml now holds.
+    let config_path = d.work_dir.path().join("config/config.toml");
+    let config = std::fs::read_to_string(&config_path).unwrap();
+    std::fs::write(&config_path, "").unwrap();
+    let out = d
+        .assert_cmd()
+        .args(["task", "logs", &task_id])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(String::from_utf8(out).unwrap(), "old [redacted] output\n");
+    std::fs::write(&config_path, config).unwrap();
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "show", &task_id, "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(json_item(&out)["description"], "push with [redacted]");
+    let out = d
+        .assert_cmd()
+        .args(["task", "list", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert!(!String::from_utf8(out).unwrap().contains("ghp_abc123"));
+
+    // So are search results and exports.
+    let out = d
+        .assert_cmd()
+        .args(["search", "push", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("push with [redacted]"), "{out}");
+    assert!(!out.contains("ghp_abc123"), "{out}");
+    let export_path = d.work_dir.path().join("state.json");
+    d.assert_cmd()
+        .args(["export", "--tasks", "--output"])
+        .arg(&export_path)
+        .assert()
+        .success();
+    let export = std::fs::read_to_string(&export_path).unwrap();
+    assert!(export.contains("push with [redacted]"), "{export}");
+    assert!(!export.contains("ghp_abc123"), "{export}");
+}
+
+#[test]
+fn task_logs_list_group_sections() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.grouped]
+type = "command"
+command = "sh"
+args = ["-c", "echo start; echo '::group::Install deps'; echo a; echo b; echo '::endgroup::'; echo '::group::Test'; echo ok"]
+
+[environments.providers.sandbox]
+type = "mock"
+"#,
+    );
+    let proj = tempfile::TempDir::new().unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "grouped-proj", "--path"])
+        .arg(proj.path())
+        .assert()
+        .success();
+
+    let task_out = d
+        .assert_cmd()
+        .args(["task", "new", "sections", "--project", "grouped-proj"])
+        .args(["--provider", "grouped", "--env-provider", "sandbox"])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task_id = json_item(&task_out)["id"].as_str().unwrap().to_string();
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+        "complete"
+    );
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "logs", &task_id, "--sections"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let listed = String::from_utf8(out).unwrap();
+    let rows: Vec<Vec<&str>> = listed
+        .lines()
+        .skip(1)
+        .map(|line| {
+            line.split("  ")
+                .map(str::trim)
+                .filter(|c| !c.is_empty())
+                .collect()
+        })
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            vec!["2-5", "2 lines", "Install deps"],
+            vec!["6-", "open", "Test"],
+        ],
+        "{listed}"
+    );
+}
+
+#[test]
+fn status_counts_the_current_projects_tasks() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.ok]
+type = "command"
+command = "true"
+
+[tasks.providers.bad]
+type = "command"
+command = "false"
+
+[environments.providers.sandbox]
+type = "mock"
+"#,
+    );
+    let proj = tempfile::TempDir::new().unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "counted", "--path"])
+        .arg(proj.path())
+        .assert()
+        .success();
+
+    for provider in ["ok", "bad"] {
+        let out = d
+            .assert_cmd()
+            .args(["task", "new", provider, "--project", "counted"])
+            .args(["--provider", provider, "--env-provider", "sandbox"])
+            .args(["--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let task_id = json_item(&out)["id"].as_str().unwrap().to_string();
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20));
+    }
+
+    d.assert_cmd()
+        .current_dir(proj.path())
+        .args(["status", "--porcelain"])
+        .assert()
+        .success()
+        .stdout("project=counted running=0 pending=0 failed=1 pool=0\n");
+
+    // Outside every project there's nothing to count.
+    d.assert_cmd()
+        .current_dir(d.work_dir.path())
+        .args(["status", "--porcelain"])
+        .assert()
+        .success()
+        .stdout("");
+}
+
+#[test]
+fn mock_providers_run_tasks_without_scripts() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[daemon.retries.prepare_environment]
+limit = 0
+
+[tasks.providers.fake]
+type = "mock"
+output = ["working on: {task_description}", "done"]
+latency-ms = 100
+summary = "did it"
+
+[tasks.providers.broken]
+type = "mock"
+exit-code = 3
+
+[environments.providers.sandbox]
+type = "mock"
+latency-ms = 50
+
+[environments.providers.unclaimable]
+type = "mock"
+fail = ["claim"]
+
+[environments.providers.unrunnable]
+type = "mock"
+fail = ["run"]
+"#,
+    );
+
+    let proj = d.work_dir.path().join("mock-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "mock-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let run = |description: &str, provider: &str, env_provider: &str| -> (String, String) {
+        let out = d
+            .assert_cmd()
+            .args(["task", "new", description, "--project", "mock-proj"])
+            .args(["--provider", provider, "--env-provider", env_provider])
+            .args(["--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let task_id = json_item(&out)["id"].as_str().unwrap().to_string();
+        let status = wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20));
+        (task_id, status)
+    };
+
+    let (task_id, status) = run("tidy up", "fake", "sandbox");
+    assert_eq!(status, "complete");
+    d.assert_cmd()
+        .args(["task", "logs", &task_id])
+        .assert()
+        .success()
+        .stdout("working on: tidy up\ndone\n");
+    let out = d
+        .assert_cmd()
+        .args(["task", "show", &task_id, "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(json_item(&out)["result_summary"], "did it");
+
+    let (_, status) = run("fall over", "broken", "sandbox");
+    assert_eq!(status, "failed");
+
+    let (_, status) = run("never start", "fake", "unclaimable");
+    assert_eq!(status, "failed");
+
+    // A run that can't start fails its task once, without retrying a job
+    // whose claim is already spent.
+    let (task_id, status) = run("no runner", "fake", "unrunnable");
+    assert_eq!(status, "failed");
+    let out = d
+        .assert_cmd()
+        .args(["job", "list", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let jobs = json_items(&out);
+    let run_job = jobs
+        .iter()
+        .find(|job| job["type"] == "run_task" && job["payload"]["task_id"] == task_id.as_str())
+        .expect("run_task job");
+    assert_eq!(run_job["status"], "complete");
+    assert_eq!(run_job["attempt"], 1);
+}
+
+#[test]
+fn chaos_mode_fails_jobs_until_their_retries_run_out() {
+    let d = DaemonFixture::start_with_env(&[("WORK_CHAOS", "prepare_environment:1")]);
+
+    d.write_config(
+        r#"[daemon.retries.prepare_environment]
+limit = 2
+max-delay = 1
+
+[tasks.providers.fake]
+type = "mock"
+
+[environments.providers.sandbox]
+type = "mock"
+"#,
+    );
+
+    let proj = d.work_dir.path().join("chaos-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "chaos-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "new", "unlucky", "--project", "chaos-proj"])
+        .args(["--provider", "fake", "--env-provider", "sandbox"])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task_id = json_item(&out)["id"].as_str().unwrap().to_string();
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(10)),
+        "failed"
+    );
+
+    let out = d
+        .assert_cmd()
+        .args(["job", "list", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let jobs = json_items(&out);
+    let dead: Vec<&serde_json::Value> = jobs
+        .iter()
+        .filter(|job| job["status"].as_str() == Some("dead"))
+        .collect();
+    assert_eq!(dead.len(), 1);
+    // Every attempt was failed on purpose.
+    let errors = dead[0]["errors"].as_array().unwrap();
+    assert!(errors.len() > 1, "job was not retried: {}", dead[0]);
+    assert_eq!(Some(errors.len() as u64), dead[0]["attempt"].as_u64());
+    for error in errors {
+        assert!(
+            error
+                .to_string()
+                .contains("chaos: injected prepare_environment failure"),
+            "unexpected error {error}"
+        );
+    }
+}
+
+#[test]
+fn job_traces_replay_in_order_against_mock_providers() {
+    let trace_dir = tempfile::TempDir::new().unwrap();
+    let trace = trace_dir.path().join("trace.jsonl");
+    let d = DaemonFixture::start_with_env(&[("WORK_TRACE", trace.to_str().unwrap())]);
+
+    d.write_config(
+        r#"[daemon.retries.prepare_environment]
+limit = 2
+max-delay = 1
+
+[tasks.providers.fake]
+type = "mock"
+
+[environments.providers.sandbox]
+type = "mock"
+
+[environments.providers.unclaimable]
+type = "mock"
+fail = ["claim"]
+"#,
+    );
+
+    let proj = d.work_dir.path().join("trace-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "trace-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let run = |description: &str, env_provider: &str| {
+        let out = d
+            .assert_cmd()
+            .args(["task", "new", description, "--project", "trace-proj"])
+            .args(["--provider", "fake", "--env-provider", env_provider])
+            .args(["--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let task_id = json_item(&out)["id"].as_str().unwrap().to_string();
+        let status = wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(10));
+        (task_id, status)
+    };
+    let (fine, status) = run("works", "sandbox");
+    assert_eq!(status, "complete");
+    let (stuck, status) = run("never starts", "unclaimable");
+    assert_eq!(status, "failed");
+
+    let records: Vec<serde_json::Value> = std::fs::read_to_string(&trace)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert!(records.iter().any(|r| r["event"] == "claim"
+        && r["job"]["type"] == "run_task"
+        && r["task"]["id"] == fine.as_str()));
+    let outcomes: Vec<&str> = records
+        .iter()
+        .filter(|r| r["event"] == "finish" && r["error"] == "mock claim failed")
+        .map(|r| r["outcome"].as_str().unwrap())
+        .collect();
+    assert_eq!(outcomes, ["retry", "dead"]);
+
+    let scratch = trace_dir.path().join("replay");
+    let out = d
+        .assert_cmd()
+        .args(["debug", "replay"])
+        .arg(&trace)
+        .arg("--home")
+        .arg(&scratch)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("run_task"), "{out}");
+    assert!(!out.contains("differs"), "{out}");
+
+    // The replay left its own records behind, and never touched the real
+    // ones.
+    let out = d
+        .assert_cmd()
+        .arg("--work-home")
+        .arg(&scratch)
+        .args(["task", "list", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let tasks = json_items(&out);
+    let status = |id: &str| {
+        tasks
+            .iter()
+            .find(|t| t["id"] == id)
+            .map(|t| t["status"].as_str().unwrap().to_string())
+    };
+    assert_eq!(status(&fine).as_deref(), Some("complete"));
+    assert_eq!(status(&stuck).as_deref(), Some("failed"));
+    assert!(tasks.iter().all(|t| t["provider"] == "replay"));
+
+    d.assert_cmd()
+        .args(["debug", "replay"])
+        .arg(&trace)
+        .arg("--home")
+        .arg(&scratch)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("isn't empty"));
+}
+
+#[test]
+fn run_task_job_reclaimed_after_its_lease_expires_does_not_run_the_task_again() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.slow]
+type = "mock"
+output = ["ran once"]
+latency-ms = 2000
+
+[environments.providers.sandbox]
+type = "mock"
+"#,
+    );
+
+    let proj = d.work_dir.path().join("claim-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "claim-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "new", "run me once", "--project", "claim-proj"])
+        .args(["--provider", "slow", "--env-provider", "sandbox"])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task_id = json_item(&out)["id"].as_str().unwrap().to_string();
+
+    // Expire the running job's lease, as if the daemon had stalled, so the
+    // job processor claims it again while the runner is still going.
+    let conn = rusqlite::Connection::open(d.work_dir.path().join("data/database.sqlite3")).unwrap();
+    conn.busy_timeout(Duration::from_secs(5)).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let expired = conn
+            .execute(
+                "UPDATE jobs SET lease_expires_at = '2000-01-01T00:00:00+00:00'
+                 WHERE type = 'run_task' AND status = 'running'
+                 AND json_extract(payload, '$.task_id') = ?1",
+                rusqlite::params![&task_id],
+            )
+            .unwrap();
+        if expired == 1 {
+            break;
+        }
+        assert!(Instant::now() < deadline, "run_task job never started");
+        thread::sleep(Duration::from_millis(50));
+    }
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let attempt: i64 = conn
+            .query_row(
+                "SELECT attempt FROM jobs WHERE type = 'run_task'
+                 AND json_extract(payload, '$.task_id') = ?1",
+                rusqlite::params![&task_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        if attempt == 2 {
+            break;
+        }
+        assert!(Instant::now() < deadline, "expired job was not reclaimed");
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+        "complete"
+    );
+    d.assert_cmd()
+        .args(["task", "logs", &task_id])
+        .assert()
+        .success()
+        .stdout("ran once\n");
+}
+
+#[test]
+fn task_log_timestamps_prefix_lines_and_raw_logs_leave_them_out() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks]
+log-timestamps = true
+
+[tasks.providers.both]
+type = "command"
+command = "sh"
+args = ["-c", "echo out; sleep 0.2; echo err >&2"]
+"#,
+    );
+
+    let proj = d.work_dir.path().join("stamp-proj");
+    std::fs::create_dir(&proj).unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(&proj)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&["init", "-q"]);
+    git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+    d.assert_cmd()
+        .args(["project", "new", "stamp-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let task_out = d
+        .assert_cmd()
+        .args([
+            "task",
+            "new",
+            "print to both streams",
+            "--project",
+            "stamp-proj",
+            "--provider",
+            "both",
+            "--env-provider",
+            "git-worktree",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&task_out);
+    let task_id = task["id"].as_str().unwrap().to_string();
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+        "complete"
+    );
+
+    let logs = |extra: &[&str]| -> String {
+        let out = d
+            .assert_cmd()
+            .args(["task", "logs", &task_id])
+            .args(extra)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        String::from_utf8(out).unwrap()
+    };
+
+    let stamped = logs(&[]);
+    let lines: Vec<&str> = stamped.lines().collect();
+    assert_eq!(lines.len(), 2, "log was {stamped:?}");
+    for (line, expected) in lines.iter().zip(["stdout out", "stderr err"]) {
+        let (at, rest) = line.split_once(' ').unwrap();
+        assert!(
+            chrono::DateTime::parse_from_rfc3339(at).is_ok(),
+            "bad timestamp in {line:?}"
+        );
+        assert!(at.ends_with('Z'), "timestamp not in UTC: {line:?}");
+        assert_eq!(rest, expected);
+    }
+
+    assert_eq!(logs(&["--raw"]), "out\nerr\n");
+}
+
+#[test]
+fn task_note_is_recorded_and_appended_to_the_log() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.echo]
+type = "command"
+command = "echo"
+args = ["runner output"]
+"#,
+    );
+
+    let proj = d.work_dir.path().join("note-proj");
+    std::fs::create_dir(&proj).unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(&proj)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&["init", "-q"]);
+    git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+    d.assert_cmd()
+        .args(["project", "new", "note-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let task_out = d
+        .assert_cmd()
+        .args([
+            "task",
+            "new",
+            "say something",
+            "--project",
+            "note-proj",
+            "--provider",
+            "echo",
+            "--env-provider",
+            "git-worktree",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&task_out);
+    let task_id = task["id"].as_str().unwrap().to_string();
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+        "complete"
+    );
+
+    let out = d
+        .assert_cmd()
+        .args([
+            "task",
+            "note",
+            &task_id,
+            "  retried after flaky network  ",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&out);
+    let notes = task["notes"].as_array().unwrap();
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0]["text"], "retried after flaky network");
+    let at = notes[0]["at"].as_str().unwrap();
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "logs", &task_id])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let log = String::from_utf8(out).unwrap();
+    assert!(log.starts_with("runner output\n"), "{log}");
+    assert!(
+        log.ends_with(&format!("[work] {at} note: retried after flaky network\n")),
+        "{log}"
+    );
+
+    d.assert_cmd()
+        .args(["task", "note", &task_id, "   "])
+        .assert()
+        .failure();
+    d.assert_cmd()
+        .args(["task", "note", "no-such-task", "hello"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn task_note_on_a_pending_task_reaches_the_log() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.echo]
+type = "command"
+command = "echo"
+args = ["runner output"]
+
+[environments.providers.slow]
+type = "mock"
+latency-ms = 2000
+"#,
+    );
+    let proj = tempfile::TempDir::new().unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "pending-note-proj", "--path"])
+        .arg(proj.path())
+        .assert()
+        .success();
+
+    let task_out = d
+        .assert_cmd()
+        .args(["task", "new", "wait", "--project", "pending-note-proj"])
+        .args(["--provider", "echo", "--env-provider", "slow"])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task_id = json_item(&task_out)["id"].as_str().unwrap().to_string();
+    let out = d
+        .assert_cmd()
+        .args([
+            "task",
+            "note",
+            &task_id,
+            "before it ran",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&out);
+    assert_eq!(task["status"], "pending");
+    let at = task["notes"][0]["at"].as_str().unwrap().to_string();
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+        "complete"
+    );
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "logs", &task_id])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let log = String::from_utf8(out).unwrap();
+    assert!(
+        log.contains(&format!("[work] {at} note: before it ran\n")),
+        "{log}"
+    );
+    assert!(log.ends_with("runner output\n"), "{log}");
+}
+
+#[test]
+fn debug_bundle_collects_task_state_and_logs_with_secrets_redacted() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.echo]
+type = "command"
+command = "echo"
+args = ["runner output"]
+env = { AGENT_API_KEY = "sk-very-secret" }
+
+[redact]
+patterns = ["hunter[0-9]+"]
+"#,
+    );
+
+    let proj = d.work_dir.path().join("bundle-proj");
+    std::fs::create_dir(&proj).unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(&proj)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&["init", "-q"]);
+    git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+    d.assert_cmd()
+        .args(["project", "new", "bundle-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let task_out = d
+        .assert_cmd()
+        .args([
+            "task",
+            "new",
+            "say something, the password is hunter2",
+            "--project",
+            "bundle-proj",
+            "--provider",
+            "echo",
+            "--env-provider",
+            "git-worktree",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&task_out);
+    let task_id = task["id"].as_str().unwrap().to_string();
+    let env_id = task["environment_id"].as_str().unwrap().to_string();
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+        "complete"
+    );
+
+    let bundle = d.work_dir.path().join("bundle.tar.gz");
+    d.assert_cmd()
+        .args(["debug", "bundle", "--task", &task_id, "--output"])
+        .arg(&bundle)
+        .assert()
+        .success();
+
+    let extracted = d.work_dir.path().join("extracted");
+    std::fs::create_dir(&extracted).unwrap();
+    let status = std::process::Command::new("tar")
+        .arg("-xzf")
+        .arg(&bundle)
+        .arg("-C")
+        .arg(&extracted)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let root = std::fs::read_dir(&extracted)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let read = |name: &str| std::fs::read_to_string(root.join(name)).unwrap();
+
+    let manifest: serde_json::Value = serde_json::from_str(&read("manifest.json")).unwrap();
+    assert_eq!(manifest["task"], task_id.as_str());
+    assert_eq!(manifest["daemon_running"], true);
+
+    let tasks: Vec<serde_json::Value> = serde_json::from_str(&read("tasks.json")).unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0]["id"], task_id.as_str());
+    assert_eq!(
+        tasks[0]["description"],
+        "say something, the password is [redacted]"
+    );
+    let envs: Vec<serde_json::Value> = serde_json::from_str(&read("environments.json")).unwrap();
+    assert_eq!(envs.len(), 1);
+    assert_eq!(envs[0]["id"], env_id.as_str());
+    let jobs: Vec<serde_json::Value> = serde_json::from_str(&read("jobs.json")).unwrap();
+    assert!(
+        jobs.iter()
+            .all(|job| job["payload"]["task_id"] == task_id.as_str()
+                || job["payload"]["env_id"] == env_id.as_str()),
+        "{jobs:?}"
+    );
+
+    assert_eq!(
+        read(&format!("logs/tasks/{task_id}.log")),
+        "runner output\n"
+    );
+    assert!(
+        root.join(format!("logs/environments/{env_id}.log"))
+            .exists()
+    );
+    for name in ["tasks.json", "jobs.json", "events.json"] {
+        let contents = read(name);
+        assert!(!contents.contains("hunter2"), "{name}: {contents}");
+    }
+
+    let config = read("config.toml");
+    assert!(config.contains("AGENT_API_KEY"), "{config}");
+    assert!(!config.contains("sk-very-secret"), "{config}");
+
+    d.assert_cmd()
+        .args(["debug", "bundle", "--task", "no-such-task", "--output"])
+        .arg(d.work_dir.path().join("missing.tar.gz"))
+        .assert()
+        .failure();
+}
+
+#[test]
+fn task_on_pty_keeps_escapes_in_log_and_plain_strips_them() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.colorful]
+type = "command"
+command = "sh"
+args = ["-c", "test -t 1 && printf '\\033[32m%s\\033[0m\\n' \"$TERM\"; stty size <&1"]
+pty = true
+pty-columns = 100
+"#,
+    );
+
+    let proj = d.work_dir.path().join("pty-proj");
+    std::fs::create_dir(&proj).unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(&proj)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&["init", "-q"]);
+    git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+    d.assert_cmd()
+        .args(["project", "new", "pty-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let task_out = d
+        .assert_cmd()
+        .args([
+            "task",
+            "new",
+            "color",
+            "--project",
+            "pty-proj",
+            "--provider",
+            "colorful",
+            "--env-provider",
+            "git-worktree",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&task_out);
+    let task_id = task["id"].as_str().unwrap().to_string();
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+        "complete"
+    );
+
+    let raw = d
+        .assert_cmd()
+        .args(["task", "logs", &task_id])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(
+        String::from_utf8(raw).unwrap(),
+        "\x1b[32mxterm-256color\x1b[0m\n40 100\n"
+    );
+
+    let plain = d
+        .assert_cmd()
+        .args(["task", "logs", &task_id, "--plain"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(
+        String::from_utf8(plain).unwrap(),
+        "xterm-256color\n40 100\n"
+    );
+}
+
+#[test]
+fn task_groups_roll_up_status_and_remove_with_their_tasks() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.ok]
+type = "command"
+command = "true"
+
+[tasks.providers.bad]
+type = "command"
+command = "false"
+"#,
+    );
+
+    let proj = d.work_dir.path().join("group-proj");
+    std::fs::create_dir(&proj).unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(&proj)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&["init", "-q"]);
+    git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+    d.assert_cmd()
+        .args(["project", "new", "group-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let group_out = d
+        .assert_cmd()
+        .args([
+            "group",
+            "new",
+            "refactor auth",
+            "--project",
+            "group-proj",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let group = json_item(&group_out);
+    let group_id = group["id"].as_str().unwrap().to_string();
+    assert_eq!(group["status"], "empty");
+
+    // The project comes from the group.
+    let mut task_ids = Vec::new();
+    for provider in ["ok", "bad"] {
+        let task_out = d
+            .assert_cmd()
+            .args([
+                "task",
+                "new",
+                provider,
+                "--parent",
+                &group_id,
+                "--provider",
+                provider,
+                "--env-provider",
+                "git-worktree",
+                "--format",
+                "json",
+            ])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let task = json_item(&task_out);
+        assert_eq!(task["group_id"], group_id.as_str());
+        task_ids.push(task["id"].as_str().unwrap().to_string());
+    }
+    for task_id in &task_ids {
+        wait_for_task_terminal_status(&d, task_id, Duration::from_secs(20));
+    }
+
+    let show_out = d
+        .assert_cmd()
+        .args(["group", "show", &group_id, "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let shown = json_item(&show_out);
+    assert_eq!(shown["status"], "failed");
+    assert_eq!(shown["counts"]["total"], 2);
+    assert_eq!(shown["counts"]["complete"], 1);
+    assert_eq!(shown["counts"]["failed"], 1);
+    assert_eq!(shown["tasks"].as_array().unwrap().len(), 2);
+
+    d.assert_cmd()
+        .args(["group", "rm", &group_id])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--cascade"));
+
+    d.assert_cmd()
+        .args(["group", "rm", &group_id, "--cascade", "--yes"])
+        .assert()
+        .success();
+    d.assert_cmd()
+        .args(["group", "list", "--format", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("{\"api_version\":1,\"items\":[]}\n"));
+
+    let deadline = Instant::now() + Duration::from_secs(20);
+    loop {
+        let tasks_out = d
+            .assert_cmd()
+            .args(["task", "list", "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let tasks = json_items(&tasks_out);
+        if tasks.is_empty() {
+            break;
+        }
+        if Instant::now() >= deadline {
+            panic!("timed out waiting for grouped tasks to be removed");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // A project's groups go with it.
+    d.assert_cmd()
+        .args(["group", "new", "left behind", "--project", "group-proj"])
+        .assert()
+        .success();
+    d.assert_cmd()
+        .args(["project", "rm", "group-proj"])
+        .assert()
+        .success();
+    d.assert_cmd()
+        .args(["group", "list", "--format", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::diff("{\"api_version\":1,\"items\":[]}\n"));
+}
+
+#[test]
+fn environment_exec_alias_runs_provider_exec_action_and_completes_provider_commands() {
+    let d = DaemonFixture::start();
+
+    let provider_script = d.work_dir.path().join("execable-env-provider.sh");
+    write_executable_script(
+        &provider_script,
+        r#"#!/bin/sh
+set -eu
+action="$1"
+case "$action" in
+  prepare)
+    echo '{"sandbox_id":"sbx-test"}'
+    ;;
+  update|claim)
+    cat
+    ;;
+  remove)
+    exit 0
+    ;;
+  run)
+    exit 0
+    ;;
+  commands)
+    echo '[{"name":"ssh","description":"Connect to sandbox"},{"name":"logs"}]'
+    ;;
+  exec)
+    command="$2"
+    shift 2
+    printf 'exec-action command=%s args=%s metadata=%s\n' "$command" "$*" "${WORK_ENV_METADATA:-}"
+    ;;
+  *)
+    exit 1
+    ;;
+esac
+"#,
+    );
+
+    d.write_config(format!(
+        r#"[environments.providers.execable]
+type = "script"
+path = "{}"
+"#,
+        provider_script.to_string_lossy()
+    ));
+
+    let proj = d.work_dir.path().join("exec-env-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "exec-env-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let create_out = d
+        .assert_cmd()
+        .args([
+            "environment",
+            "create",
+            "exec-env-proj",
+            "--provider",
+            "execable",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let env = json_item(&create_out);
+    let env_id = env["id"].as_str().unwrap().to_string();
+    wait_for_env_status(&d, &env_id, "in_use", Duration::from_secs(8));
+
+    let completion_out = d
+        .assert_cmd()
+        .env("COMPLETE", "bash")
+        .env("_CLAP_COMPLETE_INDEX", "4")
+        .args(["--", "work", "env", "x", &env_id, "s"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let completion_text = String::from_utf8(completion_out).unwrap();
+    assert!(
+        completion_text.lines().any(|line| line == "ssh"),
+        "expected env exec completion to include ssh, got: {completion_text:?}"
+    );
+
+    d.assert_cmd()
+        .args(["env", "x", &env_id, "ssh"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("exec-action command=ssh"))
+        .stdout(predicate::str::contains("\"sandbox_id\":\"sbx-test\""));
+
+    // Without a terminal there is no session to record.
+    d.assert_cmd()
+        .args(["env", "x", "--record", &env_id, "ssh"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("exec-action command=ssh"))
+        .stderr(predicate::str::contains("not recording"));
+
+    let show_out = d
+        .assert_cmd()
+        .args(["env", "show", &env_id, "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let shown = json_item(&show_out);
+    assert_eq!(shown["id"].as_str(), Some(env_id.as_str()));
+    assert_eq!(shown["sessions"], serde_json::json!([]));
+}
+
+#[test]
+fn task_top_level_aliases_run_task_commands_and_complete_exec_commands() {
+    let d = DaemonFixture::start();
+
+    let provider_script = d.work_dir.path().join("execable-task-env-provider.sh");
+    write_executable_script(
+        &provider_script,
+        r#"#!/bin/sh
+set -eu
+action="$1"
+case "$action" in
+  prepare)
+    echo '{"sandbox_id":"sbx-task"}'
+    ;;
+  update|claim)
+    cat
+    ;;
+  remove)
+    exit 0
+    ;;
+  run)
+    exit 0
+    ;;
+  commands)
+    echo '["ssh","logs"]'
+    ;;
+  exec)
+    command="$2"
+    shift 2
+    printf 'exec-action command=%s args=%s metadata=%s\n' "$command" "$*" "${WORK_ENV_METADATA:-}"
+    ;;
+  *)
+    exit 1
+    ;;
+esac
+"#,
+    );
+
+    d.write_config(format!(
+        r#"[tasks.providers.noop]
+type = "command"
+command = "sh"
+args = ["-c", "true"]
+
+[environments.providers.execable]
+type = "script"
+path = "{}"
+"#,
+        provider_script.to_string_lossy()
+    ));
+
+    let proj = d.work_dir.path().join("exec-task-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "exec-task-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let task_out = d
+        .assert_cmd()
+        .args([
+            "new",
+            "task exec flow",
+            "--project",
+            "exec-task-proj",
+            "--provider",
+            "noop",
+            "--env-provider",
+            "execable",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&task_out);
+    let task_id = task["id"].as_str().unwrap().to_string();
+
+    let _ = wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(8));
+
+    let task_list_out = d
+        .assert_cmd()
+        .args(["list", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let tasks = json_items(&task_list_out);
+    assert!(
+        tasks
+            .iter()
+            .any(|candidate| candidate["id"].as_str() == Some(task_id.as_str())),
+        "expected top-level list to include task {task_id}"
+    );
+
+    d.assert_cmd().args(["logs", &task_id]).assert().success();
+
+    let completion_out = d
+        .assert_cmd()
+        .env("COMPLETE", "bash")
+        .env("_CLAP_COMPLETE_INDEX", "4")
+        .args(["--", "work", "task", "x", &task_id, "s"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let completion_text = String::from_utf8(completion_out).unwrap();
+    assert!(
+        completion_text.lines().any(|line| line == "ssh"),
+        "expected task exec completion to include ssh, got: {completion_text:?}"
+    );
+
+    let top_level_completion_out = d
+        .assert_cmd()
+        .env("COMPLETE", "bash")
+        .env("_CLAP_COMPLETE_INDEX", "3")
+        .args(["--", "work", "exec", &task_id, "s"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let top_level_completion_text = String::from_utf8(top_level_completion_out).unwrap();
+    assert!(
+        top_level_completion_text.lines().any(|line| line == "ssh"),
+        "expected top-level exec completion to include ssh, got: {top_level_completion_text:?}"
+    );
+
+    d.assert_cmd()
+        .args(["exec", &task_id, "ssh"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("exec-action command=ssh"))
+        .stdout(predicate::str::contains("\"sandbox_id\":\"sbx-task\""));
+
+    d.assert_cmd().args(["remove", &task_id]).assert().success();
+
+    let deadline = Instant::now() + Duration::from_secs(12);
+    loop {
+        let tasks_out = d
+            .assert_cmd()
+            .args(["list", "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let tasks = json_items(&tasks_out);
+        if tasks
+            .iter()
+            .all(|candidate| candidate["id"].as_str() != Some(task_id.as_str()))
+        {
+            break;
+        }
+        if Instant::now() >= deadline {
+            panic!("task was not deleted by top-level remove alias");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn search_finds_tasks_by_description_and_log_output() {
+    let d = DaemonFixture::start();
+
+    let provider_script = d.work_dir.path().join("search-provider.sh");
+    write_executable_script(
+        &provider_script,
+        r#"#!/bin/sh
+set -eu
+action="$1"
+case "$action" in
+  prepare)
+    echo '{"worktree_path":"/tmp/fake"}'
+    ;;
+  update|claim)
+    echo '{}'
+    ;;
+  remove)
+    exit 0
+    ;;
+  run)
+    echo "touched the pelican module"
+    ;;
+  *)
+    exit 1
+    ;;
+esac
+"#,
+    );
+
+    d.write_config(format!(
+        r#"[tasks.providers.noop]
+type = "command"
+command = "sh"
+args = ["-c", "true"]
+
+[environments.providers.searchable]
+type = "script"
+path = "{}"
+"#,
+        provider_script.to_string_lossy()
+    ));
+
+    let proj = d.work_dir.path().join("search-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "search-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let task_out = d
+        .assert_cmd()
+        .args([
+            "task",
+            "new",
+            "refactor the authentication flow",
+            "--project",
+            "search-proj",
+            "--provider",
+            "noop",
+            "--env-provider",
+            "searchable",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&task_out);
+    let task_id = task["id"].as_str().unwrap().to_string();
+
+    let search = |args: &[&str]| -> Vec<serde_json::Value> {
+        let out = d
+            .assert_cmd()
+            .arg("search")
+            .args(args)
+            .args(["--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        json_items(&out)
+    };
+
+    // Descriptions are indexed as soon as the task is created; stemming
+    // matches "authenticate" against "authentication".
+    let hits = search(&["authenticate"]);
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0]["id"].as_str(), Some(task_id.as_str()));
+    assert_eq!(hits[0]["matched"].as_str(), Some("description"));
+
+    wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(8));
+
+    // Logs are indexed after the task finishes, and only searched with --logs.
+    let deadline = Instant::now() + Duration::from_secs(8);
+    loop {
+        let hits = search(&["pelican", "--logs"]);
+        if let Some(hit) = hits.first() {
+            assert_eq!(hit["id"].as_str(), Some(task_id.as_str()));
+            assert_eq!(hit["matched"].as_str(), Some("log"));
+            assert!(hit["snippet"].as_str().unwrap().contains("pelican"));
+            break;
+        }
+        if Instant::now() >= deadline {
+            panic!("timed out waiting for task log to be indexed");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert!(search(&["pelican"]).is_empty());
+
+    d.assert_cmd()
+        .args(["search", "--format", "json", "   "])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("search query is empty"));
+}
+
+#[test]
+fn task_new_from_ref_records_ref_and_passes_it_to_prepare() {
+    let d = DaemonFixture::start();
+
+    let provider_script = d.work_dir.path().join("ref-provider.sh");
+    write_executable_script(
+        &provider_script,
+        r#"#!/bin/sh
+set -eu
+action="$1"
+case "$action" in
+  prepare)
+    input=$(cat)
+    printf '{"prepare_input":%s}\n' "$input"
+    ;;
+  update|claim)
+    cat
+    ;;
+  remove)
+    exit 0
+    ;;
+  run)
+    exit 0
+    ;;
+  *)
+    exit 1
+    ;;
+esac
+"#,
+    );
+
+    d.write_config(format!(
+        r#"[tasks.providers.noop]
+type = "command"
+command = "sh"
+args = ["-c", "true"]
+
+[environments.providers.refs]
+type = "script"
+path = "{}"
+"#,
+        provider_script.to_string_lossy()
+    ));
+
+    let proj = d.work_dir.path().join("ref-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "ref-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let task_out = d
+        .assert_cmd()
+        .args([
+            "task",
+            "new",
+            "review the feature branch",
+            "--project",
+            "ref-proj",
+            "--provider",
+            "noop",
+            "--env-provider",
+            "refs",
+            "--from-ref",
+            "feature/login",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&task_out);
+    assert_eq!(task["source_ref"].as_str(), Some("feature/login"));
+    let env_id = task["environment_id"].as_str().unwrap().to_string();
+
+    wait_for_env_status(&d, &env_id, "in_use", Duration::from_secs(8));
+
+    let env_out = d
+        .assert_cmd()
+        .args(["environment", "list", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let envs = json_items(&env_out);
+    let env = envs
+        .iter()
+        .find(|env| env["id"].as_str() == Some(env_id.as_str()))
+        .unwrap();
+    assert_eq!(
+        env["metadata"]["prepare_input"]["options"]["source-ref"].as_str(),
+        Some("feature/login")
+    );
+}
+
+#[test]
+fn shared_pool_environments_serve_other_projects() {
+    let d = DaemonFixture::start();
+
+    let provider_script = d.work_dir.path().join("shared-provider.sh");
+    write_executable_script(
+        &provider_script,
+        r#"#!/bin/sh
+set -eu
+action="$1"
+case "$action" in
+  prepare)
+    echo '{"image":"generic"}'
+    ;;
+  update|claim)
+    cat
+    ;;
+  remove|run)
+    exit 0
+    ;;
+  *)
+    exit 1
+    ;;
+esac
+"#,
+    );
+
+    d.write_config(format!(
+        r#"[tasks.providers.noop]
+type = "command"
+command = "sh"
+args = ["-c", "true"]
+
+[environments]
+shared-pools = ["shared"]
+
+[environments.providers.shared]
+type = "script"
+path = "{}"
+"#,
+        provider_script.to_string_lossy()
+    ));
+
+    for name in ["pool-owner", "pool-borrower"] {
+        let proj = d.work_dir.path().join(name);
+        std::fs::create_dir(&proj).unwrap();
+        d.assert_cmd()
+            .args(["project", "new", name, "--path"])
+            .arg(&proj)
+            .assert()
+            .success();
+    }
+
+    let prepare_out = d
+        .assert_cmd()
+        .args([
+            "environment",
+            "prepare",
+            "pool-owner",
+            "--provider",
+            "shared",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let env = json_item(&prepare_out);
+    let env_id = env["id"].as_str().unwrap().to_string();
+    wait_for_env_status(&d, &env_id, "pool", Duration::from_secs(8));
+
+    let task_out = d
+        .assert_cmd()
+        .args([
+            "task",
+            "new",
+            "borrow a pooled environment",
+            "--project",
+            "pool-borrower",
+            "--provider",
+            "noop",
+            "--env-provider",
+            "shared",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&task_out);
+    assert_eq!(task["environment_id"].as_str(), Some(env_id.as_str()));
+
+    let env_out = d
+        .assert_cmd()
+        .args(["environment", "list", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let envs = json_items(&env_out);
+    let env = envs
+        .iter()
+        .find(|env| env["id"].as_str() == Some(env_id.as_str()))
+        .unwrap();
+    assert_eq!(env["project_id"], task["project_id"]);
+}
+
+#[test]
+fn project_provider_allowlists_refuse_other_providers() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.noop]
+type = "command"
+command = "true"
+
+[tasks.providers.trusted]
+type = "command"
+command = "true"
+
+[projects.secure-repo]
+allowed-env-providers = ["sandboxed-worktree"]
+allowed-task-providers = ["trusted"]
+"#,
+    );
+
+    let proj = d.work_dir.path().join("secure-repo");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "secure-repo", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    d.assert_cmd()
+        .args([
+            "environment",
+            "prepare",
+            "secure-repo",
+            "--provider",
+            "git-worktree",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "project secure-repo is not allowed to use environment provider git-worktree",
+        ));
+
+    d.assert_cmd()
+        .args([
+            "task",
+            "new",
+            "untrusted runner",
+            "--project",
+            "secure-repo",
+            "--provider",
+            "noop",
+            "--env-provider",
+            "git-worktree",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "project secure-repo is not allowed to use task provider noop",
+        ));
+
+    d.assert_cmd()
+        .args([
+            "task",
+            "new",
+            "trusted runner, wrong environment",
+            "--project",
+            "secure-repo",
+            "--provider",
+            "trusted",
+            "--env-provider",
+            "git-worktree",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "not allowed to use environment provider git-worktree",
+        ));
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "list", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert!(json_items(&out).is_empty());
+}
+
+#[test]
+fn json_output_keeps_its_envelope_and_record_fields() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.noop]
+type = "command"
+command = "sh"
+args = ["-c", "true"]
+
+[environments.providers.never]
+type = "script"
+path = "/usr/bin/false"
+"#,
+    );
+
+    let proj = d.work_dir.path().join("schema-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "schema-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+    let out = d
+        .assert_cmd()
+        .args([
+            "task",
+            "new",
+            "check the schema",
+            "--project",
+            "schema-proj",
+            "--provider",
+            "noop",
+            "--env-provider",
+            "never",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task_id = json_item(&out)["id"].as_str().unwrap().to_string();
+
+    let json = |args: &[&str]| -> Vec<serde_json::Value> {
+        let out = d
+            .assert_cmd()
+            .args(args)
+            .args(["--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        json_items(&out)
+    };
+    // Scripts rely on these fields. Adding fields is fine; removing or
+    // renaming one needs a new api_version.
+    let assert_fields = |record: &serde_json::Value, fields: &[&str]| {
+        for field in fields {
+            assert!(record.get(field).is_some(), "{field} missing from {record}");
+        }
+    };
+
+    let projects = json(&["project", "list"]);
+    assert_eq!(projects.len(), 1);
+    assert_fields(
+        &projects[0],
+        &["id", "name", "path", "created_at", "updated_at"],
+    );
+
+    let task_fields = [
+        "id",
+        "environment_id",
+        "project_id",
+        "provider",
+        "description",
+        "status",
+        "labels",
+        "requirements",
+        "priority",
+        "notes",
+        "created_at",
+        "updated_at",
+    ];
+    let tasks = json(&["task", "list"]);
+    assert_eq!(tasks.len(), 1);
+    assert_fields(&tasks[0], &task_fields);
+    let shown = json(&["task", "show", &task_id]);
+    assert_eq!(shown.len(), 1);
+    assert_fields(&shown[0], &task_fields);
+
+    let envs = json(&["environment", "list"]);
+    assert!(!envs.is_empty());
+    assert_fields(
+        &envs[0],
+        &[
+            "id",
+            "project_id",
+            "provider",
+            "status",
+            "metadata",
+            "created_at",
+            "updated_at",
+        ],
+    );
+
+    let jobs = json(&["job", "list"]);
+    assert!(!jobs.is_empty());
+    assert_fields(
+        &jobs[0],
+        &[
+            "id",
+            "type",
+            "payload",
+            "status",
+            "attempt",
+            "created_at",
+            "updated_at",
+        ],
+    );
+
+    // Lists are still wrapped when empty.
+    assert!(json(&["group", "list"]).is_empty());
+}
+
+#[test]
+fn task_new_priority_is_recorded_and_given_to_its_jobs() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.noop]
+type = "command"
+command = "sh"
+args = ["-c", "true"]
+
+[environments.providers.never]
+type = "script"
+path = "/usr/bin/false"
+"#,
+    );
+
+    let proj = d.work_dir.path().join("prio-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "prio-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let create = |priority: &str| -> serde_json::Value {
+        let out = d
+            .assert_cmd()
+            .args([
+                "task",
+                "new",
+                "do the thing",
+                "--project",
+                "prio-proj",
+                "--provider",
+                "noop",
+                "--env-provider",
+                "never",
+                "--priority",
+                priority,
+                "--format",
+                "json",
+            ])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        json_item(&out)
+    };
+    let urgent = create("high");
+    let later = create("low");
+    assert_eq!(urgent["priority"], "high");
+    assert_eq!(later["priority"], "low");
+
+    let conn = rusqlite::Connection::open(d.work_dir.path().join("data/database.sqlite3")).unwrap();
+    let job_priority = |task: &serde_json::Value| -> i64 {
+        conn.query_row(
+            "SELECT priority FROM jobs WHERE json_extract(payload, '$.task_id') = ?1",
+            rusqlite::params![task["id"].as_str().unwrap()],
+            |row| row.get(0),
+        )
+        .unwrap()
+    };
+    assert_eq!(job_priority(&urgent), 1);
+    assert_eq!(job_priority(&later), -1);
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "duplicate", urgent["id"].as_str().unwrap()])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let copy = json_item(&out);
+    assert_eq!(copy["priority"], "high");
+}
+
+#[test]
+fn task_new_reads_a_markdown_description_from_stdin() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.noop]
+type = "command"
+command = "sh"
+args = ["-c", "true"]
+
+[environments.providers.never]
+type = "script"
+path = "/usr/bin/false"
+"#,
+    );
+
+    let proj = d.work_dir.path().join("md-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "md-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let description = "Fix the flaky test\n\n- run `cargo test`\n- keep the retry";
+    let out = d
+        .assert_cmd()
+        .args(["task", "new", "-", "--project", "md-proj"])
+        .args(["--provider", "noop", "--env-provider", "never"])
+        .args(["--format", "json"])
+        .write_stdin(format!("{description}\n\n"))
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&out);
+    assert_eq!(task["description"], description);
+    let task_id = task["id"].as_str().unwrap();
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "list", "--format", "plain"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let list = String::from_utf8_lossy(&out);
+    assert_eq!(list.lines().count(), 1, "{list}");
+    assert!(list.contains("\tFix the flaky test"), "{list}");
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "show", task_id])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let shown = String::from_utf8_lossy(&out);
+    assert!(shown.contains("Fix the flaky test\n"), "{shown}");
+    assert!(shown.contains("- run `cargo test`\n"), "{shown}");
+    assert!(shown.contains("- keep the retry\n"), "{shown}");
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "show", task_id, "--format", "plain"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let shown = String::from_utf8_lossy(&out);
+    let fields: Vec<&str> = shown.trim_end().split('\t').collect();
+    assert_eq!(fields.len(), 7, "{shown}");
+    assert_eq!(fields[0], task_id);
+    assert_eq!(fields[5], "normal");
+    assert_eq!(
+        fields[6],
+        "Fix the flaky test\\n\\n- run `cargo test`\\n- keep the retry"
+    );
+
+    d.assert_cmd()
+        .args(["task", "new", "-", "--project", "md-proj"])
+        .write_stdin("  \n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("task description is empty"));
+}
+
+#[test]
+fn task_new_require_picks_tagged_provider_pool() {
+    let d = DaemonFixture::start();
+
+    let provider_script = d.work_dir.path().join("tagged-provider.sh");
+    write_executable_script(
+        &provider_script,
+        r#"#!/bin/sh
+set -eu
+action="$1"
+case "$action" in
+  prepare)
+    echo '{}'
+    ;;
+  update|claim)
+    cat
+    ;;
+  remove|run)
+    exit 0
+    ;;
+  *)
+    exit 1
+    ;;
+esac
+"#,
+    );
+
+    d.write_config(format!(
+        r#"environment-provider = "cpu"
+
+[tasks.providers.noop]
+type = "command"
+command = "sh"
+args = ["-c", "true"]
+
+[environments.providers.cpu]
+type = "script"
+path = "{script}"
+tags = ["node20"]
+
+[environments.providers.gpu]
+type = "script"
+path = "{script}"
+tags = ["gpu", "node20"]
+"#,
+        script = provider_script.to_string_lossy()
+    ));
+
+    let proj = d.work_dir.path().join("tagged-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "tagged-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let prepare_out = d
+        .assert_cmd()
+        .args([
+            "environment",
+            "prepare",
+            "tagged-proj",
+            "--provider",
+            "gpu",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let env = json_item(&prepare_out);
+    let env_id = env["id"].as_str().unwrap().to_string();
+    wait_for_env_status(&d, &env_id, "pool", Duration::from_secs(8));
+
+    let task_out = d
+        .assert_cmd()
+        .args([
+            "task",
+            "new",
+            "train the model",
+            "--project",
+            "tagged-proj",
+            "--provider",
+            "noop",
+            "--require",
+            "gpu",
+            "--require",
+            "node20",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&task_out);
+    assert_eq!(task["environment_id"].as_str(), Some(env_id.as_str()));
+    assert_eq!(task["requirements"], serde_json::json!(["gpu", "node20"]));
+
+    d.assert_cmd()
+        .args([
+            "task",
+            "new",
+            "wrong provider",
+            "--project",
+            "tagged-proj",
+            "--provider",
+            "noop",
+            "--env-provider",
+            "cpu",
+            "--require",
+            "gpu",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "environment provider cpu is not tagged with gpu",
+        ));
+
+    d.assert_cmd()
+        .args([
+            "task",
+            "new",
+            "no such hardware",
+            "--project",
+            "tagged-proj",
+            "--provider",
+            "noop",
+            "--require",
+            "tpu",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "no environment provider is tagged with tpu",
+        ));
+}
+
+#[test]
+fn export_and_import_round_trip_projects_and_tasks() {
+    let d = DaemonFixture::start();
+
+    let provider_script = d.work_dir.path().join("export-env-provider.sh");
+    write_executable_script(
+        &provider_script,
+        r#"#!/bin/sh
+case "$1" in
+  run)
+    exec sh -c 'true'
+    ;;
+  *)
+    echo '{}'
+    ;;
+esac
+"#,
+    );
+    d.write_config(format!(
+        r#"[tasks.providers.noop]
+type = "command"
+command = "sh"
+args = ["-c", "true"]
+
+[environments.providers.exportable]
+type = "script"
+path = "{}"
+"#,
+        provider_script.to_string_lossy()
+    ));
+
+    let proj = d.work_dir.path().join("old-home/export-proj");
+    std::fs::create_dir_all(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "export-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+    let group_out = d
+        .assert_cmd()
+        .args(["group", "new", "exported group", "--project", "export-proj"])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let group_id = json_item(&group_out)["id"].as_str().unwrap().to_string();
+
+    let task_out = d
+        .assert_cmd()
+        .args([
+            "new",
+            "exported task",
+            "--parent",
+            &group_id,
+            "--provider",
+            "noop",
+            "--env-provider",
+            "exportable",
+            "--label",
+            "keep",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task = json_item(&task_out);
+    let task_id = task["id"].as_str().unwrap().to_string();
+    let status = wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(8));
+
+    let export_path = d.work_dir.path().join("state.json");
+    d.assert_cmd()
+        .args(["export", "--tasks", "--output"])
+        .arg(&export_path)
+        .assert()
+        .success();
+
+    d.assert_cmd()
+        .args(["reset-database", "--yes"])
+        .assert()
+        .success();
+
+    let new_home = d.work_dir.path().join("new-home");
+    let import_out = d
+        .assert_cmd()
+        .arg("import")
+        .arg(&export_path)
+        .arg("--map")
+        .arg(format!(
+            "{}={}",
+            d.work_dir.path().join("old-home").display(),
+            new_home.display()
+        ))
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let summary = json_item(&import_out);
+    assert_eq!(summary["projects_created"], 1);
+    assert_eq!(summary["tasks_imported"], 1);
+
+    let projects_out = d
+        .assert_cmd()
+        .args(["project", "list", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let projects = json_items(&projects_out);
+    assert_eq!(projects.len(), 1);
+    assert_eq!(
+        projects[0]["path"].as_str().unwrap(),
+        new_home.join("export-proj").to_string_lossy()
+    );
+
+    let imported_out = d
+        .assert_cmd()
+        .args(["task", "show", &task_id, "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let imported = json_item(&imported_out);
+    assert_eq!(imported["description"], "exported task");
+    assert_eq!(imported["status"], status.as_str());
+    assert_eq!(imported["labels"], serde_json::json!(["keep"]));
+    assert_eq!(imported["group_id"], group_id.as_str());
+    let group_out = d
+        .assert_cmd()
+        .args(["group", "show", &group_id, "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(json_item(&group_out)["description"], "exported group");
+
+    // Importing again without the path map fails: the project's recorded
+    // path no longer matches the one in the file.
+    let again_out = d
+        .assert_cmd()
+        .arg("import")
+        .arg(&export_path)
+        .args(["--format", "json"])
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .clone();
+    assert!(String::from_utf8_lossy(&again_out).contains("already exists"));
+
+    let skipped_out = d
+        .assert_cmd()
+        .arg("import")
+        .arg(&export_path)
+        .args(["--on-conflict", "skip", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let skipped = json_item(&skipped_out);
+    assert_eq!(skipped["projects_skipped"], 1);
+    assert_eq!(skipped["tasks_skipped"], 1);
+
+    // Nor can an import move a project inside another one.
+    let outer = d.work_dir.path().join("outer");
+    std::fs::create_dir_all(&outer).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "outer", "--path"])
+        .arg(&outer)
+        .assert()
+        .success();
+    d.assert_cmd()
+        .arg("import")
+        .arg(&export_path)
+        .arg("--map")
+        .arg(format!(
+            "{}={}",
+            d.work_dir.path().join("old-home").display(),
+            outer.canonicalize().unwrap().display()
+        ))
+        .args(["--on-conflict", "replace"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is inside project outer"));
+
+    // Imported tasks have no checkout here, so removal never calls a provider.
+    d.assert_cmd().args(["rm", &task_id]).assert().success();
+    let deadline = Instant::now() + Duration::from_secs(8);
+    while d
+        .assert_cmd()
+        .args(["task", "show", &task_id])
+        .output()
+        .unwrap()
+        .status
+        .success()
+    {
+        assert!(Instant::now() < deadline, "imported task was not removed");
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // Nor does removing a placeholder environment on its own.
+    let conn = rusqlite::Connection::open(d.work_dir.path().join("data/database.sqlite3")).unwrap();
+    conn.execute(
+        "INSERT INTO environments (id, project_id, provider, status, metadata, created_at, updated_at) SELECT 'imported-env', id, 'imported', 'failed', '{}', created_at, updated_at FROM projects WHERE name = 'export-proj'",
+        [],
+    )
+    .unwrap();
+    drop(conn);
+    d.assert_cmd()
+        .args(["env", "rm", "imported-env"])
+        .assert()
+        .success();
+    let deadline = Instant::now() + Duration::from_secs(8);
+    while d
+        .assert_cmd()
+        .args(["env", "show", "imported-env"])
+        .output()
+        .unwrap()
+        .status
+        .success()
+    {
+        assert!(
+            Instant::now() < deadline,
+            "imported environment was not removed"
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn remap_paths_rewrites_projects_and_environment_metadata() {
+    let d = DaemonFixture::start();
+
+    let old_home = d.work_dir.path().join("old-home");
+    std::fs::create_dir_all(old_home.join("remap-proj")).unwrap();
+    let old_home = old_home.canonicalize().unwrap();
+    let old = old_home.to_string_lossy().into_owned();
+
+    let provider_script = d.work_dir.path().join("remap-env-provider.sh");
+    write_executable_script(
+        &provider_script,
+        &format!(
+            r#"#!/bin/sh
+case "$1" in
+  prepare)
+    echo '{{"project_path": "{old}/remap-proj", "worktree_path": "{old}/.work/wt", "branch": "b"}}'
+    ;;
+  *)
+    cat
+    ;;
+esac
+"#
+        ),
+    );
+    d.write_config(format!(
+        r#"[environments.providers.remap]
+type = "script"
+path = "{}"
+"#,
+        provider_script.to_string_lossy()
+    ));
+
+    d.assert_cmd()
+        .args(["project", "new", "remap-proj", "--path"])
+        .arg(old_home.join("remap-proj"))
+        .assert()
+        .success();
+    let prepare_out = d
+        .assert_cmd()
+        .args([
+            "environment",
+            "prepare",
+            "remap-proj",
+            "--provider",
+            "remap",
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let env = json_item(&prepare_out);
+    let env_id = env["id"].as_str().unwrap().to_string();
+    wait_for_env_status(&d, &env_id, "pool", Duration::from_secs(8));
+
+    let remap_out = d
+        .assert_cmd()
+        .args([
+            "remap-paths",
+            "--map",
+            &format!("{old}=/new/home"),
+            "--format",
+            "json",
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let summary = json_item(&remap_out);
+    assert_eq!(summary["projects"], 1);
+    assert_eq!(summary["environments"], 1);
+
+    let projects_out = d
+        .assert_cmd()
+        .args(["project", "list", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let projects = json_items(&projects_out);
+    assert_eq!(projects[0]["path"], "/new/home/remap-proj");
+
+    let envs_out = d
+        .assert_cmd()
+        .args(["environment", "list", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let envs = json_items(&envs_out);
+    let env = envs
+        .iter()
+        .find(|env| env["id"].as_str() == Some(env_id.as_str()))
+        .unwrap();
+    assert_eq!(env["metadata"]["project_path"], "/new/home/remap-proj");
+    assert_eq!(env["metadata"]["worktree_path"], "/new/home/.work/wt");
+    assert_eq!(env["metadata"]["branch"], "b");
+
+    d.assert_cmd()
+        .args(["remap-paths", "--map", "relative=/new"])
+        .assert()
+        .failure();
+
+    // A remap can't land one project on or inside another.
+    let other = d.work_dir.path().join("other-proj");
+    std::fs::create_dir_all(&other).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "other-proj", "--path"])
+        .arg(&other)
+        .assert()
+        .success();
+    d.assert_cmd()
+        .args(["remap-paths", "--map"])
+        .arg(format!(
+            "/new/home/remap-proj={}",
+            other.canonicalize().unwrap().join("inner").display()
+        ))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("inside project other-proj"));
+}
+
+#[test]
+fn exhausted_jobs_go_dead_and_can_be_retried_or_purged() {
+    let d = DaemonFixture::start();
+    let fail_flag = d.work_dir.path().join("prepare-fail.flag");
+    std::fs::write(&fail_flag, "1").unwrap();
+
+    let provider_script = d.work_dir.path().join("flaky-provider.sh");
+    write_executable_script(
+        &provider_script,
+        &format!(
+            r#"#!/bin/sh
+set -eu
+action="$1"
+case "$action" in
+  prepare)
+    if [ -f "{}" ]; then
+      echo "prepare broke" >&2
+      exit 1
+    fi
+    echo '{{}}'
+    ;;
+  update|claim)
+    cat
+    ;;
+  remove|run)
+    exit 0
+    ;;
+  *)
+    exit 1
+    ;;
+esac
+"#,
+            fail_flag.to_string_lossy()
+        ),
+    );
+
+    d.write_config(format!(
+        r#"[daemon.retries.prepare_environment]
+limit = 0
+
+[tasks.providers.noop]
+type = "command"
+command = "sh"
+args = ["-c", "true"]
+
+[environments.providers.flaky]
+type = "script"
+path = "{}"
+"#,
+        provider_script.to_string_lossy()
+    ));
+
+    let proj = d.work_dir.path().join("dead-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "dead-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let new_task = |description: &str| -> String {
+        let out = d
+            .assert_cmd()
+            .args([
+                "task",
+                "new",
+                description,
+                "--project",
+                "dead-proj",
+                "--provider",
+                "noop",
+                "--env-provider",
+                "flaky",
+                "--format",
+                "json",
+            ])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let task = json_item(&out);
+        task["id"].as_str().unwrap().to_string()
+    };
+    let dead_jobs = || -> Vec<serde_json::Value> {
+        let out = d
+            .assert_cmd()
+            .args(["job", "list", "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let jobs = json_items(&out);
+        jobs.into_iter()
+            .filter(|job| job["status"].as_str() == Some("dead"))
+            .collect()
+    };
+
+    let task_id = new_task("dies then recovers");
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(8)),
+        "failed"
+    );
+    let dead = dead_jobs();
+    assert_eq!(dead.len(), 1);
+    assert_eq!(dead[0]["type"].as_str(), Some("prepare_environment"));
+    assert_eq!(
+        dead[0]["payload"]["task_id"].as_str(),
+        Some(task_id.as_str())
+    );
+    assert_eq!(dead[0]["errors"].as_array().map(Vec::len), Some(1));
+    let job_id = dead[0]["id"].as_str().unwrap().to_string();
+
+    std::fs::remove_file(&fail_flag).unwrap();
+    d.assert_cmd()
+        .args(["job", "retry", &job_id, "--format", "plain"])
+        .assert()
+        .success()
+        .stdout(format!("{job_id}\n"));
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(8)),
+        "complete"
+    );
+    assert!(dead_jobs().is_empty());
+
+    let job_out = d
+        .assert_cmd()
+        .args(["job", "show", &job_id, "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let job = json_item(&job_out);
+    assert_eq!(job["status"].as_str(), Some("complete"));
+    assert_eq!(job["errors"].as_array().map(Vec::len), Some(1));
+
+    d.assert_cmd()
+        .args(["job", "retry", &job_id])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not dead"));
+
+    std::fs::write(&fail_flag, "1").unwrap();
+    let task_id = new_task("stays dead");
+    wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(8));
+    assert_eq!(dead_jobs().len(), 1);
+    d.assert_cmd()
+        .args(["job", "purge-dead", "--format", "plain"])
+        .assert()
+        .success()
+        .stdout("1\n");
+    assert!(dead_jobs().is_empty());
+}
+
+#[test]
+fn db_check_reports_orphaned_group_and_repair_removes_it() {
+    let d = DaemonFixture::start();
+
+    d.assert_cmd()
+        .args(["db", "check"])
+        .assert()
+        .success()
+        .stdout("no problems found\n");
+
+    let conn = rusqlite::Connection::open(d.work_dir.path().join("data/database.sqlite3")).unwrap();
+    conn.execute_batch(
+        "PRAGMA foreign_keys = OFF;
+         INSERT INTO task_groups (id, project_id, description, created_at, updated_at) VALUES ('g-orphan', 'gone', 'orphan', '2026-01-01T00:00:00Z', '2026-01-01T00:00:00Z');",
+    )
+    .unwrap();
+
+    let out = d
+        .assert_cmd()
+        .args(["db", "check", "--format", "json"])
+        .assert()
+        .failure()
+        .get_output()
+        .stdout
+        .clone();
+    let problems = json_items(&out);
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0]["kind"], "group_missing_project");
+    assert_eq!(problems[0]["id"], "g-orphan");
+
+    d.assert_cmd()
+        .args(["db", "repair"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--yes"));
+    d.assert_cmd()
+        .args(["db", "repair", "--yes"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("fixed 1 problem(s)"));
+
+    d.assert_cmd().args(["db", "check"]).assert().success();
+}
+
+#[test]
+fn db_maintain_prunes_old_completed_jobs_and_events() {
+    let d = DaemonFixture::start();
+
+    let conn = rusqlite::Connection::open(d.work_dir.path().join("data/database.sqlite3")).unwrap();
+    conn.busy_timeout(Duration::from_secs(5)).unwrap();
+    conn.execute_batch(
+        "INSERT INTO jobs (id, type, payload, status, created_at, updated_at) VALUES
+             ('j-old', 'notify', '{}', 'complete', '2020-01-01T00:00:00Z', '2020-01-01T00:00:00Z'),
+             ('j-dead', 'notify', '{}', 'dead', '2020-01-01T00:00:00Z', '2020-01-01T00:00:00Z'),
+             ('j-new', 'notify', '{}', 'complete', '2999-01-01T00:00:00Z', '2999-01-01T00:00:00Z');
+         UPDATE events SET created_at = '2020-01-01T00:00:00Z' WHERE subject_id = 'j-old';",
+    )
+    .unwrap();
+
+    let out = d
+        .assert_cmd()
+        .args(["db", "maintain", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let summary = json_item(&out);
+    assert_eq!(summary["jobs_pruned"], 1);
+    assert_eq!(summary["events_pruned"], 1);
+    assert_eq!(summary["converted"], true);
+
+    let mut stmt = conn.prepare("SELECT id FROM jobs ORDER BY id").unwrap();
+    let jobs: Vec<String> = stmt
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(jobs, ["j-dead", "j-new"]);
+    let removals: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM events WHERE subject_id = 'j-old'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(removals, 0);
+
+    d.assert_cmd()
+        .args(["db", "maintain"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("pruned 0 jobs and 0 events"));
+}
+
+#[test]
+fn task_provider_pre_and_post_commands_run_around_the_main_command() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.skip-post]
+type = "command"
+command = "sh"
+args = ["-c", "echo main output; exit 3"]
+pre = ["sh", "-c", "echo pre output"]
+post = ["sh", "-c", "echo post output"]
+
+[tasks.providers.always-post]
+type = "command"
+command = "sh"
+args = ["-c", "echo main output; exit 3"]
+pre = ["sh", "-c", "echo pre output"]
+post = ["sh", "-c", "echo post output"]
+post-on-failure = "always"
+
+[tasks.providers.missing-pre]
+type = "command"
+command = "sh"
+args = ["-c", "echo main output"]
+pre = ["work-test-no-such-program"]
+post = ["sh", "-c", "echo post output"]
+post-on-failure = "always"
+
+[tasks.providers.with-result]
+type = "command"
+command = "sh"
+args = ["-c", "echo main output; echo '{\"work_result\": \"chained result\"}'"]
+pre = ["true"]
+"#,
+    );
+
+    let proj = d.work_dir.path().join("chain-proj");
+    std::fs::create_dir(&proj).unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(&proj)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&["init", "-q"]);
+    git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+    d.assert_cmd()
+        .args(["project", "new", "chain-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let run = |provider: &str| {
+        let out = d
+            .assert_cmd()
+            .args(["task", "new", "chain", "--project", "chain-proj"])
+            .args(["--provider", provider, "--env-provider", "git-worktree"])
+            .args(["--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let task_id = json_item(&out)["id"].as_str().unwrap().to_string();
+        let status = wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20));
+        let out = d
+            .assert_cmd()
+            .args(["task", "logs", &task_id])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        (task_id, status, String::from_utf8(out).unwrap())
+    };
+    let sections = |task_id: &str| {
+        let out = d
+            .assert_cmd()
+            .args(["task", "logs", task_id, "--sections"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_whitespace().last().map(str::to_string))
+            .collect::<Vec<_>>()
+    };
+
+    let (task_id, status, log) = run("skip-post");
+    assert_eq!(status, "failed");
+    assert!(log.contains("pre: sh -c echo pre output\n"), "{log}");
+    assert!(log.contains("\npre output\n"), "{log}");
+    assert!(log.contains("\nmain output\n"), "{log}");
+    assert!(log.contains("command: exit status: 3\n"), "{log}");
+    assert!(log.contains("post: skipped\n"), "{log}");
+    assert!(!log.contains("post output"), "{log}");
+    // Each step that ran is a section of the log; a skipped one isn't.
+    assert_eq!(sections(&task_id), ["pre", "command"]);
+
+    let (task_id, status, log) = run("always-post");
+    assert_eq!(status, "failed");
+    assert!(log.contains("\npost output\n"), "{log}");
+    assert!(log.contains("post: exit status: 0\n"), "{log}");
+    assert_eq!(sections(&task_id), ["pre", "command", "post"]);
+
+    // A step that can't start fails like one that exits nonzero.
+    let (_, status, log) = run("missing-pre");
+    assert_eq!(status, "failed");
+    assert!(log.contains("pre: failed to start: "), "{log}");
+    assert!(log.contains("command: skipped\n"), "{log}");
+    assert!(log.contains("\npost output\n"), "{log}");
+
+    // The step lines after the main command don't hide its result.
+    let (task_id, status, _) = run("with-result");
+    assert_eq!(status, "complete");
+    let out = d
+        .assert_cmd()
+        .args(["task", "show", &task_id, "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(json_item(&out)["result_summary"], "chained result");
+}
+
+#[test]
+fn env_logs_show_the_lifecycle_and_follow_ends_when_the_environment_is_removed() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[environments.providers.sandbox]
+type = "mock"
+latency-ms = 200
+"#,
+    );
+
+    let proj = d.work_dir.path().join("env-logs-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "env-logs-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let out = d
+        .assert_cmd()
+        .args(["env", "prepare", "env-logs-proj", "--provider", "sandbox"])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let env_id = json_item(&out)["id"].as_str().unwrap().to_string();
+    wait_for_env_status(&d, &env_id, "pool", Duration::from_secs(8));
+
+    let out = d
+        .assert_cmd()
+        .args(["env", "logs", &env_id, "--plain"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert!(String::from_utf8_lossy(&out).contains("mock: prepare\n"));
+
+    let follow = d
+        .cmd()
+        .args(["env", "logs", &env_id, "--follow"])
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    d.assert_cmd()
+        .args(["env", "rm", &env_id])
+        .assert()
+        .success();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(follow.wait_with_output());
+    });
+    let output = rx
+        .recv_timeout(Duration::from_secs(15))
+        .expect("env logs --follow kept running after the environment was removed")
+        .unwrap();
+    assert!(output.status.success());
+    let log = String::from_utf8_lossy(&output.stdout);
+    assert!(log.contains("mock: prepare\n"), "log: {log}");
+    assert!(log.contains("mock: remove\n"), "log: {log}");
+
+    d.assert_cmd()
+        .args(["env", "logs", "no-such-env", "--follow"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn environment_snapshot_restores_files_and_is_removed_with_the_environment() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[environments.providers.sandbox]
+type = "mock"
+"#,
+    );
+
+    let proj = d.work_dir.path().join("snap-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "snap-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let out = d
+        .assert_cmd()
+        .args(["env", "prepare", "snap-proj", "--provider", "sandbox"])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let env_id = json_item(&out)["id"].as_str().unwrap().to_string();
+    wait_for_env_status(&d, &env_id, "pool", Duration::from_secs(8));
+    let show = || {
+        let out = d
+            .assert_cmd()
+            .args(["env", "show", &env_id, "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        json_item(&out)
+    };
+    let worktree = std::path::PathBuf::from(
+        show()["metadata"]["worktree_path"]
+            .as_str()
+            .unwrap()
+            .to_string(),
+    );
+    std::fs::create_dir(worktree.join("src")).unwrap();
+    std::fs::write(worktree.join("src/lib.rs"), "v1").unwrap();
+
+    let out = d
+        .assert_cmd()
+        .args(["env", "snapshot", &env_id, "--name", "before"])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let snapshot = json_item(&out);
+    assert_eq!(snapshot["name"], "before");
+    assert!(snapshot["size_bytes"].as_u64().unwrap() > 0, "{snapshot}");
+
+    std::fs::write(worktree.join("src/lib.rs"), "v2").unwrap();
+    std::fs::write(worktree.join("scratch.txt"), "left behind").unwrap();
+    d.assert_cmd()
+        .args(["env", "restore", &env_id, "before"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("restored snapshot before"));
+    assert_eq!(
+        std::fs::read_to_string(worktree.join("src/lib.rs")).unwrap(),
+        "v1"
+    );
+    assert!(!worktree.join("scratch.txt").exists());
+
+    let snapshots = show()["snapshots"].as_array().unwrap().clone();
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0]["id"], snapshot["id"]);
+    assert!(snapshots[0]["restored_at"].is_string(), "{snapshots:?}");
+    d.assert_cmd()
+        .args(["env", "show", &env_id])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("snapshots:"))
+        .stdout(predicate::str::contains("before"));
+
+    d.assert_cmd()
+        .args(["env", "restore", &env_id, "no-such-snapshot"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no-such-snapshot"));
+
+    // A snapshot in progress holds the environment.
+    let conn = rusqlite::Connection::open(d.work_dir.path().join("data/database.sqlite3")).unwrap();
+    conn.execute(
+        "INSERT INTO environment_locks (environment_id, operation, locked_at) VALUES (?1, 'snapshot', '2026-01-01T00:00:00Z')",
+        [&env_id],
+    )
+    .unwrap();
+    d.assert_cmd()
+        .args(["env", "restore", &env_id, "before"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("busy with a snapshot"));
+    conn.execute("DELETE FROM environment_locks", []).unwrap();
+
+    let snapshot_dir = d.work_dir.path().join("data/snapshots").join(&env_id);
+    assert!(snapshot_dir.exists());
+    d.assert_cmd()
+        .args(["env", "rm", &env_id])
+        .assert()
+        .success();
+    assert!(common::wait_for_path_removed(
+        &snapshot_dir,
+        Duration::from_secs(10)
+    ));
+}
+
+#[test]
+fn provider_list_reports_prepare_stats_that_outlive_removed_environments() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[daemon.retries.prepare_environment]
+limit = 0
+
+[environments.providers.quick]
+type = "mock"
+latency-ms = 100
+
+[environments.providers.flaky]
+type = "mock"
+fail = ["prepare"]
+"#,
+    );
+
+    let proj = d.work_dir.path().join("stats-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "stats-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let prepare = |provider: &str, expected_status: &str| -> String {
+        let out = d
+            .assert_cmd()
+            .args(["env", "prepare", "stats-proj", "--provider", provider])
+            .args(["--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let env_id = json_item(&out)["id"].as_str().unwrap().to_string();
+        wait_for_env_status(&d, &env_id, expected_status, Duration::from_secs(8));
+        env_id
+    };
+    let removed = prepare("quick", "pool");
+    prepare("quick", "pool");
+    prepare("flaky", "failed");
+
+    d.assert_cmd()
+        .args(["env", "rm", &removed])
+        .assert()
+        .success();
+    let deadline = Instant::now() + Duration::from_secs(8);
+    while d
+        .assert_cmd()
+        .args(["env", "show", &removed])
+        .output()
+        .unwrap()
+        .status
+        .success()
+    {
+        assert!(Instant::now() < deadline, "environment was never removed");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    let stats = |args: &[&str]| -> Vec<serde_json::Value> {
+        let out = d
+            .assert_cmd()
+            .args(["env", "provider", "list", "--format", "json"])
+            .args(args)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        json_items(&out)
+    };
+    let providers = stats(&[]);
+    let find = |name: &str| {
+        providers
+            .iter()
+            .find(|p| p["provider"] == name)
+            .unwrap_or_else(|| panic!("no {name} in {providers:?}"))
+            .clone()
+    };
+
+    let quick = find("quick");
+    assert_eq!(quick["environments"], serde_json::json!({ "pool": 1 }));
+    assert_eq!(quick["prepares"], 2);
+    assert_eq!(quick["prepare_failures"], 0);
+    assert_eq!(quick["failure_rate"], 0.0);
+    assert!(quick["average_prepare_ms"].as_i64().unwrap() >= 100);
+
+    let flaky = find("flaky");
+    assert_eq!(flaky["environments"], serde_json::json!({ "failed": 1 }));
+    assert_eq!(flaky["prepare_failures"], 1);
+    assert_eq!(flaky["failure_rate"], 1.0);
+    assert!(flaky["average_prepare_ms"].is_null());
+
+    let git = find("git-worktree");
+    assert_eq!(git["prepares"], 0);
+    assert!(git["failure_rate"].is_null());
+
+    let recent = stats(&["--last", "1"]);
+    let quick = recent.iter().find(|p| p["provider"] == "quick").unwrap();
+    assert_eq!(quick["prepares"], 1);
+}
+
+#[test]
+fn capture_diff_saves_what_a_task_changed_as_a_patch() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[projects.diff-proj]
+capture-diff = true
+
+[tasks.providers.edit]
+type = "command"
+command = "sh"
+args = ["-c", "echo more >> README && echo hello > new.txt"]
+"#,
+    );
+
+    let proj = d.work_dir.path().join("diff-proj");
+    std::fs::create_dir(&proj).unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(&proj)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&["init", "-q"]);
+    std::fs::write(proj.join("README"), "readme\n").unwrap();
+    git(&["add", "README"]);
+    git(&["commit", "-q", "-m", "init"]);
+    d.assert_cmd()
+        .args(["project", "new", "diff-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "new", "change things", "--project", "diff-proj"])
+        .args(["--provider", "edit", "--env-provider", "git-worktree"])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task_id = json_item(&out)["id"].as_str().unwrap().to_string();
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+        "complete"
+    );
+
+    let diff_path = d
+        .work_dir
+        .path()
+        .join("data/artifacts/tasks")
+        .join(&task_id)
+        .join("patch.diff");
+    let diff = std::fs::read_to_string(&diff_path).unwrap();
+    assert!(diff.contains("+++ b/README\n"), "{diff}");
+    assert!(diff.contains("+more\n"), "{diff}");
+    assert!(diff.contains("+++ b/new.txt\n"), "{diff}");
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "show", &task_id])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let show = String::from_utf8(out).unwrap();
+    assert!(
+        show.contains(&diff_path.display().to_string()),
+        "task show doesn't link the diff: {show}"
+    );
+    d.assert_cmd()
+        .args(["task", "logs", &task_id])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("diff: saved to"));
+}
+
+#[test]
+fn task_merge_brings_the_task_branch_into_the_project_and_cleans_up() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.committer]
+type = "command"
+command = "sh"
+args = ["-c", "echo done > done.txt && git add done.txt && git -c user.name=t -c user.email=t@example.com commit -qm 'task work'"]
+"#,
+    );
+
+    let proj = d.work_dir.path().join("merge-proj");
+    std::fs::create_dir(&proj).unwrap();
+    let git = |args: &[&str]| {
+        let output = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .current_dir(&proj)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed");
+        String::from_utf8(output.stdout).unwrap()
+    };
+    git(&["init", "-q", "-b", "main"]);
+    git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+    d.assert_cmd()
+        .args(["project", "new", "merge-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "new", "write done.txt", "--project", "merge-proj"])
+        .args(["--provider", "committer", "--env-provider", "git-worktree"])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task_id = json_item(&out)["id"].as_str().unwrap().to_string();
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+        "complete"
+    );
+
+    d.assert_cmd()
+        .args(["task", "merge", &task_id, "--delete-env"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("into main (fast-forward)"));
+    assert_eq!(git(&["log", "-1", "--format=%s"]).trim(), "task work");
+    assert!(proj.join("done.txt").exists());
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while d
+        .assert_cmd()
+        .args(["task", "show", &task_id])
+        .output()
+        .unwrap()
+        .status
+        .success()
+    {
+        assert!(Instant::now() < deadline, "task was never removed");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn tasks_and_environments_resolve_by_alias_and_unambiguous_prefix() {
+    let d = DaemonFixture::start();
+
+    let config = r#"[tasks.providers.fake]
+type = "mock"
+output = ["hello from {task_description}"]
+
+[environments.providers.sandbox]
+type = "mock"
+"#;
+    d.write_config(config);
+
+    let proj = d.work_dir.path().join("alias-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "alias-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let mut tasks = Vec::new();
+    for description in ["first", "second"] {
+        let out = d
+            .assert_cmd()
+            .args(["task", "new", description, "--project", "alias-proj"])
+            .args(["--provider", "fake", "--env-provider", "sandbox"])
+            .args(["--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let task = json_item(&out);
+        let id = task["id"].as_str().unwrap().to_string();
+        assert_eq!(
+            wait_for_task_terminal_status(&d, &id, Duration::from_secs(20)),
+            "complete"
+        );
+        tasks.push(task);
+    }
+    assert_eq!(tasks[0]["alias"], 1);
+    assert_eq!(tasks[1]["alias"], 2);
+    let first = tasks[0]["id"].as_str().unwrap();
+    let second = tasks[1]["id"].as_str().unwrap();
+
+    let show = |args: &[&str]| {
+        let out = d
+            .assert_cmd()
+            .args(args)
+            .args(["--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        json_item(&out)
+    };
+    assert_eq!(show(&["task", "show", "t2"])["id"], second);
+    d.assert_cmd()
+        .args(["task", "logs", "t1"])
+        .assert()
+        .success()
+        .stdout("hello from first\n");
+    let env = show(&["env", "show", "e1"]);
+    assert_eq!(env["id"], tasks[0]["environment_id"]);
+    assert_eq!(env["alias"], 1);
+
+    // IDs made moments apart share their leading characters.
+    let shared = first
+        .chars()
+        .zip(second.chars())
+        .take_while(|(a, b)| a == b)
+        .count();
+    assert!(shared > 0, "{first} and {second} share no prefix");
+    assert_eq!(show(&["task", "show", &first[..=shared]])["id"], first);
+    d.assert_cmd()
+        .args(["task", "show", &first[..shared]])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("matches more than one task"));
+    d.assert_cmd()
+        .args(["task", "show", "t99"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("task not found: t99"));
+
+    d.assert_cmd()
+        .args(["task", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("ALIAS").and(predicate::str::contains(first)));
+    d.write_config(format!("id-format = \"alias\"\n{config}"));
+    d.assert_cmd()
+        .args(["task", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("t1").and(predicate::str::contains(first).not()));
+}
+
+#[test]
+fn notification_scripts_get_subscribed_events_and_are_retried() {
+    let d = DaemonFixture::start();
+
+    let out = d.work_dir.path().join("notifications");
+    std::fs::create_dir(&out).unwrap();
+    let log_script = d.work_dir.path().join("log-event.sh");
+    write_executable_script(
+        &log_script,
+        "#!/bin/sh\ncat >> \"$OUT/events\"\necho >> \"$OUT/events\"\n",
+    );
+    let flaky_script = d.work_dir.path().join("flaky.sh");
+    write_executable_script(
+        &flaky_script,
+        "#!/bin/sh\nif [ ! -e \"$OUT/failed-once\" ]; then\n  touch \"$OUT/failed-once\"\n  echo 'not yet' >&2\n  exit 1\nfi\ncat > \"$OUT/flaky\"\n",
+    );
+
+    d.write_config(format!(
+        r#"[daemon.retries.notify]
+limit = 2
+max-delay = 1
+
+[tasks.providers.fake]
+type = "mock"
+output = ["done"]
+
+[environments.providers.sandbox]
+type = "mock"
+
+[notifications.providers.log]
+type = "script"
+path = "{}"
+events = ["task.complete"]
+env = {{ OUT = "{}" }}
+
+[notifications.providers.flaky]
+type = "script"
+path = "{}"
+events = ["task.created"]
+env = {{ OUT = "{}" }}
+"#,
+        log_script.display(),
+        out.display(),
+        flaky_script.display(),
+        out.display()
+    ));
+
+    let proj = d.work_dir.path().join("notify-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "notify-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+    let created = d
+        .assert_cmd()
+        .args(["task", "new", "ping me", "--project", "notify-proj"])
+        .args(["--provider", "fake", "--env-provider", "sandbox"])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task_id = json_item(&created)["id"].as_str().unwrap().to_string();
+    assert_eq!(
+        wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+        "complete"
+    );
+
+    // The flaky script fails its first run, so the event arrives on the
+    // job's retry.
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while !(out.join("flaky").exists() && out.join("events").exists()) {
+        assert!(Instant::now() < deadline, "notifications never arrived");
+        thread::sleep(Duration::from_millis(100));
+    }
+    let flaky: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(out.join("flaky")).unwrap()).unwrap();
+    assert_eq!(flaky["provider"], "flaky");
+    assert_eq!(flaky["type"], "task.created");
+    assert_eq!(flaky["event"]["subject_id"], task_id.as_str());
+
+    // Later updates to the complete task don't send task.complete again.
+    d.assert_cmd()
+        .args(["task", "label", &task_id, "seen"])
+        .assert()
+        .success();
+    thread::sleep(Duration::from_millis(1000));
+    let events = std::fs::read_to_string(out.join("events")).unwrap();
+    let events: Vec<serde_json::Value> = events
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(events.len(), 1, "{events:?}");
+    assert_eq!(events[0]["type"], "task.complete");
+    assert_eq!(events[0]["provider"], "log");
+    assert_eq!(events[0]["subject"]["id"], task_id.as_str());
+    assert_eq!(events[0]["subject"]["status"], "complete");
+}
+
+#[test]
+fn reported_task_costs_total_by_project_and_month() {
+    let d = DaemonFixture::start();
+
+    d.write_config(
+        r#"[tasks.providers.billed]
+type = "command"
+command = "sh"
+args = ["-c", "echo '{\"work_result\": {\"summary\": \"done\", \"tokens_in\": 1000, \"tokens_out\": 250, \"cost_usd\": 0.5}}'"]
+
+[tasks.providers.free]
+type = "command"
+command = "echo"
+args = ["done"]
+
+[environments.providers.sandbox]
+type = "mock"
+"#,
+    );
+
+    for name in ["spendy", "thrifty"] {
+        let proj = d.work_dir.path().join(name);
+        std::fs::create_dir(&proj).unwrap();
+        d.assert_cmd()
+            .args(["project", "new", name, "--path"])
+            .arg(&proj)
+            .assert()
+            .success();
+    }
+    let run = |project: &str, provider: &str| {
+        let out = d
+            .assert_cmd()
+            .args(["task", "new", "spend", "--project", project])
+            .args(["--provider", provider, "--env-provider", "sandbox"])
+            .args(["--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let task_id = json_item(&out)["id"].as_str().unwrap().to_string();
+        assert_eq!(
+            wait_for_task_terminal_status(&d, &task_id, Duration::from_secs(20)),
+            "complete"
+        );
+        task_id
+    };
+    let billed = run("spendy", "billed");
+    run("spendy", "billed");
+    run("spendy", "free");
+    run("thrifty", "free");
+
+    let out = d
+        .assert_cmd()
+        .args(["task", "show", &billed, "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(
+        json_item(&out)["cost"],
+        serde_json::json!({"tokens_in": 1000, "tokens_out": 250, "cost_usd": 0.5})
+    );
+    d.assert_cmd()
+        .args(["task", "show", &billed])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("$0.50, 1000 tokens in, 250 out"));
+
+    // Only tasks that reported a cost count, so thrifty has no row.
+    let month = chrono::Utc::now().format("%Y-%m").to_string();
+    let out = d
+        .assert_cmd()
+        .args(["stats", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(
+        json_items(&out),
+        [serde_json::json!({
+            "project": "spendy",
+            "month": month,
+            "tasks": 2,
+            "tokens_in": 2000,
+            "tokens_out": 500,
+            "cost_usd": 1.0,
+        })]
+    );
+    let out = d
+        .assert_cmd()
+        .args(["stats", "--project", "thrifty", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert!(json_items(&out).is_empty());
+    d.assert_cmd()
+        .args(["stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("spendy"))
+        .stdout(predicate::str::contains("$1.00"));
+}
+
+#[test]
+fn tasks_record_when_their_status_changed_and_sort_by_it() {
+    let d = DaemonFixture::start();
+
+    d.write_config("[tasks.providers.fake]\ntype = \"mock\"\n\n[environments.providers.sandbox]\ntype = \"mock\"\n");
+    let proj = d.work_dir.path().join("since-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "since-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let mut ids = Vec::new();
+    for description in ["first", "second"] {
+        let out = d
+            .assert_cmd()
+            .args(["task", "new", description, "--project", "since-proj"])
+            .args(["--provider", "fake", "--env-provider", "sandbox"])
+            .args(["--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        let id = json_item(&out)["id"].as_str().unwrap().to_string();
+        assert_eq!(
+            wait_for_task_terminal_status(&d, &id, Duration::from_secs(20)),
+            "complete"
+        );
+        ids.push(id);
+    }
+
+    let conn = rusqlite::Connection::open(d.work_dir.path().join("data/database.sqlite3")).unwrap();
+    conn.busy_timeout(Duration::from_secs(5)).unwrap();
+    let events = || -> i64 {
+        conn.query_row("SELECT COUNT(*) FROM events", [], |row| row.get(0))
+            .unwrap()
+    };
+    let changed_at = || -> String {
+        conn.query_row(
+            "SELECT changed_at FROM task_status_times WHERE id = ?1",
+            [&ids[0]],
+            |row| row.get(0),
+        )
+        .unwrap()
+    };
+
+    // A status change is still one event, and other changes leave the
+    // timestamp alone.
+    let before = events();
+    conn.execute(
+        "UPDATE tasks SET status = 'started', updated_at = '2026-01-01T00:00:00+00:00' WHERE id = ?1",
+        [&ids[0]],
+    )
+    .unwrap();
+    assert_eq!(events(), before + 1);
+    assert_eq!(changed_at(), "2026-01-01T00:00:00+00:00");
+    conn.execute(
+        "UPDATE tasks SET updated_at = '2026-01-02T00:00:00+00:00' WHERE id = ?1",
+        [&ids[0]],
+    )
+    .unwrap();
+    assert_eq!(changed_at(), "2026-01-01T00:00:00+00:00");
+
+    let listed = |sort: &str| -> Vec<String> {
+        let out = d
+            .assert_cmd()
+            .args(["task", "list", "--sort", sort, "--format", "json"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        json_items(&out)
+            .iter()
+            .map(|t| t["id"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(listed("created"), [ids[1].clone(), ids[0].clone()]);
+    assert_eq!(listed("in-status"), [ids[0].clone(), ids[1].clone()]);
+
+    d.assert_cmd()
+        .args(["task", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("IN STATUS").and(predicate::str::contains("d ")));
+}
+
+#[test]
+fn cancelled_runners_get_their_signal_and_cancel_file_before_sigkill() {
+    let d = DaemonFixture::start();
+
+    let out = d.work_dir.path().join("agent-out");
+    std::fs::create_dir(&out).unwrap();
+    let agent = d.work_dir.path().join("agent.sh");
+    write_executable_script(
+        &agent,
+        &format!(
+            r#"#!/bin/sh
+out="{}"
+trap 'echo interrupted >> "$out/trace"' INT
+touch "$out/started"
+while [ ! -e "$WORK_CANCEL_FILE" ]; do sleep 0.1; done
+echo checkpointed >> "$out/trace"
+"#,
+            out.display()
+        ),
+    );
+
+    d.write_config(format!(
+        r#"[tasks.providers.agent]
+type = "command"
+command = "{}"
+cancel = {{ signal = "SIGINT", grace = "20s" }}
+
+[environments.providers.sandbox]
+type = "mock"
+"#,
+        agent.display()
+    ));
+    let proj = d.work_dir.path().join("cancel-proj");
+    std::fs::create_dir(&proj).unwrap();
+    d.assert_cmd()
+        .args(["project", "new", "cancel-proj", "--path"])
+        .arg(&proj)
+        .assert()
+        .success();
+
+    let created = d
+        .assert_cmd()
+        .args(["task", "new", "long job", "--project", "cancel-proj"])
+        .args(["--provider", "agent", "--env-provider", "sandbox"])
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let task_id = json_item(&created)["id"].as_str().unwrap().to_string();
+    assert!(common::wait_for_path(
+        &out.join("started"),
+        Duration::from_secs(20)
+    ));
+
+    d.assert_cmd()
+        .args(["task", "remove", &task_id])
+        .assert()
+        .success();
+
+    // The runner traps the signal, then sees the file and finishes well
+    // inside its grace period.
+    let trace = out.join("trace");
+    let deadline = Instant::now() + Duration::from_secs(15);
+    while !std::fs::read_to_string(&trace).is_ok_and(|t| t.contains("checkpointed")) {
+        assert!(
+            Instant::now() < deadline,
+            "runner never saw the cancel file"
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(
+        std::fs::read_to_string(&trace).unwrap(),
+        "interrupted\ncheckpointed\n"
+    );
 }